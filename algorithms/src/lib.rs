@@ -1,9 +1,11 @@
-//! Zero knowledge range proofs, safe prime generator and SHA512-256 wrapper
+//! Zero knowledge range proofs, proofs of correctness of moduli, safe prime generator and SHA512-256 wrapper
 //!
 #![allow(non_snake_case)]
 pub mod config;
 pub mod dlog_proof;
 pub mod nizk_rsa;
+pub mod no_small_factor;
+pub mod paillier_blum;
 pub mod primes;
 pub mod sha;
 pub mod types;
@@ -81,6 +81,15 @@ pub const N_MIN_SIZE: usize = 2 * PRIME_BIT_LENGTH_IN_PAILLIER_SCHEMA - 1;
 ///
 /// implements rejection sampling algorithm for $`\rho`$ as described in the [whitepaper](https://eprint.iacr.org/2018/057.pdf) , section C.4
 pub fn get_rho_vec(n: &BigInt) -> Vec<BigInt> {
+    rho_vec(n, &[])
+}
+
+/// generates the vector of $` \rho_{i} `$ of size M2, which depends on the context of the proof, like the session of a protocol
+pub fn get_rho_vec_with_context(n: &BigInt, context: &BigInt) -> Vec<BigInt> {
+    rho_vec(n, &[context])
+}
+
+fn rho_vec(n: &BigInt, context: &[&BigInt]) -> Vec<BigInt> {
     let key_length = n.bit_length();
     let salt = BigInt::from_str_radix(SALT, 10).expect("not a decimal number");
 
@@ -88,7 +97,11 @@ pub fn get_rho_vec(n: &BigInt) -> Vec<BigInt> {
         .map(|i| {
             (1u64..1000) // this upper limit should be never hit normally, unless gen_mask() is changed to return numbers too big
                 .map(|j| {
-                    let s = hash(&[n, &salt, &BigInt::from(i), &BigInt::from(j)]);
+                    let (i, j) = (BigInt::from(i), BigInt::from(j));
+                    let mut input = vec![n, &salt];
+                    input.extend_from_slice(context);
+                    input.extend_from_slice(&[&i, &j]);
+                    let s = hash(&input);
                     gen_mask(key_length, &s)
                 })
                 .find(|rho| rho < n)
@@ -100,8 +113,17 @@ pub fn get_rho_vec(n: &BigInt) -> Vec<BigInt> {
 /// generates non-interactive proof of correctness of public Paillier key
 pub fn gen_proof(dk: &DecryptionKey) -> Vec<BigInt> {
     let n = &dk.q * &dk.p;
+    prove_rho_vec(dk, get_rho_vec(&n))
+}
 
-    get_rho_vec(&n)
+/// generates non-interactive proof of correctness of public Paillier key, bound to the context
+pub fn gen_proof_with_context(dk: &DecryptionKey, context: &BigInt) -> Vec<BigInt> {
+    let n = &dk.q * &dk.p;
+    prove_rho_vec(dk, get_rho_vec_with_context(&n, context))
+}
+
+fn prove_rho_vec(dk: &DecryptionKey, rho_vec: Vec<BigInt>) -> Vec<BigInt> {
+    rho_vec
         .into_iter()
         .map(|rho| extract_nroot(dk, &rho))
         .collect()
@@ -110,6 +132,26 @@ pub fn gen_proof(dk: &DecryptionKey) -> Vec<BigInt> {
 /// Verifies non-interactive proof of correctness of public Paillier key.
 /// Checks also whether given public key has expected bit size
 pub fn verify(encryption: &EncryptionKey, sigmas: &[BigInt]) -> Result<(), NIZKError> {
+    verify_rho_vec(encryption, sigmas, get_rho_vec)
+}
+
+/// Verifies non-interactive proof of correctness of public Paillier key, which is bound to the context
+pub fn verify_with_context(
+    encryption: &EncryptionKey,
+    sigmas: &[BigInt],
+    context: &BigInt,
+) -> Result<(), NIZKError> {
+    verify_rho_vec(encryption, sigmas, |n| get_rho_vec_with_context(n, context))
+}
+
+fn verify_rho_vec<F>(
+    encryption: &EncryptionKey,
+    sigmas: &[BigInt],
+    rho_vec: F,
+) -> Result<(), NIZKError>
+where
+    F: FnOnce(&BigInt) -> Vec<BigInt>,
+{
     if sigmas.len() != M2 {
         return Err(NIZKError::WrongSizeOFProof);
     }
@@ -123,7 +165,7 @@ pub fn verify(encryption: &EncryptionKey, sigmas: &[BigInt]) -> Result<(), NIZKE
 
     let rho_correct = sigmas
         .iter()
-        .zip(get_rho_vec(n).into_iter())
+        .zip(rho_vec(n).into_iter())
         .all(|(sigma, rho)| rho == sigma.powm_sec(n, n));
     if !rho_correct {
         return Err(NIZKError::IncorrectRho);
@@ -150,7 +192,7 @@ fn hash(bigints: &[&BigInt]) -> BigInt {
 /// For counter from 0 to $` \lceil \frac{mask\_length}{DIGEST\_SIZE} \rceil - 1`$, and output T as empty string, do T = T || Hash(seed || counter)
 ///
 /// Note that $` \lceil \frac{mask\_length}{DIGEST\_SIZE} \rceil - 1 = \lfloor \frac{mask\_length-1}{DIGEST\_SIZE} \rfloor `$
-fn gen_mask(mask_length: usize, seed: &BigInt) -> BigInt {
+pub(crate) fn gen_mask(mask_length: usize, seed: &BigInt) -> BigInt {
     let counter =
         u64::try_from((mask_length - 1) / DIGEST_SIZE).expect("gen_mask: parameters too large");
 
@@ -178,6 +220,17 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_proof_bound_to_context() -> Result<(), NIZKError> {
+        let (encryption, decryption) =
+            Paillier::keypair_with_modulus_size(2 * PRIME_BIT_LENGTH_IN_PAILLIER_SCHEMA).keys();
+        let proof = gen_proof_with_context(&decryption, &BigInt::from(1));
+        verify_with_context(&encryption, &proof, &BigInt::from(1))?;
+        assert!(verify_with_context(&encryption, &proof, &BigInt::from(2)).is_err());
+        assert!(verify(&encryption, &proof).is_err());
+        Ok(())
+    }
+
     #[test]
     #[ignore]
     fn test_key_size() {
@@ -0,0 +1,255 @@
+//!  Non interactive zero knowledge proof that a modulus has no small factors, as described in *"UC Non-Interactive, Proactive, Threshold ECDSA
+//!  with Identifiable Aborts"*, section "No Small Factor Proof", [`link`](https://eprint.iacr.org/2021/060.pdf) .
+//!
+//!  The proof shows that $`N_{0} = pq`$, where both factors are larger than $`2^{\ell}`$. It is made with the ring-Pedersen setup $`\hat{N}, s, t`$ of the verifier,
+//!  i.e. with the public range proof setup $`\tilde{N}, h_{1}, h_{2}`$ of the party which verifies it, as the prover must not know the discrete logarithm of $`t`$ to the base $`s`$.
+//!  Hence the prover makes a separate proof for each verifier.
+
+use crate::sha::HSha512Trunc256;
+use crate::types::{
+    BasicOps, BigInt, BitManipulation, Converter, Modulo, One, Powm, Samplable, Zero,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use zeroize::Zeroize;
+
+#[derive(Debug, Error)]
+pub enum NoSmallFactorError {
+    #[error("no small factor proof: element out of range")]
+    OutOfRange,
+    #[error("no small factor proof: element not invertible")]
+    NotInvertible,
+    #[error("no small factor proof: response out of range")]
+    ResponseOutOfRange,
+    #[error("no small factor proof: equality {0} does not hold")]
+    FailedEquality(usize),
+}
+
+/// Bit length of factors the proof rules out, which is the bit length of the order of the curve
+pub const L: usize = 256;
+
+/// Slackness parameter of the proof
+pub const EPSILON: usize = 2 * L;
+
+const SALT: &str = "ING TS no small factor proof v1.0";
+
+/// Ring-Pedersen setup of the verifier
+#[derive(Debug, Clone, Copy)]
+pub struct RingPedersen<'a> {
+    pub N: &'a BigInt,
+    pub s: &'a BigInt,
+    pub t: &'a BigInt,
+}
+
+/// Non-interactive proof that the modulus has no factors smaller than $`2^{\ell}`$
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NoSmallFactorProof {
+    pub P: BigInt,
+    pub Q: BigInt,
+    pub A: BigInt,
+    pub B: BigInt,
+    pub T: BigInt,
+    pub sigma: BigInt,
+    pub z1: BigInt,
+    pub z2: BigInt,
+    pub w1: BigInt,
+    pub w2: BigInt,
+    pub v: BigInt,
+}
+
+#[allow(clippy::many_single_char_names)]
+impl NoSmallFactorProof {
+    /// generates the proof for the modulus $`N_{0} = pq`$ with the setup of the verifier, bound to the context
+    pub fn create(p: &BigInt, q: &BigInt, setup: &RingPedersen, context: &BigInt) -> Self {
+        Self::prove(&(p * q), p, q, setup, context)
+    }
+
+    /// generates the proof for the modulus $`N_{0}`$ with claimed factors $`p, q`$, which hold $`N_{0} = pq`$ unless the prover cheats
+    fn prove(n0: &BigInt, p: &BigInt, q: &BigInt, setup: &RingPedersen, context: &BigInt) -> Self {
+        let n_hat = setup.N;
+        let two_l = BigInt::one() << L;
+        let two_l_eps = BigInt::one() << (L + EPSILON);
+        let sqrt_n0 = sqrt_bound(n0);
+
+        let mut alpha = sample_signed(&(&two_l_eps * &sqrt_n0));
+        let mut beta = sample_signed(&(&two_l_eps * &sqrt_n0));
+        let mut mu = sample_signed(&(&two_l * n_hat));
+        let mut nu = sample_signed(&(&two_l * n_hat));
+        let sigma = sample_signed(&(&two_l * n0 * n_hat));
+        let mut r = sample_signed(&(&two_l_eps * n0 * n_hat));
+        let mut x = sample_signed(&(&two_l_eps * n_hat));
+        let mut y = sample_signed(&(&two_l_eps * n_hat));
+
+        let P = commit(setup, p, &mu);
+        let Q = commit(setup, q, &nu);
+        let A = commit(setup, &alpha, &x);
+        let B = commit(setup, &beta, &y);
+        let T = BigInt::mod_mul(
+            &pow_signed(&Q, &alpha, n_hat).expect("Q is invertible"),
+            &pow_signed(setup.t, &r, n_hat).expect("t is invertible"),
+            n_hat,
+        );
+        let e = challenge(n0, setup, &[&P, &Q, &A, &B, &T, &sigma], context);
+
+        let mut sigma_hat = &sigma - &nu * p;
+        let proof = NoSmallFactorProof {
+            z1: &alpha + &e * p,
+            z2: &beta + &e * q,
+            w1: &x + &e * &mu,
+            w2: &y + &e * &nu,
+            v: &r + &e * &sigma_hat,
+            P,
+            Q,
+            A,
+            B,
+            T,
+            sigma,
+        };
+        for secret in [
+            &mut alpha,
+            &mut beta,
+            &mut mu,
+            &mut nu,
+            &mut sigma_hat,
+            &mut r,
+            &mut x,
+            &mut y,
+        ]
+        .iter_mut()
+        {
+            secret.zeroize();
+        }
+        proof
+    }
+
+    /// verifies the proof for the modulus $`N_{0}`$ with the own setup of the verifier, which is bound to the context
+    pub fn verify(
+        &self,
+        n0: &BigInt,
+        setup: &RingPedersen,
+        context: &BigInt,
+    ) -> Result<(), NoSmallFactorError> {
+        let n_hat = setup.N;
+        let zero = BigInt::zero();
+        if ![&self.P, &self.Q, &self.A, &self.B, &self.T]
+            .iter()
+            .all(|v| **v > zero && *v < n_hat)
+        {
+            return Err(NoSmallFactorError::OutOfRange);
+        }
+
+        let bound = (BigInt::one() << (L + EPSILON)) * sqrt_bound(n0);
+        if self.z1.abs() > bound || self.z2.abs() > bound {
+            return Err(NoSmallFactorError::ResponseOutOfRange);
+        }
+
+        let e = challenge(
+            n0,
+            setup,
+            &[&self.P, &self.Q, &self.A, &self.B, &self.T, &self.sigma],
+            context,
+        );
+        let R = commit(setup, n0, &self.sigma);
+
+        let mul = |a: &BigInt, b: &BigInt| BigInt::mod_mul(a, b, n_hat);
+        let pow = |base: &BigInt, exp: &BigInt| {
+            pow_signed(base, exp, n_hat).ok_or(NoSmallFactorError::NotInvertible)
+        };
+        let equalities = [
+            (
+                commit(setup, &self.z1, &self.w1),
+                mul(&self.A, &pow(&self.P, &e)?),
+            ),
+            (
+                commit(setup, &self.z2, &self.w2),
+                mul(&self.B, &pow(&self.Q, &e)?),
+            ),
+            (
+                mul(&pow(&self.Q, &self.z1)?, &pow(setup.t, &self.v)?),
+                mul(&self.T, &pow(&R, &e)?),
+            ),
+        ];
+        match equalities.iter().position(|(lhs, rhs)| lhs != rhs) {
+            Some(i) => Err(NoSmallFactorError::FailedEquality(i)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// $`s^{m} t^{r} \mod \hat{N}`$
+fn commit(setup: &RingPedersen, m: &BigInt, r: &BigInt) -> BigInt {
+    BigInt::mod_mul(
+        &pow_signed(setup.s, m, setup.N).expect("s is invertible"),
+        &pow_signed(setup.t, r, setup.N).expect("t is invertible"),
+        setup.N,
+    )
+}
+
+/// Modular exponentiation which accepts negative exponents, if the base is invertible
+fn pow_signed(base: &BigInt, exp: &BigInt, modulus: &BigInt) -> Option<BigInt> {
+    if *exp < BigInt::zero() {
+        BigInt::mod_inv(base, modulus).map(|inv| inv.powm_sec(&exp.abs(), modulus))
+    } else {
+        Some(base.powm_sec(exp, modulus))
+    }
+}
+
+/// Samples from $`[-bound, bound)`$
+fn sample_signed(bound: &BigInt) -> BigInt {
+    BigInt::sample_below(&(bound << 1)) - bound
+}
+
+/// The power of two which is not less than the square root of the modulus
+fn sqrt_bound(n: &BigInt) -> BigInt {
+    BigInt::one() << n.bit_length().div_ceil(2)
+}
+
+fn challenge(n0: &BigInt, setup: &RingPedersen, values: &[&BigInt], context: &BigInt) -> BigInt {
+    let salt = BigInt::from_bytes(SALT.as_bytes());
+    let mut input = vec![&salt, context, n0, setup.N, setup.s, setup.t];
+    input.extend_from_slice(values);
+    HSha512Trunc256::create_hash(&input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PRIME_BIT_LENGTH_IN_PAILLIER_SCHEMA;
+    use crate::primes::{random_blum_prime, PairOfSafePrimes};
+    use crate::types::PaillierKeys;
+    use crate::utils::sample_generator_of_rsa_group;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn test_correct_proof() -> anyhow::Result<()> {
+        let path = Path::new("../tests/data/safe-primes.json");
+        let primes: Vec<PairOfSafePrimes> = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let n_hat = &primes[0].p * &primes[0].q;
+        let s = sample_generator_of_rsa_group(&primes[0].p, &primes[0].q);
+        let t = s.powm_sec(&BigInt::from(65537), &n_hat);
+        let setup = RingPedersen {
+            N: &n_hat,
+            s: &s,
+            t: &t,
+        };
+
+        let keys = PaillierKeys::random();
+        let context = BigInt::from(1);
+        let proof = NoSmallFactorProof::create(&keys.dk.p, &keys.dk.q, &setup, &context);
+        proof.verify(&keys.ek.n, &setup, &context)?;
+        assert!(proof.verify(&keys.ek.n, &setup, &BigInt::from(2)).is_err());
+
+        // the modulus has the factor 3, the prover claims factors of the size of the square root of the modulus,
+        // so that responses are in range, and the relation of the factors to the modulus does not hold
+        let n0 = BigInt::from(3) * random_blum_prime(2 * PRIME_BIT_LENGTH_IN_PAILLIER_SCHEMA - 2);
+        let proof = NoSmallFactorProof::prove(&n0, &keys.dk.p, &keys.dk.q, &setup, &context);
+        let bound = (BigInt::one() << (L + EPSILON)) * sqrt_bound(&n0);
+        assert!(proof.z1.abs() <= bound && proof.z2.abs() <= bound);
+        assert!(matches!(
+            proof.verify(&n0, &setup, &context),
+            Err(NoSmallFactorError::FailedEquality(2))
+        ));
+        Ok(())
+    }
+}
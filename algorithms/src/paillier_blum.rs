@@ -0,0 +1,218 @@
+//!  Non interactive zero knowledge proof that a modulus is a Paillier-Blum modulus, as described in *"UC Non-Interactive, Proactive, Threshold ECDSA
+//!  with Identifiable Aborts"*, section "Paillier-Blum Modulus ZK", [`link`](https://eprint.iacr.org/2021/060.pdf) .
+//!
+//!  The proof shows that $`N = pq`$, where $`p, q \equiv 3 \mod 4`$ are primes, and that $`gcd(N, \phi(N)) = 1`$.
+//!  The prover picks $`w`$ whose Jacobi symbol is $`-1`$. For each of $`M`$ points $`y_{i}`$ derived from the hash of $`N`$, $`w`$ and the context of the proof,
+//!  it takes the $`N`$th root $`z_{i}`$ of $`y_{i}`$ and the fourth root $`x_{i}`$ of $`(-1)^{a_{i}} w^{b_{i}} y_{i}`$, which exists for exactly one pair of bits $`a_{i}, b_{i}`$
+//!  when $`N`$ is a Paillier-Blum modulus.
+
+use crate::nizk_rsa::{gen_mask, N_MIN_SIZE};
+use crate::primes::is_prime;
+use crate::sha::HSha512Trunc256;
+use crate::types::{BigInt, BitManipulation, Converter, Modulo, One, Powm, Samplable, Zero};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use zeroize::Zeroize;
+
+#[derive(Debug, Error)]
+pub enum PaillierBlumError {
+    #[error("Paillier-Blum proof: wrong size")]
+    WrongSizeOfProof,
+    #[error("Paillier-Blum proof: N can be too small: {0}")]
+    WrongSizeOfN(usize),
+    #[error("Paillier-Blum proof: N is not an odd composite number")]
+    NotComposite,
+    #[error("Paillier-Blum proof: element out of range")]
+    OutOfRange,
+    #[error("Paillier-Blum proof: invalid N-th root {0}")]
+    InvalidNthRoot(usize),
+    #[error("Paillier-Blum proof: invalid fourth root {0}")]
+    InvalidFourthRoot(usize),
+    #[error("Paillier-Blum proof: factors are not Blum primes")]
+    NotBlumPrimes,
+}
+
+/// The number of points in the proof, which determines its statistical soundness $`2^{-M}`$
+pub const M: usize = 80;
+
+const SALT: &str = "ING TS Paillier-Blum modulus proof v1.0";
+
+/// Non-interactive proof that the modulus is the product of two Blum primes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PaillierBlumProof {
+    pub w: BigInt,
+    pub x: Vec<BigInt>,
+    pub a: Vec<bool>,
+    pub b: Vec<bool>,
+    pub z: Vec<BigInt>,
+}
+
+#[allow(clippy::many_single_char_names)]
+impl PaillierBlumProof {
+    /// generates the proof for the modulus $`N = pq`$, bound to the context
+    pub fn create(p: &BigInt, q: &BigInt, context: &BigInt) -> Result<Self, PaillierBlumError> {
+        let four = BigInt::from(4);
+        let three = BigInt::from(3);
+        if p % &four != three || q % &four != three || p == q {
+            return Err(PaillierBlumError::NotBlumPrimes);
+        }
+        let n = p * q;
+        let mut phi = (p - BigInt::one()) * (q - BigInt::one());
+        let mut n_inv = BigInt::mod_inv(&n, &phi).ok_or(PaillierBlumError::NotComposite)?;
+        phi.zeroize();
+
+        // w is a quadratic residue modulo exactly one of primes, hence its Jacobi symbol is -1
+        let w = loop {
+            let w = BigInt::sample_below(&n);
+            if is_quadratic_residue(&w, p) != is_quadratic_residue(&w, q) {
+                break w;
+            }
+        };
+
+        let mut proof = PaillierBlumProof {
+            w,
+            x: Vec::with_capacity(M),
+            a: Vec::with_capacity(M),
+            b: Vec::with_capacity(M),
+            z: Vec::with_capacity(M),
+        };
+        for y in challenges(&n, &proof.w, context) {
+            let (a, b, y_prim) = [(false, false), (true, false), (false, true), (true, true)]
+                .iter()
+                .map(|&(a, b)| (a, b, twisted(&n, &proof.w, &y, a, b)))
+                .find(|(_, _, y_prim)| {
+                    is_quadratic_residue(y_prim, p) && is_quadratic_residue(y_prim, q)
+                })
+                .ok_or(PaillierBlumError::NotBlumPrimes)?;
+            let x = crate::utils::crt_solver(
+                &[&fourth_root(&y_prim, p), &fourth_root(&y_prim, q)],
+                &[p, q],
+            );
+            proof.x.push(x);
+            proof.a.push(a);
+            proof.b.push(b);
+            proof.z.push(y.powm_sec(&n_inv, &n));
+        }
+        n_inv.zeroize();
+        Ok(proof)
+    }
+
+    /// verifies the proof for the modulus $`N`$, which is bound to the context
+    pub fn verify(&self, n: &BigInt, context: &BigInt) -> Result<(), PaillierBlumError> {
+        if [self.x.len(), self.a.len(), self.b.len(), self.z.len()]
+            .iter()
+            .any(|len| *len != M)
+        {
+            return Err(PaillierBlumError::WrongSizeOfProof);
+        }
+
+        let bit_length_of_n = n.bit_length();
+        if bit_length_of_n < N_MIN_SIZE {
+            return Err(PaillierBlumError::WrongSizeOfN(bit_length_of_n));
+        }
+        if !n.test_bit(0) || is_prime(n, bit_length_of_n) {
+            return Err(PaillierBlumError::NotComposite);
+        }
+
+        let zero = BigInt::zero();
+        let in_range = |v: &BigInt| *v > zero && v < n;
+        if !in_range(&self.w) || !self.x.iter().chain(self.z.iter()).all(in_range) {
+            return Err(PaillierBlumError::OutOfRange);
+        }
+
+        let four = BigInt::from(4);
+        challenges(n, &self.w, context)
+            .iter()
+            .enumerate()
+            .try_for_each(|(i, y)| {
+                if self.z[i].powm_sec(n, n) != *y {
+                    Err(PaillierBlumError::InvalidNthRoot(i))
+                } else if self.x[i].powm_sec(&four, n)
+                    != twisted(n, &self.w, y, self.a[i], self.b[i])
+                {
+                    Err(PaillierBlumError::InvalidFourthRoot(i))
+                } else {
+                    Ok(())
+                }
+            })
+    }
+}
+
+/// generates points $`y_{i} \in Z_{N}`$ from the hash of the modulus, of $`w`$ and of the context
+///
+/// implements rejection sampling as in [`get_rho_vec`](../nizk_rsa/fn.get_rho_vec.html)
+fn challenges(n: &BigInt, w: &BigInt, context: &BigInt) -> Vec<BigInt> {
+    let key_length = n.bit_length();
+    let salt = BigInt::from_bytes(SALT.as_bytes());
+    (0..M as u64)
+        .map(|i| {
+            (1u64..1000)
+                .map(|j| {
+                    let (i, j) = (BigInt::from(i), BigInt::from(j));
+                    let s = HSha512Trunc256::create_hash(&[&salt, n, w, context, &i, &j]);
+                    gen_mask(key_length, &s)
+                })
+                .find(|y| y < n)
+                .expect("cant find y")
+        })
+        .collect()
+}
+
+/// $`(-1)^{a} w^{b} y \mod N`$
+fn twisted(n: &BigInt, w: &BigInt, y: &BigInt, a: bool, b: bool) -> BigInt {
+    let y = if b { BigInt::mod_mul(w, y, n) } else { y % n };
+    if a && y != BigInt::zero() {
+        n - y
+    } else {
+        y
+    }
+}
+
+/// Euler's criterion for prime $`p`$
+fn is_quadratic_residue(a: &BigInt, p: &BigInt) -> bool {
+    let exp = (p - BigInt::one()) >> 1;
+    (a % p).powm_sec(&exp, p) == BigInt::one()
+}
+
+/// The fourth root of quadratic residue modulo Blum prime $`p`$
+///
+/// The square root $`a^{(p+1)/4}`$ of a quadratic residue is a quadratic residue itself, so that the root is taken twice.
+fn fourth_root(a: &BigInt, p: &BigInt) -> BigInt {
+    let exp = (p + BigInt::one()) >> 2;
+    (a % p).powm_sec(&exp, p).powm_sec(&exp, p)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PRIME_BIT_LENGTH_IN_PAILLIER_SCHEMA;
+    use crate::primes::random_blum_prime;
+    use crate::types::PaillierKeys;
+
+    #[test]
+    fn test_correct_proof() -> Result<(), PaillierBlumError> {
+        let keys = PaillierKeys::random();
+        let context = BigInt::from(1);
+        let proof = PaillierBlumProof::create(&keys.dk.p, &keys.dk.q, &context)?;
+        proof.verify(&keys.ek.n, &context)?;
+        assert!(proof.verify(&keys.ek.n, &BigInt::from(2)).is_err());
+
+        let mut tampered = proof.clone();
+        tampered.a[0] = !tampered.a[0];
+        assert!(matches!(
+            tampered.verify(&keys.ek.n, &context),
+            Err(PaillierBlumError::InvalidFourthRoot(0))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_modulus_with_non_blum_factor() {
+        let p = random_blum_prime(PRIME_BIT_LENGTH_IN_PAILLIER_SCHEMA);
+        let q = p.clone() + BigInt::from(2);
+        assert!(matches!(
+            PaillierBlumProof::create(&p, &q, &BigInt::zero()),
+            Err(PaillierBlumError::NotBlumPrimes)
+        ));
+    }
+}
@@ -76,6 +76,26 @@ pub fn random_safe_prime(bit_length: usize) -> (BigInt, BigInt) {
     }
 }
 
+/// generate Blum prime p, such that p = 3 mod 4
+///
+/// Two most significant bits are set, so that the product of two such primes has exactly twice the bit size of each of them.
+pub fn random_blum_prime(bit_length: usize) -> BigInt {
+    if bit_length < 3 {
+        panic!("bit length cant be less than 3");
+    }
+    loop {
+        let mut p = BigInt::sample(bit_length);
+        // p = 3 mod 4
+        BigInt::set_bit(&mut p, 0, true);
+        BigInt::set_bit(&mut p, 1, true);
+        BigInt::set_bit(&mut p, bit_length - 1, true);
+        BigInt::set_bit(&mut p, bit_length - 2, true);
+        if is_prime(&p, bit_length) {
+            return p;
+        }
+    }
+}
+
 /// tests probabilistically whether given number is prime
 ///
 /// * first, check if the number is divisible by each of first 2048 known primes.
@@ -191,6 +211,17 @@ mod tests {
         println!("{} obtained in {} seconds", p, now.elapsed().as_secs_f32());
     }
 
+    #[test]
+    fn blum_prime() {
+        use crate::primes::random_blum_prime;
+        const DEFAULT_BIT_LENGTH: usize = 256;
+
+        let p = random_blum_prime(DEFAULT_BIT_LENGTH);
+        assert_eq!(p.bit_length(), DEFAULT_BIT_LENGTH);
+        assert_eq!(&p % BigInt::from(4), BigInt::from(3));
+        assert!(is_prime(&p, DEFAULT_BIT_LENGTH));
+    }
+
     #[test]
     #[ignore]
     fn print_product_of_first_primes() {
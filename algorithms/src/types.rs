@@ -1,9 +1,11 @@
 use crate::config::PRIME_BIT_LENGTH_IN_PAILLIER_SCHEMA;
+use crate::primes::random_blum_prime;
 pub use curv::arithmetic::traits::{BasicOps, BitManipulation, Converter, NumberTests};
 pub use curv::arithmetic::{BigInt, Integer, Modulo, One, Samplable, Zero};
 pub use curv::cryptographic_primitives::proofs::sigma_dlog::DLogProof;
 pub use paillier::{
-    Decrypt, DecryptionKey, EncryptionKey, KeyGeneration, Paillier, RawCiphertext, RawPlaintext,
+    Decrypt, DecryptionKey, EncryptionKey, KeyGeneration, Keypair, Paillier, RawCiphertext,
+    RawPlaintext,
 };
 
 #[allow(dead_code)]
@@ -56,9 +58,17 @@ impl PaillierKeys {
     }
 
     /// produces new Paiiliier key pair
+    ///
+    /// The modulus is the product of two Blum primes, so that its correctness can be proven with [`PaillierBlumProof`](../paillier_blum/struct.PaillierBlumProof.html)
     pub fn random() -> Self {
-        let (ek, dk) =
-            Paillier::keypair_with_modulus_size(2 * PRIME_BIT_LENGTH_IN_PAILLIER_SCHEMA).keys();
+        let p = random_blum_prime(PRIME_BIT_LENGTH_IN_PAILLIER_SCHEMA);
+        let q = loop {
+            let q = random_blum_prime(PRIME_BIT_LENGTH_IN_PAILLIER_SCHEMA);
+            if q != p {
+                break q;
+            }
+        };
+        let (ek, dk) = Keypair::from((&p, &q)).keys();
         Self { ek, dk }
     }
 
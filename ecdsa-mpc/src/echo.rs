@@ -0,0 +1,908 @@
+//! Echo broadcast, which detects parties sending different broadcasts to different peers
+//!
+//! The protocols assume that each broadcast reaches all its recipients unchanged. A malicious party can nevertheless send different commitments to different peers,
+//! which is detected only indirectly, if at all. The module is an optional layer between the state machine and the network, which adds an echo round to each broadcast.
+//!
+//! Every party which receives a broadcast holds it back, and sends the digest of the message to the other members of its echo group. The broadcast is delivered to the state machine
+//! once all other members of the group have echoed the same digest. If a digest differs, [`EchoBroadcast::receive`] fails with [`EchoError::Equivocation`],
+//! which names the sender of the broadcast, and the application is expected to abort the protocol, e.g. with `Instruction::Terminate`.
+//! Once a broadcast is delivered, echoes of it are dropped, and only its digest is kept, so that a later copy of the broadcast is recognized.
+//!
+//! The error does not prove by itself which of the two parties is faulty: echoes carry bare digests, since signatures are checked by the separate
+//! [`identity`](../identity/index.html) layer, if at all. The error carries both digests instead. In the identity mode the party holds the signed broadcast behind its own digest
+//! as evidence of the authenticator, and the witness holds the one behind its echo. A judge which gets both can check them with `Signed::attest`
+//! and match them to the digests with [`broadcast_digest`]: two validly signed broadcasts of the same round with different digests prove that the origin has equivocated,
+//! while a witness which cannot present a signed broadcast behind its echo is to blame.
+//!
+//! The echo group is the set of parties which receive broadcasts of the protocol:
+//! * all parties of key generation
+//! * signing parties
+//! * the new committee of resharing, as messages of old members are multicast to new members, and new members multicast to each other;
+//!   members of the old committee which leave it are accepted as origins of broadcasts
+//! * all parties of key refresh
+//! * holders of the key in share recovery, as the lost party multicasts its new keys to them
+//!
+//! Broadcasts are accepted only from members of the group, and from other parties given with [`EchoBroadcast::with_origins`],
+//! like the old committee of resharing. Broadcasts of other senders, and echoes of them, are dropped at once.
+//!
+//! Message types tell which of their variants are broadcast with the [`Echoable`] trait. Some broadcasts contain parts meant for a single peer, like range proofs of signing or factor proofs of keygen,
+//! so that digests cover only the part of the broadcast which has to be the same for every recipient. Messages addressed to a single peer pass through the layer without echoes.
+//!
+//! [`EchoBroadcast::receive`]: struct.EchoBroadcast.html#method.receive
+//! [`EchoError::Equivocation`]: enum.EchoError.html#variant.Equivocation
+//! [`EchoBroadcast::with_origins`]: struct.EchoBroadcast.html#method.with_origins
+//! [`broadcast_digest`]: fn.broadcast_digest.html
+//! [`Echoable`]: trait.Echoable.html
+
+use crate::messages::{keygen, recovery, refresh, resharing, signing};
+use crate::protocol::{Address, InputMessage, MessageHeader, OutputMessage, PartyIndex};
+use crate::wire::{to_bytes, Reader, Wire, WireError};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap};
+use thiserror::Error;
+
+/// Marks messages which every recipient has to receive unchanged
+pub trait Echoable {
+    /// Canonical bytes of the part of the message which has to be the same for every recipient, or `None` if the message is addressed to a single peer
+    fn echoed_part(&self) -> Result<Option<Vec<u8>>, WireError>;
+}
+
+impl Echoable for keygen::Message {
+    fn echoed_part(&self) -> Result<Option<Vec<u8>>, WireError> {
+        match self {
+            // the notice of the failing party ends the session of its recipients at once, requests to send messages again are addressed to single peers
+            keygen::Message::R3(_) | keygen::Message::Abort(_) | keygen::Message::Resend(_) => {
+                Ok(None)
+            }
+            // the decommitment carries factor proofs for each peer, but the decommitted key is common
+            keygen::Message::R2(msg) => {
+                let mut part = Vec::new();
+                msg.y_i.encode(&mut part)?;
+                msg.blind_factor.encode(&mut part)?;
+                Ok(Some(part))
+            }
+            _ => to_bytes(self).map(Some),
+        }
+    }
+}
+
+impl Echoable for signing::Message {
+    fn echoed_part(&self) -> Result<Option<Vec<u8>>, WireError> {
+        match self {
+            // the first message of MtA may carry a range proof for each peer, but the commitment is common
+            signing::Message::R1(msg) => to_bytes(&msg.com).map(Some),
+            signing::Message::R2(_)
+            | signing::Message::R2b(_)
+            | signing::Message::Abort(_)
+            | signing::Message::Resend(_) => Ok(None),
+            _ => to_bytes(self).map(Some),
+        }
+    }
+}
+
+impl Echoable for resharing::Message {
+    fn echoed_part(&self) -> Result<Option<Vec<u8>>, WireError> {
+        match self {
+            resharing::Message::R1(_) | resharing::Message::R2(_) => to_bytes(self).map(Some),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl Echoable for refresh::Message {
+    fn echoed_part(&self) -> Result<Option<Vec<u8>>, WireError> {
+        match self {
+            refresh::Message::R2(_) | refresh::Message::Abort(_) | refresh::Message::Resend(_) => {
+                Ok(None)
+            }
+            _ => to_bytes(self).map(Some),
+        }
+    }
+}
+
+impl Echoable for recovery::Message {
+    fn echoed_part(&self) -> Result<Option<Vec<u8>>, WireError> {
+        match self {
+            recovery::Message::R1(_) => to_bytes(self).map(Some),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// The body of a message on the wire
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Echoed<B> {
+    Message(B),
+    /// the digest of the broadcast of `origin` with the same header, as received by the sender of the echo
+    Echo {
+        origin: PartyIndex,
+        digest: [u8; 32],
+    },
+}
+
+impl<B: Wire> Wire for Echoed<B> {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), WireError> {
+        match self {
+            Echoed::Message(body) => {
+                out.push(0);
+                body.encode(out)
+            }
+            Echoed::Echo { origin, digest } => {
+                out.push(1);
+                origin.encode(out)?;
+                digest.encode(out)
+            }
+        }
+    }
+    fn decode(input: &mut Reader<'_>) -> Result<Self, WireError> {
+        match u8::decode(input)? {
+            0 => Ok(Echoed::Message(B::decode(input)?)),
+            1 => Ok(Echoed::Echo {
+                origin: PartyIndex::decode(input)?,
+                digest: Wire::decode(input)?,
+            }),
+            tag => Err(WireError::UnknownTag {
+                kind: "echoed message",
+                tag,
+            }),
+        }
+    }
+}
+
+/// Errors of echo broadcast
+#[derive(Debug, Clone, Error, PartialEq, Eq)]
+pub enum EchoError {
+    /// the echo of `witness` does not match the broadcast of `origin` as received by the party
+    ///
+    /// Either `origin` has sent different broadcasts to different peers, or `witness` has echoed a wrong digest.
+    /// If `origin` has sent two different broadcasts to the party itself, `witness` is `origin`.
+    #[error(
+        "party {origin} sent inconsistent broadcasts in round {round}, as echoed by {witness}"
+    )]
+    Equivocation {
+        origin: PartyIndex,
+        round: u16,
+        witness: PartyIndex,
+        /// the digest of the broadcast as received by the party
+        view: [u8; 32],
+        /// the digest of the broadcast as echoed by `witness`
+        echoed: [u8; 32],
+    },
+}
+
+/// The result of receiving a message
+pub struct EchoStep<B> {
+    /// messages to be delivered to the state machine
+    pub deliver: Vec<InputMessage<B>>,
+    /// echoes to be sent out
+    pub output: Vec<OutputMessage<Echoed<B>>>,
+}
+
+/// The broadcast identified by its sender and its round
+type BroadcastId = (PartyIndex, u16);
+
+/// Echo broadcast layer of a party
+pub struct EchoBroadcast<B> {
+    /// other members of the echo group
+    group: BTreeSet<PartyIndex>,
+    /// parties outside of the group whose broadcasts are accepted
+    origins: BTreeSet<PartyIndex>,
+    /// digests of broadcasts as received by the party, including delivered ones
+    views: HashMap<BroadcastId, [u8; 32]>,
+    /// echoes of peers for broadcasts which have not been delivered yet, which may arrive before the broadcast itself
+    echoes: HashMap<BroadcastId, HashMap<PartyIndex, [u8; 32]>>,
+    /// broadcasts waiting for echoes
+    held: HashMap<BroadcastId, InputMessage<B>>,
+}
+
+impl<B: Echoable> EchoBroadcast<B> {
+    /// Creates the layer of the party, with the parties which receive broadcasts of the protocol
+    pub fn new<I>(party: PartyIndex, group: I) -> Self
+    where
+        I: IntoIterator<Item = PartyIndex>,
+    {
+        EchoBroadcast {
+            group: group.into_iter().filter(|p| *p != party).collect(),
+            origins: BTreeSet::new(),
+            views: HashMap::new(),
+            echoes: HashMap::new(),
+            held: HashMap::new(),
+        }
+    }
+
+    /// Accepts broadcasts of parties which do not belong to the group, like the old committee of resharing
+    pub fn with_origins<I>(mut self, origins: I) -> Self
+    where
+        I: IntoIterator<Item = PartyIndex>,
+    {
+        self.origins.extend(origins);
+        self
+    }
+
+    /// Wraps the message of the party
+    pub fn send(&self, msg: OutputMessage<B>) -> OutputMessage<Echoed<B>> {
+        OutputMessage {
+            recipient: msg.recipient,
+            header: msg.header,
+            body: Echoed::Message(msg.body),
+        }
+    }
+
+    /// Receives the message or the echo of a peer
+    ///
+    /// Broadcasts are held back until their echoes arrive, messages addressed to the party are delivered at once.
+    /// Broadcasts of parties which are neither members of the group nor accepted origins are dropped.
+    #[allow(clippy::result_large_err)]
+    pub fn receive(&mut self, msg: InputMessage<Echoed<B>>) -> Result<EchoStep<B>, EchoError> {
+        let mut step = EchoStep {
+            deliver: Vec::new(),
+            output: Vec::new(),
+        };
+        let InputMessage {
+            sender,
+            header,
+            body,
+        } = msg;
+        match body {
+            Echoed::Message(body) => {
+                let digest = match body.echoed_part() {
+                    Ok(Some(_)) if !self.is_origin(sender) => {
+                        log::warn!("broadcast from unknown party {} ignored", sender);
+                        return Ok(step);
+                    }
+                    Ok(Some(part)) => digest(sender, &header, &part),
+                    Ok(None) => {
+                        step.deliver.push(InputMessage {
+                            sender,
+                            header,
+                            body,
+                        });
+                        return Ok(step);
+                    }
+                    Err(e) => Err(e),
+                };
+                let digest = match digest {
+                    Ok(digest) => digest,
+                    Err(e) => {
+                        log::warn!("broadcast from {} ignored: {}", sender, e);
+                        return Ok(step);
+                    }
+                };
+                let id = (sender, header.round);
+                match self.views.get(&id) {
+                    Some(view) if *view == digest => {
+                        log::warn!("duplicate broadcast from {} ignored", sender);
+                        return Ok(step);
+                    }
+                    Some(view) => {
+                        return Err(EchoError::Equivocation {
+                            origin: sender,
+                            round: id.1,
+                            witness: sender,
+                            view: *view,
+                            echoed: digest,
+                        })
+                    }
+                    None => {}
+                }
+                self.views.insert(id, digest);
+                step.output = self
+                    .expected_echoes(sender)
+                    .map(|peer| OutputMessage {
+                        recipient: Address::Peer(peer),
+                        header,
+                        body: Echoed::Echo {
+                            origin: sender,
+                            digest,
+                        },
+                    })
+                    .collect();
+                self.held.insert(
+                    id,
+                    InputMessage {
+                        sender,
+                        header,
+                        body,
+                    },
+                );
+                self.check(id)?;
+            }
+            Echoed::Echo { origin, digest } => {
+                if !self.group.contains(&sender) || sender == origin || !self.is_origin(origin) {
+                    log::warn!("unexpected echo from {} ignored", sender);
+                    return Ok(step);
+                }
+                let id = (origin, header.round);
+                if self.is_delivered(id) {
+                    log::debug!("echo from {} of delivered broadcast ignored", sender);
+                    return Ok(step);
+                }
+                self.echoes
+                    .entry(id)
+                    .or_default()
+                    .entry(sender)
+                    .or_insert(digest);
+                self.check(id)?;
+            }
+        }
+        step.deliver.extend(self.release());
+        Ok(step)
+    }
+
+    /// Broadcasts which wait for echoes
+    pub fn pending(&self) -> impl Iterator<Item = &InputMessage<B>> {
+        self.held.values()
+    }
+
+    fn is_origin(&self, party: PartyIndex) -> bool {
+        self.group.contains(&party) || self.origins.contains(&party)
+    }
+
+    /// true if the broadcast has been received and released, so that only its digest is kept
+    fn is_delivered(&self, id: BroadcastId) -> bool {
+        self.views.contains_key(&id) && !self.held.contains_key(&id)
+    }
+
+    fn expected_echoes(&self, origin: PartyIndex) -> impl Iterator<Item = PartyIndex> + '_ {
+        self.group.iter().copied().filter(move |p| *p != origin)
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn check(&self, id: BroadcastId) -> Result<(), EchoError> {
+        let (view, echoes) = match (self.views.get(&id), self.echoes.get(&id)) {
+            (Some(view), Some(echoes)) => (view, echoes),
+            _ => return Ok(()),
+        };
+        match echoes.iter().find(|(_, digest)| *digest != view) {
+            Some((witness, echoed)) => Err(EchoError::Equivocation {
+                origin: id.0,
+                round: id.1,
+                witness: *witness,
+                view: *view,
+                echoed: *echoed,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    fn release(&mut self) -> Vec<InputMessage<B>> {
+        let complete = self
+            .held
+            .keys()
+            .filter(|(origin, round)| {
+                let echoes = self.echoes.get(&(*origin, *round));
+                self.expected_echoes(*origin)
+                    .all(|p| echoes.is_some_and(|e| e.contains_key(&p)))
+            })
+            .copied()
+            .collect::<Vec<_>>();
+        complete
+            .iter()
+            .filter_map(|id| {
+                self.echoes.remove(id);
+                self.held.remove(id)
+            })
+            .collect()
+    }
+}
+
+/// The digest of the broadcast which parties echo, or `None` if the message is addressed to a single peer
+///
+/// Lets a judge match a signed broadcast to a digest of [`EchoError::Equivocation`](enum.EchoError.html#variant.Equivocation).
+pub fn broadcast_digest<B: Echoable>(
+    origin: PartyIndex,
+    header: &MessageHeader,
+    body: &B,
+) -> Result<Option<[u8; 32]>, WireError> {
+    body.echoed_part()?
+        .map(|part| digest(origin, header, &part))
+        .transpose()
+}
+
+fn digest(origin: PartyIndex, header: &MessageHeader, part: &[u8]) -> Result<[u8; 32], WireError> {
+    let digest = Sha256::new()
+        .chain(origin.0)
+        .chain(to_bytes(header)?)
+        .chain(part)
+        .finalize();
+    let mut result = [0u8; 32];
+    result.copy_from_slice(&digest);
+    Ok(result)
+}
+
+#[cfg(test)]
+#[allow(clippy::result_large_err)]
+mod tests {
+    use super::*;
+    use crate::limits::Limits;
+    use crate::protocol::{ProtocolId, Round, Session, SessionId};
+    use crate::recovery::{holder, lost_party};
+    use crate::refresh;
+    use crate::resharing::{member, new_member};
+    use crate::signature;
+    use crate::simulator::tests::{keygen_drivers, load_infos, load_setups, signing_drivers};
+    use crate::simulator::{
+        check_keygen, check_recovery, check_resharing, check_signing, NetworkConditions,
+        NodeOutcome, RecoveryResult, ResharingResult, Simulation, SimulationReport,
+    };
+    use crate::types::{BigInt, Parameters, Point};
+    use crate::zk_range_proofs::MessageA;
+    use state_machine::driver::Driver;
+    use std::fmt::Debug;
+    use std::time::Duration;
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Msg {
+        Commitment(u32),
+        Share(u32),
+    }
+
+    impl Round for Msg {
+        fn round(&self) -> u16 {
+            match self {
+                Msg::Commitment(_) => 1,
+                Msg::Share(_) => 2,
+            }
+        }
+    }
+
+    impl Echoable for Msg {
+        fn echoed_part(&self) -> Result<Option<Vec<u8>>, WireError> {
+            match self {
+                Msg::Commitment(x) => Ok(Some(x.to_be_bytes().to_vec())),
+                Msg::Share(_) => Ok(None),
+            }
+        }
+    }
+
+    fn session() -> Session {
+        Session {
+            protocol: ProtocolId::Keygen,
+            id: SessionId::random(),
+        }
+    }
+
+    fn layers(n: usize) -> Vec<EchoBroadcast<Msg>> {
+        (0..n)
+            .map(|i| EchoBroadcast::new(PartyIndex::from(i), (0..n).map(PartyIndex::from)))
+            .collect()
+    }
+
+    fn deliver<B>(sender: usize, msg: OutputMessage<Echoed<B>>) -> InputMessage<Echoed<B>> {
+        InputMessage::from_output(PartyIndex::from(sender), msg)
+    }
+
+    fn peer(msg: &OutputMessage<Echoed<Msg>>) -> usize {
+        match msg.recipient {
+            Address::Peer(p) => (0..4).find(|i| PartyIndex::from(*i) == p).unwrap(),
+            Address::Broadcast => panic!("echoes are sent to peers"),
+        }
+    }
+
+    #[test]
+    fn broadcast_released_after_echoes() -> Result<(), EchoError> {
+        let mut layers = layers(3);
+        let session = session();
+        let msg = layers[0].send(OutputMessage::new(
+            session,
+            Address::Broadcast,
+            Msg::Commitment(7),
+        ));
+
+        // party 1 holds the broadcast and echoes it to party 2 only
+        let step = layers[1].receive(deliver(0, msg.clone()))?;
+        assert!(step.deliver.is_empty());
+        assert_eq!(step.output.len(), 1);
+        assert_eq!(peer(&step.output[0]), 2);
+        let echo_from_1 = step.output[0].clone();
+
+        // the echo may arrive before the broadcast
+        assert!(layers[2]
+            .receive(deliver(1, echo_from_1))?
+            .deliver
+            .is_empty());
+        let step = layers[2].receive(deliver(0, msg))?;
+        assert_eq!(step.deliver.len(), 1);
+        assert_eq!(step.deliver[0].body, Msg::Commitment(7));
+        let step = layers[1].receive(deliver(2, step.output[0].clone()))?;
+        assert_eq!(step.deliver[0].body, Msg::Commitment(7));
+
+        // messages to a single peer pass through
+        let share = layers[0].send(OutputMessage::new(
+            session,
+            Address::Peer(PartyIndex::from(1)),
+            Msg::Share(42),
+        ));
+        let step = layers[1].receive(deliver(0, share))?;
+        assert_eq!(step.deliver[0].body, Msg::Share(42));
+        assert!(step.output.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn equivocation_detected() -> Result<(), EchoError> {
+        let mut layers = layers(4);
+        let session = session();
+        let to_1 = layers[0].send(OutputMessage::new(
+            session,
+            Address::Broadcast,
+            Msg::Commitment(7),
+        ));
+        let to_others = layers[0].send(OutputMessage::new(
+            session,
+            Address::Broadcast,
+            Msg::Commitment(8),
+        ));
+
+        let origin = PartyIndex::from(0);
+        let digest_of = |body: &Msg| broadcast_digest(origin, &to_1.header, body).unwrap();
+        let (view, echoed) = (
+            digest_of(&Msg::Commitment(8)),
+            digest_of(&Msg::Commitment(7)),
+        );
+
+        let echoes = layers[1].receive(deliver(0, to_1))?.output;
+        layers[2].receive(deliver(0, to_others.clone()))?;
+        layers[3].receive(deliver(0, to_others))?;
+        let echo_to_2 = echoes.iter().find(|m| peer(m) == 2).unwrap().clone();
+        assert_eq!(
+            layers[2].receive(deliver(1, echo_to_2)).err(),
+            Some(EchoError::Equivocation {
+                origin,
+                round: 1,
+                witness: PartyIndex::from(1),
+                view: view.unwrap(),
+                echoed: echoed.unwrap(),
+            })
+        );
+        assert_eq!(layers[2].pending().count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn broadcasts_of_unknown_parties_dropped() -> Result<(), EchoError> {
+        let session = session();
+        let outsider = PartyIndex::from(5);
+        let mut layer =
+            EchoBroadcast::<Msg>::new(PartyIndex::from(0), (0..3).map(PartyIndex::from));
+        let msg = layer.send(OutputMessage::new(
+            session,
+            Address::Broadcast,
+            Msg::Commitment(7),
+        ));
+        let step = layer.receive(InputMessage::from_output(outsider, msg.clone()))?;
+        assert!(step.output.is_empty());
+        assert_eq!(layer.pending().count(), 0);
+        let echo = OutputMessage {
+            recipient: Address::Peer(PartyIndex::from(0)),
+            header: msg.header,
+            body: Echoed::Echo {
+                origin: outsider,
+                digest: [0; 32],
+            },
+        };
+        layer.receive(deliver(1, echo))?;
+        assert!(layer.echoes.is_empty());
+
+        // the old committee of resharing is accepted as origin, but does not echo
+        let mut layer = layer.with_origins(vec![outsider]);
+        let step = layer.receive(InputMessage::from_output(outsider, msg))?;
+        let mut recipients = step.output.iter().map(peer).collect::<Vec<_>>();
+        recipients.sort_unstable();
+        assert_eq!(recipients, vec![1, 2]);
+        Ok(())
+    }
+
+    #[test]
+    fn delivered_broadcasts_pruned() -> Result<(), EchoError> {
+        let mut layers = layers(3);
+        let msg = layers[0].send(OutputMessage::new(
+            session(),
+            Address::Broadcast,
+            Msg::Commitment(7),
+        ));
+        let echo = layers[2].receive(deliver(0, msg.clone()))?.output[0].clone();
+        layers[1].receive(deliver(0, msg.clone()))?;
+        let step = layers[1].receive(deliver(2, echo.clone()))?;
+        assert_eq!(step.deliver.len(), 1);
+        assert!(layers[1].held.is_empty());
+        assert!(layers[1].echoes.is_empty());
+
+        // late copies of the broadcast and its echoes are dropped
+        let step = layers[1].receive(deliver(0, msg))?;
+        assert!(step.deliver.is_empty() && step.output.is_empty());
+        layers[1].receive(deliver(2, echo))?;
+        assert!(layers[1].echoes.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn signing_commitment_echoed_without_range_proofs() {
+        let msg = |c: u32| {
+            signing::Message::R1(signing::SignBroadcastPhase1 {
+                com: BigInt::from(77),
+                mta_a: MessageA {
+                    c: BigInt::from(c),
+                    range_proof: None,
+                },
+            })
+        };
+        assert_eq!(msg(1).echoed_part().unwrap(), msg(2).echoed_part().unwrap());
+        assert!(resharing::Message::Ack.echoed_part().unwrap().is_none());
+    }
+
+    /// network which delays and reorders messages, so that echoes may overtake broadcasts
+    fn conditions() -> NetworkConditions {
+        NetworkConditions {
+            seed: 13,
+            min_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(20),
+            reorder: true,
+            ..NetworkConditions::default()
+        }
+    }
+
+    /// Checks that some node has detected the equivocation of `origin`, and that other nodes except `origin` hold the broadcast back
+    ///
+    /// A node which detects the equivocation on receiving the broadcast does not echo it, so that its peers may wait for the echo.
+    fn assert_equivocation<B, R: Debug>(report: &SimulationReport<B, R>, origin: PartyIndex) {
+        let mut detected = false;
+        for (party, outcome) in report.outcomes.iter().filter(|(p, _)| *p != origin) {
+            match outcome {
+                NodeOutcome::Equivocation(EchoError::Equivocation { origin: o, .. }) => {
+                    assert_eq!(*o, origin, "party {} blamed the wrong origin", party);
+                    detected = true;
+                }
+                NodeOutcome::Stalled => {}
+                other => panic!("party {} missed the equivocation: {:?}", party, other),
+            }
+        }
+        assert!(detected, "no party detected the equivocation");
+    }
+
+    fn keygen_simulation(
+        tampered: bool,
+    ) -> anyhow::Result<SimulationReport<Echoed<keygen::Message>, crate::keygen::MachineResult>>
+    {
+        let parties = (0..=2).map(PartyIndex::from).collect::<Vec<_>>();
+        let mut simulation = Simulation::new(conditions())?;
+        for (party, driver) in keygen_drivers()? {
+            simulation.add_echoed_party(party, driver, EchoBroadcast::new(party, parties.clone()));
+        }
+        if tampered {
+            // party 1 decommits with another blind factor to party 0
+            simulation.tamper(PartyIndex::from(1), |recipient, body| {
+                if let Echoed::Message(keygen::Message::R2(msg)) = body {
+                    if recipient == PartyIndex::from(0) {
+                        msg.blind_factor = &msg.blind_factor + BigInt::from(1);
+                    }
+                }
+            });
+        }
+        Ok(simulation.run())
+    }
+
+    #[test]
+    fn keygen_through_echo_broadcast() -> anyhow::Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+        // decommitments carry factor proofs for each peer
+        check_keygen(&keygen_simulation(false)?)?;
+        let report = keygen_simulation(true)?;
+        assert_equivocation(&report, PartyIndex::from(1));
+        Ok(())
+    }
+
+    fn signing_simulation(
+        tampered: bool,
+    ) -> anyhow::Result<SimulationReport<Echoed<signing::Message>, signature::MachineResult>> {
+        let parties = (0..=2).map(PartyIndex::from).collect::<Vec<_>>();
+        let (drivers, _) = signing_drivers(&[0, 1, 2], None)?;
+        let mut simulation = Simulation::new(conditions())?;
+        for (party, driver) in drivers {
+            simulation.add_echoed_party(party, driver, EchoBroadcast::new(party, parties.clone()));
+        }
+        if tampered {
+            // party 0 commits to another value in the message to party 1
+            simulation.tamper(PartyIndex::from(0), |recipient, body| {
+                if let Echoed::Message(signing::Message::R1(msg)) = body {
+                    if recipient == PartyIndex::from(1) {
+                        msg.com = &msg.com + BigInt::from(1);
+                    }
+                }
+            });
+        }
+        Ok(simulation.run())
+    }
+
+    #[test]
+    fn signing_through_echo_broadcast() -> anyhow::Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let public_key = load_infos()?[0].public_key.clone();
+        let report = signing_simulation(false)?;
+        check_signing(&report, &public_key)?;
+        let report = signing_simulation(true)?;
+        assert_equivocation(&report, PartyIndex::from(0));
+        Ok(())
+    }
+
+    /// resharing from parties 0, 1 and 2 to parties 1, 2 and 3, where parties of the old committee run both roles
+    fn resharing_simulation(
+        tampered: bool,
+    ) -> anyhow::Result<SimulationReport<Echoed<resharing::Message>, ResharingResult>> {
+        let infos = load_infos()?;
+        let zkp_setups = load_setups()?;
+        let new_params = Parameters::new(2, 3)?;
+        let old_committee = (0..=2).map(PartyIndex::from).collect::<Vec<_>>();
+        let new_committee = (1..=3).map(PartyIndex::from).collect::<Vec<_>>();
+        let layer = |party| {
+            EchoBroadcast::new(party, new_committee.clone()).with_origins(old_committee.clone())
+        };
+        let session_id = SessionId::random();
+
+        let mut simulation = Simulation::new(conditions())?;
+        for (info, setup) in infos.iter().zip(&zkp_setups) {
+            let phase = member::Member::new(
+                session_id,
+                info,
+                &new_params,
+                &old_committee,
+                &new_committee,
+                Some(setup.clone()),
+                None,
+                Limits::default(),
+            )?;
+            simulation.add_echoed_party(
+                info.own_party_index,
+                Driver::<member::KeyResharingTraits>::new(Box::new(phase)),
+                layer(info.own_party_index),
+            );
+        }
+        let party = PartyIndex::from(3);
+        let phase = new_member::Phase1::new(
+            session_id,
+            &infos[0].key_params,
+            &new_params,
+            &old_committee,
+            &new_committee,
+            party,
+            Some(zkp_setups[3].clone()),
+            None,
+            Limits::default(),
+        )?;
+        simulation.add_echoed_party(
+            party,
+            Driver::<new_member::KeyResharingTraits>::new(Box::new(phase)),
+            layer(party),
+        );
+        if tampered {
+            // party 0 leaves the committee, and sends another commitment to party 3
+            simulation.tamper(PartyIndex::from(0), |recipient, body| {
+                if let Echoed::Message(resharing::Message::R1(msg)) = body {
+                    if recipient == PartyIndex::from(3) {
+                        msg.vss_commitment = &msg.vss_commitment + BigInt::from(1);
+                    }
+                }
+            });
+        }
+        Ok(simulation.run())
+    }
+
+    #[test]
+    fn resharing_through_echo_broadcast() -> anyhow::Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let public_key = load_infos()?[0].public_key.clone();
+        let report = resharing_simulation(false)?;
+        check_resharing(&report, &public_key)?;
+        let report = resharing_simulation(true)?;
+        assert_equivocation(&report, PartyIndex::from(0));
+        Ok(())
+    }
+
+    fn refresh_simulation(
+        tampered: bool,
+    ) -> anyhow::Result<SimulationReport<Echoed<refresh::Message>, refresh::MachineResult>> {
+        let infos = load_infos()?;
+        let zkp_setups = load_setups()?;
+        let parties = (0..=2).map(PartyIndex::from).collect::<Vec<_>>();
+        let session_id = SessionId::random();
+        let mut simulation = Simulation::new(conditions())?;
+        for (info, setup) in infos.iter().zip(&zkp_setups[3..]) {
+            let phase =
+                refresh::Phase1::new(session_id, info, setup.clone(), None, Limits::default())?;
+            simulation.add_echoed_party(
+                info.own_party_index,
+                Driver::<refresh::KeyRefreshTraits>::new(Box::new(phase)),
+                EchoBroadcast::new(info.own_party_index, parties.clone()),
+            );
+        }
+        if tampered {
+            simulation.tamper(PartyIndex::from(2), |recipient, body| {
+                if let Echoed::Message(refresh::Message::R1(msg)) = body {
+                    if recipient == PartyIndex::from(0) {
+                        msg.public_share = Point::generator().to_point();
+                    }
+                }
+            });
+        }
+        Ok(simulation.run())
+    }
+
+    #[test]
+    fn refresh_through_echo_broadcast() -> anyhow::Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let public_key = load_infos()?[0].public_key.clone();
+        let report = refresh_simulation(false)?;
+        for (party, outcome) in &report.outcomes {
+            match outcome {
+                NodeOutcome::Finished(Ok(f)) => {
+                    assert_eq!(f.multiparty_shared_info.public_key, public_key)
+                }
+                other => anyhow::bail!("party {} did not finish: {:?}", party, other),
+            }
+        }
+        let report = refresh_simulation(true)?;
+        assert_equivocation(&report, PartyIndex::from(2));
+        Ok(())
+    }
+
+    /// recovery of the share of party 2 by parties 0 and 1
+    fn recovery_simulation(
+        tampered: bool,
+    ) -> anyhow::Result<SimulationReport<Echoed<recovery::Message>, RecoveryResult>> {
+        let infos = load_infos()?;
+        let zkp_setups = load_setups()?;
+        let lost = PartyIndex::from(2);
+        let holders = vec![PartyIndex::from(0), PartyIndex::from(1)];
+        let layer = |party| EchoBroadcast::new(party, holders.clone()).with_origins(vec![lost]);
+        let session_id = SessionId::random();
+
+        let mut simulation = Simulation::new(conditions())?;
+        for info in &infos[0..2] {
+            let phase =
+                holder::Phase1::new(session_id, info, lost, &holders, None, Limits::default())?;
+            simulation.add_echoed_party(
+                info.own_party_index,
+                Driver::<holder::KeyRecoveryTraits>::new(Box::new(phase)),
+                layer(info.own_party_index),
+            );
+        }
+        let phase = lost_party::Phase1::new(
+            session_id,
+            lost,
+            &infos[2].key_params,
+            &holders,
+            &holders,
+            Some(zkp_setups[3].clone()),
+            None,
+            Limits::default(),
+        )?;
+        simulation.add_echoed_party(
+            lost,
+            Driver::<lost_party::KeyRecoveryTraits>::new(Box::new(phase)),
+            layer(lost),
+        );
+        if tampered {
+            // the lost party hides its range proof setup from party 1
+            simulation.tamper(lost, |recipient, body| {
+                if let Echoed::Message(recovery::Message::R1(msg)) = body {
+                    if recipient == PartyIndex::from(1) {
+                        msg.range_proof_setup = None;
+                    }
+                }
+            });
+        }
+        Ok(simulation.run())
+    }
+
+    #[test]
+    fn recovery_through_echo_broadcast() -> anyhow::Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let public_key = load_infos()?[0].public_key.clone();
+        let report = recovery_simulation(false)?;
+        check_recovery(&report, &public_key)?;
+        let report = recovery_simulation(true)?;
+        assert_equivocation(&report, PartyIndex::from(2));
+        Ok(())
+    }
+}
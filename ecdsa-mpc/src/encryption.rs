@@ -0,0 +1,549 @@
+//! Authenticated encryption of peer-to-peer messages
+//!
+//! Some messages of the protocols carry secrets of their senders, like [`FeldmanVSS`] shares of key generation or `VSS` shares of resharing.
+//! They are addressed to a single peer, and have to stay confidential on their way to it. The module is an optional layer between the state machine and the network,
+//! which seals each [`OutputMessage`] addressed to a peer with authenticated public key encryption of `sodiumoxide` (`crypto_box`), and opens each sealed [`InputMessage`].
+//!
+//! Every party owns static [`PartyKeys`], bound to its `PartyIndex`, and learns public keys of its peers from the outside of the crate, for instance from its configuration.
+//! The plaintext of each sealed message contains indexes of its sender and of its recipient, so that the message cannot be replayed to another party or reflected back to its sender.
+//! It also contains the header of the message, so that the message cannot be moved into another session or round.
+//!
+//! Broadcast messages are not encrypted. Message types mark their confidential variants with the [`Confidential`] trait, and the module refuses to carry them in plaintext in both directions:
+//! [`SecureChannels::seal`] fails if such message is broadcast, and [`SecureChannels::open`] refuses such message if it arrives unencrypted.
+//! Refused messages are counted per sender and reason, and are added to the error state of the protocol with [`ReportRefused`], like messages rejected by the state machine.
+//!
+//! [`FeldmanVSS`]: ../messages/struct.FeldmanVSS.html
+//! [`OutputMessage`]: ../protocol/struct.OutputMessage.html
+//! [`InputMessage`]: ../protocol/struct.InputMessage.html
+//! [`PartyKeys`]: struct.PartyKeys.html
+//! [`Confidential`]: trait.Confidential.html
+//! [`SecureChannels::seal`]: struct.SecureChannels.html#method.seal
+//! [`SecureChannels::open`]: struct.SecureChannels.html#method.open
+//! [`ReportRefused`]: trait.ReportRefused.html
+
+use crate::messages::{keygen, recovery, refresh, resharing, signing};
+use crate::protocol::{Address, InputMessage, MessageHeader, OutputMessage, PartyIndex};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sodiumoxide::crypto::box_;
+use std::collections::HashMap;
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+/// The number of distinct records of refused messages the channels keep
+///
+/// Records are kept per sender, and the network layer may attribute messages to any sender, so a peer could create a new record with each message.
+pub const MAX_REFUSED_RECORDS: usize = 256;
+
+/// Marks messages which must never be sent in plaintext
+///
+/// Messages which carry secret shares, or pieces of them, are confidential:
+/// * `R3` of key generation and of resharing, the Shamir's share of the key of the sender for the recipient
+/// * `R2` of key refresh, the share of zero which the recipient adds to its share of the key
+/// * `R3` and `R5` of share recovery, the pieces of the lost share
+///
+/// Responses `R2` and `R2b` of multiplicative-to-additive conversions of signing are confidential too, although they are encrypted with the Paillier key of the recipient.
+/// They carry the product of secrets of both parties, masked by the additive share of the sender, so that anyone who learns the Paillier key of the recipient later,
+/// e.g. from a leaked backup, would learn the additive share of the recipient from a recorded response. The analysis of the signing protocol assumes private channels for them.
+///
+/// Other messages carry commitments, their openings, public keys and proofs, which are broadcast or sent to one peer because proofs are made with the setup of the recipient.
+/// They are public and are carried in plaintext.
+pub trait Confidential {
+    fn is_confidential(&self) -> bool;
+}
+
+/// Error states of protocols, which record messages refused by the secure channels
+pub trait ReportRefused {
+    /// Adds senders of messages refused by the secure channels, once per reason, as they behave suspiciously
+    fn report_refused(&mut self, refused: &HashMap<RefusedMessage, usize>);
+}
+
+impl Confidential for keygen::Message {
+    fn is_confidential(&self) -> bool {
+        matches!(self, keygen::Message::R3(_))
+    }
+}
+
+impl Confidential for signing::Message {
+    fn is_confidential(&self) -> bool {
+        matches!(self, signing::Message::R2(_) | signing::Message::R2b(_))
+    }
+}
+
+impl Confidential for resharing::Message {
+    fn is_confidential(&self) -> bool {
+        matches!(self, resharing::Message::R3(_))
+    }
+}
+
+impl Confidential for refresh::Message {
+    fn is_confidential(&self) -> bool {
+        matches!(self, refresh::Message::R2(_))
+    }
+}
+
+impl Confidential for recovery::Message {
+    fn is_confidential(&self) -> bool {
+        matches!(self, recovery::Message::R3(_) | recovery::Message::R5(_))
+    }
+}
+
+/// Static public key of a party
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartyPublicKey(pub [u8; box_::PUBLICKEYBYTES]);
+
+/// Static key pair of a party
+pub struct PartyKeys {
+    party: PartyIndex,
+    public_key: box_::PublicKey,
+    secret_key: box_::SecretKey,
+}
+
+impl PartyKeys {
+    /// generates new random key pair of the party
+    pub fn generate(party: PartyIndex) -> Self {
+        let _ = sodiumoxide::init();
+        let (public_key, secret_key) = box_::gen_keypair();
+        PartyKeys {
+            party,
+            public_key,
+            secret_key,
+        }
+    }
+
+    /// Creates the key pair from bytes of its secret key. Returns `None` if the length of the slice is not equal to `box_::SECRETKEYBYTES`
+    pub fn from_secret_key(party: PartyIndex, bytes: &[u8]) -> Option<Self> {
+        let secret_key = box_::SecretKey::from_slice(bytes)?;
+        Some(PartyKeys {
+            party,
+            public_key: secret_key.public_key(),
+            secret_key,
+        })
+    }
+
+    pub fn party(&self) -> PartyIndex {
+        self.party
+    }
+
+    pub fn public_key(&self) -> PartyPublicKey {
+        PartyPublicKey(self.public_key.0)
+    }
+
+    /// Precomputes the key of the channel to the peer, which is same at both ends of the channel
+    pub fn channel_key(&self, peer: &PartyPublicKey) -> box_::PrecomputedKey {
+        box_::precompute(&box_::PublicKey(peer.0), &self.secret_key)
+    }
+}
+
+/// Errors of sealing messages
+#[derive(Debug, Error)]
+pub enum SealError {
+    #[error("public key of party {0} is unknown")]
+    UnknownRecipient(PartyIndex),
+    #[error("confidential message cannot be broadcast")]
+    ConfidentialBroadcast,
+    #[error("message serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+}
+
+/// Reason why a message from a peer is refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RefuseReason {
+    /// the public key of the sender is unknown
+    UnknownSender,
+    /// the message cannot be decrypted with the key of the sender, or it has been modified
+    Unauthenticated,
+    /// the decrypted message is addressed to another party, comes from another sender, or its header has been changed
+    Misaddressed,
+    /// the decrypted message cannot be parsed
+    Malformed,
+    /// the confidential message arrived in plaintext
+    Plaintext,
+}
+
+/// The record of a message refused by [`SecureChannels::open`](struct.SecureChannels.html#method.open)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RefusedMessage {
+    pub sender: PartyIndex,
+    pub reason: RefuseReason,
+}
+
+/// The body of a message on the wire
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Sealed<B> {
+    Plain(B),
+    Encrypted { nonce: Vec<u8>, ciphertext: Vec<u8> },
+}
+
+/// The plaintext of encrypted message
+#[derive(Serialize, Deserialize)]
+struct Envelope<B> {
+    sender: PartyIndex,
+    recipient: PartyIndex,
+    header: MessageHeader,
+    body: B,
+}
+
+/// Encrypted channels of the party to each of its peers
+pub struct SecureChannels {
+    party: PartyIndex,
+    keys: HashMap<PartyIndex, box_::PrecomputedKey>,
+    refused: HashMap<RefusedMessage, usize>,
+    unrecorded: usize,
+}
+
+impl SecureChannels {
+    /// Precomputes keys of channels to the peers with the key pair of the party and the public keys of the peers
+    pub fn new<I>(own_keys: &PartyKeys, peers: I) -> Self
+    where
+        I: IntoIterator<Item = (PartyIndex, PartyPublicKey)>,
+    {
+        let keys = peers
+            .into_iter()
+            .filter(|(party, _)| *party != own_keys.party)
+            .map(|(party, public_key)| (party, own_keys.channel_key(&public_key)))
+            .collect();
+        SecureChannels {
+            party: own_keys.party,
+            keys,
+            refused: HashMap::new(),
+            unrecorded: 0,
+        }
+    }
+
+    /// Encrypts the message addressed to a peer, and passes broadcast message through, unless it is confidential
+    pub fn seal<B>(&self, msg: OutputMessage<B>) -> Result<OutputMessage<Sealed<B>>, SealError>
+    where
+        B: Serialize + Confidential,
+    {
+        let recipient = match msg.recipient {
+            Address::Broadcast if msg.body.is_confidential() => {
+                return Err(SealError::ConfidentialBroadcast)
+            }
+            Address::Broadcast => {
+                return Ok(OutputMessage {
+                    recipient: Address::Broadcast,
+                    header: msg.header,
+                    body: Sealed::Plain(msg.body),
+                })
+            }
+            Address::Peer(party) => party,
+        };
+        let key = self
+            .keys
+            .get(&recipient)
+            .ok_or(SealError::UnknownRecipient(recipient))?;
+        let plaintext = Zeroizing::new(serde_json::to_vec(&Envelope {
+            sender: self.party,
+            recipient,
+            header: msg.header,
+            body: &msg.body,
+        })?);
+        let nonce = box_::gen_nonce();
+        Ok(OutputMessage {
+            recipient: msg.recipient,
+            header: msg.header,
+            body: Sealed::Encrypted {
+                ciphertext: box_::seal_precomputed(&plaintext, &nonce, key),
+                nonce: nonce.0.to_vec(),
+            },
+        })
+    }
+
+    /// Decrypts the message from a peer and checks its sender and recipient
+    ///
+    /// Returns `None` if the message is refused. The message is counted in its record, see [`refused_messages`](#method.refused_messages).
+    pub fn open<B>(&mut self, msg: InputMessage<Sealed<B>>) -> Option<InputMessage<B>>
+    where
+        B: DeserializeOwned + Confidential,
+    {
+        let sender = msg.sender;
+        match self.decrypt(msg) {
+            Ok(msg) => Some(msg),
+            Err(reason) => {
+                log::warn!("message from {} refused: {:?}", sender, reason);
+                let record = RefusedMessage { sender, reason };
+                if let Some(count) = self.refused.get_mut(&record) {
+                    *count += 1;
+                } else if self.refused.len() < MAX_REFUSED_RECORDS {
+                    self.refused.insert(record, 1);
+                } else {
+                    self.unrecorded += 1;
+                }
+                None
+            }
+        }
+    }
+
+    /// Numbers of messages refused since the channels were created, per sender and reason
+    ///
+    /// At most [`MAX_REFUSED_RECORDS`](constant.MAX_REFUSED_RECORDS.html) distinct records are kept.
+    pub fn refused_messages(&self) -> &HashMap<RefusedMessage, usize> {
+        &self.refused
+    }
+
+    /// The number of refused messages not counted by [`refused_messages`](#method.refused_messages), as their records would exceed the limit
+    pub fn unrecorded_messages(&self) -> usize {
+        self.unrecorded
+    }
+
+    fn decrypt<B>(&self, msg: InputMessage<Sealed<B>>) -> Result<InputMessage<B>, RefuseReason>
+    where
+        B: DeserializeOwned + Confidential,
+    {
+        let (nonce, ciphertext) = match msg.body {
+            Sealed::Plain(body) if body.is_confidential() => return Err(RefuseReason::Plaintext),
+            Sealed::Plain(body) => {
+                return Ok(InputMessage {
+                    sender: msg.sender,
+                    header: msg.header,
+                    body,
+                })
+            }
+            Sealed::Encrypted { nonce, ciphertext } => (nonce, ciphertext),
+        };
+        let key = self
+            .keys
+            .get(&msg.sender)
+            .ok_or(RefuseReason::UnknownSender)?;
+        let nonce = box_::Nonce::from_slice(&nonce).ok_or(RefuseReason::Malformed)?;
+        let plaintext = Zeroizing::new(
+            box_::open_precomputed(&ciphertext, &nonce, key)
+                .map_err(|_| RefuseReason::Unauthenticated)?,
+        );
+        let envelope: Envelope<B> =
+            serde_json::from_slice(&plaintext).map_err(|_| RefuseReason::Malformed)?;
+        if envelope.sender != msg.sender
+            || envelope.recipient != self.party
+            || envelope.header != msg.header
+        {
+            return Err(RefuseReason::Misaddressed);
+        }
+        Ok(InputMessage {
+            sender: msg.sender,
+            header: msg.header,
+            body: envelope.body,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keygen::ErrorState;
+    use crate::messages::FeldmanVSS;
+    use crate::proofs::DLogProof;
+    use crate::protocol::{AbortReport, ProtocolId, Round, Session, SessionId};
+    use crate::types::{VerifiableSS, FE};
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    enum Msg {
+        Commitment(u32),
+        Share(u32),
+    }
+
+    impl Confidential for Msg {
+        fn is_confidential(&self) -> bool {
+            matches!(self, Msg::Share(_))
+        }
+    }
+
+    impl Round for Msg {
+        fn round(&self) -> u16 {
+            match self {
+                Msg::Commitment(_) => 1,
+                Msg::Share(_) => 2,
+            }
+        }
+    }
+
+    fn session() -> Session {
+        Session {
+            protocol: ProtocolId::Keygen,
+            id: SessionId::random(),
+        }
+    }
+
+    fn channels(keys: &[PartyKeys]) -> Vec<SecureChannels> {
+        keys.iter()
+            .map(|own| SecureChannels::new(own, keys.iter().map(|k| (k.party(), k.public_key()))))
+            .collect()
+    }
+
+    fn deliver<B>(sender: usize, msg: OutputMessage<Sealed<B>>) -> InputMessage<Sealed<B>> {
+        InputMessage::from_output(PartyIndex::from(sender), msg)
+    }
+
+    #[test]
+    fn shares_travel_encrypted() -> anyhow::Result<()> {
+        let keys = (0..3)
+            .map(|i| PartyKeys::generate(PartyIndex::from(i)))
+            .collect::<Vec<_>>();
+        let mut channels = channels(&keys);
+        let session = session();
+
+        let sealed = channels[0].seal(OutputMessage::new(
+            session,
+            Address::Peer(PartyIndex::from(1)),
+            Msg::Share(42),
+        ))?;
+        assert!(matches!(sealed.body, Sealed::Encrypted { .. }));
+        let opened = channels[1].open(deliver(0, sealed.clone())).unwrap();
+        assert_eq!(opened.body, Msg::Share(42));
+
+        // the share cannot be read by another peer, nor attributed to another sender
+        assert!(channels[2].open(deliver(0, sealed.clone())).is_none());
+        assert!(channels[1].open(deliver(2, sealed)).is_none());
+
+        // broadcast commitments stay in plaintext, shares never do
+        let broadcast = channels[0].seal(OutputMessage::new(
+            session,
+            Address::Broadcast,
+            Msg::Commitment(7),
+        ))?;
+        assert!(matches!(broadcast.body, Sealed::Plain(Msg::Commitment(7))));
+        assert!(matches!(
+            channels[0].seal(OutputMessage::new(
+                session,
+                Address::Broadcast,
+                Msg::Share(42)
+            )),
+            Err(SealError::ConfidentialBroadcast)
+        ));
+        assert!(channels[1]
+            .open(InputMessage {
+                sender: PartyIndex::from(0),
+                header: MessageHeader::new(session, &Msg::Share(42)),
+                body: Sealed::Plain(Msg::Share(42)),
+            })
+            .is_none());
+
+        assert_eq!(
+            channels[1].refused_messages(),
+            &HashMap::from([
+                (
+                    RefusedMessage {
+                        sender: PartyIndex::from(2),
+                        reason: RefuseReason::Unauthenticated
+                    },
+                    1
+                ),
+                (
+                    RefusedMessage {
+                        sender: PartyIndex::from(0),
+                        reason: RefuseReason::Plaintext
+                    },
+                    1
+                ),
+            ])
+        );
+        assert_eq!(
+            channels[2].refused_messages(),
+            &HashMap::from([(
+                RefusedMessage {
+                    sender: PartyIndex::from(0),
+                    reason: RefuseReason::Unauthenticated
+                },
+                1
+            )])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reflected_message_refused() -> anyhow::Result<()> {
+        let keys = (0..2)
+            .map(|i| PartyKeys::generate(PartyIndex::from(i)))
+            .collect::<Vec<_>>();
+        let mut channels = channels(&keys);
+        let session = session();
+
+        // the peer returns the share to its author, pretending it is its own
+        let sealed = channels[0].seal(OutputMessage::new(
+            session,
+            Address::Peer(PartyIndex::from(1)),
+            Msg::Share(42),
+        ))?;
+        assert!(channels[0].open(deliver(1, sealed.clone())).is_none());
+
+        // the share is moved into another session
+        let mut moved = deliver(0, sealed);
+        moved.header.session.id = SessionId::random();
+        assert!(channels[1].open(moved).is_none());
+
+        assert_eq!(
+            channels[0].refused_messages(),
+            &HashMap::from([(
+                RefusedMessage {
+                    sender: PartyIndex::from(1),
+                    reason: RefuseReason::Misaddressed
+                },
+                1
+            )])
+        );
+        assert_eq!(
+            channels[1].refused_messages(),
+            &HashMap::from([(
+                RefusedMessage {
+                    sender: PartyIndex::from(0),
+                    reason: RefuseReason::Misaddressed
+                },
+                1
+            )])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn refusals_counted_up_to_limit() -> anyhow::Result<()> {
+        let keys = (0..2)
+            .map(|i| PartyKeys::generate(PartyIndex::from(i)))
+            .collect::<Vec<_>>();
+        let mut channels = channels(&keys);
+        let session = session();
+        let plaintext = |sender: u16| InputMessage {
+            sender: PartyIndex::from(sender as usize),
+            header: MessageHeader::new(session, &Msg::Share(42)),
+            body: Sealed::Plain(Msg::Share(42)),
+        };
+        for _ in 0..3 {
+            assert!(channels[1].open(plaintext(0)).is_none());
+        }
+        // each made up sender creates a distinct record
+        for sender in 1..=MAX_REFUSED_RECORDS as u16 + 1 {
+            assert!(channels[1].open(plaintext(1000 + sender)).is_none());
+        }
+        assert_eq!(channels[1].refused_messages().len(), MAX_REFUSED_RECORDS);
+        assert_eq!(
+            channels[1].refused_messages()[&RefusedMessage {
+                sender: PartyIndex::from(0),
+                reason: RefuseReason::Plaintext
+            }],
+            3
+        );
+        assert_eq!(channels[1].unrecorded_messages(), 2);
+
+        let mut errors = ErrorState::new(vec![]);
+        errors.report_refused(channels[1].refused_messages());
+        assert!(errors.abort_notice().blamed.contains(&PartyIndex::from(0)));
+        Ok(())
+    }
+
+    #[test]
+    fn shares_confidential() {
+        let (vss, shares) = VerifiableSS::share(1, 3, &FE::random());
+        assert!(keygen::Message::R3(FeldmanVSS {
+            vss: vss.clone(),
+            share: (1, shares[0].clone()),
+        })
+        .is_confidential());
+        assert!(resharing::Message::R3(resharing::VSS {
+            share: (1, shares[0].clone()),
+            vss,
+        })
+        .is_confidential());
+        assert!(
+            !keygen::Message::R4(DLogProof::prove(&session(), &FE::random())).is_confidential()
+        );
+    }
+}
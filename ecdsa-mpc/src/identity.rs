@@ -0,0 +1,547 @@
+//! Signed messages of parties with long-term identity keys
+//!
+//! By default the crate trusts the `sender` of each [`InputMessage`], as filled in by the network layer.
+//! The module is an optional layer between the state machine and the network, which makes the sender of each message verifiable.
+//!
+//! In the identity mode each party owns long-term [`IdentityKeys`] for `ed25519` signatures of `sodiumoxide`, and its `PartyIndex` is the hash of the public key,
+//! see [`IdentityPublicKey::party_index`]. Parties pass such indexes to the protocols of the crate instead of indexes assigned by the application.
+//!
+//! [`Authenticator::sign`] signs each [`OutputMessage`] along with the index of its sender, its recipient and its header, and attaches the public key of the signer.
+//! As the index is derived from the key, the receiver does not need a directory of keys of its peers: [`Authenticator::verify`] refuses the message
+//! unless its key hashes to the claimed sender and its signature is valid. Refused messages are counted per sender and reason as [`UnverifiedMessage`],
+//! and can be added to the error state of the protocol with its `report_unverified`, like messages rejected by the state machine.
+//! The record names the sender claimed by the network, so it identifies the forger only if the network layer authenticates its peers.
+//!
+//! The [`Signed`] message is self-contained evidence of its origin. When a party blames a peer for a faulty message, any third party
+//! can check with [`Signed::attest`] that the message comes from the peer, without trusting the party which blames it.
+//! The authenticator keeps such evidence only for the peers it is told about, up to a limit per peer, see [`Authenticator::with_evidence`].
+//!
+//! [`InputMessage`]: ../protocol/struct.InputMessage.html
+//! [`OutputMessage`]: ../protocol/struct.OutputMessage.html
+//! [`IdentityKeys`]: struct.IdentityKeys.html
+//! [`IdentityPublicKey::party_index`]: struct.IdentityPublicKey.html#method.party_index
+//! [`Authenticator::sign`]: struct.Authenticator.html#method.sign
+//! [`Authenticator::verify`]: struct.Authenticator.html#method.verify
+//! [`Authenticator::with_evidence`]: struct.Authenticator.html#method.with_evidence
+//! [`UnverifiedMessage`]: struct.UnverifiedMessage.html
+//! [`Signed`]: struct.Signed.html
+//! [`Signed::attest`]: struct.Signed.html#method.attest
+
+use crate::protocol::{Address, InputMessage, MessageHeader, OutputMessage, PartyIndex};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sodiumoxide::crypto::sign;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use thiserror::Error;
+
+/// Domain separation of the hash which derives `PartyIndex` from the public key
+const PARTY_INDEX_DOMAIN: &[u8] = b"ecdsa-mpc party index v1";
+
+/// Domain separation of signatures, so that the identity key cannot be tricked into signing protocol messages elsewhere
+const SIGNATURE_DOMAIN: &[u8] = b"ecdsa-mpc signed message v1";
+
+/// Domain separation of signed challenges, so that a signed challenge cannot pass for a signed message or the other way round
+const CHALLENGE_DOMAIN: &[u8] = b"ecdsa-mpc signed challenge v1";
+
+/// The number of distinct records of refused messages the authenticator keeps
+///
+/// Anyone can make up new identities, so a peer could create a new record with each message.
+pub const MAX_UNVERIFIED_RECORDS: usize = 256;
+
+/// Long-term public key of a party
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct IdentityPublicKey(pub [u8; sign::PUBLICKEYBYTES]);
+
+impl IdentityPublicKey {
+    /// The index of the party which owns the key
+    pub fn party_index(&self) -> PartyIndex {
+        let digest = Sha256::new()
+            .chain(PARTY_INDEX_DOMAIN)
+            .chain(self.0)
+            .finalize();
+        let mut result = [0u8; 32];
+        result.copy_from_slice(&digest);
+        PartyIndex(result)
+    }
+
+    /// Checks the signature of the challenge, made by [`IdentityKeys::sign_challenge`](struct.IdentityKeys.html#method.sign_challenge)
+    pub fn verify_challenge(&self, challenge: &[u8], signature: &[u8]) -> bool {
+        sign::Signature::try_from(signature).is_ok_and(|signature| {
+            sign::verify_detached(
+                &signature,
+                &challenge_bytes(challenge),
+                &sign::PublicKey(self.0),
+            )
+        })
+    }
+}
+
+/// Long-term signing key pair of a party
+pub struct IdentityKeys {
+    public_key: sign::PublicKey,
+    secret_key: sign::SecretKey,
+}
+
+impl IdentityKeys {
+    /// generates new random key pair
+    pub fn generate() -> Self {
+        let _ = sodiumoxide::init();
+        let (public_key, secret_key) = sign::gen_keypair();
+        IdentityKeys {
+            public_key,
+            secret_key,
+        }
+    }
+
+    /// Creates the key pair from the seed of its secret key. Returns `None` if the length of the slice is not equal to `sign::SEEDBYTES`
+    pub fn from_seed(bytes: &[u8]) -> Option<Self> {
+        let seed = sign::Seed::from_slice(bytes)?;
+        let (public_key, secret_key) = sign::keypair_from_seed(&seed);
+        Some(IdentityKeys {
+            public_key,
+            secret_key,
+        })
+    }
+
+    pub fn public_key(&self) -> IdentityPublicKey {
+        IdentityPublicKey(self.public_key.0)
+    }
+
+    /// The index of the party, derived from its public key
+    pub fn party_index(&self) -> PartyIndex {
+        self.public_key().party_index()
+    }
+
+    /// Signs the challenge of a server, so that the party proves its identity to the server it connects to
+    pub fn sign_challenge(&self, challenge: &[u8]) -> [u8; sign::SIGNATUREBYTES] {
+        sign::sign_detached(&challenge_bytes(challenge), &self.secret_key).to_bytes()
+    }
+}
+
+fn challenge_bytes(challenge: &[u8]) -> Vec<u8> {
+    let mut bytes = CHALLENGE_DOMAIN.to_vec();
+    bytes.extend_from_slice(challenge);
+    bytes
+}
+
+/// Errors of signing messages
+#[derive(Debug, Error)]
+pub enum SignError {
+    #[error("message serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+}
+
+/// Reason why a signed message fails the verification of its origin
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnverifiedReason {
+    /// the public key attached to the message does not belong to its sender
+    IdentityMismatch,
+    /// the signature of the message is invalid
+    BadSignature,
+    /// the signed message is addressed to another party, comes from another sender, or its header has been changed
+    Misaddressed,
+    /// the signed message cannot be parsed
+    Malformed,
+}
+
+/// The record of a message refused by [`Authenticator::verify`](struct.Authenticator.html#method.verify)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct UnverifiedMessage {
+    pub sender: PartyIndex,
+    pub reason: UnverifiedReason,
+}
+
+/// The signed content of a message
+#[derive(Serialize, Deserialize)]
+struct Envelope<B> {
+    sender: PartyIndex,
+    recipient: Address,
+    header: MessageHeader,
+    body: B,
+}
+
+/// The body of a signed message on the wire
+///
+/// The payload is kept in the serialized form, so that the signature can be checked again by anyone who holds the message.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Signed {
+    pub public_key: IdentityPublicKey,
+    pub payload: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+/// The content of a signed message, attested by its signature
+#[derive(Debug, Clone)]
+pub struct Attested<B> {
+    pub sender: PartyIndex,
+    pub recipient: Address,
+    pub header: MessageHeader,
+    pub body: B,
+}
+
+impl Signed {
+    /// Checks the signature and the identity of the signer, and returns the content of the message
+    ///
+    /// Needs nothing but the message, so that a third party can check which party has sent it.
+    pub fn attest<B: DeserializeOwned>(&self) -> Result<Attested<B>, UnverifiedReason> {
+        let signature = sign::Signature::try_from(self.signature.as_slice())
+            .map_err(|_| UnverifiedReason::Malformed)?;
+        let public_key = sign::PublicKey(self.public_key.0);
+        if !sign::verify_detached(&signature, &signed_bytes(&self.payload), &public_key) {
+            return Err(UnverifiedReason::BadSignature);
+        }
+        let envelope: Envelope<B> =
+            serde_json::from_slice(&self.payload).map_err(|_| UnverifiedReason::Malformed)?;
+        if envelope.sender != self.public_key.party_index() {
+            return Err(UnverifiedReason::IdentityMismatch);
+        }
+        Ok(Attested {
+            sender: envelope.sender,
+            recipient: envelope.recipient,
+            header: envelope.header,
+            body: envelope.body,
+        })
+    }
+}
+
+fn signed_bytes(payload: &[u8]) -> Vec<u8> {
+    let mut bytes = SIGNATURE_DOMAIN.to_vec();
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+/// Signs outgoing messages of the party and verifies incoming messages of its peers
+pub struct Authenticator {
+    keys: IdentityKeys,
+    evidence_limit: usize,
+    evidence: HashMap<PartyIndex, Vec<Signed>>,
+    unverified: HashMap<UnverifiedMessage, usize>,
+    unrecorded: usize,
+}
+
+impl Authenticator {
+    pub fn new(keys: IdentityKeys) -> Self {
+        Authenticator {
+            keys,
+            evidence_limit: 0,
+            evidence: HashMap::new(),
+            unverified: HashMap::new(),
+            unrecorded: 0,
+        }
+    }
+
+    /// Keeps up to `limit` accepted signed messages of each of the peers, so that the party can prove their origin later, see [`evidence_from`](#method.evidence_from)
+    ///
+    /// Messages of other signers, and messages beyond the limit, are not kept.
+    pub fn with_evidence<I>(mut self, peers: I, limit: usize) -> Self
+    where
+        I: IntoIterator<Item = PartyIndex>,
+    {
+        self.evidence_limit = limit;
+        self.evidence = peers.into_iter().map(|p| (p, Vec::new())).collect();
+        self
+    }
+
+    /// The index of the party, derived from its identity key
+    pub fn party_index(&self) -> PartyIndex {
+        self.keys.party_index()
+    }
+
+    /// Signs the message of the party
+    pub fn sign<B: Serialize>(
+        &self,
+        msg: OutputMessage<B>,
+    ) -> Result<OutputMessage<Signed>, SignError> {
+        let payload = serde_json::to_vec(&Envelope {
+            sender: self.party_index(),
+            recipient: msg.recipient.clone(),
+            header: msg.header,
+            body: &msg.body,
+        })?;
+        let signature = sign::sign_detached(&signed_bytes(&payload), &self.keys.secret_key);
+        Ok(OutputMessage {
+            recipient: msg.recipient,
+            header: msg.header,
+            body: Signed {
+                public_key: self.keys.public_key(),
+                payload,
+                signature: signature.to_bytes().to_vec(),
+            },
+        })
+    }
+
+    /// Verifies the message of a peer against the sender claimed by the network
+    ///
+    /// Returns `None` if the message is refused. The message is counted in its record, see [`unverified_messages`](#method.unverified_messages).
+    pub fn verify<B: DeserializeOwned>(
+        &mut self,
+        msg: InputMessage<Signed>,
+    ) -> Option<InputMessage<B>> {
+        let sender = msg.sender;
+        match self.check(&msg) {
+            Ok(body) => {
+                let limit = self.evidence_limit;
+                if let Some(kept) = self.evidence.get_mut(&sender).filter(|k| k.len() < limit) {
+                    kept.push(msg.body);
+                }
+                Some(InputMessage {
+                    sender,
+                    header: msg.header,
+                    body,
+                })
+            }
+            Err(reason) => {
+                log::warn!("message from {} refused: {:?}", sender, reason);
+                let record = UnverifiedMessage { sender, reason };
+                if let Some(count) = self.unverified.get_mut(&record) {
+                    *count += 1;
+                } else if self.unverified.len() < MAX_UNVERIFIED_RECORDS {
+                    self.unverified.insert(record, 1);
+                } else {
+                    self.unrecorded += 1;
+                }
+                None
+            }
+        }
+    }
+
+    /// Numbers of messages refused since the authenticator was created, per sender and reason
+    ///
+    /// At most [`MAX_UNVERIFIED_RECORDS`](constant.MAX_UNVERIFIED_RECORDS.html) distinct records are kept.
+    pub fn unverified_messages(&self) -> &HashMap<UnverifiedMessage, usize> {
+        &self.unverified
+    }
+
+    /// The number of refused messages not counted by [`unverified_messages`](#method.unverified_messages), as their records would exceed the limit
+    pub fn unrecorded_messages(&self) -> usize {
+        self.unrecorded
+    }
+
+    /// Accepted signed messages of the party, if the evidence is kept
+    pub fn evidence_from(&self, party: PartyIndex) -> impl Iterator<Item = &Signed> {
+        self.evidence.get(&party).into_iter().flatten()
+    }
+
+    fn check<B: DeserializeOwned>(
+        &self,
+        msg: &InputMessage<Signed>,
+    ) -> Result<B, UnverifiedReason> {
+        if msg.body.public_key.party_index() != msg.sender {
+            return Err(UnverifiedReason::IdentityMismatch);
+        }
+        let attested = msg.body.attest::<B>()?;
+        let own_address = Address::Peer(self.party_index());
+        if attested.header != msg.header
+            || (attested.recipient != Address::Broadcast && attested.recipient != own_address)
+        {
+            return Err(UnverifiedReason::Misaddressed);
+        }
+        Ok(attested.body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keygen::ErrorState;
+    use crate::protocol::{AbortReport, ProtocolId, Round, Session, SessionId};
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    enum Msg {
+        Commitment(u32),
+        Share(u32),
+    }
+
+    impl Round for Msg {
+        fn round(&self) -> u16 {
+            match self {
+                Msg::Commitment(_) => 1,
+                Msg::Share(_) => 2,
+            }
+        }
+    }
+
+    fn session() -> Session {
+        Session {
+            protocol: ProtocolId::Signing,
+            id: SessionId::random(),
+        }
+    }
+
+    fn authenticators(n: usize) -> Vec<Authenticator> {
+        let keys = (0..n).map(|_| IdentityKeys::generate()).collect::<Vec<_>>();
+        let parties = keys.iter().map(|k| k.party_index()).collect::<Vec<_>>();
+        keys.into_iter()
+            .map(|k| Authenticator::new(k).with_evidence(parties.clone(), 2))
+            .collect()
+    }
+
+    #[test]
+    fn party_index_derived_from_key() {
+        let seed = [7u8; sign::SEEDBYTES];
+        let keys = IdentityKeys::from_seed(&seed).unwrap();
+        let same = IdentityKeys::from_seed(&seed).unwrap();
+        assert_eq!(keys.public_key(), same.public_key());
+        assert_eq!(keys.party_index(), same.party_index());
+        assert_ne!(keys.party_index(), IdentityKeys::generate().party_index());
+        assert!(IdentityKeys::from_seed(&seed[1..]).is_none());
+    }
+
+    #[test]
+    fn challenge_signed_by_identity() {
+        let keys = IdentityKeys::generate();
+        let other = IdentityKeys::generate();
+        let signature = keys.sign_challenge(b"challenge");
+        assert!(keys.public_key().verify_challenge(b"challenge", &signature));
+        assert!(!keys
+            .public_key()
+            .verify_challenge(b"other challenge", &signature));
+        assert!(!other
+            .public_key()
+            .verify_challenge(b"challenge", &signature));
+        assert!(!keys
+            .public_key()
+            .verify_challenge(b"challenge", &signature[1..]));
+        // signatures of messages are not signatures of challenges
+        let message = sign::sign_detached(&signed_bytes(b"challenge"), &keys.secret_key);
+        assert!(!keys
+            .public_key()
+            .verify_challenge(b"challenge", &message.to_bytes()));
+    }
+
+    #[test]
+    fn forged_sender_refused() -> anyhow::Result<()> {
+        let mut parties = authenticators(3);
+        let session = session();
+        let alice = parties[0].party_index();
+        let mallory = parties[2].party_index();
+
+        let signed = parties[0].sign(OutputMessage::new(
+            session,
+            Address::Broadcast,
+            Msg::Commitment(7),
+        ))?;
+        let accepted = parties[1]
+            .verify::<Msg>(InputMessage::from_output(alice, signed.clone()))
+            .unwrap();
+        assert_eq!(accepted.body, Msg::Commitment(7));
+
+        // the network claims another sender
+        assert!(parties[1]
+            .verify::<Msg>(InputMessage::from_output(mallory, signed.clone()))
+            .is_none());
+
+        // mallory replaces the body, keeping the key of alice
+        let mut tampered = signed.clone();
+        tampered.body.payload = serde_json::to_vec(&Envelope {
+            sender: alice,
+            recipient: Address::Broadcast,
+            header: signed.header,
+            body: Msg::Commitment(8),
+        })?;
+        assert!(parties[1]
+            .verify::<Msg>(InputMessage::from_output(alice, tampered))
+            .is_none());
+
+        // mallory signs the message with its own key, claiming to be alice
+        let mut impersonated = parties[2].sign(OutputMessage::new(
+            session,
+            Address::Broadcast,
+            Msg::Commitment(8),
+        ))?;
+        impersonated.body.public_key = parties[0].keys.public_key();
+        assert!(parties[1]
+            .verify::<Msg>(InputMessage::from_output(alice, impersonated))
+            .is_none());
+
+        // the share for mallory is not accepted by another peer, nor in another round
+        let share = parties[0].sign(OutputMessage::new(
+            session,
+            Address::Peer(mallory),
+            Msg::Share(42),
+        ))?;
+        assert!(parties[1]
+            .verify::<Msg>(InputMessage::from_output(alice, share.clone()))
+            .is_none());
+        let mut moved = InputMessage::from_output(alice, share);
+        moved.header.round = 1;
+        assert!(parties[2].verify::<Msg>(moved).is_none());
+
+        let record = |sender, reason| UnverifiedMessage { sender, reason };
+        assert_eq!(
+            parties[1].unverified_messages(),
+            &HashMap::from([
+                (record(mallory, UnverifiedReason::IdentityMismatch), 1),
+                (record(alice, UnverifiedReason::BadSignature), 2),
+                (record(alice, UnverifiedReason::Misaddressed), 1),
+            ])
+        );
+        assert_eq!(
+            parties[2].unverified_messages(),
+            &HashMap::from([(record(alice, UnverifiedReason::Misaddressed), 1)])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn forged_sender_blamed() -> anyhow::Result<()> {
+        let mut parties = authenticators(3);
+        let mallory = parties[2].party_index();
+        let signed = parties[0].sign(OutputMessage::new(
+            session(),
+            Address::Broadcast,
+            Msg::Commitment(7),
+        ))?;
+        assert!(parties[1]
+            .verify::<Msg>(InputMessage::from_output(mallory, signed))
+            .is_none());
+
+        let mut errors = ErrorState::new(Vec::new());
+        errors.report_unverified(parties[1].unverified_messages());
+        assert_eq!(errors.abort_notice().blamed, vec![mallory]);
+        Ok(())
+    }
+
+    #[test]
+    fn evidence_attested_by_third_party() -> anyhow::Result<()> {
+        let mut parties = authenticators(2);
+        let session = session();
+        let alice = parties[0].party_index();
+
+        for share in [13, 14, 15] {
+            let signed = parties[0].sign(OutputMessage::new(
+                session,
+                Address::Peer(parties[1].party_index()),
+                Msg::Share(share),
+            ))?;
+            assert!(parties[1]
+                .verify::<Msg>(InputMessage::from_output(alice, signed))
+                .is_some());
+        }
+        // the stranger has a valid identity, but its messages are not kept
+        let stranger = Authenticator::new(IdentityKeys::generate());
+        let signed = stranger.sign(OutputMessage::new(
+            session,
+            Address::Broadcast,
+            Msg::Commitment(1),
+        ))?;
+        assert!(parties[1]
+            .verify::<Msg>(InputMessage::from_output(stranger.party_index(), signed))
+            .is_some());
+        assert!(parties[1]
+            .evidence_from(stranger.party_index())
+            .next()
+            .is_none());
+
+        // the receiver blames alice, and hands the message over to a judge
+        let evidence = parties[1].evidence_from(alice).cloned().collect::<Vec<_>>();
+        assert_eq!(evidence.len(), 2);
+        let attested = evidence[0].attest::<Msg>().unwrap();
+        assert_eq!(attested.sender, alice);
+        assert_eq!(attested.header.session, session);
+        assert_eq!(attested.body, Msg::Share(13));
+        Ok(())
+    }
+}
@@ -5,6 +5,9 @@
 //! * The key shard is sampled randomly by each party. The resulting public key is the product of partial public keys and the resulting private key is the sum of individual private keys.
 //! * Private keys are shared with Shamir's secret sharing schema. The signing threshold is fixed for a key.
 //! * Traditional attacks on this schema are addressed by non-malleable commitments to a partial public key and by Feldman's verifiable secret sharing.
+//! * Each party proves that its Paillier modulus and the modulus of its range proof setup are products of two Blum primes in the first round.
+//!   In the second round, it proves to each of other parties that these moduli have no small factors, with the range proof setup of that party,
+//!   and each party verifies the proofs before it deals shares of its key.
 //!
 //! # Details
 //!
@@ -17,7 +20,7 @@
 //! * creates the stream `protocol_sink` for incoming messages
 //! * creates another stream `state_machine_stream` for outgoing messages
 //! * generates new keys
-//! * instantiates [`Phase1`] of the protocol with parameters *(key, initial secrets, optional range proof setup, own party index,  key loader)*
+//! * instantiates [`Phase1`] of the protocol with parameters *(session id, key, initial secrets, optional range proof setup, own party index,  key loader)*
 //! * creates state machine providing the [`Phase1`] object and streams as arguments
 //! * executes the machine and obtains the result
 //! ```text
@@ -30,6 +33,7 @@
 //!   let key_loader = Arc::new(Box::new(SecretKeyLoaderImpl::new(the_vault)));
 //!
 //!   let start_phase = Box::new(Phase1::new(
+//!             session_id,
 //!             &parameters,
 //!             init_pubkeys,
 //!             range_proofs_setup,
@@ -104,15 +108,20 @@
 
 use std::collections::{BTreeSet, HashMap, HashSet};
 
+use crate::limits::Limits;
+use crate::proofs::DLogProof;
 use crate::types::{CommitmentScheme, InitialPublicKeys, Parameters, PointFromBytesError};
-use crate::types::{CurvDLogProofType, DLogProof, SecretShares, VerifiableSS};
-use crate::utils::is_valid_curve_point;
+use crate::types::{SecretShares, VerifiableSS};
+use crate::utils::{is_valid_curve_point, phase_snapshot};
 use algorithms::types::{ManagedPaillierDecryptionKey, PaillierKeys};
 
-use crate::messages::{FeldmanVSS, SecretShare};
+use crate::messages::{Abort, FactorProofs, FeldmanVSS, ModulusProofs, SecretShare};
 use algorithms::nizk_rsa;
 
-use crate::protocol::{Address, PartyIndex};
+use crate::protocol::{
+    abort_broadcast, aborted_by_peer, resend_requests, resent_output, AbortReport, Address,
+    PartyIndex, ProtocolId, SenderRole, Session, SessionId,
+};
 use crate::types::{BigInt, CurvVerifiableSS, InitialKeys, Point, Scalar, FE, GE};
 pub use paillier::DecryptionKey;
 use paillier::EncryptionKey;
@@ -121,8 +130,14 @@ use thiserror::Error;
 #[doc(inline)]
 pub use super::messages::keygen::{DecommitPublicKey, InMsg, Message, OutMsg, Phase1Broadcast};
 
+use crate::encryption::{RefuseReason, RefusedMessage, ReportRefused};
+use crate::identity::{UnverifiedMessage, UnverifiedReason};
 use serde::{Deserialize, Serialize};
-use state_machine::types::{State, StateMachineTraits, Transition};
+use state_machine::checkpoint::RestoreState;
+use state_machine::types::{
+    BoxedState, MessageClass, RejectReason, RejectedMessage, Resent, State, StateMachineTraits,
+    Transition,
+};
 
 use crate::types::One;
 use crate::zk_range_proofs::{ZkpPublicSetup, ZkpSetup, ZkpSetupVerificationError};
@@ -143,6 +158,27 @@ pub trait SecretKeyLoader: std::fmt::Debug {
 #[derive(Debug)]
 pub struct SecretKeyLoaderError(pub String);
 
+/// Stands in for the loader of a phase restored from a snapshot, until the actual loader is provided
+#[derive(Debug)]
+struct MissingSecretKeyLoader;
+
+impl SecretKeyLoader for MissingSecretKeyLoader {
+    fn get_initial_secret(&self) -> Result<Box<FE>, SecretKeyLoaderError> {
+        Err(SecretKeyLoaderError(
+            "secret key loader is not restored".to_string(),
+        ))
+    }
+    fn get_paillier_secret(&self) -> Result<Box<DecryptionKey>, SecretKeyLoaderError> {
+        Err(SecretKeyLoaderError(
+            "secret key loader is not restored".to_string(),
+        ))
+    }
+}
+
+fn missing_secret_key_loader() -> ASecretKeyLoader {
+    Arc::new(Box::new(MissingSecretKeyLoader))
+}
+
 /// the type of the reference to [`SecretKeyLoader`]  used by key generation protocol to load secret key of EC schema or secret Paillier key
 ///
 /// # Rationale
@@ -194,6 +230,10 @@ pub enum KeygenError {
     InvalidDlogProof { proof: String, party: PartyIndex },
     #[error("invalid correct key proof {proof}, party {party}")]
     InvalidCorrectKeyProof { proof: String, party: PartyIndex },
+    #[error("invalid modulus proof: {reason}, party {party}")]
+    InvalidModulusProof { reason: String, party: PartyIndex },
+    #[error("invalid no small factor proof: {reason}, party {party}")]
+    InvalidFactorProof { reason: String, party: PartyIndex },
     #[error("missing range proof from {party} ")]
     RangeProofSetupMissing { party: PartyIndex },
     #[error("unexpected range proof from {party}, proof {proof:?} ")]
@@ -202,10 +242,54 @@ pub enum KeygenError {
     RangeProofSetupDlogProofFailed { proof: String, party: PartyIndex },
     #[error("protocol setup error: {0}")]
     ProtocolSetupError(String),
+    #[error("message rejected: {reason:?}, party {party}")]
+    RejectedMessage {
+        party: PartyIndex,
+        reason: RejectReason,
+    },
+    #[error("message refused by secure channel: {reason:?}, party {party}")]
+    RefusedMessage {
+        party: PartyIndex,
+        reason: RefuseReason,
+    },
+    #[error("message failed identity verification: {reason:?}, party {party}")]
+    UnverifiedMessage {
+        party: PartyIndex,
+        reason: UnverifiedReason,
+    },
+    #[error("party {party} aborted the protocol: {reason}")]
+    PeerAborted {
+        party: PartyIndex,
+        reason: String,
+        blamed: Vec<PartyIndex>,
+    },
     #[error("{0}")]
     GeneralError(String),
 }
 
+impl KeygenError {
+    /// The party which the error is attributed to, if any
+    pub fn blamed_party(&self) -> Option<PartyIndex> {
+        match self {
+            KeygenError::InvalidComm { party, .. }
+            | KeygenError::InvalidVSS { party, .. }
+            | KeygenError::InvalidPublicKey { party, .. }
+            | KeygenError::UnknownMessageType { party, .. }
+            | KeygenError::InvalidDlogProof { party, .. }
+            | KeygenError::InvalidCorrectKeyProof { party, .. }
+            | KeygenError::InvalidModulusProof { party, .. }
+            | KeygenError::InvalidFactorProof { party, .. }
+            | KeygenError::RangeProofSetupMissing { party }
+            | KeygenError::RangeProofSetupUnexpected { party, .. }
+            | KeygenError::RangeProofSetupDlogProofFailed { party, .. }
+            | KeygenError::RejectedMessage { party, .. }
+            | KeygenError::RefusedMessage { party, .. }
+            | KeygenError::UnverifiedMessage { party, .. } => Some(*party),
+            _ => None,
+        }
+    }
+}
+
 impl InitialKeys {
     /// samples from randomness
     pub fn random() -> Self {
@@ -261,6 +345,18 @@ fn is_broadcast_input_complete(
     other_parties.iter().all(|p| senders.contains(p))
 }
 
+/// Parties of the set which have not delivered their messages yet
+fn missing_senders(
+    current_msg_set: &[InMsg],
+    other_parties: &BTreeSet<PartyIndex>,
+) -> Vec<PartyIndex> {
+    other_parties
+        .iter()
+        .filter(|p| current_msg_set.iter().all(|m| m.sender != **p))
+        .copied()
+        .collect()
+}
+
 /// Extracts payloads form enum variants of input message into the hash map
 #[trace(disable(current_msg_set), res = "{:?}")]
 fn to_hash_map_gen<K, V>(current_msg_set: Vec<InMsg>) -> Result<HashMap<K, V>, Vec<KeygenError>>
@@ -313,6 +409,41 @@ impl StateMachineTraits for KeyGeneratorTraits {
 }
 pub type MachineResult = Result<FinalState, ErrorState>;
 
+/// Phases of the protocol restored from snapshots
+#[derive(Deserialize)]
+enum PhaseSnapshot {
+    Phase1(Phase1),
+    Phase2(Phase2),
+    Phase3(Phase3),
+    Phase4(Phase4),
+}
+
+/// Restores phases of key generation. Secret keys are loaded by the loader passed as the context of restoring.
+impl RestoreState for KeyGeneratorTraits {
+    type Context = ASecretKeyLoader;
+
+    fn restore_state(
+        snapshot: serde_json::Value,
+        secret_key_loader: &ASecretKeyLoader,
+    ) -> Result<BoxedState<Self>, serde_json::Error> {
+        Ok(match serde_json::from_value(snapshot)? {
+            PhaseSnapshot::Phase1(mut phase) => {
+                phase.secret_key_loader = secret_key_loader.clone();
+                Box::new(phase)
+            }
+            PhaseSnapshot::Phase2(mut phase) => {
+                phase.secret_key_loader = secret_key_loader.clone();
+                Box::new(phase)
+            }
+            PhaseSnapshot::Phase3(mut phase) => {
+                phase.secret_key_loader = secret_key_loader.clone();
+                Box::new(phase)
+            }
+            PhaseSnapshot::Phase4(phase) => Box::new(phase),
+        })
+    }
+}
+
 /// First phase of key generation protocol
 ///
 /// Generates and broadcasts:
@@ -322,15 +453,20 @@ pub type MachineResult = Result<FinalState, ErrorState>;
 /// * proof of correct range proof setup, if applicable
 ///
 /// Receives all aforementioned from other parties, verifies proofs. If succeeds , stores commitments, public Paiilier keys, and public range proof setups  to their respective hash maps.
-#[derive(Debug)]
+/// Then proves to each of other parties that own moduli have no small factors, using range proof setup of that party, so that the proofs travel with the decommitment of the second phase.
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Phase1 {
+    session: Session,
+    limits: Limits,
     params: Parameters,
     keys: InitialPublicKeys,
     own_party_index: PartyIndex,
     other_parties: BTreeSet<PartyIndex>,
     comm_scheme: CommitmentScheme,
+    #[serde(skip, default = "missing_secret_key_loader")]
     secret_key_loader: ASecretKeyLoader,
     paillier_key_proof: CorrectKeyProof,
+    modulus_proofs: ModulusProofs,
     range_proof_setup: Option<ZkpSetup>,
     timeout: Option<Duration>,
 }
@@ -344,7 +480,9 @@ fn verify_zkp_public_setup(setup: &ZkpSetup) -> Result<(), ZkpSetupVerificationE
 #[trace(pretty, prefix = "Phase1::")]
 impl Phase1 {
     /// initializes the protocol
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
+        session_id: SessionId,
         params: &Parameters,
         init_keys: InitialPublicKeys,
         range_proof_setup: Option<ZkpSetup>,
@@ -352,8 +490,13 @@ impl Phase1 {
         own_party_index: PartyIndex,
         secret_key_loader: ASecretKeyLoader,
         timeout: Option<Duration>,
+        limits: Limits,
     ) -> Result<Self, KeygenError> {
-        let proof = {
+        let session = Session {
+            protocol: ProtocolId::Keygen,
+            id: session_id,
+        };
+        let (proof, modulus_proofs) = {
             let dk = secret_key_loader
                 .get_paillier_secret()
                 .map(ManagedPaillierDecryptionKey)
@@ -363,9 +506,17 @@ impl Phase1 {
                     "invalid own Paillier key".to_string(),
                 ));
             }
-            nizk_rsa::gen_proof(&dk.0)
+            let context = session.to_bigint();
+            let modulus_proofs = ModulusProofs::new(&dk.0, range_proof_setup.as_ref(), &context)
+                .map_err(|e| {
+                    KeygenError::ProtocolSetupError(format!("invalid own moduli: {}", e))
+                })?;
+            (
+                nizk_rsa::gen_proof_with_context(&dk.0, &context),
+                modulus_proofs,
+            )
         };
-        let scheme = CommitmentScheme::from_GE(&init_keys.y_i);
+        let scheme = CommitmentScheme::from_GE(&session, &init_keys.y_i);
 
         let acting_parties = BTreeSet::from_iter(parties.iter().cloned());
         if acting_parties.len() != parties.len() {
@@ -391,6 +542,7 @@ impl Phase1 {
             ));
         }
         Ok(Phase1 {
+            session,
             params: *params,
             keys: init_keys,
             own_party_index,
@@ -398,12 +550,46 @@ impl Phase1 {
             comm_scheme: scheme,
             secret_key_loader,
             paillier_key_proof: CorrectKeyProof(proof),
+            modulus_proofs,
             range_proof_setup,
             timeout,
+            limits,
         })
     }
 }
 
+impl Phase1 {
+    /// proves to each of other parties that own moduli have no small factors, using range proof setup of that party
+    #[allow(clippy::result_large_err)]
+    fn factor_proofs(
+        &self,
+        setups: Option<&RangeProofSetups>,
+    ) -> Result<HashMap<PartyIndex, FactorProofs>, KeygenError> {
+        let setups = setups.ok_or_else(|| {
+            KeygenError::ProtocolSetupError("range proof setups are missing".to_string())
+        })?;
+        let dk = self
+            .secret_key_loader
+            .get_paillier_secret()
+            .map(ManagedPaillierDecryptionKey)
+            .map_err(|e| KeygenError::GeneralError(e.0))?;
+        let context = self.session.to_bigint();
+        self.other_parties
+            .iter()
+            .map(|party| match setups.party_setups.get(party) {
+                Some(setup) => Ok((
+                    *party,
+                    FactorProofs::new(&dk.0, &setups.my_setup, setup, &context),
+                )),
+                None => Err(KeygenError::ProtocolSetupError(format!(
+                    "range proof setup of party {} is missing",
+                    party
+                ))),
+            })
+            .collect()
+    }
+}
+
 #[trace(pretty, prefix = "Phase1::")]
 impl State<KeyGeneratorTraits> for Phase1 {
     fn start(&mut self) -> Option<OutMsgVec> {
@@ -413,20 +599,29 @@ impl State<KeyGeneratorTraits> for Phase1 {
             .as_ref()
             .map(ZkpPublicSetup::from_private_zkp_setup);
 
-        let output = vec![OutMsg {
-            recipient: Address::Broadcast,
-            body: Message::R1(Phase1Broadcast {
+        let output = vec![OutMsg::new(
+            self.session,
+            Address::Broadcast,
+            Message::R1(Phase1Broadcast {
                 com: self.comm_scheme.comm.clone(),
                 e: self.keys.paillier_encryption_key.clone(),
                 correct_key_proof: self.paillier_key_proof.clone(),
                 range_proof_setup: zkp_public_setup,
+                modulus_proofs: self.modulus_proofs.clone(),
             }),
-        }];
+        )];
         Some(output)
     }
     #[trace(disable(input))]
-    fn is_message_expected(&self, msg: &InMsg, input: &[InMsg]) -> bool {
-        matches!(msg.body, Message::R1(_) if self.other_parties.contains(&msg.sender) && !msg.is_duplicate(input))
+    fn classify_message(&self, msg: &InMsg, input: &[InMsg]) -> MessageClass {
+        msg.classify(
+            &self.session,
+            &self.limits,
+            1,
+            matches!(msg.body, Message::R1(_)),
+            SenderRole::of_member(self.other_parties.contains(&msg.sender)),
+            input,
+        )
     }
 
     #[trace(disable(current_msg_set))]
@@ -434,6 +629,11 @@ impl State<KeyGeneratorTraits> for Phase1 {
         is_broadcast_input_complete(current_msg_set, &self.other_parties)
     }
 
+    #[trace(disable(current_msg_set))]
+    fn awaited_senders(&self, current_msg_set: &[InMsg]) -> Vec<PartyIndex> {
+        missing_senders(current_msg_set, &self.other_parties)
+    }
+
     fn consume(&self, current_msg_set: Vec<InMsg>) -> Transition<KeyGeneratorTraits> {
         match to_hash_map_gen::<PartyIndex, Phase1Broadcast>(current_msg_set) {
             Ok(comms) => {
@@ -479,7 +679,16 @@ impl State<KeyGeneratorTraits> for Phase1 {
                                 })
                                 .collect::<HashMap<_, _>>(),
                         });
+                    let factor_proofs = match self.factor_proofs(range_proof_setups.as_ref()) {
+                        Ok(proofs) => proofs,
+                        Err(e) => {
+                            log::error!("Phase1 returns error {:?}", e);
+                            return Transition::FinalState(Err(ErrorState::new(vec![e])));
+                        }
+                    };
                     Transition::NewState(Box::new(Phase2 {
+                        session: self.session,
+                        limits: self.limits,
                         keys: self.keys.clone(),
                         params: self.params,
                         own_party_index: self.own_party_index,
@@ -488,6 +697,7 @@ impl State<KeyGeneratorTraits> for Phase1 {
                         commitments: comms,
                         secret_key_loader: self.secret_key_loader.clone(),
                         range_proof_setups,
+                        factor_proofs,
                         timeout: self.timeout,
                     }))
                 } else {
@@ -511,18 +721,67 @@ impl State<KeyGeneratorTraits> for Phase1 {
     fn timeout(&self) -> Option<Duration> {
         self.timeout
     }
+
+    #[trace(disable(res))]
+    fn snapshot(&self) -> Option<serde_json::Value> {
+        phase_snapshot("Phase1", self)
+    }
+
+    fn name(&self) -> &'static str {
+        "keygen.phase1"
+    }
+
+    fn abort_output(&self, error: &ErrorState) -> Option<Vec<OutMsg>> {
+        abort_broadcast(self.session, error)
+    }
+
+    fn peer_aborted(&self, msg: &InMsg) -> Option<ErrorState> {
+        aborted_by_peer(
+            &self.session,
+            &self.limits,
+            msg,
+            self.other_parties.contains(&msg.sender),
+        )
+    }
+
+    #[trace(disable(current_msg_set))]
+    fn resend_request(&self, current_msg_set: &[InMsg]) -> Option<Vec<OutMsg>> {
+        resend_requests(
+            self.session,
+            1,
+            missing_senders(current_msg_set, &self.other_parties),
+        )
+    }
+
+    fn resend_output(&self, msg: &InMsg, sent: &[OutMsg]) -> Option<Resent<OutMsg>> {
+        resent_output(
+            &self.session,
+            &self.limits,
+            msg,
+            self.other_parties.contains(&msg.sender),
+            sent,
+        )
+    }
 }
 
 /// Second phase of the protocol: broadcasts decommitments, verifies them, and verifies Pailliier key correctness
+///
+/// The decommitment is sent to each of other parties along with proofs that own moduli have no small factors,
+/// made with the range proof setup of that party. The proofs of other parties are verified before the party deals shares of its key in the third phase.
+#[derive(Serialize, Deserialize)]
 struct Phase2 {
+    session: Session,
+    limits: Limits,
     params: Parameters,
     keys: InitialPublicKeys,
     own_party_index: PartyIndex,
     other_parties: BTreeSet<PartyIndex>,
     comm_scheme: CommitmentScheme,
     commitments: HashMap<PartyIndex, Phase1Broadcast>,
+    #[serde(skip, default = "missing_secret_key_loader")]
     secret_key_loader: ASecretKeyLoader,
     range_proof_setups: Option<RangeProofSetups>,
+    factor_proofs: HashMap<PartyIndex, FactorProofs>,
     timeout: Option<Duration>,
 }
 
@@ -553,18 +812,34 @@ impl Phase2 {
 impl State<KeyGeneratorTraits> for Phase2 {
     fn start(&mut self) -> Option<OutMsgVec> {
         log::debug!("Phase2 starts");
-        Some(vec![OutMsg {
-            recipient: Address::Broadcast,
-            body: Message::R2(DecommitPublicKey {
-                y_i: self.keys.y_i.clone(),
-                blind_factor: self.comm_scheme.decomm.clone(),
-            }),
-        }])
+        Some(
+            self.factor_proofs
+                .iter()
+                .map(|(party, proofs)| {
+                    OutMsg::new(
+                        self.session,
+                        Address::Peer(*party),
+                        Message::R2(DecommitPublicKey {
+                            y_i: self.keys.y_i.clone(),
+                            blind_factor: self.comm_scheme.decomm.clone(),
+                            factor_proofs: proofs.clone(),
+                        }),
+                    )
+                })
+                .collect(),
+        )
     }
 
     #[trace(disable(input))]
-    fn is_message_expected(&self, msg: &InMsg, input: &[InMsg]) -> bool {
-        matches!(msg.body, Message::R2(_) if self.other_parties.contains(&msg.sender) && !msg.is_duplicate(input))
+    fn classify_message(&self, msg: &InMsg, input: &[InMsg]) -> MessageClass {
+        msg.classify(
+            &self.session,
+            &self.limits,
+            2,
+            matches!(msg.body, Message::R2(_)),
+            SenderRole::of_member(self.other_parties.contains(&msg.sender)),
+            input,
+        )
     }
 
     #[trace(disable(current_msg_set))]
@@ -572,6 +847,11 @@ impl State<KeyGeneratorTraits> for Phase2 {
         is_broadcast_input_complete(current_msg_set, &self.other_parties)
     }
 
+    #[trace(disable(current_msg_set))]
+    fn awaited_senders(&self, current_msg_set: &[InMsg]) -> Vec<PartyIndex> {
+        missing_senders(current_msg_set, &self.other_parties)
+    }
+
     fn consume(&self, current_msg_set: Vec<InMsg>) -> Transition<KeyGeneratorTraits> {
         let decomms = match to_hash_map_gen::<PartyIndex, DecommitPublicKey>(current_msg_set) {
             Ok(map) => map,
@@ -584,7 +864,13 @@ impl State<KeyGeneratorTraits> for Phase2 {
             .commitments
             .iter()
             .filter_map(|(party, msg)| {
-                if nizk_rsa::verify(&msg.e, &msg.correct_key_proof.0).is_err() {
+                if nizk_rsa::verify_with_context(
+                    &msg.e,
+                    &msg.correct_key_proof.0,
+                    &self.session.to_bigint(),
+                )
+                .is_err()
+                {
                     Some(KeygenError::InvalidCorrectKeyProof {
                         proof: format!("{:?}", msg.correct_key_proof),
                         party: *party,
@@ -597,6 +883,52 @@ impl State<KeyGeneratorTraits> for Phase2 {
 
         errors.extend(factorization_errors);
 
+        let modulus_errors = self
+            .commitments
+            .iter()
+            .filter_map(|(party, msg)| {
+                msg.modulus_proofs
+                    .verify(
+                        &msg.e,
+                        msg.range_proof_setup.as_ref(),
+                        &self.session.to_bigint(),
+                    )
+                    .err()
+                    .map(|e| KeygenError::InvalidModulusProof {
+                        reason: e.to_string(),
+                        party: *party,
+                    })
+            })
+            .collect::<Vec<_>>();
+
+        errors.extend(modulus_errors);
+
+        let factor_errors = decomms
+            .iter()
+            .filter_map(|(party, decomm)| {
+                let verified = match &self.range_proof_setups {
+                    Some(setups) => {
+                        match (self.commitments.get(party), setups.party_setups.get(party)) {
+                            (Some(comm), Some(setup)) => decomm
+                                .factor_proofs
+                                .verify(&comm.e, setup, &setups.my_setup, &self.session.to_bigint())
+                                .map_err(|e| e.to_string()),
+                            _ => Err("moduli of the party are unknown".to_string()),
+                        }
+                    }
+                    None => Err("range proof setups are missing".to_string()),
+                };
+                verified
+                    .err()
+                    .map(|reason| KeygenError::InvalidFactorProof {
+                        reason,
+                        party: *party,
+                    })
+            })
+            .collect::<Vec<_>>();
+
+        errors.extend(factor_errors);
+
         let party_index_set = self
             .commitments
             .keys()
@@ -614,7 +946,7 @@ impl State<KeyGeneratorTraits> for Phase2 {
                             comm: comm.com.clone(),
                             decomm: decomm.blind_factor.clone(),
                         };
-                        if scheme.verify_commitment(&decomm.y_i) {
+                        if scheme.verify_commitment(&self.session, &decomm.y_i) {
                             None
                         } else {
                             Some(KeygenError::InvalidComm {
@@ -678,6 +1010,8 @@ impl State<KeyGeneratorTraits> for Phase2 {
             .collect::<HashMap<_, _>>();
 
         Transition::NewState(Box::new(Phase3 {
+            session: self.session,
+            limits: self.limits,
             params: self.params,
             keys: self.keys.clone(),
             own_party_index: self.own_party_index,
@@ -701,6 +1035,47 @@ impl State<KeyGeneratorTraits> for Phase2 {
             phase: "phase2".to_string(),
         }]))
     }
+
+    #[trace(disable(res))]
+    fn snapshot(&self) -> Option<serde_json::Value> {
+        phase_snapshot("Phase2", self)
+    }
+
+    fn name(&self) -> &'static str {
+        "keygen.phase2"
+    }
+
+    fn abort_output(&self, error: &ErrorState) -> Option<Vec<OutMsg>> {
+        abort_broadcast(self.session, error)
+    }
+
+    fn peer_aborted(&self, msg: &InMsg) -> Option<ErrorState> {
+        aborted_by_peer(
+            &self.session,
+            &self.limits,
+            msg,
+            self.other_parties.contains(&msg.sender),
+        )
+    }
+
+    #[trace(disable(current_msg_set))]
+    fn resend_request(&self, current_msg_set: &[InMsg]) -> Option<Vec<OutMsg>> {
+        resend_requests(
+            self.session,
+            2,
+            missing_senders(current_msg_set, &self.other_parties),
+        )
+    }
+
+    fn resend_output(&self, msg: &InMsg, sent: &[OutMsg]) -> Option<Resent<OutMsg>> {
+        resent_output(
+            &self.session,
+            &self.limits,
+            msg,
+            self.other_parties.contains(&msg.sender),
+            sent,
+        )
+    }
 }
 /// Computes the sum of points on the curve and validates every point
 /// Returns Ok(Some(pk)) on success
@@ -739,7 +1114,10 @@ fn try_computing_public_key(pubkey_map: &HashMap<PartyIndex, GE>) -> Result<GE,
 }
 
 /// Third phase of the protocol: broadcasts Shamir's shares with Feldman's proofs and verifies them
+#[derive(Serialize, Deserialize)]
 struct Phase3 {
+    session: Session,
+    limits: Limits,
     params: Parameters,
     keys: InitialPublicKeys,
     own_party_index: PartyIndex,
@@ -749,6 +1127,7 @@ struct Phase3 {
     own_point: SecretShare,
     other_points: HashMap<PartyIndex, SecretShare>,
     vss_scheme: CurvVerifiableSS,
+    #[serde(skip, default = "missing_secret_key_loader")]
     secret_key_loader: ASecretKeyLoader,
     range_proof_setups: Option<RangeProofSetups>,
     timeout: Option<Duration>,
@@ -762,20 +1141,30 @@ impl State<KeyGeneratorTraits> for Phase3 {
         Some(
             self.other_points
                 .iter()
-                .map(|(party, share_xy)| OutMsg {
-                    recipient: Address::Peer(*party),
-                    body: Message::R3(FeldmanVSS {
-                        vss: self.vss_scheme.clone(),
-                        share: share_xy.clone(),
-                    }),
+                .map(|(party, share_xy)| {
+                    OutMsg::new(
+                        self.session,
+                        Address::Peer(*party),
+                        Message::R3(FeldmanVSS {
+                            vss: self.vss_scheme.clone(),
+                            share: share_xy.clone(),
+                        }),
+                    )
                 })
                 .collect::<OutMsgVec>(),
         )
     }
 
     #[trace(disable(input))]
-    fn is_message_expected(&self, msg: &InMsg, input: &[InMsg]) -> bool {
-        matches!(msg.body, Message::R3(_)  if self.other_parties.contains(&msg.sender) && !msg.is_duplicate(input))
+    fn classify_message(&self, msg: &InMsg, input: &[InMsg]) -> MessageClass {
+        msg.classify(
+            &self.session,
+            &self.limits,
+            3,
+            matches!(msg.body, Message::R3(_)),
+            SenderRole::of_member(self.other_parties.contains(&msg.sender)),
+            input,
+        )
     }
 
     #[trace(disable(current_msg_set))]
@@ -783,6 +1172,11 @@ impl State<KeyGeneratorTraits> for Phase3 {
         is_broadcast_input_complete(current_msg_set, &self.other_parties)
     }
 
+    #[trace(disable(current_msg_set))]
+    fn awaited_senders(&self, current_msg_set: &[InMsg]) -> Vec<PartyIndex> {
+        missing_senders(current_msg_set, &self.other_parties)
+    }
+
     fn consume(&self, current_msg_set: Vec<InMsg>) -> Transition<KeyGeneratorTraits> {
         let mut shares = match to_hash_map_gen::<PartyIndex, FeldmanVSS>(current_msg_set) {
             Ok(map) => map,
@@ -867,6 +1261,8 @@ impl State<KeyGeneratorTraits> for Phase3 {
             .collect();
 
         Transition::NewState(Box::new(Phase4 {
+            session: self.session,
+            limits: self.limits,
             own_party_index: self.own_party_index,
             other_parties: self.other_parties.clone(),
             multiparty_shared: MultiPartyInfo {
@@ -894,10 +1290,54 @@ impl State<KeyGeneratorTraits> for Phase3 {
     fn timeout(&self) -> Option<Duration> {
         self.timeout
     }
+
+    #[trace(disable(res))]
+    fn snapshot(&self) -> Option<serde_json::Value> {
+        phase_snapshot("Phase3", self)
+    }
+
+    fn name(&self) -> &'static str {
+        "keygen.phase3"
+    }
+
+    fn abort_output(&self, error: &ErrorState) -> Option<Vec<OutMsg>> {
+        abort_broadcast(self.session, error)
+    }
+
+    fn peer_aborted(&self, msg: &InMsg) -> Option<ErrorState> {
+        aborted_by_peer(
+            &self.session,
+            &self.limits,
+            msg,
+            self.other_parties.contains(&msg.sender),
+        )
+    }
+
+    #[trace(disable(current_msg_set))]
+    fn resend_request(&self, current_msg_set: &[InMsg]) -> Option<Vec<OutMsg>> {
+        resend_requests(
+            self.session,
+            3,
+            missing_senders(current_msg_set, &self.other_parties),
+        )
+    }
+
+    fn resend_output(&self, msg: &InMsg, sent: &[OutMsg]) -> Option<Resent<OutMsg>> {
+        resent_output(
+            &self.session,
+            &self.limits,
+            msg,
+            self.other_parties.contains(&msg.sender),
+            sent,
+        )
+    }
 }
 
 /// Last phase of the protocol: broadcasts `DlogProof` for partial key share and verifies it
+#[derive(Serialize, Deserialize)]
 struct Phase4 {
+    session: Session,
+    limits: Limits,
     own_party_index: PartyIndex,
     other_parties: BTreeSet<PartyIndex>,
     multiparty_shared: MultiPartyInfo,
@@ -907,16 +1347,24 @@ struct Phase4 {
 impl State<KeyGeneratorTraits> for Phase4 {
     fn start(&mut self) -> Option<OutMsgVec> {
         log::debug!("Phase4 starts");
-        let dlog_proof = DLogProof::prove(self.multiparty_shared.own_share());
-        Some(vec![OutMsg {
-            recipient: Address::Broadcast,
-            body: Message::R4(dlog_proof),
-        }])
+        let dlog_proof = DLogProof::prove(&self.session, self.multiparty_shared.own_share());
+        Some(vec![OutMsg::new(
+            self.session,
+            Address::Broadcast,
+            Message::R4(dlog_proof),
+        )])
     }
 
     #[trace(disable(input))]
-    fn is_message_expected(&self, msg: &InMsg, current_msg_set: &[InMsg]) -> bool {
-        matches!(msg.body, Message::R4(_) if self.other_parties.contains(&msg.sender) && !msg.is_duplicate(current_msg_set))
+    fn classify_message(&self, msg: &InMsg, current_msg_set: &[InMsg]) -> MessageClass {
+        msg.classify(
+            &self.session,
+            &self.limits,
+            4,
+            matches!(msg.body, Message::R4(_)),
+            SenderRole::of_member(self.other_parties.contains(&msg.sender)),
+            current_msg_set,
+        )
     }
 
     #[trace(disable(current_msg_set))]
@@ -924,8 +1372,13 @@ impl State<KeyGeneratorTraits> for Phase4 {
         is_broadcast_input_complete(current_msg_set, &self.other_parties)
     }
 
+    #[trace(disable(current_msg_set))]
+    fn awaited_senders(&self, current_msg_set: &[InMsg]) -> Vec<PartyIndex> {
+        missing_senders(current_msg_set, &self.other_parties)
+    }
+
     fn consume(&self, current_msg_set: Vec<InMsg>) -> Transition<KeyGeneratorTraits> {
-        let proofs = match to_hash_map_gen::<PartyIndex, CurvDLogProofType>(current_msg_set) {
+        let proofs = match to_hash_map_gen::<PartyIndex, DLogProof>(current_msg_set) {
             Ok(p) => p,
             Err(e) => {
                 let err_state = ErrorState::new(e);
@@ -937,7 +1390,7 @@ impl State<KeyGeneratorTraits> for Phase4 {
         let verification_error_vec = proofs
             .iter()
             .filter_map(|(party, msg)| {
-                if DLogProof::verify(msg).is_ok() {
+                if msg.verify(&self.session).is_ok() {
                     None
                 } else {
                     Some(KeygenError::InvalidDlogProof {
@@ -983,6 +1436,47 @@ impl State<KeyGeneratorTraits> for Phase4 {
             phase: "phase4".to_string(),
         }]))
     }
+
+    #[trace(disable(res))]
+    fn snapshot(&self) -> Option<serde_json::Value> {
+        phase_snapshot("Phase4", self)
+    }
+
+    fn name(&self) -> &'static str {
+        "keygen.phase4"
+    }
+
+    fn abort_output(&self, error: &ErrorState) -> Option<Vec<OutMsg>> {
+        abort_broadcast(self.session, error)
+    }
+
+    fn peer_aborted(&self, msg: &InMsg) -> Option<ErrorState> {
+        aborted_by_peer(
+            &self.session,
+            &self.limits,
+            msg,
+            self.other_parties.contains(&msg.sender),
+        )
+    }
+
+    #[trace(disable(current_msg_set))]
+    fn resend_request(&self, current_msg_set: &[InMsg]) -> Option<Vec<OutMsg>> {
+        resend_requests(
+            self.session,
+            4,
+            missing_senders(current_msg_set, &self.other_parties),
+        )
+    }
+
+    fn resend_output(&self, msg: &InMsg, sent: &[OutMsg]) -> Option<Resent<OutMsg>> {
+        resent_output(
+            &self.session,
+            &self.limits,
+            msg,
+            self.other_parties.contains(&msg.sender),
+            sent,
+        )
+    }
 }
 
 /// Map of `PartyIndex` of each party into the x-coordinate of the shares received by this party
@@ -1057,32 +1551,85 @@ impl ErrorState {
     pub fn new(errors: Vec<KeygenError>) -> Self {
         ErrorState { errors }
     }
+
+    /// Adds senders of messages rejected by the state machine, once per reason, as they behave suspiciously
+    pub fn report_rejected(&mut self, rejected: &HashMap<RejectedMessage<PartyIndex>, usize>) {
+        self.errors
+            .extend(rejected.keys().map(|r| KeygenError::RejectedMessage {
+                party: r.sender,
+                reason: r.reason,
+            }));
+    }
+
+    /// Adds senders of messages which failed the verification of their identity, once per reason, as they behave suspiciously
+    pub fn report_unverified(&mut self, unverified: &HashMap<UnverifiedMessage, usize>) {
+        self.errors
+            .extend(unverified.keys().map(|r| KeygenError::UnverifiedMessage {
+                party: r.sender,
+                reason: r.reason,
+            }));
+    }
+}
+
+impl ReportRefused for ErrorState {
+    fn report_refused(&mut self, refused: &HashMap<RefusedMessage, usize>) {
+        self.errors
+            .extend(refused.keys().map(|r| KeygenError::RefusedMessage {
+                party: r.sender,
+                reason: r.reason,
+            }));
+    }
+}
+
+impl AbortReport for ErrorState {
+    fn abort_notice(&self) -> Abort {
+        let blamed = self
+            .errors
+            .iter()
+            .filter_map(KeygenError::blamed_party)
+            .collect::<BTreeSet<_>>();
+        Abort::new(&self.errors, blamed.into_iter().collect())
+    }
+
+    fn aborted_by(party: PartyIndex, notice: &Abort) -> Self {
+        ErrorState::new(vec![KeygenError::PeerAborted {
+            party,
+            reason: notice.reason.clone(),
+            blamed: notice.blamed.clone(),
+        }])
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::keygen::{
-        FinalState, InMsg, KeyGeneratorTraits, OutMsg, Phase1, SecretKeyLoader,
-        SecretKeyLoaderError,
+        ASecretKeyLoader, DecommitPublicKey, FinalState, InMsg, KeyGeneratorTraits, KeygenError,
+        Message, OutMsg, Phase1, SecretKeyLoader, SecretKeyLoaderError,
     };
-    use crate::messages::SecretShare;
-    use crate::protocol::{Address, InputMessage, PartyIndex};
+    use crate::limits::Limits;
+    use crate::messages::{FactorProofs, Resend, SecretShare};
+    use crate::protocol::{Address, InputMessage, PartyIndex, SessionId};
     use crate::types::VerifiableSS;
     use crate::types::Zero;
     use crate::types::{BigInt, FE, GE};
     use crate::types::{InitialKeys, InitialPublicKeys, Parameters};
     use crate::utils::all_mapped_equal;
-    use crate::zk_range_proofs::ZkpSetup;
+    use crate::wire;
+    use crate::zk_range_proofs::{ZkpPublicSetup, ZkpSetup};
     use algorithms::types::PaillierKeys;
-    use anyhow::bail;
+    use anyhow::{anyhow, bail};
     use crossbeam_channel::{Receiver, Sender};
     use paillier::DecryptionKey;
+    use state_machine::checkpoint::RestoreState;
+    use state_machine::driver::Driver;
     use state_machine::sync_channels::StateMachine;
+    use state_machine::types::{RejectReason, RejectedMessage, State, TerminationReason};
     use std::collections::HashMap;
     use std::fs::File;
     use std::io::Write;
     use std::path::Path;
     use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
     use std::{fs, thread};
 
     struct Node {
@@ -1170,6 +1717,412 @@ mod tests {
         keygen_helper(true)
     }
 
+    #[test]
+    fn phase_restored_from_snapshot() -> anyhow::Result<()> {
+        let params = Parameters::new(2, 3)?;
+        let parties = (0..=2).map(PartyIndex::from).collect::<Vec<_>>();
+        let init_keys = InitialKeys::random();
+        let init_pub_keys = InitialPublicKeys::from(&init_keys);
+        let wallet = Arc::new(Mutex::new(Wallet::new(
+            vec![(0, init_keys)].into_iter().collect(),
+        )));
+        let loader: ASecretKeyLoader = Arc::new(Box::new(SecretKeyLoaderImpl::new(&wallet, 0)));
+        let path = Path::new("../tests/data/rp-setups.json");
+        let zkp_setups: Vec<ZkpSetup> = serde_json::from_str(&fs::read_to_string(path)?)?;
+
+        let phase = Phase1::new(
+            SessionId::random(),
+            &params,
+            init_pub_keys,
+            Some(zkp_setups[0].clone()),
+            parties.as_slice(),
+            parties[0],
+            loader.clone(),
+            None,
+            Limits::default(),
+        )?;
+        let snapshot = State::<KeyGeneratorTraits>::snapshot(&phase).unwrap();
+        let restored = KeyGeneratorTraits::restore_state(snapshot.clone(), &loader)?;
+        assert_eq!(restored.snapshot(), Some(snapshot));
+        Ok(())
+    }
+
+    #[test]
+    fn silent_parties_reported() -> anyhow::Result<()> {
+        let params = Parameters::new(2, 3)?;
+        let parties = (0..=2).map(PartyIndex::from).collect::<Vec<_>>();
+        let init_keys = InitialKeys::random();
+        let init_pub_keys = InitialPublicKeys::from(&init_keys);
+        let wallet = Arc::new(Mutex::new(Wallet::new(
+            vec![(0, init_keys)].into_iter().collect(),
+        )));
+        let loader: ASecretKeyLoader = Arc::new(Box::new(SecretKeyLoaderImpl::new(&wallet, 0)));
+        let path = Path::new("../tests/data/rp-setups.json");
+        let zkp_setups: Vec<ZkpSetup> = serde_json::from_str(&fs::read_to_string(path)?)?;
+
+        let phase = Phase1::new(
+            SessionId::random(),
+            &params,
+            init_pub_keys,
+            Some(zkp_setups[0].clone()),
+            parties.as_slice(),
+            parties[0],
+            loader,
+            None,
+            Limits::default(),
+        )?;
+        let mut driver = Driver::<KeyGeneratorTraits>::new(Box::new(phase));
+        driver
+            .start(Instant::now())
+            .map_err(|e| anyhow!("{:?}", e))?;
+        let aborted = driver.terminate(TerminationReason::Requested(
+            "operator cancelled".to_string(),
+        ));
+        assert_eq!(aborted.phase, "keygen.phase1");
+        assert_eq!(aborted.awaited, vec![parties[1], parties[2]]);
+        assert!(aborted.retained.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn failing_party_notifies_peers() -> anyhow::Result<()> {
+        let params = Parameters::new(2, 3)?;
+        let parties = (0..=2).map(PartyIndex::from).collect::<Vec<_>>();
+        let keys = (0..=1)
+            .map(|i| (i, InitialKeys::random()))
+            .collect::<Vec<_>>();
+        let pub_keys = keys
+            .iter()
+            .map(|(_, k)| InitialPublicKeys::from(k))
+            .collect::<Vec<_>>();
+        let wallet = Arc::new(Mutex::new(Wallet::new(keys.into_iter().collect())));
+        let path = Path::new("../tests/data/rp-setups.json");
+        let zkp_setups: Vec<ZkpSetup> = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let session_id = SessionId::random();
+        let now = Instant::now();
+
+        let mut drivers = (0..=1)
+            .map(|i| {
+                let phase = Phase1::new(
+                    session_id,
+                    &params,
+                    pub_keys[i].clone(),
+                    Some(zkp_setups[i].clone()),
+                    parties.as_slice(),
+                    parties[i],
+                    Arc::new(Box::new(SecretKeyLoaderImpl::new(&wallet, i))),
+                    None,
+                    Limits::default(),
+                )?;
+                Ok(Driver::<KeyGeneratorTraits>::new(Box::new(phase)))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let first = drivers[0]
+            .start(now)
+            .map_err(|e| anyhow!("{:?}", e))?
+            .output;
+        drivers[1].start(now).map_err(|e| anyhow!("{:?}", e))?;
+
+        // other parties send their first messages without range proof setups, so that the party fails
+        let mut outcome = None;
+        let mut notice = Vec::new();
+        for sender in &parties[1..] {
+            let mut msg = InputMessage::from_output(*sender, first[0].clone());
+            if let Message::R1(broadcast) = &mut msg.body {
+                broadcast.range_proof_setup = None;
+            }
+            let step = drivers[0]
+                .handle_message(msg, now)
+                .map_err(|e| anyhow!("{:?}", e))?;
+            outcome = step.outcome;
+            notice = step.output;
+        }
+        assert!(matches!(outcome, Some(Err(_))));
+        let notice = match notice.as_slice() {
+            [msg @ OutMsg {
+                recipient: Address::Broadcast,
+                body: Message::Abort(abort),
+                ..
+            }] => {
+                assert_eq!(abort.blamed, parties[1..].to_vec());
+                assert!(abort.reason.contains("missing range proof"));
+                msg.clone()
+            }
+            _ => bail!("no abort notice in {:?}", notice),
+        };
+
+        // the peer ends its session as soon as the notice arrives
+        let step = drivers[1]
+            .handle_message(InputMessage::from_output(parties[0], notice), now)
+            .map_err(|e| anyhow!("{:?}", e))?;
+        assert!(step.output.is_empty());
+        match step.outcome {
+            Some(Err(error_state)) => assert!(matches!(
+                error_state.errors.as_slice(),
+                [KeygenError::PeerAborted { party, blamed, .. }]
+                    if *party == parties[0] && *blamed == parties[1..].to_vec()
+            )),
+            _ => bail!("peer did not abort"),
+        }
+        assert!(drivers[1].is_finished());
+        Ok(())
+    }
+
+    /// drivers of three parties which run the protocol with range proof setups
+    fn drivers_with_range_proofs(
+        parties: &[PartyIndex],
+    ) -> anyhow::Result<Vec<Driver<KeyGeneratorTraits>>> {
+        let params = Parameters::new(2, 3)?;
+        let keys = (0..=2)
+            .map(|i| (i, InitialKeys::random()))
+            .collect::<Vec<_>>();
+        let pub_keys = keys
+            .iter()
+            .map(|(_, k)| InitialPublicKeys::from(k))
+            .collect::<Vec<_>>();
+        let wallet = Arc::new(Mutex::new(Wallet::new(keys.into_iter().collect())));
+        let path = Path::new("../tests/data/rp-setups.json");
+        let zkp_setups: Vec<ZkpSetup> = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let session_id = SessionId::random();
+
+        (0..=2)
+            .map(|i| {
+                let phase = Phase1::new(
+                    session_id,
+                    &params,
+                    pub_keys[i].clone(),
+                    Some(zkp_setups[i].clone()),
+                    parties,
+                    parties[i],
+                    Arc::new(Box::new(SecretKeyLoaderImpl::new(&wallet, i))),
+                    None,
+                    Limits::default(),
+                )?;
+                Ok(Driver::<KeyGeneratorTraits>::new(Box::new(phase)))
+            })
+            .collect()
+    }
+
+    /// the message of the sender, sent in the round, which is addressed to the recipient
+    fn addressed_to(sent: &[OutMsg], recipient: PartyIndex) -> anyhow::Result<OutMsg> {
+        sent.iter()
+            .find(|m| m.recipient == Address::Peer(recipient))
+            .cloned()
+            .ok_or_else(|| anyhow!("no message to {}", recipient))
+    }
+
+    #[test]
+    fn forged_modulus_proof_blamed() -> anyhow::Result<()> {
+        let parties = (0..=2).map(PartyIndex::from).collect::<Vec<_>>();
+        let mut drivers = drivers_with_range_proofs(&parties)?;
+        let now = Instant::now();
+        let first = drivers
+            .iter_mut()
+            .map(|d| Ok(d.start(now).map_err(|e| anyhow!("{:?}", e))?.output))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        // the first party receives the proofs of the third party along with the Paillier key of the second one
+        let mut second = vec![Vec::new(); parties.len()];
+        for (i, driver) in drivers.iter_mut().enumerate() {
+            for j in (0..parties.len()).filter(|j| *j != i) {
+                let mut msg = InputMessage::from_output(parties[j], first[j][0].clone());
+                if let (0, 1, Message::R1(broadcast)) = (i, j, &mut msg.body) {
+                    if let Message::R1(other) = &first[2][0].body {
+                        broadcast.modulus_proofs = other.modulus_proofs.clone();
+                    }
+                }
+                second[i] = driver
+                    .handle_message(msg, now)
+                    .map_err(|e| anyhow!("{:?}", e))?
+                    .output;
+            }
+        }
+
+        let mut outcome = None;
+        for j in 1..parties.len() {
+            let msg = InputMessage::from_output(parties[j], addressed_to(&second[j], parties[0])?);
+            outcome = drivers[0]
+                .handle_message(msg, now)
+                .map_err(|e| anyhow!("{:?}", e))?
+                .outcome;
+        }
+        match outcome {
+            Some(Err(error_state)) => assert!(matches!(
+                error_state.errors.as_slice(),
+                [KeygenError::InvalidModulusProof { party, .. }] if *party == parties[1]
+            )),
+            _ => bail!("forged proof is not detected"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn factor_proofs_verified_before_shares_dealt() -> anyhow::Result<()> {
+        let parties = (0..=2).map(PartyIndex::from).collect::<Vec<_>>();
+        let mut drivers = drivers_with_range_proofs(&parties)?;
+        let now = Instant::now();
+        let first = drivers
+            .iter_mut()
+            .map(|d| Ok(d.start(now).map_err(|e| anyhow!("{:?}", e))?.output))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let mut second = vec![Vec::new(); parties.len()];
+        for (i, driver) in drivers.iter_mut().enumerate() {
+            for j in (0..parties.len()).filter(|j| *j != i) {
+                let msg = InputMessage::from_output(parties[j], first[j][0].clone());
+                second[i] = driver
+                    .handle_message(msg, now)
+                    .map_err(|e| anyhow!("{:?}", e))?
+                    .output;
+            }
+        }
+        // the decommitment carries proofs made for the recipient only
+        assert_eq!(second[1].len(), parties.len() - 1);
+
+        // the first party receives the proofs the second party made for the third one
+        let mut outcome = None;
+        for (j, recipient) in [(1, parties[2]), (2, parties[0])] {
+            let msg = InputMessage::from_output(parties[j], addressed_to(&second[j], recipient)?);
+            let step = drivers[0]
+                .handle_message(msg, now)
+                .map_err(|e| anyhow!("{:?}", e))?;
+            assert!(
+                !step.output.iter().any(|m| matches!(m.body, Message::R3(_))),
+                "shares are dealt"
+            );
+            outcome = step.outcome;
+        }
+        match outcome {
+            Some(Err(error_state)) => assert!(matches!(
+                error_state.errors.as_slice(),
+                [KeygenError::InvalidFactorProof { party, .. }] if *party == parties[1]
+            )),
+            _ => bail!("proofs made for another party are accepted"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn lost_messages_resent_on_request() -> anyhow::Result<()> {
+        let params = Parameters::new(2, 3)?;
+        let parties = (0..=2).map(PartyIndex::from).collect::<Vec<_>>();
+        let keys = InitialKeys::random();
+        let pub_keys = InitialPublicKeys::from(&keys);
+        let wallet = Arc::new(Mutex::new(Wallet::new(
+            vec![(0, keys)].into_iter().collect(),
+        )));
+        let path = Path::new("../tests/data/rp-setups.json");
+        let zkp_setups: Vec<ZkpSetup> = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let interval = Duration::from_secs(1);
+        let now = Instant::now();
+
+        let phase = Phase1::new(
+            SessionId::random(),
+            &params,
+            pub_keys,
+            Some(zkp_setups[0].clone()),
+            parties.as_slice(),
+            parties[0],
+            Arc::new(Box::new(SecretKeyLoaderImpl::new(&wallet, 0))),
+            None,
+            Limits::default(),
+        )?;
+        let mut driver =
+            Driver::<KeyGeneratorTraits>::new(Box::new(phase)).with_resend_interval(interval);
+        let first = driver.start(now).map_err(|e| anyhow!("{:?}", e))?.output;
+
+        // nothing has arrived within the interval, so that the party asks both peers for their first messages
+        let requests = driver.request_resend(now + interval);
+        assert_eq!(requests.len(), 2);
+        for (request, peer) in requests.iter().zip(&parties[1..]) {
+            assert_eq!(request.recipient, Address::Peer(*peer));
+            assert!(matches!(request.body, Message::Resend(Resend { round: 1 })));
+        }
+
+        // the peer which has lost the broadcast receives an identical copy of it
+        let step = driver
+            .handle_message(
+                InputMessage::from_output(parties[1], requests[0].clone()),
+                now + interval,
+            )
+            .map_err(|e| anyhow!("{:?}", e))?;
+        assert!(step.outcome.is_none());
+        match step.output.as_slice() {
+            [resent] => {
+                assert_eq!(resent.recipient, Address::Peer(parties[1]));
+                assert_eq!(resent.header, first[0].header);
+                assert_eq!(
+                    wire::to_bytes(&resent.body).unwrap(),
+                    wire::to_bytes(&first[0].body).unwrap()
+                );
+            }
+            _ => bail!("unexpected output {:?}", step.output),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn messages_of_outsiders_not_deferred() -> anyhow::Result<()> {
+        let params = Parameters::new(2, 3)?;
+        let parties = (0..=2).map(PartyIndex::from).collect::<Vec<_>>();
+        let keys = InitialKeys::random();
+        let pub_keys = InitialPublicKeys::from(&keys);
+        let path = Path::new("../tests/data/rp-setups.json");
+        let zkp_setups: Vec<ZkpSetup> = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let factor_proofs = FactorProofs::new(
+            &keys.paillier_keys.dk,
+            &zkp_setups[1],
+            &ZkpPublicSetup::from_private_zkp_setup(&zkp_setups[0]),
+            &BigInt::from(1),
+        );
+        let wallet = Arc::new(Mutex::new(Wallet::new(
+            vec![(0, keys)].into_iter().collect(),
+        )));
+        let phase = Phase1::new(
+            SessionId::random(),
+            &params,
+            pub_keys,
+            Some(zkp_setups[0].clone()),
+            parties.as_slice(),
+            parties[0],
+            Arc::new(Box::new(SecretKeyLoaderImpl::new(&wallet, 0))),
+            None,
+            Limits::default(),
+        )?;
+        let mut driver = Driver::<KeyGeneratorTraits>::new(Box::new(phase));
+        let now = Instant::now();
+        let session = driver.start(now).map_err(|e| anyhow!("{:?}", e))?.output[0]
+            .header
+            .session;
+
+        // the decommitment of the second round is deferred for a party of the session only
+        let decommitment = OutMsg::new(
+            session,
+            Address::Peer(parties[0]),
+            Message::R2(DecommitPublicKey {
+                blind_factor: BigInt::from(1),
+                y_i: GE::generator().to_point(),
+                factor_proofs,
+            }),
+        );
+        let outsider = PartyIndex::from(7);
+        for sender in [parties[1], outsider] {
+            driver
+                .handle_message(InputMessage::from_output(sender, decommitment.clone()), now)
+                .map_err(|e| anyhow!("{:?}", e))?;
+        }
+        assert_eq!(
+            driver.rejected_messages(),
+            &HashMap::from([(
+                RejectedMessage {
+                    sender: outsider,
+                    reason: RejectReason::UnknownSender,
+                },
+                1
+            )])
+        );
+        Ok(())
+    }
+
     fn keygen_helper(enable_range_proofs: bool) -> anyhow::Result<()> {
         let _ = env_logger::builder().is_test(true).try_init();
         let params = Parameters::new(2, 3)?;
@@ -1183,6 +2136,7 @@ mod tests {
         let mut handles = Vec::new();
         let wallet = Wallet::new(HashMap::new());
         let shared_wallet_reference = Arc::new(Mutex::new(wallet));
+        let session_id = SessionId::random();
 
         // the setup from the bank of pre-generated ones
         let path = Path::new("../tests/data/rp-setups.json");
@@ -1209,6 +2163,7 @@ mod tests {
             let parties = parties.clone();
             let join_handle = thread::spawn(move || {
                 let start_state = Box::new(Phase1::new(
+                    session_id,
                     &params,
                     init_pub_keys,
                     range_proof_setup,
@@ -1216,15 +2171,16 @@ mod tests {
                     i.into(),
                     Arc::new(Box::new(secret_loader)),
                     None,
+                    Limits::default(),
                 )?);
                 let mut machine = StateMachine::<KeyGeneratorTraits>::new(start_state, &rx, &tx);
                 match machine.execute() {
-                    Some(Ok(fs)) => Ok(fs),
-                    Some(Err(e)) => {
+                    Ok(Ok(fs)) => Ok(fs),
+                    Ok(Err(e)) => {
                         bail!("error {:?}", e);
                     }
-                    None => {
-                        bail!("error in the machine");
+                    Err(aborted) => {
+                        bail!("machine aborted {:?}", aborted);
                     }
                 }
             });
@@ -1266,20 +2222,15 @@ mod tests {
                                 .iter()
                                 .filter(|node| node.party != mm.source)
                                 .for_each(|node| {
-                                    let message_to_deliver = InputMessage {
-                                        sender: mm.source,
-                                        body: mm.msg.body.clone(),
-                                    };
+                                    let message_to_deliver =
+                                        InputMessage::from_output(mm.source, mm.msg.clone());
                                     node.ingress.send(message_to_deliver).unwrap();
                                 });
                         }
                         Address::Peer(peer) => {
                             if let Some(node) = nodes.iter().find(|node| (*node).party == *peer) {
                                 node.ingress
-                                    .send(InputMessage {
-                                        sender: mm.source,
-                                        body: mm.msg.body.clone(),
-                                    })
+                                    .send(InputMessage::from_output(mm.source, mm.msg.clone()))
                                     .unwrap();
                             }
                         }
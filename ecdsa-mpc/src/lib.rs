@@ -5,16 +5,27 @@
 //!  The module implements following algorithms:
 //! * Key generation
 //! * Signing
-//! * key refresh or re-sharing
+//! * key refresh
+//! * re-sharing
+//! * share recovery
 //!
 
+pub mod echo;
+pub mod encryption;
+pub mod identity;
 pub mod keygen;
+pub mod limits;
 pub mod messages;
+pub mod proofs;
 pub mod protocol;
+pub mod recovery;
+pub mod refresh;
 pub mod resharing;
 pub mod signature;
+pub mod simulator;
 pub mod types;
 mod utils;
+pub mod wire;
 pub mod zk_range_proofs;
 
 #[macro_use]
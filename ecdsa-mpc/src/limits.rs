@@ -0,0 +1,721 @@
+//! Limits of sizes of inbound messages
+//!
+//! A peer can send a message which carries huge big integers or long vectors. Without limits, such message would be deserialized and fed into modular arithmetic of proofs,
+//! so that the peer could exhaust CPU or memory of the party. Each message type of the crate implements [`Bounded`], which checks bit lengths of its numbers and lengths of its vectors
+//! against [`Limits`], derived from bit lengths of moduli in the configuration of the crate.
+//!
+//! Limits are enforced at each step of the way of the message:
+//! * [`from_json`] refuses input longer than any message may take before parsing it, and checks the parsed message along with the length of its input
+//! * the binary codec of the module [`wire`] refuses numbers and vectors longer than the limits allow while it reads them, and checks each protocol message,
+//!   along with the length of its encoding, as soon as it is decoded
+//! * phases of protocols reject messages exceeding the limits given to their constructors before they consume them, so that the sender is reported in the error state
+//!   of the protocol, along with other rejected messages
+//!
+//! The same limits should be given to the codec and to the phases, e.g. `WireCodec::new().with_limits(limits)` and `Phase1::new(.., limits)`.
+//!
+//! [`Bounded`]: trait.Bounded.html
+//! [`Limits`]: struct.Limits.html
+//!
+//! Lengths of messages are limited in the binary form, by [`Limits::max_message_bytes`], except for messages which carry modulus proofs, i.e.
+//! the first message of key generation, the second message of resharing and the first messages of key refresh and share recovery,
+//! which are limited by [`Limits::max_proof_message_bytes`]. The JSON form of a message may be [`JSON_INFLATION`] times longer than its binary form.
+//!
+//! [`Limits::max_message_bytes`]: struct.Limits.html#structfield.max_message_bytes
+//! [`Limits::max_proof_message_bytes`]: struct.Limits.html#structfield.max_proof_message_bytes
+//! [`JSON_INFLATION`]: constant.JSON_INFLATION.html
+//! [`from_json`]: fn.from_json.html
+//! [`wire`]: ../wire/index.html
+
+use crate::keygen::CorrectKeyProof;
+use crate::messages::{
+    keygen, recovery, refresh, resharing, signing, Abort, FactorProofs, FeldmanVSS, ModulusProofs,
+    MAX_ABORT_REASON_BYTES,
+};
+use crate::protocol::{InputMessage, OutputMessage};
+use crate::types::{BigInt, CurvVerifiableSS};
+use crate::zk_range_proofs::{
+    AliceProof, BobProof, BobProofExt, BobProofType, MessageA, MessageB, ZkpPublicSetup,
+};
+use algorithms::config::{DEFAULT_GROUP_ORDER_BIT_LENGTH, PRIME_BIT_LENGTH_IN_PAILLIER_SCHEMA};
+use algorithms::dlog_proof::DlogProof;
+use algorithms::nizk_rsa;
+use algorithms::no_small_factor::NoSmallFactorProof;
+use algorithms::paillier_blum::{self, PaillierBlumProof};
+use algorithms::sha::HSha512Trunc256;
+use curv::arithmetic::BitManipulation;
+use paillier::EncryptionKey;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::cmp::max;
+use thiserror::Error;
+
+/// Bit length of hashes, commitments and blind factors
+const HASH_BITS: usize = 256;
+
+/// Maximal ratio of the length of the JSON form of a message to the length of its binary form
+pub const JSON_INFLATION: usize = 4;
+
+/// Errors of messages exceeding limits
+#[derive(Debug, Error)]
+pub enum LimitError {
+    #[error("message of {len} bytes exceeds the limit of {limit} bytes")]
+    MessageTooLong { len: usize, limit: usize },
+    #[error("{field} of {bits} bits exceeds the limit of {limit} bits")]
+    NumberTooLong {
+        field: &'static str,
+        bits: usize,
+        limit: usize,
+    },
+    #[error("{field} has {len} elements, expected {expected}")]
+    WrongLength {
+        field: &'static str,
+        len: usize,
+        expected: usize,
+    },
+    #[error("{field} has {len} elements, exceeds the limit of {limit}")]
+    TooManyElements {
+        field: &'static str,
+        len: usize,
+        limit: usize,
+    },
+    #[error("message deserialization error: {0}")]
+    Deserialization(#[from] serde_json::Error),
+}
+
+/// Limits of sizes of inbound messages
+///
+/// Bit lengths of all numbers in messages are derived from the bit length of moduli, i.e. Paillier moduli and moduli of range proof setups.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Limits {
+    /// maximal bit length of moduli
+    pub modulus_bits: usize,
+    /// maximal number of parties, which bounds the number of VSS commitments
+    pub max_parties: usize,
+    /// maximal length of a message in its binary form
+    pub max_message_bytes: usize,
+    /// maximal length of a message carrying modulus proofs in its binary form
+    pub max_proof_message_bytes: usize,
+}
+
+impl Default for Limits {
+    /// Limits of moduli generated by the crate in its current configuration
+    fn default() -> Self {
+        Limits {
+            modulus_bits: max(
+                2 * PRIME_BIT_LENGTH_IN_PAILLIER_SCHEMA,
+                DEFAULT_GROUP_ORDER_BIT_LENGTH,
+            ),
+            max_parties: 256,
+            max_message_bytes: 1 << 16,
+            // two Paillier-Blum modulus proofs take about 90 KB
+            max_proof_message_bytes: 1 << 17,
+        }
+    }
+}
+
+impl Limits {
+    pub fn with_modulus_bits(mut self, modulus_bits: usize) -> Self {
+        self.modulus_bits = modulus_bits;
+        self
+    }
+
+    pub fn with_max_parties(mut self, max_parties: usize) -> Self {
+        self.max_parties = max_parties;
+        self
+    }
+
+    pub fn with_max_message_bytes(mut self, max_message_bytes: usize) -> Self {
+        self.max_message_bytes = max_message_bytes;
+        self
+    }
+
+    pub fn with_max_proof_message_bytes(mut self, max_proof_message_bytes: usize) -> Self {
+        self.max_proof_message_bytes = max_proof_message_bytes;
+        self
+    }
+
+    /// the longest input [`from_json`](fn.from_json.html) parses
+    fn max_json_bytes(&self) -> usize {
+        JSON_INFLATION * max(self.max_message_bytes, self.max_proof_message_bytes)
+    }
+
+    /// the longest number any message may carry, in bytes, which the binary decoder applies to every number
+    pub(crate) fn max_number_bytes(&self) -> usize {
+        max(self.ciphertext_bits(), self.response_bits()).div_ceil(8)
+    }
+
+    /// the longest vector any message may carry, which the binary decoder applies to every vector
+    pub(crate) fn max_vec_len(&self) -> usize {
+        // VSS commitments and blamed parties are bounded by the number of parties, vectors of proofs have fixed lengths
+        max(self.max_parties + 1, max(nizk_rsa::M2, paillier_blum::M))
+    }
+
+    /// numbers modulo a modulus
+    fn residue_bits(&self) -> usize {
+        self.modulus_bits
+    }
+
+    /// Paillier ciphertexts and other numbers modulo the square of a modulus
+    fn ciphertext_bits(&self) -> usize {
+        2 * self.modulus_bits
+    }
+
+    /// responses of range proofs and of proofs of range proof setups, which are not reduced by any modulus
+    fn response_bits(&self) -> usize {
+        3 * self.modulus_bits
+    }
+}
+
+/// Types whose sizes can be checked against limits
+pub trait Bounded {
+    fn check_limits(&self, limits: &Limits) -> Result<(), LimitError>;
+
+    /// maximal length of the value in its binary form
+    fn max_bytes(&self, limits: &Limits) -> usize {
+        limits.max_message_bytes
+    }
+}
+
+/// Refuses the message if its form of `len` bytes exceeds `limit`
+pub(crate) fn check_message_len(len: usize, limit: usize) -> Result<(), LimitError> {
+    if len > limit {
+        Err(LimitError::MessageTooLong { len, limit })
+    } else {
+        Ok(())
+    }
+}
+
+/// Parses the message from JSON, refusing input and messages exceeding limits
+pub fn from_json<T>(bytes: &[u8], limits: &Limits) -> Result<T, LimitError>
+where
+    T: DeserializeOwned + Bounded,
+{
+    check_message_len(bytes.len(), limits.max_json_bytes())?;
+    let msg: T = serde_json::from_slice(bytes)?;
+    msg.check_limits(limits)?;
+    check_message_len(bytes.len(), JSON_INFLATION * msg.max_bytes(limits))?;
+    Ok(msg)
+}
+
+fn check_bits(field: &'static str, n: &BigInt, limit: usize) -> Result<(), LimitError> {
+    let bits = n.bit_length();
+    if bits > limit {
+        Err(LimitError::NumberTooLong { field, bits, limit })
+    } else {
+        Ok(())
+    }
+}
+
+fn check_len(field: &'static str, len: usize, expected: usize) -> Result<(), LimitError> {
+    if len != expected {
+        Err(LimitError::WrongLength {
+            field,
+            len,
+            expected,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+fn check_count(field: &'static str, len: usize, limit: usize) -> Result<(), LimitError> {
+    if len > limit {
+        Err(LimitError::TooManyElements { field, len, limit })
+    } else {
+        Ok(())
+    }
+}
+
+/// hash of a range proof, along with the nonce of the hash
+fn check_hash_with_nonce(field: &'static str, e: &(BigInt, BigInt)) -> Result<(), LimitError> {
+    check_bits(field, &e.0, HASH_BITS)?;
+    check_bits(field, &e.1, 8 * HSha512Trunc256::NONCE_SIZE_BYTES)
+}
+
+impl<B: Bounded> Bounded for InputMessage<B> {
+    fn check_limits(&self, limits: &Limits) -> Result<(), LimitError> {
+        self.body.check_limits(limits)
+    }
+
+    fn max_bytes(&self, limits: &Limits) -> usize {
+        self.body.max_bytes(limits)
+    }
+}
+
+impl<B: Bounded> Bounded for OutputMessage<B> {
+    fn check_limits(&self, limits: &Limits) -> Result<(), LimitError> {
+        self.body.check_limits(limits)
+    }
+
+    fn max_bytes(&self, limits: &Limits) -> usize {
+        self.body.max_bytes(limits)
+    }
+}
+
+impl Bounded for EncryptionKey {
+    fn check_limits(&self, limits: &Limits) -> Result<(), LimitError> {
+        check_bits("Paillier modulus", &self.n, limits.modulus_bits)?;
+        check_bits(
+            "square of Paillier modulus",
+            &self.nn,
+            limits.ciphertext_bits(),
+        )
+    }
+}
+
+impl Bounded for CorrectKeyProof {
+    fn check_limits(&self, limits: &Limits) -> Result<(), LimitError> {
+        check_len("proof of Paillier key", self.0.len(), nizk_rsa::M2)?;
+        self.0
+            .iter()
+            .try_for_each(|x| check_bits("proof of Paillier key", x, limits.residue_bits()))
+    }
+}
+
+impl Bounded for DlogProof {
+    fn check_limits(&self, limits: &Limits) -> Result<(), LimitError> {
+        check_bits("challenge of dlog proof", &self.c, HASH_BITS)?;
+        check_bits("response of dlog proof", &self.y, limits.response_bits())
+    }
+}
+
+impl Bounded for ZkpPublicSetup {
+    fn check_limits(&self, limits: &Limits) -> Result<(), LimitError> {
+        check_bits("N_tilde", &self.N_tilde, limits.modulus_bits)?;
+        check_bits("h1", &self.h1, limits.residue_bits())?;
+        check_bits("h2", &self.h2, limits.residue_bits())?;
+        self.dlog_proof.check_limits(limits)?;
+        self.inv_dlog_proof.check_limits(limits)?;
+        check_len("proof of N_tilde", self.n_tilde_proof.len(), nizk_rsa::M2)?;
+        self.n_tilde_proof
+            .iter()
+            .try_for_each(|x| check_bits("proof of N_tilde", x, limits.residue_bits()))
+    }
+}
+
+impl Bounded for PaillierBlumProof {
+    fn check_limits(&self, limits: &Limits) -> Result<(), LimitError> {
+        check_bits("w of modulus proof", &self.w, limits.residue_bits())?;
+        check_len("a of modulus proof", self.a.len(), paillier_blum::M)?;
+        check_len("b of modulus proof", self.b.len(), paillier_blum::M)?;
+        check_len("x of modulus proof", self.x.len(), paillier_blum::M)?;
+        check_len("z of modulus proof", self.z.len(), paillier_blum::M)?;
+        self.x
+            .iter()
+            .chain(self.z.iter())
+            .try_for_each(|x| check_bits("root of modulus proof", x, limits.residue_bits()))
+    }
+}
+
+impl Bounded for ModulusProofs {
+    fn check_limits(&self, limits: &Limits) -> Result<(), LimitError> {
+        self.paillier.check_limits(limits)?;
+        self.n_tilde.check_limits(limits)
+    }
+}
+
+impl Bounded for NoSmallFactorProof {
+    fn check_limits(&self, limits: &Limits) -> Result<(), LimitError> {
+        [&self.P, &self.Q, &self.A, &self.B, &self.T]
+            .iter()
+            .try_for_each(|x| check_bits("commitment of factor proof", x, limits.residue_bits()))?;
+        [&self.sigma, &self.z1, &self.z2, &self.w1, &self.w2, &self.v]
+            .iter()
+            .try_for_each(|x| check_bits("response of factor proof", x, limits.response_bits()))
+    }
+}
+
+impl Bounded for FactorProofs {
+    fn check_limits(&self, limits: &Limits) -> Result<(), LimitError> {
+        self.paillier.check_limits(limits)?;
+        self.n_tilde.check_limits(limits)
+    }
+}
+
+impl<T: Bounded> Bounded for Option<T> {
+    fn check_limits(&self, limits: &Limits) -> Result<(), LimitError> {
+        self.as_ref().map_or(Ok(()), |x| x.check_limits(limits))
+    }
+}
+
+impl Bounded for CurvVerifiableSS {
+    fn check_limits(&self, limits: &Limits) -> Result<(), LimitError> {
+        check_count(
+            "VSS share count",
+            self.parameters.share_count as usize,
+            limits.max_parties,
+        )?;
+        check_len(
+            "VSS commitments",
+            self.commitments.len(),
+            self.parameters.threshold as usize + 1,
+        )
+    }
+}
+
+impl Bounded for FeldmanVSS {
+    fn check_limits(&self, limits: &Limits) -> Result<(), LimitError> {
+        self.vss.check_limits(limits)
+    }
+}
+
+impl Bounded for AliceProof {
+    fn check_limits(&self, limits: &Limits) -> Result<(), LimitError> {
+        check_bits("z of Alice's proof", &self.z, limits.residue_bits())?;
+        check_bits("u of Alice's proof", &self.u, limits.ciphertext_bits())?;
+        check_bits("w of Alice's proof", &self.w, limits.residue_bits())?;
+        check_hash_with_nonce("e of Alice's proof", &self.e)?;
+        check_bits("s of Alice's proof", &self.s, limits.residue_bits())?;
+        check_bits("s1 of Alice's proof", &self.s1, limits.response_bits())?;
+        check_bits("s2 of Alice's proof", &self.s2, limits.response_bits())
+    }
+}
+
+impl Bounded for BobProof {
+    fn check_limits(&self, limits: &Limits) -> Result<(), LimitError> {
+        check_bits("t of Bob's proof", &self.t, limits.residue_bits())?;
+        check_bits("v of Bob's proof", &self.v, limits.ciphertext_bits())?;
+        check_bits("w of Bob's proof", &self.w, limits.residue_bits())?;
+        check_bits("z of Bob's proof", &self.z, limits.residue_bits())?;
+        check_bits("z' of Bob's proof", &self.z_prim, limits.residue_bits())?;
+        check_hash_with_nonce("e of Bob's proof", &self.e)?;
+        check_bits("s of Bob's proof", &self.s, limits.residue_bits())?;
+        check_bits("s1 of Bob's proof", &self.s1, limits.response_bits())?;
+        check_bits("s2 of Bob's proof", &self.s2, limits.response_bits())?;
+        check_bits("t1 of Bob's proof", &self.t1, limits.response_bits())?;
+        check_bits("t2 of Bob's proof", &self.t2, limits.response_bits())
+    }
+}
+
+impl Bounded for BobProofExt {
+    fn check_limits(&self, limits: &Limits) -> Result<(), LimitError> {
+        self.proof.check_limits(limits)
+    }
+}
+
+impl Bounded for BobProofType {
+    fn check_limits(&self, limits: &Limits) -> Result<(), LimitError> {
+        match self {
+            BobProofType::RangeProofExt(proof) => proof.check_limits(limits),
+            BobProofType::RangeProof(proof) => proof.check_limits(limits),
+            BobProofType::DLogProofs(_) => Ok(()),
+        }
+    }
+}
+
+impl Bounded for MessageA {
+    fn check_limits(&self, limits: &Limits) -> Result<(), LimitError> {
+        check_bits("MtA ciphertext", &self.c, limits.ciphertext_bits())?;
+        self.range_proof.check_limits(limits)
+    }
+}
+
+impl Bounded for MessageB {
+    fn check_limits(&self, limits: &Limits) -> Result<(), LimitError> {
+        check_bits("MtA ciphertext", &self.c, limits.ciphertext_bits())?;
+        self.proof.check_limits(limits)
+    }
+}
+
+impl Bounded for Abort {
+    fn check_limits(&self, limits: &Limits) -> Result<(), LimitError> {
+        check_count("abort reason", self.reason.len(), MAX_ABORT_REASON_BYTES)?;
+        check_count("blamed parties", self.blamed.len(), limits.max_parties)
+    }
+}
+
+impl Bounded for keygen::Message {
+    fn check_limits(&self, limits: &Limits) -> Result<(), LimitError> {
+        match self {
+            keygen::Message::R1(msg) => {
+                msg.e.check_limits(limits)?;
+                check_bits("commitment", &msg.com, HASH_BITS)?;
+                msg.correct_key_proof.check_limits(limits)?;
+                msg.range_proof_setup.check_limits(limits)?;
+                msg.modulus_proofs.check_limits(limits)
+            }
+            keygen::Message::R2(msg) => {
+                check_bits("blind factor", &msg.blind_factor, HASH_BITS)?;
+                msg.factor_proofs.check_limits(limits)
+            }
+            keygen::Message::R3(msg) => msg.check_limits(limits),
+            keygen::Message::R4(_) | keygen::Message::Resend(_) => Ok(()),
+            keygen::Message::Abort(msg) => msg.check_limits(limits),
+        }
+    }
+
+    fn max_bytes(&self, limits: &Limits) -> usize {
+        match self {
+            keygen::Message::R1(_) => limits.max_proof_message_bytes,
+            _ => limits.max_message_bytes,
+        }
+    }
+}
+
+impl Bounded for signing::Message {
+    fn check_limits(&self, limits: &Limits) -> Result<(), LimitError> {
+        use signing::Message::*;
+        match self {
+            R1(msg) => {
+                check_bits("commitment", &msg.com, HASH_BITS)?;
+                msg.mta_a.check_limits(limits)
+            }
+            R2(msg) | R2b(msg) => msg.check_limits(limits),
+            R4(msg) => check_bits("blind factor", &msg.blind_factor, HASH_BITS),
+            R5(msg) => check_bits("commitment", &msg.com, HASH_BITS),
+            R6(msg) => check_bits("blind factor", &msg.blind_factor, HASH_BITS),
+            R7(msg) => check_bits("commitment", &msg.com, HASH_BITS),
+            R8(msg) => check_bits("blind factor", &msg.blind_factor, HASH_BITS),
+            R3(_) | R9(_) | Resend(_) => Ok(()),
+            Abort(msg) => msg.check_limits(limits),
+        }
+    }
+}
+
+impl Bounded for resharing::Message {
+    fn check_limits(&self, limits: &Limits) -> Result<(), LimitError> {
+        match self {
+            resharing::Message::R1(msg) => {
+                check_bits("VSS commitment", &msg.vss_commitment, HASH_BITS)
+            }
+            resharing::Message::R2(msg) => {
+                msg.ek.check_limits(limits)?;
+                msg.correct_key_proof.check_limits(limits)?;
+                msg.range_proof_setup.check_limits(limits)?;
+                msg.modulus_proofs.check_limits(limits)
+            }
+            resharing::Message::R3(msg) => msg.vss.check_limits(limits),
+            resharing::Message::R4(msg) => msg.factor_proofs.check_limits(limits),
+            resharing::Message::Ack
+            | resharing::Message::FinalAck(_)
+            | resharing::Message::ProofsAck
+            | resharing::Message::Resend(_) => Ok(()),
+            resharing::Message::Abort(msg) => msg.check_limits(limits),
+        }
+    }
+
+    fn max_bytes(&self, limits: &Limits) -> usize {
+        match self {
+            resharing::Message::R2(_) => limits.max_proof_message_bytes,
+            _ => limits.max_message_bytes,
+        }
+    }
+}
+
+impl Bounded for refresh::Message {
+    fn check_limits(&self, limits: &Limits) -> Result<(), LimitError> {
+        match self {
+            refresh::Message::R1(msg) => {
+                msg.vss.check_limits(limits)?;
+                msg.ek.check_limits(limits)?;
+                msg.correct_key_proof.check_limits(limits)?;
+                msg.range_proof_setup.check_limits(limits)?;
+                msg.modulus_proofs.check_limits(limits)
+            }
+            refresh::Message::R2(msg) => msg.factor_proofs.check_limits(limits),
+            refresh::Message::R3(_) | refresh::Message::Resend(_) => Ok(()),
+            refresh::Message::Abort(msg) => msg.check_limits(limits),
+        }
+    }
+
+    fn max_bytes(&self, limits: &Limits) -> usize {
+        match self {
+            refresh::Message::R1(_) => limits.max_proof_message_bytes,
+            _ => limits.max_message_bytes,
+        }
+    }
+}
+
+impl Bounded for recovery::Message {
+    fn check_limits(&self, limits: &Limits) -> Result<(), LimitError> {
+        match self {
+            recovery::Message::R1(msg) => {
+                msg.ek.check_limits(limits)?;
+                msg.correct_key_proof.check_limits(limits)?;
+                msg.range_proof_setup.check_limits(limits)?;
+                msg.modulus_proofs.check_limits(limits)
+            }
+            recovery::Message::R2(msg) => {
+                msg.ek.check_limits(limits)?;
+                msg.correct_key_proof.check_limits(limits)?;
+                msg.range_proof_setup.check_limits(limits)
+            }
+            recovery::Message::R4(msg) => msg.factor_proofs.check_limits(limits),
+            recovery::Message::R3(_) | recovery::Message::R5(_) | recovery::Message::Resend(_) => {
+                Ok(())
+            }
+            recovery::Message::Abort(msg) => msg.check_limits(limits),
+        }
+    }
+
+    fn max_bytes(&self, limits: &Limits) -> usize {
+        match self {
+            recovery::Message::R1(_) => limits.max_proof_message_bytes,
+            _ => limits.max_message_bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{Address, ProtocolId, Session, SessionId};
+    use crate::types::{PaillierKeys, Scalar, VerifiableSS};
+    use crate::wire;
+    use crate::zk_range_proofs::ZkpSetup;
+
+    fn session() -> Session {
+        Session {
+            protocol: ProtocolId::Keygen,
+            id: SessionId::random(),
+        }
+    }
+
+    fn phase1(keys: &PaillierKeys, setup: &ZkpSetup) -> keygen::Message {
+        keygen::Message::R1(keygen::Phase1Broadcast {
+            e: keys.ek.clone(),
+            com: BigInt::from(42),
+            correct_key_proof: CorrectKeyProof(nizk_rsa::gen_proof(&keys.dk)),
+            range_proof_setup: Some(ZkpPublicSetup::from_private_zkp_setup(setup)),
+            modulus_proofs: ModulusProofs::new(&keys.dk, Some(setup), &BigInt::from(42))
+                .expect("keys are made of Blum primes"),
+        })
+    }
+
+    #[test]
+    fn oversized_numbers_refused() {
+        let limits = Limits::default();
+        let keys = PaillierKeys::random();
+        let setup = ZkpSetup::random(DEFAULT_GROUP_ORDER_BIT_LENGTH);
+        let honest = phase1(&keys, &setup);
+        assert!(honest.check_limits(&limits).is_ok());
+
+        let mut huge = keys.ek.clone();
+        huge.n = BigInt::from(1) << 100_000;
+        let mut msg = phase1(&keys, &setup);
+        if let keygen::Message::R1(m) = &mut msg {
+            m.e = huge;
+        }
+        assert!(matches!(
+            msg.check_limits(&limits),
+            Err(LimitError::NumberTooLong { bits: 100_001, .. })
+        ));
+
+        let mut msg = phase1(&keys, &setup);
+        if let keygen::Message::R1(m) = &mut msg {
+            m.correct_key_proof.0 = vec![BigInt::from(1); 1000];
+        }
+        assert!(matches!(
+            msg.check_limits(&limits),
+            Err(LimitError::WrongLength { len: 1000, .. })
+        ));
+
+        let mut msg = phase1(&keys, &setup);
+        if let keygen::Message::R1(m) = &mut msg {
+            m.modulus_proofs.paillier.z.pop();
+        }
+        assert!(matches!(
+            msg.check_limits(&limits),
+            Err(LimitError::WrongLength { len, .. }) if len == paillier_blum::M - 1
+        ));
+
+        // larger keys are accepted once the limits allow them
+        assert!(honest
+            .check_limits(&limits.with_modulus_bits(1024))
+            .is_err());
+    }
+
+    #[test]
+    fn vss_structure_checked() {
+        let (mut vss, shares) = VerifiableSS::share(2, 5, &Scalar::random());
+        let msg = |vss: &CurvVerifiableSS| {
+            resharing::Message::R3(resharing::VSS {
+                share: (1, shares[0].clone()),
+                vss: vss.clone(),
+            })
+        };
+        assert!(msg(&vss).check_limits(&Limits::default()).is_ok());
+        assert!(msg(&vss)
+            .check_limits(&Limits::default().with_max_parties(4))
+            .is_err());
+        vss.commitments.push(vss.commitments[0].clone());
+        assert!(matches!(
+            msg(&vss).check_limits(&Limits::default()),
+            Err(LimitError::WrongLength { .. })
+        ));
+    }
+
+    #[test]
+    fn json_length_checked_before_parsing() -> anyhow::Result<()> {
+        let msg = OutputMessage::new(
+            session(),
+            Address::Broadcast,
+            resharing::Message::R1(resharing::Phase1Broadcast {
+                y: crate::types::Point::generator().to_point(),
+                vss_commitment: BigInt::from(1) << 255,
+            }),
+        );
+        let json = serde_json::to_vec(&msg)?;
+        let limits = Limits::default();
+        assert!(from_json::<resharing::OutMsg>(&json, &limits).is_ok());
+        let short = limits
+            .with_max_message_bytes(json.len() / JSON_INFLATION - 1)
+            .with_max_proof_message_bytes(json.len() / JSON_INFLATION - 1);
+        assert!(matches!(
+            from_json::<resharing::OutMsg>(&json, &short),
+            Err(LimitError::MessageTooLong { .. })
+        ));
+
+        let mut oversized = msg;
+        oversized.body = resharing::Message::R1(resharing::Phase1Broadcast {
+            y: crate::types::Point::generator().to_point(),
+            vss_commitment: BigInt::from(1) << 256,
+        });
+        let json = serde_json::to_vec(&oversized)?;
+        assert!(matches!(
+            from_json::<resharing::OutMsg>(&json, &limits),
+            Err(LimitError::NumberTooLong { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn messages_with_modulus_proofs_have_own_length_limit() -> anyhow::Result<()> {
+        let keys = PaillierKeys::random();
+        let setup = ZkpSetup::random(DEFAULT_GROUP_ORDER_BIT_LENGTH);
+        let limits = Limits::default();
+        let phase1 = OutputMessage::new(session(), Address::Broadcast, phase1(&keys, &setup));
+        let len = wire::to_bytes(&phase1).unwrap().len();
+        assert!(len > limits.max_message_bytes && len <= limits.max_proof_message_bytes);
+
+        let json = serde_json::to_vec(&phase1)?;
+        assert!(from_json::<keygen::OutMsg>(&json, &limits).is_ok());
+        assert!(matches!(
+            from_json::<keygen::OutMsg>(&json, &limits.with_max_proof_message_bytes(len / 2)),
+            Err(LimitError::MessageTooLong { .. })
+        ));
+
+        // other messages are still bound by the global limit
+        let phase2 = OutputMessage::new(
+            session(),
+            Address::Broadcast,
+            keygen::Message::R2(keygen::DecommitPublicKey {
+                blind_factor: BigInt::from(42),
+                y_i: crate::types::Point::generator().to_point(),
+                factor_proofs: FactorProofs::new(
+                    &keys.dk,
+                    &setup,
+                    &ZkpPublicSetup::from_private_zkp_setup(&setup),
+                    &BigInt::from(42),
+                ),
+            }),
+        );
+        let json = serde_json::to_vec(&phase2)?;
+        assert!(from_json::<keygen::OutMsg>(&json, &limits).is_ok());
+        assert!(matches!(
+            from_json::<keygen::OutMsg>(&json, &limits.with_max_message_bytes(8)),
+            Err(LimitError::MessageTooLong { .. })
+        ));
+        Ok(())
+    }
+}
@@ -3,19 +3,96 @@
 #![allow(non_snake_case)]
 #![allow(clippy::large_enum_variant)]
 
+use crate::protocol::PartyIndex;
 use crate::types::CurvVerifiableSS;
 use crate::types::{BigInt, FE, GE};
+use crate::zk_range_proofs::{ZkpPublicSetup, ZkpSetup};
+use algorithms::no_small_factor::NoSmallFactorProof;
+use algorithms::paillier_blum::PaillierBlumProof;
+use paillier::{DecryptionKey, EncryptionKey};
 use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use thiserror::Error;
 use zeroize::Zeroize;
 
+/// Maximal length of the reason of [`Abort`](struct.Abort.html), in bytes
+pub const MAX_ABORT_REASON_BYTES: usize = 1024;
+
+/// Notice of the party which has aborted the protocol
+///
+/// The failing party sends the notice to its peers, so that they end the session without waiting for their timeouts.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Abort {
+    /// summary of errors of the party
+    pub reason: String,
+    /// parties which the errors are attributed to
+    pub blamed: Vec<PartyIndex>,
+}
+
+impl Abort {
+    /// Summarizes errors, truncating the summary to [`MAX_ABORT_REASON_BYTES`](constant.MAX_ABORT_REASON_BYTES.html)
+    pub fn new<E: Display>(errors: &[E], blamed: Vec<PartyIndex>) -> Self {
+        let mut reason = errors
+            .iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>()
+            .join("; ");
+        if reason.len() > MAX_ABORT_REASON_BYTES {
+            let mut end = MAX_ABORT_REASON_BYTES;
+            while !reason.is_char_boundary(end) {
+                end -= 1;
+            }
+            reason.truncate(end);
+        }
+        Abort { reason, blamed }
+    }
+}
+
+/// Request of the party which asks a peer to send its messages of the round again
+///
+/// The peer sends the messages it has kept since it created them, so that no new randomness is exposed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Resend {
+    pub round: u16,
+}
+
+/// Implements [`ControlMessage`](../protocol/trait.ControlMessage.html) for the message enum of a protocol, which has variants `Abort` and `Resend`
+macro_rules! control_message {
+    ($message:ident) => {
+        impl crate::protocol::ControlMessage for $message {
+            fn abort(notice: Abort) -> Self {
+                $message::Abort(notice)
+            }
+
+            fn as_abort(&self) -> Option<&Abort> {
+                match self {
+                    $message::Abort(notice) => Some(notice),
+                    _ => None,
+                }
+            }
+
+            fn resend(request: Resend) -> Self {
+                $message::Resend(request)
+            }
+
+            fn as_resend(&self) -> Option<&Resend> {
+                match self {
+                    $message::Resend(request) => Some(request),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
 /// key generation related message data types
 pub mod keygen {
-    use super::{BigInt, GE};
+    use super::{Abort, BigInt, FactorProofs, ModulusProofs, Resend, GE};
     use crate::keygen::CorrectKeyProof;
     use crate::messages::FeldmanVSS;
     use crate::zk_range_proofs::ZkpPublicSetup;
 
-    use crate::types::CurvDLogProofType;
+    use crate::proofs::DLogProof;
     use paillier::EncryptionKey;
     use serde::{Deserialize, Serialize};
 
@@ -25,9 +102,25 @@ pub mod keygen {
         R1(Phase1Broadcast),
         R2(DecommitPublicKey),
         R3(FeldmanVSS),
-        R4(CurvDLogProofType),
+        R4(DLogProof),
+        Abort(Abort),
+        Resend(Resend),
+    }
+
+    impl crate::protocol::Round for Message {
+        fn round(&self) -> u16 {
+            match self {
+                Message::R1(_) => 1,
+                Message::R2(_) => 2,
+                Message::R3(_) => 3,
+                Message::R4(_) => 4,
+                Message::Abort(_) | Message::Resend(_) => 0,
+            }
+        }
     }
 
+    control_message!(Message);
+
     pub type InMsg = crate::protocol::InputMessage<Message>;
     pub type OutMsg = crate::protocol::OutputMessage<Message>;
 
@@ -65,8 +158,8 @@ pub mod keygen {
         }
     }
 
-    impl From<Message> for Option<CurvDLogProofType> {
-        fn from(m: Message) -> Option<CurvDLogProofType> {
+    impl From<Message> for Option<DLogProof> {
+        fn from(m: Message) -> Option<DLogProof> {
             match m {
                 Message::R4(proof) => Some(proof),
                 _ => None,
@@ -81,28 +174,34 @@ pub mod keygen {
     /// * commitment to partial public EC schema key
     /// * proof for Paillier key
     /// * optional public range proof setup   
+    /// * Paillier-Blum modulus proofs of Paillier key and of range proof setup
     #[derive(Debug, Clone, Serialize, Deserialize)]
     pub struct Phase1Broadcast {
         pub e: EncryptionKey,
         pub com: BigInt,
         pub correct_key_proof: CorrectKeyProof,
         pub range_proof_setup: Option<ZkpPublicSetup>,
+        pub modulus_proofs: ModulusProofs,
     }
 
     /// Decommitment of partial public EC schema key
+    ///
+    /// It also carries proofs that moduli of the sender have no small factors, made with the range proof setup of the recipient,
+    /// hence it is sent to each of other parties separately.
     #[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
     pub struct DecommitPublicKey {
         pub blind_factor: BigInt,
         pub y_i: GE,
+        pub factor_proofs: FactorProofs,
     }
 }
 
 /// Message data types used by the signing algorithm
 pub mod signing {
-    use super::{BigInt, FE, GE};
+    use super::{Abort, BigInt, Resend, FE, GE};
     use crate::zk_range_proofs::{MessageA, MessageB};
 
-    use crate::types::{CurvDLogProofType, CurvHomoElGamalProof};
+    use crate::proofs::{DLogProof, HomoElGamalProof};
     use serde::{Deserialize, Serialize};
 
     pub type InMsg = crate::protocol::InputMessage<Message>;
@@ -122,7 +221,7 @@ pub mod signing {
     pub struct SignDecommitPhase4 {
         pub blind_factor: BigInt,
         pub g_gamma_i: GE,
-        pub gamma_proof: CurvDLogProofType,
+        pub gamma_proof: DLogProof,
     }
 
     /// Commitment to $` V_{i} , \space A_{i} `$, see `Phase5A` in the paper
@@ -144,7 +243,7 @@ pub mod signing {
         pub A_i: GE,
         pub B_i: GE,
         pub blind_factor: BigInt,
-        pub proof: CurvHomoElGamalProof,
+        pub proof: HomoElGamalProof,
     }
 
     /// Decommitment to $` U_{i} , \space T_{i} `$, see Phase 5D in the paper
@@ -180,8 +279,30 @@ pub mod signing {
         R7(Phase5Com2),
         R8(Phase5Decom2),
         R9(Phase5Edata), // signature part
+        Abort(Abort),
+        Resend(Resend),
+    }
+
+    impl crate::protocol::Round for Message {
+        fn round(&self) -> u16 {
+            match self {
+                Message::R1(_) => 1,
+                Message::R2(_) => 2,
+                Message::R2b(_) => 3,
+                Message::R3(_) => 4,
+                Message::R4(_) => 5,
+                Message::R5(_) => 6,
+                Message::R6(_) => 7,
+                Message::R7(_) => 8,
+                Message::R8(_) => 9,
+                Message::R9(_) => 10,
+                Message::Abort(_) | Message::Resend(_) => 0,
+            }
+        }
     }
 
+    control_message!(Message);
+
     impl InMsg {
         pub fn is_duplicate(&self, current_msg_set: &[InMsg]) -> bool {
             current_msg_set.iter().any(|m| m.sender == self.sender)
@@ -272,13 +393,16 @@ pub mod signing {
 }
 /// key resharing related message data types
 pub mod resharing {
+    use super::{Abort, FactorProofs, ModulusProofs, Resend};
     use crate::keygen::CorrectKeyProof;
     use crate::messages::SecretShare;
+    use crate::proofs::{ProofError, SchnorrSignature};
+    use crate::protocol::Session;
     use crate::zk_range_proofs::ZkpPublicSetup;
 
     use crate::types::BigInt;
 
-    use crate::types::{CurvVerifiableSS, GE};
+    use crate::types::{CurvVerifiableSS, FE, GE};
     use paillier::EncryptionKey;
     use serde::{Deserialize, Serialize};
 
@@ -293,6 +417,7 @@ pub mod resharing {
         pub ek: EncryptionKey,
         pub correct_key_proof: CorrectKeyProof,
         pub range_proof_setup: Option<ZkpPublicSetup>,
+        pub modulus_proofs: ModulusProofs,
     }
 
     #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -301,6 +426,42 @@ pub mod resharing {
         pub vss: CurvVerifiableSS,
     }
 
+    /// Proofs that moduli of a member of new committee have no small factors, made with the range proof setup of the recipient
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct Phase4Broadcast {
+        pub factor_proofs: FactorProofs,
+    }
+
+    /// Acknowledgement of a member of new committee that it holds a valid share of the public key
+    ///
+    /// `public_share` is the public image of the new share of the member, and `signature` signs the public key with the new share,
+    /// so that a member of old committee can keep the acknowledgement as the receipt.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct SignedAck {
+        pub public_key: GE,
+        pub public_share: GE,
+        pub signature: SchnorrSignature,
+    }
+
+    impl SignedAck {
+        pub fn new(session: &Session, public_key: &GE, share: &FE) -> Self {
+            SignedAck {
+                public_key: public_key.clone(),
+                public_share: GE::generator() * share,
+                signature: SchnorrSignature::sign(session, share, &public_key.to_bytes(true)),
+            }
+        }
+
+        /// Checks that the acknowledgement is given for the public key, and that it is signed with the share of `public_share`
+        pub fn verify(&self, session: &Session, public_key: &GE) -> Result<(), ProofError> {
+            if self.public_key != *public_key {
+                return Err(ProofError);
+            }
+            self.signature
+                .verify(session, &self.public_share, &public_key.to_bytes(true))
+        }
+    }
+
     /// Messages used by key resharing algorithm
     #[derive(Debug, Clone, Deserialize, Serialize, Display)]
     pub enum Message {
@@ -308,9 +469,30 @@ pub mod resharing {
         R2(Phase2Broadcast),
         R3(VSS),
         Ack,
-        FinalAck,
+        FinalAck(SignedAck),
+        R4(Phase4Broadcast),
+        ProofsAck,
+        Abort(Abort),
+        Resend(Resend),
+    }
+
+    impl crate::protocol::Round for Message {
+        fn round(&self) -> u16 {
+            match self {
+                Message::R1(_) => 1,
+                Message::R2(_) => 2,
+                Message::R3(_) => 3,
+                Message::Ack => 4,
+                Message::FinalAck(_) => 5,
+                Message::R4(_) => 6,
+                Message::ProofsAck => 7,
+                Message::Abort(_) | Message::Resend(_) => 0,
+            }
+        }
     }
 
+    control_message!(Message);
+
     pub type InMsg = crate::protocol::InputMessage<Message>;
     pub type OutMsg = crate::protocol::OutputMessage<Message>;
 
@@ -347,6 +529,250 @@ pub mod resharing {
             }
         }
     }
+
+    impl From<Message> for Option<SignedAck> {
+        fn from(x: Message) -> Option<SignedAck> {
+            match x {
+                Message::FinalAck(msg) => Some(msg),
+                _ => None,
+            }
+        }
+    }
+
+    impl From<Message> for Option<Phase4Broadcast> {
+        fn from(x: Message) -> Option<Phase4Broadcast> {
+            match x {
+                Message::R4(msg) => Some(msg),
+                _ => None,
+            }
+        }
+    }
+}
+
+pub mod refresh {
+    use super::{Abort, FactorProofs, ModulusProofs, Resend};
+    use crate::keygen::CorrectKeyProof;
+    use crate::messages::SecretShare;
+    use crate::proofs::DLogProof;
+    use crate::types::{CurvVerifiableSS, GE};
+    use crate::zk_range_proofs::ZkpPublicSetup;
+    use paillier::EncryptionKey;
+    use serde::{Deserialize, Serialize};
+
+    /// Initial broadcast in the key refresh protocol
+    ///
+    /// Contains:
+    /// * Feldman's VSS of the polynomial with zero constant term
+    /// * public image of the current share of the sender
+    /// * new public Paillier key and proof of its correctness
+    /// * new public range proof setup
+    /// * Paillier-Blum modulus proofs of new Paillier key and of new range proof setup
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct Phase1Broadcast {
+        pub vss: CurvVerifiableSS,
+        pub public_share: GE,
+        pub ek: EncryptionKey,
+        pub correct_key_proof: CorrectKeyProof,
+        pub range_proof_setup: ZkpPublicSetup,
+        pub modulus_proofs: ModulusProofs,
+    }
+
+    /// The share of the zero polynomial of the sender at the point of the recipient
+    ///
+    /// Also carries proofs that new moduli of the sender have no small factors, made with the new range proof setup of the recipient.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct Phase2Share {
+        pub share: SecretShare,
+        pub factor_proofs: FactorProofs,
+    }
+
+    /// Messages used by key refresh algorithm
+    #[derive(Debug, Clone, Deserialize, Serialize, Display)]
+    pub enum Message {
+        R1(Phase1Broadcast),
+        R2(Phase2Share),
+        R3(DLogProof),
+        Abort(Abort),
+        Resend(Resend),
+    }
+
+    impl crate::protocol::Round for Message {
+        fn round(&self) -> u16 {
+            match self {
+                Message::R1(_) => 1,
+                Message::R2(_) => 2,
+                Message::R3(_) => 3,
+                Message::Abort(_) | Message::Resend(_) => 0,
+            }
+        }
+    }
+
+    control_message!(Message);
+
+    pub type InMsg = crate::protocol::InputMessage<Message>;
+    pub type OutMsg = crate::protocol::OutputMessage<Message>;
+
+    // Conversion helpers : unwrap MessageType variant to one of its inner structs
+    impl From<Message> for Option<Phase1Broadcast> {
+        fn from(x: Message) -> Option<Phase1Broadcast> {
+            match x {
+                Message::R1(msg) => Some(msg),
+                _ => None,
+            }
+        }
+    }
+
+    impl From<Message> for Option<Phase2Share> {
+        fn from(x: Message) -> Option<Phase2Share> {
+            match x {
+                Message::R2(msg) => Some(msg),
+                _ => None,
+            }
+        }
+    }
+
+    impl From<Message> for Option<DLogProof> {
+        fn from(x: Message) -> Option<DLogProof> {
+            match x {
+                Message::R3(proof) => Some(proof),
+                _ => None,
+            }
+        }
+    }
+}
+
+pub mod recovery {
+    use super::{Abort, FactorProofs, ModulusProofs, Resend};
+    use crate::keygen::CorrectKeyProof;
+    use crate::types::{FE, GE};
+    use crate::zk_range_proofs::ZkpPublicSetup;
+    use paillier::EncryptionKey;
+    use serde::{Deserialize, Serialize};
+
+    /// New keys of the lost party, sent to all holders of the key
+    ///
+    /// Contains:
+    /// * new public Paillier key and proof of its correctness
+    /// * optional new public range proof setup
+    /// * Paillier-Blum modulus proofs of new Paillier key and of new range proof setup
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct Phase1Broadcast {
+        pub ek: EncryptionKey,
+        pub correct_key_proof: CorrectKeyProof,
+        pub range_proof_setup: Option<ZkpPublicSetup>,
+        pub modulus_proofs: ModulusProofs,
+    }
+
+    /// Public record of a holder of the key, sent to the lost party
+    ///
+    /// Contains the point of the holder, its public Paillier key with the proof of its correctness, and its optional public range proof setup.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct Phase2Record {
+        pub point: u16,
+        pub ek: EncryptionKey,
+        pub correct_key_proof: CorrectKeyProof,
+        pub range_proof_setup: Option<ZkpPublicSetup>,
+    }
+
+    /// Random additive piece of the contribution of a helper, sent to another helper
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct Phase3Piece {
+        pub piece: FE,
+    }
+
+    /// Proofs that new moduli of the lost party have no small factors, made with the range proof setup of the recipient
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct Phase4Proofs {
+        pub factor_proofs: Option<FactorProofs>,
+    }
+
+    /// Blinded contribution of a helper to the share of the lost party
+    ///
+    /// `blinded_sum` is the sum of pieces the helper has received, so that only the sum of contributions of all helpers is the share.
+    /// `public_share` is the public image of the share of the helper, and `point` is the point of the lost party.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct Phase5Contribution {
+        pub blinded_sum: FE,
+        pub public_share: GE,
+        pub public_key: GE,
+        pub point: u16,
+    }
+
+    /// Messages used by share recovery algorithm
+    #[derive(Debug, Clone, Deserialize, Serialize, Display)]
+    pub enum Message {
+        R1(Phase1Broadcast),
+        R2(Phase2Record),
+        R3(Phase3Piece),
+        R4(Phase4Proofs),
+        R5(Phase5Contribution),
+        Abort(Abort),
+        Resend(Resend),
+    }
+
+    impl crate::protocol::Round for Message {
+        fn round(&self) -> u16 {
+            match self {
+                Message::R1(_) => 1,
+                Message::R2(_) => 2,
+                Message::R3(_) => 3,
+                Message::R4(_) => 4,
+                Message::R5(_) => 5,
+                Message::Abort(_) | Message::Resend(_) => 0,
+            }
+        }
+    }
+
+    control_message!(Message);
+
+    pub type InMsg = crate::protocol::InputMessage<Message>;
+    pub type OutMsg = crate::protocol::OutputMessage<Message>;
+
+    // Conversion helpers : unwrap MessageType variant to one of its inner structs
+    impl From<Message> for Option<Phase1Broadcast> {
+        fn from(x: Message) -> Option<Phase1Broadcast> {
+            match x {
+                Message::R1(msg) => Some(msg),
+                _ => None,
+            }
+        }
+    }
+
+    impl From<Message> for Option<Phase2Record> {
+        fn from(x: Message) -> Option<Phase2Record> {
+            match x {
+                Message::R2(msg) => Some(msg),
+                _ => None,
+            }
+        }
+    }
+
+    impl From<Message> for Option<Phase3Piece> {
+        fn from(x: Message) -> Option<Phase3Piece> {
+            match x {
+                Message::R3(msg) => Some(msg),
+                _ => None,
+            }
+        }
+    }
+
+    impl From<Message> for Option<Phase4Proofs> {
+        fn from(x: Message) -> Option<Phase4Proofs> {
+            match x {
+                Message::R4(msg) => Some(msg),
+                _ => None,
+            }
+        }
+    }
+
+    impl From<Message> for Option<Phase5Contribution> {
+        fn from(x: Message) -> Option<Phase5Contribution> {
+            match x {
+                Message::R5(msg) => Some(msg),
+                _ => None,
+            }
+        }
+    }
 }
 
 /// Shamir's secret share
@@ -399,3 +825,115 @@ impl FeldmanVSS {
         valid && pubkey_valid
     }
 }
+
+/// Failure of the proof of a modulus of a party
+#[derive(Debug, Error)]
+#[error("{modulus} modulus: {reason}")]
+pub struct ModulusProofError {
+    pub modulus: &'static str,
+    pub reason: String,
+}
+
+impl ModulusProofError {
+    fn paillier<E: Display>(reason: E) -> Self {
+        ModulusProofError {
+            modulus: "Paillier",
+            reason: reason.to_string(),
+        }
+    }
+
+    fn n_tilde<E: Display>(reason: E) -> Self {
+        ModulusProofError {
+            modulus: "range proof setup",
+            reason: reason.to_string(),
+        }
+    }
+}
+
+/// Proofs that moduli of the party are products of two Blum primes
+///
+/// The party proves its Paillier modulus, and $`\tilde{N}`$ of its range proof setup if it has one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModulusProofs {
+    pub paillier: PaillierBlumProof,
+    pub n_tilde: Option<PaillierBlumProof>,
+}
+
+impl ModulusProofs {
+    pub fn new(
+        dk: &DecryptionKey,
+        setup: Option<&ZkpSetup>,
+        context: &BigInt,
+    ) -> Result<Self, ModulusProofError> {
+        Ok(ModulusProofs {
+            paillier: PaillierBlumProof::create(&dk.p, &dk.q, context)
+                .map_err(ModulusProofError::paillier)?,
+            n_tilde: setup.map(|s| s.modulus_proof(context)),
+        })
+    }
+
+    /// Verifies proofs of the Paillier key and of the public range proof setup of the sender
+    pub fn verify(
+        &self,
+        ek: &EncryptionKey,
+        setup: Option<&ZkpPublicSetup>,
+        context: &BigInt,
+    ) -> Result<(), ModulusProofError> {
+        self.paillier
+            .verify(&ek.n, context)
+            .map_err(ModulusProofError::paillier)?;
+        match (setup, &self.n_tilde) {
+            (Some(setup), Some(proof)) => proof
+                .verify(&setup.N_tilde, context)
+                .map_err(ModulusProofError::n_tilde),
+            (None, None) => Ok(()),
+            (Some(_), None) => Err(ModulusProofError::n_tilde("missing proof")),
+            (None, Some(_)) => Err(ModulusProofError::n_tilde("unexpected proof")),
+        }
+    }
+}
+
+/// Proofs that moduli of the party have no small factors
+///
+/// The proofs are made with the range proof setup of the recipient, hence the party sends distinct proofs to each of its peers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FactorProofs {
+    pub paillier: NoSmallFactorProof,
+    pub n_tilde: NoSmallFactorProof,
+}
+
+impl FactorProofs {
+    pub fn new(
+        dk: &DecryptionKey,
+        setup: &ZkpSetup,
+        recipient_setup: &ZkpPublicSetup,
+        context: &BigInt,
+    ) -> Self {
+        FactorProofs {
+            paillier: NoSmallFactorProof::create(
+                &dk.p,
+                &dk.q,
+                &recipient_setup.ring_pedersen(),
+                context,
+            ),
+            n_tilde: setup.factor_proof(recipient_setup, context),
+        }
+    }
+
+    /// Verifies proofs of the Paillier key and of the public range proof setup of the sender, made with the own setup of the party
+    pub fn verify(
+        &self,
+        ek: &EncryptionKey,
+        sender_setup: &ZkpPublicSetup,
+        own_setup: &ZkpSetup,
+        context: &BigInt,
+    ) -> Result<(), ModulusProofError> {
+        let ring_pedersen = own_setup.ring_pedersen();
+        self.paillier
+            .verify(&ek.n, &ring_pedersen, context)
+            .map_err(ModulusProofError::paillier)?;
+        self.n_tilde
+            .verify(&sender_setup.N_tilde, &ring_pedersen, context)
+            .map_err(ModulusProofError::n_tilde)
+    }
+}
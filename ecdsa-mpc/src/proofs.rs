@@ -0,0 +1,201 @@
+//! Zero knowledge proofs bound to sessions of protocols
+//!
+//! Sigma protocols made non-interactive with Fiat-Shamir transform. Challenges of these proofs include the bytes of the [`Session`],
+//! so that the proof created in one session of a protocol is not accepted in another session.
+//!
+//! [`Session`]: ../protocol/struct.Session.html
+#![allow(non_snake_case)]
+
+use crate::protocol::Session;
+use crate::types::{DigestExt, HomoElGamalStatement, HomoElGamalWitness, Point, Scalar};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+#[error("proof verification failed")]
+pub struct ProofError;
+
+/// Proof of knowledge of the discrete logarithm of `pk`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DLogProof {
+    pub pk: Point,
+    pub pk_t_rand_commitment: Point,
+    pub challenge_response: Scalar,
+}
+
+impl DLogProof {
+    pub fn prove(session: &Session, sk: &Scalar) -> Self {
+        let sk_t_rand_commitment = Scalar::random();
+        let pk_t_rand_commitment = Point::generator() * &sk_t_rand_commitment;
+        let pk = Point::generator() * sk;
+        let challenge = Self::challenge(session, &pk_t_rand_commitment, &pk);
+        let challenge_response = &sk_t_rand_commitment - &(challenge * sk);
+        DLogProof {
+            pk,
+            pk_t_rand_commitment,
+            challenge_response,
+        }
+    }
+
+    pub fn verify(&self, session: &Session) -> Result<(), ProofError> {
+        let challenge = Self::challenge(session, &self.pk_t_rand_commitment, &self.pk);
+        let pk_verifier = Point::generator() * &self.challenge_response + &self.pk * &challenge;
+        if pk_verifier == self.pk_t_rand_commitment {
+            Ok(())
+        } else {
+            Err(ProofError)
+        }
+    }
+
+    fn challenge(session: &Session, pk_t_rand_commitment: &Point, pk: &Point) -> Scalar {
+        Sha256::new()
+            .chain(session.to_bytes())
+            .chain_point(pk_t_rand_commitment)
+            .chain_point(&Point::generator().to_point())
+            .chain_point(pk)
+            .result_scalar()
+    }
+}
+
+/// Schnorr signature of a message with the secret key of `pk`
+///
+/// The signature is the proof of knowledge of the secret key whose challenge also includes the message.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SchnorrSignature {
+    pub rand_commitment: Point,
+    pub challenge_response: Scalar,
+}
+
+impl SchnorrSignature {
+    pub fn sign(session: &Session, sk: &Scalar, message: &[u8]) -> Self {
+        let sk_t_rand_commitment = Scalar::random();
+        let rand_commitment = Point::generator() * &sk_t_rand_commitment;
+        let pk = Point::generator() * sk;
+        let challenge = Self::challenge(session, &rand_commitment, &pk, message);
+        let challenge_response = &sk_t_rand_commitment - &(challenge * sk);
+        SchnorrSignature {
+            rand_commitment,
+            challenge_response,
+        }
+    }
+
+    pub fn verify(&self, session: &Session, pk: &Point, message: &[u8]) -> Result<(), ProofError> {
+        let challenge = Self::challenge(session, &self.rand_commitment, pk, message);
+        let pk_verifier = Point::generator() * &self.challenge_response + pk * &challenge;
+        if pk_verifier == self.rand_commitment {
+            Ok(())
+        } else {
+            Err(ProofError)
+        }
+    }
+
+    fn challenge(session: &Session, rand_commitment: &Point, pk: &Point, message: &[u8]) -> Scalar {
+        Sha256::new()
+            .chain(session.to_bytes())
+            .chain_point(rand_commitment)
+            .chain_point(&Point::generator().to_point())
+            .chain_point(pk)
+            .chain(message)
+            .result_scalar()
+    }
+}
+
+/// Proof of correct homomorphic ElGamal encryption, used in the phase 5 of signing
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HomoElGamalProof {
+    pub T: Point,
+    pub A3: Point,
+    pub z1: Scalar,
+    pub z2: Scalar,
+}
+
+type Statement = HomoElGamalStatement<crate::types::Secp256k1>;
+type Witness = HomoElGamalWitness<crate::types::Secp256k1>;
+
+impl HomoElGamalProof {
+    pub fn prove(session: &Session, w: &Witness, delta: &Statement) -> Self {
+        let s1 = Scalar::random();
+        let s2 = Scalar::random();
+        let A1 = &delta.H * &s1;
+        let A2 = &delta.Y * &s2;
+        let A3 = &delta.G * &s2;
+        let T = A1 + A2;
+        let e = Self::challenge(session, &T, &A3, delta);
+        let z1 = &s1 + &w.x * &e;
+        let z2 = s2 + &w.r * e;
+        HomoElGamalProof { T, A3, z1, z2 }
+    }
+
+    pub fn verify(&self, session: &Session, delta: &Statement) -> Result<(), ProofError> {
+        let e = Self::challenge(session, &self.T, &self.A3, delta);
+        let z1H_plus_z2Y = &delta.H * &self.z1 + &delta.Y * &self.z2;
+        let T_plus_eD = &self.T + &delta.D * &e;
+        let z2G = &delta.G * &self.z2;
+        let A3_plus_eE = &self.A3 + &delta.E * &e;
+        if z1H_plus_z2Y == T_plus_eD && z2G == A3_plus_eE {
+            Ok(())
+        } else {
+            Err(ProofError)
+        }
+    }
+
+    fn challenge(session: &Session, T: &Point, A3: &Point, delta: &Statement) -> Scalar {
+        Sha256::new()
+            .chain(session.to_bytes())
+            .chain_point(T)
+            .chain_point(A3)
+            .chain_point(&delta.G)
+            .chain_point(&delta.H)
+            .chain_point(&delta.Y)
+            .chain_point(&delta.D)
+            .chain_point(&delta.E)
+            .result_scalar()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{ProtocolId, SessionId};
+
+    #[test]
+    fn dlog_proof_bound_to_session() {
+        let session = Session {
+            protocol: ProtocolId::Keygen,
+            id: SessionId::random(),
+        };
+        let proof = DLogProof::prove(&session, &Scalar::random());
+        assert!(proof.verify(&session).is_ok());
+        let other = Session {
+            id: SessionId::random(),
+            ..session
+        };
+        assert!(proof.verify(&other).is_err());
+        let other = Session {
+            protocol: ProtocolId::Signing,
+            ..session
+        };
+        assert!(proof.verify(&other).is_err());
+    }
+
+    #[test]
+    fn schnorr_signature_bound_to_message() {
+        let session = Session {
+            protocol: ProtocolId::Resharing,
+            id: SessionId::random(),
+        };
+        let sk = Scalar::random();
+        let pk = Point::generator() * &sk;
+        let signature = SchnorrSignature::sign(&session, &sk, b"message");
+        assert!(signature.verify(&session, &pk, b"message").is_ok());
+        assert!(signature.verify(&session, &pk, b"other message").is_err());
+        let other_pk = Point::generator() * &Scalar::random();
+        assert!(signature.verify(&session, &other_pk, b"message").is_err());
+        let other = Session {
+            id: SessionId::random(),
+            ..session
+        };
+        assert!(signature.verify(&other, &pk, b"message").is_err());
+    }
+}
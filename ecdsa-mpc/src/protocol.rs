@@ -7,7 +7,11 @@ use hex::FromHexError;
 use serde::de::Visitor;
 use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
+use crate::limits::{Bounded, Limits};
+use crate::messages::{Abort, Resend};
+use crate::types::{BigInt, Converter};
 use anyhow::bail;
+use state_machine::types::{MessageClass, MessageOrigin, RejectReason, Resent};
 use std::cmp::Ordering::{Equal, Greater, Less};
 use std::fmt::{Debug, Display};
 
@@ -126,6 +130,90 @@ impl<'a> Deserialize<'a> for PartyIndex {
     }
 }
 
+/// Identifier of a session of a protocol
+///
+/// Parties agree on the identifier before they start the session, for instance the coordinator of the session picks a random one.
+/// It is bound into commitments and proofs of the session, so that they cannot be replayed into another session.
+#[derive(Clone, Copy, Hash, Eq, PartialEq, Default, Serialize, Deserialize)]
+pub struct SessionId(pub [u8; 32]);
+
+impl SessionId {
+    /// samples a random identifier
+    pub fn random() -> Self {
+        SessionId(rand::random())
+    }
+}
+
+impl Display for SessionId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        self.0.iter().try_for_each(|x| write!(f, "{:02x}", x))
+    }
+}
+
+impl Debug for SessionId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        Display::fmt(self, f)
+    }
+}
+
+/// Protocols implemented by the crate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProtocolId {
+    Keygen = 1,
+    Signing = 2,
+    Resharing = 3,
+    Refresh = 4,
+    Recovery = 5,
+}
+
+/// Version of the messages of protocols in the crate
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// The session of a protocol which a message belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    pub protocol: ProtocolId,
+    pub id: SessionId,
+}
+
+impl Session {
+    /// Bytes which bind commitments and proofs to the session and to the version of the protocol
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.protocol as u8];
+        bytes.extend_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+        bytes.extend_from_slice(&self.id.0);
+        bytes
+    }
+
+    /// The same bytes as a number, for proofs which hash lists of numbers
+    pub fn to_bigint(&self) -> BigInt {
+        BigInt::from_bytes(&self.to_bytes())
+    }
+}
+
+/// Round of the protocol which a message body belongs to
+pub trait Round {
+    fn round(&self) -> u16;
+}
+
+/// Header of every message of the protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MessageHeader {
+    pub session: Session,
+    pub version: u16,
+    pub round: u16,
+}
+
+impl MessageHeader {
+    pub fn new<B: Round>(session: Session, body: &B) -> Self {
+        MessageHeader {
+            session,
+            version: PROTOCOL_VERSION,
+            round: body.round(),
+        }
+    }
+}
+
 /// Message destination address type
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 pub enum Address {
@@ -137,19 +225,636 @@ pub enum Address {
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct InputMessage<BodyType> {
     pub sender: PartyIndex,
+    pub header: MessageHeader,
     pub body: BodyType,
 }
 
+impl<BodyType> InputMessage<BodyType> {
+    /// Wraps the message received from the sender
+    pub fn from_output(sender: PartyIndex, msg: OutputMessage<BodyType>) -> Self {
+        InputMessage {
+            sender,
+            header: msg.header,
+            body: msg.body,
+        }
+    }
+}
+
+/// Relation of the sender of a message to the phase which classifies the message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SenderRole {
+    /// the phase waits for the input of the sender
+    Awaited,
+    /// the sender takes part in the session, but the phase does not wait for its input
+    Member,
+    /// the sender does not take part in the session
+    Outsider,
+}
+
+impl SenderRole {
+    /// The role of the sender which takes part in the session if `member`, and whose input the phase waits for if `awaited`
+    pub fn new(awaited: bool, member: bool) -> Self {
+        match (awaited, member) {
+            (true, _) => SenderRole::Awaited,
+            (false, true) => SenderRole::Member,
+            (false, false) => SenderRole::Outsider,
+        }
+    }
+
+    /// The role of the sender in phases which wait for the input of every other party of the session
+    pub fn of_member(member: bool) -> Self {
+        Self::new(member, member)
+    }
+}
+
+impl<BodyType: Round + Bounded> InputMessage<BodyType> {
+    /// Classifies the message for a phase of a protocol
+    ///
+    /// Messages of other sessions, protocols or versions are rejected, as well as messages whose header declares a round which differs from their body,
+    /// and messages exceeding `limits` of sizes of their numbers and vectors, which the phase takes from its constructor.
+    /// Messages of parties which do not take part in the session are rejected before anything else is checked, so that they are never deferred.
+    ///
+    /// `round` is the round of messages which the phase consumes, `expected_kind` tells whether the message body is the one the phase waits for,
+    /// and `sender` tells how the sender relates to the phase. Messages of other kinds are deferred, as they may be expected by following phases,
+    /// unless they belong to a round before `round`: such messages have either been consumed by previous phases or arrived too late, and are rejected as stale.
+    /// Control messages have the round 0, hence phases which handle them pass the round 0.
+    pub fn classify(
+        &self,
+        session: &Session,
+        limits: &Limits,
+        round: u16,
+        expected_kind: bool,
+        sender: SenderRole,
+        current_msg_set: &[InputMessage<BodyType>],
+    ) -> MessageClass {
+        if self.header.session != *session || self.header.version != PROTOCOL_VERSION {
+            MessageClass::Reject(RejectReason::ForeignSession)
+        } else if self.header.round != self.body.round() {
+            MessageClass::Reject(RejectReason::RoundMismatch)
+        } else if let Err(e) = self.body.check_limits(limits) {
+            log::warn!("message from {} exceeds limits: {}", self.sender, e);
+            MessageClass::Reject(RejectReason::LimitExceeded)
+        } else if sender == SenderRole::Outsider {
+            MessageClass::Reject(RejectReason::UnknownSender)
+        } else if !expected_kind && self.body.round() < round {
+            MessageClass::Reject(RejectReason::Stale)
+        } else if !expected_kind {
+            MessageClass::Defer
+        } else if sender != SenderRole::Awaited {
+            MessageClass::Reject(RejectReason::UnknownSender)
+        } else if current_msg_set.iter().any(|m| m.sender == self.sender) {
+            MessageClass::Reject(RejectReason::Duplicate)
+        } else {
+            MessageClass::Accept
+        }
+    }
+}
+
+impl<BodyType> MessageOrigin for InputMessage<BodyType> {
+    type Party = PartyIndex;
+    fn sender(&self) -> PartyIndex {
+        self.sender
+    }
+}
+
 /// Outgoing message wrapper
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct OutputMessage<BodyType> {
     pub recipient: Address,
+    pub header: MessageHeader,
     pub body: BodyType,
 }
 
+impl<BodyType: Round> OutputMessage<BodyType> {
+    /// Creates the message of the session, with the header which declares the round of the body
+    pub fn new(session: Session, recipient: Address, body: BodyType) -> Self {
+        OutputMessage {
+            recipient,
+            header: MessageHeader::new(session, &body),
+            body,
+        }
+    }
+}
+
+impl<BodyType: Clone> OutputMessage<BodyType> {
+    /// The copy of the message for the peer which asks to send it again, if the peer is one of its recipients
+    ///
+    /// The copy has the same header and body as the original, only its recipient may differ: the copy of a broadcast is addressed to the peer alone,
+    /// so that other parties do not receive the broadcast twice. The recipient is not part of the received message,
+    /// hence the [echo broadcast](../echo/index.html), whose digests cover the sender, the header and the body, treats the copy like the original.
+    pub fn resent_to(&self, peer: PartyIndex) -> Option<Self> {
+        match self.recipient {
+            Address::Peer(recipient) if recipient != peer => None,
+            _ => Some(OutputMessage {
+                recipient: Address::Peer(peer),
+                header: self.header,
+                body: self.body.clone(),
+            }),
+        }
+    }
+}
+
+/// Asks each of awaited parties to send its messages of the round again
+pub fn resend_requests<B>(
+    session: Session,
+    round: u16,
+    awaited: Vec<PartyIndex>,
+) -> Option<Vec<OutputMessage<B>>>
+where
+    B: ControlMessage + Round,
+{
+    Some(
+        awaited
+            .into_iter()
+            .map(|p| OutputMessage::new(session, Address::Peer(p), B::resend(Resend { round })))
+            .collect(),
+    )
+}
+
+/// Earlier output of the party if the message is the request of a peer to send it again
+///
+/// Messages are re-sent with their original bodies, so that no new randomness is exposed. The state machine limits how often it answers each peer.
+pub fn resent_output<B>(
+    session: &Session,
+    limits: &Limits,
+    msg: &InputMessage<B>,
+    known_sender: bool,
+    sent: &[OutputMessage<B>],
+) -> Option<Resent<OutputMessage<B>>>
+where
+    B: ControlMessage + Round + Bounded + Clone,
+{
+    let request = msg.body.as_resend()?;
+    match msg.classify(
+        session,
+        limits,
+        0,
+        true,
+        SenderRole::of_member(known_sender),
+        &[],
+    ) {
+        MessageClass::Accept => {
+            log::debug!(
+                "party {} asks for round {} of {:?} again",
+                msg.sender,
+                request.round,
+                session.protocol
+            );
+            Some(Resent {
+                round: request.round,
+                messages: sent
+                    .iter()
+                    .filter(|m| m.header.round == request.round)
+                    .filter_map(|m| m.resent_to(msg.sender))
+                    .collect(),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Message bodies of a protocol which carry notices of parties which abort the protocol, and requests to send messages again
+pub trait ControlMessage {
+    /// Wraps the abort notice into the body
+    fn abort(notice: Abort) -> Self;
+    /// The abort notice carried by the body, if any
+    fn as_abort(&self) -> Option<&Abort>;
+    /// Wraps the request to send messages again into the body
+    fn resend(request: Resend) -> Self;
+    /// The request to send messages again carried by the body, if any
+    fn as_resend(&self) -> Option<&Resend>;
+}
+
+/// Error states of a protocol which explain the failure to peers, and record failures of peers
+pub trait AbortReport {
+    /// The notice which tells peers why the party aborts the protocol and which parties it blames
+    fn abort_notice(&self) -> Abort;
+    /// The error state of the party whose peer has aborted the protocol with the notice
+    fn aborted_by(party: PartyIndex, notice: &Abort) -> Self;
+}
+
+/// Broadcasts the notice of the failure of the party to its peers
+pub fn abort_broadcast<B, E>(session: Session, error: &E) -> Option<Vec<OutputMessage<B>>>
+where
+    B: ControlMessage + Round,
+    E: AbortReport,
+{
+    Some(vec![OutputMessage::new(
+        session,
+        Address::Broadcast,
+        B::abort(error.abort_notice()),
+    )])
+}
+
+/// Sends the notice of the failure of the party to each of its peers
+pub fn abort_notices<'a, B, E>(
+    session: Session,
+    error: &E,
+    peers: impl Iterator<Item = &'a PartyIndex>,
+) -> Option<Vec<OutputMessage<B>>>
+where
+    B: ControlMessage + Round,
+    E: AbortReport,
+{
+    let notice = error.abort_notice();
+    Some(
+        peers
+            .map(|p| OutputMessage::new(session, Address::Peer(*p), B::abort(notice.clone())))
+            .collect(),
+    )
+}
+
+/// The error state of the party if the message is the notice of a peer which has aborted the session
+pub fn aborted_by_peer<B, E>(
+    session: &Session,
+    limits: &Limits,
+    msg: &InputMessage<B>,
+    known_sender: bool,
+) -> Option<E>
+where
+    B: ControlMessage + Round + Bounded,
+    E: AbortReport,
+{
+    let notice = msg.body.as_abort()?;
+    match msg.classify(
+        session,
+        limits,
+        0,
+        true,
+        SenderRole::of_member(known_sender),
+        &[],
+    ) {
+        MessageClass::Accept => {
+            log::error!(
+                "party {} aborted {:?}: {}",
+                msg.sender,
+                session.protocol,
+                notice.reason
+            );
+            Some(E::aborted_by(msg.sender, notice))
+        }
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::PartyIndex;
+    use super::*;
+    use crate::limits::LimitError;
+    use crate::types::{CommitmentScheme, Point};
+
+    #[derive(Debug, Clone)]
+    enum Body {
+        First,
+        Second,
+        Abort(Abort),
+        Resend(Resend),
+    }
+
+    impl Round for Body {
+        fn round(&self) -> u16 {
+            match self {
+                Body::First => 1,
+                Body::Second => 2,
+                Body::Abort(_) | Body::Resend(_) => 0,
+            }
+        }
+    }
+
+    impl ControlMessage for Body {
+        fn abort(notice: Abort) -> Self {
+            Body::Abort(notice)
+        }
+
+        fn as_abort(&self) -> Option<&Abort> {
+            match self {
+                Body::Abort(notice) => Some(notice),
+                _ => None,
+            }
+        }
+
+        fn resend(request: Resend) -> Self {
+            Body::Resend(request)
+        }
+
+        fn as_resend(&self) -> Option<&Resend> {
+            match self {
+                Body::Resend(request) => Some(request),
+                _ => None,
+            }
+        }
+    }
+
+    /// blamed parties of the failure, or the peer which has aborted with its notice
+    #[derive(Debug, PartialEq)]
+    enum Failure {
+        Blamed(Vec<PartyIndex>),
+        PeerAborted(PartyIndex, Abort),
+    }
+
+    impl AbortReport for Failure {
+        fn abort_notice(&self) -> Abort {
+            match self {
+                Failure::Blamed(blamed) => Abort::new(&["invalid proof"], blamed.clone()),
+                Failure::PeerAborted(party, _) => Abort::new(&["peer aborted"], vec![*party]),
+            }
+        }
+
+        fn aborted_by(party: PartyIndex, notice: &Abort) -> Self {
+            Failure::PeerAborted(party, notice.clone())
+        }
+    }
+
+    impl Bounded for Body {
+        fn check_limits(&self, _limits: &Limits) -> Result<(), LimitError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn foreign_messages_rejected() {
+        let session = Session {
+            protocol: ProtocolId::Keygen,
+            id: SessionId::random(),
+        };
+        let deliver = |session, body| {
+            InputMessage::from_output(
+                PartyIndex::from(1),
+                OutputMessage::new(session, Address::Broadcast, body),
+            )
+        };
+        let classify = |msg: &InputMessage<Body>| {
+            msg.classify(
+                &session,
+                &Limits::default(),
+                1,
+                true,
+                SenderRole::Awaited,
+                &[],
+            )
+        };
+
+        assert_eq!(
+            classify(&deliver(session, Body::First)),
+            MessageClass::Accept
+        );
+
+        let other_session = Session {
+            id: SessionId::random(),
+            ..session
+        };
+        let other_protocol = Session {
+            protocol: ProtocolId::Resharing,
+            ..session
+        };
+        for foreign in [other_session, other_protocol].iter() {
+            assert_eq!(
+                classify(&deliver(*foreign, Body::First)),
+                MessageClass::Reject(RejectReason::ForeignSession)
+            );
+        }
+
+        let mut newer = deliver(session, Body::First);
+        newer.header.version += 1;
+        assert_eq!(
+            classify(&newer),
+            MessageClass::Reject(RejectReason::ForeignSession)
+        );
+
+        let mut relabeled = deliver(session, Body::First);
+        relabeled.body = Body::Second;
+        assert_eq!(
+            classify(&relabeled),
+            MessageClass::Reject(RejectReason::RoundMismatch)
+        );
+    }
+
+    #[test]
+    fn senders_checked_before_deferral() {
+        let session = Session {
+            protocol: ProtocolId::Keygen,
+            id: SessionId::random(),
+        };
+        let deliver = |body| {
+            InputMessage::from_output(
+                PartyIndex::from(1),
+                OutputMessage::new(session, Address::Broadcast, body),
+            )
+        };
+        let first = deliver(Body::First);
+        let second = deliver(Body::Second);
+
+        // the first phase defers the message of the next round, unless the sender is an outsider
+        assert_eq!(
+            second.classify(
+                &session,
+                &Limits::default(),
+                1,
+                false,
+                SenderRole::Awaited,
+                &[]
+            ),
+            MessageClass::Defer
+        );
+        assert_eq!(
+            second.classify(
+                &session,
+                &Limits::default(),
+                1,
+                false,
+                SenderRole::Member,
+                &[]
+            ),
+            MessageClass::Defer
+        );
+        assert_eq!(
+            second.classify(
+                &session,
+                &Limits::default(),
+                1,
+                false,
+                SenderRole::Outsider,
+                &[]
+            ),
+            MessageClass::Reject(RejectReason::UnknownSender)
+        );
+        assert_eq!(
+            first.classify(
+                &session,
+                &Limits::default(),
+                1,
+                true,
+                SenderRole::Outsider,
+                &[]
+            ),
+            MessageClass::Reject(RejectReason::UnknownSender)
+        );
+        assert_eq!(
+            first.classify(
+                &session,
+                &Limits::default(),
+                1,
+                true,
+                SenderRole::Member,
+                &[]
+            ),
+            MessageClass::Reject(RejectReason::UnknownSender)
+        );
+        assert_eq!(
+            first.classify(
+                &session,
+                &Limits::default(),
+                1,
+                true,
+                SenderRole::Awaited,
+                std::slice::from_ref(&first)
+            ),
+            MessageClass::Reject(RejectReason::Duplicate)
+        );
+    }
+
+    #[test]
+    fn stale_messages_rejected() {
+        let session = Session {
+            protocol: ProtocolId::Keygen,
+            id: SessionId::random(),
+        };
+        // the duplicate of the first round, e.g. sent again on request, arrives in the second phase
+        let late = InputMessage::from_output(
+            PartyIndex::from(1),
+            OutputMessage::new(session, Address::Broadcast, Body::First),
+        );
+        assert_eq!(
+            late.classify(
+                &session,
+                &Limits::default(),
+                2,
+                false,
+                SenderRole::Awaited,
+                &[]
+            ),
+            MessageClass::Reject(RejectReason::Stale)
+        );
+        assert_eq!(
+            late.classify(
+                &session,
+                &Limits::default(),
+                1,
+                true,
+                SenderRole::Awaited,
+                &[]
+            ),
+            MessageClass::Accept
+        );
+        // control messages are not stale in the phases which handle them
+        let notice = InputMessage::from_output(
+            PartyIndex::from(1),
+            OutputMessage::new(
+                session,
+                Address::Broadcast,
+                Body::Abort(Abort::new(&["invalid proof"], vec![])),
+            ),
+        );
+        assert!(
+            aborted_by_peer::<_, Failure>(&session, &Limits::default(), &notice, true).is_some()
+        );
+    }
+
+    #[test]
+    fn abort_notices_exchanged() {
+        let session = Session {
+            protocol: ProtocolId::Keygen,
+            id: SessionId::random(),
+        };
+        let failure = Failure::Blamed(vec![PartyIndex::from(2)]);
+        let peers = [PartyIndex::from(1), PartyIndex::from(2)];
+        let notices: Vec<OutputMessage<Body>> =
+            abort_notices(session, &failure, peers.iter()).unwrap();
+        assert_eq!(notices.len(), 2);
+        assert_eq!(notices[1].recipient, Address::Peer(PartyIndex::from(2)));
+
+        let broadcast: Vec<OutputMessage<Body>> = abort_broadcast(session, &failure).unwrap();
+        let notice = broadcast[0].body.as_abort().cloned().unwrap();
+        assert_eq!(notice.blamed, vec![PartyIndex::from(2)]);
+        let received = InputMessage::from_output(PartyIndex::from(1), broadcast[0].clone());
+        assert_eq!(
+            aborted_by_peer::<_, Failure>(&session, &Limits::default(), &received, true),
+            Some(Failure::PeerAborted(PartyIndex::from(1), notice))
+        );
+        // notices of unknown parties and other sessions are ignored, as well as other messages
+        assert_eq!(
+            aborted_by_peer::<_, Failure>(&session, &Limits::default(), &received, false),
+            None
+        );
+        let other_session = Session {
+            id: SessionId::random(),
+            ..session
+        };
+        assert_eq!(
+            aborted_by_peer::<_, Failure>(&other_session, &Limits::default(), &received, true),
+            None
+        );
+        let first = InputMessage::from_output(
+            PartyIndex::from(1),
+            OutputMessage::new(session, Address::Broadcast, Body::First),
+        );
+        assert_eq!(
+            aborted_by_peer::<_, Failure>(&session, &Limits::default(), &first, true),
+            None
+        );
+    }
+
+    #[test]
+    fn earlier_output_resent() {
+        let session = Session {
+            protocol: ProtocolId::Keygen,
+            id: SessionId::random(),
+        };
+        let peer = PartyIndex::from(2);
+        let sent = vec![
+            OutputMessage::new(session, Address::Broadcast, Body::First),
+            OutputMessage::new(session, Address::Peer(PartyIndex::from(3)), Body::Second),
+        ];
+        let requests: Vec<OutputMessage<Body>> = resend_requests(session, 1, vec![peer]).unwrap();
+        assert_eq!(requests[0].recipient, Address::Peer(peer));
+        let request = |round| {
+            InputMessage::from_output(
+                peer,
+                OutputMessage::new(session, Address::Peer(peer), Body::Resend(Resend { round })),
+            )
+        };
+
+        let resent = resent_output(&session, &Limits::default(), &request(1), true, &sent).unwrap();
+        assert_eq!(resent.round, 1);
+        assert_eq!(resent.messages.len(), 1);
+        // the copy of the broadcast has the same header, and is addressed to the peer alone
+        assert_eq!(resent.messages[0].recipient, Address::Peer(peer));
+        assert_eq!(resent.messages[0].header, sent[0].header);
+        // the message of the round 2 is addressed to another peer
+        assert!(
+            resent_output(&session, &Limits::default(), &request(2), true, &sent)
+                .unwrap()
+                .messages
+                .is_empty()
+        );
+        assert!(resent_output(&session, &Limits::default(), &request(1), false, &sent).is_none());
+    }
+
+    #[test]
+    fn commitment_bound_to_session() {
+        let session = Session {
+            protocol: ProtocolId::Signing,
+            id: SessionId::random(),
+        };
+        let point = Point::generator() * crate::types::Scalar::random();
+        let scheme = CommitmentScheme::from_GE(&session, &point);
+        assert!(scheme.verify_commitment(&session, &point));
+        let other = Session {
+            id: SessionId::random(),
+            ..session
+        };
+        assert!(!scheme.verify_commitment(&other, &point));
+    }
 
     #[test]
     fn serde() -> anyhow::Result<()> {
@@ -0,0 +1,1443 @@
+//! Recovery of the share of a party which has lost its key material
+//!
+//! A holder of the key which has lost its [`MultiPartyInfo`] rebuilds its Shamir's share at its existing point, without resharing the key among all parties.
+//!
+//! * The lost party generates new Paillier keys and an optional new range proof setup, and sends them to all other holders of the key along with proofs of both moduli.
+//!   Each holder answers with its point, its public Paillier key and its range proof setup.
+//! * Any `t+1` holders, called helpers, weigh their shares with Lagrange coefficients at the point of the lost party. Each helper splits its weighted share into random pieces,
+//!   one for every helper, and sends the sum of pieces it has received to the lost party. The recovered share is the sum of these sums, hence no helper learns it.
+//! * Helpers also send public images of their shares, so that the lost party checks that they interpolate to the public key, and that the recovered share lies on the same polynomial.
+//! * The lost party proves to each holder that its new moduli have no small factors, and holders replace its entries of `party_he_keys` and of range proof setups.
+//!
+//! The lost party starts with [`lost_party::Phase1`], other holders of the key start with [`holder::Phase1`].
+//!
+//! [`MultiPartyInfo`]: ../keygen/struct.MultiPartyInfo.html
+//! [`lost_party::Phase1`]: lost_party/struct.Phase1.html
+//! [`holder::Phase1`]: holder/struct.Phase1.html
+
+use crate::encryption::{RefuseReason, RefusedMessage, ReportRefused};
+use crate::identity::{UnverifiedMessage, UnverifiedReason};
+use crate::keygen::CorrectKeyProof;
+use crate::messages::{Abort, ModulusProofs};
+use crate::protocol::{
+    abort_notices, aborted_by_peer, resend_requests, resent_output, AbortReport, PartyIndex,
+};
+use crate::types::{BigInt, Parameters};
+use crate::utils::lagrange_coefficient;
+use crate::zk_range_proofs::ZkpPublicSetup;
+use algorithms::nizk_rsa;
+use paillier::EncryptionKey;
+use state_machine::types::{RejectReason, RejectedMessage};
+use std::collections::{BTreeSet, HashMap};
+use std::iter::FromIterator;
+use thiserror::Error;
+use trace::trace;
+
+#[doc(inline)]
+pub use super::messages::recovery::{
+    InMsg, Message, OutMsg, Phase1Broadcast, Phase2Record, Phase3Piece, Phase4Proofs,
+    Phase5Contribution,
+};
+
+/// Enumerates errors which can be reported by share recovery protocol
+#[derive(Debug, Error)]
+#[allow(clippy::large_enum_variant)]
+pub enum RecoveryError {
+    #[error("protocol setup error: {0}")]
+    ProtocolSetupError(String),
+    #[error("recovery: timeout in {phase}")]
+    Timeout { phase: String },
+    #[error("unexpected message {message_type:?}, party {party}")]
+    UnknownMessageType {
+        message_type: Message,
+        party: PartyIndex,
+    },
+    #[error("invalid correct key proof {proof}, party {party}")]
+    InvalidCorrectKeyProof { proof: String, party: PartyIndex },
+    #[error("missing range proof from {party}")]
+    RangeProofSetupMissing { party: PartyIndex },
+    #[error("unexpected range proof from {party}, proof {proof:?} ")]
+    RangeProofSetupUnexpected { proof: String, party: PartyIndex },
+    #[error("range proof setup: dlog proof failed , party {party}, proof {proof} ")]
+    RangeProofSetupDlogProofFailed { proof: String, party: PartyIndex },
+    #[error("invalid modulus proof: {reason}, party {party}")]
+    InvalidModulusProof { reason: String, party: PartyIndex },
+    #[error("invalid no small factor proof: {reason}, party {party}")]
+    InvalidFactorProof { reason: String, party: PartyIndex },
+    #[error("point {point} is already taken, party {party}")]
+    InvalidPoint { point: u16, party: PartyIndex },
+    #[error("contributions of helpers do not belong to the same public key")]
+    InconsistentContributions,
+    #[error("recovered share does not belong to the public key")]
+    InvalidRecoveredShare,
+    #[error("message rejected: {reason:?}, party {party}")]
+    RejectedMessage {
+        party: PartyIndex,
+        reason: RejectReason,
+    },
+    #[error("message refused by secure channel: {reason:?}, party {party}")]
+    RefusedMessage {
+        party: PartyIndex,
+        reason: RefuseReason,
+    },
+    #[error("message failed identity verification: {reason:?}, party {party}")]
+    UnverifiedMessage {
+        party: PartyIndex,
+        reason: UnverifiedReason,
+    },
+    #[error("party {party} aborted the protocol: {reason}")]
+    PeerAborted {
+        party: PartyIndex,
+        reason: String,
+        blamed: Vec<PartyIndex>,
+    },
+    #[error("{0}")]
+    GeneralError(String),
+}
+
+impl RecoveryError {
+    /// The party which the error is attributed to, if any
+    pub fn blamed_party(&self) -> Option<PartyIndex> {
+        match self {
+            RecoveryError::UnknownMessageType { party, .. }
+            | RecoveryError::InvalidCorrectKeyProof { party, .. }
+            | RecoveryError::RangeProofSetupMissing { party }
+            | RecoveryError::RangeProofSetupUnexpected { party, .. }
+            | RecoveryError::RangeProofSetupDlogProofFailed { party, .. }
+            | RecoveryError::InvalidModulusProof { party, .. }
+            | RecoveryError::InvalidFactorProof { party, .. }
+            | RecoveryError::InvalidPoint { party, .. }
+            | RecoveryError::RejectedMessage { party, .. }
+            | RecoveryError::RefusedMessage { party, .. }
+            | RecoveryError::UnverifiedMessage { party, .. } => Some(*party),
+            _ => None,
+        }
+    }
+}
+
+/// Contains a vector of possible recovery errors
+#[derive(Debug)]
+pub struct ErrorState {
+    pub errors: Vec<RecoveryError>,
+}
+
+/// the state the machine returns in case of error(s)
+impl ErrorState {
+    pub fn new(errors: Vec<RecoveryError>) -> Self {
+        ErrorState { errors }
+    }
+
+    /// Adds senders of messages rejected by the state machine, once per reason, as they behave suspiciously
+    pub fn report_rejected(&mut self, rejected: &HashMap<RejectedMessage<PartyIndex>, usize>) {
+        self.errors
+            .extend(rejected.keys().map(|r| RecoveryError::RejectedMessage {
+                party: r.sender,
+                reason: r.reason,
+            }));
+    }
+
+    /// Adds senders of messages which failed the verification of their identity, once per reason, as they behave suspiciously
+    pub fn report_unverified(&mut self, unverified: &HashMap<UnverifiedMessage, usize>) {
+        self.errors
+            .extend(unverified.keys().map(|r| RecoveryError::UnverifiedMessage {
+                party: r.sender,
+                reason: r.reason,
+            }));
+    }
+}
+
+impl ReportRefused for ErrorState {
+    fn report_refused(&mut self, refused: &HashMap<RefusedMessage, usize>) {
+        self.errors
+            .extend(refused.keys().map(|r| RecoveryError::RefusedMessage {
+                party: r.sender,
+                reason: r.reason,
+            }));
+    }
+}
+
+impl AbortReport for ErrorState {
+    fn abort_notice(&self) -> Abort {
+        let blamed = self
+            .errors
+            .iter()
+            .filter_map(RecoveryError::blamed_party)
+            .collect::<BTreeSet<_>>();
+        Abort::new(&self.errors, blamed.into_iter().collect())
+    }
+
+    fn aborted_by(party: PartyIndex, notice: &Abort) -> Self {
+        ErrorState::new(vec![RecoveryError::PeerAborted {
+            party,
+            reason: notice.reason.clone(),
+            blamed: notice.blamed.clone(),
+        }])
+    }
+}
+
+/// Checks lists of holders and helpers against parameters of the key, and returns them as sets
+#[allow(clippy::result_large_err)]
+fn check_parties(
+    key_params: &Parameters,
+    lost_party: PartyIndex,
+    holders: &[PartyIndex],
+    helpers: &[PartyIndex],
+) -> Result<(BTreeSet<PartyIndex>, BTreeSet<PartyIndex>), RecoveryError> {
+    let holders_as_set = BTreeSet::from_iter(holders.iter().cloned());
+    if holders_as_set.len() != holders.len() {
+        return Err(RecoveryError::ProtocolSetupError(
+            "duplicate entries in holders' list".to_string(),
+        ));
+    }
+    if holders_as_set.contains(&lost_party) {
+        return Err(RecoveryError::ProtocolSetupError(
+            "lost party is in holders' list".to_string(),
+        ));
+    }
+    if holders.len() + 1 != key_params.share_count() as usize {
+        return Err(RecoveryError::ProtocolSetupError(format!(
+            "{} holders of the key besides the lost party, expected {}",
+            holders.len(),
+            key_params.share_count() - 1
+        )));
+    }
+
+    let helpers_as_set = BTreeSet::from_iter(helpers.iter().cloned());
+    if helpers_as_set.len() != helpers.len() {
+        return Err(RecoveryError::ProtocolSetupError(
+            "duplicate entries in helpers' list".to_string(),
+        ));
+    }
+    if !helpers_as_set.is_subset(&holders_as_set) {
+        return Err(RecoveryError::ProtocolSetupError(
+            "helpers are not holders of the key".to_string(),
+        ));
+    }
+    if helpers.len() as u16 <= key_params.threshold() {
+        return Err(RecoveryError::ProtocolSetupError(
+            "not enough helpers".to_string(),
+        ));
+    }
+    Ok((holders_as_set, helpers_as_set))
+}
+
+/// Verifies the Paillier key and the range proof setup of the party, along with proofs of their new moduli if any
+///
+/// `setup_expected` tells whether the verifier uses range proofs, so that the party has to share its setup as well.
+/// Keys of holders were proven in key generation, hence only new keys of the lost party come with modulus proofs.
+fn verify_keys(
+    party: PartyIndex,
+    ek: &EncryptionKey,
+    correct_key_proof: &CorrectKeyProof,
+    range_proof_setup: Option<&ZkpPublicSetup>,
+    modulus_proofs: Option<&ModulusProofs>,
+    setup_expected: bool,
+    context: &BigInt,
+) -> Vec<RecoveryError> {
+    let mut errors = Vec::new();
+    if nizk_rsa::verify_with_context(ek, &correct_key_proof.0, context).is_err() {
+        errors.push(RecoveryError::InvalidCorrectKeyProof {
+            proof: format!("{:?}", correct_key_proof),
+            party,
+        });
+    }
+    match (setup_expected, range_proof_setup) {
+        (true, None) => errors.push(RecoveryError::RangeProofSetupMissing { party }),
+        (false, Some(setup)) => errors.push(RecoveryError::RangeProofSetupUnexpected {
+            proof: format!("{:?}", setup.dlog_proof),
+            party,
+        }),
+        (true, Some(setup)) => {
+            if let Err(e) = setup.verify() {
+                errors.push(RecoveryError::RangeProofSetupDlogProofFailed {
+                    proof: format!("{:?} {:?}", e, setup.dlog_proof),
+                    party,
+                });
+            }
+        }
+        (false, None) => {}
+    }
+    if let Some(Err(e)) = modulus_proofs.map(|proofs| proofs.verify(ek, range_proof_setup, context))
+    {
+        errors.push(RecoveryError::InvalidModulusProof {
+            reason: e.to_string(),
+            party,
+        });
+    }
+    errors
+}
+
+/// Checks whether all expected messages have been received so far from other parties
+fn is_broadcast_input_complete(
+    current_msg_set: &[InMsg],
+    other_parties: &BTreeSet<PartyIndex>,
+) -> bool {
+    let senders = current_msg_set.iter().map(|m| m.sender).collect::<Vec<_>>();
+    other_parties.iter().all(|p| senders.contains(p))
+}
+
+/// Parties of the set which have not delivered their messages yet
+fn missing_senders(
+    current_msg_set: &[InMsg],
+    other_parties: &BTreeSet<PartyIndex>,
+) -> Vec<PartyIndex> {
+    other_parties
+        .iter()
+        .filter(|p| current_msg_set.iter().all(|m| m.sender != **p))
+        .copied()
+        .collect()
+}
+
+/// Extracts payloads form enum variants of input message into the hash map
+#[trace(disable(current_msg_set), res = "{:?}")]
+fn to_hash_map_gen<K, V>(current_msg_set: Vec<InMsg>) -> Result<HashMap<K, V>, Vec<RecoveryError>>
+where
+    K: std::cmp::Eq + std::hash::Hash + std::convert::From<PartyIndex> + std::fmt::Debug,
+    V: std::fmt::Debug,
+    Option<V>: std::convert::From<Message>,
+{
+    let (converted_messages, errors) =
+        current_msg_set
+            .iter()
+            .fold((vec![], vec![]), |(mut values, mut errors), m| {
+                let body: Option<V> = m.body.clone().into();
+                match body {
+                    Some(b) => values.push((m.sender, b)),
+                    None => errors.push(RecoveryError::UnknownMessageType {
+                        message_type: m.body.clone(),
+                        party: m.sender,
+                    }),
+                };
+                (values, errors)
+            });
+
+    if errors.is_empty() {
+        Ok(converted_messages
+            .into_iter()
+            .map(|(party, body)| (party.into(), body))
+            .collect::<HashMap<K, V>>())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Contains the protocol part performed by the party which has lost its key material
+pub mod lost_party {
+    use super::{
+        is_broadcast_input_complete, lagrange_coefficient, missing_senders, to_hash_map_gen,
+        ErrorState, InMsg, Message, OutMsg, Phase1Broadcast, Phase2Record, Phase4Proofs,
+        Phase5Contribution, RecoveryError,
+    };
+    use crate::keygen::{CorrectKeyProof, MultiPartyInfo, Party2PointMap, RangeProofSetups};
+    use crate::limits::Limits;
+    use crate::messages::{FactorProofs, ModulusProofs};
+    use crate::protocol::{Address, PartyIndex, ProtocolId, SenderRole, Session, SessionId};
+    use crate::types::{Parameters, FE, GE};
+    use crate::utils::{all_mapped_equal, phase_snapshot};
+    use crate::zk_range_proofs::{ZkpPublicSetup, ZkpSetup};
+    use algorithms::nizk_rsa;
+    use algorithms::types::PaillierKeys;
+    use serde::{Deserialize, Serialize};
+    use state_machine::checkpoint::RestoreState;
+    use state_machine::types::{
+        BoxedState, MessageClass, Resent, State, StateMachineTraits, Transition,
+    };
+    use std::collections::{BTreeSet, HashMap};
+    use std::time::Duration;
+    use trace::trace;
+
+    /// Result of recovery protocol
+    ///
+    /// The rebuilt `MultiPartyInfo` of the party, with the share at its existing point and with its new Paillier keys.
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct FinalState {
+        pub info: MultiPartyInfo,
+    }
+
+    #[doc(hidden)]
+    type OutMsgVec = Vec<OutMsg>;
+    pub type MachineResult = Result<FinalState, ErrorState>;
+
+    /// Type definitions
+    #[derive(Debug)]
+    pub struct KeyRecoveryTraits;
+
+    impl StateMachineTraits for KeyRecoveryTraits {
+        type InMsg = InMsg;
+        type OutMsg = OutMsg;
+        type FinalState = FinalState;
+        type ErrorState = ErrorState;
+    }
+
+    /// Phases of the protocol restored from snapshots
+    #[derive(Deserialize)]
+    enum PhaseSnapshot {
+        Phase1(Phase1),
+        Phase2(Phase2),
+    }
+
+    impl RestoreState for KeyRecoveryTraits {
+        type Context = ();
+
+        fn restore_state(
+            snapshot: serde_json::Value,
+            _context: &(),
+        ) -> Result<BoxedState<Self>, serde_json::Error> {
+            Ok(match serde_json::from_value(snapshot)? {
+                PhaseSnapshot::Phase1(phase) => Box::new(phase),
+                PhaseSnapshot::Phase2(phase) => Box::new(phase),
+            })
+        }
+    }
+
+    /// Initial phase of the protocol
+    ///
+    /// * generates new Paillier keys, ZKP of their correctness and Paillier-Blum modulus proofs of new moduli
+    /// * sends them to all holders of the key, along with the optional new range proof setup
+    /// * collects and verifies public records of holders
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct Phase1 {
+        session: Session,
+        limits: Limits,
+        key_params: Parameters,
+        own_party_index: PartyIndex,
+        holders: BTreeSet<PartyIndex>,
+        helpers: BTreeSet<PartyIndex>,
+        paillier_keys: PaillierKeys,
+        paillier_key_proof: CorrectKeyProof,
+        modulus_proofs: ModulusProofs,
+        range_proof_setup: Option<ZkpSetup>,
+        timeout: Option<Duration>,
+    }
+
+    impl Phase1 {
+        /// initializes the protocol
+        ///
+        /// `holders` are all other parties which hold shares of the key, and `helpers` are any `t+1` of them.
+        /// `range_proof_setup` is required if holders of the key use range proofs.
+        #[allow(clippy::result_large_err, clippy::too_many_arguments)]
+        pub fn new(
+            session_id: SessionId,
+            own_party_index: PartyIndex,
+            key_params: &Parameters,
+            holders: &[PartyIndex],
+            helpers: &[PartyIndex],
+            range_proof_setup: Option<ZkpSetup>,
+            timeout: Option<Duration>,
+            limits: Limits,
+        ) -> Result<Self, RecoveryError> {
+            let (holders, helpers) =
+                super::check_parties(key_params, own_party_index, holders, helpers)?;
+            if let Some(setup) = &range_proof_setup {
+                ZkpPublicSetup::from_private_zkp_setup(setup)
+                    .verify()
+                    .map_err(|e| RecoveryError::ProtocolSetupError(format!("{:?}", e)))?;
+            }
+
+            let session = Session {
+                protocol: ProtocolId::Recovery,
+                id: session_id,
+            };
+            let paillier_keys = PaillierKeys::random();
+            let context = session.to_bigint();
+            let modulus_proofs =
+                ModulusProofs::new(&paillier_keys.dk, range_proof_setup.as_ref(), &context)
+                    .map_err(|e| {
+                        RecoveryError::ProtocolSetupError(format!("invalid own moduli: {}", e))
+                    })?;
+            let paillier_key_proof = CorrectKeyProof(nizk_rsa::gen_proof_with_context(
+                &paillier_keys.dk,
+                &context,
+            ));
+
+            Ok(Phase1 {
+                session,
+                key_params: *key_params,
+                own_party_index,
+                holders,
+                helpers,
+                paillier_keys,
+                paillier_key_proof,
+                modulus_proofs,
+                range_proof_setup,
+                timeout,
+                limits,
+            })
+        }
+    }
+
+    #[trace(pretty, prefix = "Phase1::")]
+    impl State<KeyRecoveryTraits> for Phase1 {
+        fn start(&mut self) -> Option<OutMsgVec> {
+            log::info!("Phase1 (lost party) starts");
+            let range_proof_setup = self
+                .range_proof_setup
+                .as_ref()
+                .map(ZkpPublicSetup::from_private_zkp_setup);
+            Some(
+                self.holders
+                    .iter()
+                    .map(|p| {
+                        OutMsg::new(
+                            self.session,
+                            Address::Peer(*p),
+                            Message::R1(Phase1Broadcast {
+                                ek: self.paillier_keys.ek.clone(),
+                                correct_key_proof: self.paillier_key_proof.clone(),
+                                range_proof_setup: range_proof_setup.clone(),
+                                modulus_proofs: self.modulus_proofs.clone(),
+                            }),
+                        )
+                    })
+                    .collect(),
+            )
+        }
+
+        #[trace(disable(current_msg_set))]
+        fn classify_message(&self, msg: &InMsg, current_msg_set: &[InMsg]) -> MessageClass {
+            msg.classify(
+                &self.session,
+                &self.limits,
+                2,
+                matches!(msg.body, Message::R2(_)),
+                SenderRole::of_member(self.holders.contains(&msg.sender)),
+                current_msg_set,
+            )
+        }
+
+        #[trace(disable(current_msg_set))]
+        fn is_input_complete(&self, current_msg_set: &[InMsg]) -> bool {
+            is_broadcast_input_complete(current_msg_set, &self.holders)
+        }
+
+        #[trace(disable(current_msg_set))]
+        fn awaited_senders(&self, current_msg_set: &[InMsg]) -> Vec<PartyIndex> {
+            missing_senders(current_msg_set, &self.holders)
+        }
+
+        fn consume(&self, current_msg_set: Vec<InMsg>) -> Transition<KeyRecoveryTraits> {
+            let records = match to_hash_map_gen::<PartyIndex, Phase2Record>(current_msg_set) {
+                Ok(map) => map,
+                Err(e) => {
+                    let error_state = ErrorState::new(e);
+                    log::error!("Phase1 returns {:?}", error_state);
+                    return Transition::FinalState(Err(error_state));
+                }
+            };
+
+            let context = self.session.to_bigint();
+            let mut errors = records
+                .iter()
+                .flat_map(|(party, msg)| {
+                    super::verify_keys(
+                        *party,
+                        &msg.ek,
+                        &msg.correct_key_proof,
+                        msg.range_proof_setup.as_ref(),
+                        None,
+                        self.range_proof_setup.is_some(),
+                        &context,
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            // each holder has its own point
+            let mut taken = BTreeSet::new();
+            let points = records
+                .iter()
+                .map(|(party, msg)| (*party, msg.point))
+                .collect::<BTreeSet<_>>();
+            for (party, point) in points {
+                if !taken.insert(point) {
+                    errors.push(RecoveryError::InvalidPoint { point, party });
+                }
+            }
+
+            if !errors.is_empty() {
+                let error_state = ErrorState::new(errors);
+                log::error!("Phase1 returns {:?}", error_state);
+                return Transition::FinalState(Err(error_state));
+            }
+            Transition::NewState(Box::new(Phase2 {
+                previous_phase: self.clone(),
+                records,
+            }))
+        }
+
+        fn timeout(&self) -> Option<Duration> {
+            self.timeout
+        }
+
+        fn timeout_outcome(&self, _current_msg_set: Vec<InMsg>) -> MachineResult {
+            Err(ErrorState::new(vec![RecoveryError::Timeout {
+                phase: "Lost.phase1".to_owned(),
+            }]))
+        }
+
+        #[trace(disable(res))]
+        fn snapshot(&self) -> Option<serde_json::Value> {
+            phase_snapshot("Phase1", self)
+        }
+
+        fn name(&self) -> &'static str {
+            "recovery.lost.phase1"
+        }
+
+        fn abort_output(&self, error: &ErrorState) -> Option<Vec<OutMsg>> {
+            super::abort_notices(self.session, error, self.holders.iter())
+        }
+
+        fn peer_aborted(&self, msg: &InMsg) -> Option<ErrorState> {
+            super::aborted_by_peer(
+                &self.session,
+                &self.limits,
+                msg,
+                self.holders.contains(&msg.sender),
+            )
+        }
+
+        #[trace(disable(current_msg_set))]
+        fn resend_request(&self, current_msg_set: &[InMsg]) -> Option<Vec<OutMsg>> {
+            super::resend_requests(self.session, 2, self.awaited_senders(current_msg_set))
+        }
+
+        fn resend_output(&self, msg: &InMsg, sent: &[OutMsg]) -> Option<Resent<OutMsg>> {
+            super::resent_output(
+                &self.session,
+                &self.limits,
+                msg,
+                self.holders.contains(&msg.sender),
+                sent,
+            )
+        }
+    }
+
+    /// Last phase of the protocol
+    ///
+    /// * sends proofs that new moduli have no small factors to each holder, made with its range proof setup
+    /// * collects blinded contributions of helpers and sums them up into the share
+    /// * verifies that public shares of helpers interpolate to the public key, and that the share lies on the same polynomial
+    #[derive(Serialize, Deserialize)]
+    struct Phase2 {
+        previous_phase: Phase1,
+        records: HashMap<PartyIndex, Phase2Record>,
+    }
+
+    impl Phase2 {
+        /// Verifies contributions and returns the point and the share of the party, along with the public key
+        #[allow(clippy::result_large_err)]
+        fn recover(
+            &self,
+            contributions: &HashMap<PartyIndex, Phase5Contribution>,
+        ) -> Result<(u16, FE, GE), RecoveryError> {
+            if !all_mapped_equal(contributions.iter(), |(_, msg)| {
+                (msg.public_key.clone(), msg.point)
+            }) {
+                return Err(RecoveryError::InconsistentContributions);
+            }
+            let (public_key, point) = contributions
+                .values()
+                .next()
+                .map(|msg| (msg.public_key.clone(), msg.point))
+                .ok_or_else(|| RecoveryError::GeneralError("no contributions".to_string()))?;
+            if self.records.values().any(|record| record.point == point) {
+                return Err(RecoveryError::InconsistentContributions);
+            }
+
+            let helper_points = contributions
+                .keys()
+                .map(|p| self.records[p].point)
+                .collect::<Vec<_>>();
+            let interpolate = |at: u16| {
+                contributions.iter().fold(GE::zero(), |acc, (p, msg)| {
+                    let x = self.records[p].point;
+                    acc + &msg.public_share * lagrange_coefficient(&helper_points, x, at)
+                })
+            };
+            if interpolate(0) != public_key {
+                return Err(RecoveryError::InconsistentContributions);
+            }
+
+            let share = contributions
+                .values()
+                .fold(FE::zero(), |acc, msg| acc + &msg.blinded_sum);
+            if GE::generator() * &share != interpolate(point) {
+                return Err(RecoveryError::InvalidRecoveredShare);
+            }
+            Ok((point, share, public_key))
+        }
+    }
+
+    #[trace(pretty, prefix = "Phase2::")]
+    impl State<KeyRecoveryTraits> for Phase2 {
+        fn start(&mut self) -> Option<OutMsgVec> {
+            log::debug!("Phase2 (lost party) starts");
+            let context = self.previous_phase.session.to_bigint();
+            Some(
+                self.records
+                    .iter()
+                    .map(|(p, record)| {
+                        let factor_proofs = match (
+                            &self.previous_phase.range_proof_setup,
+                            &record.range_proof_setup,
+                        ) {
+                            (Some(my_setup), Some(setup)) => Some(FactorProofs::new(
+                                &self.previous_phase.paillier_keys.dk,
+                                my_setup,
+                                setup,
+                                &context,
+                            )),
+                            _ => None,
+                        };
+                        OutMsg::new(
+                            self.previous_phase.session,
+                            Address::Peer(*p),
+                            Message::R4(Phase4Proofs { factor_proofs }),
+                        )
+                    })
+                    .collect(),
+            )
+        }
+
+        #[trace(disable(current_msg_set))]
+        fn classify_message(&self, msg: &InMsg, current_msg_set: &[InMsg]) -> MessageClass {
+            msg.classify(
+                &self.previous_phase.session,
+                &self.previous_phase.limits,
+                5,
+                matches!(msg.body, Message::R5(_)),
+                SenderRole::new(
+                    self.previous_phase.helpers.contains(&msg.sender),
+                    self.previous_phase.holders.contains(&msg.sender),
+                ),
+                current_msg_set,
+            )
+        }
+
+        #[trace(disable(current_msg_set))]
+        fn is_input_complete(&self, current_msg_set: &[InMsg]) -> bool {
+            is_broadcast_input_complete(current_msg_set, &self.previous_phase.helpers)
+        }
+
+        #[trace(disable(current_msg_set))]
+        fn awaited_senders(&self, current_msg_set: &[InMsg]) -> Vec<PartyIndex> {
+            missing_senders(current_msg_set, &self.previous_phase.helpers)
+        }
+
+        fn consume(&self, current_msg_set: Vec<InMsg>) -> Transition<KeyRecoveryTraits> {
+            let contributions =
+                match to_hash_map_gen::<PartyIndex, Phase5Contribution>(current_msg_set) {
+                    Ok(map) => map,
+                    Err(e) => {
+                        let error_state = ErrorState::new(e);
+                        log::error!("Phase2 returns {:?}", error_state);
+                        return Transition::FinalState(Err(error_state));
+                    }
+                };
+
+            let (point, share, public_key) = match self.recover(&contributions) {
+                Ok(recovered) => recovered,
+                Err(e) => {
+                    let error_state = ErrorState::new(vec![e]);
+                    log::error!("Phase2 returns {:?}", error_state);
+                    return Transition::FinalState(Err(error_state));
+                }
+            };
+
+            let own_party_index = self.previous_phase.own_party_index;
+            let mut points = self
+                .records
+                .iter()
+                .map(|(p, record)| (*p, record.point))
+                .collect::<HashMap<_, _>>();
+            points.insert(own_party_index, point);
+            let range_proof_setups =
+                self.previous_phase
+                    .range_proof_setup
+                    .as_ref()
+                    .map(|my_setup| RangeProofSetups {
+                        my_setup: my_setup.clone(),
+                        party_setups: self
+                            .records
+                            .iter()
+                            .filter_map(|(p, record)| Some((*p, record.range_proof_setup.clone()?)))
+                            .collect(),
+                    });
+
+            log::info!("Phase2 succeeded");
+            Transition::FinalState(Ok(FinalState {
+                info: MultiPartyInfo {
+                    key_params: self.previous_phase.key_params,
+                    own_party_index,
+                    secret_share: (point, share),
+                    public_key,
+                    own_he_keys: self.previous_phase.paillier_keys.clone(),
+                    party_he_keys: self
+                        .records
+                        .iter()
+                        .map(|(p, record)| (*p, record.ek.clone()))
+                        .collect(),
+                    party_to_point_map: Party2PointMap { points },
+                    range_proof_setups,
+                },
+            }))
+        }
+
+        fn timeout(&self) -> Option<Duration> {
+            self.previous_phase.timeout
+        }
+
+        fn timeout_outcome(&self, _current_msg_set: Vec<InMsg>) -> MachineResult {
+            Err(ErrorState::new(vec![RecoveryError::Timeout {
+                phase: "Lost.phase2".to_owned(),
+            }]))
+        }
+
+        #[trace(disable(res))]
+        fn snapshot(&self) -> Option<serde_json::Value> {
+            phase_snapshot("Phase2", self)
+        }
+
+        fn name(&self) -> &'static str {
+            "recovery.lost.phase2"
+        }
+
+        fn abort_output(&self, error: &ErrorState) -> Option<Vec<OutMsg>> {
+            self.previous_phase.abort_output(error)
+        }
+
+        fn peer_aborted(&self, msg: &InMsg) -> Option<ErrorState> {
+            self.previous_phase.peer_aborted(msg)
+        }
+
+        #[trace(disable(current_msg_set))]
+        fn resend_request(&self, current_msg_set: &[InMsg]) -> Option<Vec<OutMsg>> {
+            super::resend_requests(
+                self.previous_phase.session,
+                5,
+                self.awaited_senders(current_msg_set),
+            )
+        }
+
+        fn resend_output(&self, msg: &InMsg, sent: &[OutMsg]) -> Option<Resent<OutMsg>> {
+            self.previous_phase.resend_output(msg, sent)
+        }
+    }
+}
+
+/// Contains the protocol part performed by other holders of the key, whether they are helpers or not
+pub mod holder {
+    use super::{
+        is_broadcast_input_complete, lagrange_coefficient, missing_senders, to_hash_map_gen,
+        ErrorState, InMsg, Message, OutMsg, Phase1Broadcast, Phase2Record, Phase3Piece,
+        Phase4Proofs, Phase5Contribution, RecoveryError,
+    };
+    use crate::keygen::{CorrectKeyProof, MultiPartyInfo};
+    use crate::limits::Limits;
+    use crate::protocol::{Address, PartyIndex, ProtocolId, SenderRole, Session, SessionId};
+    use crate::types::{FE, GE};
+    use crate::utils::phase_snapshot;
+    use crate::zk_range_proofs::ZkpPublicSetup;
+    use algorithms::nizk_rsa;
+    use serde::{Deserialize, Serialize};
+    use state_machine::checkpoint::RestoreState;
+    use state_machine::types::{
+        BoxedState, MessageClass, Resent, State, StateMachineTraits, Transition,
+    };
+    use std::collections::{BTreeSet, HashMap};
+    use std::time::Duration;
+    use trace::trace;
+
+    /// Result of recovery protocol
+    ///
+    /// The `MultiPartyInfo` of the holder where the entries of the lost party are replaced by its new Paillier key and range proof setup.
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct FinalState {
+        pub info: MultiPartyInfo,
+    }
+
+    #[doc(hidden)]
+    type OutMsgVec = Vec<OutMsg>;
+    pub type MachineResult = Result<FinalState, ErrorState>;
+
+    /// Type definitions
+    #[derive(Debug)]
+    pub struct KeyRecoveryTraits;
+
+    impl StateMachineTraits for KeyRecoveryTraits {
+        type InMsg = InMsg;
+        type OutMsg = OutMsg;
+        type FinalState = FinalState;
+        type ErrorState = ErrorState;
+    }
+
+    /// Phases of the protocol restored from snapshots
+    #[derive(Deserialize)]
+    enum PhaseSnapshot {
+        Phase1(Box<Phase1>),
+        Phase2(Box<Phase2>),
+    }
+
+    impl RestoreState for KeyRecoveryTraits {
+        type Context = ();
+
+        fn restore_state(
+            snapshot: serde_json::Value,
+            _context: &(),
+        ) -> Result<BoxedState<Self>, serde_json::Error> {
+            Ok(match serde_json::from_value(snapshot)? {
+                PhaseSnapshot::Phase1(phase) => phase,
+                PhaseSnapshot::Phase2(phase) => phase,
+            })
+        }
+    }
+
+    /// Initial phase of the protocol
+    ///
+    /// * sends the public record of the holder to the lost party
+    /// * if the holder is a helper, splits its share weighted at the point of the lost party into random pieces and sends them to other helpers
+    /// * collects and verifies new keys of the lost party, and collects pieces of other helpers
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct Phase1 {
+        session: Session,
+        limits: Limits,
+        multi_party_info: MultiPartyInfo,
+        lost_party: PartyIndex,
+        is_helper: bool,
+        other_helpers: BTreeSet<PartyIndex>,
+        record: Phase2Record,
+        own_piece: FE,
+        outgoing_pieces: HashMap<PartyIndex, FE>,
+        timeout: Option<Duration>,
+    }
+
+    impl Phase1 {
+        /// initializes the protocol
+        ///
+        /// `helpers` are any `t+1` holders of the key, which may include the party itself.
+        #[allow(clippy::result_large_err)]
+        pub fn new(
+            session_id: SessionId,
+            multi_party_info: &MultiPartyInfo,
+            lost_party: PartyIndex,
+            helpers: &[PartyIndex],
+            timeout: Option<Duration>,
+            limits: Limits,
+        ) -> Result<Self, RecoveryError> {
+            let own_party_index = multi_party_info.own_party_index;
+            let points = &multi_party_info.party_to_point_map.points;
+            let lost_point = *points.get(&lost_party).ok_or_else(|| {
+                RecoveryError::ProtocolSetupError("lost party is not mapped to point".to_string())
+            })?;
+            if lost_party == own_party_index {
+                return Err(RecoveryError::ProtocolSetupError(
+                    "own party is the lost party".to_string(),
+                ));
+            }
+            let holders = points
+                .keys()
+                .filter(|p| **p != lost_party)
+                .copied()
+                .collect::<Vec<_>>();
+            let (_, helpers) =
+                super::check_parties(&multi_party_info.key_params, lost_party, &holders, helpers)?;
+
+            let session = Session {
+                protocol: ProtocolId::Recovery,
+                id: session_id,
+            };
+            let context = session.to_bigint();
+            let own_he_keys = &multi_party_info.own_he_keys;
+            let my_setup = multi_party_info
+                .range_proof_setups
+                .as_ref()
+                .map(|setups| &setups.my_setup);
+            let record = Phase2Record {
+                point: multi_party_info.own_point(),
+                ek: own_he_keys.ek.clone(),
+                correct_key_proof: CorrectKeyProof(nizk_rsa::gen_proof_with_context(
+                    &own_he_keys.dk,
+                    &context,
+                )),
+                range_proof_setup: my_setup.map(ZkpPublicSetup::from_private_zkp_setup),
+            };
+
+            let is_helper = helpers.contains(&own_party_index);
+            let mut other_helpers = helpers;
+            other_helpers.remove(&own_party_index);
+            // the weighted share is the summand of the share of the lost party, it is split so that the lost party receives only sums of pieces
+            let (own_piece, outgoing_pieces) = if is_helper {
+                let helper_points = other_helpers
+                    .iter()
+                    .map(|p| points[p])
+                    .chain(std::iter::once(multi_party_info.own_point()))
+                    .collect::<Vec<_>>();
+                let weighted_share = multi_party_info.own_share().clone()
+                    * lagrange_coefficient(
+                        &helper_points,
+                        multi_party_info.own_point(),
+                        lost_point,
+                    );
+                let outgoing_pieces = other_helpers
+                    .iter()
+                    .map(|p| (*p, FE::random()))
+                    .collect::<HashMap<_, _>>();
+                let own_piece = outgoing_pieces
+                    .values()
+                    .fold(weighted_share, |acc, piece| acc - piece);
+                (own_piece, outgoing_pieces)
+            } else {
+                (FE::zero(), HashMap::new())
+            };
+
+            Ok(Phase1 {
+                session,
+                multi_party_info: multi_party_info.clone(),
+                lost_party,
+                is_helper,
+                other_helpers,
+                record,
+                own_piece,
+                outgoing_pieces,
+                timeout,
+                limits,
+            })
+        }
+
+        /// Parties which the holder exchanges messages with: the lost party and other helpers, if the holder is a helper
+        fn peers(&self) -> BTreeSet<PartyIndex> {
+            let mut peers = self.other_helpers.clone();
+            peers.insert(self.lost_party);
+            peers
+        }
+    }
+
+    #[trace(pretty, prefix = "Phase1::")]
+    impl State<KeyRecoveryTraits> for Phase1 {
+        fn start(&mut self) -> Option<OutMsgVec> {
+            log::info!("Phase1 (holder) starts");
+            let record = OutMsg::new(
+                self.session,
+                Address::Peer(self.lost_party),
+                Message::R2(self.record.clone()),
+            );
+            Some(
+                std::iter::once(record)
+                    .chain(self.outgoing_pieces.iter().map(|(p, piece)| {
+                        OutMsg::new(
+                            self.session,
+                            Address::Peer(*p),
+                            Message::R3(Phase3Piece {
+                                piece: piece.clone(),
+                            }),
+                        )
+                    }))
+                    .collect(),
+            )
+        }
+
+        #[trace(disable(current_msg_set))]
+        fn classify_message(&self, msg: &InMsg, current_msg_set: &[InMsg]) -> MessageClass {
+            let awaited = match msg.body {
+                Message::R1(_) => msg.sender == self.lost_party,
+                Message::R3(_) => self.other_helpers.contains(&msg.sender),
+                _ => false,
+            };
+            msg.classify(
+                &self.session,
+                &self.limits,
+                1,
+                matches!(msg.body, Message::R1(_) | Message::R3(_)),
+                SenderRole::new(awaited, self.peers().contains(&msg.sender)),
+                current_msg_set,
+            )
+        }
+
+        #[trace(disable(current_msg_set))]
+        fn is_input_complete(&self, current_msg_set: &[InMsg]) -> bool {
+            is_broadcast_input_complete(current_msg_set, &self.peers())
+        }
+
+        #[trace(disable(current_msg_set))]
+        fn awaited_senders(&self, current_msg_set: &[InMsg]) -> Vec<PartyIndex> {
+            missing_senders(current_msg_set, &self.peers())
+        }
+
+        fn consume(&self, current_msg_set: Vec<InMsg>) -> Transition<KeyRecoveryTraits> {
+            let (keys, pieces): (Vec<_>, Vec<_>) = current_msg_set
+                .into_iter()
+                .partition(|m| m.sender == self.lost_party);
+            let (keys, pieces) = match (
+                to_hash_map_gen::<PartyIndex, Phase1Broadcast>(keys),
+                to_hash_map_gen::<PartyIndex, Phase3Piece>(pieces),
+            ) {
+                (Ok(keys), Ok(pieces)) => (keys, pieces),
+                (keys, pieces) => {
+                    let mut errors = keys.err().unwrap_or_default();
+                    errors.extend(pieces.err().unwrap_or_default());
+                    let error_state = ErrorState::new(errors);
+                    log::error!("Phase1 returns {:?}", error_state);
+                    return Transition::FinalState(Err(error_state));
+                }
+            };
+            let lost_keys = match keys.get(&self.lost_party) {
+                Some(msg) => msg.clone(),
+                None => {
+                    let error_state = ErrorState::new(vec![RecoveryError::GeneralError(
+                        "no keys of the lost party".to_string(),
+                    )]);
+                    log::error!("Phase1 returns {:?}", error_state);
+                    return Transition::FinalState(Err(error_state));
+                }
+            };
+
+            let errors = super::verify_keys(
+                self.lost_party,
+                &lost_keys.ek,
+                &lost_keys.correct_key_proof,
+                lost_keys.range_proof_setup.as_ref(),
+                Some(&lost_keys.modulus_proofs),
+                self.multi_party_info.range_proof_setups.is_some(),
+                &self.session.to_bigint(),
+            );
+            if !errors.is_empty() {
+                let error_state = ErrorState::new(errors);
+                log::error!("Phase1 returns {:?}", error_state);
+                return Transition::FinalState(Err(error_state));
+            }
+
+            let blinded_sum = if self.is_helper {
+                Some(
+                    pieces
+                        .values()
+                        .fold(self.own_piece.clone(), |acc, msg| acc + &msg.piece),
+                )
+            } else {
+                None
+            };
+            Transition::NewState(Box::new(Phase2 {
+                previous_phase: self.clone(),
+                lost_keys,
+                blinded_sum,
+            }))
+        }
+
+        fn timeout(&self) -> Option<Duration> {
+            self.timeout
+        }
+
+        fn timeout_outcome(&self, _current_msg_set: Vec<InMsg>) -> MachineResult {
+            Err(ErrorState::new(vec![RecoveryError::Timeout {
+                phase: "Holder.phase1".to_owned(),
+            }]))
+        }
+
+        #[trace(disable(res))]
+        fn snapshot(&self) -> Option<serde_json::Value> {
+            phase_snapshot("Phase1", self)
+        }
+
+        fn name(&self) -> &'static str {
+            "recovery.holder.phase1"
+        }
+
+        fn abort_output(&self, error: &ErrorState) -> Option<Vec<OutMsg>> {
+            super::abort_notices(self.session, error, self.peers().iter())
+        }
+
+        fn peer_aborted(&self, msg: &InMsg) -> Option<ErrorState> {
+            super::aborted_by_peer(
+                &self.session,
+                &self.limits,
+                msg,
+                self.peers().contains(&msg.sender),
+            )
+        }
+
+        #[trace(disable(current_msg_set))]
+        fn resend_request(&self, current_msg_set: &[InMsg]) -> Option<Vec<OutMsg>> {
+            let awaited = self.awaited_senders(current_msg_set);
+            let (lost, helpers): (Vec<_>, Vec<_>) =
+                awaited.into_iter().partition(|p| *p == self.lost_party);
+            let mut requests = super::resend_requests(self.session, 1, lost)?;
+            requests.extend(super::resend_requests(self.session, 3, helpers)?);
+            Some(requests)
+        }
+
+        fn resend_output(&self, msg: &InMsg, sent: &[OutMsg]) -> Option<Resent<OutMsg>> {
+            super::resent_output(
+                &self.session,
+                &self.limits,
+                msg,
+                self.peers().contains(&msg.sender),
+                sent,
+            )
+        }
+    }
+
+    /// Last phase of the protocol
+    ///
+    /// * if the holder is a helper, sends the sum of received pieces to the lost party, along with the public image of its share
+    /// * collects and verifies proofs that new moduli of the lost party have no small factors
+    /// * replaces the Paillier key and the range proof setup of the lost party
+    #[derive(Serialize, Deserialize)]
+    struct Phase2 {
+        previous_phase: Phase1,
+        lost_keys: Phase1Broadcast,
+        blinded_sum: Option<FE>,
+    }
+
+    #[trace(pretty, prefix = "Phase2::")]
+    impl State<KeyRecoveryTraits> for Phase2 {
+        fn start(&mut self) -> Option<OutMsgVec> {
+            log::debug!("Phase2 (holder) starts");
+            let info = &self.previous_phase.multi_party_info;
+            Some(
+                self.blinded_sum
+                    .iter()
+                    .map(|blinded_sum| {
+                        OutMsg::new(
+                            self.previous_phase.session,
+                            Address::Peer(self.previous_phase.lost_party),
+                            Message::R5(Phase5Contribution {
+                                blinded_sum: blinded_sum.clone(),
+                                public_share: GE::generator() * info.own_share(),
+                                public_key: info.public_key.clone(),
+                                point: info.party_to_point_map.points
+                                    [&self.previous_phase.lost_party],
+                            }),
+                        )
+                    })
+                    .collect(),
+            )
+        }
+
+        #[trace(disable(current_msg_set))]
+        fn classify_message(&self, msg: &InMsg, current_msg_set: &[InMsg]) -> MessageClass {
+            msg.classify(
+                &self.previous_phase.session,
+                &self.previous_phase.limits,
+                4,
+                matches!(msg.body, Message::R4(_)),
+                SenderRole::new(
+                    msg.sender == self.previous_phase.lost_party,
+                    self.previous_phase.peers().contains(&msg.sender),
+                ),
+                current_msg_set,
+            )
+        }
+
+        #[trace(disable(current_msg_set))]
+        fn is_input_complete(&self, current_msg_set: &[InMsg]) -> bool {
+            current_msg_set
+                .iter()
+                .any(|m| m.sender == self.previous_phase.lost_party)
+        }
+
+        #[trace(disable(current_msg_set))]
+        fn awaited_senders(&self, current_msg_set: &[InMsg]) -> Vec<PartyIndex> {
+            missing_senders(
+                current_msg_set,
+                &std::iter::once(self.previous_phase.lost_party).collect(),
+            )
+        }
+
+        fn consume(&self, current_msg_set: Vec<InMsg>) -> Transition<KeyRecoveryTraits> {
+            let lost_party = self.previous_phase.lost_party;
+            let info = &self.previous_phase.multi_party_info;
+            let context = self.previous_phase.session.to_bigint();
+            let verified = match to_hash_map_gen::<PartyIndex, Phase4Proofs>(current_msg_set) {
+                Ok(proofs) => {
+                    let factor_proofs = proofs
+                        .get(&lost_party)
+                        .and_then(|m| m.factor_proofs.as_ref());
+                    let verified = match (&info.range_proof_setups, factor_proofs) {
+                        (None, None) => Ok(()),
+                        (Some(setups), Some(proofs)) => match &self.lost_keys.range_proof_setup {
+                            Some(setup) => proofs
+                                .verify(&self.lost_keys.ek, setup, &setups.my_setup, &context)
+                                .map_err(|e| e.to_string()),
+                            None => Err("range proof setup of the party is unknown".to_string()),
+                        },
+                        (Some(_), None) => Err("missing proofs".to_string()),
+                        (None, Some(_)) => Err("unexpected proofs".to_string()),
+                    };
+                    verified.map_err(|reason| {
+                        vec![RecoveryError::InvalidFactorProof {
+                            reason,
+                            party: lost_party,
+                        }]
+                    })
+                }
+                Err(e) => Err(e),
+            };
+            if let Err(errors) = verified {
+                let error_state = ErrorState::new(errors);
+                log::error!("Phase2 returns {:?}", error_state);
+                return Transition::FinalState(Err(error_state));
+            }
+
+            let mut info = info.clone();
+            info.party_he_keys
+                .insert(lost_party, self.lost_keys.ek.clone());
+            if let (Some(setups), Some(setup)) = (
+                info.range_proof_setups.as_mut(),
+                &self.lost_keys.range_proof_setup,
+            ) {
+                setups.party_setups.insert(lost_party, setup.clone());
+            }
+            log::info!("Phase2 succeeded");
+            Transition::FinalState(Ok(FinalState { info }))
+        }
+
+        fn timeout(&self) -> Option<Duration> {
+            self.previous_phase.timeout
+        }
+
+        fn timeout_outcome(&self, _current_msg_set: Vec<InMsg>) -> MachineResult {
+            Err(ErrorState::new(vec![RecoveryError::Timeout {
+                phase: "Holder.phase2".to_owned(),
+            }]))
+        }
+
+        #[trace(disable(res))]
+        fn snapshot(&self) -> Option<serde_json::Value> {
+            phase_snapshot("Phase2", self)
+        }
+
+        fn name(&self) -> &'static str {
+            "recovery.holder.phase2"
+        }
+
+        fn abort_output(&self, error: &ErrorState) -> Option<Vec<OutMsg>> {
+            self.previous_phase.abort_output(error)
+        }
+
+        fn peer_aborted(&self, msg: &InMsg) -> Option<ErrorState> {
+            self.previous_phase.peer_aborted(msg)
+        }
+
+        #[trace(disable(current_msg_set))]
+        fn resend_request(&self, current_msg_set: &[InMsg]) -> Option<Vec<OutMsg>> {
+            super::resend_requests(
+                self.previous_phase.session,
+                4,
+                self.awaited_senders(current_msg_set),
+            )
+        }
+
+        fn resend_output(&self, msg: &InMsg, sent: &[OutMsg]) -> Option<Resent<OutMsg>> {
+            self.previous_phase.resend_output(msg, sent)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::holder::KeyRecoveryTraits as HolderKeyRecoveryTraits;
+    use super::lost_party::KeyRecoveryTraits;
+    use super::{holder, lost_party, RecoveryError};
+    use crate::keygen::MultiPartyInfo;
+    use crate::limits::Limits;
+    use crate::protocol::{PartyIndex, SessionId};
+    use crate::simulator::{
+        check_recovery, NetworkConditions, NodeOutcome, RecoveryResult, Simulation,
+    };
+    use crate::zk_range_proofs::ZkpSetup;
+    use anyhow::bail;
+    use state_machine::driver::Driver;
+    use std::fs;
+    use std::path::Path;
+
+    fn load_infos() -> anyhow::Result<Vec<MultiPartyInfo>> {
+        (0..=2)
+            .map(|i| {
+                let path = format!("../tests/data/zkrp-keys.{}.json", i);
+                Ok(serde_json::from_str::<MultiPartyInfo>(
+                    &fs::read_to_string(Path::new(&path))?,
+                )?)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn recovery_restores_lost_share() -> anyhow::Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let infos = load_infos()?;
+        let public_key = infos[0].public_key.clone();
+        let path = Path::new("../tests/data/rp-setups.json");
+        let zkp_setups: Vec<ZkpSetup> = serde_json::from_str(&fs::read_to_string(path)?)?;
+
+        let lost: PartyIndex = 2.into();
+        let holders: Vec<PartyIndex> = vec![0.into(), 1.into()];
+        let session_id = SessionId::random();
+        let mut simulation = Simulation::<_, RecoveryResult>::new(NetworkConditions::default())?;
+        for info in &infos[0..2] {
+            let phase =
+                holder::Phase1::new(session_id, info, lost, &holders, None, Limits::default())?;
+            simulation.add_party(
+                info.own_party_index,
+                Driver::<HolderKeyRecoveryTraits>::new(Box::new(phase)),
+            );
+        }
+        let phase = lost_party::Phase1::new(
+            session_id,
+            lost,
+            &infos[2].key_params,
+            &holders,
+            &holders,
+            Some(zkp_setups[3].clone()),
+            None,
+            Limits::default(),
+        )?;
+        simulation.add_party(lost, Driver::<KeyRecoveryTraits>::new(Box::new(phase)));
+        let report = simulation.run();
+        check_recovery(&report, &public_key).map_err(|e| anyhow::anyhow!("{:?}", e))?;
+
+        for (party, outcome) in report.outcomes {
+            match outcome {
+                NodeOutcome::Finished(RecoveryResult::Lost(Ok(f))) => {
+                    assert_eq!(f.info.secret_share, infos[2].secret_share);
+                    assert_eq!(
+                        f.info.party_to_point_map.points,
+                        infos[2].party_to_point_map.points
+                    );
+                    assert_eq!(f.info.party_he_keys, infos[2].party_he_keys);
+                    assert!(f.info.range_proof_setups.is_some());
+                }
+                NodeOutcome::Finished(RecoveryResult::Holder(Ok(f))) => {
+                    let info = infos.iter().find(|i| i.own_party_index == party).unwrap();
+                    assert_eq!(f.info.secret_share, info.secret_share);
+                    assert_ne!(f.info.party_he_keys[&lost], info.party_he_keys[&lost]);
+                }
+                other => bail!("party {} did not finish: {:?}", party, other),
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn recovery_requires_enough_helpers() -> anyhow::Result<()> {
+        let infos = load_infos()?;
+        let lost: PartyIndex = 2.into();
+        let result = holder::Phase1::new(
+            SessionId::random(),
+            &infos[0],
+            lost,
+            &[0.into()],
+            None,
+            Limits::default(),
+        );
+        assert!(matches!(result, Err(RecoveryError::ProtocolSetupError(_))));
+        let result = holder::Phase1::new(
+            SessionId::random(),
+            &infos[0],
+            0.into(),
+            &[1.into(), 2.into()],
+            None,
+            Limits::default(),
+        );
+        assert!(matches!(result, Err(RecoveryError::ProtocolSetupError(_))));
+        Ok(())
+    }
+}
@@ -0,0 +1,1181 @@
+//! Proactive key refresh
+//!
+//! Renews Shamir's shares of an existing key among the same set of parties, so that shares stolen before the refresh become useless.
+//!
+//! * Each party shares zero with a random polynomial of the degree equal to the threshold, and adds received shares to its own share.
+//!   The secret, the public key and the points of parties stay the same.
+//! * Feldman's VSS of each polynomial is broadcast, so that every party checks that the constant term of the polynomial is zero.
+//!   Public images of current shares are broadcast as well, so that every party knows the public image of the new share of each party.
+//! * Each party also rotates its Paillier key and its range proof setup, proving that new moduli are Paillier-Blum moduli without small factors, as in [key generation](../keygen/index.html).
+//! * The last round proves the knowledge of the new share, whose public image has to match the one known to other parties.
+//!
+//! Unlike [resharing](../resharing/index.html), each party runs the single machine which starts with [`Phase1`].
+//! The result is the new [`MultiPartyInfo`] which replaces the current one.
+//!
+//! [`Phase1`]: struct.Phase1.html
+//! [`MultiPartyInfo`]: ../keygen/struct.MultiPartyInfo.html
+
+use std::collections::{BTreeSet, HashMap};
+use std::time::Duration;
+
+use crate::encryption::{RefuseReason, RefusedMessage, ReportRefused};
+use crate::identity::{UnverifiedMessage, UnverifiedReason};
+use crate::keygen::{CorrectKeyProof, MultiPartyInfo, RangeProofSetups};
+use crate::limits::Limits;
+use crate::messages::{Abort, FactorProofs, ModulusProofs, SecretShare};
+use crate::proofs::DLogProof;
+use crate::protocol::{
+    abort_broadcast, aborted_by_peer, resend_requests, resent_output, AbortReport, Address,
+    PartyIndex, ProtocolId, SenderRole, Session, SessionId,
+};
+use crate::types::{BigInt, CurvVerifiableSS, Point, VerifiableSS, FE, GE};
+use crate::utils::{lagrange_coefficient, phase_snapshot};
+use crate::zk_range_proofs::{ZkpPublicSetup, ZkpSetup};
+use algorithms::nizk_rsa;
+use algorithms::types::PaillierKeys;
+use paillier::EncryptionKey;
+use serde::{Deserialize, Serialize};
+use state_machine::checkpoint::RestoreState;
+use state_machine::types::{
+    BoxedState, MessageClass, RejectReason, RejectedMessage, Resent, State, StateMachineTraits,
+    Transition,
+};
+use thiserror::Error;
+use trace::trace;
+
+#[doc(inline)]
+pub use super::messages::refresh::{InMsg, Message, OutMsg, Phase1Broadcast, Phase2Share};
+
+/// Enumerates error type which can be raised by key refresh protocol
+#[derive(Debug, Error)]
+#[allow(clippy::large_enum_variant)]
+pub enum RefreshError {
+    #[error("protocol setup error: {0}")]
+    ProtocolSetupError(String),
+    #[error("refresh: timeout in {phase}")]
+    Timeout { phase: String },
+    #[error("unexpected message {message_type:?}, party {party}")]
+    UnknownMessageType {
+        message_type: Message,
+        party: PartyIndex,
+    },
+    #[error("invalid secret sharing {vss}, party {party}")]
+    InvalidVSS { vss: String, party: PartyIndex },
+    #[error("invalid correct key proof {proof}, party {party}")]
+    InvalidCorrectKeyProof { proof: String, party: PartyIndex },
+    #[error("range proof setup: dlog proof failed , party {party}, proof {proof} ")]
+    RangeProofSetupDlogProofFailed { proof: String, party: PartyIndex },
+    #[error("invalid modulus proof: {reason}, party {party}")]
+    InvalidModulusProof { reason: String, party: PartyIndex },
+    #[error("invalid no small factor proof: {reason}, party {party}")]
+    InvalidFactorProof { reason: String, party: PartyIndex },
+    #[error("invalid dlog proof {proof}, party {party}")]
+    InvalidDlogProof { proof: String, party: PartyIndex },
+    #[error("public image of the new share does not match, party {party}")]
+    InvalidPublicShare { party: PartyIndex },
+    #[error("public images of current shares are inconsistent with the public key")]
+    InconsistentPublicShares,
+    #[error("message rejected: {reason:?}, party {party}")]
+    RejectedMessage {
+        party: PartyIndex,
+        reason: RejectReason,
+    },
+    #[error("message refused by secure channel: {reason:?}, party {party}")]
+    RefusedMessage {
+        party: PartyIndex,
+        reason: RefuseReason,
+    },
+    #[error("message failed identity verification: {reason:?}, party {party}")]
+    UnverifiedMessage {
+        party: PartyIndex,
+        reason: UnverifiedReason,
+    },
+    #[error("party {party} aborted the protocol: {reason}")]
+    PeerAborted {
+        party: PartyIndex,
+        reason: String,
+        blamed: Vec<PartyIndex>,
+    },
+    #[error("{0}")]
+    GeneralError(String),
+}
+
+impl RefreshError {
+    /// The party which the error is attributed to, if any
+    pub fn blamed_party(&self) -> Option<PartyIndex> {
+        match self {
+            RefreshError::UnknownMessageType { party, .. }
+            | RefreshError::InvalidVSS { party, .. }
+            | RefreshError::InvalidCorrectKeyProof { party, .. }
+            | RefreshError::RangeProofSetupDlogProofFailed { party, .. }
+            | RefreshError::InvalidModulusProof { party, .. }
+            | RefreshError::InvalidFactorProof { party, .. }
+            | RefreshError::InvalidDlogProof { party, .. }
+            | RefreshError::InvalidPublicShare { party }
+            | RefreshError::RejectedMessage { party, .. }
+            | RefreshError::RefusedMessage { party, .. }
+            | RefreshError::UnverifiedMessage { party, .. } => Some(*party),
+            _ => None,
+        }
+    }
+}
+
+/// Checks whether all expected messages have been received so far from other parties
+fn is_broadcast_input_complete(
+    current_msg_set: &[InMsg],
+    other_parties: &BTreeSet<PartyIndex>,
+) -> bool {
+    let senders = current_msg_set.iter().map(|m| m.sender).collect::<Vec<_>>();
+    other_parties.iter().all(|p| senders.contains(p))
+}
+
+/// Parties of the set which have not delivered their messages yet
+fn missing_senders(
+    current_msg_set: &[InMsg],
+    other_parties: &BTreeSet<PartyIndex>,
+) -> Vec<PartyIndex> {
+    other_parties
+        .iter()
+        .filter(|p| current_msg_set.iter().all(|m| m.sender != **p))
+        .copied()
+        .collect()
+}
+
+/// Extracts payloads form enum variants of input message into the hash map
+#[trace(disable(current_msg_set), res = "{:?}")]
+fn to_hash_map_gen<K, V>(current_msg_set: Vec<InMsg>) -> Result<HashMap<K, V>, Vec<RefreshError>>
+where
+    K: std::cmp::Eq + std::hash::Hash + std::convert::From<PartyIndex> + std::fmt::Debug,
+    V: std::fmt::Debug,
+    Option<V>: std::convert::From<Message>,
+{
+    let (converted_messages, errors) =
+        current_msg_set
+            .iter()
+            .fold((vec![], vec![]), |(mut values, mut errors), m| {
+                let body: Option<V> = m.body.clone().into();
+                match body {
+                    Some(b) => values.push((m.sender, b)),
+                    None => errors.push(RefreshError::UnknownMessageType {
+                        message_type: m.body.clone(),
+                        party: m.sender,
+                    }),
+                };
+                (values, errors)
+            });
+
+    if errors.is_empty() {
+        Ok(converted_messages
+            .into_iter()
+            .map(|(party, body)| (party.into(), body))
+            .collect::<HashMap<K, V>>())
+    } else {
+        Err(errors)
+    }
+}
+
+#[doc(hidden)]
+type OutMsgVec = Vec<OutMsg>;
+
+/// Type definitions
+#[derive(Debug)]
+pub struct KeyRefreshTraits;
+
+impl StateMachineTraits for KeyRefreshTraits {
+    type InMsg = InMsg;
+    type OutMsg = OutMsg;
+    type FinalState = FinalState;
+    type ErrorState = ErrorState;
+}
+pub type MachineResult = Result<FinalState, ErrorState>;
+
+/// Phases of the protocol restored from snapshots
+#[derive(Deserialize)]
+enum PhaseSnapshot {
+    Phase1(Phase1),
+    Phase2(Phase2),
+    Phase3(Phase3),
+}
+
+/// Restores phases of key refresh. Phases keep their secrets, hence no context is needed.
+impl RestoreState for KeyRefreshTraits {
+    type Context = ();
+
+    fn restore_state(
+        snapshot: serde_json::Value,
+        _context: &(),
+    ) -> Result<BoxedState<Self>, serde_json::Error> {
+        Ok(match serde_json::from_value(snapshot)? {
+            PhaseSnapshot::Phase1(phase) => Box::new(phase),
+            PhaseSnapshot::Phase2(phase) => Box::new(phase),
+            PhaseSnapshot::Phase3(phase) => Box::new(phase),
+        })
+    }
+}
+
+/// First phase of key refresh protocol
+///
+/// Generates and broadcasts:
+/// * Feldman's VSS of the polynomial with zero constant term
+/// * public image of the current share
+/// * new Paillier pair and ZKP of correct Paillier private key
+/// * new range proof setup
+/// * Paillier-Blum modulus proofs of new moduli
+///
+/// Receives all aforementioned from other parties and verifies them.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Phase1 {
+    session: Session,
+    limits: Limits,
+    multi_party_info: MultiPartyInfo,
+    other_parties: BTreeSet<PartyIndex>,
+    vss_scheme: CurvVerifiableSS,
+    own_zero_share: SecretShare,
+    outgoing_shares: HashMap<PartyIndex, SecretShare>,
+    paillier_keys: PaillierKeys,
+    paillier_key_proof: CorrectKeyProof,
+    modulus_proofs: ModulusProofs,
+    range_proof_setup: ZkpSetup,
+    timeout: Option<Duration>,
+}
+
+impl Phase1 {
+    /// initializes the protocol
+    ///
+    /// All parties mapped to points in `multi_party_info` take part in the refresh. `range_proof_setup` replaces the current setup of the party.
+    #[allow(clippy::result_large_err)]
+    pub fn new(
+        session_id: SessionId,
+        multi_party_info: &MultiPartyInfo,
+        range_proof_setup: ZkpSetup,
+        timeout: Option<Duration>,
+        limits: Limits,
+    ) -> Result<Self, RefreshError> {
+        let session = Session {
+            protocol: ProtocolId::Refresh,
+            id: session_id,
+        };
+        let own_party_index = multi_party_info.own_party_index;
+        let points = &multi_party_info.party_to_point_map.points;
+        if points.get(&own_party_index) != Some(&multi_party_info.own_point()) {
+            return Err(RefreshError::ProtocolSetupError(
+                "own party index is not mapped to own point".to_string(),
+            ));
+        }
+        let params = multi_party_info.key_params;
+        if points.len() != params.share_count() as usize {
+            return Err(RefreshError::ProtocolSetupError(format!(
+                "{} parties mapped to points, expected {}",
+                points.len(),
+                params.share_count()
+            )));
+        }
+
+        ZkpPublicSetup::from_private_zkp_setup(&range_proof_setup)
+            .verify()
+            .map_err(|e| RefreshError::ProtocolSetupError(format!("{:?}", e)))?;
+
+        let paillier_keys = PaillierKeys::random();
+        let context = session.to_bigint();
+        let modulus_proofs =
+            ModulusProofs::new(&paillier_keys.dk, Some(&range_proof_setup), &context).map_err(
+                |e| RefreshError::ProtocolSetupError(format!("invalid own moduli: {}", e)),
+            )?;
+        let paillier_key_proof = CorrectKeyProof(nizk_rsa::gen_proof_with_context(
+            &paillier_keys.dk,
+            &context,
+        ));
+
+        let parties_points = points.iter().collect::<Vec<_>>();
+        let indices = parties_points.iter().map(|(_, x)| **x).collect::<Vec<_>>();
+        let (vss_scheme, zero_shares) = VerifiableSS::share_at_indices(
+            params.threshold(),
+            params.share_count(),
+            &FE::zero(),
+            &indices,
+        );
+        let mut outgoing_shares = parties_points
+            .into_iter()
+            .zip(zero_shares.iter().cloned())
+            .map(|((party, x), share)| (*party, (*x, share)))
+            .collect::<HashMap<_, _>>();
+        let own_zero_share = outgoing_shares
+            .remove(&own_party_index)
+            .expect("own party is mapped to point");
+
+        Ok(Phase1 {
+            session,
+            multi_party_info: multi_party_info.clone(),
+            other_parties: outgoing_shares.keys().copied().collect(),
+            vss_scheme,
+            own_zero_share,
+            outgoing_shares,
+            paillier_keys,
+            paillier_key_proof,
+            modulus_proofs,
+            range_proof_setup,
+            timeout,
+            limits,
+        })
+    }
+
+    /// Verifies the broadcast of the party
+    fn verify_broadcast(
+        &self,
+        party: PartyIndex,
+        msg: &Phase1Broadcast,
+        context: &BigInt,
+    ) -> Vec<RefreshError> {
+        let params = &self.multi_party_info.key_params;
+        let mut errors = Vec::new();
+        if msg.vss.parameters.threshold != params.threshold()
+            || msg.vss.parameters.share_count != params.share_count()
+            || msg.vss.commitments.first() != Some(&Point::zero())
+        {
+            errors.push(RefreshError::InvalidVSS {
+                vss: format!("{:?}", msg.vss),
+                party,
+            });
+        }
+        if nizk_rsa::verify_with_context(&msg.ek, &msg.correct_key_proof.0, context).is_err() {
+            errors.push(RefreshError::InvalidCorrectKeyProof {
+                proof: format!("{:?}", msg.correct_key_proof),
+                party,
+            });
+        }
+        if let Err(e) = msg.range_proof_setup.verify() {
+            errors.push(RefreshError::RangeProofSetupDlogProofFailed {
+                proof: format!("{:?} {:?}", e, msg.range_proof_setup.dlog_proof),
+                party,
+            });
+        }
+        if let Err(e) = msg
+            .modulus_proofs
+            .verify(&msg.ek, Some(&msg.range_proof_setup), context)
+        {
+            errors.push(RefreshError::InvalidModulusProof {
+                reason: e.to_string(),
+                party,
+            });
+        }
+        errors
+    }
+
+    /// Checks that public images of current shares of all parties lie on the polynomial of the degree of the threshold, whose constant term is the public key
+    ///
+    /// The check cannot tell which of parties is lying, hence the error blames nobody.
+    #[allow(clippy::result_large_err)]
+    fn verify_public_shares(
+        &self,
+        broadcasts: &HashMap<PartyIndex, Phase1Broadcast>,
+    ) -> Result<(), RefreshError> {
+        let points = &self.multi_party_info.party_to_point_map.points;
+        let mut public_shares = broadcasts
+            .iter()
+            .map(|(party, msg)| (points[party], msg.public_share.clone()))
+            .collect::<Vec<_>>();
+        public_shares.push((
+            self.multi_party_info.own_point(),
+            Point::generator() * self.multi_party_info.own_share(),
+        ));
+        public_shares.sort_by_key(|(x, _)| *x);
+
+        let threshold = self.multi_party_info.key_params.threshold() as usize;
+        let (base, rest) = public_shares.split_at(threshold + 1);
+        let base_points = base.iter().map(|(x, _)| *x).collect::<Vec<_>>();
+        let interpolate = |at: u16| {
+            base.iter().fold(Point::zero(), |acc, (x, share)| {
+                acc + share * lagrange_coefficient(&base_points, *x, at)
+            })
+        };
+        if interpolate(0) == self.multi_party_info.public_key
+            && rest.iter().all(|(x, share)| interpolate(*x) == *share)
+        {
+            Ok(())
+        } else {
+            Err(RefreshError::InconsistentPublicShares)
+        }
+    }
+}
+
+#[trace(pretty, prefix = "Phase1::")]
+impl State<KeyRefreshTraits> for Phase1 {
+    fn start(&mut self) -> Option<OutMsgVec> {
+        log::info!("Phase1 starts");
+        Some(vec![OutMsg::new(
+            self.session,
+            Address::Broadcast,
+            Message::R1(Phase1Broadcast {
+                vss: self.vss_scheme.clone(),
+                public_share: Point::generator() * self.multi_party_info.own_share(),
+                ek: self.paillier_keys.ek.clone(),
+                correct_key_proof: self.paillier_key_proof.clone(),
+                range_proof_setup: ZkpPublicSetup::from_private_zkp_setup(&self.range_proof_setup),
+                modulus_proofs: self.modulus_proofs.clone(),
+            }),
+        )])
+    }
+
+    #[trace(disable(input))]
+    fn classify_message(&self, msg: &InMsg, input: &[InMsg]) -> MessageClass {
+        msg.classify(
+            &self.session,
+            &self.limits,
+            1,
+            matches!(msg.body, Message::R1(_)),
+            SenderRole::of_member(self.other_parties.contains(&msg.sender)),
+            input,
+        )
+    }
+
+    #[trace(disable(current_msg_set))]
+    fn is_input_complete(&self, current_msg_set: &[InMsg]) -> bool {
+        is_broadcast_input_complete(current_msg_set, &self.other_parties)
+    }
+
+    #[trace(disable(current_msg_set))]
+    fn awaited_senders(&self, current_msg_set: &[InMsg]) -> Vec<PartyIndex> {
+        missing_senders(current_msg_set, &self.other_parties)
+    }
+
+    fn consume(&self, current_msg_set: Vec<InMsg>) -> Transition<KeyRefreshTraits> {
+        let broadcasts = match to_hash_map_gen::<PartyIndex, Phase1Broadcast>(current_msg_set) {
+            Ok(map) => map,
+            Err(e) => {
+                let error_state = ErrorState::new(e);
+                log::error!("Phase1 returns {:?}", error_state);
+                return Transition::FinalState(Err(error_state));
+            }
+        };
+
+        let context = self.session.to_bigint();
+        let mut errors = broadcasts
+            .iter()
+            .flat_map(|(party, msg)| self.verify_broadcast(*party, msg, &context))
+            .collect::<Vec<_>>();
+        if let Err(e) = self.verify_public_shares(&broadcasts) {
+            errors.push(e);
+        }
+        if !errors.is_empty() {
+            log::error!("Phase1 returns errors {:?}", errors);
+            return Transition::FinalState(Err(ErrorState::new(errors)));
+        }
+
+        let range_proof_setups = RangeProofSetups {
+            my_setup: self.range_proof_setup.clone(),
+            party_setups: broadcasts
+                .iter()
+                .map(|(party, msg)| (*party, msg.range_proof_setup.clone()))
+                .collect(),
+        };
+        let factor_proofs = range_proof_setups
+            .party_setups
+            .iter()
+            .map(|(party, setup)| {
+                (
+                    *party,
+                    FactorProofs::new(
+                        &self.paillier_keys.dk,
+                        &range_proof_setups.my_setup,
+                        setup,
+                        &context,
+                    ),
+                )
+            })
+            .collect();
+
+        // the new share of each party is its current share plus shares of all zero polynomials at its point
+        let points = &self.multi_party_info.party_to_point_map.points;
+        let public_shares = broadcasts
+            .iter()
+            .map(|(party, msg)| {
+                let x = points[party];
+                let public_share = broadcasts
+                    .values()
+                    .map(|m| &m.vss)
+                    .chain(std::iter::once(&self.vss_scheme))
+                    .fold(msg.public_share.clone(), |acc, vss| {
+                        acc + vss.get_point_commitment(x)
+                    });
+                (*party, public_share)
+            })
+            .collect();
+
+        Transition::NewState(Box::new(Phase2 {
+            session: self.session,
+            limits: self.limits,
+            multi_party_info: self.multi_party_info.clone(),
+            other_parties: self.other_parties.clone(),
+            vss_schemes: broadcasts
+                .iter()
+                .map(|(party, msg)| (*party, msg.vss.clone()))
+                .collect(),
+            own_zero_share: self.own_zero_share.clone(),
+            outgoing_shares: self.outgoing_shares.clone(),
+            factor_proofs,
+            paillier_keys: self.paillier_keys.clone(),
+            party_he_keys: broadcasts
+                .iter()
+                .map(|(party, msg)| (*party, msg.ek.clone()))
+                .collect(),
+            range_proof_setups,
+            public_shares,
+            timeout: self.timeout,
+        }))
+    }
+
+    fn timeout_outcome(&self, _current_msg_set: Vec<InMsg>) -> MachineResult {
+        Err(ErrorState::new(vec![RefreshError::Timeout {
+            phase: "phase1".to_string(),
+        }]))
+    }
+    fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    #[trace(disable(res))]
+    fn snapshot(&self) -> Option<serde_json::Value> {
+        phase_snapshot("Phase1", self)
+    }
+
+    fn name(&self) -> &'static str {
+        "refresh.phase1"
+    }
+
+    fn abort_output(&self, error: &ErrorState) -> Option<Vec<OutMsg>> {
+        abort_broadcast(self.session, error)
+    }
+
+    fn peer_aborted(&self, msg: &InMsg) -> Option<ErrorState> {
+        aborted_by_peer(
+            &self.session,
+            &self.limits,
+            msg,
+            self.other_parties.contains(&msg.sender),
+        )
+    }
+
+    #[trace(disable(current_msg_set))]
+    fn resend_request(&self, current_msg_set: &[InMsg]) -> Option<Vec<OutMsg>> {
+        resend_requests(
+            self.session,
+            1,
+            missing_senders(current_msg_set, &self.other_parties),
+        )
+    }
+
+    fn resend_output(&self, msg: &InMsg, sent: &[OutMsg]) -> Option<Resent<OutMsg>> {
+        resent_output(
+            &self.session,
+            &self.limits,
+            msg,
+            self.other_parties.contains(&msg.sender),
+            sent,
+        )
+    }
+}
+
+/// Second phase of the protocol: sends shares of the zero polynomial along with no small factor proofs, verifies received ones and computes the new share
+#[derive(Serialize, Deserialize)]
+struct Phase2 {
+    session: Session,
+    limits: Limits,
+    multi_party_info: MultiPartyInfo,
+    other_parties: BTreeSet<PartyIndex>,
+    vss_schemes: HashMap<PartyIndex, CurvVerifiableSS>,
+    own_zero_share: SecretShare,
+    outgoing_shares: HashMap<PartyIndex, SecretShare>,
+    factor_proofs: HashMap<PartyIndex, FactorProofs>,
+    paillier_keys: PaillierKeys,
+    party_he_keys: HashMap<PartyIndex, EncryptionKey>,
+    range_proof_setups: RangeProofSetups,
+    public_shares: HashMap<PartyIndex, GE>,
+    timeout: Option<Duration>,
+}
+
+#[trace(pretty, prefix = "Phase2::")]
+impl State<KeyRefreshTraits> for Phase2 {
+    fn start(&mut self) -> Option<OutMsgVec> {
+        log::debug!("Phase2 starts");
+        Some(
+            self.other_parties
+                .iter()
+                .filter_map(|party| {
+                    Some(OutMsg::new(
+                        self.session,
+                        Address::Peer(*party),
+                        Message::R2(Phase2Share {
+                            share: self.outgoing_shares.get(party)?.clone(),
+                            factor_proofs: self.factor_proofs.get(party)?.clone(),
+                        }),
+                    ))
+                })
+                .collect(),
+        )
+    }
+
+    #[trace(disable(input))]
+    fn classify_message(&self, msg: &InMsg, input: &[InMsg]) -> MessageClass {
+        msg.classify(
+            &self.session,
+            &self.limits,
+            2,
+            matches!(msg.body, Message::R2(_)),
+            SenderRole::of_member(self.other_parties.contains(&msg.sender)),
+            input,
+        )
+    }
+
+    #[trace(disable(current_msg_set))]
+    fn is_input_complete(&self, current_msg_set: &[InMsg]) -> bool {
+        is_broadcast_input_complete(current_msg_set, &self.other_parties)
+    }
+
+    #[trace(disable(current_msg_set))]
+    fn awaited_senders(&self, current_msg_set: &[InMsg]) -> Vec<PartyIndex> {
+        missing_senders(current_msg_set, &self.other_parties)
+    }
+
+    fn consume(&self, current_msg_set: Vec<InMsg>) -> Transition<KeyRefreshTraits> {
+        let shares = match to_hash_map_gen::<PartyIndex, Phase2Share>(current_msg_set) {
+            Ok(map) => map,
+            Err(e) => return Transition::FinalState(Err(ErrorState::new(e))),
+        };
+
+        let own_point = self.multi_party_info.own_point();
+        let mut errors = shares
+            .iter()
+            .filter_map(|(party, msg)| match self.vss_schemes.get(party) {
+                Some(vss)
+                    if msg.share.0 == own_point
+                        && vss.validate_share(&msg.share.1, own_point).is_ok() =>
+                {
+                    None
+                }
+                vss => Some(RefreshError::InvalidVSS {
+                    vss: format!("{:?}", vss),
+                    party: *party,
+                }),
+            })
+            .collect::<Vec<_>>();
+
+        let context = self.session.to_bigint();
+        let factor_errors = shares
+            .iter()
+            .filter_map(|(party, msg)| {
+                let verified = match (
+                    self.party_he_keys.get(party),
+                    self.range_proof_setups.party_setups.get(party),
+                ) {
+                    (Some(ek), Some(setup)) => msg
+                        .factor_proofs
+                        .verify(ek, setup, &self.range_proof_setups.my_setup, &context)
+                        .map_err(|e| e.to_string()),
+                    _ => Err("moduli of the party are unknown".to_string()),
+                };
+                verified
+                    .err()
+                    .map(|reason| RefreshError::InvalidFactorProof {
+                        reason,
+                        party: *party,
+                    })
+            })
+            .collect::<Vec<_>>();
+        errors.extend(factor_errors);
+
+        if !errors.is_empty() {
+            log::error!("Phase2 returns errors {:?}", errors);
+            return Transition::FinalState(Err(ErrorState::new(errors)));
+        }
+
+        let new_share = shares.values().fold(
+            self.multi_party_info.own_share().clone() + &self.own_zero_share.1,
+            |acc, msg| acc + &msg.share.1,
+        );
+
+        Transition::NewState(Box::new(Phase3 {
+            session: self.session,
+            limits: self.limits,
+            other_parties: self.other_parties.clone(),
+            multi_party_info: MultiPartyInfo {
+                key_params: self.multi_party_info.key_params,
+                own_party_index: self.multi_party_info.own_party_index,
+                secret_share: (own_point, new_share),
+                public_key: self.multi_party_info.public_key.clone(),
+                own_he_keys: self.paillier_keys.clone(),
+                party_he_keys: self.party_he_keys.clone(),
+                party_to_point_map: self.multi_party_info.party_to_point_map.clone(),
+                range_proof_setups: Some(self.range_proof_setups.clone()),
+            },
+            public_shares: self.public_shares.clone(),
+            timeout: self.timeout,
+        }))
+    }
+
+    fn timeout_outcome(&self, _current_msg_set: Vec<InMsg>) -> MachineResult {
+        Err(ErrorState::new(vec![RefreshError::Timeout {
+            phase: "phase2".to_string(),
+        }]))
+    }
+    fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    #[trace(disable(res))]
+    fn snapshot(&self) -> Option<serde_json::Value> {
+        phase_snapshot("Phase2", self)
+    }
+
+    fn name(&self) -> &'static str {
+        "refresh.phase2"
+    }
+
+    fn abort_output(&self, error: &ErrorState) -> Option<Vec<OutMsg>> {
+        abort_broadcast(self.session, error)
+    }
+
+    fn peer_aborted(&self, msg: &InMsg) -> Option<ErrorState> {
+        aborted_by_peer(
+            &self.session,
+            &self.limits,
+            msg,
+            self.other_parties.contains(&msg.sender),
+        )
+    }
+
+    #[trace(disable(current_msg_set))]
+    fn resend_request(&self, current_msg_set: &[InMsg]) -> Option<Vec<OutMsg>> {
+        resend_requests(
+            self.session,
+            2,
+            missing_senders(current_msg_set, &self.other_parties),
+        )
+    }
+
+    fn resend_output(&self, msg: &InMsg, sent: &[OutMsg]) -> Option<Resent<OutMsg>> {
+        resent_output(
+            &self.session,
+            &self.limits,
+            msg,
+            self.other_parties.contains(&msg.sender),
+            sent,
+        )
+    }
+}
+
+/// Last phase of the protocol: broadcasts `DlogProof` for the new share and verifies proofs of other parties
+///
+/// Proofs of other parties have to be made for public images of their new shares, which are known from the first phase.
+#[derive(Serialize, Deserialize)]
+struct Phase3 {
+    session: Session,
+    limits: Limits,
+    other_parties: BTreeSet<PartyIndex>,
+    multi_party_info: MultiPartyInfo,
+    public_shares: HashMap<PartyIndex, GE>,
+    timeout: Option<Duration>,
+}
+
+#[trace(pretty, prefix = "Phase3::")]
+impl State<KeyRefreshTraits> for Phase3 {
+    fn start(&mut self) -> Option<OutMsgVec> {
+        log::debug!("Phase3 starts");
+        let dlog_proof = DLogProof::prove(&self.session, self.multi_party_info.own_share());
+        Some(vec![OutMsg::new(
+            self.session,
+            Address::Broadcast,
+            Message::R3(dlog_proof),
+        )])
+    }
+
+    #[trace(disable(input))]
+    fn classify_message(&self, msg: &InMsg, input: &[InMsg]) -> MessageClass {
+        msg.classify(
+            &self.session,
+            &self.limits,
+            3,
+            matches!(msg.body, Message::R3(_)),
+            SenderRole::of_member(self.other_parties.contains(&msg.sender)),
+            input,
+        )
+    }
+
+    #[trace(disable(current_msg_set))]
+    fn is_input_complete(&self, current_msg_set: &[InMsg]) -> bool {
+        is_broadcast_input_complete(current_msg_set, &self.other_parties)
+    }
+
+    #[trace(disable(current_msg_set))]
+    fn awaited_senders(&self, current_msg_set: &[InMsg]) -> Vec<PartyIndex> {
+        missing_senders(current_msg_set, &self.other_parties)
+    }
+
+    fn consume(&self, current_msg_set: Vec<InMsg>) -> Transition<KeyRefreshTraits> {
+        let proofs = match to_hash_map_gen::<PartyIndex, DLogProof>(current_msg_set) {
+            Ok(p) => p,
+            Err(e) => {
+                let err_state = ErrorState::new(e);
+                log::error!("Phase3 returns {:?}", err_state);
+                return Transition::FinalState(Err(err_state));
+            }
+        };
+
+        let errors = proofs
+            .iter()
+            .filter_map(|(party, msg)| {
+                if msg.verify(&self.session).is_err() {
+                    Some(RefreshError::InvalidDlogProof {
+                        proof: format!("{:?}", msg),
+                        party: *party,
+                    })
+                } else if self.public_shares.get(party) != Some(&msg.pk) {
+                    Some(RefreshError::InvalidPublicShare { party: *party })
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        if errors.is_empty() {
+            log::info!("Phase3 ends successfully");
+            Transition::FinalState(Ok(FinalState {
+                multiparty_shared_info: self.multi_party_info.clone(),
+            }))
+        } else {
+            log::error!("Phase3 returns error vector {:?}", errors);
+            Transition::FinalState(Err(ErrorState::new(errors)))
+        }
+    }
+
+    fn timeout_outcome(&self, _current_msg_set: Vec<InMsg>) -> MachineResult {
+        Err(ErrorState::new(vec![RefreshError::Timeout {
+            phase: "phase3".to_string(),
+        }]))
+    }
+    fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    #[trace(disable(res))]
+    fn snapshot(&self) -> Option<serde_json::Value> {
+        phase_snapshot("Phase3", self)
+    }
+
+    fn name(&self) -> &'static str {
+        "refresh.phase3"
+    }
+
+    fn abort_output(&self, error: &ErrorState) -> Option<Vec<OutMsg>> {
+        abort_broadcast(self.session, error)
+    }
+
+    fn peer_aborted(&self, msg: &InMsg) -> Option<ErrorState> {
+        aborted_by_peer(
+            &self.session,
+            &self.limits,
+            msg,
+            self.other_parties.contains(&msg.sender),
+        )
+    }
+
+    #[trace(disable(current_msg_set))]
+    fn resend_request(&self, current_msg_set: &[InMsg]) -> Option<Vec<OutMsg>> {
+        resend_requests(
+            self.session,
+            3,
+            missing_senders(current_msg_set, &self.other_parties),
+        )
+    }
+
+    fn resend_output(&self, msg: &InMsg, sent: &[OutMsg]) -> Option<Resent<OutMsg>> {
+        resent_output(
+            &self.session,
+            &self.limits,
+            msg,
+            self.other_parties.contains(&msg.sender),
+            sent,
+        )
+    }
+}
+
+/// Result of key refresh protocol
+///
+/// The new `MultiPartyInfo` replaces the one the protocol started with, which has to be erased.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FinalState {
+    pub multiparty_shared_info: MultiPartyInfo,
+}
+
+/// Container of `RefreshError` type
+#[derive(Debug)]
+pub struct ErrorState {
+    errors: Vec<RefreshError>,
+}
+
+impl ErrorState {
+    pub fn new(errors: Vec<RefreshError>) -> Self {
+        ErrorState { errors }
+    }
+
+    /// Adds senders of messages rejected by the state machine, once per reason, as they behave suspiciously
+    pub fn report_rejected(&mut self, rejected: &HashMap<RejectedMessage<PartyIndex>, usize>) {
+        self.errors
+            .extend(rejected.keys().map(|r| RefreshError::RejectedMessage {
+                party: r.sender,
+                reason: r.reason,
+            }));
+    }
+
+    /// Adds senders of messages which failed the verification of their identity, once per reason, as they behave suspiciously
+    pub fn report_unverified(&mut self, unverified: &HashMap<UnverifiedMessage, usize>) {
+        self.errors
+            .extend(unverified.keys().map(|r| RefreshError::UnverifiedMessage {
+                party: r.sender,
+                reason: r.reason,
+            }));
+    }
+}
+
+impl ReportRefused for ErrorState {
+    fn report_refused(&mut self, refused: &HashMap<RefusedMessage, usize>) {
+        self.errors
+            .extend(refused.keys().map(|r| RefreshError::RefusedMessage {
+                party: r.sender,
+                reason: r.reason,
+            }));
+    }
+}
+
+impl AbortReport for ErrorState {
+    fn abort_notice(&self) -> Abort {
+        let blamed = self
+            .errors
+            .iter()
+            .filter_map(RefreshError::blamed_party)
+            .collect::<BTreeSet<_>>();
+        Abort::new(&self.errors, blamed.into_iter().collect())
+    }
+
+    fn aborted_by(party: PartyIndex, notice: &Abort) -> Self {
+        ErrorState::new(vec![RefreshError::PeerAborted {
+            party,
+            reason: notice.reason.clone(),
+            blamed: notice.blamed.clone(),
+        }])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InMsg, KeyRefreshTraits, MachineResult, Message, Phase1, RefreshError};
+    use crate::keygen::MultiPartyInfo;
+    use crate::limits::Limits;
+    use crate::proofs::DLogProof;
+    use crate::protocol::{Address, InputMessage, PartyIndex, SessionId};
+    use crate::simulator::{NetworkConditions, NodeOutcome, Simulation};
+    use crate::types::{Point, FE};
+    use crate::zk_range_proofs::ZkpSetup;
+    use anyhow::{anyhow, bail};
+    use state_machine::driver::Driver;
+    use std::collections::VecDeque;
+    use std::fs;
+    use std::path::Path;
+    use std::time::Instant;
+
+    fn load_infos_and_setups() -> anyhow::Result<(Vec<MultiPartyInfo>, Vec<ZkpSetup>)> {
+        let path = Path::new("../tests/data/rp-setups.json");
+        let zkp_setups: Vec<ZkpSetup> = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let infos = (0..=2)
+            .map(|i| {
+                let path = format!("../tests/data/zkrp-keys.{}.json", i);
+                Ok(serde_json::from_str::<MultiPartyInfo>(
+                    &fs::read_to_string(Path::new(&path))?,
+                )?)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok((infos, zkp_setups))
+    }
+
+    /// Runs the refresh, passing messages of the second party to the first one through `tamper`, and returns the outcome of the first party
+    fn refresh_tampered(tamper: fn(&mut InMsg)) -> anyhow::Result<MachineResult> {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let (infos, zkp_setups) = load_infos_and_setups()?;
+        let parties = (0..=2).map(PartyIndex::from).collect::<Vec<_>>();
+        let session_id = SessionId::random();
+        let now = Instant::now();
+
+        let mut drivers = infos
+            .iter()
+            .enumerate()
+            .map(|(i, info)| {
+                let phase = Phase1::new(
+                    session_id,
+                    info,
+                    zkp_setups[3 + i].clone(),
+                    None,
+                    Limits::default(),
+                )?;
+                Ok(Driver::<KeyRefreshTraits>::new(Box::new(phase)))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let mut queue = VecDeque::new();
+        for (i, driver) in drivers.iter_mut().enumerate() {
+            let step = driver.start(now).map_err(|e| anyhow!("{:?}", e))?;
+            queue.extend(step.output.into_iter().map(|msg| (parties[i], msg)));
+        }
+
+        while let Some((sender, output)) = queue.pop_front() {
+            for (i, driver) in drivers.iter_mut().enumerate() {
+                let addressed = match output.recipient {
+                    Address::Broadcast => parties[i] != sender,
+                    Address::Peer(p) => p == parties[i],
+                };
+                if !addressed {
+                    continue;
+                }
+                let mut msg = InputMessage::from_output(sender, output.clone());
+                if i == 0 && sender == parties[1] {
+                    tamper(&mut msg);
+                }
+                let step = driver
+                    .handle_message(msg, now)
+                    .map_err(|e| anyhow!("{:?}", e))?;
+                if let (0, Some(outcome)) = (i, step.outcome) {
+                    return Ok(outcome);
+                }
+                queue.extend(step.output.into_iter().map(|msg| (parties[i], msg)));
+            }
+        }
+        bail!("the first party did not finish")
+    }
+
+    fn errors_of(outcome: MachineResult) -> anyhow::Result<Vec<RefreshError>> {
+        match outcome {
+            Err(error_state) => Ok(error_state.errors),
+            Ok(_) => bail!("the tampered message is not detected"),
+        }
+    }
+
+    #[test]
+    fn refresh_keeps_public_key() -> anyhow::Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let (infos, zkp_setups) = load_infos_and_setups()?;
+
+        let session_id = SessionId::random();
+        let mut simulation = Simulation::<_, MachineResult>::new(NetworkConditions::default())?;
+        for (i, info) in infos.iter().enumerate() {
+            let phase = Phase1::new(
+                session_id,
+                info,
+                zkp_setups[3 + i].clone(),
+                None,
+                Limits::default(),
+            )?;
+            simulation.add_party(
+                PartyIndex::from(i),
+                Driver::<KeyRefreshTraits>::new(Box::new(phase)),
+            );
+        }
+        let report = simulation.run();
+
+        let mut refreshed = Vec::new();
+        for (party, outcome) in report.outcomes {
+            match outcome {
+                NodeOutcome::Finished(Ok(f)) => refreshed.push(f.multiparty_shared_info),
+                other => anyhow::bail!("party {} did not finish: {:?}", party, other),
+            }
+        }
+        refreshed.sort_by_key(|info| info.own_party_index);
+
+        for (old, new) in infos.iter().zip(refreshed.iter()) {
+            assert_eq!(new.public_key, old.public_key);
+            assert_eq!(new.own_point(), old.own_point());
+            assert_eq!(new.party_to_point_map.points, old.party_to_point_map.points);
+            assert_ne!(new.own_share(), old.own_share());
+            assert_ne!(new.own_he_keys.ek.n, old.own_he_keys.ek.n);
+        }
+
+        // any two of refreshed shares reconstruct the secret key
+        let points = refreshed
+            .iter()
+            .map(|info| FE::from(info.own_point() as u64))
+            .collect::<Vec<_>>();
+        let secret = points[0].clone() * refreshed[1].own_share()
+            - points[1].clone() * refreshed[0].own_share();
+        let secret = secret * (points[0].clone() - &points[1]).invert().unwrap();
+        assert_eq!(Point::generator() * &secret, refreshed[0].public_key);
+        Ok(())
+    }
+
+    #[test]
+    fn bad_vss_share_blamed() -> anyhow::Result<()> {
+        let errors = errors_of(refresh_tampered(|msg| {
+            if let Message::R2(share) = &mut msg.body {
+                share.share.1 = FE::random();
+            }
+        })?)?;
+        assert!(matches!(
+            errors.as_slice(),
+            [RefreshError::InvalidVSS { party, .. }] if *party == PartyIndex::from(1)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn non_zero_constant_term_blamed() -> anyhow::Result<()> {
+        let errors = errors_of(refresh_tampered(|msg| {
+            if let Message::R1(broadcast) = &mut msg.body {
+                broadcast.vss.commitments[0] = Point::generator().to_point();
+            }
+        })?)?;
+        assert!(matches!(
+            errors.as_slice(),
+            [RefreshError::InvalidVSS { party, .. }] if *party == PartyIndex::from(1)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn bad_factor_proof_blamed() -> anyhow::Result<()> {
+        let errors = errors_of(refresh_tampered(|msg| {
+            if let Message::R2(share) = &mut msg.body {
+                let proofs = &mut share.factor_proofs;
+                std::mem::swap(&mut proofs.paillier, &mut proofs.n_tilde);
+            }
+        })?)?;
+        assert!(matches!(
+            errors.as_slice(),
+            [RefreshError::InvalidFactorProof { party, .. }] if *party == PartyIndex::from(1)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn mismatched_dlog_public_key_blamed() -> anyhow::Result<()> {
+        // the proof is valid, but it is made for another share
+        let errors = errors_of(refresh_tampered(|msg| {
+            if let Message::R3(_) = msg.body {
+                msg.body = Message::R3(DLogProof::prove(&msg.header.session, &FE::random()));
+            }
+        })?)?;
+        assert!(matches!(
+            errors.as_slice(),
+            [RefreshError::InvalidPublicShare { party }] if *party == PartyIndex::from(1)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn inconsistent_public_share_detected() -> anyhow::Result<()> {
+        let errors = errors_of(refresh_tampered(|msg| {
+            if let Message::R1(broadcast) = &mut msg.body {
+                broadcast.public_share = Point::generator().to_point();
+            }
+        })?)?;
+        assert!(matches!(
+            errors.as_slice(),
+            [RefreshError::InconsistentPublicShares]
+        ));
+        Ok(())
+    }
+}
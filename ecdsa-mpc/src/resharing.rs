@@ -2,15 +2,24 @@
 //!
 //! Key resharing process resembles the key generation protocol, however no new random shards are sampled in the beginning. Instead, existing Shamir's shares are reshared with the set of new parties using new randomly generated polynomials.
 //! New shares are generated by the set of current shareholders, after which existing shares are destroyed. Eventually old shareholders do not have access to the key anymore.
+//! Members of new committee acknowledge their shares with signatures of the public key made with these shares, once they have verified proofs of each other's Paillier keys. Current shareholders keep acknowledgements as receipts in [`old_member::FinalState`],
+//! and finish only if shares of more than threshold members of new committee belong to the public key, so that existing shares are not destroyed too early.
 //!
 //! The special case where the old party set is equal to the new party set is called "key refresh". In this case every party just updates own shard with new value.
-use crate::protocol::PartyIndex;
-
+//!
+//! [`old_member::FinalState`]: old_member/struct.FinalState.html
+use crate::protocol::{
+    abort_notices, aborted_by_peer, resend_requests, resent_output, AbortReport, PartyIndex,
+};
+
+use crate::encryption::{RefuseReason, RefusedMessage, ReportRefused};
+use crate::identity::{UnverifiedMessage, UnverifiedReason};
+use state_machine::types::{RejectReason, RejectedMessage};
 use thiserror::Error;
 
 pub use super::messages::resharing::{InMsg, Message, OutMsg, Phase1Broadcast};
 
-use crate::messages::SecretShare;
+use crate::messages::{Abort, SecretShare};
 use crate::types::{BigInt, FE};
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeSet, HashMap};
@@ -51,6 +60,10 @@ pub enum ResharingError {
     InvalidDlogProof { proof: String, party: PartyIndex },
     #[error("invalid correct key proof {proof}, party {party}")]
     InvalidCorrectKeyProof { proof: String, party: PartyIndex },
+    #[error("invalid modulus proof: {reason}, party {party}")]
+    InvalidModulusProof { reason: String, party: PartyIndex },
+    #[error("invalid no small factor proof: {reason}, party {party}")]
+    InvalidFactorProof { reason: String, party: PartyIndex },
     #[error("missing range proof from {party}")]
     RangeProofSetupMissing { party: PartyIndex },
     #[error("unexpected range proof from {party}, proof {proof:?} ")]
@@ -59,10 +72,58 @@ pub enum ResharingError {
     RangeProofSetupDlogProofFailed { proof: String, party: PartyIndex },
     #[error("protocol setup error: {0}")]
     ProtocolSetupError(String),
+    #[error("message rejected: {reason:?}, party {party}")]
+    RejectedMessage {
+        party: PartyIndex,
+        reason: RejectReason,
+    },
+    #[error("message refused by secure channel: {reason:?}, party {party}")]
+    RefusedMessage {
+        party: PartyIndex,
+        reason: RefuseReason,
+    },
+    #[error("message failed identity verification: {reason:?}, party {party}")]
+    UnverifiedMessage {
+        party: PartyIndex,
+        reason: UnverifiedReason,
+    },
+    #[error("party {party} aborted the protocol: {reason}")]
+    PeerAborted {
+        party: PartyIndex,
+        reason: String,
+        blamed: Vec<PartyIndex>,
+    },
+    #[error("invalid acknowledgement of new share: {reason}, party {party}")]
+    InvalidAck { reason: String, party: PartyIndex },
+    #[error("acknowledged shares of new committee do not belong to the public key")]
+    InconsistentAcks,
     #[error("{0}")]
     GeneralError(String),
 }
 
+impl ResharingError {
+    /// The party which the error is attributed to, if any
+    pub fn blamed_party(&self) -> Option<PartyIndex> {
+        match self {
+            ResharingError::InvalidComm { party, .. }
+            | ResharingError::InvalidVSS { party, .. }
+            | ResharingError::UnknownMessageType { party, .. }
+            | ResharingError::InvalidDlogProof { party, .. }
+            | ResharingError::InvalidCorrectKeyProof { party, .. }
+            | ResharingError::InvalidModulusProof { party, .. }
+            | ResharingError::InvalidFactorProof { party, .. }
+            | ResharingError::InvalidAck { party, .. }
+            | ResharingError::RangeProofSetupMissing { party }
+            | ResharingError::RangeProofSetupUnexpected { party, .. }
+            | ResharingError::RangeProofSetupDlogProofFailed { party, .. }
+            | ResharingError::RejectedMessage { party, .. }
+            | ResharingError::RefusedMessage { party, .. }
+            | ResharingError::UnverifiedMessage { party, .. } => Some(*party),
+            _ => None,
+        }
+    }
+}
+
 /// Contains a vector of possible resharing errors
 #[derive(Debug)]
 pub struct ErrorState {
@@ -74,6 +135,26 @@ impl ErrorState {
     pub fn new(errors: Vec<ResharingError>) -> Self {
         ErrorState { errors }
     }
+    /// Adds senders of messages rejected by the state machine, once per reason, as they behave suspiciously
+    pub fn report_rejected(&mut self, rejected: &HashMap<RejectedMessage<PartyIndex>, usize>) {
+        self.errors
+            .extend(rejected.keys().map(|r| ResharingError::RejectedMessage {
+                party: r.sender,
+                reason: r.reason,
+            }));
+    }
+
+    /// Adds senders of messages which failed the verification of their identity, once per reason, as they behave suspiciously
+    pub fn report_unverified(&mut self, unverified: &HashMap<UnverifiedMessage, usize>) {
+        self.errors.extend(
+            unverified
+                .keys()
+                .map(|r| ResharingError::UnverifiedMessage {
+                    party: r.sender,
+                    reason: r.reason,
+                }),
+        );
+    }
     pub fn append(self, rhs: ErrorState) -> Self {
         let mut errors = self.errors;
         errors.extend(rhs.errors.into_iter());
@@ -81,6 +162,35 @@ impl ErrorState {
     }
 }
 
+impl ReportRefused for ErrorState {
+    fn report_refused(&mut self, refused: &HashMap<RefusedMessage, usize>) {
+        self.errors
+            .extend(refused.keys().map(|r| ResharingError::RefusedMessage {
+                party: r.sender,
+                reason: r.reason,
+            }));
+    }
+}
+
+impl AbortReport for ErrorState {
+    fn abort_notice(&self) -> Abort {
+        let blamed = self
+            .errors
+            .iter()
+            .filter_map(ResharingError::blamed_party)
+            .collect::<BTreeSet<_>>();
+        Abort::new(&self.errors, blamed.into_iter().collect())
+    }
+
+    fn aborted_by(party: PartyIndex, notice: &Abort) -> Self {
+        ErrorState::new(vec![ResharingError::PeerAborted {
+            party,
+            reason: notice.reason.clone(),
+            blamed: notice.blamed.clone(),
+        }])
+    }
+}
+
 /// Checks whether all expected messages have been received so far from other parties  
 fn is_broadcast_input_complete(
     current_msg_set: &[InMsg],
@@ -90,6 +200,18 @@ fn is_broadcast_input_complete(
     other_parties.iter().all(|p| senders.contains(p))
 }
 
+/// Parties of the set which have not delivered their messages yet
+fn missing_senders(
+    current_msg_set: &[InMsg],
+    other_parties: &BTreeSet<PartyIndex>,
+) -> Vec<PartyIndex> {
+    other_parties
+        .iter()
+        .filter(|p| current_msg_set.iter().all(|m| m.sender != **p))
+        .copied()
+        .collect()
+}
+
 /// Extracts payloads form enum variants of input message into the hash map
 #[trace(disable(current_msg_set), res = "{:?}")]
 fn to_hash_map_gen<K, V>(current_msg_set: Vec<InMsg>) -> Result<HashMap<K, V>, Vec<ResharingError>>
@@ -145,25 +267,38 @@ fn map_parties_to_shares(
 pub mod old_member {
 
     use super::ErrorState;
-    use crate::keygen::MultiPartyInfo;
-    use crate::messages::resharing::{InMsg, Message, OutMsg, Phase1Broadcast, VSS};
+    use crate::keygen::{MultiPartyInfo, Party2PointMap};
+    use crate::messages::resharing::{InMsg, Message, OutMsg, Phase1Broadcast, SignedAck, VSS};
 
-    use crate::protocol::{Address, PartyIndex};
-    use crate::resharing::{map_parties_to_shares, ResharingError};
+    use crate::limits::Limits;
+    use crate::protocol::{Address, PartyIndex, ProtocolId, SenderRole, Session, SessionId};
+    use crate::resharing::{map_parties_to_shares, to_hash_map_gen, ResharingError};
     use crate::types::Parameters;
     use crate::types::{BigInt, CurvVerifiableSS, FE, GE};
     use crate::types::{DigestExt, VerifiableSS};
+    use crate::utils::phase_snapshot;
     use sha2::{Digest, Sha256};
-    use state_machine::types::{State, StateMachineTraits, Transition};
+    use state_machine::checkpoint::RestoreState;
+    use state_machine::types::{
+        BoxedState, MessageClass, Resent, State, StateMachineTraits, Transition,
+    };
 
     use std::cell::RefCell;
-    use std::collections::BTreeSet;
+    use std::collections::{BTreeMap, BTreeSet, HashMap};
     use std::iter::FromIterator;
     use std::time::Duration;
     use trace::trace;
 
+    /// Result of resharing protocol for a member of old committee
+    ///
+    /// `receipts` are acknowledgements of members of new committee, signed with their new shares of `public_key`.
+    /// The protocol succeeds only if acknowledged shares of more than `threshold` members of new committee belong to the public key,
+    /// hence the old share can be erased.
     #[derive(Clone, Debug, super::Serialize, super::Deserialize)]
-    pub struct FinalState;
+    pub struct FinalState {
+        pub public_key: GE,
+        pub receipts: BTreeMap<PartyIndex, SignedAck>,
+    }
 
     type OutMsgVec = Vec<OutMsg>;
     pub type MachineResult = Result<FinalState, ErrorState>;
@@ -179,14 +314,37 @@ pub mod old_member {
         type ErrorState = ErrorState;
     }
 
+    /// Phases of the protocol restored from snapshots
+    #[derive(super::Deserialize)]
+    enum PhaseSnapshot {
+        Phase1(Phase1),
+        Phase2(Phase2),
+    }
+
+    impl RestoreState for KeyResharingTraits {
+        type Context = ();
+
+        fn restore_state(
+            snapshot: serde_json::Value,
+            _context: &(),
+        ) -> Result<BoxedState<Self>, serde_json::Error> {
+            Ok(match serde_json::from_value(snapshot)? {
+                PhaseSnapshot::Phase1(phase) => Box::new(phase),
+                PhaseSnapshot::Phase2(phase) => Box::new(phase),
+            })
+        }
+    }
+
     /// Initial phase of the protcol
     ///
     /// * generates new Shamir's shares of existing share to share among members of new committee
     /// * generates Feldman's VSS
     /// * broadcasts commitment to public key and commitment to Feldman's VSS
     /// * collects ACK messages
-    #[derive(Debug)]
+    #[derive(Debug, super::Serialize, super::Deserialize)]
     pub struct Phase1 {
+        session: Session,
+        limits: Limits,
         new_committee: BTreeSet<PartyIndex>,
         vss_scheme: CurvVerifiableSS,
         outgoing_shares: Vec<FE>,
@@ -198,11 +356,13 @@ pub mod old_member {
     #[trace(pretty, prefix = "Phase1::")]
     impl Phase1 {
         pub fn new(
+            session_id: SessionId,
             multi_party_info: &MultiPartyInfo,
             new_params: &Parameters,
             old_committee: &[PartyIndex],
             new_committee: &[PartyIndex],
             timeout: Option<Duration>,
+            limits: Limits,
         ) -> Result<Self, ResharingError> {
             //check if old committee is sized correctly
             if old_committee.len() as u16 <= multi_party_info.key_params.threshold() {
@@ -247,16 +407,25 @@ pub mod old_member {
                 new_params.share_count() as u16,
                 &w_i,
             );
+            let session = Session {
+                protocol: ProtocolId::Resharing,
+                id: session_id,
+            };
             let vss_refs = vss_scheme.commitments.iter().collect::<Vec<_>>();
-            let vss_comm = Sha256::new().chain_points(vss_refs).result_bigint();
+            let vss_comm = Sha256::new()
+                .chain(session.to_bytes())
+                .chain_points(vss_refs)
+                .result_bigint();
 
             Ok(Phase1 {
+                session,
                 new_committee: new_parties_as_set,
                 vss_scheme,
                 outgoing_shares: (*outgoing_shares).to_vec(),
                 vss_comm,
                 y: multi_party_info.public_key.clone(),
                 timeout,
+                limits,
             })
         }
     }
@@ -269,12 +438,15 @@ pub mod old_member {
             let output = self
                 .new_committee
                 .iter()
-                .map(|p| OutMsg {
-                    recipient: Address::Peer(*p),
-                    body: Message::R1(Phase1Broadcast {
-                        y: self.y.clone(),
-                        vss_commitment: self.vss_comm.clone(),
-                    }),
+                .map(|p| {
+                    OutMsg::new(
+                        self.session,
+                        Address::Peer(*p),
+                        Message::R1(Phase1Broadcast {
+                            y: self.y.clone(),
+                            vss_commitment: self.vss_comm.clone(),
+                        }),
+                    )
                 })
                 .collect::<Vec<_>>();
 
@@ -282,8 +454,15 @@ pub mod old_member {
         }
 
         #[trace(disable(current_msg_set))]
-        fn is_message_expected(&self, msg: &InMsg, current_msg_set: &[InMsg]) -> bool {
-            matches!(msg.body, Message::Ack if self.new_committee.contains(&msg.sender) && !msg.is_duplicate(current_msg_set))
+        fn classify_message(&self, msg: &InMsg, current_msg_set: &[InMsg]) -> MessageClass {
+            msg.classify(
+                &self.session,
+                &self.limits,
+                4,
+                matches!(msg.body, Message::Ack),
+                SenderRole::of_member(self.new_committee.contains(&msg.sender)),
+                current_msg_set,
+            )
         }
 
         #[trace(disable(current_msg_set))]
@@ -291,11 +470,19 @@ pub mod old_member {
             super::is_broadcast_input_complete(current_msg_set, &self.new_committee)
         }
 
+        #[trace(disable(current_msg_set))]
+        fn awaited_senders(&self, current_msg_set: &[InMsg]) -> Vec<PartyIndex> {
+            super::missing_senders(current_msg_set, &self.new_committee)
+        }
+
         fn consume(&self, _current_msg_set: Vec<InMsg>) -> Transition<KeyResharingTraits> {
             Transition::NewState(Box::new(Phase2 {
+                session: self.session,
+                limits: self.limits,
                 new_committee: self.new_committee.clone(),
                 vss_scheme: self.vss_scheme.clone(),
                 outgoing_shares: RefCell::new(self.outgoing_shares.clone()),
+                y: self.y.clone(),
                 timeout: self.timeout,
             }))
         }
@@ -309,19 +496,132 @@ pub mod old_member {
                 phase: "Old.phase1".to_owned(),
             }]))
         }
+
+        #[trace(disable(res))]
+        fn snapshot(&self) -> Option<serde_json::Value> {
+            phase_snapshot("Phase1", self)
+        }
+
+        fn name(&self) -> &'static str {
+            "resharing.old.phase1"
+        }
+
+        fn abort_output(&self, error: &ErrorState) -> Option<Vec<OutMsg>> {
+            super::abort_notices(self.session, error, self.new_committee.iter())
+        }
+
+        fn peer_aborted(&self, msg: &InMsg) -> Option<ErrorState> {
+            super::aborted_by_peer(
+                &self.session,
+                &self.limits,
+                msg,
+                self.new_committee.contains(&msg.sender),
+            )
+        }
+
+        #[trace(disable(current_msg_set))]
+        fn resend_request(&self, current_msg_set: &[InMsg]) -> Option<Vec<OutMsg>> {
+            super::resend_requests(self.session, 4, self.awaited_senders(current_msg_set))
+        }
+
+        fn resend_output(&self, msg: &InMsg, sent: &[OutMsg]) -> Option<Resent<OutMsg>> {
+            super::resent_output(
+                &self.session,
+                &self.limits,
+                msg,
+                self.new_committee.contains(&msg.sender),
+                sent,
+            )
+        }
     }
 
     /// Second phase of the protocol
     ///
     /// * Shares new Shamir's secrets and their respective Feldman's VSS with members of new committee
-    /// * Collect `FinalAck` messages and exits  
+    /// * Collect `FinalAck` messages signed with new shares, verifies them and exits
+    /// * On timeout, exits successfully if acknowledged shares of more than `threshold` members of new committee belong to the public key
+    #[derive(super::Serialize, super::Deserialize)]
     struct Phase2 {
+        session: Session,
+        limits: Limits,
         new_committee: BTreeSet<PartyIndex>,
         vss_scheme: CurvVerifiableSS,
         outgoing_shares: RefCell<Vec<FE>>,
+        y: GE,
         timeout: Option<Duration>,
     }
 
+    impl Phase2 {
+        /// Verifies acknowledgements of members of new committee, and returns them as receipts
+        ///
+        /// Each acknowledgement is signed with the share of its public image. Since the new threshold and the public key define the sharing of the key,
+        /// public shares of any `threshold + 1` members interpolate to the public key.
+        fn receipts(&self, current_msg_set: Vec<InMsg>) -> MachineResult {
+            let acks = to_hash_map_gen::<PartyIndex, SignedAck>(current_msg_set)
+                .map_err(ErrorState::new)?;
+            let errors = acks
+                .iter()
+                .filter_map(|(party, ack)| {
+                    ack.verify(&self.session, &self.y)
+                        .err()
+                        .map(|e| ResharingError::InvalidAck {
+                            reason: e.to_string(),
+                            party: *party,
+                        })
+                })
+                .collect::<Vec<_>>();
+            if !errors.is_empty() {
+                return Err(ErrorState::new(errors));
+            }
+
+            let threshold = self.vss_scheme.parameters.threshold as usize;
+            let parties = acks.keys().cloned().collect::<BTreeSet<_>>();
+            if parties.len() <= threshold {
+                return Err(ErrorState::new(vec![ResharingError::Timeout {
+                    phase: "Old.phase2".to_owned(),
+                }]));
+            }
+            let parties = parties.into_iter().collect::<Vec<_>>();
+            // each member beyond the first `threshold` ones is checked along with them
+            let consistent = parties[threshold..].iter().all(|last| {
+                let mut subset = parties[..threshold].to_vec();
+                subset.push(*last);
+                self.interpolate_public_key(&subset, &acks) == self.y
+            });
+            if !consistent {
+                return Err(ErrorState::new(vec![ResharingError::InconsistentAcks]));
+            }
+
+            Ok(FinalState {
+                public_key: self.y.clone(),
+                receipts: acks.into_iter().collect(),
+            })
+        }
+
+        /// Interpolates public shares of the subset of new committee at zero
+        fn interpolate_public_key(
+            &self,
+            subset: &[PartyIndex],
+            acks: &HashMap<PartyIndex, SignedAck>,
+        ) -> GE {
+            let new_committee = self.new_committee.iter().cloned().collect::<Vec<_>>();
+            let point_map = Party2PointMap {
+                // using dummy shares as we need x-coords only
+                points: map_parties_to_shares(
+                    &new_committee,
+                    &vec![FE::zero(); new_committee.len()],
+                )
+                .into_iter()
+                .map(|(party, (point, _))| (party, point))
+                .collect(),
+            };
+            subset.iter().fold(GE::zero(), |acc, party| {
+                let x = FE::from(&BigInt::from(point_map.points[party] as u64));
+                acc + &acks[party].public_share * point_map.calculate_lagrange_multiplier(subset, x)
+            })
+        }
+    }
+
     #[trace(pretty, prefix = "Phase2::")]
     impl State<KeyResharingTraits> for Phase2 {
         fn start(&mut self) -> Option<Vec<OutMsg>> {
@@ -333,20 +633,30 @@ pub mod old_member {
             Some(
                 output
                     .into_iter()
-                    .map(|(p, (x, share))| OutMsg {
-                        recipient: Address::Peer(p),
-                        body: Message::R3(VSS {
-                            share: (x, share),
-                            vss: self.vss_scheme.clone(),
-                        }),
+                    .map(|(p, (x, share))| {
+                        OutMsg::new(
+                            self.session,
+                            Address::Peer(p),
+                            Message::R3(VSS {
+                                share: (x, share),
+                                vss: self.vss_scheme.clone(),
+                            }),
+                        )
                     })
                     .collect::<Vec<_>>(),
             )
         }
 
         #[trace(disable(current_msg_set))]
-        fn is_message_expected(&self, msg: &InMsg, current_msg_set: &[InMsg]) -> bool {
-            matches!(msg.body, Message::FinalAck if self.new_committee.contains(&msg.sender) && !msg.is_duplicate(current_msg_set))
+        fn classify_message(&self, msg: &InMsg, current_msg_set: &[InMsg]) -> MessageClass {
+            msg.classify(
+                &self.session,
+                &self.limits,
+                5,
+                matches!(msg.body, Message::FinalAck(_)),
+                SenderRole::of_member(self.new_committee.contains(&msg.sender)),
+                current_msg_set,
+            )
         }
 
         #[trace(disable(current_msg_set))]
@@ -354,40 +664,93 @@ pub mod old_member {
             super::is_broadcast_input_complete(current_msg_set, &self.new_committee)
         }
 
-        fn consume(&self, _current_msg_set: Vec<InMsg>) -> Transition<KeyResharingTraits> {
-            log::info!("Phase2 succeeded");
-            Transition::FinalState(Ok(FinalState {}))
+        #[trace(disable(current_msg_set))]
+        fn awaited_senders(&self, current_msg_set: &[InMsg]) -> Vec<PartyIndex> {
+            super::missing_senders(current_msg_set, &self.new_committee)
+        }
+
+        fn consume(&self, current_msg_set: Vec<InMsg>) -> Transition<KeyResharingTraits> {
+            match self.receipts(current_msg_set) {
+                Ok(final_state) => {
+                    log::info!("Phase2 succeeded");
+                    Transition::FinalState(Ok(final_state))
+                }
+                Err(error_state) => {
+                    log::error!("Phase2 returns {:?}", error_state);
+                    Transition::FinalState(Err(error_state))
+                }
+            }
         }
 
         fn timeout(&self) -> Option<Duration> {
             self.timeout
         }
 
-        fn timeout_outcome(&self, _current_msg_set: Vec<InMsg>) -> MachineResult {
-            Err(ErrorState::new(vec![super::ResharingError::Timeout {
-                phase: "Old.phase2".to_owned(),
-            }]))
+        fn timeout_outcome(&self, current_msg_set: Vec<InMsg>) -> MachineResult {
+            self.receipts(current_msg_set)
+        }
+
+        #[trace(disable(res))]
+        fn snapshot(&self) -> Option<serde_json::Value> {
+            phase_snapshot("Phase2", self)
+        }
+
+        fn name(&self) -> &'static str {
+            "resharing.old.phase2"
+        }
+
+        fn abort_output(&self, error: &ErrorState) -> Option<Vec<OutMsg>> {
+            super::abort_notices(self.session, error, self.new_committee.iter())
+        }
+
+        fn peer_aborted(&self, msg: &InMsg) -> Option<ErrorState> {
+            super::aborted_by_peer(
+                &self.session,
+                &self.limits,
+                msg,
+                self.new_committee.contains(&msg.sender),
+            )
+        }
+
+        #[trace(disable(current_msg_set))]
+        fn resend_request(&self, current_msg_set: &[InMsg]) -> Option<Vec<OutMsg>> {
+            super::resend_requests(self.session, 5, self.awaited_senders(current_msg_set))
+        }
+
+        fn resend_output(&self, msg: &InMsg, sent: &[OutMsg]) -> Option<Resent<OutMsg>> {
+            super::resent_output(
+                &self.session,
+                &self.limits,
+                msg,
+                self.new_committee.contains(&msg.sender),
+                sent,
+            )
         }
     }
 
     /// Helper function which returns true if a given message of the resharing protocol should be forwarded to a member of old committee
     pub fn is_message_to_committee(msg: &Message) -> bool {
-        matches!(msg, Message::Ack | Message::FinalAck)
+        matches!(msg, Message::Ack | Message::FinalAck(_))
     }
 }
 /// Contains the protocol part performed by a member of new committee
 pub mod new_member {
     use crate::keygen::{CorrectKeyProof, MultiPartyInfo, Party2PointMap, RangeProofSetups};
-    use crate::messages::resharing::{Phase1Broadcast, Phase2Broadcast, VSS};
-    use crate::protocol::{Address, PartyIndex};
+    use crate::limits::Limits;
+    use crate::messages::resharing::{
+        Phase1Broadcast, Phase2Broadcast, Phase4Broadcast, SignedAck, VSS,
+    };
+    use crate::protocol::{Address, PartyIndex, ProtocolId, SenderRole, Session, SessionId};
     use crate::resharing::{
         map_parties_to_shares, to_hash_map_gen, ErrorState, InMsg, Message, OutMsg, ResharingError,
     };
     use crate::types::Parameters;
-    use crate::utils::all_mapped_equal;
-    use algorithms::config::PRIME_BIT_LENGTH_IN_PAILLIER_SCHEMA;
+    use crate::utils::{all_mapped_equal, phase_snapshot};
     use algorithms::nizk_rsa;
-    use state_machine::types::{State, StateMachineTraits, Transition};
+    use state_machine::checkpoint::RestoreState;
+    use state_machine::types::{
+        BoxedState, MessageClass, Resent, State, StateMachineTraits, Transition,
+    };
 
     use crate::types::DigestExt;
     use sha2::{Digest, Sha256};
@@ -396,9 +759,9 @@ pub mod new_member {
 
     use algorithms::types::PaillierKeys;
 
-    use paillier::{EncryptionKey, KeyGeneration, Paillier};
+    use paillier::EncryptionKey;
 
-    use crate::messages::SecretShare;
+    use crate::messages::{FactorProofs, ModulusProofs, SecretShare};
     use crate::zk_range_proofs::{ZkpPublicSetup, ZkpSetup};
     use std::collections::{BTreeSet, HashMap};
     use std::iter::FromIterator;
@@ -425,6 +788,33 @@ pub mod new_member {
         type ErrorState = ErrorState;
     }
 
+    /// Phases of the protocol restored from snapshots
+    #[derive(super::Deserialize)]
+    enum PhaseSnapshot {
+        Phase1(Phase1),
+        Phase2(Phase2),
+        Phase3(Phase3),
+        Phase4(Phase4),
+        Phase5(Phase5),
+    }
+
+    impl RestoreState for KeyResharingTraits {
+        type Context = ();
+
+        fn restore_state(
+            snapshot: serde_json::Value,
+            _context: &(),
+        ) -> Result<BoxedState<Self>, serde_json::Error> {
+            Ok(match serde_json::from_value(snapshot)? {
+                PhaseSnapshot::Phase1(phase) => Box::new(phase),
+                PhaseSnapshot::Phase2(phase) => Box::new(phase),
+                PhaseSnapshot::Phase3(phase) => Box::new(phase),
+                PhaseSnapshot::Phase4(phase) => Box::new(phase),
+                PhaseSnapshot::Phase5(phase) => Box::new(phase),
+            })
+        }
+    }
+
     pub type MachineResult = Result<FinalState, ErrorState>;
 
     /// Starting phase of resharing protocol
@@ -432,21 +822,25 @@ pub mod new_member {
     /// * Sends nothing out
     /// * collects commitments to public key and to Feldman's VSS
     /// * verifies that all public keys are same
-    #[derive(Clone, Debug)]
+    #[derive(Clone, Debug, super::Serialize, super::Deserialize)]
     #[allow(dead_code)]
     pub struct Phase1 {
+        session: Session,
+        limits: Limits,
         old_params: Parameters,
         new_params: Parameters,
         old_committee: BTreeSet<PartyIndex>,
         others_from_new_committee: BTreeSet<PartyIndex>,
         own_party_index: PartyIndex,
-        range_proof_setup: Option<ZkpSetup>,
+        range_proof_setup: ZkpSetup,
         timeout: Option<Duration>,
     }
 
     #[trace(pretty, prefix = "Phase1::")]
     impl Phase1 {
+        #[allow(clippy::too_many_arguments)]
         pub fn new(
+            session_id: SessionId,
             old_params: &Parameters,
             new_params: &Parameters,
             old_committee: &[PartyIndex],
@@ -454,6 +848,7 @@ pub mod new_member {
             own_party_index: PartyIndex,
             range_proof_setup: Option<ZkpSetup>,
             timeout: Option<Duration>,
+            limits: Limits,
         ) -> Result<Self, ResharingError> {
             // check if old committee is sized correctly
             if old_committee.len() as u16 <= old_params.threshold() {
@@ -504,7 +899,25 @@ pub mod new_member {
             let mut others_from_new_committee = new_parties_as_set;
             others_from_new_committee.remove(&own_party_index);
 
+            let range_proof_setup = match range_proof_setup {
+                Some(setup) => {
+                    ZkpPublicSetup::from_private_zkp_setup(&setup)
+                        .verify()
+                        .map_err(|e| ResharingError::ProtocolSetupError(format!("{:?}", e)))?;
+                    setup
+                }
+                None => {
+                    return Err(ResharingError::ProtocolSetupError(
+                        "Using this signature scheme without range proofs is insecure".to_string(),
+                    ))
+                }
+            };
+
             Ok(Phase1 {
+                session: Session {
+                    protocol: ProtocolId::Resharing,
+                    id: session_id,
+                },
                 old_params: *old_params,
                 new_params: *new_params,
                 old_committee: BTreeSet::from_iter(old_committee.iter().cloned()),
@@ -512,10 +925,34 @@ pub mod new_member {
                 own_party_index,
                 range_proof_setup,
                 timeout,
+                limits,
             })
         }
     }
 
+    impl Phase1 {
+        /// Parties which the member exchanges messages with: old committee and other members of new committee
+        fn peers(&self) -> impl Iterator<Item = &PartyIndex> {
+            self.old_committee
+                .union(&self.others_from_new_committee)
+                .filter(move |p| **p != self.own_party_index)
+        }
+
+        fn abort_notices(&self, error: &ErrorState) -> Option<Vec<OutMsg>> {
+            super::abort_notices(self.session, error, self.peers())
+        }
+
+        fn aborted_by_peer(&self, msg: &InMsg) -> Option<ErrorState> {
+            let known_sender = self.peers().any(|p| *p == msg.sender);
+            super::aborted_by_peer(&self.session, &self.limits, msg, known_sender)
+        }
+
+        fn resent_output(&self, msg: &InMsg, sent: &[OutMsg]) -> Option<Resent<OutMsg>> {
+            let known_sender = self.peers().any(|p| *p == msg.sender);
+            super::resent_output(&self.session, &self.limits, msg, known_sender, sent)
+        }
+    }
+
     #[trace(pretty, prefix = "Phase1::")]
     impl State<KeyResharingTraits> for Phase1 {
         fn start(&mut self) -> Option<OutMsgVec> {
@@ -524,8 +961,18 @@ pub mod new_member {
         }
 
         #[trace(disable(current_msg_set))]
-        fn is_message_expected(&self, msg: &InMsg, current_msg_set: &[InMsg]) -> bool {
-            matches!(msg.body, Message::R1(_)) && !msg.is_duplicate(current_msg_set)
+        fn classify_message(&self, msg: &InMsg, current_msg_set: &[InMsg]) -> MessageClass {
+            msg.classify(
+                &self.session,
+                &self.limits,
+                1,
+                matches!(msg.body, Message::R1(_)),
+                SenderRole::new(
+                    self.old_committee.contains(&msg.sender),
+                    self.peers().any(|p| *p == msg.sender),
+                ),
+                current_msg_set,
+            )
         }
 
         #[trace(disable(current_msg_set))]
@@ -533,6 +980,11 @@ pub mod new_member {
             super::is_broadcast_input_complete(current_msg_set, &self.old_committee)
         }
 
+        #[trace(disable(current_msg_set))]
+        fn awaited_senders(&self, current_msg_set: &[InMsg]) -> Vec<PartyIndex> {
+            super::missing_senders(current_msg_set, &self.old_committee)
+        }
+
         fn consume(&self, current_msg_set: Vec<InMsg>) -> Transition<KeyResharingTraits> {
             match to_hash_map_gen::<PartyIndex, Phase1Broadcast>(current_msg_set) {
                 Ok(input) => {
@@ -555,10 +1007,6 @@ pub mod new_member {
                     }
 
                     // Actual consuming happens here
-                    let (ek, dk) = Paillier::keypair_with_modulus_size(
-                        2 * PRIME_BIT_LENGTH_IN_PAILLIER_SCHEMA,
-                    )
-                    .keys();
                     let y = input.iter().next().map(|(_, msg)| msg.y.clone()).unwrap();
                     let vss_comms = input
                         .into_iter()
@@ -569,7 +1017,7 @@ pub mod new_member {
                         previous_phase: (*self).clone(),
                         y,
                         vss_comms,
-                        my_paillier_keys: PaillierKeys { dk, ek },
+                        my_paillier_keys: PaillierKeys::random(),
                     }))
                 }
                 Err(e) => {
@@ -589,13 +1037,39 @@ pub mod new_member {
         fn timeout(&self) -> Option<Duration> {
             self.timeout
         }
+
+        #[trace(disable(res))]
+        fn snapshot(&self) -> Option<serde_json::Value> {
+            phase_snapshot("Phase1", self)
+        }
+
+        fn name(&self) -> &'static str {
+            "resharing.new.phase1"
+        }
+
+        fn abort_output(&self, error: &ErrorState) -> Option<Vec<OutMsg>> {
+            self.abort_notices(error)
+        }
+
+        fn peer_aborted(&self, msg: &InMsg) -> Option<ErrorState> {
+            self.aborted_by_peer(msg)
+        }
+
+        #[trace(disable(current_msg_set))]
+        fn resend_request(&self, current_msg_set: &[InMsg]) -> Option<Vec<OutMsg>> {
+            super::resend_requests(self.session, 1, self.awaited_senders(current_msg_set))
+        }
+
+        fn resend_output(&self, msg: &InMsg, sent: &[OutMsg]) -> Option<Resent<OutMsg>> {
+            self.resent_output(msg, sent)
+        }
     }
 
     /// Second phase of the resharing protocol
     ///
-    /// * Broadcasts public Paillier key, ZK proof of its correctness and optional `RangeProof` setup to other members of new committee
+    /// * Broadcasts public Paillier key, ZK proofs of its correctness, `RangeProof` setup and Paillier-Blum proofs of both moduli to other members of new committee
     /// * Collects and verifies same items from other parties
-    #[derive(Clone)]
+    #[derive(Clone, super::Serialize, super::Deserialize)]
     pub struct Phase2 {
         previous_phase: Phase1,
         y: GE,
@@ -608,52 +1082,30 @@ pub mod new_member {
         fn verify_range_proof_setups(
             &self,
             input: &HashMap<PartyIndex, Phase2Broadcast>,
-        ) -> Result<Option<RangeProofSetups>, Vec<ResharingError>> {
-            let my_range_proof_setup = &self.previous_phase.range_proof_setup;
-            let verification_errors = input
-                .iter()
-                .filter_map(
-                    |(&p, m)| match (my_range_proof_setup, &m.range_proof_setup) {
-                        (Some(_), None) => {
-                            Some(ResharingError::RangeProofSetupMissing { party: p })
-                        }
-                        (None, Some(s)) => Some(ResharingError::RangeProofSetupUnexpected {
+        ) -> Result<RangeProofSetups, Vec<ResharingError>> {
+            let mut party_setups = HashMap::new();
+            let mut verification_errors = Vec::new();
+            for (&p, m) in input {
+                match &m.range_proof_setup {
+                    None => verification_errors
+                        .push(ResharingError::RangeProofSetupMissing { party: p }),
+                    Some(setup) if setup.verify().is_err() => {
+                        verification_errors.push(ResharingError::RangeProofSetupDlogProofFailed {
+                            proof: format!("{:?}", setup.dlog_proof),
                             party: p,
-                            proof: format!("{:?}", s.dlog_proof),
-                        }),
-                        (Some(_), Some(setup)) => {
-                            if setup.verify().is_err() {
-                                Some(ResharingError::RangeProofSetupDlogProofFailed {
-                                    proof: format!("{:?}", setup.dlog_proof),
-                                    party: p,
-                                })
-                            } else {
-                                None
-                            }
-                        }
-                        _ => None,
-                    },
-                )
-                .collect::<Vec<_>>();
+                        })
+                    }
+                    Some(setup) => {
+                        party_setups.insert(p, setup.clone());
+                    }
+                }
+            }
 
             if verification_errors.is_empty() {
-                // either local setup exists and all parties shared their setups
-                // or local setup does not exist and all parties did not share theirs setups
-                Ok(my_range_proof_setup.as_ref().map(|s| RangeProofSetups {
-                    my_setup: s.clone(),
-                    party_setups: input
-                        .iter()
-                        .map(|(&p, m)| {
-                            (
-                                p,
-                                m.range_proof_setup
-                                    .as_ref()
-                                    .expect("range proof setup can't be None here")
-                                    .clone(),
-                            )
-                        })
-                        .collect::<HashMap<_, _>>(),
-                }))
+                Ok(RangeProofSetups {
+                    my_setup: self.previous_phase.range_proof_setup.clone(),
+                    party_setups,
+                })
             } else {
                 Err(verification_errors)
             }
@@ -664,35 +1116,52 @@ pub mod new_member {
     impl State<KeyResharingTraits> for Phase2 {
         fn start(&mut self) -> Option<OutMsgVec> {
             log::debug!("Phase2 (new member) starts");
-            let range_proof_setup = self
-                .previous_phase
-                .range_proof_setup
-                .as_ref()
-                .map(ZkpPublicSetup::from_private_zkp_setup);
-            let proof = nizk_rsa::gen_proof(&self.my_paillier_keys.dk);
+            let range_proof_setup =
+                ZkpPublicSetup::from_private_zkp_setup(&self.previous_phase.range_proof_setup);
+            let context = self.previous_phase.session.to_bigint();
+            let proof = nizk_rsa::gen_proof_with_context(&self.my_paillier_keys.dk, &context);
+            let modulus_proofs = ModulusProofs::new(
+                &self.my_paillier_keys.dk,
+                Some(&self.previous_phase.range_proof_setup),
+                &context,
+            )
+            .expect("Paillier keys are generated from Blum primes");
             #[allow(clippy::if_not_else)]
             let output = self
                 .previous_phase
                 .others_from_new_committee
                 .iter()
-                .map(|p| OutMsg {
-                    recipient: Address::Peer(*p),
-                    body: Message::R2(Phase2Broadcast {
-                        ek: self.my_paillier_keys.ek.clone(),
-                        correct_key_proof: CorrectKeyProof(proof.clone()),
-                        range_proof_setup: range_proof_setup.clone(),
-                    }),
+                .map(|p| {
+                    OutMsg::new(
+                        self.previous_phase.session,
+                        Address::Peer(*p),
+                        Message::R2(Phase2Broadcast {
+                            ek: self.my_paillier_keys.ek.clone(),
+                            correct_key_proof: CorrectKeyProof(proof.clone()),
+                            range_proof_setup: Some(range_proof_setup.clone()),
+                            modulus_proofs: modulus_proofs.clone(),
+                        }),
+                    )
                 })
                 .collect();
             Some(output)
         }
 
         #[trace(disable(current_msg_set))]
-        fn is_message_expected(&self, msg: &InMsg, current_msg_set: &[InMsg]) -> bool {
-            matches!(msg.body,
-            Message::R2(_)
-            if self.previous_phase.others_from_new_committee.contains(&msg.sender)
-            && !msg.is_duplicate(current_msg_set))
+        fn classify_message(&self, msg: &InMsg, current_msg_set: &[InMsg]) -> MessageClass {
+            msg.classify(
+                &self.previous_phase.session,
+                &self.previous_phase.limits,
+                2,
+                matches!(msg.body, Message::R2(_)),
+                SenderRole::new(
+                    self.previous_phase
+                        .others_from_new_committee
+                        .contains(&msg.sender),
+                    self.previous_phase.peers().any(|p| *p == msg.sender),
+                ),
+                current_msg_set,
+            )
         }
 
         #[trace(disable(current_msg_set))]
@@ -703,13 +1172,27 @@ pub mod new_member {
             )
         }
 
+        #[trace(disable(current_msg_set))]
+        fn awaited_senders(&self, current_msg_set: &[InMsg]) -> Vec<PartyIndex> {
+            super::missing_senders(
+                current_msg_set,
+                &self.previous_phase.others_from_new_committee,
+            )
+        }
+
         fn consume(&self, current_msg_set: Vec<InMsg>) -> Transition<KeyResharingTraits> {
             match to_hash_map_gen::<PartyIndex, Phase2Broadcast>(current_msg_set) {
                 Ok(input) => {
                     let mut errors = input
                         .iter()
                         .filter_map(|(party, msg)| {
-                            if nizk_rsa::verify(&msg.ek, &msg.correct_key_proof.0).is_err() {
+                            if nizk_rsa::verify_with_context(
+                                &msg.ek,
+                                &msg.correct_key_proof.0,
+                                &self.previous_phase.session.to_bigint(),
+                            )
+                            .is_err()
+                            {
                                 Some(ResharingError::InvalidCorrectKeyProof {
                                     proof: format!("{:?}", msg.correct_key_proof),
                                     party: *party,
@@ -720,12 +1203,28 @@ pub mod new_member {
                         })
                         .collect::<Vec<_>>();
 
+                    errors.extend(input.iter().filter_map(|(party, msg)| {
+                        msg.modulus_proofs
+                            .verify(
+                                &msg.ek,
+                                msg.range_proof_setup.as_ref(),
+                                &self.previous_phase.session.to_bigint(),
+                            )
+                            .err()
+                            .map(|e| ResharingError::InvalidModulusProof {
+                                reason: e.to_string(),
+                                party: *party,
+                            })
+                    }));
+
                     let range_proof_setups = match self.verify_range_proof_setups(&input) {
-                        Err(ve) => {
-                            errors.extend(ve);
-                            None
+                        Ok(setups) if errors.is_empty() => setups,
+                        result => {
+                            errors.extend(result.err().unwrap_or_default());
+                            let error_state = ErrorState::new(errors);
+                            log::error!("Phase 2 returns {:?}", error_state);
+                            return Transition::FinalState(Err(error_state));
                         }
-                        Ok(setups) => setups,
                     };
 
                     let mut other_paillier_keys = input
@@ -736,12 +1235,6 @@ pub mod new_member {
                         self.previous_phase.own_party_index,
                         self.my_paillier_keys.ek.clone(),
                     );
-
-                    if !errors.is_empty() {
-                        let error_state = ErrorState::new(errors);
-                        log::error!("Phase 2 returns {:?}", error_state);
-                        return Transition::FinalState(Err(error_state));
-                    }
                     Transition::NewState(Box::new(Phase3 {
                         previous_phase: (*self).clone(),
                         other_paillier_keys,
@@ -765,6 +1258,36 @@ pub mod new_member {
                 phase: "New.phase2".to_owned(),
             }]))
         }
+
+        #[trace(disable(res))]
+        fn snapshot(&self) -> Option<serde_json::Value> {
+            phase_snapshot("Phase2", self)
+        }
+
+        fn name(&self) -> &'static str {
+            "resharing.new.phase2"
+        }
+
+        fn abort_output(&self, error: &ErrorState) -> Option<Vec<OutMsg>> {
+            self.previous_phase.abort_notices(error)
+        }
+
+        fn peer_aborted(&self, msg: &InMsg) -> Option<ErrorState> {
+            self.previous_phase.aborted_by_peer(msg)
+        }
+
+        #[trace(disable(current_msg_set))]
+        fn resend_request(&self, current_msg_set: &[InMsg]) -> Option<Vec<OutMsg>> {
+            super::resend_requests(
+                self.previous_phase.session,
+                2,
+                self.awaited_senders(current_msg_set),
+            )
+        }
+
+        fn resend_output(&self, msg: &InMsg, sent: &[OutMsg]) -> Option<Resent<OutMsg>> {
+            self.previous_phase.resent_output(msg, sent)
+        }
     }
 
     /// Third phase of the protocol
@@ -772,11 +1295,11 @@ pub mod new_member {
     /// * sends ACK to old members
     /// * collects new Shamir's secrets and FVSS from them
     /// * verifies FVSS
-    #[derive(Clone)]
+    #[derive(Clone, super::Serialize, super::Deserialize)]
     struct Phase3 {
         previous_phase: Phase2,
         other_paillier_keys: HashMap<PartyIndex, EncryptionKey>,
-        range_proof_setups: Option<RangeProofSetups>,
+        range_proof_setups: RangeProofSetups,
     }
 
     #[trace(pretty, prefix = "Phase3::")]
@@ -788,20 +1311,36 @@ pub mod new_member {
                     .previous_phase
                     .old_committee
                     .iter()
-                    .map(|p| OutMsg {
-                        recipient: Address::Peer(*p),
-                        body: Message::Ack,
+                    .map(|p| {
+                        OutMsg::new(
+                            self.previous_phase.previous_phase.session,
+                            Address::Peer(*p),
+                            Message::Ack,
+                        )
                     })
                     .collect::<Vec<_>>(),
             )
         }
 
         #[trace(disable(current_msg_set))]
-        fn is_message_expected(&self, msg: &InMsg, current_msg_set: &[InMsg]) -> bool {
-            matches!(msg.body,
-            Message::R3(_)
-            if self.previous_phase.previous_phase.old_committee.contains(&msg.sender)
-            && !msg.is_duplicate(current_msg_set))
+        fn classify_message(&self, msg: &InMsg, current_msg_set: &[InMsg]) -> MessageClass {
+            msg.classify(
+                &self.previous_phase.previous_phase.session,
+                &self.previous_phase.previous_phase.limits,
+                3,
+                matches!(msg.body, Message::R3(_)),
+                SenderRole::new(
+                    self.previous_phase
+                        .previous_phase
+                        .old_committee
+                        .contains(&msg.sender),
+                    self.previous_phase
+                        .previous_phase
+                        .peers()
+                        .any(|p| *p == msg.sender),
+                ),
+                current_msg_set,
+            )
         }
 
         #[trace(disable(current_msg_set))]
@@ -812,6 +1351,14 @@ pub mod new_member {
             )
         }
 
+        #[trace(disable(current_msg_set))]
+        fn awaited_senders(&self, current_msg_set: &[InMsg]) -> Vec<PartyIndex> {
+            super::missing_senders(
+                current_msg_set,
+                &self.previous_phase.previous_phase.old_committee,
+            )
+        }
+
         fn consume(&self, current_msg_set: Vec<InMsg>) -> Transition<KeyResharingTraits> {
             match to_hash_map_gen::<PartyIndex, VSS>(current_msg_set) {
                 Err(e) => {
@@ -851,7 +1398,10 @@ pub mod new_member {
                         .filter_map(|(p, vss)| {
                             let ((_, x_i), vss) = (vss.share, vss.vss);
                             let vss_refs = vss.commitments.iter().collect::<Vec<_>>();
-                            let decomm = Sha256::new().chain_points(vss_refs).result_bigint();
+                            let decomm = Sha256::new()
+                                .chain(self.previous_phase.previous_phase.session.to_bytes())
+                                .chain_points(vss_refs)
+                                .result_bigint();
                             match self.previous_phase.vss_comms.get(&p) {
                                 Some(comm) => {
                                     if *comm == decomm {
@@ -900,58 +1450,100 @@ pub mod new_member {
         fn timeout(&self) -> Option<Duration> {
             self.previous_phase.previous_phase.timeout
         }
+
+        #[trace(disable(res))]
+        fn snapshot(&self) -> Option<serde_json::Value> {
+            phase_snapshot("Phase3", self)
+        }
+
+        fn name(&self) -> &'static str {
+            "resharing.new.phase3"
+        }
+
+        fn abort_output(&self, error: &ErrorState) -> Option<Vec<OutMsg>> {
+            self.previous_phase.previous_phase.abort_notices(error)
+        }
+
+        fn peer_aborted(&self, msg: &InMsg) -> Option<ErrorState> {
+            self.previous_phase.previous_phase.aborted_by_peer(msg)
+        }
+
+        #[trace(disable(current_msg_set))]
+        fn resend_request(&self, current_msg_set: &[InMsg]) -> Option<Vec<OutMsg>> {
+            super::resend_requests(
+                self.previous_phase.previous_phase.session,
+                3,
+                self.awaited_senders(current_msg_set),
+            )
+        }
+
+        fn resend_output(&self, msg: &InMsg, sent: &[OutMsg]) -> Option<Resent<OutMsg>> {
+            self.previous_phase.previous_phase.resent_output(msg, sent)
+        }
     }
 
-    /// Last phase of the protocol
+    /// Fourth phase of the protocol
     ///
-    /// * sends `FinalAck` messages to all parties, including members of old and new committees
-    /// * collects `FinalAck` from membeers of new committee and exits  
+    /// * sends proofs that own moduli have no small factors to other members of new committee, made with their range proof setups
+    /// * collects and verifies these proofs from members of new committee
+    #[derive(Clone, super::Serialize, super::Deserialize)]
     struct Phase4 {
         previous_phase: Phase3,
         share: SecretShare,
     }
 
-    #[trace(pretty, prefix = "Phase4::")]
-    impl State<KeyResharingTraits> for Phase4 {
-        fn start(&mut self) -> Option<Vec<OutMsg>> {
-            log::debug!("Phase4 (new member) starts");
-            let self_setup = &self.previous_phase.previous_phase.previous_phase;
-            Some(
-                self_setup
-                    .old_committee
-                    .iter()
-                    .map(|p| OutMsg {
-                        recipient: Address::Peer(*p),
-                        body: Message::FinalAck,
-                    })
-                    .chain(self_setup.others_from_new_committee.iter().map(|p| OutMsg {
-                        recipient: Address::Peer(*p),
-                        body: Message::FinalAck,
-                    }))
-                    .collect::<Vec<_>>(),
-            )
-        }
-
-        #[trace(disable(current_msg_set))]
-        fn is_message_expected(&self, msg: &InMsg, current_msg_set: &[InMsg]) -> bool {
-            matches!(msg.body, Message::FinalAck
-            if self.previous_phase.previous_phase.previous_phase.others_from_new_committee.contains(&msg.sender)
-            && !msg.is_duplicate(current_msg_set))
-        }
-
-        #[trace(disable(current_msg_set))]
-        fn is_input_complete(&self, current_msg_set: &[InMsg]) -> bool {
-            super::is_broadcast_input_complete(
-                current_msg_set,
+    impl Phase4 {
+        fn factor_proofs(&self, party: &PartyIndex) -> Option<FactorProofs> {
+            let setups = &self.previous_phase.range_proof_setups;
+            Some(FactorProofs::new(
+                &self.previous_phase.previous_phase.my_paillier_keys.dk,
+                &setups.my_setup,
+                setups.party_setups.get(party)?,
                 &self
                     .previous_phase
                     .previous_phase
                     .previous_phase
-                    .others_from_new_committee,
-            )
+                    .session
+                    .to_bigint(),
+            ))
         }
 
-        fn consume(&self, _current_msg_set: Vec<InMsg>) -> Transition<KeyResharingTraits> {
+        fn verify_factor_proofs(
+            &self,
+            input: &HashMap<PartyIndex, Phase4Broadcast>,
+        ) -> Vec<ResharingError> {
+            let context = self
+                .previous_phase
+                .previous_phase
+                .previous_phase
+                .session
+                .to_bigint();
+            let setups = &self.previous_phase.range_proof_setups;
+            input
+                .iter()
+                .filter_map(|(party, msg)| {
+                    let verified = match (
+                        self.previous_phase.other_paillier_keys.get(party),
+                        setups.party_setups.get(party),
+                    ) {
+                        (Some(ek), Some(setup)) => msg
+                            .factor_proofs
+                            .verify(ek, setup, &setups.my_setup, &context)
+                            .map_err(|e| e.to_string()),
+                        _ => Err("moduli of the party are unknown".to_string()),
+                    };
+                    verified
+                        .err()
+                        .map(|reason| ResharingError::InvalidFactorProof {
+                            reason,
+                            party: *party,
+                        })
+                })
+                .collect()
+        }
+
+        /// The result of the protocol, once the member has verified proofs of all other members of new committee
+        fn final_state(&self) -> FinalState {
             let mut new_committee = self
                 .previous_phase
                 .previous_phase
@@ -978,8 +1570,7 @@ pub mod new_member {
                 .map(|(party, (point, _))| (party, point))
                 .collect::<HashMap<_, _>>(),
             };
-            log::info!("Phase4 succeeded");
-            Transition::FinalState(Ok(FinalState {
+            FinalState {
                 info: MultiPartyInfo {
                     key_params: self.previous_phase.previous_phase.previous_phase.new_params,
                     own_party_index: self
@@ -992,8 +1583,88 @@ pub mod new_member {
                     own_he_keys: self.previous_phase.previous_phase.my_paillier_keys.clone(),
                     party_he_keys: self.previous_phase.other_paillier_keys.clone(),
                     party_to_point_map: party_mapping_to_points,
-                    range_proof_setups: self.previous_phase.range_proof_setups.clone(),
+                    range_proof_setups: Some(self.previous_phase.range_proof_setups.clone()),
                 },
+            }
+        }
+    }
+
+    #[trace(pretty, prefix = "Phase4::")]
+    impl State<KeyResharingTraits> for Phase4 {
+        fn start(&mut self) -> Option<Vec<OutMsg>> {
+            log::debug!("Phase4 (new member) starts");
+            let self_setup = &self.previous_phase.previous_phase.previous_phase;
+            Some(
+                self_setup
+                    .others_from_new_committee
+                    .iter()
+                    .filter_map(|p| {
+                        self.factor_proofs(p).map(|factor_proofs| {
+                            OutMsg::new(
+                                self_setup.session,
+                                Address::Peer(*p),
+                                Message::R4(Phase4Broadcast { factor_proofs }),
+                            )
+                        })
+                    })
+                    .collect::<Vec<_>>(),
+            )
+        }
+
+        #[trace(disable(current_msg_set))]
+        fn classify_message(&self, msg: &InMsg, current_msg_set: &[InMsg]) -> MessageClass {
+            let phase1 = &self.previous_phase.previous_phase.previous_phase;
+            msg.classify(
+                &phase1.session,
+                &phase1.limits,
+                6,
+                matches!(msg.body, Message::R4(_)),
+                SenderRole::new(
+                    phase1.others_from_new_committee.contains(&msg.sender),
+                    phase1.peers().any(|p| *p == msg.sender),
+                ),
+                current_msg_set,
+            )
+        }
+
+        #[trace(disable(current_msg_set))]
+        fn is_input_complete(&self, current_msg_set: &[InMsg]) -> bool {
+            super::is_broadcast_input_complete(
+                current_msg_set,
+                &self
+                    .previous_phase
+                    .previous_phase
+                    .previous_phase
+                    .others_from_new_committee,
+            )
+        }
+
+        #[trace(disable(current_msg_set))]
+        fn awaited_senders(&self, current_msg_set: &[InMsg]) -> Vec<PartyIndex> {
+            super::missing_senders(
+                current_msg_set,
+                &self
+                    .previous_phase
+                    .previous_phase
+                    .previous_phase
+                    .others_from_new_committee,
+            )
+        }
+
+        fn consume(&self, current_msg_set: Vec<InMsg>) -> Transition<KeyResharingTraits> {
+            let errors = match to_hash_map_gen::<PartyIndex, Phase4Broadcast>(current_msg_set) {
+                Ok(input) => self.verify_factor_proofs(&input),
+                Err(e) => e,
+            };
+            if !errors.is_empty() {
+                let error_state = ErrorState::new(errors);
+                log::error!("Phase4 returns {:?}", error_state);
+                return Transition::FinalState(Err(error_state));
+            }
+
+            log::info!("Phase4 succeeded");
+            Transition::NewState(Box::new(Phase5 {
+                previous_phase: self.clone(),
             }))
         }
 
@@ -1006,6 +1677,170 @@ pub mod new_member {
                 phase: "New.phase4".to_owned(),
             }]))
         }
+
+        #[trace(disable(res))]
+        fn snapshot(&self) -> Option<serde_json::Value> {
+            phase_snapshot("Phase4", self)
+        }
+
+        fn name(&self) -> &'static str {
+            "resharing.new.phase4"
+        }
+
+        fn abort_output(&self, error: &ErrorState) -> Option<Vec<OutMsg>> {
+            self.previous_phase
+                .previous_phase
+                .previous_phase
+                .abort_notices(error)
+        }
+
+        fn peer_aborted(&self, msg: &InMsg) -> Option<ErrorState> {
+            self.previous_phase
+                .previous_phase
+                .previous_phase
+                .aborted_by_peer(msg)
+        }
+
+        #[trace(disable(current_msg_set))]
+        fn resend_request(&self, current_msg_set: &[InMsg]) -> Option<Vec<OutMsg>> {
+            super::resend_requests(
+                self.previous_phase.previous_phase.previous_phase.session,
+                6,
+                self.awaited_senders(current_msg_set),
+            )
+        }
+
+        fn resend_output(&self, msg: &InMsg, sent: &[OutMsg]) -> Option<Resent<OutMsg>> {
+            self.previous_phase
+                .previous_phase
+                .previous_phase
+                .resent_output(msg, sent)
+        }
+    }
+
+    /// Last phase of the protocol
+    ///
+    /// * sends `FinalAck` messages to members of old committee, signed with the new share, once proofs of all other members of new committee are verified
+    /// * sends `ProofsAck` messages to other members of new committee
+    /// * collects `ProofsAck` messages from members of new committee and exits
+    ///
+    /// Members of old committee thus keep as receipts only acknowledgements of members which have verified the whole new committee.
+    #[derive(super::Serialize, super::Deserialize)]
+    struct Phase5 {
+        previous_phase: Phase4,
+    }
+
+    impl Phase5 {
+        fn setup(&self) -> &Phase1 {
+            &self
+                .previous_phase
+                .previous_phase
+                .previous_phase
+                .previous_phase
+        }
+    }
+
+    #[trace(pretty, prefix = "Phase5::")]
+    impl State<KeyResharingTraits> for Phase5 {
+        fn start(&mut self) -> Option<Vec<OutMsg>> {
+            log::debug!("Phase5 (new member) starts");
+            let self_setup = self.setup();
+            let ack = SignedAck::new(
+                &self_setup.session,
+                &self.previous_phase.previous_phase.previous_phase.y,
+                &self.previous_phase.share.1,
+            );
+            Some(
+                self_setup
+                    .old_committee
+                    .iter()
+                    .map(|p| {
+                        OutMsg::new(
+                            self_setup.session,
+                            Address::Peer(*p),
+                            Message::FinalAck(ack.clone()),
+                        )
+                    })
+                    .chain(self_setup.others_from_new_committee.iter().map(|p| {
+                        OutMsg::new(self_setup.session, Address::Peer(*p), Message::ProofsAck)
+                    }))
+                    .collect::<Vec<_>>(),
+            )
+        }
+
+        #[trace(disable(current_msg_set))]
+        fn classify_message(&self, msg: &InMsg, current_msg_set: &[InMsg]) -> MessageClass {
+            let phase1 = self.setup();
+            msg.classify(
+                &phase1.session,
+                &phase1.limits,
+                7,
+                matches!(msg.body, Message::ProofsAck),
+                SenderRole::new(
+                    phase1.others_from_new_committee.contains(&msg.sender),
+                    phase1.peers().any(|p| *p == msg.sender),
+                ),
+                current_msg_set,
+            )
+        }
+
+        #[trace(disable(current_msg_set))]
+        fn is_input_complete(&self, current_msg_set: &[InMsg]) -> bool {
+            super::is_broadcast_input_complete(
+                current_msg_set,
+                &self.setup().others_from_new_committee,
+            )
+        }
+
+        #[trace(disable(current_msg_set))]
+        fn awaited_senders(&self, current_msg_set: &[InMsg]) -> Vec<PartyIndex> {
+            super::missing_senders(current_msg_set, &self.setup().others_from_new_committee)
+        }
+
+        fn consume(&self, _current_msg_set: Vec<InMsg>) -> Transition<KeyResharingTraits> {
+            log::info!("Phase5 succeeded");
+            Transition::FinalState(Ok(self.previous_phase.final_state()))
+        }
+
+        fn timeout(&self) -> Option<Duration> {
+            self.setup().timeout
+        }
+
+        fn timeout_outcome(&self, _current_msg_set: Vec<InMsg>) -> MachineResult {
+            Err(ErrorState::new(vec![super::ResharingError::Timeout {
+                phase: "New.phase5".to_owned(),
+            }]))
+        }
+
+        #[trace(disable(res))]
+        fn snapshot(&self) -> Option<serde_json::Value> {
+            phase_snapshot("Phase5", self)
+        }
+
+        fn name(&self) -> &'static str {
+            "resharing.new.phase5"
+        }
+
+        fn abort_output(&self, error: &ErrorState) -> Option<Vec<OutMsg>> {
+            self.setup().abort_notices(error)
+        }
+
+        fn peer_aborted(&self, msg: &InMsg) -> Option<ErrorState> {
+            self.setup().aborted_by_peer(msg)
+        }
+
+        #[trace(disable(current_msg_set))]
+        fn resend_request(&self, current_msg_set: &[InMsg]) -> Option<Vec<OutMsg>> {
+            super::resend_requests(
+                self.setup().session,
+                7,
+                self.awaited_senders(current_msg_set),
+            )
+        }
+
+        fn resend_output(&self, msg: &InMsg, sent: &[OutMsg]) -> Option<Resent<OutMsg>> {
+            self.setup().resent_output(msg, sent)
+        }
     }
     /// Helper function which returns true if a given message of the resharing protocol should be forwarded to a member of new committee
     pub fn is_message_to_committee(msg: &Message) -> bool {
@@ -1013,21 +1848,599 @@ pub mod new_member {
     }
 }
 
+/// Contains the protocol part performed by a holder of the key, whichever committees it is a member of
+///
+/// A holder of the key which stays in the committee would otherwise run both [`old_member::Phase1`] and [`new_member::Phase1`] and route messages between them.
+/// [`Member`] works out the roles of the party from both committees, runs the machine of each role inside a single state object, and delivers messages the party sends to itself between the roles.
+/// Holders of the key which only leave or only join the committee can use it too, while parties which do not hold the key join with [`new_member::Phase1`].
+///
+/// [`old_member::Phase1`]: ../old_member/struct.Phase1.html
+/// [`new_member::Phase1`]: ../new_member/struct.Phase1.html
+/// [`Member`]: struct.Member.html
+pub mod member {
+    use super::{new_member, old_member, ErrorState, InMsg, Message, OutMsg, ResharingError};
+    use crate::keygen::MultiPartyInfo;
+    use crate::limits::Limits;
+    use crate::protocol::{
+        Address, InputMessage, PartyIndex, ProtocolId, SenderRole, Session, SessionId,
+    };
+    use crate::types::Parameters;
+    use crate::utils::phase_snapshot;
+    use crate::zk_range_proofs::ZkpSetup;
+    use serde::{Deserialize, Serialize};
+    use state_machine::checkpoint::RestoreState;
+    use state_machine::types::{
+        BoxedState, MessageClass, Resent, State, StateMachineTraits, Transition,
+    };
+    use std::cell::RefCell;
+    use std::collections::{BTreeSet, VecDeque};
+    use std::time::Duration;
+    use trace::trace;
+
+    /// Result of resharing protocol
+    ///
+    /// `info` is `None` if the party is not a member of new committee, hence it does not hold the key anymore.
+    /// `receipts` are acknowledgements of new committee collected by the role of the member of old committee, and `None` if the party is not a member of old committee.
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct FinalState {
+        pub info: Option<MultiPartyInfo>,
+        pub receipts: Option<old_member::FinalState>,
+    }
+
+    #[doc(hidden)]
+    type OutMsgVec = Vec<OutMsg>;
+    pub type MachineResult = Result<FinalState, ErrorState>;
+
+    /// Type definitions
+    #[derive(Debug)]
+    pub struct KeyResharingTraits;
+
+    impl StateMachineTraits for KeyResharingTraits {
+        type InMsg = InMsg;
+        type OutMsg = OutMsg;
+        type FinalState = FinalState;
+        type ErrorState = ErrorState;
+    }
+
+    /// Phases of the protocol restored from snapshots
+    #[derive(Deserialize)]
+    enum PhaseSnapshot {
+        Member(MemberSnapshot),
+    }
+
+    /// Restores the state object along with the phases of its roles
+    impl RestoreState for KeyResharingTraits {
+        type Context = ();
+
+        fn restore_state(
+            snapshot: serde_json::Value,
+            _context: &(),
+        ) -> Result<BoxedState<Self>, serde_json::Error> {
+            Ok(match serde_json::from_value(snapshot)? {
+                PhaseSnapshot::Member(member) => Box::new(Member {
+                    session: member.session,
+                    limits: member.limits,
+                    own_party_index: member.own_party_index,
+                    peers: member.peers,
+                    roles: RefCell::new(Roles {
+                        old: member.old.map(Role::restore).transpose()?,
+                        new: member.new.map(Role::restore).transpose()?,
+                    }),
+                    output: Vec::new(),
+                    timeout: member.timeout,
+                }),
+            })
+        }
+    }
+
+    /// Messages which go to the role of the member of old committee, all other messages go to the role of the member of new committee
+    fn is_for_old_role(msg: &Message) -> bool {
+        old_member::is_message_to_committee(msg)
+    }
+
+    /// The machine of one role of the party
+    struct Role<T: StateMachineTraits> {
+        state: BoxedState<T>,
+        /// messages accepted by the current phase of the role
+        retained: Vec<InMsg>,
+        /// own messages which belong to following phases of the role
+        deferred: Vec<InMsg>,
+        result: Option<T::FinalState>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct RoleSnapshot<F> {
+        state: serde_json::Value,
+        retained: Vec<InMsg>,
+        deferred: Vec<InMsg>,
+        result: Option<F>,
+    }
+
+    impl<T> Role<T>
+    where
+        T: StateMachineTraits<InMsg = InMsg, OutMsg = OutMsg, ErrorState = ErrorState>,
+    {
+        fn new(state: BoxedState<T>) -> Self {
+            Role {
+                state,
+                retained: Vec::new(),
+                deferred: Vec::new(),
+                result: None,
+            }
+        }
+
+        fn is_active(&self) -> bool {
+            self.result.is_none()
+        }
+
+        /// Messages retained by the current phase, followed by messages of the set which go to the role
+        fn input(&self, current_msg_set: &[InMsg], for_old_role: bool) -> Vec<InMsg> {
+            self.retained
+                .iter()
+                .chain(
+                    current_msg_set
+                        .iter()
+                        .filter(|m| is_for_old_role(&m.body) == for_old_role),
+                )
+                .cloned()
+                .collect()
+        }
+
+        /// Passes the message to the current phase, and returns the output of phases which have started
+        fn feed(&mut self, msg: InMsg) -> Result<Vec<OutMsg>, ErrorState> {
+            if !self.is_active() {
+                return Ok(Vec::new());
+            }
+            match self.state.classify_message(&msg, &self.retained) {
+                MessageClass::Accept => self.retained.push(msg),
+                MessageClass::Defer => {
+                    self.deferred.push(msg);
+                    return Ok(Vec::new());
+                }
+                MessageClass::Reject(reason) => {
+                    log::debug!("{} rejects message: {:?}", self.state.name(), reason);
+                    return Ok(Vec::new());
+                }
+            }
+            if !self.state.is_input_complete(&self.retained) {
+                return Ok(Vec::new());
+            }
+            match self.state.consume(self.retained.drain(..).collect()) {
+                Transition::NewState(mut state) => {
+                    let mut output = state.start().unwrap_or_default();
+                    self.state = state;
+                    for msg in std::mem::take(&mut self.deferred) {
+                        output.extend(self.feed(msg)?);
+                    }
+                    Ok(output)
+                }
+                Transition::FinalState(Ok(result)) => {
+                    self.result = Some(result);
+                    Ok(Vec::new())
+                }
+                Transition::FinalState(Err(error)) => Err(error),
+            }
+        }
+
+        fn snapshot(&self) -> Option<RoleSnapshot<T::FinalState>>
+        where
+            T::FinalState: Clone,
+        {
+            Some(RoleSnapshot {
+                state: self.state.snapshot()?,
+                retained: self.retained.clone(),
+                deferred: self.deferred.clone(),
+                result: self.result.clone(),
+            })
+        }
+    }
+
+    impl<T> Role<T>
+    where
+        T: StateMachineTraits + RestoreState<Context = ()>,
+    {
+        fn restore(snapshot: RoleSnapshot<T::FinalState>) -> Result<Self, serde_json::Error> {
+            Ok(Role {
+                state: T::restore_state(snapshot.state, &())?,
+                retained: snapshot.retained,
+                deferred: snapshot.deferred,
+                result: snapshot.result,
+            })
+        }
+    }
+
+    #[derive(Default)]
+    struct Roles {
+        old: Option<Role<old_member::KeyResharingTraits>>,
+        new: Option<Role<new_member::KeyResharingTraits>>,
+    }
+
+    impl Roles {
+        /// Passes messages to their roles, and returns the output of the party to its peers
+        ///
+        /// The output of a role addressed to the party itself is passed to its other role.
+        fn deliver(
+            &mut self,
+            own_party_index: PartyIndex,
+            input: Vec<InMsg>,
+            produced: Vec<OutMsg>,
+        ) -> Result<Vec<OutMsg>, ErrorState> {
+            let mut queue = VecDeque::from(input);
+            let mut produced = produced;
+            let mut output = Vec::new();
+            loop {
+                for msg in produced.drain(..) {
+                    if msg.recipient == Address::Peer(own_party_index) {
+                        queue.push_back(InputMessage::from_output(own_party_index, msg));
+                    } else {
+                        output.push(msg);
+                    }
+                }
+                let msg = match queue.pop_front() {
+                    Some(msg) => msg,
+                    None => return Ok(output),
+                };
+                let role_output = if is_for_old_role(&msg.body) {
+                    self.old.as_mut().map(|role| role.feed(msg))
+                } else {
+                    self.new.as_mut().map(|role| role.feed(msg))
+                };
+                produced = role_output.transpose()?.unwrap_or_default();
+            }
+        }
+
+        /// The result of the party once all its roles are complete
+        fn result(&self) -> Option<FinalState> {
+            if self.old.as_ref().is_some_and(Role::is_active)
+                || self.new.as_ref().is_some_and(Role::is_active)
+            {
+                return None;
+            }
+            Some(FinalState {
+                info: self
+                    .new
+                    .as_ref()
+                    .and_then(|role| role.result.as_ref())
+                    .map(|result| result.info.clone()),
+                receipts: self.old.as_ref().and_then(|role| role.result.clone()),
+            })
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct MemberSnapshot {
+        session: Session,
+        limits: Limits,
+        own_party_index: PartyIndex,
+        peers: BTreeSet<PartyIndex>,
+        old: Option<RoleSnapshot<old_member::FinalState>>,
+        new: Option<RoleSnapshot<new_member::FinalState>>,
+        timeout: Option<Duration>,
+    }
+
+    /// The state object which runs all roles of the party
+    ///
+    /// It moves to the next state object each time any of its roles moves to the next phase, so that the timeout applies to each phase of any role.
+    pub struct Member {
+        session: Session,
+        limits: Limits,
+        own_party_index: PartyIndex,
+        /// members of both committees except the party itself
+        peers: BTreeSet<PartyIndex>,
+        // roles are taken out of the cell when the state object moves to the next one
+        roles: RefCell<Roles>,
+        output: Vec<OutMsg>,
+        timeout: Option<Duration>,
+    }
+
+    impl Member {
+        /// initializes the protocol for the holder of the key
+        ///
+        /// The party is a member of old committee, of new committee, or of both, according to lists of committees.
+        /// `range_proof_setup` is required if the party is a member of new committee.
+        #[allow(clippy::result_large_err, clippy::too_many_arguments)]
+        pub fn new(
+            session_id: SessionId,
+            multi_party_info: &MultiPartyInfo,
+            new_params: &Parameters,
+            old_committee: &[PartyIndex],
+            new_committee: &[PartyIndex],
+            range_proof_setup: Option<ZkpSetup>,
+            timeout: Option<Duration>,
+            limits: Limits,
+        ) -> Result<Self, ResharingError> {
+            let own_party_index = multi_party_info.own_party_index;
+            let in_old_committee = old_committee.contains(&own_party_index);
+            let in_new_committee = new_committee.contains(&own_party_index);
+            if !in_old_committee && !in_new_committee {
+                return Err(ResharingError::ProtocolSetupError(
+                    "own party index is in neither committee".to_string(),
+                ));
+            }
+
+            let mut roles = Roles::default();
+            if in_old_committee {
+                let phase = old_member::Phase1::new(
+                    session_id,
+                    multi_party_info,
+                    new_params,
+                    old_committee,
+                    new_committee,
+                    timeout,
+                    limits,
+                )?;
+                roles.old = Some(Role::new(Box::new(phase)));
+            }
+            if in_new_committee {
+                let phase = new_member::Phase1::new(
+                    session_id,
+                    &multi_party_info.key_params,
+                    new_params,
+                    old_committee,
+                    new_committee,
+                    own_party_index,
+                    range_proof_setup,
+                    timeout,
+                    limits,
+                )?;
+                roles.new = Some(Role::new(Box::new(phase)));
+            }
+
+            // the first phase of each role sends its output at once
+            let mut produced = Vec::new();
+            if let Some(role) = roles.old.as_mut() {
+                produced.extend(role.state.start().unwrap_or_default());
+            }
+            if let Some(role) = roles.new.as_mut() {
+                produced.extend(role.state.start().unwrap_or_default());
+            }
+            let output = roles
+                .deliver(own_party_index, Vec::new(), produced)
+                .map_err(|mut error_state| {
+                    error_state.errors.pop().unwrap_or_else(|| {
+                        ResharingError::ProtocolSetupError(
+                            "own messages are not accepted".to_string(),
+                        )
+                    })
+                })?;
+
+            Ok(Member {
+                session: Session {
+                    protocol: ProtocolId::Resharing,
+                    id: session_id,
+                },
+                own_party_index,
+                peers: old_committee
+                    .iter()
+                    .chain(new_committee.iter())
+                    .filter(|p| **p != own_party_index)
+                    .copied()
+                    .collect(),
+                roles: RefCell::new(roles),
+                output,
+                timeout,
+                limits,
+            })
+        }
+    }
+
+    #[trace(pretty, prefix = "Member::")]
+    impl State<KeyResharingTraits> for Member {
+        fn start(&mut self) -> Option<OutMsgVec> {
+            log::debug!("Member starts");
+            Some(std::mem::take(&mut self.output))
+        }
+
+        #[trace(disable(current_msg_set))]
+        fn classify_message(&self, msg: &InMsg, current_msg_set: &[InMsg]) -> MessageClass {
+            let roles = self.roles.borrow();
+            let for_old_role = is_for_old_role(&msg.body);
+            match (&roles.old, &roles.new) {
+                (Some(role), _) if for_old_role && role.is_active() => role
+                    .state
+                    .classify_message(msg, &role.input(current_msg_set, for_old_role)),
+                (_, Some(role)) if !for_old_role && role.is_active() => role
+                    .state
+                    .classify_message(msg, &role.input(current_msg_set, for_old_role)),
+                _ => msg.classify(
+                    &self.session,
+                    &self.limits,
+                    0,
+                    false,
+                    SenderRole::of_member(self.peers.contains(&msg.sender)),
+                    current_msg_set,
+                ),
+            }
+        }
+
+        #[trace(disable(current_msg_set))]
+        fn is_input_complete(&self, current_msg_set: &[InMsg]) -> bool {
+            let roles = self.roles.borrow();
+            roles.old.as_ref().is_some_and(|role| {
+                role.is_active()
+                    && role
+                        .state
+                        .is_input_complete(&role.input(current_msg_set, true))
+            }) || roles.new.as_ref().is_some_and(|role| {
+                role.is_active()
+                    && role
+                        .state
+                        .is_input_complete(&role.input(current_msg_set, false))
+            })
+        }
+
+        #[trace(disable(current_msg_set))]
+        fn awaited_senders(&self, current_msg_set: &[InMsg]) -> Vec<PartyIndex> {
+            let roles = self.roles.borrow();
+            let mut awaited = BTreeSet::new();
+            if let Some(role) = roles.old.as_ref().filter(|role| role.is_active()) {
+                awaited.extend(
+                    role.state
+                        .awaited_senders(&role.input(current_msg_set, true)),
+                );
+            }
+            if let Some(role) = roles.new.as_ref().filter(|role| role.is_active()) {
+                awaited.extend(
+                    role.state
+                        .awaited_senders(&role.input(current_msg_set, false)),
+                );
+            }
+            awaited.remove(&self.own_party_index);
+            awaited.into_iter().collect()
+        }
+
+        fn consume(&self, current_msg_set: Vec<InMsg>) -> Transition<KeyResharingTraits> {
+            let mut roles = self.roles.replace(Roles::default());
+            let output = match roles.deliver(self.own_party_index, current_msg_set, Vec::new()) {
+                Ok(output) => output,
+                Err(error_state) => {
+                    log::error!("Member returns {:?}", error_state);
+                    return Transition::FinalState(Err(error_state));
+                }
+            };
+            match roles.result() {
+                Some(result) => {
+                    log::info!("Member succeeded");
+                    Transition::FinalState(Ok(result))
+                }
+                None => Transition::NewState(Box::new(Member {
+                    session: self.session,
+                    limits: self.limits,
+                    own_party_index: self.own_party_index,
+                    peers: self.peers.clone(),
+                    roles: RefCell::new(roles),
+                    output,
+                    timeout: self.timeout,
+                })),
+            }
+        }
+
+        fn timeout(&self) -> Option<Duration> {
+            self.timeout
+        }
+
+        fn timeout_outcome(&self, current_msg_set: Vec<InMsg>) -> MachineResult {
+            // the outcome of each active role stands for its result, as the role of old member may succeed on timeout
+            let roles = self.roles.borrow();
+            let receipts = match roles.old.as_ref() {
+                Some(role) if role.is_active() => Some(
+                    role.state
+                        .timeout_outcome(role.input(&current_msg_set, true))?,
+                ),
+                Some(role) => role.result.clone(),
+                None => None,
+            };
+            let info = match roles.new.as_ref() {
+                Some(role) if role.is_active() => Some(
+                    role.state
+                        .timeout_outcome(role.input(&current_msg_set, false))?
+                        .info,
+                ),
+                Some(role) => role.result.as_ref().map(|result| result.info.clone()),
+                None => None,
+            };
+            Ok(FinalState { info, receipts })
+        }
+
+        #[trace(disable(res))]
+        fn snapshot(&self) -> Option<serde_json::Value> {
+            let roles = self.roles.borrow();
+            let snapshot = MemberSnapshot {
+                session: self.session,
+                limits: self.limits,
+                own_party_index: self.own_party_index,
+                peers: self.peers.clone(),
+                old: match &roles.old {
+                    Some(role) => Some(role.snapshot()?),
+                    None => None,
+                },
+                new: match &roles.new {
+                    Some(role) => Some(role.snapshot()?),
+                    None => None,
+                },
+                timeout: self.timeout,
+            };
+            phase_snapshot("Member", &snapshot)
+        }
+
+        fn name(&self) -> &'static str {
+            "resharing.member"
+        }
+
+        fn abort_output(&self, error: &ErrorState) -> Option<Vec<OutMsg>> {
+            super::abort_notices(self.session, error, self.peers.iter())
+        }
+
+        fn peer_aborted(&self, msg: &InMsg) -> Option<ErrorState> {
+            super::aborted_by_peer(
+                &self.session,
+                &self.limits,
+                msg,
+                self.peers.contains(&msg.sender),
+            )
+        }
+
+        #[trace(disable(current_msg_set))]
+        fn resend_request(&self, current_msg_set: &[InMsg]) -> Option<Vec<OutMsg>> {
+            let roles = self.roles.borrow();
+            let mut requests = Vec::new();
+            if let Some(role) = roles.old.as_ref().filter(|role| role.is_active()) {
+                requests.extend(
+                    role.state
+                        .resend_request(&role.input(current_msg_set, true))
+                        .unwrap_or_default(),
+                );
+            }
+            if let Some(role) = roles.new.as_ref().filter(|role| role.is_active()) {
+                requests.extend(
+                    role.state
+                        .resend_request(&role.input(current_msg_set, false))
+                        .unwrap_or_default(),
+                );
+            }
+            Some(
+                requests
+                    .into_iter()
+                    .filter(|m| m.recipient != Address::Peer(self.own_party_index))
+                    .collect(),
+            )
+        }
+
+        fn resend_output(&self, msg: &InMsg, sent: &[OutMsg]) -> Option<Resent<OutMsg>> {
+            super::resent_output(
+                &self.session,
+                &self.limits,
+                msg,
+                self.peers.contains(&msg.sender),
+                sent,
+            )
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::keygen::MultiPartyInfo;
+    use crate::limits::Limits;
+    use crate::messages::resharing::{Message, SignedAck};
     use crate::messages::SecretShare;
-    use crate::protocol::{Address, InputMessage, PartyIndex};
+    use crate::protocol::{Address, InputMessage, PartyIndex, ProtocolId, Session, SessionId};
+    use crate::resharing::member::KeyResharingTraits as MemberKeyResharingTraits;
     use crate::resharing::new_member::KeyResharingTraits;
     use crate::resharing::old_member::KeyResharingTraits as OldKeyResharingTraits;
-    use crate::resharing::{InMsg, OutMsg};
+    use crate::resharing::{ErrorState, InMsg, OutMsg, ResharingError};
+    use crate::simulator::{
+        check_resharing, NetworkConditions, NodeOutcome, ResharingResult, Simulation,
+    };
     use crate::types::Parameters;
     use crate::types::VerifiableSS;
     use crate::types::{BigInt, FE, GE};
     use crate::zk_range_proofs::ZkpSetup;
     use anyhow::bail;
     use crossbeam_channel::{Receiver, Sender};
+    use state_machine::driver::Driver;
     use state_machine::sync_channels::StateMachine;
+    use state_machine::types::{State, Transition};
 
     use std::path::Path;
     use std::{fs, thread};
@@ -1046,16 +2459,28 @@ mod tests {
     #[test]
     fn resharing() -> anyhow::Result<()> {
         let _ = env_logger::builder().is_test(true).try_init();
-        sharing_helper(false)
+        sharing_helper()
     }
 
     #[test]
-    fn resharing_with_range_proofs() -> anyhow::Result<()> {
-        let _ = env_logger::builder().is_test(true).try_init();
-        sharing_helper(true)
+    fn new_member_requires_range_proof_setup() -> anyhow::Result<()> {
+        let committee: Vec<PartyIndex> = vec![0.into(), 1.into(), 2.into()];
+        let phase = super::new_member::Phase1::new(
+            SessionId::random(),
+            &Parameters::new(2, 3)?,
+            &Parameters::new(2, 3)?,
+            &committee,
+            &committee,
+            0.into(),
+            None,
+            None,
+            Limits::default(),
+        );
+        assert!(matches!(phase, Err(ResharingError::ProtocolSetupError(_))));
+        Ok(())
     }
 
-    pub fn sharing_helper(use_range_proofs: bool) -> anyhow::Result<()> {
+    pub fn sharing_helper() -> anyhow::Result<()> {
         let _ = env_logger::builder().is_test(true).try_init();
         let old_params = Parameters::new(2, 3)?;
         let new_params = Parameters::new(2, 4)?;
@@ -1068,6 +2493,7 @@ mod tests {
 
         let mut old_handles = Vec::new();
         let mut new_handles = Vec::new();
+        let session_id = SessionId::random();
 
         // start new committee first
         for i in new_committee.clone() {
@@ -1083,19 +2509,14 @@ mod tests {
                 .map(|i| (*i).into())
                 .collect::<Vec<PartyIndex>>();
 
-            let range_proof_setup = if use_range_proofs {
-                // the setup from the bank of pre-generated ones
-                let path = Path::new("../tests/data/rp-setups.json");
-                let zkp_setups: Vec<ZkpSetup> = serde_json::from_str(&fs::read_to_string(path)?)?;
-                Some(zkp_setups[i].clone())
-            } else {
-                None
-            };
-
-            assert!(!use_range_proofs || range_proof_setup.is_some());
+            // the setup from the bank of pre-generated ones
+            let path = Path::new("../tests/data/rp-setups.json");
+            let zkp_setups: Vec<ZkpSetup> = serde_json::from_str(&fs::read_to_string(path)?)?;
+            let range_proof_setup = Some(zkp_setups[i].clone());
 
             let join_handle = thread::spawn(move || {
                 let start_state = Box::new(super::new_member::Phase1::new(
+                    session_id,
                     &old_params,
                     &new_params,
                     &oc_clone,
@@ -1103,21 +2524,22 @@ mod tests {
                     i.into(),
                     range_proof_setup,
                     None,
+                    Limits::default(),
                 )?);
 
                 let mut new_member_machine =
                     StateMachine::<KeyResharingTraits>::new(start_state, &rx, &tx);
 
                 match new_member_machine.execute() {
-                    Some(Ok(fs)) => {
+                    Ok(Ok(fs)) => {
                         log::trace!("new_{} success", i);
                         Ok(fs)
                     }
-                    Some(Err(e)) => {
+                    Ok(Err(e)) => {
                         bail!("new_{} error {:?}", i, e);
                     }
-                    None => {
-                        bail!("new_{} error in the machine", i);
+                    Err(aborted) => {
+                        bail!("new_{} machine aborted {:?}", i, aborted);
                     }
                 }
             });
@@ -1136,16 +2558,12 @@ mod tests {
             let (tx, egress) = crossbeam_channel::unbounded();
 
             log::info!("starting party old_{}", i);
-            let path = if use_range_proofs {
-                format!("../tests/data/zkrp-keys.{}.json", i)
-            } else {
-                format!("../tests/data/keys.{}.json", i)
-            };
+            let path = format!("../tests/data/zkrp-keys.{}.json", i);
             let path = Path::new(&path);
             let multi_party_shared_info: MultiPartyInfo =
                 serde_json::from_str(&fs::read_to_string(path)?)?;
 
-            assert!(!use_range_proofs || multi_party_shared_info.range_proof_setups.is_some());
+            assert!(multi_party_shared_info.range_proof_setups.is_some());
 
             let oc_clone = old_committee
                 .iter()
@@ -1159,25 +2577,27 @@ mod tests {
 
             let join_handle = thread::spawn(move || {
                 let start_state = Box::new(super::old_member::Phase1::new(
+                    session_id,
                     &multi_party_shared_info,
                     &new_params,
                     &oc_clone,
                     &nc_clone,
                     None,
+                    Limits::default(),
                 )?);
 
                 let mut old_member_machine =
                     StateMachine::<OldKeyResharingTraits>::new(start_state, &rx, &tx);
                 match old_member_machine.execute() {
-                    Some(Ok(fs)) => {
+                    Ok(Ok(fs)) => {
                         log::trace!("old_{} success", i);
                         Ok(fs)
                     }
-                    Some(Err(e)) => {
+                    Ok(Err(e)) => {
                         bail!("old_{} error {:?}", i, e);
                     }
-                    None => {
-                        bail!("old_{} error in the machine", i);
+                    Err(aborted) => {
+                        bail!("old_{} machine aborted {:?}", i, aborted);
                     }
                 }
             });
@@ -1235,10 +2655,10 @@ mod tests {
                         Address::Peer(peer) => {
                             if let Some(node) = old_nodes.iter().find(|node| (*node).party == *peer)
                             {
-                                match node.ingress.send(InputMessage {
-                                    sender: mm.source,
-                                    body: mm.msg.body.clone(),
-                                }) {
+                                match node
+                                    .ingress
+                                    .send(InputMessage::from_output(mm.source, mm.msg.clone()))
+                                {
                                     Ok(()) => {}
                                     Err(_) => log::warn!(
                                         "error sending msg to old node {}, msg: {}",
@@ -1259,10 +2679,10 @@ mod tests {
                         Address::Peer(peer) => {
                             if let Some(node) = new_nodes.iter().find(|node| (*node).party == *peer)
                             {
-                                match node.ingress.send(InputMessage {
-                                    sender: mm.source,
-                                    body: mm.msg.body.clone(),
-                                }) {
+                                match node
+                                    .ingress
+                                    .send(InputMessage::from_output(mm.source, mm.msg.clone()))
+                                {
                                     Ok(()) => {}
                                     Err(_) => log::warn!(
                                         "error sending msg to new node {}, msg: {}",
@@ -1314,6 +2734,12 @@ mod tests {
             assert!(false, "Some state machines returned error");
         }
 
+        // each member of old committee keeps receipts of the whole new committee
+        for result in old_committee_result {
+            let final_state = result.unwrap().unwrap();
+            assert_eq!(final_state.receipts.len(), new_committee.len());
+        }
+
         let new_final_states = new_committee_result
             .into_iter()
             .map(|x| x.unwrap().unwrap())
@@ -1342,6 +2768,289 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn resharing_with_combined_roles() -> anyhow::Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let new_params = Parameters::new(2, 3)?;
+        let old_committee: Vec<PartyIndex> = vec![0.into(), 1.into(), 2.into()];
+        let new_committee: Vec<PartyIndex> = vec![1.into(), 2.into(), 3.into()];
+
+        let path = Path::new("../tests/data/rp-setups.json");
+        let zkp_setups: Vec<ZkpSetup> = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let infos = (0..=2)
+            .map(|i| {
+                let path = format!("../tests/data/zkrp-keys.{}.json", i);
+                Ok(serde_json::from_str::<MultiPartyInfo>(
+                    &fs::read_to_string(Path::new(&path))?,
+                )?)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let public_key = infos[0].public_key.clone();
+
+        let session_id = SessionId::random();
+        let mut simulation = Simulation::<_, ResharingResult>::new(NetworkConditions::default())?;
+        for (i, info) in infos.iter().enumerate() {
+            let phase = super::member::Member::new(
+                session_id,
+                info,
+                &new_params,
+                &old_committee,
+                &new_committee,
+                Some(zkp_setups[i].clone()),
+                None,
+                Limits::default(),
+            )?;
+            simulation.add_party(
+                PartyIndex::from(i),
+                Driver::<MemberKeyResharingTraits>::new(Box::new(phase)),
+            );
+        }
+        let phase = super::new_member::Phase1::new(
+            session_id,
+            &infos[0].key_params,
+            &new_params,
+            &old_committee,
+            &new_committee,
+            3.into(),
+            Some(zkp_setups[3].clone()),
+            None,
+            Limits::default(),
+        )?;
+        simulation.add_party(
+            PartyIndex::from(3),
+            Driver::<KeyResharingTraits>::new(Box::new(phase)),
+        );
+        let report = simulation.run();
+        check_resharing(&report, &public_key).map_err(|e| anyhow::anyhow!("{:?}", e))?;
+
+        let mut secret_shares = Vec::new();
+        for (party, outcome) in report.outcomes {
+            match outcome {
+                NodeOutcome::Finished(ResharingResult::Member(Ok(f))) => {
+                    let receipts = f.receipts.expect("all members hold old shares");
+                    assert_eq!(receipts.public_key, public_key);
+                    assert_eq!(receipts.receipts.len(), new_committee.len());
+                    match f.info {
+                        Some(info) => secret_shares.push(info.secret_share),
+                        None => assert_eq!(party, PartyIndex::from(0)),
+                    }
+                }
+                NodeOutcome::Finished(ResharingResult::New(Ok(f))) => {
+                    secret_shares.push(f.info.secret_share)
+                }
+                other => bail!("party {} did not finish: {:?}", party, other),
+            }
+        }
+        assert_eq!(secret_shares.len(), 3);
+        assert_eq!(GE::generator() * &reconstruct(&secret_shares), public_key);
+        Ok(())
+    }
+
+    #[test]
+    fn old_member_gets_no_receipts_for_unverified_factor_proofs() -> anyhow::Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let new_params = Parameters::new(2, 3)?;
+        let old_committee: Vec<PartyIndex> = vec![0.into(), 1.into(), 2.into()];
+        let new_committee: Vec<PartyIndex> = vec![3.into(), 4.into(), 5.into()];
+
+        let path = Path::new("../tests/data/rp-setups.json");
+        let zkp_setups: Vec<ZkpSetup> = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let infos = (0..=2)
+            .map(|i| {
+                let path = format!("../tests/data/zkrp-keys.{}.json", i);
+                Ok(serde_json::from_str::<MultiPartyInfo>(
+                    &fs::read_to_string(Path::new(&path))?,
+                )?)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let session_id = SessionId::random();
+        let mut simulation = Simulation::<_, ResharingResult>::new(NetworkConditions::default())?;
+        for info in &infos {
+            let phase = super::old_member::Phase1::new(
+                session_id,
+                info,
+                &new_params,
+                &old_committee,
+                &new_committee,
+                None,
+                Limits::default(),
+            )?;
+            simulation.add_filtered_party(
+                info.own_party_index,
+                Driver::<OldKeyResharingTraits>::new(Box::new(phase)),
+                super::old_member::is_message_to_committee,
+            );
+        }
+        for (party, setup) in new_committee.iter().zip(&zkp_setups[3..]) {
+            let phase = super::new_member::Phase1::new(
+                session_id,
+                &infos[0].key_params,
+                &new_params,
+                &old_committee,
+                &new_committee,
+                *party,
+                Some(setup.clone()),
+                None,
+                Limits::default(),
+            )?;
+            simulation.add_filtered_party(
+                *party,
+                Driver::<KeyResharingTraits>::new(Box::new(phase)),
+                super::new_member::is_message_to_committee,
+            );
+        }
+        // the last member of new committee proves its moduli with each other's proofs
+        simulation.tamper(5.into(), |_, msg| {
+            if let Message::R4(msg) = msg {
+                let proofs = &mut msg.factor_proofs;
+                std::mem::swap(&mut proofs.paillier, &mut proofs.n_tilde);
+            }
+        });
+        let report = simulation.run();
+
+        for (party, outcome) in report.outcomes {
+            match outcome {
+                NodeOutcome::Finished(ResharingResult::Old(Ok(f))) => {
+                    bail!("party {} keeps receipts {:?}", party, f.receipts.keys())
+                }
+                NodeOutcome::Finished(ResharingResult::New(Err(e)))
+                    if party != PartyIndex::from(5) =>
+                {
+                    assert!(e.errors.iter().any(|e| matches!(
+                        e,
+                        ResharingError::InvalidFactorProof { party, .. } if *party == PartyIndex::from(5)
+                    )))
+                }
+                NodeOutcome::Finished(ResharingResult::New(Ok(_))) => {
+                    bail!("party {} accepts invalid proofs", party)
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn member_timeout_keeps_roles() -> anyhow::Result<()> {
+        let path = Path::new("../tests/data/rp-setups.json");
+        let zkp_setups: Vec<ZkpSetup> = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let path = Path::new("../tests/data/zkrp-keys.1.json");
+        let info = serde_json::from_str::<MultiPartyInfo>(&fs::read_to_string(path)?)?;
+        let old_committee: Vec<PartyIndex> = vec![0.into(), 1.into(), 2.into()];
+        let new_committee: Vec<PartyIndex> = vec![1.into(), 2.into(), 3.into()];
+        let member = super::member::Member::new(
+            SessionId::random(),
+            &info,
+            &Parameters::new(2, 3)?,
+            &old_committee,
+            &new_committee,
+            Some(zkp_setups[1].clone()),
+            None,
+            Limits::default(),
+        )?;
+
+        let snapshot = member.snapshot();
+        assert!(snapshot.is_some());
+        assert!(member.timeout_outcome(Vec::new()).is_err());
+        assert!(member.timeout_outcome(Vec::new()).is_err());
+        assert_eq!(member.snapshot(), snapshot);
+        Ok(())
+    }
+
+    #[test]
+    fn old_member_requires_threshold_of_acks() -> anyhow::Result<()> {
+        let infos = (0..=2)
+            .map(|i| {
+                let path = format!("../tests/data/keys.{}.json", i);
+                Ok(serde_json::from_str::<MultiPartyInfo>(
+                    &fs::read_to_string(Path::new(&path))?,
+                )?)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let secret = reconstruct(
+            &infos
+                .iter()
+                .map(|info| info.secret_share.clone())
+                .collect::<Vec<_>>(),
+        );
+        let public_key = infos[0].public_key.clone();
+
+        let new_params = Parameters::new(2, 3)?;
+        let old_committee: Vec<PartyIndex> = vec![0.into(), 1.into(), 2.into()];
+        let new_committee: Vec<PartyIndex> = vec![3.into(), 4.into(), 5.into()];
+        let session_id = SessionId::random();
+        let session = Session {
+            protocol: ProtocolId::Resharing,
+            id: session_id,
+        };
+        let mut phase1 = super::old_member::Phase1::new(
+            session_id,
+            &infos[0],
+            &new_params,
+            &old_committee,
+            &new_committee,
+            None,
+            Limits::default(),
+        )?;
+        phase1.start();
+        let mut phase2 = match phase1.consume(Vec::new()) {
+            Transition::NewState(phase) => phase,
+            Transition::FinalState(_) => bail!("old member finished early"),
+        };
+        phase2.start();
+
+        // new shares of the same key, at points of the new committee
+        let (_, shares) = VerifiableSS::share(1, 3, &secret);
+        let ack = |party: usize, ack: SignedAck| {
+            InputMessage::from_output(
+                party.into(),
+                OutMsg::new(session, Address::Peer(0.into()), Message::FinalAck(ack)),
+            )
+        };
+        let valid = |party: usize| {
+            ack(
+                party,
+                SignedAck::new(&session, &public_key, &shares[party - 3]),
+            )
+        };
+
+        // a single acknowledgement is not enough for threshold 1
+        assert!(matches!(
+            phase2.timeout_outcome(vec![valid(3)]),
+            Err(ErrorState { errors }) if matches!(errors[..], [ResharingError::Timeout { .. }])
+        ));
+
+        let final_state = phase2
+            .timeout_outcome(vec![valid(3), valid(5)])
+            .map_err(|e| anyhow::anyhow!("{:?}", e))?;
+        assert_eq!(final_state.public_key, public_key);
+        assert_eq!(
+            final_state.receipts.keys().cloned().collect::<Vec<_>>(),
+            vec![PartyIndex::from(3), PartyIndex::from(5)]
+        );
+        assert!(final_state
+            .receipts
+            .values()
+            .all(|ack| ack.verify(&session, &public_key).is_ok()));
+
+        // the share of another key is signed correctly, but it does not belong to the public key
+        let other = ack(4, SignedAck::new(&session, &public_key, &FE::random()));
+        assert!(matches!(
+            phase2.timeout_outcome(vec![valid(3), other]),
+            Err(ErrorState { errors }) if matches!(errors[..], [ResharingError::InconsistentAcks])
+        ));
+
+        // the acknowledgement of another public key
+        let other = ack(4, SignedAck::new(&session, &GE::generator(), &shares[1]));
+        assert!(matches!(
+            phase2.consume(vec![valid(3), other, valid(5)]),
+            Transition::FinalState(Err(ErrorState { errors }))
+                if matches!(errors[..], [ResharingError::InvalidAck { .. }])
+        ));
+        Ok(())
+    }
+
     pub fn reconstruct(secret_shares: &[SecretShare]) -> FE {
         let (points, shares): (Vec<FE>, Vec<FE>) = secret_shares
             .iter()
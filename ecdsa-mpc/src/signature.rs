@@ -38,7 +38,7 @@
 //!
 //!   // to do: determine which parties(nodes) will participate in threshold signing
 //!   // and fill the vector `signing_parties` with their PartyIndexes
-//!   let start_phase = Box::new(Phase1::new(&message, key, signing_parties));
+//!   let start_phase = Box::new(Phase1::new(session_id, &message, key, signing_parties));
 //!
 //!   let state_machine = StateMachine::new(start_phase, protocol_stream, state_machine_sink);
 //!   // to do : share protocol_stream and state_machine_sink with a network layer
@@ -65,12 +65,15 @@ use super::messages::signing::{
     SignBroadcastPhase1, SignDecommitPhase4,
 };
 use super::signature::phase5::LocalSignature;
-use crate::protocol::{Address, PartyIndex};
-use crate::types::{
-    CommitmentScheme, CurvDLogProofType, MessageHashType, Scalar, SigningParameters,
+use crate::limits::Limits;
+use crate::proofs::DLogProof;
+use crate::protocol::{
+    abort_broadcast, aborted_by_peer, resend_requests, resent_output, AbortReport, Address,
+    PartyIndex, ProtocolId, SenderRole, Session, SessionId,
 };
-use crate::types::{DigestExt, HomoELGamalProof, HomoElGamalStatement, HomoElGamalWitness};
-use crate::utils::is_valid_curve_point;
+use crate::types::{CommitmentScheme, MessageHashType, Scalar, SigningParameters};
+use crate::types::{DigestExt, HomoElGamalStatement, HomoElGamalWitness};
+use crate::utils::{is_valid_curve_point, phase_snapshot};
 use algorithms::types::PaillierKeys;
 
 use sha2::{Digest, Sha256};
@@ -83,7 +86,14 @@ use thiserror::Error;
 use std::collections::{BTreeSet, HashMap};
 
 pub use super::messages::signing::{InMsg, Message, OutMsg};
-use state_machine::types::{State, StateMachineTraits, Transition};
+use crate::encryption::{RefuseReason, RefusedMessage, ReportRefused};
+use crate::identity::{UnverifiedMessage, UnverifiedReason};
+use crate::messages::Abort;
+use state_machine::checkpoint::RestoreState;
+use state_machine::types::{
+    BoxedState, MessageClass, RejectReason, RejectedMessage, Resent, State, StateMachineTraits,
+    Transition,
+};
 use std::time::Duration;
 
 use crate::keygen::RangeProofSetups;
@@ -132,10 +142,7 @@ pub enum SigningError {
     MissingPhase1Commitment(PartyIndex),
 
     #[error("Dlog proof failed party {party:?} proofs {proof:?}")]
-    DlogProofFailed {
-        party: PartyIndex,
-        proof: CurvDLogProofType,
-    },
+    DlogProofFailed { party: PartyIndex, proof: DLogProof },
     #[error("invalid decommitment at phase 4 , party {party:?}")]
     InvalidDecommitment { party: PartyIndex },
     #[error("invalid ElGamal proof at phase 5b , party {party:?}")]
@@ -146,12 +153,53 @@ pub enum SigningError {
     SignatureVerificationFailed,
     #[error("protocol setup error: {0}")]
     ProtocolSetupError(String),
+    #[error("message rejected: {reason:?}, party {party}")]
+    RejectedMessage {
+        party: PartyIndex,
+        reason: RejectReason,
+    },
+    #[error("message refused by secure channel: {reason:?}, party {party}")]
+    RefusedMessage {
+        party: PartyIndex,
+        reason: RefuseReason,
+    },
+    #[error("message failed identity verification: {reason:?}, party {party}")]
+    UnverifiedMessage {
+        party: PartyIndex,
+        reason: UnverifiedReason,
+    },
     #[error("invalid public key {point}")]
     InvalidPublicKey { point: String },
+    #[error("party {party} aborted the protocol: {reason}")]
+    PeerAborted {
+        party: PartyIndex,
+        reason: String,
+        blamed: Vec<PartyIndex>,
+    },
     #[error("{0}")]
     GeneralError(String),
 }
 
+impl SigningError {
+    /// The party which the error is attributed to, if any
+    pub fn blamed_party(&self) -> Option<PartyIndex> {
+        match self {
+            SigningError::UnexpectedMessageType { party, .. }
+            | SigningError::AliceRangeProofIncomplete { party, .. }
+            | SigningError::AliceProofFailed { party, .. }
+            | SigningError::BobProofFailed { party, .. }
+            | SigningError::MissingPhase1Commitment(party)
+            | SigningError::DlogProofFailed { party, .. }
+            | SigningError::InvalidDecommitment { party }
+            | SigningError::InvalidElGamalProof { party }
+            | SigningError::RejectedMessage { party, .. }
+            | SigningError::RefusedMessage { party, .. }
+            | SigningError::UnverifiedMessage { party, .. } => Some(*party),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum ECDSAError {
     #[error("{desc}")]
@@ -165,15 +213,16 @@ mod mta {
         trace, AliceProof, BigInt, BobProofType, Decrypt, EncryptionKey, Paillier, PartyIndex,
         RawCiphertext, SigningError,
     };
-    use crate::types::CurvDLogProofType;
+    use crate::protocol::Session;
     use crate::zk_range_proofs::BobProofType::{RangeProof, RangeProofExt};
     use crate::zk_range_proofs::{MessageA, ZkpSetup};
     use algorithms::types::PaillierKeys;
 
     use super::{FE, GE};
+    use serde::{Deserialize, Serialize};
     use std::collections::HashMap;
 
-    #[derive(Debug, Clone)]
+    #[derive(Debug, Clone, Serialize, Deserialize)]
     pub enum MtaAliceOutput {
         Simple(MessageA),
         WithRangeProofs(HashMap<PartyIndex, MessageA>),
@@ -181,6 +230,7 @@ mod mta {
     /// Verifies `AliceProof`
     #[trace(pretty)]
     pub(crate) fn verify_alice_range_proof(
+        session: &Session,
         cipher: &BigInt,
         party: &PartyIndex,
         bob_setup: Option<&ZkpSetup>,
@@ -194,7 +244,7 @@ mod mta {
 
         match (proof, alice_ek) {
             (Some(proof), Some(enc_key)) => {
-                if proof.verify(cipher, enc_key, bob_setup) {
+                if proof.verify(session, cipher, enc_key, bob_setup) {
                     Ok(())
                 } else {
                     Err(SigningError::AliceProofFailed {
@@ -216,8 +266,10 @@ mod mta {
     ///
     /// Supports three choices of the proof:   `DLogProofs`, `BobProof`, and `BobProofExt`.
     /// Need the initial value of $` a `$ provided by Alice at the start of MtA
+    #[allow(clippy::too_many_arguments)]
     #[trace(pretty)]
     pub(crate) fn verify_bob_range_proof(
+        session: &Session,
         party: &PartyIndex,
         proof: &BobProofType,
         mta_output: &BigInt,
@@ -243,13 +295,13 @@ mod mta {
                 let g = GE::generator();
                 let g_alpha = g * &alpha;
                 let ba_btag = &dlog_proofs.b_proof.pk * a + &dlog_proofs.beta_tag_proof.pk;
-                if CurvDLogProofType::verify(&dlog_proofs.b_proof).is_err() {
+                if dlog_proofs.b_proof.verify(session).is_err() {
                     errors.push(SigningError::DlogProofFailed {
                         party: *party,
                         proof: dlog_proofs.b_proof.clone(),
                     });
                 }
-                if CurvDLogProofType::verify(&dlog_proofs.beta_tag_proof).is_err() {
+                if dlog_proofs.beta_tag_proof.verify(session).is_err() {
                     errors.push(SigningError::DlogProofFailed {
                         party: *party,
                         proof: dlog_proofs.beta_tag_proof.clone(),
@@ -264,7 +316,13 @@ mod mta {
             }
             // Bob's range proof
             RangeProof(range_proof) => {
-                if !range_proof.verify(a_enc, mta_output, &alice_keys.ek, alice_setup.unwrap()) {
+                if !range_proof.verify(
+                    session,
+                    a_enc,
+                    mta_output,
+                    &alice_keys.ek,
+                    alice_setup.unwrap(),
+                ) {
                     errors.push(SigningError::BobProofFailed {
                         party: *party,
                         proof: proof.clone(),
@@ -273,7 +331,13 @@ mod mta {
             }
             // Bob's range proof with proof of knowing b and beta_prim
             RangeProofExt(range_proof) => {
-                if !range_proof.verify(a_enc, mta_output, &alice_keys.ek, alice_setup.unwrap()) {
+                if !range_proof.verify(
+                    session,
+                    a_enc,
+                    mta_output,
+                    &alice_keys.ek,
+                    alice_setup.unwrap(),
+                ) {
                     errors.push(SigningError::BobProofFailed {
                         party: *party,
                         proof: proof.clone(),
@@ -293,8 +357,10 @@ mod mta {
 mod phase5 {
 
     use super::{trace, CommitmentScheme, ECDSAError, MessageHashType, Sha256, FE, GE};
-    use super::{HomoELGamalProof, HomoElGamalStatement, HomoElGamalWitness};
+    use super::{HomoElGamalStatement, HomoElGamalWitness};
     use crate::messages::signing::{Phase5Com1, Phase5Com2, Phase5Decom1, Phase5Decom2};
+    use crate::proofs::HomoElGamalProof;
+    use crate::protocol::Session;
     use crate::signature::ECDSAError::VerificationFailed;
     use crate::types::{DigestExt, Integer, Point, Scalar, Signature};
     use serde::{Deserialize, Serialize};
@@ -329,7 +395,7 @@ mod phase5 {
         }
 
         /// generates (Comm,Decomm) for $` V_{i} , \space A_{i} `$
-        pub fn phase5b_proof(&self) -> (Phase5Com1, Phase5Decom1) {
+        pub fn phase5b_proof(&self, session: &Session) -> (Phase5Com1, Phase5Decom1) {
             let g = Point::generator();
             let A_i = g * &self.rho_i;
             let l_i_rho_i = &self.l_i * &self.rho_i;
@@ -338,7 +404,7 @@ mod phase5 {
             let input_hash = Sha256::new()
                 .chain_points([&V_i, &A_i, &B_i])
                 .result_bigint();
-            let commitment_scheme = CommitmentScheme::from_BigInt(&input_hash);
+            let commitment_scheme = CommitmentScheme::from_BigInt(session, &input_hash);
 
             let witness = HomoElGamalWitness {
                 r: self.l_i.clone(),
@@ -351,7 +417,7 @@ mod phase5 {
                 D: V_i.clone(),
                 E: B_i.clone(),
             };
-            let proof = HomoELGamalProof::prove(&witness, &delta);
+            let proof = HomoElGamalProof::prove(session, &witness, &delta);
             (
                 Phase5Com1 {
                     com: commitment_scheme.comm,
@@ -367,11 +433,11 @@ mod phase5 {
         }
 
         /// generates (Comm, Decomm) for $` U_{i}, \space T_{i} `$
-        pub fn phase5d_proof(&self, v: GE, a: GE) -> (Phase5Com2, Phase5Decom2) {
+        pub fn phase5d_proof(&self, session: &Session, v: GE, a: GE) -> (Phase5Com2, Phase5Decom2) {
             let u_i = v * &self.rho_i;
             let t_i = a * &self.l_i;
             let input_hash = Sha256::new().chain_points([&u_i, &t_i]).result_bigint();
-            let scheme = CommitmentScheme::from_BigInt(&input_hash);
+            let scheme = CommitmentScheme::from_BigInt(session, &input_hash);
             (
                 Phase5Com2 { com: scheme.comm },
                 Phase5Decom2 {
@@ -417,6 +483,38 @@ impl StateMachineTraits for SigningTraits {
 
 pub type MachineResult = Result<SignedMessage, ErrorState>;
 
+/// Phases of the protocol restored from snapshots
+#[derive(Deserialize)]
+#[allow(clippy::large_enum_variant)]
+enum PhaseSnapshot {
+    Phase1(Phase1),
+    Phase2a(Phase2a),
+    Phase2b(Phase2b),
+    Phase3(Phase3),
+    Phase4(Phase4),
+    Phase5ab(Phase5ab),
+    Phase5cde(Phase5cde),
+}
+
+impl RestoreState for SigningTraits {
+    type Context = ();
+
+    fn restore_state(
+        snapshot: serde_json::Value,
+        _context: &(),
+    ) -> Result<BoxedState<Self>, serde_json::Error> {
+        Ok(match serde_json::from_value(snapshot)? {
+            PhaseSnapshot::Phase1(phase) => Box::new(phase),
+            PhaseSnapshot::Phase2a(phase) => Box::new(phase),
+            PhaseSnapshot::Phase2b(phase) => Box::new(phase),
+            PhaseSnapshot::Phase3(phase) => Box::new(phase),
+            PhaseSnapshot::Phase4(phase) => Box::new(phase),
+            PhaseSnapshot::Phase5ab(phase) => Box::new(phase),
+            PhaseSnapshot::Phase5cde(phase) => Box::new(phase),
+        })
+    }
+}
+
 /// Signature in (r,s)  format, and the hash of the signed message
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SignedMessage {
@@ -436,6 +534,53 @@ impl ErrorState {
     pub fn new(errors: Vec<SigningError>) -> Self {
         ErrorState { errors }
     }
+
+    /// Adds senders of messages rejected by the state machine, once per reason, as they behave suspiciously
+    pub fn report_rejected(&mut self, rejected: &HashMap<RejectedMessage<PartyIndex>, usize>) {
+        self.errors
+            .extend(rejected.keys().map(|r| SigningError::RejectedMessage {
+                party: r.sender,
+                reason: r.reason,
+            }));
+    }
+
+    /// Adds senders of messages which failed the verification of their identity, once per reason, as they behave suspiciously
+    pub fn report_unverified(&mut self, unverified: &HashMap<UnverifiedMessage, usize>) {
+        self.errors
+            .extend(unverified.keys().map(|r| SigningError::UnverifiedMessage {
+                party: r.sender,
+                reason: r.reason,
+            }));
+    }
+}
+
+impl ReportRefused for ErrorState {
+    fn report_refused(&mut self, refused: &HashMap<RefusedMessage, usize>) {
+        self.errors
+            .extend(refused.keys().map(|r| SigningError::RefusedMessage {
+                party: r.sender,
+                reason: r.reason,
+            }));
+    }
+}
+
+impl AbortReport for ErrorState {
+    fn abort_notice(&self) -> Abort {
+        let blamed = self
+            .errors
+            .iter()
+            .filter_map(SigningError::blamed_party)
+            .collect::<BTreeSet<_>>();
+        Abort::new(&self.errors, blamed.into_iter().collect())
+    }
+
+    fn aborted_by(party: PartyIndex, notice: &Abort) -> Self {
+        ErrorState::new(vec![SigningError::PeerAborted {
+            party,
+            reason: notice.reason.clone(),
+            blamed: notice.blamed.clone(),
+        }])
+    }
 }
 
 /// Checks whether all expected messages have been received so far from other parties  
@@ -447,6 +592,18 @@ fn is_broadcast_input_complete(
     other_parties.iter().all(|p| senders.contains(p))
 }
 
+/// Parties of the set which have not delivered their messages yet
+fn missing_senders(
+    current_msg_set: &[InMsg],
+    other_parties: &BTreeSet<PartyIndex>,
+) -> Vec<PartyIndex> {
+    other_parties
+        .iter()
+        .filter(|p| current_msg_set.iter().all(|m| m.sender != **p))
+        .copied()
+        .collect()
+}
+
 /// Extracts payloads form enum variants of input message into the hash map
 #[trace(disable(current_msg_set), res = "{:?}")]
 fn to_hash_map_gen<K, V>(current_msg_set: Vec<InMsg>) -> Result<HashMap<K, V>, SigningError>
@@ -485,8 +642,10 @@ where
 /// * Broadcasts the commitment to $` g^{\gamma_{i}} `$
 /// * Broadcasts `MtA` with the share of $` k_{i}  `$
 /// * Collects `MtA` inputs from other parties and verifies them using [`mta::verify_alice_range_proof`](mta/fn.verify_alice_range_proof.html)
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Phase1 {
+    session: Session,
+    limits: Limits,
     pub params: SigningParameters,
     pub multi_party_info: MultiPartyInfo,
     other_parties: BTreeSet<PartyIndex>,
@@ -505,10 +664,12 @@ impl Phase1 {
     /// * Generates first message of MtA protocol where $`  k_{i} `$ is shared
     /// * Generates (comm, decomm) to $` g^{\gamma_{i}} `$
     pub fn new(
+        session_id: SessionId,
         message_hash: MessageHashType,
         multi_party_info: MultiPartyInfo,
         parties: &[PartyIndex],
         timeout: Option<Duration>,
+        limits: Limits,
     ) -> Result<Self, SigningError> {
         let signing_parties = BTreeSet::from_iter(parties.iter().cloned());
         if signing_parties.len() != parties.len() {
@@ -571,6 +732,10 @@ impl Phase1 {
                 point: format!("{:?}", public_key),
             });
         }
+        let session = Session {
+            protocol: ProtocolId::Signing,
+            id: session_id,
+        };
         let k_i = FE::random();
         let ek = &multi_party_info.own_he_keys.ek;
 
@@ -579,19 +744,20 @@ impl Phase1 {
                 setups
                     .party_setups
                     .iter()
-                    .map(|(p, setup)| (*p, MessageA::new(&k_i, ek, Some(setup))))
+                    .map(|(p, setup)| (*p, MessageA::new(&session, &k_i, ek, Some(setup))))
                     .collect::<HashMap<_, _>>(),
             )
         } else {
-            MtaAliceOutput::Simple(MessageA::new(&k_i, ek, None))
+            MtaAliceOutput::Simple(MessageA::new(&session, &k_i, ek, None))
         };
 
         let gamma_i = FE::random();
         let g = GE::generator();
         let g_gamma_i = g.mul(&gamma_i);
-        let comm_scheme = CommitmentScheme::from_GE(&g_gamma_i);
+        let comm_scheme = CommitmentScheme::from_GE(&session, &g_gamma_i);
 
         Ok(Phase1 {
+            session,
             params: SigningParameters {
                 keygen_params: multi_party_info.key_params,
                 signing_parties,
@@ -604,6 +770,7 @@ impl Phase1 {
             mta_a,
             comm_scheme,
             timeout,
+            limits,
         })
     }
     /// Checks if Shamir's secret sharing points are known for each other party involved into the signing protocol
@@ -631,6 +798,7 @@ impl Phase1 {
             .iter()
             .filter_map(|(party, msg)| {
                 match mta::verify_alice_range_proof(
+                    &self.session,
                     &msg.c,
                     party,
                     Some(&range_proof_setup.my_setup),
@@ -656,21 +824,25 @@ impl State<SigningTraits> for Phase1 {
         log::info!("Phase 1 starts");
 
         let output = match &self.mta_a {
-            MtaAliceOutput::Simple(msg) => vec![OutMsg {
-                recipient: Address::Broadcast,
-                body: Message::R1(SignBroadcastPhase1 {
+            MtaAliceOutput::Simple(msg) => vec![OutMsg::new(
+                self.session,
+                Address::Broadcast,
+                Message::R1(SignBroadcastPhase1 {
                     com: self.comm_scheme.comm.clone(),
                     mta_a: msg.clone(),
                 }),
-            }],
+            )],
             MtaAliceOutput::WithRangeProofs(map) => map
                 .iter()
-                .map(|(p, msg)| OutMsg {
-                    recipient: Address::Peer(*p),
-                    body: Message::R1(SignBroadcastPhase1 {
-                        com: self.comm_scheme.comm.clone(),
-                        mta_a: msg.clone(),
-                    }),
+                .map(|(p, msg)| {
+                    OutMsg::new(
+                        self.session,
+                        Address::Peer(*p),
+                        Message::R1(SignBroadcastPhase1 {
+                            com: self.comm_scheme.comm.clone(),
+                            mta_a: msg.clone(),
+                        }),
+                    )
                 })
                 .collect::<Vec<_>>(),
         };
@@ -678,10 +850,14 @@ impl State<SigningTraits> for Phase1 {
     }
 
     #[trace(disable(current_msg_set))]
-    fn is_message_expected(&self, msg: &InMsg, current_msg_set: &[InMsg]) -> bool {
-        matches!(
-            msg.body,
-            Message::R1(_) if self.other_parties.contains(&msg.sender) && !msg.is_duplicate(current_msg_set)
+    fn classify_message(&self, msg: &InMsg, current_msg_set: &[InMsg]) -> MessageClass {
+        msg.classify(
+            &self.session,
+            &self.limits,
+            1,
+            matches!(msg.body, Message::R1(_)),
+            SenderRole::of_member(self.other_parties.contains(&msg.sender)),
+            current_msg_set,
         )
     }
     #[trace(disable(current_msg_set))]
@@ -689,6 +865,11 @@ impl State<SigningTraits> for Phase1 {
         is_broadcast_input_complete(current_msg_set, &self.other_parties)
     }
 
+    #[trace(disable(current_msg_set))]
+    fn awaited_senders(&self, current_msg_set: &[InMsg]) -> Vec<PartyIndex> {
+        missing_senders(current_msg_set, &self.other_parties)
+    }
+
     fn consume(&self, current_msg_set: Vec<InMsg>) -> Transition<SigningTraits> {
         let responses = match to_hash_map_gen::<PartyIndex, SignBroadcastPhase1>(current_msg_set) {
             Err(e) => {
@@ -716,6 +897,8 @@ impl State<SigningTraits> for Phase1 {
             }
         }
         Transition::NewState(Box::new(Phase2a {
+            session: self.session,
+            limits: self.limits,
             params: self.params.clone(),
             multi_party_info: self.multi_party_info.clone(),
             other_parties: self.other_parties.clone(),
@@ -739,6 +922,47 @@ impl State<SigningTraits> for Phase1 {
     fn timeout(&self) -> Option<Duration> {
         self.timeout
     }
+
+    #[trace(disable(res))]
+    fn snapshot(&self) -> Option<serde_json::Value> {
+        phase_snapshot("Phase1", self)
+    }
+
+    fn name(&self) -> &'static str {
+        "signing.phase1"
+    }
+
+    fn abort_output(&self, error: &ErrorState) -> Option<Vec<OutMsg>> {
+        abort_broadcast(self.session, error)
+    }
+
+    fn peer_aborted(&self, msg: &InMsg) -> Option<ErrorState> {
+        aborted_by_peer(
+            &self.session,
+            &self.limits,
+            msg,
+            self.other_parties.contains(&msg.sender),
+        )
+    }
+
+    #[trace(disable(current_msg_set))]
+    fn resend_request(&self, current_msg_set: &[InMsg]) -> Option<Vec<OutMsg>> {
+        resend_requests(
+            self.session,
+            1,
+            missing_senders(current_msg_set, &self.other_parties),
+        )
+    }
+
+    fn resend_output(&self, msg: &InMsg, sent: &[OutMsg]) -> Option<Resent<OutMsg>> {
+        resent_output(
+            &self.session,
+            &self.limits,
+            msg,
+            self.other_parties.contains(&msg.sender),
+            sent,
+        )
+    }
 }
 
 /// Second phase of the protocol, part A
@@ -746,7 +970,10 @@ impl State<SigningTraits> for Phase1 {
 /// * Broadcasts Bob's `MtA` message where $` \gamma_{i} `$ is shared
 /// * Collects Bob's `MtA` messages from other parties and verifies ZK range proof for each of them optionally.
 /// * Computes $` \delta_{i} = k_{i}\gamma_{i} + \sum_{i \not = j} \alpha_{ij} + \sum_{i \not = j} \beta_{ij} `$
+#[derive(Serialize, Deserialize)]
 struct Phase2a {
+    session: Session,
+    limits: Limits,
     params: SigningParameters,
     multi_party_info: MultiPartyInfo,
     other_parties: BTreeSet<PartyIndex>,
@@ -774,6 +1001,7 @@ impl State<SigningTraits> for Phase2a {
                     .map(|s| s.party_setups.get(party).expect("zkp setup not found"));
 
                 let (message, beta_prime) = MessageB::new(
+                    &self.session,
                     &self.gamma_i,
                     party_ek,
                     alice_zkp_setup,
@@ -781,10 +1009,11 @@ impl State<SigningTraits> for Phase2a {
                     MtA, // first round of Mta goes without extra checks
                 );
                 self.beta_outputs.insert(*party, beta_prime);
-                result.push(OutMsg {
-                    recipient: Address::Peer(*party),
-                    body: Message::R2(message),
-                });
+                result.push(OutMsg::new(
+                    self.session,
+                    Address::Peer(*party),
+                    Message::R2(message),
+                ));
             } else {
                 // the following statement should never be executed if Phase1::new() checks that all paillier keys available
                 log::error!("paillier key not found for party {}", *party);
@@ -799,10 +1028,14 @@ impl State<SigningTraits> for Phase2a {
     }
 
     #[trace(disable(current_msg_set))]
-    fn is_message_expected(&self, msg: &InMsg, current_msg_set: &[InMsg]) -> bool {
-        matches!(
-            msg.body,
-            Message::R2(_) if self.other_parties.contains(&msg.sender) && !msg.is_duplicate(current_msg_set)
+    fn classify_message(&self, msg: &InMsg, current_msg_set: &[InMsg]) -> MessageClass {
+        msg.classify(
+            &self.session,
+            &self.limits,
+            2,
+            matches!(msg.body, Message::R2(_)),
+            SenderRole::of_member(self.other_parties.contains(&msg.sender)),
+            current_msg_set,
         )
     }
 
@@ -811,6 +1044,11 @@ impl State<SigningTraits> for Phase2a {
         is_broadcast_input_complete(current_msg_set, &self.other_parties)
     }
 
+    #[trace(disable(current_msg_set))]
+    fn awaited_senders(&self, current_msg_set: &[InMsg]) -> Vec<PartyIndex> {
+        missing_senders(current_msg_set, &self.other_parties)
+    }
+
     fn consume(&self, current_msg_set: Vec<InMsg>) -> Transition<SigningTraits> {
         let responses = match to_hash_map_gen::<PartyIndex, MessageB>(current_msg_set) {
             Ok(map) => map,
@@ -839,6 +1077,7 @@ impl State<SigningTraits> for Phase2a {
             };
 
             match mta::verify_bob_range_proof(
+                &self.session,
                 party,
                 &msg.proof,
                 &msg.c,
@@ -868,6 +1107,8 @@ impl State<SigningTraits> for Phase2a {
         // k * gamma = sum(delta) across the cluster
 
         Transition::NewState(Box::new(Phase2b {
+            session: self.session,
+            limits: self.limits,
             params: self.params.clone(),
             multi_party_shared_info: self.multi_party_info.clone(),
             other_parties: self.other_parties.clone(),
@@ -893,13 +1134,57 @@ impl State<SigningTraits> for Phase2a {
     fn timeout(&self) -> Option<Duration> {
         self.timeout
     }
+
+    #[trace(disable(res))]
+    fn snapshot(&self) -> Option<serde_json::Value> {
+        phase_snapshot("Phase2a", self)
+    }
+
+    fn name(&self) -> &'static str {
+        "signing.phase2a"
+    }
+
+    fn abort_output(&self, error: &ErrorState) -> Option<Vec<OutMsg>> {
+        abort_broadcast(self.session, error)
+    }
+
+    fn peer_aborted(&self, msg: &InMsg) -> Option<ErrorState> {
+        aborted_by_peer(
+            &self.session,
+            &self.limits,
+            msg,
+            self.other_parties.contains(&msg.sender),
+        )
+    }
+
+    #[trace(disable(current_msg_set))]
+    fn resend_request(&self, current_msg_set: &[InMsg]) -> Option<Vec<OutMsg>> {
+        resend_requests(
+            self.session,
+            2,
+            missing_senders(current_msg_set, &self.other_parties),
+        )
+    }
+
+    fn resend_output(&self, msg: &InMsg, sent: &[OutMsg]) -> Option<Resent<OutMsg>> {
+        resent_output(
+            &self.session,
+            &self.limits,
+            msg,
+            self.other_parties.contains(&msg.sender),
+            sent,
+        )
+    }
 }
 /// Second phase of the protocol, part B
 ///
 /// * Broadcasts Bob's `MtAwc` message where $` \omega_{i} `$ is shared. Note that $` k_{i}  `$ is already broadcast at phase 1
 /// * Collects Bob's `MtAwc` messages from other parties and verifies ZK range proof for each of them optionally.
 /// * Computes $` \sigma_{i} = k_{i}\omega_{i} + \sum_{i \not = j} \mu_{ij} + \sum_{i \not = j} \upsilon_{ij} `$, where $` \mu, \space \upsilon `$ have same meaning as $` \alpha , \space \beta `$ in part A
+#[derive(Serialize, Deserialize)]
 struct Phase2b {
+    session: Session,
+    limits: Limits,
     params: SigningParameters,
     multi_party_shared_info: MultiPartyInfo,
     other_parties: BTreeSet<PartyIndex>,
@@ -947,13 +1232,20 @@ impl State<SigningTraits> for Phase2b {
                     .range_proof_setups
                     .as_ref()
                     .map(|s| s.party_setups.get(party).expect("zkp setup not found"));
-                let (message, beta_prime) =
-                    MessageB::new(&self.w_i, party_ek, alice_zkp_setup, messageA, MtAwc);
+                let (message, beta_prime) = MessageB::new(
+                    &self.session,
+                    &self.w_i,
+                    party_ek,
+                    alice_zkp_setup,
+                    messageA,
+                    MtAwc,
+                );
                 self.omega_outputs.insert(*party, beta_prime);
-                result.push(OutMsg {
-                    recipient: Address::Peer(*party),
-                    body: Message::R2b(message),
-                });
+                result.push(OutMsg::new(
+                    self.session,
+                    Address::Peer(*party),
+                    Message::R2b(message),
+                ));
             } else {
                 log::error!("paillier key not found for party {}", *party);
             }
@@ -967,10 +1259,14 @@ impl State<SigningTraits> for Phase2b {
     }
 
     #[trace(disable(current_msg_set))]
-    fn is_message_expected(&self, msg: &InMsg, current_msg_set: &[InMsg]) -> bool {
-        matches!(
-            msg.body,
-            Message::R2b(_) if self.other_parties.contains(&msg.sender) && !msg.is_duplicate(current_msg_set)
+    fn classify_message(&self, msg: &InMsg, current_msg_set: &[InMsg]) -> MessageClass {
+        msg.classify(
+            &self.session,
+            &self.limits,
+            3,
+            matches!(msg.body, Message::R2b(_)),
+            SenderRole::of_member(self.other_parties.contains(&msg.sender)),
+            current_msg_set,
         )
     }
 
@@ -979,6 +1275,11 @@ impl State<SigningTraits> for Phase2b {
         is_broadcast_input_complete(current_msg_set, &self.other_parties)
     }
 
+    #[trace(disable(current_msg_set))]
+    fn awaited_senders(&self, current_msg_set: &[InMsg]) -> Vec<PartyIndex> {
+        missing_senders(current_msg_set, &self.other_parties)
+    }
+
     fn consume(&self, current_msg_set: Vec<InMsg>) -> Transition<SigningTraits> {
         let responses = match to_hash_map_gen::<PartyIndex, MessageB>(current_msg_set) {
             Ok(map) => map,
@@ -1007,6 +1308,7 @@ impl State<SigningTraits> for Phase2b {
             };
 
             match mta::verify_bob_range_proof(
+                &self.session,
                 party,
                 &msg.proof,
                 &msg.c,
@@ -1036,6 +1338,8 @@ impl State<SigningTraits> for Phase2b {
         // k * w = sum(sigma) across the cluster, check the paper
 
         Transition::NewState(Box::new(Phase3 {
+            session: self.session,
+            limits: self.limits,
             params: self.params.clone(),
             multi_party_info: self.multi_party_shared_info.clone(),
             other_parties: self.other_parties.clone(),
@@ -1058,12 +1362,56 @@ impl State<SigningTraits> for Phase2b {
             phase: "phase2b".to_string(),
         }]))
     }
+
+    #[trace(disable(res))]
+    fn snapshot(&self) -> Option<serde_json::Value> {
+        phase_snapshot("Phase2b", self)
+    }
+
+    fn name(&self) -> &'static str {
+        "signing.phase2b"
+    }
+
+    fn abort_output(&self, error: &ErrorState) -> Option<Vec<OutMsg>> {
+        abort_broadcast(self.session, error)
+    }
+
+    fn peer_aborted(&self, msg: &InMsg) -> Option<ErrorState> {
+        aborted_by_peer(
+            &self.session,
+            &self.limits,
+            msg,
+            self.other_parties.contains(&msg.sender),
+        )
+    }
+
+    #[trace(disable(current_msg_set))]
+    fn resend_request(&self, current_msg_set: &[InMsg]) -> Option<Vec<OutMsg>> {
+        resend_requests(
+            self.session,
+            3,
+            missing_senders(current_msg_set, &self.other_parties),
+        )
+    }
+
+    fn resend_output(&self, msg: &InMsg, sent: &[OutMsg]) -> Option<Resent<OutMsg>> {
+        resent_output(
+            &self.session,
+            &self.limits,
+            msg,
+            self.other_parties.contains(&msg.sender),
+            sent,
+        )
+    }
 }
 /// Third phase of the protocol
 ///
 /// * Broadcasts  $` \delta_{i} `$
 /// * Reconstructs $` \delta = \sum_{i \in S} \delta_{i} = k \gamma `$, where $`S`$ is the signing quorum    
+#[derive(Serialize, Deserialize)]
 struct Phase3 {
+    session: Session,
+    limits: Limits,
     params: SigningParameters,
     multi_party_info: MultiPartyInfo,
     other_parties: BTreeSet<PartyIndex>,
@@ -1080,20 +1428,25 @@ struct Phase3 {
 impl State<SigningTraits> for Phase3 {
     fn start(&mut self) -> Option<OutMsgVec> {
         log::debug!("Phase 3 starts");
-        let output = vec![OutMsg {
-            recipient: Address::Broadcast,
-            body: Message::R3(Phase3data {
+        let output = vec![OutMsg::new(
+            self.session,
+            Address::Broadcast,
+            Message::R3(Phase3data {
                 delta_i: self.delta_i.clone(),
             }),
-        }];
+        )];
         Some(output)
     }
 
     #[trace(disable(current_msg_set))]
-    fn is_message_expected(&self, msg: &InMsg, current_msg_set: &[InMsg]) -> bool {
-        matches!(
-            msg.body,
-            Message::R3(_) if self.other_parties.contains(&msg.sender) && !msg.is_duplicate(current_msg_set)
+    fn classify_message(&self, msg: &InMsg, current_msg_set: &[InMsg]) -> MessageClass {
+        msg.classify(
+            &self.session,
+            &self.limits,
+            4,
+            matches!(msg.body, Message::R3(_)),
+            SenderRole::of_member(self.other_parties.contains(&msg.sender)),
+            current_msg_set,
         )
     }
 
@@ -1102,6 +1455,11 @@ impl State<SigningTraits> for Phase3 {
         is_broadcast_input_complete(current_msg_set, &self.other_parties)
     }
 
+    #[trace(disable(current_msg_set))]
+    fn awaited_senders(&self, current_msg_set: &[InMsg]) -> Vec<PartyIndex> {
+        missing_senders(current_msg_set, &self.other_parties)
+    }
+
     fn consume(&self, current_msg_set: Vec<InMsg>) -> Transition<SigningTraits> {
         let responses = match to_hash_map_gen::<PartyIndex, Phase3data>(current_msg_set) {
             Ok(map) => map,
@@ -1120,6 +1478,8 @@ impl State<SigningTraits> for Phase3 {
         let delta_inv = delta.invert().expect("can't invert Delta");
 
         Transition::NewState(Box::new(Phase4 {
+            session: self.session,
+            limits: self.limits,
             params: self.params.clone(),
             multi_party_info: self.multi_party_info.clone(),
             other_parties: self.other_parties.clone(),
@@ -1142,6 +1502,47 @@ impl State<SigningTraits> for Phase3 {
     fn timeout(&self) -> Option<Duration> {
         self.timeout
     }
+
+    #[trace(disable(res))]
+    fn snapshot(&self) -> Option<serde_json::Value> {
+        phase_snapshot("Phase3", self)
+    }
+
+    fn name(&self) -> &'static str {
+        "signing.phase3"
+    }
+
+    fn abort_output(&self, error: &ErrorState) -> Option<Vec<OutMsg>> {
+        abort_broadcast(self.session, error)
+    }
+
+    fn peer_aborted(&self, msg: &InMsg) -> Option<ErrorState> {
+        aborted_by_peer(
+            &self.session,
+            &self.limits,
+            msg,
+            self.other_parties.contains(&msg.sender),
+        )
+    }
+
+    #[trace(disable(current_msg_set))]
+    fn resend_request(&self, current_msg_set: &[InMsg]) -> Option<Vec<OutMsg>> {
+        resend_requests(
+            self.session,
+            4,
+            missing_senders(current_msg_set, &self.other_parties),
+        )
+    }
+
+    fn resend_output(&self, msg: &InMsg, sent: &[OutMsg]) -> Option<Resent<OutMsg>> {
+        resent_output(
+            &self.session,
+            &self.limits,
+            msg,
+            self.other_parties.contains(&msg.sender),
+            sent,
+        )
+    }
 }
 
 /// Fourth phase of the protocol
@@ -1149,7 +1550,10 @@ impl State<SigningTraits> for Phase3 {
 /// * Broadcasts $`  \Gamma_{i} = g^{\gamma_{i}} `$ and ZKP of it
 /// * Verifies ZKP of each other party
 /// * Reconstructs $` R = ( \prod_{i \in S} \Gamma_{i})^{\delta^{-1}} = g^{k^{-1}} `$ and $` r = H^{\prime}(R) `$
+#[derive(Serialize, Deserialize)]
 struct Phase4 {
+    session: Session,
+    limits: Limits,
     params: SigningParameters,
     multi_party_info: MultiPartyInfo,
     other_parties: BTreeSet<PartyIndex>,
@@ -1168,22 +1572,27 @@ impl State<SigningTraits> for Phase4 {
         log::debug!("Phase 4 starts");
         let g = GE::generator();
         let g_gamma_i = g * &self.gamma_i;
-        let output = vec![OutMsg {
-            recipient: Address::Broadcast,
-            body: Message::R4(SignDecommitPhase4 {
+        let output = vec![OutMsg::new(
+            self.session,
+            Address::Broadcast,
+            Message::R4(SignDecommitPhase4 {
                 blind_factor: self.comm_scheme.decomm.clone(),
                 g_gamma_i,
-                gamma_proof: CurvDLogProofType::prove(&self.gamma_i),
+                gamma_proof: DLogProof::prove(&self.session, &self.gamma_i),
             }),
-        }];
+        )];
         Some(output)
     }
 
     #[trace(disable(current_msg_set))]
-    fn is_message_expected(&self, msg: &InMsg, current_msg_set: &[InMsg]) -> bool {
-        matches!(
-            msg.body,
-            Message::R4(_) if self.other_parties.contains(&msg.sender) && !msg.is_duplicate(current_msg_set)
+    fn classify_message(&self, msg: &InMsg, current_msg_set: &[InMsg]) -> MessageClass {
+        msg.classify(
+            &self.session,
+            &self.limits,
+            5,
+            matches!(msg.body, Message::R4(_)),
+            SenderRole::of_member(self.other_parties.contains(&msg.sender)),
+            current_msg_set,
         )
     }
 
@@ -1192,6 +1601,11 @@ impl State<SigningTraits> for Phase4 {
         is_broadcast_input_complete(current_msg_set, &self.other_parties)
     }
 
+    #[trace(disable(current_msg_set))]
+    fn awaited_senders(&self, current_msg_set: &[InMsg]) -> Vec<PartyIndex> {
+        missing_senders(current_msg_set, &self.other_parties)
+    }
+
     fn consume(&self, current_msg_set: Vec<InMsg>) -> Transition<SigningTraits> {
         let responses = match to_hash_map_gen::<PartyIndex, SignDecommitPhase4>(current_msg_set) {
             Ok(map) => map,
@@ -1214,8 +1628,8 @@ impl State<SigningTraits> for Phase4 {
                     decomm: msg.blind_factor.clone(),
                 };
                 if is_valid_curve_point(&msg.g_gamma_i)
-                    && foreign_comm_scheme.verify_commitment(&msg.g_gamma_i)
-                    && CurvDLogProofType::verify(&msg.gamma_proof).is_ok()
+                    && foreign_comm_scheme.verify_commitment(&self.session, &msg.g_gamma_i)
+                    && msg.gamma_proof.verify(&self.session).is_ok()
                 // TODO : map 2 possible bad outcomes into 2 errors
                 {
                     None
@@ -1237,9 +1651,11 @@ impl State<SigningTraits> for Phase4 {
             let R = g_gamma_sum * &self.delta_inv;
             let local_sig =
                 LocalSignature::new(&self.params.message_hash, &R, &self.k_i, &self.sigma_i);
-            let (p5_commit, p5_decommit) = local_sig.phase5b_proof();
+            let (p5_commit, p5_decommit) = local_sig.phase5b_proof(&self.session);
 
             Transition::NewState(Box::new(Phase5ab {
+                session: self.session,
+                limits: self.limits,
                 params: self.params.clone(),
                 multi_party_info: self.multi_party_info.clone(),
                 other_parties: self.other_parties.clone(),
@@ -1267,6 +1683,47 @@ impl State<SigningTraits> for Phase4 {
     fn timeout(&self) -> Option<Duration> {
         self.timeout
     }
+
+    #[trace(disable(res))]
+    fn snapshot(&self) -> Option<serde_json::Value> {
+        phase_snapshot("Phase4", self)
+    }
+
+    fn name(&self) -> &'static str {
+        "signing.phase4"
+    }
+
+    fn abort_output(&self, error: &ErrorState) -> Option<Vec<OutMsg>> {
+        abort_broadcast(self.session, error)
+    }
+
+    fn peer_aborted(&self, msg: &InMsg) -> Option<ErrorState> {
+        aborted_by_peer(
+            &self.session,
+            &self.limits,
+            msg,
+            self.other_parties.contains(&msg.sender),
+        )
+    }
+
+    #[trace(disable(current_msg_set))]
+    fn resend_request(&self, current_msg_set: &[InMsg]) -> Option<Vec<OutMsg>> {
+        resend_requests(
+            self.session,
+            5,
+            missing_senders(current_msg_set, &self.other_parties),
+        )
+    }
+
+    fn resend_output(&self, msg: &InMsg, sent: &[OutMsg]) -> Option<Resent<OutMsg>> {
+        resent_output(
+            &self.session,
+            &self.limits,
+            msg,
+            self.other_parties.contains(&msg.sender),
+            sent,
+        )
+    }
 }
 
 impl Drop for Phase4 {
@@ -1277,7 +1734,7 @@ impl Drop for Phase4 {
 }
 
 /// Discriminates the sub phase in Phase 5 protocol
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, PartialEq, Serialize, Deserialize)]
 enum SubPhaseAB {
     A,
     B,
@@ -1292,7 +1749,10 @@ enum SubPhaseAB {
 /// * Broadcasts [`Phase5Decom1`](../messages/signing/struct.Phase5Decom1.html)
 /// * Verifies each party's `Phase5Com` using [`check_comms_A`](#method.check_comms_A)
 ///
+#[derive(Serialize, Deserialize)]
 struct Phase5ab {
+    session: Session,
+    limits: Limits,
     params: SigningParameters,
     multi_party_info: MultiPartyInfo,
     other_parties: BTreeSet<PartyIndex>,
@@ -1317,7 +1777,7 @@ impl Phase5ab {
             comm: comm.clone(),
             decomm: msg.blind_factor.clone(),
         };
-        if scheme.verify_hash(&input_hash)
+        if scheme.verify_hash(&self.session, &input_hash)
             && is_valid_curve_point(&msg.A_i)
             && is_valid_curve_point(&msg.V_i)
         {
@@ -1339,7 +1799,7 @@ impl Phase5ab {
             D: msg.V_i.clone(),
             E: msg.B_i.clone(),
         };
-        if msg.proof.verify(&delta).is_ok() {
+        if msg.proof.verify(&self.session, &delta).is_ok() {
             Ok(())
         } else {
             Err(SigningError::InvalidElGamalProof { party: *party })
@@ -1365,6 +1825,8 @@ impl Phase5ab {
 impl Clone for Phase5ab {
     fn clone(&self) -> Self {
         Phase5ab {
+            session: self.session,
+            limits: self.limits,
             params: self.params.clone(),
             multi_party_info: self.multi_party_info.clone(),
             other_parties: self.other_parties.clone(),
@@ -1386,31 +1848,44 @@ impl State<SigningTraits> for Phase5ab {
         match &self.subphase {
             SubPhaseAB::A => {
                 log::debug!("Subphase A starts");
-                let output = vec![OutMsg {
-                    recipient: Address::Broadcast,
-                    body: Message::R5(self.p5_commit.clone()),
-                }];
+                let output = vec![OutMsg::new(
+                    self.session,
+                    Address::Broadcast,
+                    Message::R5(self.p5_commit.clone()),
+                )];
                 Some(output)
             }
             SubPhaseAB::B => {
                 log::debug!("Subphase B starts");
-                let output = vec![OutMsg {
-                    recipient: Address::Broadcast,
-                    body: Message::R6(self.p5_decommit.clone()),
-                }];
+                let output = vec![OutMsg::new(
+                    self.session,
+                    Address::Broadcast,
+                    Message::R6(self.p5_decommit.clone()),
+                )];
                 Some(output)
             }
         }
     }
 
     #[trace(disable(current_msg_set))]
-    fn is_message_expected(&self, msg: &InMsg, current_msg_set: &[InMsg]) -> bool {
-        (match msg.body {
+    fn classify_message(&self, msg: &InMsg, current_msg_set: &[InMsg]) -> MessageClass {
+        let expected_kind = match msg.body {
             Message::R5(_) => self.subphase == SubPhaseAB::A,
             Message::R6(_) => self.subphase == SubPhaseAB::B,
             _ => false,
-        }) && self.other_parties.contains(&msg.sender)
-            && !msg.is_duplicate(current_msg_set)
+        };
+        let round = match self.subphase {
+            SubPhaseAB::A => 6,
+            SubPhaseAB::B => 7,
+        };
+        msg.classify(
+            &self.session,
+            &self.limits,
+            round,
+            expected_kind,
+            SenderRole::of_member(self.other_parties.contains(&msg.sender)),
+            current_msg_set,
+        )
     }
 
     #[trace(disable(current_msg_set))]
@@ -1418,6 +1893,11 @@ impl State<SigningTraits> for Phase5ab {
         is_broadcast_input_complete(current_msg_set, &self.other_parties)
     }
 
+    #[trace(disable(current_msg_set))]
+    fn awaited_senders(&self, current_msg_set: &[InMsg]) -> Vec<PartyIndex> {
+        missing_senders(current_msg_set, &self.other_parties)
+    }
+
     fn consume(&self, current_msg_set: Vec<InMsg>) -> Transition<SigningTraits> {
         match &self.subphase {
             SubPhaseAB::A => match to_hash_map_gen::<PartyIndex, Phase5Com1>(current_msg_set) {
@@ -1447,10 +1927,13 @@ impl State<SigningTraits> for Phase5ab {
                         }));
 
                         let (V, A) = self.compute_va(&decomms);
-                        let (p5commit2, p5decommit2) = self.local_sig.phase5d_proof(V, A);
+                        let (p5commit2, p5decommit2) =
+                            self.local_sig.phase5d_proof(&self.session, V, A);
 
                         if errors.is_empty() {
                             Transition::NewState(Box::new(Phase5cde {
+                                session: self.session,
+                                limits: self.limits,
                                 params: self.params.clone(),
                                 shared_keys: self.multi_party_info.clone(),
                                 other_parties: self.other_parties.clone(),
@@ -1490,10 +1973,55 @@ impl State<SigningTraits> for Phase5ab {
     fn timeout(&self) -> Option<Duration> {
         self.timeout
     }
+
+    #[trace(disable(res))]
+    fn snapshot(&self) -> Option<serde_json::Value> {
+        phase_snapshot("Phase5ab", self)
+    }
+
+    fn name(&self) -> &'static str {
+        "signing.phase5ab"
+    }
+
+    fn abort_output(&self, error: &ErrorState) -> Option<Vec<OutMsg>> {
+        abort_broadcast(self.session, error)
+    }
+
+    fn peer_aborted(&self, msg: &InMsg) -> Option<ErrorState> {
+        aborted_by_peer(
+            &self.session,
+            &self.limits,
+            msg,
+            self.other_parties.contains(&msg.sender),
+        )
+    }
+
+    #[trace(disable(current_msg_set))]
+    fn resend_request(&self, current_msg_set: &[InMsg]) -> Option<Vec<OutMsg>> {
+        let round = match self.subphase {
+            SubPhaseAB::A => 6,
+            SubPhaseAB::B => 7,
+        };
+        resend_requests(
+            self.session,
+            round,
+            missing_senders(current_msg_set, &self.other_parties),
+        )
+    }
+
+    fn resend_output(&self, msg: &InMsg, sent: &[OutMsg]) -> Option<Resent<OutMsg>> {
+        resent_output(
+            &self.session,
+            &self.limits,
+            msg,
+            self.other_parties.contains(&msg.sender),
+            sent,
+        )
+    }
 }
 
 /// Discriminates the sub phase in Phase 5 protocol
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, PartialEq, Serialize, Deserialize)]
 enum SubPhaseCDE {
     C,
     D,
@@ -1512,7 +2040,10 @@ enum SubPhaseCDE {
 /// Subphase E:, see (5E) in the paper
 /// * Broadcasts the partial signature [`Phase5Edata`](../messages/signing/struct.Phase5Edata.html)
 /// * Reconstructs full signature and verifies it using standard method
+#[derive(Serialize, Deserialize)]
 struct Phase5cde {
+    session: Session,
+    limits: Limits,
     params: SigningParameters,
     shared_keys: MultiPartyInfo,
     other_parties: BTreeSet<PartyIndex>,
@@ -1539,7 +2070,7 @@ impl Phase5cde {
             comm: comm.clone(),
             decomm: msg.blind_factor.clone(),
         };
-        if scheme.verify_hash(&input_hash) {
+        if scheme.verify_hash(&self.session, &input_hash) {
             Ok(())
         } else {
             Err(SigningError::InvalidDecommitment { party: *party })
@@ -1550,6 +2081,8 @@ impl Phase5cde {
 impl Clone for Phase5cde {
     fn clone(&self) -> Self {
         Phase5cde {
+            session: self.session,
+            limits: self.limits,
             params: self.params.clone(),
             shared_keys: self.shared_keys.clone(),
             other_parties: self.other_parties.clone(),
@@ -1573,42 +2106,57 @@ impl State<SigningTraits> for Phase5cde {
         match &self.subphase {
             SubPhaseCDE::C => {
                 log::debug!("Subphase C starts");
-                let output = vec![OutMsg {
-                    recipient: Address::Broadcast,
-                    body: Message::R7(self.p5_commit2.clone()),
-                }];
+                let output = vec![OutMsg::new(
+                    self.session,
+                    Address::Broadcast,
+                    Message::R7(self.p5_commit2.clone()),
+                )];
                 Some(output)
             }
             SubPhaseCDE::D => {
                 log::debug!("Subphase D starts");
-                let output = vec![OutMsg {
-                    recipient: Address::Broadcast,
-                    body: Message::R8(self.p5_decommit2.clone()),
-                }];
+                let output = vec![OutMsg::new(
+                    self.session,
+                    Address::Broadcast,
+                    Message::R8(self.p5_decommit2.clone()),
+                )];
                 Some(output)
             }
             SubPhaseCDE::E => {
                 log::debug!("Subphase E starts");
-                let output = vec![OutMsg {
-                    recipient: Address::Broadcast,
-                    body: Message::R9(Phase5Edata {
+                let output = vec![OutMsg::new(
+                    self.session,
+                    Address::Broadcast,
+                    Message::R9(Phase5Edata {
                         s_i: self.local_sig.s_i.clone(),
                     }),
-                }];
+                )];
                 Some(output)
             }
         }
     }
 
     #[trace(disable(current_msg_set))]
-    fn is_message_expected(&self, msg: &InMsg, current_msg_set: &[InMsg]) -> bool {
-        (match msg.body {
+    fn classify_message(&self, msg: &InMsg, current_msg_set: &[InMsg]) -> MessageClass {
+        let expected_kind = match msg.body {
             Message::R7(_) => self.subphase == SubPhaseCDE::C,
             Message::R8(_) => self.subphase == SubPhaseCDE::D,
             Message::R9(_) => self.subphase == SubPhaseCDE::E,
             _ => false,
-        }) && self.other_parties.contains(&msg.sender)
-            && !msg.is_duplicate(current_msg_set)
+        };
+        let round = match self.subphase {
+            SubPhaseCDE::C => 8,
+            SubPhaseCDE::D => 9,
+            SubPhaseCDE::E => 10,
+        };
+        msg.classify(
+            &self.session,
+            &self.limits,
+            round,
+            expected_kind,
+            SenderRole::of_member(self.other_parties.contains(&msg.sender)),
+            current_msg_set,
+        )
     }
 
     #[trace(disable(current_msg_set))]
@@ -1616,6 +2164,11 @@ impl State<SigningTraits> for Phase5cde {
         is_broadcast_input_complete(current_msg_set, &self.other_parties)
     }
 
+    #[trace(disable(current_msg_set))]
+    fn awaited_senders(&self, current_msg_set: &[InMsg]) -> Vec<PartyIndex> {
+        missing_senders(current_msg_set, &self.other_parties)
+    }
+
     fn consume(&self, current_msg_set: Vec<InMsg>) -> Transition<SigningTraits> {
         match self.subphase {
             SubPhaseCDE::C => {
@@ -1716,6 +2269,52 @@ impl State<SigningTraits> for Phase5cde {
     fn timeout(&self) -> Option<Duration> {
         self.timeout
     }
+
+    #[trace(disable(res))]
+    fn snapshot(&self) -> Option<serde_json::Value> {
+        phase_snapshot("Phase5cde", self)
+    }
+
+    fn name(&self) -> &'static str {
+        "signing.phase5cde"
+    }
+
+    fn abort_output(&self, error: &ErrorState) -> Option<Vec<OutMsg>> {
+        abort_broadcast(self.session, error)
+    }
+
+    fn peer_aborted(&self, msg: &InMsg) -> Option<ErrorState> {
+        aborted_by_peer(
+            &self.session,
+            &self.limits,
+            msg,
+            self.other_parties.contains(&msg.sender),
+        )
+    }
+
+    #[trace(disable(current_msg_set))]
+    fn resend_request(&self, current_msg_set: &[InMsg]) -> Option<Vec<OutMsg>> {
+        let round = match self.subphase {
+            SubPhaseCDE::C => 8,
+            SubPhaseCDE::D => 9,
+            SubPhaseCDE::E => 10,
+        };
+        resend_requests(
+            self.session,
+            round,
+            missing_senders(current_msg_set, &self.other_parties),
+        )
+    }
+
+    fn resend_output(&self, msg: &InMsg, sent: &[OutMsg]) -> Option<Resent<OutMsg>> {
+        resent_output(
+            &self.session,
+            &self.limits,
+            msg,
+            self.other_parties.contains(&msg.sender),
+            sent,
+        )
+    }
 }
 
 #[cfg(test)]
@@ -1726,7 +2325,8 @@ mod tests {
     use crate::types::FE;
 
     use crate::keygen::MultiPartyInfo;
-    use crate::protocol::{Address, InputMessage, PartyIndex};
+    use crate::limits::Limits;
+    use crate::protocol::{Address, InputMessage, PartyIndex, SessionId};
     use anyhow::bail;
     use crossbeam_channel::{Receiver, Sender};
     use sha2::{Digest, Sha256};
@@ -1774,6 +2374,7 @@ mod tests {
             .iter()
             .map(|x| PartyIndex::from(*x))
             .collect::<Vec<_>>();
+        let session_id = SessionId::random();
 
         for i in parties {
             let (ingress, rx) = crossbeam_channel::unbounded();
@@ -1794,22 +2395,24 @@ mod tests {
             log::info!("starting party {}", i);
             let join_handle = thread::spawn(move || {
                 let start_state = Box::new(Phase1::new(
+                    session_id,
                     msg_hash_copy,
                     multi_party_shared_info,
                     &signing_parties,
                     None,
+                    Limits::default(),
                 )?);
                 let mut machine = StateMachine::<SigningTraits>::new(start_state, &rx, &tx);
                 match machine.execute() {
-                    Some(Ok(fs)) => {
+                    Ok(Ok(fs)) => {
                         log::info!("success");
                         Ok(fs)
                     }
-                    Some(Err(e)) => {
+                    Ok(Err(e)) => {
                         bail!("error {:?}", e);
                     }
-                    None => {
-                        bail!("error in the machine");
+                    Err(aborted) => {
+                        bail!("machine aborted {:?}", aborted);
                     }
                 }
             });
@@ -1851,10 +2454,8 @@ mod tests {
                                 .iter()
                                 .filter(|node| node.party != mm.source)
                                 .for_each(|node| {
-                                    let message_to_deliver = InputMessage {
-                                        sender: mm.source,
-                                        body: mm.msg.body.clone(),
-                                    };
+                                    let message_to_deliver =
+                                        InputMessage::from_output(mm.source, mm.msg.clone());
                                     node.ingress.send(message_to_deliver).unwrap();
                                 });
                         }
@@ -1862,10 +2463,7 @@ mod tests {
                             if let Some(node) = nodes.iter().find(|node| (*node).party == *peer) {
                                 log::trace!("unicast from {} to  {:?}", mm.source, *peer);
                                 node.ingress
-                                    .send(InputMessage {
-                                        sender: mm.source,
-                                        body: mm.msg.body.clone(),
-                                    })
+                                    .send(InputMessage::from_output(mm.source, mm.msg.clone()))
                                     .unwrap();
                             }
                         }
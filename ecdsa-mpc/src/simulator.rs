@@ -0,0 +1,1186 @@
+//! Deterministic in-process network simulator
+//!
+//! The simulator runs several parties of a protocol in one thread, using the sans-IO [`Driver`] of each party.
+//! Messages travel through a simulated network, which delays, reorders, duplicates and loses them, and which can be split by partitions.
+//! All decisions of the network are taken by the random generator seeded with [`NetworkConditions::seed`], and the time is simulated,
+//! so that the same seed reproduces the same schedule of deliveries and timeouts.
+//! Note that the protocols themselves still use the system source of randomness for their secrets.
+//! [`Simulation::new`] refuses conditions which the network cannot follow, like rates which are not probabilities, see [`NetworkConditions::validate`].
+//!
+//! Lost messages are recovered only by drivers configured `with_resend_interval`: the simulator asks them to request missing messages again at their resend deadlines.
+//! Otherwise, and if the message is lost in the last round, when its sender has finished and no longer answers requests, the loss ends in the timeout of the recipient.
+//! Nodes with the resend interval should have a timeout, otherwise the simulation does not end while they request messages of finished parties.
+//!
+//! Each run produces the [`SimulationReport`] with outcomes of all parties and the transcript of the network.
+//! Functions [`check_keygen`], [`check_signing`], [`check_resharing`] and [`check_recovery`] verify the invariants of respective protocols on the report.
+//!
+//! Resharing runs two machines on parties which are members of both old and new committees.
+//! Each machine is added as a separate node, along with the filter of messages it receives, like [`old_member::is_message_to_committee`].
+//! Alternatively such party runs the single machine of [`member::Member`].
+//!
+//! Nodes added with [`Simulation::add_echoed_party`] pass their messages through the [`EchoBroadcast`] layer of their party, and end with [`NodeOutcome::Equivocation`]
+//! once the layer detects inconsistent broadcasts. [`Simulation::tamper`] alters messages of a party for each recipient, so that the party can be made to equivocate.
+//!
+//! [`Driver`]: ../../state_machine/driver/struct.Driver.html
+//! [`NetworkConditions::seed`]: struct.NetworkConditions.html#structfield.seed
+//! [`Simulation::new`]: struct.Simulation.html#method.new
+//! [`NetworkConditions::validate`]: struct.NetworkConditions.html#method.validate
+//! [`SimulationReport`]: struct.SimulationReport.html
+//! [`check_keygen`]: fn.check_keygen.html
+//! [`check_signing`]: fn.check_signing.html
+//! [`check_resharing`]: fn.check_resharing.html
+//! [`check_recovery`]: fn.check_recovery.html
+//! [`old_member::is_message_to_committee`]: ../resharing/old_member/fn.is_message_to_committee.html
+//! [`member::Member`]: ../resharing/member/struct.Member.html
+//! [`Simulation::add_echoed_party`]: struct.Simulation.html#method.add_echoed_party
+//! [`EchoBroadcast`]: ../echo/struct.EchoBroadcast.html
+//! [`NodeOutcome::Equivocation`]: enum.NodeOutcome.html#variant.Equivocation
+//! [`Simulation::tamper`]: struct.Simulation.html#method.tamper
+
+use crate::echo::{EchoBroadcast, EchoError, Echoable, Echoed};
+use crate::keygen;
+use crate::protocol::{Address, InputMessage, OutputMessage, PartyIndex};
+use crate::recovery::{holder, lost_party};
+use crate::resharing::{member, new_member, old_member};
+use crate::signature;
+use crate::types::{Signature, GE};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use state_machine::driver::{Driver, Step};
+use state_machine::types::StateMachineTraits;
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, BinaryHeap, HashMap};
+use std::fmt::Debug;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// Behavior of the simulated network
+#[derive(Debug, Clone)]
+pub struct NetworkConditions {
+    /// seed of all random decisions of the network
+    pub seed: u64,
+    /// the delay of each message is drawn uniformly from `min_delay..=max_delay`
+    pub min_delay: Duration,
+    pub max_delay: Duration,
+    /// if false, messages between any two nodes are delivered in the order they are sent, otherwise they may overtake each other
+    pub reorder: bool,
+    /// probability that a message is lost
+    pub loss_rate: f64,
+    /// probability that a message is delivered twice
+    pub duplication_rate: f64,
+    pub partitions: Vec<Partition>,
+}
+
+impl Default for NetworkConditions {
+    /// reliable network which delivers messages without delays
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            min_delay: Duration::from_millis(0),
+            max_delay: Duration::from_millis(0),
+            reorder: false,
+            loss_rate: 0.0,
+            duplication_rate: 0.0,
+            partitions: Vec::new(),
+        }
+    }
+}
+
+impl NetworkConditions {
+    /// Checks that rates are probabilities and that the minimum delay does not exceed the maximum one
+    pub fn validate(&self) -> Result<(), ConditionsError> {
+        for (name, rate) in [
+            ("loss rate", self.loss_rate),
+            ("duplication rate", self.duplication_rate),
+        ] {
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(ConditionsError::InvalidRate { name, rate });
+            }
+        }
+        if self.min_delay > self.max_delay {
+            return Err(ConditionsError::InvalidDelay {
+                min: self.min_delay,
+                max: self.max_delay,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Network conditions which the simulator cannot follow
+#[derive(Debug, Error, PartialEq)]
+pub enum ConditionsError {
+    #[error("{name} {rate} is not a probability")]
+    InvalidRate { name: &'static str, rate: f64 },
+    #[error("minimum delay {min:?} exceeds maximum delay {max:?}")]
+    InvalidDelay { min: Duration, max: Duration },
+}
+
+/// Isolates a group of parties from the rest of the network
+///
+/// Messages sent between a member of the group and any other party within given period of simulated time are lost.
+#[derive(Debug, Clone)]
+pub struct Partition {
+    pub isolated: BTreeSet<PartyIndex>,
+    pub from: Duration,
+    pub until: Duration,
+}
+
+impl Partition {
+    fn separates(&self, a: PartyIndex, b: PartyIndex, at: Duration) -> bool {
+        at >= self.from
+            && at < self.until
+            && self.isolated.contains(&a) != self.isolated.contains(&b)
+    }
+}
+
+/// What the network did with a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fate {
+    Delivered,
+    Lost,
+    Partitioned,
+}
+
+/// Record of the transcript of the simulated network
+#[derive(Debug, Clone)]
+pub struct TranscriptEntry<B> {
+    /// simulated time of delivery, or of sending if the message was not delivered
+    pub at: Duration,
+    pub sender: PartyIndex,
+    pub recipient: PartyIndex,
+    /// index of the recipient node in the order nodes were added
+    pub node: usize,
+    pub body: B,
+    pub fate: Fate,
+}
+
+/// How a node has ended
+#[derive(Debug)]
+pub enum NodeOutcome<R> {
+    /// the protocol is complete, successfully or not
+    Finished(R),
+    /// a deadline of the node expired, the value is the `timeout_outcome` of its current phase
+    TimedOut(R),
+    /// the deck of deferred messages of the node overflowed
+    Overflow,
+    /// the network has no more messages for the node, and the node has no deadline
+    Stalled,
+    /// the echo broadcast layer of the node detected inconsistent broadcasts
+    Equivocation(EchoError),
+}
+
+/// The result of the simulation
+#[derive(Debug)]
+pub struct SimulationReport<B, R> {
+    /// party index and outcome of each node, in the order nodes were added
+    pub outcomes: Vec<(PartyIndex, NodeOutcome<R>)>,
+    pub transcript: Vec<TranscriptEntry<B>>,
+    /// simulated duration of the run
+    pub elapsed: Duration,
+}
+
+/// Object safe interface of drivers of any protocol with messages of type `B` and results convertible to `R`
+trait SimulatedDriver<B, R> {
+    fn start(&mut self, now: Instant) -> Progress<B, R>;
+    fn handle_message(&mut self, message: InputMessage<B>, now: Instant) -> Progress<B, R>;
+    fn handle_timeout(&mut self, now: Instant) -> Option<R>;
+    fn deadline(&self) -> Option<Instant>;
+    fn resend_deadline(&self) -> Option<Instant>;
+    fn request_resend(&mut self, now: Instant) -> Vec<OutputMessage<B>>;
+
+    /// The earliest of both deadlines
+    fn wake_up(&self) -> Option<Instant> {
+        match (self.deadline(), self.resend_deadline()) {
+            (Some(deadline), Some(resend)) => Some(deadline.min(resend)),
+            (deadline, resend) => deadline.or(resend),
+        }
+    }
+}
+
+struct Progress<B, R> {
+    output: Vec<OutputMessage<B>>,
+    outcome: Option<NodeOutcome<R>>,
+}
+
+impl<T, B, R> SimulatedDriver<B, R> for Driver<T>
+where
+    T: StateMachineTraits<InMsg = InputMessage<B>, OutMsg = OutputMessage<B>>,
+    R: From<Result<T::FinalState, T::ErrorState>>,
+{
+    fn start(&mut self, now: Instant) -> Progress<B, R> {
+        progress(Driver::start(self, now))
+    }
+
+    fn handle_message(&mut self, message: InputMessage<B>, now: Instant) -> Progress<B, R> {
+        progress(Driver::handle_message(self, message, now))
+    }
+
+    fn handle_timeout(&mut self, now: Instant) -> Option<R> {
+        Driver::handle_timeout(self, now).map(R::from)
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        Driver::deadline(self)
+    }
+
+    fn resend_deadline(&self) -> Option<Instant> {
+        Driver::resend_deadline(self)
+    }
+
+    fn request_resend(&mut self, now: Instant) -> Vec<OutputMessage<B>> {
+        Driver::request_resend(self, now)
+    }
+}
+
+/// Driver behind the echo broadcast layer of its party
+struct EchoNode<B, R> {
+    driver: Box<dyn SimulatedDriver<B, R>>,
+    layer: EchoBroadcast<B>,
+}
+
+impl<B: Echoable, R> EchoNode<B, R> {
+    fn wrap(&self, progress: Progress<B, R>) -> Progress<Echoed<B>, R> {
+        Progress {
+            output: progress
+                .output
+                .into_iter()
+                .map(|msg| self.layer.send(msg))
+                .collect(),
+            outcome: progress.outcome,
+        }
+    }
+}
+
+impl<B: Echoable, R> SimulatedDriver<Echoed<B>, R> for EchoNode<B, R> {
+    fn start(&mut self, now: Instant) -> Progress<Echoed<B>, R> {
+        let progress = self.driver.start(now);
+        self.wrap(progress)
+    }
+
+    fn handle_message(
+        &mut self,
+        message: InputMessage<Echoed<B>>,
+        now: Instant,
+    ) -> Progress<Echoed<B>, R> {
+        let step = match self.layer.receive(message) {
+            Ok(step) => step,
+            Err(e) => {
+                return Progress {
+                    output: Vec::new(),
+                    outcome: Some(NodeOutcome::Equivocation(e)),
+                }
+            }
+        };
+        let mut output = step.output;
+        for message in step.deliver {
+            let progress = self.driver.handle_message(message, now);
+            let progress = self.wrap(progress);
+            output.extend(progress.output);
+            if progress.outcome.is_some() {
+                return Progress {
+                    output,
+                    outcome: progress.outcome,
+                };
+            }
+        }
+        Progress {
+            output,
+            outcome: None,
+        }
+    }
+
+    fn handle_timeout(&mut self, now: Instant) -> Option<R> {
+        self.driver.handle_timeout(now)
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.driver.deadline()
+    }
+
+    fn resend_deadline(&self) -> Option<Instant> {
+        self.driver.resend_deadline()
+    }
+
+    fn request_resend(&mut self, now: Instant) -> Vec<OutputMessage<Echoed<B>>> {
+        let output = self.driver.request_resend(now);
+        output.into_iter().map(|msg| self.layer.send(msg)).collect()
+    }
+}
+
+fn progress<T, B, R, E>(step: Result<Step<T>, E>) -> Progress<B, R>
+where
+    T: StateMachineTraits<OutMsg = OutputMessage<B>>,
+    R: From<Result<T::FinalState, T::ErrorState>>,
+{
+    match step {
+        Ok(step) => Progress {
+            output: step.output,
+            outcome: step.outcome.map(|o| NodeOutcome::Finished(R::from(o))),
+        },
+        Err(_) => Progress {
+            output: Vec::new(),
+            outcome: Some(NodeOutcome::Overflow),
+        },
+    }
+}
+
+struct Node<B, R> {
+    party: PartyIndex,
+    accepts: fn(&B) -> bool,
+    driver: Box<dyn SimulatedDriver<B, R>>,
+    outcome: Option<NodeOutcome<R>>,
+}
+
+/// message in flight
+struct Delivery<B> {
+    node: usize,
+    message: InputMessage<B>,
+}
+
+/// Simulated network of nodes running protocols with message body `B` and results convertible to `R`
+pub struct Simulation<B, R> {
+    conditions: NetworkConditions,
+    rng: StdRng,
+    nodes: Vec<Node<B, R>>,
+    origin: Instant,
+    now: Duration,
+    // deliveries ordered by time and then by the order of sending
+    queue: BinaryHeap<Reverse<(Duration, u64)>>,
+    in_flight: HashMap<u64, Delivery<B>>,
+    seq: u64,
+    // the latest delivery time on each link, which keeps links FIFO
+    links: HashMap<(PartyIndex, usize), Duration>,
+    // alterations of messages of each sender, given the recipient
+    tampering: HashMap<PartyIndex, fn(PartyIndex, &mut B)>,
+    transcript: Vec<TranscriptEntry<B>>,
+}
+
+impl<B, R> Simulation<B, R>
+where
+    B: Clone + Debug,
+{
+    /// Creates the network, refusing invalid conditions
+    pub fn new(conditions: NetworkConditions) -> Result<Self, ConditionsError> {
+        conditions.validate()?;
+        Ok(Simulation {
+            rng: StdRng::seed_from_u64(conditions.seed),
+            conditions,
+            nodes: Vec::new(),
+            origin: Instant::now(),
+            now: Duration::from_secs(0),
+            queue: BinaryHeap::new(),
+            in_flight: HashMap::new(),
+            seq: 0,
+            links: HashMap::new(),
+            tampering: HashMap::new(),
+            transcript: Vec::new(),
+        })
+    }
+
+    /// Adds the node which receives all messages addressed to its party
+    pub fn add_party<T>(&mut self, party: PartyIndex, driver: Driver<T>) -> &mut Self
+    where
+        T: StateMachineTraits<InMsg = InputMessage<B>, OutMsg = OutputMessage<B>> + 'static,
+        R: From<Result<T::FinalState, T::ErrorState>>,
+    {
+        self.add_filtered_party(party, driver, |_| true)
+    }
+
+    /// Adds the node which receives only messages addressed to its party and accepted by the filter
+    pub fn add_filtered_party<T>(
+        &mut self,
+        party: PartyIndex,
+        driver: Driver<T>,
+        accepts: fn(&B) -> bool,
+    ) -> &mut Self
+    where
+        T: StateMachineTraits<InMsg = InputMessage<B>, OutMsg = OutputMessage<B>> + 'static,
+        R: From<Result<T::FinalState, T::ErrorState>>,
+    {
+        self.nodes.push(Node {
+            party,
+            accepts,
+            driver: Box::new(driver),
+            outcome: None,
+        });
+        self
+    }
+
+    /// Alters each message of the sender before it enters the network, given its recipient
+    ///
+    /// Lets the sender e.g. send different broadcasts to different peers.
+    pub fn tamper(&mut self, sender: PartyIndex, tamper: fn(PartyIndex, &mut B)) -> &mut Self {
+        self.tampering.insert(sender, tamper);
+        self
+    }
+
+    /// Runs all nodes until each of them ends or stalls
+    pub fn run(mut self) -> SimulationReport<B, R> {
+        for i in 0..self.nodes.len() {
+            let progress = self.nodes[i].driver.start(self.origin);
+            self.apply(i, progress);
+        }
+
+        while self.nodes.iter().any(|n| n.outcome.is_none()) {
+            let next_delivery = self.queue.peek().map(|Reverse((at, _))| *at);
+            let next_deadline = self
+                .nodes
+                .iter()
+                .filter(|n| n.outcome.is_none())
+                .filter_map(|n| n.driver.wake_up())
+                .min()
+                .map(|d| d - self.origin);
+
+            match (next_delivery, next_deadline) {
+                (Some(at), Some(deadline)) if at < deadline => self.deliver_next(),
+                (_, Some(deadline)) => {
+                    self.now = self.now.max(deadline);
+                    self.expire_deadlines();
+                }
+                (Some(_), None) => self.deliver_next(),
+                (None, None) => break,
+            }
+        }
+
+        let elapsed = self.now;
+        SimulationReport {
+            outcomes: self
+                .nodes
+                .into_iter()
+                .map(|n| (n.party, n.outcome.unwrap_or(NodeOutcome::Stalled)))
+                .collect(),
+            transcript: self.transcript,
+            elapsed,
+        }
+    }
+
+    /// times out nodes whose deadlines expired, and sends requests of nodes whose resend deadlines are reached
+    fn expire_deadlines(&mut self) {
+        let now = self.origin + self.now;
+        for i in 0..self.nodes.len() {
+            let node = &mut self.nodes[i];
+            if node.outcome.is_some() {
+                continue;
+            }
+            if let Some(outcome) = node.driver.handle_timeout(now) {
+                log::debug!("simulator: party {} timed out", node.party);
+                node.outcome = Some(NodeOutcome::TimedOut(outcome));
+            } else if matches!(node.driver.resend_deadline(), Some(deadline) if deadline <= now) {
+                let output = node.driver.request_resend(now);
+                self.apply(
+                    i,
+                    Progress {
+                        output,
+                        outcome: None,
+                    },
+                );
+            }
+        }
+    }
+
+    fn deliver_next(&mut self) {
+        let Reverse((at, seq)) = self.queue.pop().expect("the queue is not empty");
+        let delivery = self
+            .in_flight
+            .remove(&seq)
+            .expect("each queued delivery is in flight");
+        self.now = at;
+        self.transcript.push(TranscriptEntry {
+            at,
+            sender: delivery.message.sender,
+            recipient: self.nodes[delivery.node].party,
+            node: delivery.node,
+            body: delivery.message.body.clone(),
+            fate: Fate::Delivered,
+        });
+        if self.nodes[delivery.node].outcome.is_none() {
+            let progress = self.nodes[delivery.node]
+                .driver
+                .handle_message(delivery.message, self.origin + at);
+            self.apply(delivery.node, progress);
+        }
+    }
+
+    /// sends out the output of the node and records its outcome
+    fn apply(&mut self, node: usize, progress: Progress<B, R>) {
+        let sender = self.nodes[node].party;
+        for message in progress.output {
+            let recipients = self
+                .nodes
+                .iter()
+                .enumerate()
+                .filter(|(_, n)| match &message.recipient {
+                    Address::Peer(p) => n.party == *p,
+                    Address::Broadcast => n.party != sender,
+                })
+                .filter(|(_, n)| (n.accepts)(&message.body))
+                .map(|(i, _)| i)
+                .collect::<Vec<_>>();
+            for recipient in recipients {
+                self.send(sender, recipient, &message);
+            }
+        }
+        if progress.outcome.is_some() {
+            self.nodes[node].outcome = progress.outcome;
+        }
+    }
+
+    /// decides the fate of the message and schedules its delivery
+    fn send(&mut self, sender: PartyIndex, node: usize, message: &OutputMessage<B>) {
+        let recipient = self.nodes[node].party;
+        let mut body = message.body.clone();
+        if let Some(tamper) = self.tampering.get(&sender) {
+            tamper(recipient, &mut body);
+        }
+        let now = self.now;
+        let fate = if self
+            .conditions
+            .partitions
+            .iter()
+            .any(|p| p.separates(sender, recipient, now))
+        {
+            Fate::Partitioned
+        } else if self.rng.gen_bool(self.conditions.loss_rate) {
+            Fate::Lost
+        } else {
+            Fate::Delivered
+        };
+        if fate != Fate::Delivered {
+            log::debug!("simulator: message {} -> {} {:?}", sender, recipient, fate);
+            self.transcript.push(TranscriptEntry {
+                at: now,
+                sender,
+                recipient,
+                node,
+                body,
+                fate,
+            });
+            return;
+        }
+
+        let copies = if self.rng.gen_bool(self.conditions.duplication_rate) {
+            2
+        } else {
+            1
+        };
+        for _ in 0..copies {
+            let mut at = now + self.delay();
+            if !self.conditions.reorder {
+                let last = self.links.entry((sender, node)).or_insert(at);
+                at = at.max(*last);
+                *last = at;
+            }
+            self.seq += 1;
+            self.queue.push(Reverse((at, self.seq)));
+            self.in_flight.insert(
+                self.seq,
+                Delivery {
+                    node,
+                    message: InputMessage {
+                        sender,
+                        header: message.header,
+                        body: body.clone(),
+                    },
+                },
+            );
+        }
+    }
+
+    fn delay(&mut self) -> Duration {
+        let (min, max) = (self.conditions.min_delay, self.conditions.max_delay);
+        if max == min {
+            return min;
+        }
+        let micros = self
+            .rng
+            .gen_range(min.as_micros() as u64, max.as_micros() as u64 + 1);
+        Duration::from_micros(micros)
+    }
+}
+
+impl<B, R> Simulation<Echoed<B>, R>
+where
+    B: Echoable + Clone + Debug + 'static,
+    R: 'static,
+{
+    /// Adds the node which receives all messages addressed to its party through the echo broadcast layer of the party
+    pub fn add_echoed_party<T>(
+        &mut self,
+        party: PartyIndex,
+        driver: Driver<T>,
+        layer: EchoBroadcast<B>,
+    ) -> &mut Self
+    where
+        T: StateMachineTraits<InMsg = InputMessage<B>, OutMsg = OutputMessage<B>> + 'static,
+        R: From<Result<T::FinalState, T::ErrorState>>,
+    {
+        self.nodes.push(Node {
+            party,
+            accepts: |_| true,
+            driver: Box::new(EchoNode {
+                driver: Box::new(driver),
+                layer,
+            }),
+            outcome: None,
+        });
+        self
+    }
+}
+
+/// Violations of invariants of protocols
+#[derive(Debug, Error)]
+pub enum InvariantViolation {
+    #[error("party {party} did not finish: {outcome}")]
+    NotFinished { party: PartyIndex, outcome: String },
+    #[error("party {party} failed: {error}")]
+    ProtocolError { party: PartyIndex, error: String },
+    #[error("party {party} has different public key")]
+    PublicKeyMismatch { party: PartyIndex },
+    #[error("party {party} has outdated Paillier key of the recovered party")]
+    PaillierKeyMismatch { party: PartyIndex },
+    #[error("party {party} produced invalid signature")]
+    InvalidSignature { party: PartyIndex },
+    #[error("no party finished the protocol")]
+    NoResult,
+}
+
+/// Result of either role of resharing protocol, or of the machine running both roles
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
+pub enum ResharingResult {
+    Old(old_member::MachineResult),
+    New(new_member::MachineResult),
+    Member(member::MachineResult),
+}
+
+impl From<old_member::MachineResult> for ResharingResult {
+    fn from(result: old_member::MachineResult) -> Self {
+        ResharingResult::Old(result)
+    }
+}
+
+impl From<new_member::MachineResult> for ResharingResult {
+    fn from(result: new_member::MachineResult) -> Self {
+        ResharingResult::New(result)
+    }
+}
+
+impl From<member::MachineResult> for ResharingResult {
+    fn from(result: member::MachineResult) -> Self {
+        ResharingResult::Member(result)
+    }
+}
+
+/// Result of either role of share recovery protocol
+#[derive(Debug)]
+pub enum RecoveryResult {
+    Lost(lost_party::MachineResult),
+    Holder(holder::MachineResult),
+}
+
+impl From<lost_party::MachineResult> for RecoveryResult {
+    fn from(result: lost_party::MachineResult) -> Self {
+        RecoveryResult::Lost(result)
+    }
+}
+
+impl From<holder::MachineResult> for RecoveryResult {
+    fn from(result: holder::MachineResult) -> Self {
+        RecoveryResult::Holder(result)
+    }
+}
+
+/// takes successful results of all nodes
+fn finished<'a, B, R, F, E>(
+    report: &'a SimulationReport<B, R>,
+    result: impl Fn(&'a R) -> Result<&'a F, &'a E>,
+) -> Result<Vec<(PartyIndex, &'a F)>, InvariantViolation>
+where
+    R: Debug,
+    E: Debug + 'a,
+{
+    report
+        .outcomes
+        .iter()
+        .map(|(party, outcome)| match outcome {
+            NodeOutcome::Finished(r) => {
+                result(r)
+                    .map(|f| (*party, f))
+                    .map_err(|e| InvariantViolation::ProtocolError {
+                        party: *party,
+                        error: format!("{:?}", e),
+                    })
+            }
+            other => Err(InvariantViolation::NotFinished {
+                party: *party,
+                outcome: format!("{:?}", other),
+            }),
+        })
+        .collect()
+}
+
+/// Checks that all parties of keygen succeeded with the same public key, and returns this key
+pub fn check_keygen<B>(
+    report: &SimulationReport<B, keygen::MachineResult>,
+) -> Result<GE, InvariantViolation> {
+    let results = finished(report, |r| r.as_ref())?;
+    let public_key = results
+        .first()
+        .ok_or(InvariantViolation::NoResult)?
+        .1
+        .multiparty_shared_info
+        .public_key
+        .clone();
+    match results
+        .iter()
+        .find(|(_, f)| f.multiparty_shared_info.public_key != public_key)
+    {
+        Some((party, _)) => Err(InvariantViolation::PublicKeyMismatch { party: *party }),
+        None => Ok(public_key),
+    }
+}
+
+/// Checks that all parties of signing succeeded, and their signatures pass the verification with the public key
+pub fn check_signing<B>(
+    report: &SimulationReport<B, signature::MachineResult>,
+    public_key: &GE,
+) -> Result<(), InvariantViolation> {
+    for (party, signed) in finished(report, |r| r.as_ref())? {
+        let signature = Signature {
+            r: signed.r.clone(),
+            s: signed.s.clone(),
+        };
+        if !signature.verify(public_key, &signed.hash) {
+            return Err(InvariantViolation::InvalidSignature { party });
+        }
+    }
+    Ok(())
+}
+
+/// Checks that all nodes of resharing succeeded, and the new committee shares the same public key
+pub fn check_resharing<B>(
+    report: &SimulationReport<B, ResharingResult>,
+    public_key: &GE,
+) -> Result<(), InvariantViolation> {
+    for (party, outcome) in &report.outcomes {
+        let error = match outcome {
+            NodeOutcome::Finished(ResharingResult::New(Ok(f))) => {
+                if f.info.public_key != *public_key {
+                    return Err(InvariantViolation::PublicKeyMismatch { party: *party });
+                }
+                continue;
+            }
+            NodeOutcome::Finished(ResharingResult::Member(Ok(f))) => match &f.info {
+                Some(info) if info.public_key != *public_key => {
+                    return Err(InvariantViolation::PublicKeyMismatch { party: *party });
+                }
+                _ => continue,
+            },
+            NodeOutcome::Finished(ResharingResult::Old(Ok(_))) => continue,
+            NodeOutcome::Finished(ResharingResult::New(Err(e))) => format!("{:?}", e),
+            NodeOutcome::Finished(ResharingResult::Old(Err(e))) => format!("{:?}", e),
+            NodeOutcome::Finished(ResharingResult::Member(Err(e))) => format!("{:?}", e),
+            other => {
+                return Err(InvariantViolation::NotFinished {
+                    party: *party,
+                    outcome: format!("{:?}", other),
+                })
+            }
+        };
+        return Err(InvariantViolation::ProtocolError {
+            party: *party,
+            error,
+        });
+    }
+    Ok(())
+}
+
+/// Checks that all nodes of share recovery succeeded with the same public key, and all holders know the new Paillier key of the lost party
+pub fn check_recovery<B>(
+    report: &SimulationReport<B, RecoveryResult>,
+    public_key: &GE,
+) -> Result<(), InvariantViolation> {
+    let results = finished(report, |r| match r {
+        RecoveryResult::Lost(r) => r.as_ref().map(|f| &f.info),
+        RecoveryResult::Holder(r) => r.as_ref().map(|f| &f.info),
+    })?;
+    if let Some((party, _)) = results
+        .iter()
+        .find(|(_, info)| info.public_key != *public_key)
+    {
+        return Err(InvariantViolation::PublicKeyMismatch { party: *party });
+    }
+    let lost = report
+        .outcomes
+        .iter()
+        .find_map(|(_, outcome)| match outcome {
+            NodeOutcome::Finished(RecoveryResult::Lost(Ok(f))) => Some(&f.info),
+            _ => None,
+        })
+        .ok_or(InvariantViolation::NoResult)?;
+    match results.iter().find(|(party, info)| {
+        *party != lost.own_party_index
+            && info.party_he_keys.get(&lost.own_party_index) != Some(&lost.own_he_keys.ek)
+    }) {
+        Some((party, _)) => Err(InvariantViolation::PaillierKeyMismatch { party: *party }),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::keygen::{
+        KeyGeneratorTraits, MultiPartyInfo, SecretKeyLoader, SecretKeyLoaderError,
+    };
+    use crate::limits::Limits;
+    use crate::messages::signing::Message;
+    use crate::protocol::SessionId;
+    use crate::resharing::new_member::KeyResharingTraits as NewKeyResharingTraits;
+    use crate::resharing::old_member::KeyResharingTraits as OldKeyResharingTraits;
+    use crate::signature::SigningTraits;
+    use crate::types::{DigestExt, InitialKeys, InitialPublicKeys, Parameters, FE};
+    use crate::zk_range_proofs::ZkpSetup;
+    use paillier::DecryptionKey;
+    use sha2::{Digest, Sha256};
+    use std::fmt;
+    use std::fs;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    type SigningSimulation = Simulation<Message, signature::MachineResult>;
+
+    /// Hands out secrets of the party kept in memory
+    struct KeyLoader(InitialKeys);
+
+    impl Debug for KeyLoader {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "KeyLoader({})", self.0)
+        }
+    }
+
+    impl SecretKeyLoader for KeyLoader {
+        fn get_initial_secret(&self) -> Result<Box<FE>, SecretKeyLoaderError> {
+            Ok(Box::new(self.0.u_i.clone()))
+        }
+
+        fn get_paillier_secret(&self) -> Result<Box<DecryptionKey>, SecretKeyLoaderError> {
+            Ok(Box::new(self.0.paillier_keys.dk.clone()))
+        }
+    }
+
+    pub fn load_setups() -> anyhow::Result<Vec<ZkpSetup>> {
+        let path = Path::new("../tests/data/rp-setups.json");
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// infos of parties 0, 1 and 2 holding the key of test data
+    pub fn load_infos() -> anyhow::Result<Vec<MultiPartyInfo>> {
+        (0..=2)
+            .map(|i| {
+                let path = format!("../tests/data/zkrp-keys.{}.json", i);
+                Ok(serde_json::from_str::<MultiPartyInfo>(
+                    &fs::read_to_string(Path::new(&path))?,
+                )?)
+            })
+            .collect()
+    }
+
+    /// drivers of parties 0, 1 and 2 generating a new key with range proof setups from test data
+    pub fn keygen_drivers() -> anyhow::Result<Vec<(PartyIndex, Driver<KeyGeneratorTraits>)>> {
+        let params = Parameters::new(2, 3)?;
+        let parties = (0..=2).map(PartyIndex::from).collect::<Vec<_>>();
+        let zkp_setups = load_setups()?;
+        let session_id = SessionId::random();
+        parties
+            .iter()
+            .zip(zkp_setups)
+            .map(|(party, setup)| {
+                let keys = InitialKeys::random();
+                let phase = keygen::Phase1::new(
+                    session_id,
+                    &params,
+                    InitialPublicKeys::from(&keys),
+                    Some(setup),
+                    &parties,
+                    *party,
+                    Arc::new(Box::new(KeyLoader(keys))),
+                    None,
+                    Limits::default(),
+                )?;
+                Ok((*party, Driver::new(Box::new(phase))))
+            })
+            .collect()
+    }
+
+    /// drivers of given parties signing with keys from test data, and the public key
+    #[allow(clippy::type_complexity)]
+    pub fn signing_drivers(
+        signers: &[usize],
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<(Vec<(PartyIndex, Driver<SigningTraits>)>, GE)> {
+        let mut hasher = Sha256::new();
+        hasher.update("MPC TS simulator tests");
+        let msg_hash = FE::from(hasher.result_bigint());
+        let infos = load_infos()?;
+        let signing_parties = signers
+            .iter()
+            .map(|i| infos[*i].own_party_index)
+            .collect::<Vec<_>>();
+        let session_id = SessionId::random();
+        let drivers = signers
+            .iter()
+            .map(|i| {
+                let info = &infos[*i];
+                let phase = signature::Phase1::new(
+                    session_id,
+                    msg_hash.clone(),
+                    info.clone(),
+                    &signing_parties,
+                    timeout,
+                    Limits::default(),
+                )?;
+                Ok((info.own_party_index, Driver::new(Box::new(phase))))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok((drivers, infos[0].public_key.clone()))
+    }
+
+    fn signing(
+        conditions: NetworkConditions,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<(SigningSimulation, GE)> {
+        let (drivers, public_key) = signing_drivers(&[0, 2], timeout)?;
+        let mut simulation = Simulation::new(conditions)?;
+        for (party, driver) in drivers {
+            simulation.add_party(party, driver);
+        }
+        Ok((simulation, public_key))
+    }
+
+    #[test]
+    fn signing_on_unreliable_network() -> anyhow::Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let conditions = NetworkConditions {
+            seed: 7,
+            min_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(50),
+            reorder: true,
+            duplication_rate: 0.3,
+            ..NetworkConditions::default()
+        };
+        let (simulation, public_key) = signing(conditions.clone(), None)?;
+        let report = simulation.run();
+        check_signing(&report, &public_key)?;
+
+        // same seed gives same schedule
+        let (simulation, _) = signing(conditions, None)?;
+        let schedule = |report: &SimulationReport<Message, _>| {
+            report
+                .transcript
+                .iter()
+                .map(|e| (e.at, e.sender, e.node, e.fate))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(schedule(&simulation.run()), schedule(&report));
+        Ok(())
+    }
+
+    #[test]
+    fn partition_causes_timeout() -> anyhow::Result<()> {
+        let conditions = NetworkConditions {
+            partitions: vec![Partition {
+                isolated: vec![PartyIndex::from(2)].into_iter().collect(),
+                from: Duration::from_secs(0),
+                until: Duration::from_secs(60),
+            }],
+            ..NetworkConditions::default()
+        };
+        let (simulation, public_key) = signing(conditions, Some(Duration::from_secs(10)))?;
+        let report = simulation.run();
+        assert!(report
+            .outcomes
+            .iter()
+            .all(|(_, outcome)| matches!(outcome, NodeOutcome::TimedOut(Err(_)))));
+        assert!(report
+            .transcript
+            .iter()
+            .all(|e| e.fate == Fate::Partitioned));
+        assert_eq!(report.elapsed, Duration::from_secs(10));
+        assert!(matches!(
+            check_signing(&report, &public_key),
+            Err(InvariantViolation::NotFinished { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn lost_messages_requested_again() -> anyhow::Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let conditions = NetworkConditions {
+            seed: 3,
+            min_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(50),
+            loss_rate: 0.2,
+            ..NetworkConditions::default()
+        };
+        let (drivers, public_key) = signing_drivers(&[0, 2], Some(Duration::from_secs(60)))?;
+        let mut simulation = Simulation::new(conditions)?;
+        for (party, driver) in drivers {
+            simulation.add_party(party, driver.with_resend_interval(Duration::from_secs(1)));
+        }
+        let report = simulation.run();
+        check_signing(&report, &public_key)?;
+        assert!(report.transcript.iter().any(|e| e.fate == Fate::Lost));
+        assert!(report
+            .transcript
+            .iter()
+            .any(|e| e.fate == Fate::Delivered && matches!(e.body, Message::Resend(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_conditions_refused() {
+        let refused = |conditions: NetworkConditions| {
+            Simulation::<Message, signature::MachineResult>::new(conditions).err()
+        };
+        assert_eq!(
+            refused(NetworkConditions {
+                loss_rate: 1.5,
+                ..NetworkConditions::default()
+            }),
+            Some(ConditionsError::InvalidRate {
+                name: "loss rate",
+                rate: 1.5
+            })
+        );
+        assert!(matches!(
+            refused(NetworkConditions {
+                duplication_rate: f64::NAN,
+                ..NetworkConditions::default()
+            }),
+            Some(ConditionsError::InvalidRate {
+                name: "duplication rate",
+                ..
+            })
+        ));
+        assert_eq!(
+            refused(NetworkConditions {
+                min_delay: Duration::from_millis(2),
+                max_delay: Duration::from_millis(1),
+                ..NetworkConditions::default()
+            }),
+            Some(ConditionsError::InvalidDelay {
+                min: Duration::from_millis(2),
+                max: Duration::from_millis(1),
+            })
+        );
+        assert!(refused(NetworkConditions {
+            loss_rate: 1.0,
+            min_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(1),
+            ..NetworkConditions::default()
+        })
+        .is_none());
+    }
+
+    #[test]
+    fn keygen_on_unreliable_network() -> anyhow::Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let mut simulation = Simulation::new(NetworkConditions {
+            seed: 11,
+            min_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(50),
+            reorder: true,
+            duplication_rate: 0.3,
+            ..NetworkConditions::default()
+        })?;
+        for (party, driver) in keygen_drivers()? {
+            simulation.add_party(party, driver);
+        }
+        let report = simulation.run();
+        check_keygen(&report)?;
+        for (_, outcome) in &report.outcomes {
+            match outcome {
+                NodeOutcome::Finished(Ok(f)) => {
+                    assert!(f.multiparty_shared_info.range_proof_setups.is_some())
+                }
+                other => panic!("unexpected outcome {:?}", other),
+            }
+        }
+
+        // one party which fails breaks the invariant
+        let mut report = report;
+        report.outcomes[1].1 = NodeOutcome::Stalled;
+        assert!(matches!(
+            check_keygen(&report),
+            Err(InvariantViolation::NotFinished { party, .. }) if party == PartyIndex::from(1)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn resharing_with_separate_roles() -> anyhow::Result<()> {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let infos = load_infos()?;
+        let zkp_setups = load_setups()?;
+        let public_key = infos[0].public_key.clone();
+        let new_params = Parameters::new(2, 3)?;
+        let old_committee = (0..=2).map(PartyIndex::from).collect::<Vec<_>>();
+        let new_committee = (1..=3).map(PartyIndex::from).collect::<Vec<_>>();
+        let session_id = SessionId::random();
+
+        let mut simulation = Simulation::<_, ResharingResult>::new(NetworkConditions {
+            seed: 5,
+            min_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(20),
+            reorder: true,
+            ..NetworkConditions::default()
+        })?;
+        for info in &infos {
+            let phase = old_member::Phase1::new(
+                session_id,
+                info,
+                &new_params,
+                &old_committee,
+                &new_committee,
+                None,
+                Limits::default(),
+            )?;
+            simulation.add_filtered_party(
+                info.own_party_index,
+                Driver::<OldKeyResharingTraits>::new(Box::new(phase)),
+                old_member::is_message_to_committee,
+            );
+        }
+        for (party, setup) in new_committee.iter().zip(&zkp_setups[1..]) {
+            let phase = new_member::Phase1::new(
+                session_id,
+                &infos[0].key_params,
+                &new_params,
+                &old_committee,
+                &new_committee,
+                *party,
+                Some(setup.clone()),
+                None,
+                Limits::default(),
+            )?;
+            simulation.add_filtered_party(
+                *party,
+                Driver::<NewKeyResharingTraits>::new(Box::new(phase)),
+                new_member::is_message_to_committee,
+            );
+        }
+        let report = simulation.run();
+        check_resharing(&report, &public_key)?;
+        assert_eq!(report.outcomes.len(), 6);
+
+        // the new committee has to share the original key
+        assert!(matches!(
+            check_resharing(&report, &GE::generator().to_point()),
+            Err(InvariantViolation::PublicKeyMismatch { .. })
+        ));
+        Ok(())
+    }
+}
@@ -4,7 +4,8 @@ pub(crate) use curv::arithmetic::traits::Converter;
 pub(crate) use curv::arithmetic::{BigInt, Integer, One, Samplable, Zero};
 
 use crate::keygen::KeygenError;
-use crate::protocol::PartyIndex;
+use crate::protocol::{PartyIndex, Session};
+use sha2::{Digest, Sha256};
 
 use curv::cryptographic_primitives::commitments::hash_commitment::HashCommitment;
 use curv::cryptographic_primitives::commitments::traits::Commitment;
@@ -15,9 +16,6 @@ pub type Scalar = curv::elliptic::curves::Scalar<Secp256k1>;
 pub(crate) type GE = Point;
 pub(crate) type FE = Scalar;
 
-pub(crate) use curv::cryptographic_primitives::proofs::sigma_dlog::DLogProof;
-pub(crate) type CurvDLogProofType = DLogProof<Secp256k1, sha2::Sha256>;
-
 pub type SecretShares =
     curv::cryptographic_primitives::secret_sharing::feldman_vss::SecretShares<Secp256k1>;
 
@@ -25,9 +23,8 @@ pub(crate) use curv::cryptographic_primitives::secret_sharing::feldman_vss::Veri
 pub(crate) type CurvVerifiableSS = VerifiableSS<Secp256k1>;
 
 pub(crate) use curv::cryptographic_primitives::proofs::sigma_correct_homomorphic_elgamal_enc::{
-    HomoELGamalProof, HomoElGamalStatement, HomoElGamalWitness,
+    HomoElGamalStatement, HomoElGamalWitness,
 };
-pub(crate) type CurvHomoElGamalProof = HomoELGamalProof<Secp256k1, sha2::Sha256>;
 
 pub(crate) use curv::cryptographic_primitives::hashing::DigestExt;
 
@@ -229,7 +226,8 @@ impl Signature {
 
 ///  Non-malleable commitment scheme
 ///
-/// Commitment scheme based on hash commitment
+/// Commitment scheme based on hash commitment. The committed value is bound to the session of the protocol,
+/// so that the decommitment is not accepted in another session.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct CommitmentScheme {
     pub comm: BigInt,
@@ -239,39 +237,40 @@ pub(crate) struct CommitmentScheme {
 impl CommitmentScheme {
     /// creates commitment scheme from EC group element
     #[allow(non_snake_case)]
-    pub fn from_GE(elem: &GE) -> Self {
-        let decomm = BigInt::sample(256);
-        let comm = HashCommitment::<sha2::Sha256>::create_commitment_with_user_defined_randomness(
-            &BigInt::from_bytes(&elem.to_bytes(true)),
-            &decomm,
-        );
-        CommitmentScheme { comm, decomm }
+    pub fn from_GE(session: &Session, elem: &GE) -> Self {
+        Self::from_BigInt(session, &BigInt::from_bytes(&elem.to_bytes(true)))
     }
 
     /// creates commitment scheme from `BigInt`
     #[allow(non_snake_case)]
-    pub fn from_BigInt(message: &BigInt) -> Self {
+    pub fn from_BigInt(session: &Session, message: &BigInt) -> Self {
         let decomm = BigInt::sample(256);
         let comm = HashCommitment::<sha2::Sha256>::create_commitment_with_user_defined_randomness(
-            message, &decomm,
+            &Self::bind(session, message),
+            &decomm,
         );
         CommitmentScheme { comm, decomm }
     }
 
     /// verifies commitment using EC group element
-    pub fn verify_commitment(&self, elem: &GE) -> bool {
+    pub fn verify_commitment(&self, session: &Session, elem: &GE) -> bool {
         is_valid_curve_point(elem)
-            && HashCommitment::<sha2::Sha256>::create_commitment_with_user_defined_randomness(
-                &BigInt::from_bytes(&elem.to_bytes(true)),
-                &self.decomm,
-            ) == self.comm
+            && self.verify_hash(session, &BigInt::from_bytes(&elem.to_bytes(true)))
     }
 
     /// verifies commitment using `BigInt` value
-    pub fn verify_hash(&self, hash: &BigInt) -> bool {
+    pub fn verify_hash(&self, session: &Session, hash: &BigInt) -> bool {
         HashCommitment::<sha2::Sha256>::create_commitment_with_user_defined_randomness(
-            hash,
+            &Self::bind(session, hash),
             &self.decomm,
         ) == self.comm
     }
+
+    fn bind(session: &Session, message: &BigInt) -> BigInt {
+        let digest = Sha256::new()
+            .chain(session.to_bytes())
+            .chain(message.to_bytes())
+            .finalize();
+        BigInt::from_bytes(&digest)
+    }
 }
@@ -1,4 +1,5 @@
-use crate::types::GE;
+use crate::types::{BigInt, FE, GE};
+use serde::Serialize;
 
 pub fn is_valid_curve_point(pk: &GE) -> bool {
     GE::from_bytes(&pk.to_bytes(false)).is_ok()
@@ -20,9 +21,35 @@ where
     }
 }
 
+/// Serializes the phase of a protocol as the variant `phase` of the enum of protocol's phases
+pub fn phase_snapshot<S: Serialize>(phase: &str, state: &S) -> Option<serde_json::Value> {
+    match serde_json::to_value(state) {
+        Ok(value) => {
+            let mut variant = serde_json::Map::new();
+            variant.insert(phase.to_string(), value);
+            Some(serde_json::Value::Object(variant))
+        }
+        Err(e) => {
+            log::error!("{} cannot be serialized: {}", phase, e);
+            None
+        }
+    }
+}
+
+/// Lagrange coefficient of the point `x` among `points`, for the interpolation of the polynomial at `at`
+pub fn lagrange_coefficient(points: &[u16], x: u16, at: u16) -> FE {
+    let to_fe = |p: u16| FE::from(&BigInt::from(p as u64));
+    let (x, at) = (to_fe(x), to_fe(at));
+    let (num, denom) = points.iter().map(|p| to_fe(*p)).filter(|p| *p != x).fold(
+        (FE::from(&BigInt::from(1)), FE::from(&BigInt::from(1))),
+        |(num, denom), p| (num * (&at - &p), denom * (&x - &p)),
+    );
+    num * denom.invert().expect("points are distinct")
+}
+
 #[cfg(test)]
 mod tests {
-    use super::is_valid_curve_point;
+    use super::{is_valid_curve_point, lagrange_coefficient};
     use crate::types::{BigInt, FE, GE};
     use crate::types::{Converter, Samplable};
     use std::ops::Deref;
@@ -45,6 +72,18 @@ mod tests {
         v
     }
 
+    #[test]
+    fn lagrange_coefficients_interpolate_line() {
+        // f(x) = 3 + 2x, known at points 1 and 2
+        let points = [1u16, 2];
+        let f = |x: u64| FE::from(&BigInt::from(3 + 2 * x));
+        for at in 0..=3u16 {
+            let interpolated = lagrange_coefficient(&points, 1, at) * f(1)
+                + lagrange_coefficient(&points, 2, at) * f(2);
+            assert_eq!(interpolated, f(at as u64));
+        }
+    }
+
     #[test]
     fn pk_utilities() {
         let pk = GE::generator() * FE::random();
@@ -0,0 +1,1505 @@
+//! Compact binary encoding of protocol messages
+//!
+//! Messages of the crate are serde types, and their JSON form inflates Paillier ciphertexts and range proofs, which are the bulk of [`MessageA`] and [`MessageB`].
+//! The module defines the binary form of every message of key generation, signing, resharing, key refresh and share recovery, along with their headers and [`OutputMessage`] / [`InputMessage`] wrappers.
+//!
+//! The encoding has no self-describing metadata, and every field has either a fixed width or a fixed-width length prefix:
+//! * scalars take 32 bytes, curve points take 33 bytes in the compressed form
+//! * big integers take a sign byte, a big-endian `u16` length and the big-endian magnitude, limited by [`MAX_BIGINT_BYTES`]
+//! * vectors take a big-endian `u16` length, limited by [`MAX_VEC_LEN`], followed by their elements
+//! * options and enums take one tag byte followed by the payload of the variant
+//!
+//! [`to_bytes`] prepends the version of the encoding, [`WIRE_VERSION`], and [`from_bytes`] refuses other versions, truncated input and trailing bytes.
+//! Length limits are checked before anything is allocated, so that a peer cannot make the decoder allocate more than the size of its input.
+//! The encoder checks the same length limits and fails instead of producing bytes which the decoder would refuse.
+//! The decoder also applies [`Limits`] while it reads the input: big integers cannot be longer than the longest number a message may carry,
+//! and vectors cannot be longer than the longest vector of a message. Decoded protocol messages and lengths of their encodings are checked against the same limits
+//! before they are returned. [`from_bytes`] applies default limits, [`from_bytes_with_limits`] and [`WireCodec::with_limits`] apply the given ones.
+//!
+//! [`WireCodec`] implements `tokio-util` codec traits over the same encoding, with frames prefixed by their big-endian `u32` length, limited by [`MAX_FRAME_LEN`] by default.
+//!
+//! [`MessageA`]: ../zk_range_proofs/struct.MessageA.html
+//! [`MessageB`]: ../zk_range_proofs/struct.MessageB.html
+//! [`OutputMessage`]: ../protocol/struct.OutputMessage.html
+//! [`InputMessage`]: ../protocol/struct.InputMessage.html
+//! [`to_bytes`]: fn.to_bytes.html
+//! [`from_bytes`]: fn.from_bytes.html
+//! [`from_bytes_with_limits`]: fn.from_bytes_with_limits.html
+//! [`WireCodec::with_limits`]: struct.WireCodec.html#method.with_limits
+//! [`Limits`]: ../limits/struct.Limits.html
+//! [`WireCodec`]: struct.WireCodec.html
+//! [`WIRE_VERSION`]: constant.WIRE_VERSION.html
+//! [`MAX_BIGINT_BYTES`]: constant.MAX_BIGINT_BYTES.html
+//! [`MAX_VEC_LEN`]: constant.MAX_VEC_LEN.html
+//! [`MAX_FRAME_LEN`]: constant.MAX_FRAME_LEN.html
+#![allow(non_snake_case)]
+
+use crate::keygen::CorrectKeyProof;
+use crate::limits::{check_message_len, Bounded, LimitError, Limits};
+use crate::messages::{
+    keygen, recovery, refresh, resharing, signing, Abort, FactorProofs, FeldmanVSS, ModulusProofs,
+    Resend, MAX_ABORT_REASON_BYTES,
+};
+use crate::proofs::{DLogProof, HomoElGamalProof, SchnorrSignature};
+use crate::protocol::{
+    Address, InputMessage, MessageHeader, OutputMessage, PartyIndex, ProtocolId, Session, SessionId,
+};
+use crate::types::{BigInt, Converter, CurvVerifiableSS, Point, Scalar, Zero};
+use crate::zk_range_proofs::{
+    AliceProof, BobProof, BobProofExt, BobProofType, DLogProofs, MessageA, MessageB, ZkpPublicSetup,
+};
+use algorithms::dlog_proof::DlogProof;
+use algorithms::no_small_factor::NoSmallFactorProof;
+use algorithms::paillier_blum::PaillierBlumProof;
+use bytes::{Buf, BufMut, BytesMut};
+use curv::cryptographic_primitives::secret_sharing::feldman_vss::ShamirSecretSharing;
+use paillier::EncryptionKey;
+use std::marker::PhantomData;
+use thiserror::Error;
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Version of the binary encoding
+pub const WIRE_VERSION: u8 = 1;
+
+/// Maximal length of the magnitude of a big integer, in bytes
+///
+/// Fits Paillier ciphertexts of 4096 bit long modulus with a margin.
+pub const MAX_BIGINT_BYTES: usize = 1024;
+
+/// Maximal number of elements of a vector
+pub const MAX_VEC_LEN: usize = 1024;
+
+/// Default maximal length of a frame of [`WireCodec`](struct.WireCodec.html)
+pub const MAX_FRAME_LEN: usize = 1 << 20;
+
+/// Errors of decoding binary messages
+#[derive(Debug, Error)]
+pub enum WireError {
+    #[error("unexpected end of input")]
+    Truncated,
+    #[error("unsupported wire version {0}")]
+    UnsupportedVersion(u8),
+    #[error("unknown tag {tag} of {kind}")]
+    UnknownTag { kind: &'static str, tag: u8 },
+    #[error("length {len} of {kind} exceeds the limit {limit}")]
+    TooLong {
+        kind: &'static str,
+        len: usize,
+        limit: usize,
+    },
+    #[error("non-canonical encoding of a big integer")]
+    NonCanonicalBigInt,
+    #[error("invalid scalar")]
+    InvalidScalar,
+    #[error("invalid curve point")]
+    InvalidPoint,
+    #[error("invalid UTF-8 string")]
+    InvalidUtf8,
+    #[error("{0} unexpected trailing bytes")]
+    TrailingBytes(usize),
+    #[error(transparent)]
+    Limit(#[from] LimitError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Reads the binary encoding from a byte slice
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    limits: Limits,
+}
+
+impl<'a> Reader<'a> {
+    /// Creates the reader which applies default limits
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self::with_limits(bytes, Limits::default())
+    }
+
+    /// Creates the reader which refuses numbers and vectors longer than the limits allow
+    pub fn with_limits(bytes: &'a [u8], limits: Limits) -> Self {
+        Reader { bytes, limits }
+    }
+
+    /// Number of bytes which have not been read yet
+    pub fn remaining(&self) -> usize {
+        self.bytes.len()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], WireError> {
+        if len > self.bytes.len() {
+            return Err(WireError::Truncated);
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn byte(&mut self) -> Result<u8, WireError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], WireError> {
+        let mut result = [0u8; N];
+        result.copy_from_slice(self.take(N)?);
+        Ok(result)
+    }
+
+    fn length(&mut self, kind: &'static str, limit: usize) -> Result<usize, WireError> {
+        let len = u16::decode(self)? as usize;
+        if len > limit {
+            return Err(WireError::TooLong { kind, len, limit });
+        }
+        Ok(len)
+    }
+
+    /// Checks the decoded protocol message, which started when `start` bytes were remaining, against limits of the reader
+    fn checked<M: Bounded>(&self, msg: M, start: usize) -> Result<M, WireError> {
+        msg.check_limits(&self.limits)?;
+        check_message_len(start - self.remaining(), msg.max_bytes(&self.limits))?;
+        Ok(msg)
+    }
+}
+
+/// Types with the binary encoding
+pub trait Wire: Sized {
+    /// Appends the encoding of the value, fails on values beyond the limits of the decoder
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), WireError>;
+    fn decode(input: &mut Reader<'_>) -> Result<Self, WireError>;
+}
+
+/// Encodes the value, prefixed by the version of the encoding
+pub fn to_bytes<T: Wire>(value: &T) -> Result<Vec<u8>, WireError> {
+    let mut out = vec![WIRE_VERSION];
+    value.encode(&mut out)?;
+    Ok(out)
+}
+
+/// Decodes the value from bytes produced by [`to_bytes`](fn.to_bytes.html), applying default limits
+pub fn from_bytes<T: Wire>(bytes: &[u8]) -> Result<T, WireError> {
+    from_bytes_with_limits(bytes, &Limits::default())
+}
+
+/// Decodes the value from bytes produced by [`to_bytes`](fn.to_bytes.html), applying the limits
+pub fn from_bytes_with_limits<T: Wire>(bytes: &[u8], limits: &Limits) -> Result<T, WireError> {
+    let mut input = Reader::with_limits(bytes, *limits);
+    let version = input.byte()?;
+    if version != WIRE_VERSION {
+        return Err(WireError::UnsupportedVersion(version));
+    }
+    let value = T::decode(&mut input)?;
+    match input.remaining() {
+        0 => Ok(value),
+        n => Err(WireError::TrailingBytes(n)),
+    }
+}
+
+/// Writes the length prefix, refusing lengths which the decoder would refuse
+fn write_length(
+    kind: &'static str,
+    len: usize,
+    limit: usize,
+    out: &mut Vec<u8>,
+) -> Result<(), WireError> {
+    if len > limit {
+        return Err(WireError::TooLong { kind, len, limit });
+    }
+    (len as u16).encode(out)
+}
+
+fn write_tag(tag: u8, out: &mut Vec<u8>) {
+    out.push(tag);
+}
+
+impl Wire for u8 {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), WireError> {
+        out.push(*self);
+        Ok(())
+    }
+    fn decode(input: &mut Reader<'_>) -> Result<Self, WireError> {
+        input.byte()
+    }
+}
+
+impl Wire for bool {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), WireError> {
+        write_tag(*self as u8, out);
+        Ok(())
+    }
+    fn decode(input: &mut Reader<'_>) -> Result<Self, WireError> {
+        match input.byte()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => Err(WireError::UnknownTag { kind: "bool", tag }),
+        }
+    }
+}
+
+impl Wire for u16 {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), WireError> {
+        out.extend_from_slice(&self.to_be_bytes());
+        Ok(())
+    }
+    fn decode(input: &mut Reader<'_>) -> Result<Self, WireError> {
+        Ok(u16::from_be_bytes(input.array()?))
+    }
+}
+
+impl<const N: usize> Wire for [u8; N] {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), WireError> {
+        out.extend_from_slice(self);
+        Ok(())
+    }
+    fn decode(input: &mut Reader<'_>) -> Result<Self, WireError> {
+        input.array()
+    }
+}
+
+impl Wire for BigInt {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), WireError> {
+        let negative = self < &BigInt::zero();
+        // zero has the empty magnitude, as leading zeroes are not canonical
+        let magnitude = if self.is_zero() {
+            Vec::new()
+        } else if negative {
+            (-self).to_bytes()
+        } else {
+            self.to_bytes()
+        };
+        write_tag(negative as u8, out);
+        write_length("big integer", magnitude.len(), MAX_BIGINT_BYTES, out)?;
+        out.extend_from_slice(&magnitude);
+        Ok(())
+    }
+    fn decode(input: &mut Reader<'_>) -> Result<Self, WireError> {
+        let negative = match input.byte()? {
+            0 => false,
+            1 => true,
+            tag => return Err(WireError::UnknownTag { kind: "sign", tag }),
+        };
+        let limit = MAX_BIGINT_BYTES.min(input.limits.max_number_bytes());
+        let len = input.length("big integer", limit)?;
+        let magnitude = input.take(len)?;
+        // leading zeroes and negative zero would give another encoding of the same number
+        if magnitude.first() == Some(&0) || (negative && magnitude.is_empty()) {
+            return Err(WireError::NonCanonicalBigInt);
+        }
+        let n = BigInt::from_bytes(magnitude);
+        Ok(if negative { -n } else { n })
+    }
+}
+
+impl Wire for Scalar {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), WireError> {
+        out.extend_from_slice(&self.to_bytes());
+        Ok(())
+    }
+    fn decode(input: &mut Reader<'_>) -> Result<Self, WireError> {
+        Scalar::from_bytes(input.take(32)?).map_err(|_| WireError::InvalidScalar)
+    }
+}
+
+impl Wire for Point {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), WireError> {
+        out.extend_from_slice(&self.to_bytes(true));
+        Ok(())
+    }
+    fn decode(input: &mut Reader<'_>) -> Result<Self, WireError> {
+        Point::from_bytes(input.take(33)?).map_err(|_| WireError::InvalidPoint)
+    }
+}
+
+impl Wire for String {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), WireError> {
+        write_length("string", self.len(), MAX_ABORT_REASON_BYTES, out)?;
+        out.extend_from_slice(self.as_bytes());
+        Ok(())
+    }
+    fn decode(input: &mut Reader<'_>) -> Result<Self, WireError> {
+        let len = input.length("string", MAX_ABORT_REASON_BYTES)?;
+        String::from_utf8(input.take(len)?.to_vec()).map_err(|_| WireError::InvalidUtf8)
+    }
+}
+
+impl<T: Wire> Wire for Vec<T> {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), WireError> {
+        write_length("vector", self.len(), MAX_VEC_LEN, out)?;
+        for x in self {
+            x.encode(out)?;
+        }
+        Ok(())
+    }
+    fn decode(input: &mut Reader<'_>) -> Result<Self, WireError> {
+        let limit = MAX_VEC_LEN.min(input.limits.max_vec_len());
+        let len = input.length("vector", limit)?;
+        // every element takes at least one byte
+        let mut result = Vec::with_capacity(len.min(input.remaining()));
+        for _ in 0..len {
+            result.push(T::decode(input)?);
+        }
+        Ok(result)
+    }
+}
+
+impl<T: Wire> Wire for Option<T> {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), WireError> {
+        match self {
+            None => write_tag(0, out),
+            Some(x) => {
+                write_tag(1, out);
+                x.encode(out)?;
+            }
+        }
+        Ok(())
+    }
+    fn decode(input: &mut Reader<'_>) -> Result<Self, WireError> {
+        match input.byte()? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode(input)?)),
+            tag => Err(WireError::UnknownTag {
+                kind: "option",
+                tag,
+            }),
+        }
+    }
+}
+
+impl<A: Wire, B: Wire> Wire for (A, B) {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), WireError> {
+        self.0.encode(out)?;
+        self.1.encode(out)?;
+        Ok(())
+    }
+    fn decode(input: &mut Reader<'_>) -> Result<Self, WireError> {
+        Ok((A::decode(input)?, B::decode(input)?))
+    }
+}
+
+/// Implements [`Wire`] for a struct by encoding its fields in the given order
+macro_rules! wire_struct {
+    ($ty:ty { $($field:ident),* $(,)? }) => {
+        impl Wire for $ty {
+            fn encode(&self, out: &mut Vec<u8>) -> Result<(), WireError> {
+                $(self.$field.encode(out)?;)*
+                Ok(())
+            }
+            fn decode(input: &mut Reader<'_>) -> Result<Self, WireError> {
+                Ok(Self {
+                    $($field: Wire::decode(input)?,)*
+                })
+            }
+        }
+    };
+}
+
+impl Wire for PartyIndex {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), WireError> {
+        self.0.encode(out)?;
+        Ok(())
+    }
+    fn decode(input: &mut Reader<'_>) -> Result<Self, WireError> {
+        Ok(PartyIndex(Wire::decode(input)?))
+    }
+}
+
+impl Wire for SessionId {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), WireError> {
+        self.0.encode(out)?;
+        Ok(())
+    }
+    fn decode(input: &mut Reader<'_>) -> Result<Self, WireError> {
+        Ok(SessionId(Wire::decode(input)?))
+    }
+}
+
+impl Wire for ProtocolId {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), WireError> {
+        write_tag(*self as u8, out);
+        Ok(())
+    }
+    fn decode(input: &mut Reader<'_>) -> Result<Self, WireError> {
+        match input.byte()? {
+            1 => Ok(ProtocolId::Keygen),
+            2 => Ok(ProtocolId::Signing),
+            3 => Ok(ProtocolId::Resharing),
+            4 => Ok(ProtocolId::Refresh),
+            5 => Ok(ProtocolId::Recovery),
+            tag => Err(WireError::UnknownTag {
+                kind: "protocol",
+                tag,
+            }),
+        }
+    }
+}
+
+wire_struct!(Session { protocol, id });
+wire_struct!(MessageHeader {
+    session,
+    version,
+    round
+});
+
+impl Wire for Address {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), WireError> {
+        match self {
+            Address::Broadcast => write_tag(0, out),
+            Address::Peer(party) => {
+                write_tag(1, out);
+                party.encode(out)?;
+            }
+        }
+        Ok(())
+    }
+    fn decode(input: &mut Reader<'_>) -> Result<Self, WireError> {
+        match input.byte()? {
+            0 => Ok(Address::Broadcast),
+            1 => Ok(Address::Peer(PartyIndex::decode(input)?)),
+            tag => Err(WireError::UnknownTag {
+                kind: "address",
+                tag,
+            }),
+        }
+    }
+}
+
+impl<B: Wire> Wire for OutputMessage<B> {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), WireError> {
+        self.recipient.encode(out)?;
+        self.header.encode(out)?;
+        self.body.encode(out)?;
+        Ok(())
+    }
+    fn decode(input: &mut Reader<'_>) -> Result<Self, WireError> {
+        Ok(OutputMessage {
+            recipient: Wire::decode(input)?,
+            header: Wire::decode(input)?,
+            body: Wire::decode(input)?,
+        })
+    }
+}
+
+impl<B: Wire> Wire for InputMessage<B> {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), WireError> {
+        self.sender.encode(out)?;
+        self.header.encode(out)?;
+        self.body.encode(out)?;
+        Ok(())
+    }
+    fn decode(input: &mut Reader<'_>) -> Result<Self, WireError> {
+        Ok(InputMessage {
+            sender: Wire::decode(input)?,
+            header: Wire::decode(input)?,
+            body: Wire::decode(input)?,
+        })
+    }
+}
+
+/// Only the modulus is encoded, its square is computed by the decoder
+impl Wire for EncryptionKey {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), WireError> {
+        self.n.encode(out)?;
+        Ok(())
+    }
+    fn decode(input: &mut Reader<'_>) -> Result<Self, WireError> {
+        Ok(EncryptionKey::from(&BigInt::decode(input)?))
+    }
+}
+
+impl Wire for CorrectKeyProof {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), WireError> {
+        self.0.encode(out)?;
+        Ok(())
+    }
+    fn decode(input: &mut Reader<'_>) -> Result<Self, WireError> {
+        Ok(CorrectKeyProof(Wire::decode(input)?))
+    }
+}
+
+wire_struct!(DlogProof { y, c });
+wire_struct!(ZkpPublicSetup {
+    N_tilde,
+    h1,
+    h2,
+    dlog_proof,
+    inv_dlog_proof,
+    n_tilde_proof,
+});
+wire_struct!(DLogProof {
+    pk,
+    pk_t_rand_commitment,
+    challenge_response,
+});
+wire_struct!(SchnorrSignature {
+    rand_commitment,
+    challenge_response,
+});
+wire_struct!(HomoElGamalProof { T, A3, z1, z2 });
+wire_struct!(ShamirSecretSharing {
+    threshold,
+    share_count
+});
+wire_struct!(CurvVerifiableSS {
+    parameters,
+    commitments
+});
+wire_struct!(PaillierBlumProof { w, x, a, b, z });
+wire_struct!(ModulusProofs { paillier, n_tilde });
+wire_struct!(NoSmallFactorProof {
+    P,
+    Q,
+    A,
+    B,
+    T,
+    sigma,
+    z1,
+    z2,
+    w1,
+    w2,
+    v
+});
+wire_struct!(FactorProofs { paillier, n_tilde });
+wire_struct!(FeldmanVSS { vss, share });
+
+wire_struct!(AliceProof {
+    z,
+    u,
+    w,
+    e,
+    s,
+    s1,
+    s2
+});
+wire_struct!(BobProof {
+    t,
+    v,
+    w,
+    z,
+    z_prim,
+    e,
+    s,
+    s1,
+    s2,
+    t1,
+    t2,
+});
+wire_struct!(BobProofExt { proof, u, X });
+wire_struct!(DLogProofs {
+    b_proof,
+    beta_tag_proof
+});
+wire_struct!(MessageA { c, range_proof });
+wire_struct!(MessageB { c, proof });
+
+impl Wire for BobProofType {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), WireError> {
+        match self {
+            BobProofType::RangeProofExt(proof) => {
+                write_tag(0, out);
+                proof.encode(out)?;
+            }
+            BobProofType::RangeProof(proof) => {
+                write_tag(1, out);
+                proof.encode(out)?;
+            }
+            BobProofType::DLogProofs(proofs) => {
+                write_tag(2, out);
+                proofs.encode(out)?;
+            }
+        }
+        Ok(())
+    }
+    fn decode(input: &mut Reader<'_>) -> Result<Self, WireError> {
+        match input.byte()? {
+            0 => Ok(BobProofType::RangeProofExt(Wire::decode(input)?)),
+            1 => Ok(BobProofType::RangeProof(Wire::decode(input)?)),
+            2 => Ok(BobProofType::DLogProofs(Wire::decode(input)?)),
+            tag => Err(WireError::UnknownTag {
+                kind: "Bob's proof",
+                tag,
+            }),
+        }
+    }
+}
+
+wire_struct!(Abort { reason, blamed });
+wire_struct!(Resend { round });
+
+wire_struct!(keygen::Phase1Broadcast {
+    e,
+    com,
+    correct_key_proof,
+    range_proof_setup,
+    modulus_proofs,
+});
+wire_struct!(keygen::DecommitPublicKey {
+    blind_factor,
+    y_i,
+    factor_proofs
+});
+
+impl Wire for keygen::Message {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), WireError> {
+        use keygen::Message::*;
+        match self {
+            R1(msg) => {
+                write_tag(1, out);
+                msg.encode(out)?;
+            }
+            R2(msg) => {
+                write_tag(2, out);
+                msg.encode(out)?;
+            }
+            R3(msg) => {
+                write_tag(3, out);
+                msg.encode(out)?;
+            }
+            R4(msg) => {
+                write_tag(4, out);
+                msg.encode(out)?;
+            }
+            Abort(msg) => {
+                write_tag(0, out);
+                msg.encode(out)?;
+            }
+            Resend(msg) => {
+                write_tag(5, out);
+                msg.encode(out)?;
+            }
+        }
+        Ok(())
+    }
+    fn decode(input: &mut Reader<'_>) -> Result<Self, WireError> {
+        use keygen::Message::*;
+        let start = input.remaining();
+        let msg = match input.byte()? {
+            0 => Ok(Abort(Wire::decode(input)?)),
+            1 => Ok(R1(Wire::decode(input)?)),
+            2 => Ok(R2(Wire::decode(input)?)),
+            3 => Ok(R3(Wire::decode(input)?)),
+            4 => Ok(R4(Wire::decode(input)?)),
+            5 => Ok(Resend(Wire::decode(input)?)),
+            tag => Err(WireError::UnknownTag {
+                kind: "keygen message",
+                tag,
+            }),
+        }?;
+        input.checked(msg, start)
+    }
+}
+
+wire_struct!(signing::SignBroadcastPhase1 { com, mta_a });
+wire_struct!(signing::SignDecommitPhase4 {
+    blind_factor,
+    g_gamma_i,
+    gamma_proof,
+});
+wire_struct!(signing::Phase5Com1 { com });
+wire_struct!(signing::Phase5Com2 { com });
+wire_struct!(signing::Phase5Decom1 {
+    V_i,
+    A_i,
+    B_i,
+    blind_factor,
+    proof,
+});
+wire_struct!(signing::Phase5Decom2 {
+    U_i,
+    T_i,
+    blind_factor
+});
+wire_struct!(signing::Phase3data { delta_i });
+wire_struct!(signing::Phase5Edata { s_i });
+
+impl Wire for signing::Message {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), WireError> {
+        use signing::Message::*;
+        match self {
+            R1(msg) => {
+                write_tag(1, out);
+                msg.encode(out)?;
+            }
+            R2(msg) => {
+                write_tag(2, out);
+                msg.encode(out)?;
+            }
+            R2b(msg) => {
+                write_tag(3, out);
+                msg.encode(out)?;
+            }
+            R3(msg) => {
+                write_tag(4, out);
+                msg.encode(out)?;
+            }
+            R4(msg) => {
+                write_tag(5, out);
+                msg.encode(out)?;
+            }
+            R5(msg) => {
+                write_tag(6, out);
+                msg.encode(out)?;
+            }
+            R6(msg) => {
+                write_tag(7, out);
+                msg.encode(out)?;
+            }
+            R7(msg) => {
+                write_tag(8, out);
+                msg.encode(out)?;
+            }
+            R8(msg) => {
+                write_tag(9, out);
+                msg.encode(out)?;
+            }
+            R9(msg) => {
+                write_tag(10, out);
+                msg.encode(out)?;
+            }
+            Abort(msg) => {
+                write_tag(0, out);
+                msg.encode(out)?;
+            }
+            Resend(msg) => {
+                write_tag(11, out);
+                msg.encode(out)?;
+            }
+        }
+        Ok(())
+    }
+    fn decode(input: &mut Reader<'_>) -> Result<Self, WireError> {
+        use signing::Message::*;
+        let start = input.remaining();
+        let msg = match input.byte()? {
+            0 => Ok(Abort(Wire::decode(input)?)),
+            1 => Ok(R1(Wire::decode(input)?)),
+            2 => Ok(R2(Wire::decode(input)?)),
+            3 => Ok(R2b(Wire::decode(input)?)),
+            4 => Ok(R3(Wire::decode(input)?)),
+            5 => Ok(R4(Wire::decode(input)?)),
+            6 => Ok(R5(Wire::decode(input)?)),
+            7 => Ok(R6(Wire::decode(input)?)),
+            8 => Ok(R7(Wire::decode(input)?)),
+            9 => Ok(R8(Wire::decode(input)?)),
+            10 => Ok(R9(Wire::decode(input)?)),
+            11 => Ok(Resend(Wire::decode(input)?)),
+            tag => Err(WireError::UnknownTag {
+                kind: "signing message",
+                tag,
+            }),
+        }?;
+        input.checked(msg, start)
+    }
+}
+
+wire_struct!(resharing::Phase1Broadcast { y, vss_commitment });
+wire_struct!(resharing::Phase2Broadcast {
+    ek,
+    correct_key_proof,
+    range_proof_setup,
+    modulus_proofs,
+});
+wire_struct!(resharing::VSS { share, vss });
+wire_struct!(resharing::Phase4Broadcast { factor_proofs });
+wire_struct!(resharing::SignedAck {
+    public_key,
+    public_share,
+    signature,
+});
+
+impl Wire for resharing::Message {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), WireError> {
+        use resharing::Message::*;
+        match self {
+            R1(msg) => {
+                write_tag(1, out);
+                msg.encode(out)?;
+            }
+            R2(msg) => {
+                write_tag(2, out);
+                msg.encode(out)?;
+            }
+            R3(msg) => {
+                write_tag(3, out);
+                msg.encode(out)?;
+            }
+            Ack => write_tag(4, out),
+            FinalAck(msg) => {
+                write_tag(5, out);
+                msg.encode(out)?;
+            }
+            R4(msg) => {
+                write_tag(7, out);
+                msg.encode(out)?;
+            }
+            ProofsAck => write_tag(8, out),
+            Abort(msg) => {
+                write_tag(0, out);
+                msg.encode(out)?;
+            }
+            Resend(msg) => {
+                write_tag(6, out);
+                msg.encode(out)?;
+            }
+        }
+        Ok(())
+    }
+    fn decode(input: &mut Reader<'_>) -> Result<Self, WireError> {
+        use resharing::Message::*;
+        let start = input.remaining();
+        let msg = match input.byte()? {
+            0 => Ok(Abort(Wire::decode(input)?)),
+            1 => Ok(R1(Wire::decode(input)?)),
+            2 => Ok(R2(Wire::decode(input)?)),
+            3 => Ok(R3(Wire::decode(input)?)),
+            4 => Ok(Ack),
+            5 => Ok(FinalAck(Wire::decode(input)?)),
+            6 => Ok(Resend(Wire::decode(input)?)),
+            7 => Ok(R4(Wire::decode(input)?)),
+            8 => Ok(ProofsAck),
+            tag => Err(WireError::UnknownTag {
+                kind: "resharing message",
+                tag,
+            }),
+        }?;
+        input.checked(msg, start)
+    }
+}
+
+wire_struct!(refresh::Phase1Broadcast {
+    vss,
+    public_share,
+    ek,
+    correct_key_proof,
+    range_proof_setup,
+    modulus_proofs,
+});
+wire_struct!(refresh::Phase2Share {
+    share,
+    factor_proofs,
+});
+
+impl Wire for refresh::Message {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), WireError> {
+        use refresh::Message::*;
+        match self {
+            Abort(msg) => {
+                write_tag(0, out);
+                msg.encode(out)?;
+            }
+            R1(msg) => {
+                write_tag(1, out);
+                msg.encode(out)?;
+            }
+            R2(msg) => {
+                write_tag(2, out);
+                msg.encode(out)?;
+            }
+            R3(msg) => {
+                write_tag(3, out);
+                msg.encode(out)?;
+            }
+            Resend(msg) => {
+                write_tag(4, out);
+                msg.encode(out)?;
+            }
+        }
+        Ok(())
+    }
+    fn decode(input: &mut Reader<'_>) -> Result<Self, WireError> {
+        use refresh::Message::*;
+        let start = input.remaining();
+        let msg = match input.byte()? {
+            0 => Ok(Abort(Wire::decode(input)?)),
+            1 => Ok(R1(Wire::decode(input)?)),
+            2 => Ok(R2(Wire::decode(input)?)),
+            3 => Ok(R3(Wire::decode(input)?)),
+            4 => Ok(Resend(Wire::decode(input)?)),
+            tag => Err(WireError::UnknownTag {
+                kind: "refresh message",
+                tag,
+            }),
+        }?;
+        input.checked(msg, start)
+    }
+}
+
+wire_struct!(recovery::Phase1Broadcast {
+    ek,
+    correct_key_proof,
+    range_proof_setup,
+    modulus_proofs,
+});
+wire_struct!(recovery::Phase2Record {
+    point,
+    ek,
+    correct_key_proof,
+    range_proof_setup,
+});
+wire_struct!(recovery::Phase3Piece { piece });
+wire_struct!(recovery::Phase4Proofs { factor_proofs });
+wire_struct!(recovery::Phase5Contribution {
+    blinded_sum,
+    public_share,
+    public_key,
+    point,
+});
+
+impl Wire for recovery::Message {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), WireError> {
+        use recovery::Message::*;
+        match self {
+            Abort(msg) => {
+                write_tag(0, out);
+                msg.encode(out)?;
+            }
+            R1(msg) => {
+                write_tag(1, out);
+                msg.encode(out)?;
+            }
+            R2(msg) => {
+                write_tag(2, out);
+                msg.encode(out)?;
+            }
+            R3(msg) => {
+                write_tag(3, out);
+                msg.encode(out)?;
+            }
+            R4(msg) => {
+                write_tag(4, out);
+                msg.encode(out)?;
+            }
+            R5(msg) => {
+                write_tag(5, out);
+                msg.encode(out)?;
+            }
+            Resend(msg) => {
+                write_tag(6, out);
+                msg.encode(out)?;
+            }
+        }
+        Ok(())
+    }
+    fn decode(input: &mut Reader<'_>) -> Result<Self, WireError> {
+        use recovery::Message::*;
+        let start = input.remaining();
+        let msg = match input.byte()? {
+            0 => Ok(Abort(Wire::decode(input)?)),
+            1 => Ok(R1(Wire::decode(input)?)),
+            2 => Ok(R2(Wire::decode(input)?)),
+            3 => Ok(R3(Wire::decode(input)?)),
+            4 => Ok(R4(Wire::decode(input)?)),
+            5 => Ok(R5(Wire::decode(input)?)),
+            6 => Ok(Resend(Wire::decode(input)?)),
+            tag => Err(WireError::UnknownTag {
+                kind: "recovery message",
+                tag,
+            }),
+        }?;
+        input.checked(msg, start)
+    }
+}
+
+/// `tokio-util` codec of binary messages, in frames prefixed by their length
+///
+/// Usually the item is the [`OutputMessage`] of a protocol, which the receiver wraps into the [`InputMessage`] with the sender known from the connection.
+///
+/// [`OutputMessage`]: ../protocol/struct.OutputMessage.html
+/// [`InputMessage`]: ../protocol/struct.InputMessage.html
+#[derive(Debug, Clone, Copy)]
+pub struct WireCodec<T> {
+    max_frame_length: usize,
+    limits: Limits,
+    _item: PhantomData<fn() -> T>,
+}
+
+impl<T> WireCodec<T> {
+    pub fn new() -> Self {
+        WireCodec {
+            max_frame_length: MAX_FRAME_LEN,
+            limits: Limits::default(),
+            _item: PhantomData,
+        }
+    }
+
+    /// Sets the limit of the length of frames in both directions
+    pub fn with_max_frame_length(mut self, max_frame_length: usize) -> Self {
+        self.max_frame_length = max_frame_length;
+        self
+    }
+
+    /// Sets limits of decoded messages, which are default limits otherwise
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn max_frame_length(&self) -> usize {
+        self.max_frame_length
+    }
+
+    fn check_length(&self, len: usize) -> Result<(), WireError> {
+        if len > self.max_frame_length {
+            Err(WireError::TooLong {
+                kind: "frame",
+                len,
+                limit: self.max_frame_length,
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<T> Default for WireCodec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Wire> Encoder<T> for WireCodec<T> {
+    type Error = WireError;
+
+    fn encode(&mut self, item: T, dst: &mut BytesMut) -> Result<(), WireError> {
+        let frame = to_bytes(&item)?;
+        self.check_length(frame.len())?;
+        dst.reserve(4 + frame.len());
+        dst.put_u32(frame.len() as u32);
+        dst.put_slice(&frame);
+        Ok(())
+    }
+}
+
+impl<T: Wire> Decoder for WireCodec<T> {
+    type Item = T;
+    type Error = WireError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<T>, WireError> {
+        if src.len() < 4 {
+            return Ok(None);
+        }
+        let mut prefix = [0u8; 4];
+        prefix.copy_from_slice(&src[..4]);
+        let len = u32::from_be_bytes(prefix) as usize;
+        self.check_length(len)?;
+        if src.len() < 4 + len {
+            src.reserve(4 + len - src.len());
+            return Ok(None);
+        }
+        src.advance(4);
+        let frame = src.split_to(len);
+        from_bytes_with_limits(&frame, &self.limits).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::PROTOCOL_VERSION;
+    use crate::types::{PaillierKeys, VerifiableSS};
+    use crate::zk_range_proofs::{MTAMode, ZkpSetup};
+    use algorithms::config::DEFAULT_GROUP_ORDER_BIT_LENGTH;
+    use serde::Serialize;
+
+    fn session(protocol: ProtocolId) -> Session {
+        Session {
+            protocol,
+            id: SessionId::random(),
+        }
+    }
+
+    /// round trip through the binary form, returns decoded value and the ratio of the size of JSON to the size of binary form
+    fn round_trip<T: Wire + Serialize>(value: &T) -> (T, f64) {
+        let bytes = to_bytes(value).unwrap();
+        let decoded: T = from_bytes(&bytes).expect("decodes");
+        let json = serde_json::to_vec(value).unwrap();
+        assert_eq!(
+            serde_json::to_value(value).unwrap(),
+            serde_json::to_value(&decoded).unwrap()
+        );
+        (decoded, json.len() as f64 / bytes.len() as f64)
+    }
+
+    #[test]
+    fn signing_messages_round_trip() {
+        let session = session(ProtocolId::Signing);
+        let alice_keys = PaillierKeys::random();
+        let alice_setup = ZkpSetup::random(DEFAULT_GROUP_ORDER_BIT_LENGTH);
+        let alice_public_setup = ZkpPublicSetup::from_private_zkp_setup(&alice_setup);
+        let bob_setup = ZkpSetup::random(DEFAULT_GROUP_ORDER_BIT_LENGTH);
+        let bob_public_setup = ZkpPublicSetup::from_private_zkp_setup(&bob_setup);
+
+        let msg_a = MessageA::new(
+            &session,
+            &Scalar::random(),
+            &alice_keys.ek,
+            Some(&bob_public_setup),
+        );
+        let (msg_a, ratio) = round_trip(&msg_a);
+        assert!(ratio > 2.0, "binary form is not compact: {}", ratio);
+        assert!(msg_a.range_proof.as_ref().unwrap().verify(
+            &session,
+            &msg_a.c,
+            &alice_keys.ek,
+            &bob_setup
+        ));
+
+        let (msg_b, _) = MessageB::new(
+            &session,
+            &Scalar::random(),
+            &alice_keys.ek,
+            Some(&alice_public_setup),
+            &msg_a,
+            MTAMode::MtAwc,
+        );
+        let out = OutputMessage::new(
+            session,
+            Address::Peer(PartyIndex::from(2)),
+            signing::Message::R2b(msg_b),
+        );
+        let (decoded, ratio) = round_trip(&out);
+        assert!(ratio > 2.0, "binary form is not compact: {}", ratio);
+        assert_eq!(decoded.header, out.header);
+        assert_eq!(decoded.recipient, out.recipient);
+        match decoded.body {
+            signing::Message::R2b(MessageB {
+                proof: BobProofType::RangeProofExt(_),
+                ..
+            }) => {}
+            other => panic!("unexpected message {}", other),
+        }
+
+        let decom = signing::Message::R8(signing::Phase5Decom2 {
+            U_i: Point::generator() * Scalar::random(),
+            T_i: Point::zero(),
+            blind_factor: BigInt::from(-12345),
+        });
+        round_trip(&decom);
+    }
+
+    #[test]
+    fn keygen_and_resharing_messages_round_trip() {
+        let session = session(ProtocolId::Keygen);
+        let keys = PaillierKeys::random();
+        let setup = ZkpSetup::random(DEFAULT_GROUP_ORDER_BIT_LENGTH);
+        let phase1 = keygen::Message::R1(keygen::Phase1Broadcast {
+            e: keys.ek.clone(),
+            com: BigInt::from(123_456_789),
+            correct_key_proof: CorrectKeyProof(algorithms::nizk_rsa::gen_proof(&keys.dk)),
+            range_proof_setup: Some(ZkpPublicSetup::from_private_zkp_setup(&setup)),
+            modulus_proofs: ModulusProofs::new(&keys.dk, Some(&setup), &session.to_bigint())
+                .unwrap(),
+        });
+        let (decoded, _) = round_trip(&phase1);
+        match decoded {
+            keygen::Message::R1(msg) => {
+                assert_eq!(msg.e.nn, keys.ek.nn);
+                let setup = msg.range_proof_setup.unwrap();
+                assert!(setup.verify().is_ok());
+                assert!(msg
+                    .modulus_proofs
+                    .verify(&msg.e, Some(&setup), &session.to_bigint())
+                    .is_ok());
+            }
+            other => panic!("unexpected message {}", other),
+        }
+        let proof = keygen::Message::R4(DLogProof::prove(&session, &Scalar::random()));
+        match round_trip(&proof).0 {
+            keygen::Message::R4(proof) => assert!(proof.verify(&session).is_ok()),
+            other => panic!("unexpected message {}", other),
+        }
+
+        let secret = Scalar::random();
+        let (vss, shares) = VerifiableSS::share(1, 3, &secret);
+        let share = resharing::Message::R3(resharing::VSS {
+            share: (1, shares[0].clone()),
+            vss,
+        });
+        match round_trip(&share).0 {
+            resharing::Message::R3(msg) => {
+                assert!(msg.vss.validate_share(&msg.share.1, msg.share.0).is_ok())
+            }
+            other => panic!("unexpected message {}", other),
+        }
+        assert_eq!(
+            to_bytes(&resharing::Message::Ack).unwrap(),
+            vec![WIRE_VERSION, 4]
+        );
+        assert!(matches!(
+            round_trip(&resharing::Message::ProofsAck).0,
+            resharing::Message::ProofsAck
+        ));
+        let public_key = Point::generator() * &secret;
+        let ack = resharing::Message::FinalAck(resharing::SignedAck::new(
+            &session,
+            &public_key,
+            &shares[0],
+        ));
+        match round_trip(&ack).0 {
+            resharing::Message::FinalAck(ack) => assert!(ack.verify(&session, &public_key).is_ok()),
+            other => panic!("unexpected message {}", other),
+        }
+
+        let public_setup = ZkpPublicSetup::from_private_zkp_setup(&setup);
+        let factor_proofs = FactorProofs::new(&keys.dk, &setup, &public_setup, &BigInt::from(1));
+        let proofs = resharing::Message::R4(resharing::Phase4Broadcast { factor_proofs });
+        match round_trip(&proofs).0 {
+            resharing::Message::R4(resharing::Phase4Broadcast {
+                factor_proofs: proofs,
+            }) => assert!(proofs
+                .verify(&keys.ek, &public_setup, &setup, &BigInt::from(1))
+                .is_ok()),
+            other => panic!("unexpected message {}", other),
+        }
+
+        // the first commitment of the zero polynomial of refresh is the point at infinity
+        let (vss, shares) = VerifiableSS::share(1, 3, &Scalar::zero());
+        let share = refresh::Message::R2(refresh::Phase2Share {
+            share: (2, shares[1].clone()),
+            factor_proofs: FactorProofs::new(&keys.dk, &setup, &public_setup, &BigInt::from(1)),
+        });
+        round_trip(&share);
+        let phase1 = refresh::Message::R1(refresh::Phase1Broadcast {
+            vss,
+            public_share: Point::generator() * &shares[0],
+            ek: keys.ek.clone(),
+            correct_key_proof: CorrectKeyProof(algorithms::nizk_rsa::gen_proof(&keys.dk)),
+            range_proof_setup: public_setup.clone(),
+            modulus_proofs: ModulusProofs::new(&keys.dk, Some(&setup), &BigInt::from(1)).unwrap(),
+        });
+        match round_trip(&phase1).0 {
+            refresh::Message::R1(msg) => {
+                assert_eq!(msg.vss.commitments[0], Point::zero());
+                assert!(msg.vss.validate_share(&shares[1], 2).is_ok());
+            }
+            other => panic!("unexpected message {}", other),
+        }
+
+        let record = recovery::Message::R2(recovery::Phase2Record {
+            point: 2,
+            ek: keys.ek.clone(),
+            correct_key_proof: CorrectKeyProof(algorithms::nizk_rsa::gen_proof(&keys.dk)),
+            range_proof_setup: Some(public_setup.clone()),
+        });
+        round_trip(&record);
+        let contribution = recovery::Message::R5(recovery::Phase5Contribution {
+            blinded_sum: shares[0].clone(),
+            public_share: Point::generator() * &shares[0],
+            public_key: Point::generator() * &shares[1],
+            point: 3,
+        });
+        match round_trip(&contribution).0 {
+            recovery::Message::R5(msg) => {
+                assert_eq!(msg.public_share, Point::generator() * &msg.blinded_sum);
+                assert_eq!(msg.point, 3);
+            }
+            other => panic!("unexpected message {}", other),
+        }
+
+        let abort = Abort::new(&["invalid proof, party 1"], vec![PartyIndex::from(1)]);
+        match round_trip(&resharing::Message::Abort(abort.clone())).0 {
+            resharing::Message::Abort(decoded) => assert_eq!(decoded, abort),
+            other => panic!("unexpected message {}", other),
+        }
+        assert_eq!(
+            to_bytes(&resharing::Message::Resend(Resend { round: 3 })).unwrap(),
+            vec![WIRE_VERSION, 6, 0, 3]
+        );
+        let reason = "é".repeat(MAX_ABORT_REASON_BYTES);
+        assert_eq!(
+            Abort::new(&[&reason], vec![]).reason.len(),
+            MAX_ABORT_REASON_BYTES
+        );
+        assert!(matches!(
+            to_bytes(&reason),
+            Err(WireError::TooLong { kind: "string", .. })
+        ));
+        let mut long = vec![WIRE_VERSION];
+        (reason.len() as u16).encode(&mut long).unwrap();
+        long.extend_from_slice(reason.as_bytes());
+        assert!(matches!(
+            from_bytes::<String>(&long),
+            Err(WireError::TooLong { kind: "string", .. })
+        ));
+    }
+
+    #[test]
+    fn big_integers_round_trip() {
+        for n in [0, 1, -1, 255, -256, 1 << 30, -(1 << 30)] {
+            let n = BigInt::from(n);
+            assert_eq!(from_bytes::<BigInt>(&to_bytes(&n).unwrap()).unwrap(), n);
+        }
+        assert_eq!(
+            to_bytes(&BigInt::zero()).unwrap(),
+            vec![WIRE_VERSION, 0, 0, 0]
+        );
+        let wide = Limits::default().with_modulus_bits(8 * MAX_BIGINT_BYTES);
+        let large = BigInt::from(1) << (8 * MAX_BIGINT_BYTES - 1);
+        assert_eq!(
+            from_bytes_with_limits::<BigInt>(&to_bytes(&large).unwrap(), &wide).unwrap(),
+            large
+        );
+    }
+
+    #[test]
+    fn oversized_values_not_encoded() {
+        let huge = BigInt::from(1) << (8 * MAX_BIGINT_BYTES);
+        assert!(matches!(
+            to_bytes(&huge),
+            Err(WireError::TooLong {
+                kind: "big integer",
+                ..
+            })
+        ));
+        assert!(matches!(
+            to_bytes(&vec![0u8; MAX_VEC_LEN + 1]),
+            Err(WireError::TooLong { kind: "vector", .. })
+        ));
+        let mut codec = WireCodec::<Vec<BigInt>>::new();
+        let mut buf = BytesMut::new();
+        assert!(codec.encode(vec![huge], &mut buf).is_err());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn malformed_input_rejected() {
+        let msg = signing::Message::R3(signing::Phase3data {
+            delta_i: Scalar::random(),
+        });
+        let bytes = to_bytes(&msg).unwrap();
+
+        let mut newer = bytes.clone();
+        newer[0] = WIRE_VERSION + 1;
+        assert!(matches!(
+            from_bytes::<signing::Message>(&newer),
+            Err(WireError::UnsupportedVersion(_))
+        ));
+        assert!(matches!(
+            from_bytes::<signing::Message>(&bytes[..bytes.len() - 1]),
+            Err(WireError::Truncated)
+        ));
+        let mut longer = bytes.clone();
+        longer.push(0);
+        assert!(matches!(
+            from_bytes::<signing::Message>(&longer),
+            Err(WireError::TrailingBytes(1))
+        ));
+        let mut unknown = bytes;
+        unknown[1] = 42;
+        assert!(matches!(
+            from_bytes::<signing::Message>(&unknown),
+            Err(WireError::UnknownTag { tag: 42, .. })
+        ));
+
+        // the length is checked before the magnitude is read
+        let mut huge = vec![WIRE_VERSION, 0];
+        (MAX_BIGINT_BYTES as u16 + 1).encode(&mut huge).unwrap();
+        assert!(matches!(
+            from_bytes::<BigInt>(&huge),
+            Err(WireError::TooLong { .. })
+        ));
+        assert!(matches!(
+            from_bytes::<BigInt>(&[WIRE_VERSION, 0, 0, 2, 0, 1]),
+            Err(WireError::NonCanonicalBigInt)
+        ));
+        let mut many = vec![WIRE_VERSION];
+        (MAX_VEC_LEN as u16 + 1).encode(&mut many).unwrap();
+        assert!(matches!(
+            from_bytes::<Vec<BigInt>>(&many),
+            Err(WireError::TooLong { .. })
+        ));
+        let mut off_curve = vec![WIRE_VERSION, 2];
+        off_curve.extend_from_slice(&[0xff; 32]);
+        assert!(matches!(
+            from_bytes::<Point>(&off_curve),
+            Err(WireError::InvalidPoint)
+        ));
+    }
+
+    #[test]
+    fn reading_bounded_by_limits() {
+        let limits = Limits::default().with_modulus_bits(256).with_max_parties(4);
+        let number = BigInt::from(1) << (8 * limits.max_number_bytes());
+        let bytes = to_bytes(&number).unwrap();
+        assert_eq!(from_bytes::<BigInt>(&bytes).unwrap(), number);
+        assert!(matches!(
+            from_bytes_with_limits::<BigInt>(&bytes, &limits),
+            Err(WireError::TooLong {
+                kind: "big integer",
+                ..
+            })
+        ));
+
+        // the length is refused before elements are read
+        let mut many = vec![WIRE_VERSION];
+        (limits.max_vec_len() as u16 + 1).encode(&mut many).unwrap();
+        assert!(matches!(
+            from_bytes_with_limits::<Vec<BigInt>>(&many, &limits),
+            Err(WireError::TooLong { kind: "vector", .. })
+        ));
+        assert!(matches!(
+            from_bytes::<Vec<BigInt>>(&many),
+            Err(WireError::Truncated)
+        ));
+
+        let mut codec = WireCodec::<Vec<BigInt>>::new();
+        let mut buf = BytesMut::new();
+        codec.encode(vec![number], &mut buf).unwrap();
+        assert!(matches!(
+            WireCodec::<Vec<BigInt>>::new()
+                .with_limits(limits)
+                .decode(&mut buf),
+            Err(WireError::TooLong {
+                kind: "big integer",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn codec_frames() {
+        let session = session(ProtocolId::Resharing);
+        let msgs = [
+            OutputMessage::new(session, Address::Broadcast, resharing::Message::Ack),
+            OutputMessage::new(
+                session,
+                Address::Peer(PartyIndex::from(7)),
+                resharing::Message::R1(resharing::Phase1Broadcast {
+                    y: Point::generator().to_point(),
+                    vss_commitment: BigInt::from(77),
+                }),
+            ),
+        ];
+        let mut codec = WireCodec::<resharing::OutMsg>::new();
+        let mut buf = BytesMut::new();
+        for msg in msgs.iter().cloned() {
+            codec.encode(msg, &mut buf).unwrap();
+        }
+
+        // frames arrive in pieces
+        let mut incoming = BytesMut::new();
+        let mut decoded = Vec::new();
+        for byte in buf.iter() {
+            incoming.put_u8(*byte);
+            if let Some(msg) = codec.decode(&mut incoming).unwrap() {
+                decoded.push(msg);
+            }
+        }
+        assert!(incoming.is_empty());
+        assert_eq!(decoded.len(), msgs.len());
+        for (sent, received) in msgs.iter().zip(decoded.iter()) {
+            assert_eq!(sent.header, received.header);
+            assert_eq!(sent.header.version, PROTOCOL_VERSION);
+            assert_eq!(sent.recipient, received.recipient);
+        }
+
+        let mut small = WireCodec::<resharing::OutMsg>::new().with_max_frame_length(16);
+        assert!(matches!(
+            small.encode(msgs[1].clone(), &mut BytesMut::new()),
+            Err(WireError::TooLong { .. })
+        ));
+        let mut oversized = BytesMut::new();
+        oversized.put_u32(17);
+        assert!(matches!(
+            small.decode(&mut oversized),
+            Err(WireError::TooLong { .. })
+        ));
+    }
+}
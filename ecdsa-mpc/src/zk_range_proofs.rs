@@ -191,9 +191,12 @@ use algorithms::types::{BasicOps, BitManipulation, Integer, Modulo, One, Samplab
 use std::borrow::Borrow;
 use zeroize::Zeroize;
 
-use crate::types::{CurvDLogProofType, DLogProof};
+use crate::proofs::DLogProof;
+use crate::protocol::Session;
 use algorithms::dlog_proof::DlogProof;
 use algorithms::nizk_rsa;
+use algorithms::no_small_factor::{NoSmallFactorProof, RingPedersen};
+use algorithms::paillier_blum::PaillierBlumProof;
 use algorithms::primes::PairOfSafePrimes;
 use algorithms::sha::HSha512Trunc256;
 use serde::{Deserialize, Serialize};
@@ -349,8 +352,38 @@ impl ZkpSetup {
     }
 }
 
+impl ZkpSetup {
+    /// The setup as ring-Pedersen parameters, which are used to verify no small factor proofs made for the party
+    pub fn ring_pedersen(&self) -> RingPedersen<'_> {
+        RingPedersen {
+            N: &self.N_tilde,
+            s: &self.h1,
+            t: &self.h2,
+        }
+    }
+
+    /// generates the proof that $`\tilde{N}`$ is the product of two Blum primes, which safe primes of the setup are
+    pub fn modulus_proof(&self, context: &BigInt) -> PaillierBlumProof {
+        PaillierBlumProof::create(&self.p, &self.q, context).expect("safe primes are Blum primes")
+    }
+
+    /// generates the proof that $`\tilde{N}`$ has no small factors, made with the setup of the verifier
+    pub fn factor_proof(&self, verifier: &ZkpPublicSetup, context: &BigInt) -> NoSmallFactorProof {
+        NoSmallFactorProof::create(&self.p, &self.q, &verifier.ring_pedersen(), context)
+    }
+}
+
 #[trace(pretty, prefix = "ZkpPublicSetup::")]
 impl ZkpPublicSetup {
+    /// The setup as ring-Pedersen parameters, which are used to make no small factor proofs for the party
+    pub fn ring_pedersen(&self) -> RingPedersen<'_> {
+        RingPedersen {
+            N: &self.N_tilde,
+            s: &self.h1,
+            t: &self.h2,
+        }
+    }
+
     const DLOG_PROOF_SECURITY_PARAMETER: u32 = 128;
     ///  Creates new public setup from private one
     ///
@@ -489,6 +522,7 @@ pub struct MessageA {
 impl MessageA {
     // a - Alice 's secret
     pub fn new(
+        session: &Session,
         a: &Scalar,
         alice_pk: &EncryptionKey,
         bob_setup: Option<&ZkpPublicSetup>,
@@ -504,6 +538,7 @@ impl MessageA {
 
         let proof = bob_setup.map(|zkp_setup| {
             AliceProof::generate(
+                session,
                 &a.to_bigint(),
                 &cipher,
                 alice_pk,
@@ -635,19 +670,20 @@ pub type HashWithNonce = (BigInt, BigInt);
 /// Alice's proof
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AliceProof {
-    z: BigInt,
-    u: BigInt,
-    w: BigInt,
-    e: HashWithNonce,
-    s: BigInt,
-    s1: BigInt,
-    s2: BigInt,
+    pub(crate) z: BigInt,
+    pub(crate) u: BigInt,
+    pub(crate) w: BigInt,
+    pub(crate) e: HashWithNonce,
+    pub(crate) s: BigInt,
+    pub(crate) s1: BigInt,
+    pub(crate) s2: BigInt,
 }
 #[trace(pretty, prefix = "AliceProof::")]
 impl AliceProof {
     /// verify Alice's proof using the proof and public keys
     pub fn verify(
         &self,
+        session: &Session,
         cipher: &BigInt,
         alice_ek: &EncryptionKey,
         bob_zkp_setup: &ZkpSetup,
@@ -658,7 +694,15 @@ impl AliceProof {
         let Gen = alice_ek.n.borrow() + 1;
 
         let e = HSha512Trunc256::create_hash_with_nonce(
-            &[N, &Gen, cipher, &self.z, &self.u, &self.w],
+            &[
+                &session.to_bigint(),
+                N,
+                &Gen,
+                cipher,
+                &self.z,
+                &self.u,
+                &self.w,
+            ],
             &self.e.1,
         );
         if e != self.e {
@@ -716,6 +760,7 @@ impl AliceProof {
     /// requires randomness used for encrypting Alice's secret a
     /// requires the EC group order of the used curve
     pub fn generate(
+        session: &Session,
         a: &BigInt,
         cipher: &BigInt,
         alice_pk: &EncryptionKey,
@@ -728,7 +773,15 @@ impl AliceProof {
 
         let Gen = init.N() + 1;
         let e = HSha512Trunc256::create_hash_bounded_by_q(
-            &[init.N(), &Gen, cipher, &round1.z, &round1.u, &round1.w],
+            &[
+                &session.to_bigint(),
+                init.N(),
+                &Gen,
+                cipher,
+                &round1.z,
+                &round1.u,
+                &round1.w,
+            ],
             q,
         );
 
@@ -748,8 +801,8 @@ impl AliceProof {
 /// simple discrete log proof, used as the alternative to range proof
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DLogProofs {
-    pub b_proof: CurvDLogProofType,
-    pub beta_tag_proof: CurvDLogProofType,
+    pub b_proof: DLogProof,
+    pub beta_tag_proof: DLogProof,
 }
 
 /// enumerates types of proofs Bob can use in the protocol
@@ -777,6 +830,7 @@ impl MessageB {
     /// b  is Bob's secret
     /// returns ( Message, new Bob's secret )
     pub fn new(
+        session: &Session,
         b: &Scalar,
         alice_ek: &EncryptionKey,
         alice_zkp_setup: Option<&ZkpPublicSetup>,
@@ -810,6 +864,7 @@ impl MessageB {
                 // generate range proof
                 match mta_mode {
                     MTAMode::MtA => BobProofType::RangeProof(BobProof::generate(
+                        session,
                         alice_c,
                         mta_out.0.borrow(),
                         b,
@@ -820,6 +875,7 @@ impl MessageB {
                         Scalar::group_order(),
                     )),
                     MTAMode::MtAwc => BobProofType::RangeProofExt(BobProofExt::generate(
+                        session,
                         alice_c,
                         mta_out.0.borrow(),
                         b,
@@ -835,8 +891,8 @@ impl MessageB {
             None => {
                 // generate dlog_proof
                 BobProofType::DLogProofs(DLogProofs {
-                    b_proof: DLogProof::prove(b),
-                    beta_tag_proof: DLogProof::prove(&beta_prim_fe),
+                    b_proof: DLogProof::prove(session, b),
+                    beta_tag_proof: DLogProof::prove(session, &beta_prim_fe),
                 })
             }
         };
@@ -984,17 +1040,17 @@ impl BobZkpRound2 {
 /// Bob's regular proof
 #[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub struct BobProof {
-    t: BigInt,
-    v: BigInt,
-    w: BigInt,
-    z: BigInt,
-    z_prim: BigInt,
-    e: HashWithNonce,
-    s: BigInt,
-    s1: BigInt,
-    s2: BigInt,
-    t1: BigInt,
-    t2: BigInt,
+    pub(crate) t: BigInt,
+    pub(crate) v: BigInt,
+    pub(crate) w: BigInt,
+    pub(crate) z: BigInt,
+    pub(crate) z_prim: BigInt,
+    pub(crate) e: HashWithNonce,
+    pub(crate) s: BigInt,
+    pub(crate) s1: BigInt,
+    pub(crate) s2: BigInt,
+    pub(crate) t1: BigInt,
+    pub(crate) t2: BigInt,
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -1002,6 +1058,7 @@ pub struct BobProof {
 impl BobProof {
     pub fn verify(
         &self,
+        session: &Session,
         a_enc: &BigInt,
         mta_avc_out: &BigInt,
         alice_ek: &EncryptionKey,
@@ -1010,6 +1067,7 @@ impl BobProof {
         let Gen = alice_ek.n.borrow() + 1;
         let e = HSha512Trunc256::create_hash_with_nonce(
             &[
+                &session.to_bigint(),
                 &alice_ek.n,
                 &Gen,
                 a_enc,
@@ -1076,6 +1134,7 @@ impl BobProof {
     }
 
     pub fn generate(
+        session: &Session,
         a_encrypted: &BigInt,
         mta_encrypted: &BigInt,
         b: &Scalar,
@@ -1089,6 +1148,7 @@ impl BobProof {
         let round1 = BobZkpRound1::from(&init, b, beta_prim, a_encrypted);
 
         let e = HSha512Trunc256::create_hash_with_random_nonce(&[
+            &session.to_bigint(),
             init.N(),
             &init.Gen(),
             a_encrypted,
@@ -1121,9 +1181,9 @@ impl BobProof {
 /// Bob's extended proof, adds the knowledge of $`B = g^b \in \mathcal{G}`$
 #[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct BobProofExt {
-    proof: BobProof,
-    u: Point,
-    X: Point,
+    pub(crate) proof: BobProof,
+    pub(crate) u: Point,
+    pub(crate) X: Point,
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -1131,6 +1191,7 @@ pub struct BobProofExt {
 impl BobProofExt {
     pub fn verify(
         &self,
+        session: &Session,
         a_enc: &BigInt,
         mta_avc_out: &BigInt,
         alice_ek: &EncryptionKey,
@@ -1139,6 +1200,7 @@ impl BobProofExt {
         let Gen = alice_ek.n.borrow() + 1;
         let e = HSha512Trunc256::create_hash_with_nonce(
             &[
+                &session.to_bigint(),
                 &alice_ek.n,
                 &Gen,
                 &self.X.x_coord().unwrap(),
@@ -1181,6 +1243,7 @@ impl BobProofExt {
     }
 
     pub fn generate(
+        session: &Session,
         a_encrypted: &BigInt,
         mta_encrypted: &BigInt,
         b: &Scalar,
@@ -1201,6 +1264,7 @@ impl BobProofExt {
         let round1 = BobZkpRound1::from(&init, b, beta_prim, a_encrypted);
 
         let e = HSha512Trunc256::create_hash_with_random_nonce(&[
+            &session.to_bigint(),
             init.N(),
             &init.Gen(),
             &X.x_coord().unwrap(),
@@ -1272,7 +1336,15 @@ impl SampleFromMultiplicativeGroup for BigInt {
 mod tests {
 
     use super::*;
+    use crate::protocol::{ProtocolId, SessionId};
     use crate::types::PaillierKeys;
+
+    fn session() -> Session {
+        Session {
+            protocol: ProtocolId::Signing,
+            id: SessionId::random(),
+        }
+    }
     use algorithms::primes::is_prime;
 
     #[test]
@@ -1299,13 +1371,14 @@ mod tests {
 
         let bob_setup = ZkpSetup::random(DEFAULT_GROUP_ORDER_BIT_LENGTH);
         let bob_public_setup = ZkpPublicSetup::from_private_zkp_setup(&bob_setup);
+        let session = session();
 
         // Alice private
         let (message, public_paillier_key) = {
             let paillier_keys = PaillierKeys::random();
             let a = Scalar::random();
             (
-                MessageA::new(&a, &paillier_keys.ek, Some(&bob_public_setup)),
+                MessageA::new(&session, &a, &paillier_keys.ek, Some(&bob_public_setup)),
                 paillier_keys.ek.clone(),
             )
         };
@@ -1313,7 +1386,14 @@ mod tests {
         assert!(message.range_proof.is_some());
         let proof = message.range_proof.unwrap();
         // Bob
-        assert!(proof.verify(&message.c, &public_paillier_key, &bob_setup));
+        assert!(proof.verify(&session, &message.c, &public_paillier_key, &bob_setup));
+        // the proof is not accepted in another session
+        assert!(!proof.verify(
+            &self::session(),
+            &message.c,
+            &public_paillier_key,
+            &bob_setup
+        ));
     }
 
     #[derive(Debug)]
@@ -1346,9 +1426,11 @@ mod tests {
 
             // run MtA protocol with different inputs
             (0..5).for_each(|_| {
+                let session = session();
                 // Simulate Alice
                 let a = Scalar::random();
                 let msga = MessageA::new(
+                    &session,
                     &a,
                     &alice_setup.paillier_keys.ek,
                     Some(&bob_public_zkp_setup),
@@ -1357,6 +1439,7 @@ mod tests {
                 let b = Scalar::random();
                 // Bob follows MtA
                 let (msgb, _beta) = MessageB::new(
+                    &session,
                     &b,
                     alice_public_key,
                     Some(alice_public_zkp_setup),
@@ -1365,8 +1448,13 @@ mod tests {
                 );
                 match msgb.proof {
                     BobProofType::RangeProof(proof) => {
-                        if !proof.verify(&msga.c, &msgb.c, alice_public_key, &alice_setup.zkp_setup)
-                        {
+                        if !proof.verify(
+                            &session,
+                            &msga.c,
+                            &msgb.c,
+                            alice_public_key,
+                            &alice_setup.zkp_setup,
+                        ) {
                             assert!(
                                 false,
                                 "BobProof fails: alice={:?},\nbob={:?}",
@@ -1379,6 +1467,7 @@ mod tests {
                 // Bob follows MtAWC
 
                 let (msgb, beta) = MessageB::new(
+                    &session,
                     &b,
                     alice_public_key,
                     Some(alice_public_zkp_setup),
@@ -1394,8 +1483,13 @@ mod tests {
                         let alpha = Scalar::from(&alice_share);
                         assert_eq!(a * b, alpha + beta);
                         // verify range proof
-                        if !proof.verify(&msga.c, &msgb.c, alice_public_key, &alice_setup.zkp_setup)
-                        {
+                        if !proof.verify(
+                            &session,
+                            &msga.c,
+                            &msgb.c,
+                            alice_public_key,
+                            &alice_setup.zkp_setup,
+                        ) {
                             assert!(
                                 false,
                                 "BobProofExt fails: alice={:?},\nbob={:?}",
@@ -1,24 +1,20 @@
 use algorithms::config::DEFAULT_GROUP_ORDER_BIT_LENGTH;
 use anyhow::{anyhow, bail};
-use crossbeam_channel::{Receiver, Sender};
-use ecdsa_mpc::keygen::{
-    FinalState, KeyGeneratorTraits, Phase1, SecretKeyLoader, SecretKeyLoaderError,
-};
-use ecdsa_mpc::messages::keygen::InMsg;
-use ecdsa_mpc::messages::keygen::OutMsg;
-use ecdsa_mpc::protocol::{Address, InputMessage, PartyIndex};
+use ecdsa_mpc::keygen::{KeyGeneratorTraits, Phase1, SecretKeyLoader, SecretKeyLoaderError};
+use ecdsa_mpc::limits::Limits;
+use ecdsa_mpc::protocol::{PartyIndex, SessionId};
+use ecdsa_mpc::simulator::{check_keygen, NetworkConditions, NodeOutcome, Simulation};
 use ecdsa_mpc::types::Parameters;
 use ecdsa_mpc::types::Scalar;
 use ecdsa_mpc::types::{InitialKeys, InitialPublicKeys};
 use ecdsa_mpc::zk_range_proofs::ZkpSetup;
 use paillier::DecryptionKey;
-use state_machine::sync_channels::StateMachine;
+use state_machine::driver::Driver;
 use std::collections::HashMap;
+use std::env;
 use std::fs::File;
 use std::io::prelude::*;
 use std::sync::{Arc, Mutex};
-use std::thread::JoinHandle;
-use std::{env, thread};
 
 fn main() -> anyhow::Result<()> {
     let args: Vec<String> = env::args().collect();
@@ -54,10 +50,10 @@ fn keygen_helper(
         .map(|i| PartyIndex::from(i))
         .collect::<Vec<_>>();
 
-    let mut nodes = Vec::new();
-    let mut node_results = Vec::new();
     let wallet = Wallet::new(HashMap::new());
     let shared_wallet_reference = Arc::new(Mutex::new(wallet));
+    let mut simulation = Simulation::new(NetworkConditions::default())?;
+    let session_id = SessionId::random();
 
     for i in 0..share_count as usize {
         let range_proof_setup = if generate_range_proof_setup {
@@ -65,8 +61,6 @@ fn keygen_helper(
         } else {
             None
         };
-        let (ingress, rx) = crossbeam_channel::unbounded();
-        let (tx, egress) = crossbeam_channel::unbounded();
         let init_keys = InitialKeys::random();
         let init_pub_keys = InitialPublicKeys::from(&init_keys);
         shared_wallet_reference
@@ -77,131 +71,42 @@ fn keygen_helper(
         let secret_loader = SecretKeyLoaderImpl::new(&shared_wallet_reference, i);
 
         log::info!("starting party {}", i);
-        let parties = parties.clone();
-        let join_handle = thread::spawn(move || {
-            let start_state = Box::new(Phase1::new(
-                &params,
-                init_pub_keys,
-                range_proof_setup,
-                parties.as_slice(),
-                PartyIndex::from(i),
-                Arc::new(Box::new(secret_loader)),
-                None,
-            )?);
-            let mut machine = StateMachine::<KeyGeneratorTraits>::new(start_state, &rx, &tx);
-            match machine.execute() {
-                Some(Ok(fs)) => Ok(fs),
-                Some(Err(e)) => bail!("error {:?}", e),
-                None => bail!("error in the machine"),
-            }
-        });
-        nodes.push(Node {
-            party: PartyIndex::from(i),
-            egress,
-            ingress,
-        });
-        node_results.push(NodeResult {
-            index: i,
-            join_handle,
-        })
+        let start_state = Box::new(Phase1::new(
+            session_id,
+            &params,
+            init_pub_keys,
+            range_proof_setup,
+            parties.as_slice(),
+            PartyIndex::from(i),
+            Arc::new(Box::new(secret_loader)),
+            None,
+            Limits::default(),
+        )?);
+        simulation.add_party(
+            PartyIndex::from(i),
+            Driver::<KeyGeneratorTraits>::new(start_state),
+        );
     }
 
-    let _mx_thread = thread::spawn(move || {
-        loop {
-            let mut output_messages = Vec::new();
-            // collect output from nodes
-            for node in nodes.iter() {
-                if let Ok(out_msg) = node.egress.try_recv() {
-                    output_messages.push(OutputMessageWithSource {
-                        msg: out_msg,
-                        source: node.party,
-                    });
-                }
-            }
-            // forward collected messages
-            output_messages
-                .iter()
-                .for_each(|mm| match &mm.msg.recipient {
-                    Address::Broadcast => {
-                        log::trace!(
-                            "broadcast from {} to parties {:?}",
-                            mm.source,
-                            nodes
-                                .iter()
-                                .filter(|node| node.party != mm.source)
-                                .map(|node| node.party)
-                                .collect::<Vec<_>>()
-                        );
-                        nodes
-                            .iter()
-                            .filter(|node| node.party != mm.source)
-                            .for_each(|node| {
-                                let message_to_deliver = InputMessage {
-                                    sender: mm.source,
-                                    body: mm.msg.body.clone(),
-                                };
-                                node.ingress.send(message_to_deliver).unwrap();
-                            });
-                    }
-                    Address::Peer(peer) => {
-                        if let Some(node) = nodes.iter().find(|node| (*node).party == *peer) {
-                            node.ingress
-                                .send(InputMessage {
-                                    sender: mm.source,
-                                    body: mm.msg.body.clone(),
-                                })
-                                .unwrap();
-                        }
-                    }
-                })
-        }
-    });
-
-    let results = node_results
-        .into_iter()
-        .map(|h| (h.index, h.join_handle.join()))
-        .collect::<Vec<_>>();
+    let report = simulation.run();
+    if let Err(e) = check_keygen(&report) {
+        report
+            .outcomes
+            .iter()
+            .for_each(|(party, outcome)| log::error!("{}: {:?}", party, outcome));
+        return Err(anyhow!("Some state machines returned error: {}", e));
+    }
 
-    return if results
-        .iter()
-        .any(|(_, r)| r.is_err() || r.as_ref().unwrap().is_err())
-    {
-        results.iter().for_each(|r| match r {
-            (_, Ok(result)) => match result {
-                Ok(final_state) => log::error!("{:?}", final_state),
-                Err(e) => log::error!("{:?}", e),
-            },
-            (_, Err(e)) => log::error!("{:?}", e),
-        });
-        Err(anyhow!("Some state machines returned error"))
-    } else {
-        for (index, result) in results.into_iter() {
-            // safe to unwrap because results with errors cause the early exit
-            let final_state = result.unwrap().unwrap();
+    for (index, (_, outcome)) in report.outcomes.into_iter().enumerate() {
+        if let NodeOutcome::Finished(Ok(final_state)) = outcome {
             let path = format!("{}.{}.json", filename_prefix, index);
             let mut file = File::create(&path)?;
             file.write_all(
                 serde_json::to_string_pretty(&final_state.multiparty_shared_info)?.as_bytes(),
             )?;
         }
-        Ok(())
-    };
-}
-
-struct Node {
-    party: PartyIndex,
-    egress: Receiver<OutMsg>,
-    ingress: Sender<InMsg>,
-}
-
-struct NodeResult {
-    index: usize,
-    join_handle: JoinHandle<anyhow::Result<FinalState>>,
-}
-
-struct OutputMessageWithSource {
-    msg: OutMsg,
-    source: PartyIndex,
+    }
+    Ok(())
 }
 
 #[derive(Debug)]
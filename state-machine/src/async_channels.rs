@@ -6,11 +6,20 @@
 
 use futures::channel::mpsc::{UnboundedReceiver, UnboundedSender};
 use futures::{SinkExt, StreamExt};
-use tokio::time;
-
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+use std::time::Duration;
+use tokio::time::{self, Instant};
+
+use crate::checkpoint::{CheckpointError, RestoreState, SealedSnapshot, SnapshotKey};
+use crate::discarded_deck::{DeckLimits, DroppedMessage};
+use crate::driver::{Aborted, Driver, Step};
+use crate::observer::Observer;
 use crate::types::Instruction;
-use crate::types::{BoxedState, StateMachineTraits, Transition};
-use std::collections::VecDeque;
+use crate::types::{
+    BoxedState, MessageOrigin, RejectedMessage, StateMachineTraits, TerminationReason,
+};
 
 /// Finite state machine
 ///
@@ -27,50 +36,17 @@ use std::collections::VecDeque;
 pub struct StateMachine<T>
 where
     T: StateMachineTraits,
+    T::InMsg: MessageOrigin,
 {
-    state: BoxedState<T>,
+    driver: Driver<T>,
     inqueue: UnboundedReceiver<Instruction<T::InMsg>>,
     outqueue: UnboundedSender<T::OutMsg>,
-    retained: Vec<T::InMsg>,
-    discarded: DiscardedDeck<T::InMsg>,
-}
-
-/// container for deferred messaged
-///
-/// When several distributed nodes execute same network protocol and each node sends and receives same message types, it is hard to achieve the scenario
-/// where all nodes pace with same speed through the protocol. It is very common case when some nodes are faster then others so that their messages arrive to destinations early.   
-/// To address that the state machine collects all messages discarded by current state object into the `discarded` container.
-/// The contents of the container becomes available for *next* state object as priority input.
-///
-/// To prevent a state from checking already discarded messages at same stage of the protocol more than once, the container has two decks, one for having input for the state object and another for collecting discarded messages.
-struct DiscardedDeck<T> {
-    current: VecDeque<T>,
-    next_state: VecDeque<T>,
-}
-
-impl<T> DiscardedDeck<T> {
-    pub fn new() -> Self {
-        Self {
-            current: VecDeque::new(),
-            next_state: VecDeque::new(),
-        }
-    }
-    pub fn save(&mut self, m: T) {
-        self.next_state.push_back(m);
-    }
-    pub fn pop(&mut self) -> Option<T> {
-        self.current.pop_front()
-    }
-    pub fn flip(&mut self) {
-        while let Some(m) = self.next_state.pop_front() {
-            self.current.push_back(m)
-        }
-    }
 }
 
 impl<T> StateMachine<T>
 where
     T: StateMachineTraits,
+    T::InMsg: MessageOrigin,
 {
     /// Create and initialize new machine
     pub fn new(
@@ -79,117 +55,193 @@ where
         outqueue: UnboundedSender<T::OutMsg>,
     ) -> Self {
         StateMachine {
-            state: start_state,
+            driver: Driver::new(start_state),
             inqueue,
             outqueue,
-            retained: Vec::new(),
-            discarded: DiscardedDeck::new(),
         }
     }
 
-    /// Execute main loop of the machine.
-    pub async fn execute(&mut self) -> Option<Result<T::FinalState, T::ErrorState>> {
-        log::trace!("starting State Machine");
+    /// Restores the machine from the snapshot
+    ///
+    /// The machine continues with the state object stored in the snapshot, and does not send out its output again.
+    /// The snapshot is refused if its sequence number is less than `latest`, the number of the newest snapshot known to the caller.
+    pub fn restore(
+        sealed: &SealedSnapshot,
+        key: &SnapshotKey,
+        context: &T::Context,
+        latest: u64,
+        inqueue: UnboundedReceiver<Instruction<T::InMsg>>,
+        outqueue: UnboundedSender<T::OutMsg>,
+    ) -> Result<Self, CheckpointError>
+    where
+        T: RestoreState,
+        T::InMsg: for<'de> Deserialize<'de>,
+    {
+        Ok(StateMachine {
+            driver: Driver::restore(sealed, key, context, latest)?,
+            inqueue,
+            outqueue,
+        })
+    }
+
+    /// Makes the machine emit the snapshot each time a state object starts, and each time it retains or defers a message
+    pub fn with_checkpoints<F>(mut self, key: SnapshotKey, sink: F) -> Self
+    where
+        T::InMsg: Serialize,
+        F: FnMut(SealedSnapshot) + Send + 'static,
+    {
+        self.driver = self.driver.with_checkpoints(key, sink);
+        self
+    }
 
-        self.state_post_transition().await;
+    /// Creates the snapshot of the machine
+    pub fn checkpoint(&self, key: &SnapshotKey) -> Result<SealedSnapshot, CheckpointError>
+    where
+        T::InMsg: Serialize,
+    {
+        self.driver.checkpoint(key)
+    }
 
-        let driving_result = {
-            let timeout = self.state.timeout();
-            let driver = self.drive_to_completion();
+    /// Limits the duration of entire protocol, in addition to timeouts of its phases
+    pub fn with_session_timeout(mut self, timeout: Duration) -> Self {
+        self.driver = self.driver.with_session_timeout(timeout);
+        self
+    }
 
-            if let Some(t) = timeout {
-                let timed_exec = time::timeout(t, driver).await;
-                timed_exec.ok()
-            } else {
-                Some(driver.await)
-            }
-        };
+    /// Reports lifecycle events of the machine to the observer
+    pub fn with_observer<O>(mut self, observer: O) -> Self
+    where
+        O: Observer<<T::InMsg as MessageOrigin>::Party> + 'static,
+    {
+        self.driver = self.driver.with_observer(observer);
+        self
+    }
 
-        driving_result.unwrap_or_else(|| {
-            Some(
-                self.state
-                    .timeout_outcome(self.retained.drain(..).collect()),
-            )
-        })
+    /// Writes the transcript of the session into the writer, see [`transcript`](../transcript/index.html)
+    pub fn with_recorder<W>(mut self, writer: W) -> Self
+    where
+        T::InMsg: Serialize,
+        T::OutMsg: Serialize,
+        W: Write + Send + 'static,
+    {
+        self.driver = self.driver.with_recorder(writer);
+        self
+    }
+
+    /// Makes the machine ask awaited parties to send their messages again, each time the current phase has waited for `interval`
+    pub fn with_resend_interval(mut self, interval: Duration) -> Self {
+        self.driver = self.driver.with_resend_interval(interval);
+        self
+    }
+
+    /// Bounds the deck of deferred messages, which is unbounded by default
+    pub fn with_deck_limits(mut self, limits: DeckLimits) -> Self {
+        self.driver = self.driver.with_deck_limits(limits);
+        self
+    }
+
+    /// Numbers of messages dropped from the deck of deferred messages due to its limits, per sender and reason
+    pub fn dropped_messages(
+        &self,
+    ) -> &HashMap<DroppedMessage<<T::InMsg as MessageOrigin>::Party>, usize> {
+        self.driver.dropped_messages()
+    }
+
+    /// Numbers of messages rejected by states of the machine, per sender and reason
+    pub fn rejected_messages(
+        &self,
+    ) -> &HashMap<RejectedMessage<<T::InMsg as MessageOrigin>::Party>, usize> {
+        self.driver.rejected_messages()
     }
 
-    /// Drives the machine loop to completion.
-    async fn drive_to_completion(&mut self) -> Option<Result<T::FinalState, T::ErrorState>> {
+    /// The number of rejected messages not counted by [`rejected_messages`](#method.rejected_messages), as their records would exceed the limit
+    pub fn unrecorded_rejections(&self) -> usize {
+        self.driver.unrecorded_rejections()
+    }
+
+    /// Execute main loop of the machine.
+    ///
+    /// The timeout of each phase is armed when the phase becomes current. If the phase or the session deadline expires,
+    /// the machine returns the `timeout_outcome` of the phase which is current at that moment.
+    /// If the machine is terminated, its input stream is closed, or its deck of deferred messages overflows, it returns the diagnostics of the current phase.
+    pub async fn execute(&mut self) -> Result<Result<T::FinalState, T::ErrorState>, Aborted<T>> {
+        let mut processed = self.driver.start(Instant::now().into_std());
+
         loop {
-            let transition = match self.discarded.pop() {
-                // first a message is taken from the deck of discarded
-                Some(m) => self.process_message(m),
-                // inc case the deck is empty,  read from the channel
-                None => match self.inqueue.next().await {
-                    Some(Instruction::Data(m)) => self.process_message(m),
-                    Some(Instruction::Terminate) => {
-                        log::debug!("State machine: termination requested");
-                        return None;
-                    }
-                    None => {
-                        log::error!("State machine: stream terminated");
-                        return None;
-                    }
-                },
+            let step = match processed {
+                Ok(step) => step,
+                Err(overflow) => {
+                    log::error!("State machine: discarded deck overflow {:?}", overflow);
+                    return Err(self.driver.terminate(TerminationReason::Overflow(overflow)));
+                }
             };
+            if let Some(outcome) = self.send_output(step).await {
+                return Ok(outcome);
+            }
 
-            if let Some(transition) = transition {
-                match transition {
-                    Transition::NewState(state) => {
-                        let _ = std::mem::replace(&mut self.state, state);
-                        self.state_post_transition().await;
-                        self.discarded.flip();
+            let next = match self.driver.wake_up() {
+                Some(deadline) => {
+                    // the timeout polls the queue first, so that a peer which keeps the queue ready would keep the deadline from firing
+                    let deadline_at = Instant::from_std(deadline);
+                    let next = if Instant::now() < deadline_at {
+                        time::timeout_at(deadline_at, self.inqueue.next())
+                            .await
+                            .ok()
+                    } else {
+                        None
+                    };
+                    match next {
+                        Some(next) => next,
+                        None => match self.driver.handle_wake_up(deadline) {
+                            Err(outcome) => return Ok(outcome),
+                            Ok(step) => {
+                                processed = Ok(step);
+                                continue;
+                            }
+                        },
                     }
-                    Transition::FinalState(outcome) => return Some(outcome),
                 }
-            }
-        }
-    }
-
-    /// internal function which processes the message according to the state machine algorithm
-    fn process_message(&mut self, message: T::InMsg) -> Option<Transition<T>> {
-        // Check message is expected.
-        log::trace!("message received");
-        if self.state.is_message_expected(&message, &self.retained) {
-            // Message is expected. Retain it.
-            self.retained.push(message);
-        } else {
-            // unexpected message.
-            self.discarded.save(message);
-            return None;
-        }
-
-        // check input is complete.
-        if self.state.is_input_complete(&self.retained) {
-            // Progress to the next state.
-            let transition = self.state.consume(self.retained.drain(..).collect());
-            Some(transition)
-        } else {
-            // More input is required.
-            None
+                None => self.inqueue.next().await,
+            };
+            let message = match next {
+                Some(Instruction::Data(m)) => m,
+                Some(Instruction::Terminate(reason)) => {
+                    log::debug!("State machine: termination requested");
+                    return Err(self.driver.terminate(TerminationReason::Requested(reason)));
+                }
+                None => {
+                    log::error!("State machine: stream terminated");
+                    return Err(self.driver.terminate(TerminationReason::InputClosed));
+                }
+            };
+            processed = self
+                .driver
+                .handle_message(message, Instant::now().into_std());
         }
     }
 
-    /// Sends out all messages generated after transition from phase to phase.
-    async fn state_post_transition(&mut self) {
-        if let Some(output) = self.state.start() {
-            for m in output {
-                if let Err(err) = self.outqueue.send(m).await {
-                    log::error!("State machine cannot send out message: {:?}", err);
-                }
+    /// Sends out the output of the step and returns its outcome
+    async fn send_output(&mut self, step: Step<T>) -> Option<Result<T::FinalState, T::ErrorState>> {
+        for m in step.output {
+            if let Err(err) = self.outqueue.send(m).await {
+                log::error!("State machine cannot send out message: {:?}", err);
             }
         }
+        step.outcome
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use ecdsa_mpc::protocol::{Address, InputMessage, OutputMessage};
+    use ecdsa_mpc::protocol::{
+        Address, InputMessage, MessageHeader, OutputMessage, PartyIndex, ProtocolId, Round,
+        Session, SessionId,
+    };
     use std::time::Duration;
 
-    use crate::types::State;
     use crate::types::Transition::{FinalState, NewState};
+    use crate::types::{MessageClass, RejectReason, State, Transition};
 
     #[derive(Debug)]
     struct Final(i64);
@@ -197,6 +249,7 @@ mod tests {
     enum MachineError {
         _GenericError,
         TimeoutError,
+        PhaseTimeout(usize),
     }
 
     struct TestTraits;
@@ -208,12 +261,40 @@ mod tests {
         type ErrorState = MachineError;
     }
 
+    impl MessageOrigin for InputMessage<MessageType> {
+        type Party = PartyIndex;
+        fn sender(&self) -> PartyIndex {
+            self.sender
+        }
+    }
+
+    impl Round for MessageType {
+        fn round(&self) -> u16 {
+            1
+        }
+    }
+
+    fn session() -> Session {
+        Session {
+            protocol: ProtocolId::Keygen,
+            id: SessionId::default(),
+        }
+    }
+
+    fn input(sender: PartyIndex, body: MessageType) -> InputMessage<MessageType> {
+        InputMessage {
+            sender,
+            header: MessageHeader::new(session(), &body),
+            body,
+        }
+    }
+
     type In = InputMessage<MessageType>;
     type Out = OutputMessage<MessageType>;
     type MachineResult = Result<Final, MachineError>;
 
     #[allow(non_camel_case_types)]
-    #[derive(PartialEq)]
+    #[derive(Debug, Clone, PartialEq)]
     enum MessageType {
         P1_Input,
         P2_Input,
@@ -241,18 +322,19 @@ mod tests {
 
     impl State<TestTraits> for MultiPhase {
         fn start(&mut self) -> Option<Vec<Out>> {
-            Some(vec![OutputMessage {
-                recipient: Address::Broadcast,
-                body: MessageType::Machine_Ready,
-            }])
+            Some(vec![OutputMessage::new(
+                session(),
+                Address::Broadcast,
+                MessageType::Machine_Ready,
+            )])
         }
 
-        fn is_message_expected(&self, msg: &In, _current_msg_set: &[In]) -> bool {
+        fn classify_message(&self, msg: &In, _current_msg_set: &[In]) -> MessageClass {
             match self.sub_phase {
-                SubPhase::P1 if msg.body == MessageType::P1_Input => true,
-                SubPhase::P2 if msg.body == MessageType::P2_Input => true,
-                SubPhase::P3 if msg.body == MessageType::P3_Input => true,
-                _ => false,
+                SubPhase::P1 if msg.body == MessageType::P1_Input => MessageClass::Accept,
+                SubPhase::P2 if msg.body == MessageType::P2_Input => MessageClass::Accept,
+                SubPhase::P3 if msg.body == MessageType::P3_Input => MessageClass::Accept,
+                _ => MessageClass::Defer,
             }
         }
 
@@ -291,8 +373,8 @@ mod tests {
                 None
             }
 
-            fn is_message_expected(&self, _msg: &In, _current_msg_set: &[In]) -> bool {
-                true
+            fn classify_message(&self, _msg: &In, _current_msg_set: &[In]) -> MessageClass {
+                MessageClass::Accept
             }
 
             fn is_input_complete(&self, current_msg_set: &[In]) -> bool {
@@ -320,17 +402,17 @@ mod tests {
         let start_state = Box::new(Phase);
         let mut machine = StateMachine::<TestTraits>::new(start_state, rx, tx);
         let _ = ingress
-            .send(Instruction::Data(In {
-                sender: Default::default(),
-                body: MessageType::P1_Input,
-            }))
+            .send(Instruction::Data(input(
+                Default::default(),
+                MessageType::P1_Input,
+            )))
             .await;
 
         let result = machine.execute().await;
         assert!({
             match result {
-                Some(x) => x.is_ok(),
-                None => false,
+                Ok(x) => x.is_ok(),
+                Err(_) => false,
             }
         });
     }
@@ -345,8 +427,8 @@ mod tests {
                 None
             }
 
-            fn is_message_expected(&self, _msg: &In, _current_msg_set: &[In]) -> bool {
-                true
+            fn classify_message(&self, _msg: &In, _current_msg_set: &[In]) -> MessageClass {
+                MessageClass::Accept
             }
 
             fn is_input_complete(&self, current_msg_set: &[In]) -> bool {
@@ -374,7 +456,7 @@ mod tests {
         let start_state = Box::new(Phase);
         let mut machine = StateMachine::<TestTraits>::new(start_state, rx, tx);
         let result = machine.execute().await;
-        assert!(matches!(result, Some(Err(MachineError::TimeoutError))));
+        assert!(matches!(result, Ok(Err(MachineError::TimeoutError))));
     }
 
     #[tokio::test]
@@ -408,10 +490,7 @@ mod tests {
             ]
         } {
             let _ = ingress
-                .send(Instruction::Data(InputMessage {
-                    sender: Default::default(),
-                    body: msg_type,
-                }))
+                .send(Instruction::Data(input(Default::default(), msg_type)))
                 .await;
             log::trace!("message sent");
         }
@@ -420,11 +499,11 @@ mod tests {
         match result {
             Ok(r) => assert!({
                 match r {
-                    Some(x) => {
+                    Ok(x) => {
                         log::info!("{:?}", x);
                         x.is_ok()
                     }
-                    None => false,
+                    Err(_) => false,
                 }
             }),
             Err(e) => log::error!("{}", e),
@@ -457,23 +536,206 @@ mod tests {
         // but not further than the second stage.
         for msg_type in vec![P1_Input, P2_Input, P3_Input, P1_Input, P2_Input, P1_Input] {
             let _ = ingress
-                .send(Instruction::Data(InputMessage {
-                    sender: Default::default(),
-                    body: msg_type,
-                }))
+                .send(Instruction::Data(input(Default::default(), msg_type)))
                 .await;
             log::trace!("message sent");
         }
 
-        let _ = ingress.send(Instruction::Terminate).await;
+        let _ = ingress
+            .send(Instruction::Terminate("operator cancelled".to_string()))
+            .await;
+
+        // We should end up with no result, but with the diagnostics of the second stage.
+        let aborted = rx_result.await.unwrap().unwrap_err();
+        assert_eq!(
+            aborted.reason,
+            TerminationReason::Requested("operator cancelled".to_string())
+        );
+        assert_eq!(aborted.retained.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn duplicates_rejected() {
+        struct Phase;
+
+        impl State<TestTraits> for Phase {
+            fn start(&mut self) -> Option<Vec<Out>> {
+                None
+            }
+
+            fn classify_message(&self, msg: &In, current_msg_set: &[In]) -> MessageClass {
+                if current_msg_set.iter().any(|m| m.sender == msg.sender) {
+                    MessageClass::Reject(RejectReason::Duplicate)
+                } else {
+                    MessageClass::Accept
+                }
+            }
+
+            fn is_input_complete(&self, current_msg_set: &[In]) -> bool {
+                current_msg_set.len() == 2
+            }
+
+            fn consume(&self, _current_msg_set: Vec<In>) -> Transition<TestTraits> {
+                FinalState(Ok(Final(0)))
+            }
+
+            fn timeout_outcome(&self, _current_msg_set: Vec<In>) -> MachineResult {
+                unimplemented!()
+            }
+        }
+
+        let (mut ingress, rx) = futures::channel::mpsc::unbounded();
+        let (tx, _egress) = futures::channel::mpsc::unbounded();
+        let mut machine = StateMachine::<TestTraits>::new(Box::new(Phase), rx, tx);
+
+        for &sender in &[1usize, 1, 2] {
+            let _ = ingress
+                .send(Instruction::Data(input(
+                    PartyIndex::from(sender),
+                    MessageType::P1_Input,
+                )))
+                .await;
+        }
+
+        assert!(matches!(machine.execute().await, Ok(Ok(Final(0)))));
+        assert_eq!(
+            machine.rejected_messages(),
+            &HashMap::from([(
+                RejectedMessage {
+                    sender: PartyIndex::from(1),
+                    reason: RejectReason::Duplicate,
+                },
+                1
+            )])
+        );
+    }
+
+    #[tokio::test]
+    async fn timeout_under_flood() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        /// Rejects every message, and takes a while to do so
+        struct Phase(Arc<AtomicUsize>);
 
-        assert!(match rx_result.await {
-            // We should end up with no result.
-            Ok(r) => r.is_none(),
-            Err(e) => {
-                log::error!("{}", e);
+        impl State<TestTraits> for Phase {
+            fn start(&mut self) -> Option<Vec<Out>> {
+                None
+            }
+
+            fn classify_message(&self, _msg: &In, _current_msg_set: &[In]) -> MessageClass {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(2));
+                MessageClass::Reject(RejectReason::Duplicate)
+            }
+
+            fn is_input_complete(&self, _current_msg_set: &[In]) -> bool {
                 false
             }
-        })
+
+            fn consume(&self, _current_msg_set: Vec<In>) -> Transition<TestTraits> {
+                unimplemented!()
+            }
+
+            fn timeout(&self) -> Option<Duration> {
+                Some(Duration::from_millis(50))
+            }
+
+            fn timeout_outcome(&self, _current_msg_set: Vec<In>) -> MachineResult {
+                Err(MachineError::TimeoutError)
+            }
+        }
+
+        let (mut ingress, rx) = futures::channel::mpsc::unbounded();
+        let (tx, _egress) = futures::channel::mpsc::unbounded();
+        let classified = Arc::new(AtomicUsize::new(0));
+        let mut machine =
+            StateMachine::<TestTraits>::new(Box::new(Phase(classified.clone())), rx, tx);
+
+        // the queue stays ready for much longer than the timeout of the phase
+        let flood = 200;
+        for _ in 0..flood {
+            let _ = ingress
+                .send(Instruction::Data(input(
+                    PartyIndex::from(1),
+                    MessageType::P1_Input,
+                )))
+                .await;
+        }
+
+        assert!(matches!(
+            machine.execute().await,
+            Ok(Err(MachineError::TimeoutError))
+        ));
+        assert!(classified.load(Ordering::SeqCst) < flood);
+    }
+
+    /// Sequence of phases, each of them consumes a message and has its own timeout
+    struct Chain(Vec<Option<Duration>>);
+
+    impl State<TestTraits> for Chain {
+        fn start(&mut self) -> Option<Vec<Out>> {
+            None
+        }
+
+        fn classify_message(&self, _msg: &In, _current_msg_set: &[In]) -> MessageClass {
+            MessageClass::Accept
+        }
+
+        fn is_input_complete(&self, current_msg_set: &[In]) -> bool {
+            !current_msg_set.is_empty()
+        }
+
+        fn consume(&self, _current_msg_set: Vec<In>) -> Transition<TestTraits> {
+            if self.0.len() > 1 {
+                NewState(Box::new(Chain(self.0[1..].to_vec())))
+            } else {
+                FinalState(Ok(Final(0)))
+            }
+        }
+
+        fn timeout(&self) -> Option<Duration> {
+            self.0[0]
+        }
+
+        fn timeout_outcome(&self, _current_msg_set: Vec<In>) -> MachineResult {
+            Err(MachineError::PhaseTimeout(self.0.len()))
+        }
+    }
+
+    async fn run_chain(
+        mut machine: StateMachine<TestTraits>,
+        mut ingress: futures::channel::mpsc::UnboundedSender<Instruction<In>>,
+    ) -> Result<MachineResult, Aborted<TestTraits>> {
+        let _ = ingress
+            .send(Instruction::Data(input(
+                Default::default(),
+                MessageType::P1_Input,
+            )))
+            .await;
+        machine.execute().await
+    }
+
+    #[tokio::test]
+    async fn later_phase_timeout() {
+        let (ingress, rx) = futures::channel::mpsc::unbounded();
+        let (tx, _egress) = futures::channel::mpsc::unbounded();
+        let start_state = Box::new(Chain(vec![None, Some(Duration::from_millis(100))]));
+        let machine = StateMachine::<TestTraits>::new(start_state, rx, tx);
+
+        let result = run_chain(machine, ingress).await;
+        assert!(matches!(result, Ok(Err(MachineError::PhaseTimeout(1)))));
+    }
+
+    #[tokio::test]
+    async fn session_timeout() {
+        let (ingress, rx) = futures::channel::mpsc::unbounded();
+        let (tx, _egress) = futures::channel::mpsc::unbounded();
+        let start_state = Box::new(Chain(vec![None, None, None]));
+        let machine = StateMachine::<TestTraits>::new(start_state, rx, tx)
+            .with_session_timeout(Duration::from_millis(100));
+
+        let result = run_chain(machine, ingress).await;
+        assert!(matches!(result, Ok(Err(MachineError::PhaseTimeout(2)))));
     }
 }
@@ -0,0 +1,396 @@
+//! Encrypted snapshots of a running machine
+//!
+//! A node which restarts in the middle of a protocol loses its session, so that other parties eventually time out.
+//! To prevent that, the machine can store its current state object, the messages retained by this state, and the contents of its discarded deck into a [`SealedSnapshot`].
+//! The snapshot contains secret values of the protocol, therefore it is always encrypted with a [`SnapshotKey`].
+//!
+//! The machine restored from the snapshot continues with the stored state object, but it never calls `start` of this object again.
+//! The output of `start` may carry fresh randomness, like commitments to nonces in signing protocol, so that sending it twice with different values could leak secrets.
+//! The machine which is configured to emit snapshots creates the snapshot each time it retains or defers a message.
+//! It also creates the snapshot of the new state object after its `start` returns, but before the output is sent to the network.
+//! Therefore the output of any state object is sent at most once, as long as the latest snapshot is used to restore the machine.
+//! A snapshot created before the machine has started stores a state object which has not started yet, so the restored machine calls its `start` once it starts.
+//!
+//! Each snapshot carries the sequence number of the machine's contents, which grows each time the machine starts a state object, retains or defers a message,
+//! and continues from the restored snapshot. The number is readable without the key, see [`SealedSnapshot::sequence`], so that the storage can keep the newest snapshot.
+//! The machine refuses to restore from a snapshot older than the newest one known to the caller, as it would take back messages which the machine has processed since.
+//!
+//! [`SealedSnapshot`]: struct.SealedSnapshot.html
+//! [`SnapshotKey`]: struct.SnapshotKey.html
+//! [`SealedSnapshot::sequence`]: struct.SealedSnapshot.html#method.sequence
+
+use crate::discarded_deck::DiscardedDeck;
+use crate::types::{BoxedState, MessageOrigin, StateMachineTraits};
+use serde::{Deserialize, Serialize};
+use sodiumoxide::crypto::secretbox;
+use thiserror::Error;
+
+/// Errors of creating and restoring snapshots
+#[derive(Debug, Error)]
+pub enum CheckpointError {
+    #[error("the state object does not support snapshots")]
+    NotSupported,
+    #[error("the snapshot cannot be decrypted")]
+    Decryption,
+    #[error("the snapshot {sequence} is older than the snapshot {latest}")]
+    Stale { sequence: u64, latest: u64 },
+    #[error("snapshot serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+}
+
+/// The key which encrypts snapshots
+pub struct SnapshotKey(secretbox::Key);
+
+impl SnapshotKey {
+    /// generates new random key
+    pub fn generate() -> Self {
+        let _ = sodiumoxide::init();
+        SnapshotKey(secretbox::gen_key())
+    }
+
+    /// Creates the key from its bytes. Returns `None` if the length of the slice is not equal to `secretbox::KEYBYTES`
+    pub fn from_slice(bytes: &[u8]) -> Option<Self> {
+        secretbox::Key::from_slice(bytes).map(SnapshotKey)
+    }
+}
+
+/// Encrypted snapshot of a machine
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SealedSnapshot {
+    sequence: u64,
+    nonce: Vec<u8>,
+    ciphertext: Vec<u8>,
+}
+
+impl SealedSnapshot {
+    /// The sequence number of the snapshot, greater for newer snapshots of the machine
+    ///
+    /// The number is authenticated only when the snapshot is restored, as it is repeated in the encrypted contents.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+}
+
+/// Machine traits of protocols which state objects can be restored from snapshots
+pub trait RestoreState: StateMachineTraits {
+    /// resources required by state objects which are not stored into snapshots, like loaders of secret keys
+    type Context;
+
+    /// Rebuilds the state object from the value returned by its `snapshot` method
+    fn restore_state(
+        snapshot: serde_json::Value,
+        context: &Self::Context,
+    ) -> Result<BoxedState<Self>, serde_json::Error>;
+}
+
+/// Plain contents of the snapshot
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Snapshot<M> {
+    pub sequence: u64,
+    /// true if `start` of the state object has been called
+    pub started: bool,
+    pub state: serde_json::Value,
+    pub retained: Vec<M>,
+    pub deferred: Vec<M>,
+}
+
+impl<M: Serialize> Snapshot<M> {
+    pub fn seal(&self, key: &SnapshotKey) -> Result<SealedSnapshot, CheckpointError> {
+        let plaintext = serde_json::to_vec(self)?;
+        let nonce = secretbox::gen_nonce();
+        Ok(SealedSnapshot {
+            sequence: self.sequence,
+            ciphertext: secretbox::seal(&plaintext, &nonce, &key.0),
+            nonce: nonce.0.to_vec(),
+        })
+    }
+}
+
+impl<M: for<'de> Deserialize<'de>> Snapshot<M> {
+    pub fn open(sealed: &SealedSnapshot, key: &SnapshotKey) -> Result<Self, CheckpointError> {
+        let nonce =
+            secretbox::Nonce::from_slice(&sealed.nonce).ok_or(CheckpointError::Decryption)?;
+        let plaintext = secretbox::open(&sealed.ciphertext, &nonce, &key.0)
+            .map_err(|_| CheckpointError::Decryption)?;
+        let snapshot: Self = serde_json::from_slice(&plaintext)?;
+        if snapshot.sequence != sealed.sequence {
+            return Err(CheckpointError::Decryption);
+        }
+        Ok(snapshot)
+    }
+}
+
+impl<'m, M: MessageOrigin> Snapshot<&'m M> {
+    /// Collects the snapshot of machine's contents. Returns `None` if the state does not support snapshots
+    pub fn of<T>(
+        sequence: u64,
+        started: bool,
+        state: &BoxedState<T>,
+        retained: &'m [M],
+        deck: &'m DiscardedDeck<M>,
+    ) -> Option<Self>
+    where
+        T: StateMachineTraits<InMsg = M>,
+    {
+        Some(Snapshot {
+            sequence,
+            started,
+            state: state.snapshot()?,
+            retained: retained.iter().collect(),
+            deferred: deck.messages().collect(),
+        })
+    }
+}
+
+/// Contents of the machine rebuilt from the snapshot
+pub(crate) struct Restored<T: StateMachineTraits> {
+    pub sequence: u64,
+    pub started: bool,
+    pub state: BoxedState<T>,
+    pub retained: Vec<T::InMsg>,
+    pub deferred: Vec<T::InMsg>,
+}
+
+/// Decrypts the snapshot and rebuilds the state object, unless the snapshot is older than `latest`
+pub(crate) fn restore<T>(
+    sealed: &SealedSnapshot,
+    key: &SnapshotKey,
+    context: &T::Context,
+    latest: u64,
+) -> Result<Restored<T>, CheckpointError>
+where
+    T: RestoreState,
+    T::InMsg: for<'de> Deserialize<'de>,
+{
+    let snapshot = Snapshot::<T::InMsg>::open(sealed, key)?;
+    if snapshot.sequence < latest {
+        return Err(CheckpointError::Stale {
+            sequence: snapshot.sequence,
+            latest,
+        });
+    }
+    Ok(Restored {
+        sequence: snapshot.sequence,
+        started: snapshot.started,
+        state: T::restore_state(snapshot.state, context)?,
+        retained: snapshot.retained,
+        deferred: snapshot.deferred,
+    })
+}
+
+/// Callback which receives snapshots emitted by the machine
+pub(crate) type CheckpointHook<M> = Box<dyn for<'m> FnMut(Snapshot<&'m M>) + Send>;
+
+/// Wraps the sink of snapshots into the callback which encrypts them
+pub(crate) fn checkpoint_hook<M, F>(key: SnapshotKey, mut sink: F) -> CheckpointHook<M>
+where
+    M: Serialize,
+    F: FnMut(SealedSnapshot) + Send + 'static,
+{
+    Box::new(move |snapshot: Snapshot<&M>| match snapshot.seal(&key) {
+        Ok(sealed) => sink(sealed),
+        Err(e) => log::error!("State machine cannot create the snapshot: {:?}", e),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync_channels::StateMachine;
+    use crate::types::{MessageClass, State, TerminationReason, Transition};
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Msg {
+        sender: usize,
+        phase: u32,
+    }
+
+    impl MessageOrigin for Msg {
+        type Party = usize;
+        fn sender(&self) -> usize {
+            self.sender
+        }
+    }
+
+    struct TestTraits;
+
+    impl StateMachineTraits for TestTraits {
+        type InMsg = Msg;
+        type OutMsg = u32;
+        type FinalState = u32;
+        type ErrorState = ();
+    }
+
+    /// announces its number and waits for a message with the same number
+    #[derive(Serialize, Deserialize)]
+    struct Phase(u32);
+
+    impl State<TestTraits> for Phase {
+        fn start(&mut self) -> Option<Vec<u32>> {
+            Some(vec![self.0])
+        }
+
+        fn classify_message(&self, msg: &Msg, _current_msg_set: &[Msg]) -> MessageClass {
+            if msg.phase == self.0 {
+                MessageClass::Accept
+            } else {
+                MessageClass::Defer
+            }
+        }
+
+        fn is_input_complete(&self, current_msg_set: &[Msg]) -> bool {
+            !current_msg_set.is_empty()
+        }
+
+        fn consume(&self, _current_msg_set: Vec<Msg>) -> Transition<TestTraits> {
+            if self.0 < 2 {
+                Transition::NewState(Box::new(Phase(self.0 + 1)))
+            } else {
+                Transition::FinalState(Ok(self.0))
+            }
+        }
+
+        fn timeout_outcome(&self, _current_msg_set: Vec<Msg>) -> Result<u32, ()> {
+            Err(())
+        }
+
+        fn snapshot(&self) -> Option<serde_json::Value> {
+            serde_json::to_value(self).ok()
+        }
+    }
+
+    impl RestoreState for TestTraits {
+        type Context = ();
+
+        fn restore_state(
+            snapshot: serde_json::Value,
+            _context: &(),
+        ) -> Result<BoxedState<Self>, serde_json::Error> {
+            Ok(Box::new(serde_json::from_value::<Phase>(snapshot)?))
+        }
+    }
+
+    fn key() -> SnapshotKey {
+        SnapshotKey::from_slice(&[7u8; secretbox::KEYBYTES]).unwrap()
+    }
+
+    #[test]
+    fn restored_machine_continues() {
+        let (snapshots_tx, snapshots) = crossbeam_channel::unbounded();
+        let sealed = {
+            let (ingress, rx) = crossbeam_channel::unbounded();
+            let (tx, _egress) = crossbeam_channel::unbounded();
+            let mut machine = StateMachine::<TestTraits>::new(Box::new(Phase(0)), &rx, &tx)
+                .with_checkpoints(key(), move |s| snapshots_tx.send(s).unwrap());
+            ingress
+                .send(Msg {
+                    sender: 1,
+                    phase: 0,
+                })
+                .unwrap();
+            ingress
+                .send(Msg {
+                    sender: 1,
+                    phase: 2,
+                })
+                .unwrap();
+            drop(ingress);
+            // the node goes down in the middle of the second phase
+            let aborted = machine.execute().unwrap_err();
+            assert_eq!(aborted.reason, TerminationReason::InputClosed);
+            assert_eq!(aborted.phase, "unnamed");
+            snapshots.try_iter().last().unwrap()
+        };
+
+        let (ingress, rx) = crossbeam_channel::unbounded();
+        let (tx, egress) = crossbeam_channel::unbounded();
+        let mut machine =
+            StateMachine::<TestTraits>::restore(&sealed, &key(), &(), 0, &rx, &tx).unwrap();
+        ingress
+            .send(Msg {
+                sender: 1,
+                phase: 1,
+            })
+            .unwrap();
+        assert!(matches!(machine.execute(), Ok(Ok(2))));
+        // the output of the second phase is not sent again
+        assert_eq!(egress.try_iter().collect::<Vec<_>>(), vec![2]);
+    }
+
+    #[test]
+    fn stale_snapshot_refused() {
+        let (snapshots_tx, snapshots) = crossbeam_channel::unbounded();
+        let (ingress, rx) = crossbeam_channel::unbounded();
+        let (tx, _egress) = crossbeam_channel::unbounded();
+        let mut machine = StateMachine::<TestTraits>::new(Box::new(Phase(0)), &rx, &tx)
+            .with_checkpoints(key(), move |s| snapshots_tx.send(s).unwrap());
+        ingress
+            .send(Msg {
+                sender: 1,
+                phase: 0,
+            })
+            .unwrap();
+        drop(ingress);
+        assert!(machine.execute().is_err());
+        let sealed = snapshots.try_iter().collect::<Vec<_>>();
+        assert!(sealed.windows(2).all(|w| w[0].sequence() < w[1].sequence()));
+
+        let (first, last) = (&sealed[0], sealed.last().unwrap());
+        assert!(matches!(
+            StateMachine::<TestTraits>::restore(first, &key(), &(), last.sequence(), &rx, &tx),
+            Err(CheckpointError::Stale { .. })
+        ));
+        let restored =
+            StateMachine::<TestTraits>::restore(last, &key(), &(), last.sequence(), &rx, &tx)
+                .unwrap();
+        // snapshots of the restored machine continue the sequence
+        assert_eq!(
+            restored.checkpoint(&key()).unwrap().sequence(),
+            last.sequence()
+        );
+
+        // the sequence number in clear has to match the encrypted one
+        let mut forged = first.clone();
+        forged.sequence = last.sequence();
+        assert!(matches!(
+            StateMachine::<TestTraits>::restore(&forged, &key(), &(), last.sequence(), &rx, &tx),
+            Err(CheckpointError::Decryption)
+        ));
+    }
+
+    #[test]
+    fn snapshot_before_start_starts_again() {
+        let (ingress, rx) = crossbeam_channel::unbounded();
+        let (tx, egress) = crossbeam_channel::unbounded();
+        let sealed = StateMachine::<TestTraits>::new(Box::new(Phase(0)), &rx, &tx)
+            .checkpoint(&key())
+            .unwrap();
+        let mut machine =
+            StateMachine::<TestTraits>::restore(&sealed, &key(), &(), 0, &rx, &tx).unwrap();
+        for phase in 0..3 {
+            ingress.send(Msg { sender: 1, phase }).unwrap();
+        }
+        assert!(matches!(machine.execute(), Ok(Ok(2))));
+        // the output of the first phase was never sent, so it is not lost
+        assert_eq!(egress.try_iter().collect::<Vec<_>>(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn wrong_key_refused() {
+        let (_ingress, rx) = crossbeam_channel::unbounded();
+        let (tx, _egress) = crossbeam_channel::unbounded();
+        let machine = StateMachine::<TestTraits>::new(Box::new(Phase(0)), &rx, &tx);
+        let sealed = machine.checkpoint(&key()).unwrap();
+        assert!(matches!(
+            StateMachine::<TestTraits>::restore(
+                &sealed,
+                &SnapshotKey::generate(),
+                &(),
+                0,
+                &rx,
+                &tx
+            ),
+            Err(CheckpointError::Decryption)
+        ));
+    }
+}
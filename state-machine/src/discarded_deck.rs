@@ -0,0 +1,467 @@
+//! Container for deferred messages
+//!
+//! When several distributed nodes execute same network protocol and each node sends and receives same message types, it is hard to achieve the scenario
+//! where all nodes pace with same speed through the protocol. It is very common case when some nodes are faster then others so that their messages arrive to destinations early.
+//! To address that the state machine collects all messages discarded by current state object into the `discarded` container.
+//! The contents of the container becomes available for *next* state object as priority input.
+//!
+//! The container is bounded by [`DeckLimits`]. A misbehaving or noisy party can fill the container with messages which are never consumed,
+//! so the limits cap the total number of deferred messages, the number of deferred messages per sender, and the number of phases a message may wait.
+//! Every message removed from the container because of a limit is counted under its [`DroppedMessage`] record, i.e. per sender and reason,
+//! so that the caller of the machine can identify noisy peers, while a noisy peer cannot make the records grow without bound.
+//!
+//! [`DeckLimits`]: struct.DeckLimits.html
+//! [`DroppedMessage`]: struct.DroppedMessage.html
+
+use crate::types::MessageOrigin;
+use std::collections::{HashMap, VecDeque};
+
+/// Decides what the machine does when a message does not fit into the deck
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// the oldest message (of the same sender, if the per-sender quota is exceeded) is dropped to make room for the new one
+    DropOldest,
+    /// the new message is dropped
+    DropNewest,
+    /// the machine stops and returns no result
+    Abort,
+}
+
+/// Limits of the deck of deferred messages
+///
+/// `None` means the respective limit is not applied. Default limits do not restrict the deck.
+/// Messages which exceed `max_age` are always dropped, regardless of the `policy`.
+#[derive(Debug, Clone, Copy)]
+pub struct DeckLimits {
+    /// maximal number of deferred messages
+    pub max_total: Option<usize>,
+    /// maximal number of deferred messages of one sender
+    pub max_per_sender: Option<usize>,
+    /// maximal number of phase transitions a message may stay deferred
+    pub max_age: Option<usize>,
+    pub policy: OverflowPolicy,
+}
+
+impl Default for DeckLimits {
+    fn default() -> Self {
+        Self {
+            max_total: None,
+            max_per_sender: None,
+            max_age: None,
+            policy: OverflowPolicy::DropOldest,
+        }
+    }
+}
+
+/// The limit which caused a message to be dropped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DropReason {
+    TotalLimit,
+    SenderLimit,
+    Expired,
+}
+
+/// The record of a message dropped from the deck
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DroppedMessage<P> {
+    pub sender: P,
+    pub reason: DropReason,
+}
+
+/// deferred message with its order of arrival and the phase it was deferred at
+struct Deferred<T> {
+    message: T,
+    seq: u64,
+    phase: usize,
+}
+
+/// Two-deck container of deferred messages
+///
+/// To prevent a state from checking already discarded messages at same stage of the protocol more than once, the container has two decks, one for having input for the state object and another for collecting discarded messages.
+pub(crate) struct DiscardedDeck<T: MessageOrigin> {
+    current: VecDeque<Deferred<T>>,
+    next_state: VecDeque<Deferred<T>>,
+    per_sender: HashMap<T::Party, usize>,
+    dropped: HashMap<DroppedMessage<T::Party>, usize>,
+    limits: DeckLimits,
+    seq: u64,
+    phase: usize,
+    recycled: Option<(u64, usize)>,
+    /// messages of a restored machine, which enter the deck once the machine starts, under the limits set by then
+    restored: Vec<T>,
+}
+
+impl<T: MessageOrigin> DiscardedDeck<T> {
+    pub fn new(limits: DeckLimits) -> Self {
+        Self {
+            current: VecDeque::new(),
+            next_state: VecDeque::new(),
+            per_sender: HashMap::new(),
+            dropped: HashMap::new(),
+            limits,
+            seq: 0,
+            phase: 0,
+            recycled: None,
+            restored: Vec::new(),
+        }
+    }
+
+    pub fn set_limits(&mut self, limits: DeckLimits) {
+        self.limits = limits;
+    }
+
+    /// Defers the message
+    ///
+    /// Returns the record of the offending message if the deck is full and the policy is [`OverflowPolicy::Abort`]
+    pub fn save(&mut self, m: T) -> Result<(), DroppedMessage<T::Party>> {
+        let recycled = self.recycled.take();
+        if !self.admit(m.sender())? {
+            return Ok(());
+        }
+
+        // re-deferred messages keep their original order and age
+        let (seq, phase) = recycled.unwrap_or_else(|| {
+            self.seq += 1;
+            (self.seq, self.phase)
+        });
+        self.push(Deferred {
+            message: m,
+            seq,
+            phase,
+        });
+        Ok(())
+    }
+
+    /// Takes next message deferred by previous states
+    ///
+    /// If the message is deferred again by the following call to `save`, it keeps its original order and age.
+    /// The machine reads its input channel only after this method returns `None`, which guarantees that
+    /// a message from the channel is never taken for a re-deferred one.
+    pub fn pop(&mut self) -> Option<T> {
+        let deferred = self.current.pop_front();
+        self.recycled = deferred.as_ref().map(|d| (d.seq, d.phase));
+        let deferred = deferred?;
+        self.forget(&deferred.message);
+        Some(deferred.message)
+    }
+
+    /// Makes messages deferred by the current state available to the next one and drops expired messages
+    pub fn flip(&mut self) {
+        self.phase += 1;
+        while let Some(m) = self.next_state.pop_front() {
+            self.current.push_back(m)
+        }
+        if let Some(max_age) = self.limits.max_age {
+            let phase = self.phase;
+            let (expired, alive): (VecDeque<_>, VecDeque<_>) = self
+                .current
+                .drain(..)
+                .partition(|d| phase - d.phase > max_age);
+            self.current = alive;
+            for d in expired {
+                self.forget(&d.message);
+                self.count_dropped(d.message.sender(), DropReason::Expired);
+            }
+        }
+    }
+
+    /// deferred messages in the order the machine takes them
+    pub fn messages(&self) -> impl Iterator<Item = &T> {
+        self.restored.iter().chain(
+            self.current
+                .iter()
+                .chain(self.next_state.iter())
+                .map(|d| &d.message),
+        )
+    }
+
+    /// Keeps messages of a restored machine until [`admit_restored`](#method.admit_restored), so that limits set after restoring apply to them
+    pub fn restore(&mut self, messages: Vec<T>) {
+        self.restored = messages;
+    }
+
+    /// Puts messages of a restored machine back to the deck under its limits, so that they are available for current state
+    ///
+    /// Returns the record of the offending message if the deck is full and the policy is [`OverflowPolicy::Abort`]
+    pub fn admit_restored(&mut self) -> Result<(), DroppedMessage<T::Party>> {
+        for m in std::mem::take(&mut self.restored) {
+            if self.admit(m.sender())? {
+                self.seq += 1;
+                *self.per_sender.entry(m.sender()).or_insert(0) += 1;
+                self.current.push_back(Deferred {
+                    message: m,
+                    seq: self.seq,
+                    phase: self.phase,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// numbers of messages dropped so far, per sender and reason
+    pub fn dropped(&self) -> &HashMap<DroppedMessage<T::Party>, usize> {
+        &self.dropped
+    }
+
+    pub fn len(&self) -> usize {
+        self.current.len() + self.next_state.len()
+    }
+
+    /// Applies the limits to the new message of `sender`. Returns true if the new message can be stored.
+    fn admit(&mut self, sender: T::Party) -> Result<bool, DroppedMessage<T::Party>> {
+        let sender_count = self.per_sender.get(&sender).copied().unwrap_or(0);
+        if matches!(self.limits.max_per_sender, Some(max) if sender_count >= max)
+            && !self.make_room(sender, DropReason::SenderLimit)?
+        {
+            return Ok(false);
+        }
+        Ok(
+            !matches!(self.limits.max_total, Some(max) if self.len() >= max)
+                || self.make_room(sender, DropReason::TotalLimit)?,
+        )
+    }
+
+    /// Applies the overflow policy to the new message of `sender`. Returns true if the new message can be stored.
+    ///
+    /// Under [`OverflowPolicy::DropOldest`] the new message is dropped if there is no message to drop instead, as with zero limits.
+    fn make_room(
+        &mut self,
+        sender: T::Party,
+        reason: DropReason,
+    ) -> Result<bool, DroppedMessage<T::Party>> {
+        match self.limits.policy {
+            OverflowPolicy::Abort => Err(DroppedMessage { sender, reason }),
+            OverflowPolicy::DropNewest => {
+                self.count_dropped(sender, reason);
+                Ok(false)
+            }
+            OverflowPolicy::DropOldest => {
+                let same_sender = Some(sender).filter(|_| reason == DropReason::SenderLimit);
+                match self.remove_oldest(same_sender) {
+                    Some(oldest) => {
+                        self.count_dropped(oldest.message.sender(), reason);
+                        Ok(true)
+                    }
+                    None => {
+                        self.count_dropped(sender, reason);
+                        Ok(false)
+                    }
+                }
+            }
+        }
+    }
+
+    fn count_dropped(&mut self, sender: T::Party, reason: DropReason) {
+        *self
+            .dropped
+            .entry(DroppedMessage { sender, reason })
+            .or_insert(0) += 1;
+    }
+
+    fn push(&mut self, d: Deferred<T>) {
+        *self.per_sender.entry(d.message.sender()).or_insert(0) += 1;
+        self.next_state.push_back(d);
+    }
+
+    fn forget(&mut self, m: &T) {
+        let sender = m.sender();
+        if let Some(count) = self.per_sender.get_mut(&sender) {
+            *count -= 1;
+            if *count == 0 {
+                self.per_sender.remove(&sender);
+            }
+        }
+    }
+
+    /// removes the oldest message, optionally restricted to given sender
+    fn remove_oldest(&mut self, sender: Option<T::Party>) -> Option<Deferred<T>> {
+        let is_candidate = |d: &Deferred<T>| !matches!(sender, Some(s) if d.message.sender() != s);
+        let in_current = self
+            .current
+            .iter()
+            .enumerate()
+            .filter(|(_, d)| is_candidate(d))
+            .min_by_key(|(_, d)| d.seq)
+            .map(|(i, d)| (i, d.seq));
+        let in_next = self
+            .next_state
+            .iter()
+            .enumerate()
+            .filter(|(_, d)| is_candidate(d))
+            .min_by_key(|(_, d)| d.seq)
+            .map(|(i, d)| (i, d.seq));
+
+        let removed = match (in_current, in_next) {
+            (Some((i, a)), Some((_, b))) if a < b => self.current.remove(i),
+            (_, Some((i, _))) => self.next_state.remove(i),
+            (Some((i, _)), None) => self.current.remove(i),
+            (None, None) => None,
+        };
+        if let Some(d) = &removed {
+            self.forget(&d.message);
+        }
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Msg(usize, u32);
+
+    impl MessageOrigin for Msg {
+        type Party = usize;
+        fn sender(&self) -> usize {
+            self.0
+        }
+    }
+
+    fn dropped(records: &[(usize, DropReason, usize)]) -> HashMap<DroppedMessage<usize>, usize> {
+        records
+            .iter()
+            .map(|&(sender, reason, count)| (DroppedMessage { sender, reason }, count))
+            .collect()
+    }
+
+    fn drain(deck: &mut DiscardedDeck<Msg>) -> Vec<Msg> {
+        deck.flip();
+        std::iter::from_fn(|| deck.pop()).collect()
+    }
+
+    #[test]
+    fn unbounded_by_default() {
+        let mut deck = DiscardedDeck::new(DeckLimits::default());
+        (0..100).for_each(|i| deck.save(Msg(0, i)).unwrap());
+        assert_eq!(drain(&mut deck).len(), 100);
+        assert!(deck.dropped().is_empty());
+    }
+
+    #[test]
+    fn sender_quota_drops_oldest() {
+        let mut deck = DiscardedDeck::new(DeckLimits {
+            max_per_sender: Some(2),
+            ..DeckLimits::default()
+        });
+        for i in 0..4 {
+            deck.save(Msg(1, i)).unwrap();
+        }
+        deck.save(Msg(2, 0)).unwrap();
+        assert_eq!(drain(&mut deck), vec![Msg(1, 2), Msg(1, 3), Msg(2, 0)]);
+        assert_eq!(deck.dropped(), &dropped(&[(1, DropReason::SenderLimit, 2)]));
+    }
+
+    #[test]
+    fn total_limit_drops_newest() {
+        let mut deck = DiscardedDeck::new(DeckLimits {
+            max_total: Some(2),
+            policy: OverflowPolicy::DropNewest,
+            ..DeckLimits::default()
+        });
+        for i in 0..3 {
+            deck.save(Msg(i as usize, i)).unwrap();
+        }
+        assert_eq!(drain(&mut deck), vec![Msg(0, 0), Msg(1, 1)]);
+        assert_eq!(deck.dropped(), &dropped(&[(2, DropReason::TotalLimit, 1)]));
+    }
+
+    #[test]
+    fn total_limit_aborts() {
+        let mut deck = DiscardedDeck::new(DeckLimits {
+            max_total: Some(1),
+            policy: OverflowPolicy::Abort,
+            ..DeckLimits::default()
+        });
+        deck.save(Msg(0, 0)).unwrap();
+        assert!(deck.save(Msg(1, 0)).is_err());
+    }
+
+    #[test]
+    fn messages_expire() {
+        let mut deck = DiscardedDeck::new(DeckLimits {
+            max_age: Some(1),
+            ..DeckLimits::default()
+        });
+        deck.save(Msg(0, 0)).unwrap();
+        deck.flip();
+        // the message is re-deferred by the next state
+        let m = deck.pop().unwrap();
+        deck.save(m).unwrap();
+        deck.save(Msg(1, 0)).unwrap();
+        assert_eq!(drain(&mut deck), vec![Msg(1, 0)]);
+        assert_eq!(deck.dropped(), &dropped(&[(0, DropReason::Expired, 1)]));
+    }
+
+    #[test]
+    fn drops_counted_per_sender_and_reason() {
+        let mut deck = DiscardedDeck::new(DeckLimits {
+            max_per_sender: Some(1),
+            max_total: Some(2),
+            policy: OverflowPolicy::DropNewest,
+            ..DeckLimits::default()
+        });
+        for i in 0..1000 {
+            deck.save(Msg(1, i)).unwrap();
+        }
+        deck.save(Msg(2, 0)).unwrap();
+        deck.save(Msg(3, 0)).unwrap();
+        assert_eq!(
+            deck.dropped(),
+            &dropped(&[
+                (1, DropReason::SenderLimit, 999),
+                (3, DropReason::TotalLimit, 1)
+            ])
+        );
+    }
+
+    #[test]
+    fn zero_limits_drop_new_messages() {
+        let mut deck = DiscardedDeck::new(DeckLimits {
+            max_per_sender: Some(0),
+            ..DeckLimits::default()
+        });
+        deck.save(Msg(0, 0)).unwrap();
+        deck.set_limits(DeckLimits {
+            max_total: Some(0),
+            ..DeckLimits::default()
+        });
+        deck.save(Msg(1, 0)).unwrap();
+        assert!(drain(&mut deck).is_empty());
+        assert_eq!(
+            deck.dropped(),
+            &dropped(&[
+                (0, DropReason::SenderLimit, 1),
+                (1, DropReason::TotalLimit, 1)
+            ])
+        );
+    }
+
+    #[test]
+    fn restored_messages_limited() {
+        let mut deck = DiscardedDeck::new(DeckLimits::default());
+        deck.restore((0..3).map(|i| Msg(1, i)).chain(Some(Msg(2, 0))).collect());
+        assert_eq!(deck.messages().count(), 4);
+        deck.set_limits(DeckLimits {
+            max_per_sender: Some(2),
+            ..DeckLimits::default()
+        });
+        deck.admit_restored().unwrap();
+        assert_eq!(drain(&mut deck), vec![Msg(1, 1), Msg(1, 2), Msg(2, 0)]);
+        assert_eq!(deck.dropped(), &dropped(&[(1, DropReason::SenderLimit, 1)]));
+
+        let mut deck = DiscardedDeck::new(DeckLimits {
+            max_total: Some(1),
+            policy: OverflowPolicy::Abort,
+            ..DeckLimits::default()
+        });
+        deck.restore(vec![Msg(1, 0), Msg(2, 0)]);
+        assert_eq!(
+            deck.admit_restored(),
+            Err(DroppedMessage {
+                sender: 2,
+                reason: DropReason::TotalLimit
+            })
+        );
+    }
+}
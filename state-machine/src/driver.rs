@@ -0,0 +1,1055 @@
+//! Sans-IO driver of state objects
+//!
+//! The driver implements the algorithm of the state machine without any input/output. The caller feeds the driver with messages one by one,
+//! and gets back the messages the driver wants to send along with the outcome of the protocol, once it is available.
+//! The driver never reads the clock. The caller passes the current time to each call, and checks the expiration of timeouts with [`Driver::handle_timeout`]
+//! no later than at the instant returned by [`Driver::deadline`].
+//! This way the driver can be embedded into any event loop, without additional threads or channels.
+//!
+//! Both [`sync_channels::StateMachine`] and [`async_channels::StateMachine`] are thin adapters over the driver, which connect it to their channels.
+//!
+//! [`Driver::handle_timeout`]: struct.Driver.html#method.handle_timeout
+//! [`Driver::deadline`]: struct.Driver.html#method.deadline
+//! [`sync_channels::StateMachine`]: ../sync_channels/struct.StateMachine.html
+//! [`async_channels::StateMachine`]: ../async_channels/struct.StateMachine.html
+
+use crate::checkpoint::{
+    self, checkpoint_hook, CheckpointError, CheckpointHook, RestoreState, SealedSnapshot, Snapshot,
+    SnapshotKey,
+};
+use crate::discarded_deck::{DeckLimits, DiscardedDeck, DroppedMessage};
+use crate::observer::{Ending, Observer};
+use crate::transcript::{record_hook, Event, RecordHook};
+use crate::types::{
+    BoxedState, MessageClass, MessageOrigin, RejectedMessage, Resent, StateMachineTraits,
+    TerminationReason, Transition,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+/// The number of distinct records of rejected messages the driver keeps
+///
+/// Claimed senders of rejected messages are not verified, so a peer could make up a new record with each message.
+/// Messages which would need a record beyond the limit are only counted by [`Driver::unrecorded_rejections`](struct.Driver.html#method.unrecorded_rejections).
+pub const MAX_REJECTED_RECORDS: usize = 256;
+
+/// The record of a message which overflowed the deck configured with [`OverflowPolicy::Abort`](../discarded_deck/enum.OverflowPolicy.html)
+pub type Overflow<T> = DroppedMessage<<<T as StateMachineTraits>::InMsg as MessageOrigin>::Party>;
+
+/// The result of feeding the driver
+pub struct Step<T: StateMachineTraits> {
+    /// messages to be sent out, in the order of their creation
+    pub output: Vec<T::OutMsg>,
+    /// the outcome of the protocol, if the driver has finished
+    pub outcome: Option<Result<T::FinalState, T::ErrorState>>,
+}
+
+impl<T: StateMachineTraits> Step<T> {
+    pub(crate) fn empty() -> Self {
+        Step {
+            output: Vec::new(),
+            outcome: None,
+        }
+    }
+}
+
+/// Diagnostics of the driver which stopped before the protocol produced its outcome
+pub struct Aborted<T>
+where
+    T: StateMachineTraits,
+    T::InMsg: MessageOrigin,
+{
+    pub reason: TerminationReason<<T::InMsg as MessageOrigin>::Party>,
+    /// the name of the phase which was current
+    pub phase: &'static str,
+    /// parties which have not delivered their messages to this phase
+    pub awaited: Vec<<T::InMsg as MessageOrigin>::Party>,
+    /// messages of this phase which the driver had retained
+    pub retained: Vec<T::InMsg>,
+}
+
+impl<T> Debug for Aborted<T>
+where
+    T: StateMachineTraits,
+    T::InMsg: MessageOrigin + Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Aborted")
+            .field("reason", &self.reason)
+            .field("phase", &self.phase)
+            .field("awaited", &self.awaited)
+            .field("retained", &self.retained)
+            .finish()
+    }
+}
+
+/// Sans-IO driver of the protocol
+///
+/// The driver has to be started with [`start`](#method.start) before it handles any message.
+/// After the driver returns the outcome of the protocol, or fails due to the overflow of the deck, it is finished and ignores further input.
+pub struct Driver<T>
+where
+    T: StateMachineTraits,
+    T::InMsg: MessageOrigin,
+{
+    state: BoxedState<T>,
+    retained: Vec<T::InMsg>,
+    discarded: DiscardedDeck<T::InMsg>,
+    rejected: HashMap<RejectedMessage<<T::InMsg as MessageOrigin>::Party>, usize>,
+    unrecorded_rejections: usize,
+    session_timeout: Option<Duration>,
+    session_deadline: Option<Instant>,
+    phase_deadline: Option<Instant>,
+    resend_interval: Option<Duration>,
+    resend_deadline: Option<Instant>,
+    /// own output of the session, so that it can be sent again; released once the driver finishes
+    sent: Vec<T::OutMsg>,
+    /// the instant of the latest answer to each peer which has asked for a round again
+    ///
+    /// States select output only for their peers and only for rounds already sent, so the map is bounded by peers times rounds.
+    /// Answers older than the resend interval are pruned, as they no longer limit the rate.
+    resent: HashMap<(<T::InMsg as MessageOrigin>::Party, u16), Instant>,
+    checkpoints: Option<CheckpointHook<T::InMsg>>,
+    observer: Option<Box<dyn Observer<<T::InMsg as MessageOrigin>::Party>>>,
+    recorder: Option<RecordHook<T::InMsg, T::OutMsg>>,
+    started_at: Option<Instant>,
+    latest: Option<Instant>,
+    /// the sequence number of the driver's contents, stored in its snapshots
+    sequence: u64,
+    restored: bool,
+    ending: Option<Ending>,
+}
+
+impl<T> Driver<T>
+where
+    T: StateMachineTraits,
+    T::InMsg: MessageOrigin,
+{
+    pub fn new(start_state: BoxedState<T>) -> Self {
+        Driver {
+            state: start_state,
+            retained: Vec::new(),
+            discarded: DiscardedDeck::new(DeckLimits::default()),
+            rejected: HashMap::new(),
+            unrecorded_rejections: 0,
+            session_timeout: None,
+            session_deadline: None,
+            phase_deadline: None,
+            resend_interval: None,
+            resend_deadline: None,
+            sent: Vec::new(),
+            resent: HashMap::new(),
+            checkpoints: None,
+            observer: None,
+            recorder: None,
+            started_at: None,
+            latest: None,
+            sequence: 0,
+            restored: false,
+            ending: None,
+        }
+    }
+
+    /// Restores the driver from the snapshot
+    ///
+    /// The driver continues with the state object stored in the snapshot, and does not return its output again,
+    /// unless the snapshot was created before the driver started.
+    /// Output created before the snapshot is not kept, so that the restored driver cannot send it again on request of a peer.
+    ///
+    /// The snapshot is refused with [`CheckpointError::Stale`](../checkpoint/enum.CheckpointError.html#variant.Stale) if its sequence number is less than `latest`,
+    /// the number of the newest snapshot known to the caller.
+    pub fn restore(
+        sealed: &SealedSnapshot,
+        key: &SnapshotKey,
+        context: &T::Context,
+        latest: u64,
+    ) -> Result<Self, CheckpointError>
+    where
+        T: RestoreState,
+        T::InMsg: for<'de> Deserialize<'de>,
+    {
+        let restored = checkpoint::restore::<T>(sealed, key, context, latest)?;
+        let mut driver = Self::new(restored.state);
+        driver.retained = restored.retained;
+        driver.discarded.restore(restored.deferred);
+        driver.sequence = restored.sequence;
+        driver.restored = restored.started;
+        Ok(driver)
+    }
+
+    /// Makes the driver emit the snapshot each time a state object starts, and each time it retains or defers a message
+    ///
+    /// The snapshot of the new state object is emitted before its output is returned to the caller.
+    pub fn with_checkpoints<F>(mut self, key: SnapshotKey, sink: F) -> Self
+    where
+        T::InMsg: Serialize,
+        F: FnMut(SealedSnapshot) + Send + 'static,
+    {
+        self.checkpoints = Some(checkpoint_hook(key, sink));
+        self
+    }
+
+    /// Creates the snapshot of the driver
+    pub fn checkpoint(&self, key: &SnapshotKey) -> Result<SealedSnapshot, CheckpointError>
+    where
+        T::InMsg: Serialize,
+    {
+        Snapshot::of(
+            self.sequence,
+            self.is_state_started(),
+            &self.state,
+            &self.retained,
+            &self.discarded,
+        )
+        .ok_or(CheckpointError::NotSupported)?
+        .seal(key)
+    }
+
+    /// Reports lifecycle events of the driver to the observer
+    pub fn with_observer<O>(mut self, observer: O) -> Self
+    where
+        O: Observer<<T::InMsg as MessageOrigin>::Party> + 'static,
+    {
+        self.observer = Some(Box::new(observer));
+        self
+    }
+
+    /// Writes the transcript of the session into the writer
+    ///
+    /// The transcript contains every inbound and outbound message along with the time and the name of current phase,
+    /// so that it can be fed into a fresh driver with [`transcript::replay`](../transcript/fn.replay.html).
+    /// The transcript is written in plain text, so it exposes messages which the protocol sends over confidential channels.
+    pub fn with_recorder<W>(mut self, writer: W) -> Self
+    where
+        T::InMsg: Serialize,
+        T::OutMsg: Serialize,
+        W: Write + Send + 'static,
+    {
+        self.recorder = Some(record_hook(writer));
+        self
+    }
+
+    /// Limits the duration of entire protocol, in addition to timeouts of its phases
+    pub fn with_session_timeout(mut self, timeout: Duration) -> Self {
+        self.session_timeout = Some(timeout);
+        self
+    }
+
+    /// Makes the driver ask awaited parties to send their messages again, each time the current phase has waited for `interval`
+    ///
+    /// The caller has to call [`request_resend`](#method.request_resend) once the instant returned by [`resend_deadline`](#method.resend_deadline) is reached.
+    /// The interval also limits the rate of answers to requests of peers: the driver sends the same round to the same peer again only once the interval has passed
+    /// since its previous answer. Without the interval the driver answers each peer for each round once.
+    pub fn with_resend_interval(mut self, interval: Duration) -> Self {
+        self.resend_interval = Some(interval);
+        self
+    }
+
+    /// Bounds the deck of deferred messages, which is unbounded by default
+    pub fn with_deck_limits(mut self, limits: DeckLimits) -> Self {
+        self.discarded.set_limits(limits);
+        self
+    }
+
+    /// Numbers of messages dropped from the deck of deferred messages due to its limits, per sender and reason
+    pub fn dropped_messages(
+        &self,
+    ) -> &HashMap<DroppedMessage<<T::InMsg as MessageOrigin>::Party>, usize> {
+        self.discarded.dropped()
+    }
+
+    /// Numbers of messages rejected by states of the protocol, per sender and reason
+    ///
+    /// At most [`MAX_REJECTED_RECORDS`](constant.MAX_REJECTED_RECORDS.html) distinct records are kept.
+    pub fn rejected_messages(
+        &self,
+    ) -> &HashMap<RejectedMessage<<T::InMsg as MessageOrigin>::Party>, usize> {
+        &self.rejected
+    }
+
+    /// The number of rejected messages not counted by [`rejected_messages`](#method.rejected_messages), as their records would exceed the limit
+    pub fn unrecorded_rejections(&self) -> usize {
+        self.unrecorded_rejections
+    }
+
+    /// true if the driver has returned the outcome of the protocol or failed
+    pub fn is_finished(&self) -> bool {
+        self.ending.is_some()
+    }
+
+    /// How the driver has finished, if it has
+    pub fn ending(&self) -> Option<Ending> {
+        self.ending
+    }
+
+    /// true if the driver is restored from the snapshot and has not started yet
+    pub(crate) fn is_restored(&self) -> bool {
+        self.restored
+    }
+
+    /// The name of current phase
+    pub fn phase(&self) -> &'static str {
+        self.state.name()
+    }
+
+    /// The earliest of the deadlines of current phase and of the session
+    ///
+    /// The caller has to call [`handle_timeout`](#method.handle_timeout) once this instant is reached.
+    pub fn deadline(&self) -> Option<Instant> {
+        match (self.phase_deadline, self.session_deadline) {
+            (Some(phase), Some(session)) => Some(phase.min(session)),
+            (phase, session) => phase.or(session),
+        }
+    }
+
+    /// The instant when the driver asks awaited parties to send their messages again, if the resend interval is configured
+    pub fn resend_deadline(&self) -> Option<Instant> {
+        self.resend_deadline.filter(|_| !self.is_finished())
+    }
+
+    /// Returns requests which ask parties awaited by the current phase to send their messages again, and re-arms the resend deadline
+    pub fn request_resend(&mut self, now: Instant) -> Vec<T::OutMsg> {
+        if self.is_finished() {
+            return Vec::new();
+        }
+        self.latest = Some(now);
+        self.resend_deadline = self.resend_interval.map(|t| now + t);
+        let requests = self
+            .state
+            .resend_request(&self.retained)
+            .unwrap_or_default();
+        self.record(Event::ResendRequested);
+        for message in &requests {
+            self.record(Event::Outbound(message));
+        }
+        requests
+    }
+
+    /// Starts the first state object and arms the session deadline
+    ///
+    /// A restored driver processes messages stored in the snapshot at this point, under the limits of its deck.
+    pub fn start(&mut self, now: Instant) -> Result<Step<T>, Overflow<T>> {
+        log::trace!("starting State Machine");
+        self.started_at = Some(now);
+        self.latest = Some(now);
+        self.session_deadline = self.session_timeout.map(|t| now + t);
+        self.discarded.admit_restored().map_err(|overflow| {
+            log::error!("State machine: discarded deck overflow {:?}", overflow);
+            self.finish(Ending::Overflow);
+            overflow
+        })?;
+        let mut step = Step::empty();
+        self.enter_state(now, &mut step.output);
+        self.run(None, now, &mut step)?;
+        Ok(step)
+    }
+
+    /// Feeds the message to the current state object
+    ///
+    /// Returns the output of all state objects which became current while handling the message, and the outcome of the protocol if it is complete.
+    /// Returns the record of the message which caused the overflow of discarded deck, if the deck is configured to abort the protocol.
+    pub fn handle_message(
+        &mut self,
+        message: T::InMsg,
+        now: Instant,
+    ) -> Result<Step<T>, Overflow<T>> {
+        let mut step = Step::empty();
+        if self.is_finished() {
+            log::warn!("State machine: message ignored, the machine is finished");
+            return Ok(step);
+        }
+        self.latest = Some(now);
+        self.record(Event::Inbound(&message));
+        self.run(Some(message), now, &mut step)?;
+        Ok(step)
+    }
+
+    /// Checks the deadlines of current phase and of the session
+    ///
+    /// Returns the `timeout_outcome` of current state object, if any of these deadlines is expired at `now`.
+    pub fn handle_timeout(&mut self, now: Instant) -> Option<Result<T::FinalState, T::ErrorState>> {
+        match self.deadline() {
+            Some(deadline) if !self.is_finished() && now >= deadline => {
+                log::debug!("State machine: timeout");
+                self.latest = Some(now);
+                self.record(Event::Timeout);
+                self.observe(|o, phase| o.timeout(phase));
+                self.finish(Ending::TimedOut);
+                Some(
+                    self.state
+                        .timeout_outcome(self.retained.drain(..).collect()),
+                )
+            }
+            _ => None,
+        }
+    }
+
+    /// The earliest of [`deadline`](#method.deadline) and [`resend_deadline`](#method.resend_deadline), used by adapters of the driver
+    pub(crate) fn wake_up(&self) -> Option<Instant> {
+        match (self.deadline(), self.resend_deadline()) {
+            (Some(deadline), Some(resend)) => Some(deadline.min(resend)),
+            (deadline, resend) => deadline.or(resend),
+        }
+    }
+
+    /// Handles the instant returned by `wake_up`: requests messages again if the resend deadline is reached, then checks timeouts
+    ///
+    /// Returns the timeout outcome as the error, so that adapters can return it at once.
+    pub(crate) fn handle_wake_up(
+        &mut self,
+        now: Instant,
+    ) -> Result<Step<T>, Result<T::FinalState, T::ErrorState>> {
+        if let Some(outcome) = self.handle_timeout(now) {
+            return Err(outcome);
+        }
+        let mut step = Step::empty();
+        if matches!(self.resend_deadline(), Some(deadline) if now >= deadline) {
+            step.output = self.request_resend(now);
+        }
+        Ok(step)
+    }
+
+    /// Stops the driver, so that it ignores further input, and returns the diagnostics of the current phase
+    ///
+    /// The driver which has already finished, for instance due to the overflow of its deck, keeps its ending.
+    pub fn terminate(
+        &mut self,
+        reason: TerminationReason<<T::InMsg as MessageOrigin>::Party>,
+    ) -> Aborted<T> {
+        log::debug!("State machine: terminated {:?}", reason);
+        if !self.is_finished() {
+            self.finish(Ending::Terminated);
+        }
+        Aborted {
+            reason,
+            phase: self.state.name(),
+            awaited: self.state.awaited_senders(&self.retained),
+            retained: self.retained.drain(..).collect(),
+        }
+    }
+
+    /// Processes the message and then messages deferred by previous states, until the input is exhausted or the protocol is complete
+    fn run(
+        &mut self,
+        message: Option<T::InMsg>,
+        now: Instant,
+        step: &mut Step<T>,
+    ) -> Result<(), Overflow<T>> {
+        let mut message = message;
+        // a new message is processed only when the deck has no messages for current state, so that it is taken first
+        while let Some(m) = message.take().or_else(|| self.discarded.pop()) {
+            if let Some(resent) = self.state.resend_output(&m, &self.sent) {
+                if self.may_resend(m.sender(), &resent, now) {
+                    log::debug!(
+                        "State machine: {} messages sent again",
+                        resent.messages.len()
+                    );
+                    for message in &resent.messages {
+                        self.record(Event::Outbound(message));
+                    }
+                    step.output.extend(resent.messages);
+                }
+                continue;
+            }
+            if let Some(error) = self.state.peer_aborted(&m) {
+                log::debug!("State machine: peer aborted the protocol");
+                self.finish(Ending::Failed);
+                step.outcome = Some(Err(error));
+                break;
+            }
+            let transition = self.process_message(m).map_err(|overflow| {
+                log::error!("State machine: discarded deck overflow {:?}", overflow);
+                self.finish(Ending::Overflow);
+                overflow
+            })?;
+            match transition {
+                Some(Transition::NewState(state)) => {
+                    if self.observer.is_some() {
+                        let to = state.name();
+                        self.observe(|o, from| o.transition(from, to));
+                    }
+                    self.state = state;
+                    self.enter_state(now, &mut step.output);
+                    self.discarded.flip();
+                }
+                Some(Transition::FinalState(outcome)) => {
+                    if let Err(error) = &outcome {
+                        let notice = self.state.abort_output(error).unwrap_or_default();
+                        for message in &notice {
+                            self.record(Event::Outbound(message));
+                        }
+                        step.output.extend(notice);
+                    }
+                    self.finish(if outcome.is_ok() {
+                        Ending::Completed
+                    } else {
+                        Ending::Failed
+                    });
+                    step.outcome = Some(outcome);
+                    break;
+                }
+                None => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// internal function which processes the message according to the state machine algorithm
+    fn process_message(&mut self, message: T::InMsg) -> Result<Option<Transition<T>>, Overflow<T>> {
+        // Check message is expected.
+        log::trace!("message received");
+        let sender = message.sender();
+        match self.state.classify_message(&message, &self.retained) {
+            // Message is expected. Retain it.
+            MessageClass::Accept => {
+                self.observe(|o, phase| o.message_retained(phase, sender));
+                self.retained.push(message)
+            }
+            // Message may be expected by next states.
+            MessageClass::Defer => {
+                self.observe(|o, phase| o.message_deferred(phase, sender));
+                self.discarded.save(message)?;
+                self.emit_checkpoint();
+                return Ok(None);
+            }
+            MessageClass::Reject(reason) => {
+                log::debug!("State machine: message rejected {:?}", reason);
+                self.observe(|o, phase| o.message_rejected(phase, sender, reason));
+                self.count_rejected(RejectedMessage { sender, reason });
+                return Ok(None);
+            }
+        }
+
+        // check input is complete.
+        if self.state.is_input_complete(&self.retained) {
+            // Progress to the next state.
+            let transition = self.state.consume(self.retained.drain(..).collect());
+            Ok(Some(transition))
+        } else {
+            // More input is required.
+            self.emit_checkpoint();
+            Ok(None)
+        }
+    }
+
+    /// true if `start` of the current state object has been called, possibly before the snapshot the driver is restored from
+    fn is_state_started(&self) -> bool {
+        self.started_at.is_some() || self.restored
+    }
+
+    /// Advances the sequence number of the driver's contents,
+    /// and passes the snapshot of the driver to the sink, if the driver is configured to emit snapshots
+    fn emit_checkpoint(&mut self) {
+        self.sequence += 1;
+        let started = self.is_state_started();
+        if let Some(hook) = self.checkpoints.as_mut() {
+            match Snapshot::of(
+                self.sequence,
+                started,
+                &self.state,
+                &self.retained,
+                &self.discarded,
+            ) {
+                Some(snapshot) => hook(snapshot),
+                None => log::error!("State machine: the state does not support snapshots"),
+            }
+        }
+    }
+
+    /// Reports the event of the current phase to the observer, if any
+    fn observe<F>(&mut self, event: F)
+    where
+        F: FnOnce(&mut dyn Observer<<T::InMsg as MessageOrigin>::Party>, &'static str),
+    {
+        // names of phases are taken only if they are reported, as state objects may trace their calls
+        if let Some(observer) = self.observer.as_mut() {
+            event(observer.as_mut(), self.state.name())
+        }
+    }
+
+    /// Writes the event of the current phase into the transcript, if the driver records it
+    fn record(&mut self, event: Event<&T::InMsg, &T::OutMsg>) {
+        if let Some(recorder) = self.recorder.as_mut() {
+            let at = match (self.started_at, self.latest) {
+                (Some(started_at), Some(latest)) => latest.saturating_duration_since(started_at),
+                _ => Duration::default(),
+            };
+            recorder(at, self.state.name(), event)
+        }
+    }
+
+    fn finish(&mut self, ending: Ending) {
+        self.ending = Some(ending);
+        // the finished driver answers no requests to send its output again
+        self.sent = Vec::new();
+        self.resent = HashMap::new();
+        self.observe(|o, phase| o.finished(phase, ending));
+        self.record(Event::Finished(ending));
+    }
+
+    fn count_rejected(&mut self, record: RejectedMessage<<T::InMsg as MessageOrigin>::Party>) {
+        if let Some(count) = self.rejected.get_mut(&record) {
+            *count += 1;
+        } else if self.rejected.len() < MAX_REJECTED_RECORDS {
+            self.rejected.insert(record, 1);
+        } else {
+            self.unrecorded_rejections += 1;
+        }
+    }
+
+    /// Tells whether the selected output may be sent to the peer again, and records the answer if so
+    ///
+    /// Requests for rounds the party has not sent yet are dropped, as the peer receives the round once the party gets there.
+    /// They are not counted as answers, nor reported as rejected, since an honest peer may be ahead of the party.
+    fn may_resend(
+        &mut self,
+        peer: <T::InMsg as MessageOrigin>::Party,
+        resent: &Resent<T::OutMsg>,
+        now: Instant,
+    ) -> bool {
+        if resent.messages.is_empty() {
+            log::debug!(
+                "State machine: round {} not sent yet, request of {:?} dropped",
+                resent.round,
+                peer
+            );
+            return false;
+        }
+        // answers older than the resend interval no longer hold back requests
+        if let Some(t) = self.resend_interval {
+            self.resent.retain(|_, answered| now < *answered + t);
+        }
+        match self.resent.get(&(peer, resent.round)) {
+            Some(_) => {
+                log::debug!(
+                    "State machine: round {} already sent again to {:?}, request dropped",
+                    resent.round,
+                    peer
+                );
+                false
+            }
+            _ => {
+                self.resent.insert((peer, resent.round), now);
+                true
+            }
+        }
+    }
+
+    /// Arms the timeout of the new phase and collects its output
+    fn enter_state(&mut self, now: Instant, output: &mut Vec<T::OutMsg>) {
+        self.phase_deadline = self.state.timeout().map(|t| now + t);
+        self.resend_deadline = self.resend_interval.map(|t| now + t);
+        self.observe(|o, phase| o.phase_started(phase));
+        // the output of restored state object has been sent before the snapshot was created
+        if std::mem::replace(&mut self.restored, false) {
+            return;
+        }
+        let start_output = self.state.start().unwrap_or_default();
+        self.emit_checkpoint();
+        for message in &start_output {
+            self.record(Event::Outbound(message));
+        }
+        self.sent.extend(start_output.iter().cloned());
+        output.extend(start_output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{RejectReason, State};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Debug)]
+    struct Msg {
+        sender: usize,
+        phase: u32,
+    }
+
+    impl MessageOrigin for Msg {
+        type Party = usize;
+        fn sender(&self) -> usize {
+            self.sender
+        }
+    }
+
+    struct TestTraits;
+
+    impl StateMachineTraits for TestTraits {
+        type InMsg = Msg;
+        type OutMsg = u32;
+        type FinalState = u32;
+        type ErrorState = u32;
+    }
+
+    /// announces its number and waits for a message with the same number, the last phase has a timeout
+    struct Phase(u32);
+
+    impl State<TestTraits> for Phase {
+        fn start(&mut self) -> Option<Vec<u32>> {
+            Some(vec![self.0])
+        }
+
+        fn classify_message(&self, msg: &Msg, _current_msg_set: &[Msg]) -> MessageClass {
+            if msg.phase == self.0 {
+                MessageClass::Accept
+            } else {
+                MessageClass::Defer
+            }
+        }
+
+        fn is_input_complete(&self, current_msg_set: &[Msg]) -> bool {
+            !current_msg_set.is_empty()
+        }
+
+        fn consume(&self, _current_msg_set: Vec<Msg>) -> Transition<TestTraits> {
+            if self.0 < 2 {
+                Transition::NewState(Box::new(Phase(self.0 + 1)))
+            } else {
+                Transition::FinalState(Ok(self.0))
+            }
+        }
+
+        fn timeout(&self) -> Option<Duration> {
+            Some(Duration::from_secs(u64::from(self.0) + 1))
+        }
+
+        fn timeout_outcome(&self, _current_msg_set: Vec<Msg>) -> Result<u32, u32> {
+            Err(self.0)
+        }
+
+        fn name(&self) -> &'static str {
+            ["phase0", "phase1", "phase2"][self.0 as usize]
+        }
+
+        fn resend_request(&self, _current_msg_set: &[Msg]) -> Option<Vec<u32>> {
+            Some(vec![100 + self.0])
+        }
+
+        /// messages of phases from 10 on ask to send the output of the phase less by 10 again
+        fn resend_output(&self, msg: &Msg, sent: &[u32]) -> Option<Resent<u32>> {
+            msg.phase.checked_sub(10).map(|phase| Resent {
+                round: phase as u16,
+                messages: sent.iter().filter(|m| **m == phase).copied().collect(),
+            })
+        }
+    }
+
+    /// fails on the message of phase 0 and takes messages of party 2 as abort notices
+    struct Guarded;
+
+    impl State<TestTraits> for Guarded {
+        fn start(&mut self) -> Option<Vec<u32>> {
+            None
+        }
+
+        fn classify_message(&self, _msg: &Msg, _current_msg_set: &[Msg]) -> MessageClass {
+            MessageClass::Accept
+        }
+
+        fn is_input_complete(&self, current_msg_set: &[Msg]) -> bool {
+            !current_msg_set.is_empty()
+        }
+
+        fn consume(&self, current_msg_set: Vec<Msg>) -> Transition<TestTraits> {
+            if current_msg_set.iter().any(|m| m.phase == 0) {
+                Transition::FinalState(Err(0))
+            } else {
+                Transition::NewState(Box::new(Guarded))
+            }
+        }
+
+        fn timeout_outcome(&self, _current_msg_set: Vec<Msg>) -> Result<u32, u32> {
+            Err(1)
+        }
+
+        fn abort_output(&self, error: &u32) -> Option<Vec<u32>> {
+            Some(vec![100 + error])
+        }
+
+        fn peer_aborted(&self, msg: &Msg) -> Option<u32> {
+            if msg.sender == 2 {
+                Some(msg.phase)
+            } else {
+                None
+            }
+        }
+    }
+
+    /// rejects every message as the one of an unknown sender
+    struct Rejecting;
+
+    impl State<TestTraits> for Rejecting {
+        fn start(&mut self) -> Option<Vec<u32>> {
+            None
+        }
+
+        fn classify_message(&self, _msg: &Msg, _current_msg_set: &[Msg]) -> MessageClass {
+            MessageClass::Reject(RejectReason::UnknownSender)
+        }
+
+        fn is_input_complete(&self, _current_msg_set: &[Msg]) -> bool {
+            false
+        }
+
+        fn consume(&self, _current_msg_set: Vec<Msg>) -> Transition<TestTraits> {
+            Transition::FinalState(Err(0))
+        }
+
+        fn timeout_outcome(&self, _current_msg_set: Vec<Msg>) -> Result<u32, u32> {
+            Err(0)
+        }
+    }
+
+    /// records events as strings
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl Observer<usize> for Recorder {
+        fn phase_started(&mut self, phase: &'static str) {
+            self.0.lock().unwrap().push(format!("start {}", phase));
+        }
+        fn message_retained(&mut self, phase: &'static str, sender: usize) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("retain {} {}", phase, sender));
+        }
+        fn message_deferred(&mut self, phase: &'static str, sender: usize) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("defer {} {}", phase, sender));
+        }
+        fn transition(&mut self, from: &'static str, to: &'static str) {
+            self.0.lock().unwrap().push(format!("{} -> {}", from, to));
+        }
+        fn finished(&mut self, phase: &'static str, ending: Ending) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("end {} {:?}", phase, ending));
+        }
+    }
+
+    fn msg(phase: u32) -> Msg {
+        Msg { sender: 1, phase }
+    }
+
+    #[test]
+    fn deferred_messages_processed_after_transition() {
+        let now = Instant::now();
+        let mut driver = Driver::<TestTraits>::new(Box::new(Phase(0)));
+        assert_eq!(driver.start(now).unwrap().output, vec![0]);
+
+        let step = driver.handle_message(msg(2), now).unwrap();
+        assert!(step.output.is_empty() && step.outcome.is_none());
+        let step = driver.handle_message(msg(1), now).unwrap();
+        assert!(step.output.is_empty() && step.outcome.is_none());
+
+        // the message of phase 0 makes the deferred messages of phases 1 and 2 available
+        let step = driver.handle_message(msg(0), now).unwrap();
+        assert_eq!(step.output, vec![1, 2]);
+        assert_eq!(step.outcome, Some(Ok(2)));
+        assert!(driver.is_finished());
+    }
+
+    #[test]
+    fn timeout_checked_against_given_time() {
+        let now = Instant::now();
+        let mut driver = Driver::<TestTraits>::new(Box::new(Phase(0)))
+            .with_session_timeout(Duration::from_secs(10));
+        driver.start(now).unwrap();
+        assert_eq!(driver.deadline(), Some(now + Duration::from_secs(1)));
+
+        driver.handle_message(msg(0), now).unwrap();
+        assert_eq!(driver.deadline(), Some(now + Duration::from_secs(2)));
+        assert!(driver
+            .handle_timeout(now + Duration::from_secs(1))
+            .is_none());
+        assert_eq!(
+            driver.handle_timeout(now + Duration::from_secs(2)),
+            Some(Err(1))
+        );
+        assert!(driver.is_finished());
+    }
+
+    #[test]
+    fn earlier_output_resent_on_request() {
+        let now = Instant::now();
+        let mut driver = Driver::<TestTraits>::new(Box::new(Phase(0)));
+        driver.start(now).unwrap();
+        driver.handle_message(msg(0), now).unwrap();
+        assert_eq!(driver.phase(), "phase1");
+
+        let step = driver.handle_message(msg(10), now).unwrap();
+        assert_eq!(step.output, vec![0]);
+        assert!(step.outcome.is_none());
+        assert_eq!(driver.phase(), "phase1");
+        // nothing has been sent in the phase 2 yet
+        assert!(driver
+            .handle_message(msg(12), now)
+            .unwrap()
+            .output
+            .is_empty());
+
+        // each peer gets each round once, without the resend interval
+        assert!(driver
+            .handle_message(msg(10), now)
+            .unwrap()
+            .output
+            .is_empty());
+        let other = Msg {
+            sender: 2,
+            phase: 10,
+        };
+        assert_eq!(driver.handle_message(other, now).unwrap().output, vec![0]);
+        assert_eq!(driver.handle_message(msg(11), now).unwrap().output, vec![1]);
+        assert!(driver.rejected_messages().is_empty());
+    }
+
+    #[test]
+    fn resent_output_rate_limited() {
+        let now = Instant::now();
+        let interval = Duration::from_millis(500);
+        let mut driver =
+            Driver::<TestTraits>::new(Box::new(Phase(0))).with_resend_interval(interval);
+        driver.start(now).unwrap();
+        assert_eq!(driver.handle_message(msg(10), now).unwrap().output, vec![0]);
+        let later = now + interval / 2;
+        assert!(driver
+            .handle_message(msg(10), later)
+            .unwrap()
+            .output
+            .is_empty());
+        let later = now + interval;
+        assert_eq!(
+            driver.handle_message(msg(10), later).unwrap().output,
+            vec![0]
+        );
+    }
+
+    #[test]
+    fn records_of_resent_output_released() {
+        let now = Instant::now();
+        let interval = Duration::from_millis(500);
+        let mut driver =
+            Driver::<TestTraits>::new(Box::new(Phase(0))).with_resend_interval(interval);
+        driver.start(now).unwrap();
+        assert_eq!(driver.handle_message(msg(10), now).unwrap().output, vec![0]);
+        let other = Msg {
+            sender: 2,
+            phase: 10,
+        };
+        let later = now + interval;
+        assert_eq!(driver.handle_message(other, later).unwrap().output, vec![0]);
+        assert_eq!(driver.resent.len(), 1);
+
+        driver.handle_message(msg(0), later).unwrap();
+        driver.handle_message(msg(1), later).unwrap();
+        driver.handle_message(msg(2), later).unwrap();
+        assert!(driver.is_finished());
+        assert!(driver.sent.is_empty());
+        assert!(driver.resent.is_empty());
+    }
+
+    #[test]
+    fn rejections_counted_up_to_limit() {
+        let now = Instant::now();
+        let mut driver = Driver::<TestTraits>::new(Box::new(Rejecting));
+        driver.start(now).unwrap();
+        for sender in 0..MAX_REJECTED_RECORDS + 5 {
+            driver
+                .handle_message(Msg { sender, phase: 0 }, now)
+                .unwrap();
+        }
+        driver.handle_message(msg(0), now).unwrap();
+
+        let rejected = driver.rejected_messages();
+        assert_eq!(rejected.len(), MAX_REJECTED_RECORDS);
+        let record = RejectedMessage {
+            sender: 1,
+            reason: RejectReason::UnknownSender,
+        };
+        assert_eq!(rejected.get(&record), Some(&2));
+        assert_eq!(driver.unrecorded_rejections(), 5);
+    }
+
+    #[test]
+    fn resend_requested_at_interval() {
+        let now = Instant::now();
+        let interval = Duration::from_millis(500);
+        let mut driver =
+            Driver::<TestTraits>::new(Box::new(Phase(0))).with_resend_interval(interval);
+        driver.start(now).unwrap();
+        assert_eq!(driver.resend_deadline(), Some(now + interval));
+        assert_eq!(driver.wake_up(), Some(now + interval));
+
+        let step = driver.handle_wake_up(now + interval).ok().unwrap();
+        assert_eq!(step.output, vec![100]);
+        assert_eq!(driver.resend_deadline(), Some(now + 2 * interval));
+        // the timeout of the phase comes first, so that no request is sent
+        assert_eq!(
+            driver.handle_wake_up(now + 2 * interval).err(),
+            Some(Err(0))
+        );
+        assert!(driver.resend_deadline().is_none());
+    }
+
+    #[test]
+    fn failure_sends_abort_notice() {
+        let now = Instant::now();
+        let mut driver = Driver::<TestTraits>::new(Box::new(Guarded));
+        driver.start(now).unwrap();
+        let step = driver.handle_message(msg(1), now).unwrap();
+        assert!(step.output.is_empty() && step.outcome.is_none());
+        let step = driver.handle_message(msg(0), now).unwrap();
+        assert_eq!(step.output, vec![100]);
+        assert_eq!(step.outcome, Some(Err(0)));
+        assert!(driver.is_finished());
+    }
+
+    #[test]
+    fn peer_abort_ends_machine() {
+        let now = Instant::now();
+        let mut driver = Driver::<TestTraits>::new(Box::new(Guarded));
+        driver.start(now).unwrap();
+        let step = driver
+            .handle_message(
+                Msg {
+                    sender: 2,
+                    phase: 7,
+                },
+                now,
+            )
+            .unwrap();
+        assert!(step.output.is_empty());
+        assert_eq!(step.outcome, Some(Err(7)));
+        assert!(driver.is_finished());
+    }
+
+    #[test]
+    fn observer_sees_lifecycle() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let now = Instant::now();
+        let mut driver =
+            Driver::<TestTraits>::new(Box::new(Phase(1))).with_observer(Recorder(events.clone()));
+        driver.start(now).unwrap();
+        driver.handle_message(msg(2), now).unwrap();
+        driver.handle_message(msg(1), now).unwrap();
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                "start phase1",
+                "defer phase1 1",
+                "retain phase1 1",
+                "phase1 -> phase2",
+                "start phase2",
+                "retain phase2 1",
+                "end phase2 Completed",
+            ]
+        );
+    }
+}
@@ -7,19 +7,29 @@
 //! The machine act as a wrapper between network layer and state objects. At the high level machine performs following steps for each state:
 //!  * it sends optional output to a network
 //!  * it receives and collects input until the state indicates that sufficient number of messages for current stage of a protocol is received
-//!  * the machine let the state consume entire relevant input. Irrelevant messages are collected into discarded deck.
+//!  * the machine let the state consume entire relevant input. Messages which may be relevant for later phases are collected into discarded deck, messages rejected by the state are dropped.
 //!  * the machine checks if the result of consumption is the new state object or [`Final State`] type. The former substitutes current state object in the machine, while the latter cause the machine to terminate.
 //!  * if discarded deck is not empty, and the machine continues, it processes messages from this deck first with new state object.
 //!
+//! The machine can store its current state into an encrypted snapshot and be restored from it later, see [`checkpoint`].
+//!
+//! The discarded deck can be bounded with [`DeckLimits`]. Messages dropped from the deck are counted per sender and reason so that the caller can identify noisy parties.
+//!
+//! Lifecycle events of the machine, like starts of phases, classification of messages, transitions and timeouts, can be reported to an [`Observer`].
+//!
+//! The machine can record a [`transcript`] of the session, which can be replayed later to find where the run went wrong.
+//!
 //!
 //! # Async model and futures
 //!
 //! The module contains two implementations of the state machine, one which deals with async queues and another , which uses more traditional synchronous queues from `crossbeam_channel` crate. All remaining properties of these machines are identical.
+//! Both of them are adapters over the sans-IO [`Driver`], which implements the algorithm of the machine without any input/output. The driver can be embedded into an event loop of the caller directly, without threads and channels.
 //!
 //!  # Implementation details
 //!
 //!  First time the state object becomes the current, the machine will output the result of `start` method to a network. After that the machine enters the loop where it receives messages from its input channel and feeds them to
-//!  `is_message_expected` method of the trait [`State`]. If the function returns true, then the message will be stored in the machine's container `retained`, otherwise the message is stored into container `discarded`.
+//!  `classify_message` method of the trait [`State`]. If the function returns [`MessageClass::Accept`], then the message will be stored in the machine's container `retained`. If it returns `MessageClass::Defer`, the message is stored into container `discarded`.
+//!  Messages classified as `MessageClass::Reject` are never buffered. The machine records their senders along with the reason of rejection, so that the caller can report suspicious behavior of these parties.
 //!  If the message was stored to `retained`, the machine immediately calls `is_input_complete`. This method results with True if the state has received all expected input. The machine act on this condition
 //!  by calling `consume` method, otherwise it continues listening for input messages. The `consume` method returns [`Transition`]. IF its value is `Transition:::NewState`,
 //!  the value becomes new state object in the machine. If its value is `Transition::FinalState`, this value is returned by the machine and the machine terminates.
@@ -27,10 +37,28 @@
 //!  The machine supports timeouts in the protocol. The `timeout` method of corresponding state object has to return `Some` Duration so that when this object becomes current state object the duration will be stored internally by the machine.
 //!  The machine will check then if this duration is expired before  `is_input_complete` returns true.
 //!  In this case the machine stops and returns the value provided by `timeout_outcome` method of the current state object.
+//!  Additionally, the async machine can limit the duration of entire session. If the session deadline expires first, the machine returns the `timeout_outcome` of the current state object as well.
+//!
+//!  The machine which stops before the protocol produces its outcome, because it is terminated with `Instruction::Terminate`, its input channel is closed, or its discarded deck overflows,
+//!  returns [`Aborted`] instead. It carries the [`TerminationReason`], the name of the current phase, the parties which the phase still awaited, and the messages it had retained.
 //!
 //! [`State`]: trait.State.html
 //! [`Transition`]: enum.Transition.html
+//! [`MessageClass::Accept`]: types/enum.MessageClass.html
+//! [`DeckLimits`]: discarded_deck/struct.DeckLimits.html
+//! [`checkpoint`]: checkpoint/index.html
+//! [`Driver`]: driver/struct.Driver.html
+//! [`Aborted`]: driver/struct.Aborted.html
+//! [`TerminationReason`]: types/enum.TerminationReason.html
+//! [`Observer`]: observer/trait.Observer.html
+//! [`transcript`]: transcript/index.html
 //!
 pub mod async_channels;
+pub mod checkpoint;
+pub mod discarded_deck;
+pub mod driver;
+pub mod observer;
+pub mod router;
 pub mod sync_channels;
+pub mod transcript;
 pub mod types;
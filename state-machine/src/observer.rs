@@ -0,0 +1,45 @@
+//! Observer of the machine lifecycle
+//!
+//! The machine reports its lifecycle events to an optional [`Observer`], so that the caller can feed metrics or an audit log
+//! without turning on the trace output of state objects. Phases are identified by names returned by `State::name`.
+//! Observer methods are called synchronously from the machine loop, so they should return quickly.
+//!
+//! [`Observer`]: trait.Observer.html
+
+use crate::types::RejectReason;
+use serde::{Deserialize, Serialize};
+
+/// How the machine has ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Ending {
+    /// the protocol returned its final state
+    Completed,
+    /// the protocol returned its error state
+    Failed,
+    /// a deadline expired
+    TimedOut,
+    /// the deck of deferred messages overflowed
+    Overflow,
+    /// the machine was terminated by its caller or its input stream was closed
+    Terminated,
+}
+
+/// Receiver of lifecycle events of the machine
+///
+/// `P` is the type of party indexes. All methods do nothing by default.
+pub trait Observer<P>: Send {
+    /// the phase has become current
+    fn phase_started(&mut self, _phase: &'static str) {}
+    /// the message of `sender` is retained by the phase
+    fn message_retained(&mut self, _phase: &'static str, _sender: P) {}
+    /// the message of `sender` is deferred by the phase. A message can be deferred by several phases
+    fn message_deferred(&mut self, _phase: &'static str, _sender: P) {}
+    /// the message of `sender` is rejected by the phase
+    fn message_rejected(&mut self, _phase: &'static str, _sender: P, _reason: RejectReason) {}
+    /// the phase `from` consumed its input and was replaced with the phase `to`
+    fn transition(&mut self, _from: &'static str, _to: &'static str) {}
+    /// the deadline of the phase or of the session expired
+    fn timeout(&mut self, _phase: &'static str) {}
+    /// the machine has ended in the phase
+    fn finished(&mut self, _phase: &'static str, _ending: Ending) {}
+}
@@ -0,0 +1,447 @@
+//! Router of concurrent sessions
+//!
+//! An MPC node usually runs many sessions of different protocols at the same time, like several keygen and signing sessions.
+//! The [`SessionRouter`] hosts a [`Driver`] for each session, keyed by the session id, and routes incoming messages to the driver of their session.
+//! The router is sans-IO as the driver is: the caller passes the current time, sends out the messages the router returns, and calls
+//! [`SessionRouter::reap`] no later than at the instant returned by [`SessionRouter::next_deadline`].
+//! Drivers configured `with_resend_interval` ask awaited parties for their messages again from `reap`, which returns these requests.
+//!
+//! Drivers of different protocols are hosted by same router, as long as their message and result types are convertible into common types of the router.
+//! Typically, the application defines enums which wrap messages and results of all protocols it runs.
+//! The outcome of each session is delivered through the completion stream, which is returned along with the router.
+//!
+//! [`SessionRouter`]: struct.SessionRouter.html
+//! [`Driver`]: ../driver/struct.Driver.html
+//! [`SessionRouter::reap`]: struct.SessionRouter.html#method.reap
+//! [`SessionRouter::next_deadline`]: struct.SessionRouter.html#method.next_deadline
+
+use crate::discarded_deck::DeckLimits;
+use crate::driver::{Driver, Step};
+use crate::types::{MessageOrigin, StateMachineTraits};
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// Limits applied to every session of the router
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SessionLimits {
+    /// maximal number of concurrent sessions
+    pub max_sessions: Option<usize>,
+    /// maximal duration of each session, in addition to timeouts of phases of the protocol
+    pub session_timeout: Option<Duration>,
+    /// limits of the deck of deferred messages of each session
+    pub deck: DeckLimits,
+}
+
+/// How a session has ended
+#[derive(Debug)]
+pub enum SessionOutcome<R> {
+    /// the protocol is complete, successfully or not
+    Completed(R),
+    /// a deadline of the session expired, the value is the `timeout_outcome` of the phase which was current at that moment
+    TimedOut(R),
+    /// the deck of deferred messages overflowed and the session was aborted
+    Overflow,
+}
+
+/// The outcome of a session delivered through the completion stream
+#[derive(Debug)]
+pub struct Completion<K, R> {
+    pub session: K,
+    pub outcome: SessionOutcome<R>,
+}
+
+/// Errors of the router
+#[derive(Debug, Error)]
+pub enum RouterError<K: Debug> {
+    #[error("session {0:?} already exists")]
+    DuplicateSession(K),
+    #[error("session limit {0} reached")]
+    TooManySessions(usize),
+    #[error("unknown session {0:?}")]
+    UnknownSession(K),
+    #[error("message does not belong to the protocol of session {0:?}")]
+    UnexpectedMessage(K),
+}
+
+/// The output of the session and its outcome, converted to types of the router
+struct HostedStep<O, R> {
+    output: Vec<O>,
+    outcome: Option<SessionOutcome<R>>,
+}
+
+/// Object safe interface of drivers of any protocol
+trait HostedSession<M, O, R>: Send {
+    fn start(&mut self, now: Instant) -> HostedStep<O, R>;
+    /// returns `None` if the message does not belong to the protocol
+    fn handle_message(&mut self, message: M, now: Instant) -> Option<HostedStep<O, R>>;
+    fn handle_timeout(&mut self, now: Instant) -> Option<R>;
+    fn deadline(&self) -> Option<Instant>;
+    fn resend_deadline(&self) -> Option<Instant>;
+    fn request_resend(&mut self, now: Instant) -> Vec<O>;
+}
+
+impl<T, M, O, R> HostedSession<M, O, R> for Driver<T>
+where
+    T: StateMachineTraits,
+    T::InMsg: MessageOrigin + TryFrom<M>,
+    O: From<T::OutMsg>,
+    R: From<Result<T::FinalState, T::ErrorState>>,
+    Driver<T>: Send,
+{
+    fn start(&mut self, now: Instant) -> HostedStep<O, R> {
+        convert_step(Driver::start(self, now))
+    }
+
+    fn handle_message(&mut self, message: M, now: Instant) -> Option<HostedStep<O, R>> {
+        let message = T::InMsg::try_from(message).ok()?;
+        Some(convert_step(Driver::handle_message(self, message, now)))
+    }
+
+    fn handle_timeout(&mut self, now: Instant) -> Option<R> {
+        Driver::handle_timeout(self, now).map(R::from)
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        Driver::deadline(self)
+    }
+
+    fn resend_deadline(&self) -> Option<Instant> {
+        Driver::resend_deadline(self)
+    }
+
+    fn request_resend(&mut self, now: Instant) -> Vec<O> {
+        Driver::request_resend(self, now)
+            .into_iter()
+            .map(O::from)
+            .collect()
+    }
+}
+
+fn convert_step<T, O, R, E>(step: Result<Step<T>, E>) -> HostedStep<O, R>
+where
+    T: StateMachineTraits,
+    O: From<T::OutMsg>,
+    R: From<Result<T::FinalState, T::ErrorState>>,
+    E: Debug,
+{
+    match step {
+        Ok(step) => HostedStep {
+            output: step.output.into_iter().map(O::from).collect(),
+            outcome: step
+                .outcome
+                .map(|outcome| SessionOutcome::Completed(R::from(outcome))),
+        },
+        Err(overflow) => {
+            log::error!("Session router: discarded deck overflow {:?}", overflow);
+            HostedStep {
+                output: Vec::new(),
+                outcome: Some(SessionOutcome::Overflow),
+            }
+        }
+    }
+}
+
+/// Router which hosts drivers of concurrent sessions
+///
+/// `K` is the type of session ids, `M` and `O` are types of incoming and outgoing messages of all hosted protocols, `R` is the type of their results.
+/// A session is removed from the router as soon as its outcome is sent to the completion stream.
+pub struct SessionRouter<K, M, O, R> {
+    sessions: HashMap<K, Box<dyn HostedSession<M, O, R>>>,
+    limits: SessionLimits,
+    completions: UnboundedSender<Completion<K, R>>,
+}
+
+impl<K, M, O, R> SessionRouter<K, M, O, R>
+where
+    K: Clone + Eq + Hash + Debug,
+{
+    /// Creates the router and the stream of outcomes of its sessions
+    pub fn new(limits: SessionLimits) -> (Self, UnboundedReceiver<Completion<K, R>>) {
+        let (completions, stream) = mpsc::unbounded();
+        (
+            SessionRouter {
+                sessions: HashMap::new(),
+                limits,
+                completions,
+            },
+            stream,
+        )
+    }
+
+    /// Starts the session and returns the output of its first phase
+    ///
+    /// Limits of the router override the session timeout and deck limits of the driver.
+    pub fn insert<T>(
+        &mut self,
+        session: K,
+        driver: Driver<T>,
+        now: Instant,
+    ) -> Result<Vec<O>, RouterError<K>>
+    where
+        T: StateMachineTraits,
+        T::InMsg: MessageOrigin + TryFrom<M>,
+        O: From<T::OutMsg>,
+        R: From<Result<T::FinalState, T::ErrorState>>,
+        Driver<T>: Send + 'static,
+    {
+        if self.sessions.contains_key(&session) {
+            return Err(RouterError::DuplicateSession(session));
+        }
+        if let Some(max) = self.limits.max_sessions {
+            if self.sessions.len() >= max {
+                return Err(RouterError::TooManySessions(max));
+            }
+        }
+        let mut driver = driver.with_deck_limits(self.limits.deck);
+        if let Some(timeout) = self.limits.session_timeout {
+            driver = driver.with_session_timeout(timeout);
+        }
+        let mut hosted: Box<dyn HostedSession<M, O, R>> = Box::new(driver);
+        let step = hosted.start(now);
+        self.sessions.insert(session.clone(), hosted);
+        Ok(self.complete(session, step))
+    }
+
+    /// Routes the message to its session and returns the output of the session
+    pub fn route(
+        &mut self,
+        session: &K,
+        message: M,
+        now: Instant,
+    ) -> Result<Vec<O>, RouterError<K>> {
+        let hosted = self
+            .sessions
+            .get_mut(session)
+            .ok_or_else(|| RouterError::UnknownSession(session.clone()))?;
+        let step = hosted
+            .handle_message(message, now)
+            .ok_or_else(|| RouterError::UnexpectedMessage(session.clone()))?;
+        Ok(self.complete(session.clone(), step))
+    }
+
+    /// Removes sessions whose deadlines expired at `now`, and sends their timeout outcomes to the completion stream
+    ///
+    /// Returns requests to send messages again of the remaining sessions whose resend deadlines are reached.
+    pub fn reap(&mut self, now: Instant) -> Vec<O> {
+        let expired = self
+            .sessions
+            .iter_mut()
+            .filter_map(|(session, hosted)| {
+                hosted
+                    .handle_timeout(now)
+                    .map(|outcome| (session.clone(), outcome))
+            })
+            .collect::<Vec<_>>();
+        for (session, outcome) in expired {
+            log::debug!("Session router: session {:?} timed out", session);
+            self.sessions.remove(&session);
+            self.send_completion(session, SessionOutcome::TimedOut(outcome));
+        }
+        self.sessions
+            .values_mut()
+            .filter(|s| matches!(s.resend_deadline(), Some(deadline) if deadline <= now))
+            .flat_map(|s| s.request_resend(now))
+            .collect()
+    }
+
+    /// The earliest deadline or resend deadline of hosted sessions
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.sessions
+            .values()
+            .flat_map(|s| s.deadline().into_iter().chain(s.resend_deadline()))
+            .min()
+    }
+
+    /// Number of running sessions
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    pub fn contains(&self, session: &K) -> bool {
+        self.sessions.contains_key(session)
+    }
+
+    /// Removes the finished session and sends its outcome to the completion stream
+    fn complete(&mut self, session: K, step: HostedStep<O, R>) -> Vec<O> {
+        if let Some(outcome) = step.outcome {
+            self.sessions.remove(&session);
+            self.send_completion(session, outcome);
+        }
+        step.output
+    }
+
+    fn send_completion(&self, session: K, outcome: SessionOutcome<R>) {
+        if self
+            .completions
+            .unbounded_send(Completion { session, outcome })
+            .is_err()
+        {
+            log::warn!("Session router: completion stream is closed");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{BoxedState, MessageClass, State, Transition};
+    use futures::StreamExt;
+
+    #[derive(Debug)]
+    struct Msg {
+        sender: usize,
+    }
+
+    impl MessageOrigin for Msg {
+        type Party = usize;
+        fn sender(&self) -> usize {
+            self.sender
+        }
+    }
+
+    /// a message of the router, which belongs to one of two protocols
+    #[derive(Debug)]
+    enum AnyMsg {
+        Count(usize),
+        Other,
+    }
+
+    impl TryFrom<AnyMsg> for Msg {
+        type Error = AnyMsg;
+        fn try_from(msg: AnyMsg) -> Result<Self, AnyMsg> {
+            match msg {
+                AnyMsg::Count(sender) => Ok(Msg { sender }),
+                other => Err(other),
+            }
+        }
+    }
+
+    struct CountTraits;
+
+    impl StateMachineTraits for CountTraits {
+        type InMsg = Msg;
+        type OutMsg = String;
+        type FinalState = usize;
+        type ErrorState = ();
+    }
+
+    /// waits for messages from two parties
+    struct Count;
+
+    impl State<CountTraits> for Count {
+        fn start(&mut self) -> Option<Vec<String>> {
+            Some(vec!["hello".to_owned()])
+        }
+
+        fn classify_message(&self, _msg: &Msg, _current_msg_set: &[Msg]) -> MessageClass {
+            MessageClass::Accept
+        }
+
+        fn is_input_complete(&self, current_msg_set: &[Msg]) -> bool {
+            current_msg_set.len() == 2
+        }
+
+        fn consume(&self, current_msg_set: Vec<Msg>) -> Transition<CountTraits> {
+            Transition::FinalState(Ok(current_msg_set.len()))
+        }
+
+        fn timeout(&self) -> Option<Duration> {
+            Some(Duration::from_secs(1))
+        }
+
+        fn timeout_outcome(&self, _current_msg_set: Vec<Msg>) -> Result<usize, ()> {
+            Err(())
+        }
+
+        fn resend_request(&self, _current_msg_set: &[Msg]) -> Option<Vec<String>> {
+            Some(vec!["resend".to_owned()])
+        }
+    }
+
+    type Router = SessionRouter<u32, AnyMsg, String, Result<usize, ()>>;
+
+    fn count() -> Driver<CountTraits> {
+        Driver::new(Box::new(Count) as BoxedState<CountTraits>)
+    }
+
+    #[test]
+    fn routes_to_sessions_and_reaps() {
+        let now = Instant::now();
+        let (mut router, mut completions) = Router::new(SessionLimits {
+            max_sessions: Some(2),
+            ..SessionLimits::default()
+        });
+        assert_eq!(router.insert(1, count(), now).unwrap(), vec!["hello"]);
+        router.insert(2, count(), now).unwrap();
+        assert!(matches!(
+            router.insert(3, count(), now),
+            Err(RouterError::TooManySessions(2))
+        ));
+        assert!(matches!(
+            router.route(&1, AnyMsg::Other, now),
+            Err(RouterError::UnexpectedMessage(1))
+        ));
+
+        router.route(&1, AnyMsg::Count(1), now).unwrap();
+        router.route(&1, AnyMsg::Count(2), now).unwrap();
+        assert!(!router.contains(&1));
+        assert!(router.reap(now + Duration::from_secs(1)).is_empty());
+        assert!(router.is_empty());
+
+        let completions = futures::executor::block_on(async {
+            vec![
+                completions.next().await.unwrap(),
+                completions.next().await.unwrap(),
+            ]
+        });
+        assert!(matches!(
+            completions[0],
+            Completion {
+                session: 1,
+                outcome: SessionOutcome::Completed(Ok(2))
+            }
+        ));
+        assert!(matches!(
+            completions[1],
+            Completion {
+                session: 2,
+                outcome: SessionOutcome::TimedOut(Err(()))
+            }
+        ));
+    }
+
+    #[test]
+    fn lost_message_requested_again() {
+        let now = Instant::now();
+        let interval = Duration::from_millis(100);
+        let (mut router, mut completions) = Router::new(SessionLimits::default());
+        router
+            .insert(1, count().with_resend_interval(interval), now)
+            .unwrap();
+        router.route(&1, AnyMsg::Count(1), now).unwrap();
+
+        // the message of the second party is lost
+        assert_eq!(router.next_deadline(), Some(now + interval));
+        assert!(router.reap(now).is_empty());
+        assert_eq!(router.reap(now + interval), vec!["resend"]);
+        assert_eq!(router.next_deadline(), Some(now + interval * 2));
+        router.route(&1, AnyMsg::Count(2), now + interval).unwrap();
+        assert!(router.is_empty());
+        assert!(matches!(
+            futures::executor::block_on(completions.next()),
+            Some(Completion {
+                session: 1,
+                outcome: SessionOutcome::Completed(Ok(2))
+            })
+        ));
+    }
+}
@@ -7,10 +7,18 @@
 //!
 //! [`Async state machine`]: ../async_channels/index.html
 //!
-use crate::types::{BoxedState, StateMachineTraits, Transition};
+use crate::checkpoint::{CheckpointError, RestoreState, SealedSnapshot, SnapshotKey};
+use crate::discarded_deck::{DeckLimits, DroppedMessage};
+use crate::driver::{Aborted, Driver, Step};
+use crate::observer::Observer;
+use crate::types::{
+    BoxedState, MessageOrigin, RejectedMessage, StateMachineTraits, TerminationReason,
+};
 use crossbeam_channel::{after, Receiver, Sender};
-use std::collections::VecDeque;
-use std::time::Instant;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+use std::time::{Duration, Instant};
 
 /// Finite state machine
 ///
@@ -18,132 +26,188 @@ use std::time::Instant;
 pub struct StateMachine<'a, T>
 where
     T: StateMachineTraits,
+    T::InMsg: MessageOrigin,
 {
-    state: BoxedState<T>,
+    driver: Driver<T>,
     inqueue: &'a Receiver<T::InMsg>,
     outqueue: &'a Sender<T::OutMsg>,
-    timeout: Option<Receiver<Instant>>,
-    retained: Vec<T::InMsg>,
-    discarded: DiscardedDeck<T::InMsg>,
 }
 
-/// container for deferred messaged
-///
-/// See [`async_channels::DiscardedDeck`](../async_channels/struct.DiscardedDeck.html)
-struct DiscardedDeck<T> {
-    current: VecDeque<T>,
-    next_state: VecDeque<T>,
-}
-
-impl<T> DiscardedDeck<T> {
-    pub fn new() -> Self {
-        Self {
-            current: VecDeque::new(),
-            next_state: VecDeque::new(),
-        }
-    }
-    pub fn save(&mut self, m: T) {
-        self.next_state.push_back(m);
-    }
-    pub fn pop(&mut self) -> Option<T> {
-        self.current.pop_front()
-    }
-    pub fn flip(&mut self) {
-        while let Some(m) = self.next_state.pop_front() {
-            self.current.push_back(m)
-        }
-    }
-}
-
-impl<'a, T: StateMachineTraits> StateMachine<'a, T> {
+impl<'a, T> StateMachine<'a, T>
+where
+    T: StateMachineTraits,
+    T::InMsg: MessageOrigin,
+{
     pub fn new(
         start_state: BoxedState<T>,
         inqueue: &'a Receiver<T::InMsg>,
         outqueue: &'a Sender<T::OutMsg>,
     ) -> Self {
         StateMachine {
-            state: start_state,
+            driver: Driver::new(start_state),
             inqueue,
             outqueue,
-            timeout: None,
-            retained: Vec::new(),
-            discarded: DiscardedDeck::new(),
         }
     }
 
-    pub fn execute(&mut self) -> Option<Result<T::FinalState, T::ErrorState>> {
-        log::trace!("starting State Machine");
+    /// Restores the machine from the snapshot
+    ///
+    /// The machine continues with the state object stored in the snapshot, and does not send out its output again.
+    /// The snapshot is refused if its sequence number is less than `latest`, the number of the newest snapshot known to the caller.
+    pub fn restore(
+        sealed: &SealedSnapshot,
+        key: &SnapshotKey,
+        context: &T::Context,
+        latest: u64,
+        inqueue: &'a Receiver<T::InMsg>,
+        outqueue: &'a Sender<T::OutMsg>,
+    ) -> Result<Self, CheckpointError>
+    where
+        T: RestoreState,
+        T::InMsg: for<'de> Deserialize<'de>,
+    {
+        Ok(StateMachine {
+            driver: Driver::restore(sealed, key, context, latest)?,
+            inqueue,
+            outqueue,
+        })
+    }
+
+    /// Makes the machine emit the snapshot each time a state object starts, and each time it retains or defers a message
+    pub fn with_checkpoints<F>(mut self, key: SnapshotKey, sink: F) -> Self
+    where
+        T::InMsg: Serialize,
+        F: FnMut(SealedSnapshot) + Send + 'static,
+    {
+        self.driver = self.driver.with_checkpoints(key, sink);
+        self
+    }
+
+    /// Creates the snapshot of the machine
+    pub fn checkpoint(&self, key: &SnapshotKey) -> Result<SealedSnapshot, CheckpointError>
+    where
+        T::InMsg: Serialize,
+    {
+        self.driver.checkpoint(key)
+    }
+
+    /// Reports lifecycle events of the machine to the observer
+    pub fn with_observer<O>(mut self, observer: O) -> Self
+    where
+        O: Observer<<T::InMsg as MessageOrigin>::Party> + 'static,
+    {
+        self.driver = self.driver.with_observer(observer);
+        self
+    }
+
+    /// Writes the transcript of the session into the writer, see [`transcript`](../transcript/index.html)
+    pub fn with_recorder<W>(mut self, writer: W) -> Self
+    where
+        T::InMsg: Serialize,
+        T::OutMsg: Serialize,
+        W: Write + Send + 'static,
+    {
+        self.driver = self.driver.with_recorder(writer);
+        self
+    }
+
+    /// Makes the machine ask awaited parties to send their messages again, each time the current phase has waited for `interval`
+    pub fn with_resend_interval(mut self, interval: Duration) -> Self {
+        self.driver = self.driver.with_resend_interval(interval);
+        self
+    }
 
-        self.state_prepare();
+    /// Bounds the deck of deferred messages, which is unbounded by default
+    pub fn with_deck_limits(mut self, limits: DeckLimits) -> Self {
+        self.driver = self.driver.with_deck_limits(limits);
+        self
+    }
+
+    /// Numbers of messages dropped from the deck of deferred messages due to its limits, per sender and reason
+    pub fn dropped_messages(
+        &self,
+    ) -> &HashMap<DroppedMessage<<T::InMsg as MessageOrigin>::Party>, usize> {
+        self.driver.dropped_messages()
+    }
+
+    /// Numbers of messages rejected by states of the machine, per sender and reason
+    pub fn rejected_messages(
+        &self,
+    ) -> &HashMap<RejectedMessage<<T::InMsg as MessageOrigin>::Party>, usize> {
+        self.driver.rejected_messages()
+    }
+
+    /// The number of rejected messages not counted by [`rejected_messages`](#method.rejected_messages), as their records would exceed the limit
+    pub fn unrecorded_rejections(&self) -> usize {
+        self.driver.unrecorded_rejections()
+    }
+
+    /// Execute main loop of the machine.
+    ///
+    /// Returns the outcome of the protocol, or the diagnostics of the current phase if the input channel fails or the deck of deferred messages overflows.
+    pub fn execute(&mut self) -> Result<Result<T::FinalState, T::ErrorState>, Aborted<T>> {
+        let mut processed = self.driver.start(Instant::now());
 
         loop {
-            let transition = match self.discarded.pop() {
-                // first a message is taken from the deck of discarded
-                Some(m) => self.process_message(m),
-                // inc case the deck is empty,  read from the channel
-                None => match self.timeout.as_ref() {
-                    Some(timeout_receiver) => crossbeam_channel::select! {
-                        recv(self.inqueue) -> result =>
-                            result.map_err(|e| log::error!("SM with timeout: receive error {:?}", e))
-                            .map(| message| self.process_message(message) ).ok().flatten(),
-                        recv(timeout_receiver) -> _ => return Some(self.state.timeout_outcome(self.retained.drain(..).collect()))
-                    },
-                    None => {
-                        match self.inqueue.recv() {
-                            Ok(m) => self.process_message(m),
-                            Err(e) => {
-                                log::error!("SM with no timeout: receive error {:?}", e);
-                                //early exit required to avoid infinite loop after first RecvError
-                                return None;
-                            }
+            let step = match processed {
+                Ok(step) => step,
+                Err(overflow) => {
+                    log::error!("State machine: discarded deck overflow {:?}", overflow);
+                    return Err(self.driver.terminate(TerminationReason::Overflow(overflow)));
+                }
+            };
+            if let Some(outcome) = self.send_output(step) {
+                return Ok(outcome);
+            }
+
+            let message = match self.driver.wake_up() {
+                // the select picks a random ready operation, so that the deadline is checked first to fire under a flood of messages
+                Some(deadline) if Instant::now() >= deadline => {
+                    match self.driver.handle_wake_up(deadline) {
+                        Err(outcome) => return Ok(outcome),
+                        Ok(step) => {
+                            processed = Ok(step);
+                            continue;
                         }
                     }
-                },
-            };
-            if let Some(transition) = transition {
-                match transition {
-                    Transition::NewState(state) => {
-                        let _ = std::mem::replace(&mut self.state, state);
-                        self.state_prepare();
-                        self.discarded.flip();
+                }
+                Some(deadline) => {
+                    let timeout_receiver =
+                        after(deadline.saturating_duration_since(Instant::now()));
+                    crossbeam_channel::select! {
+                        recv(self.inqueue) -> result => result.map_err(|e| log::error!("SM with timeout: receive error {:?}", e)),
+                        recv(timeout_receiver) -> _ => match self.driver.handle_wake_up(deadline) {
+                            Err(outcome) => return Ok(outcome),
+                            Ok(step) => {
+                                processed = Ok(step);
+                                continue;
+                            }
+                        },
                     }
-                    Transition::FinalState(outcome) => return Some(outcome),
                 }
-            }
-        }
-    }
-
-    fn process_message(&mut self, message: T::InMsg) -> Option<Transition<T>> {
-        // Check message is expected.
-        if self.state.is_message_expected(&message, &self.retained) {
-            // Message is expected. Retain it.
-            self.retained.push(message);
-        } else {
-            // unexpected message.
-            self.discarded.save(message);
-            return None;
-        }
-
-        // check input is complete.
-        if self.state.is_input_complete(&self.retained) {
-            // Progress to the next state.
-            let transition = self.state.consume(self.retained.drain(..).collect());
-            Some(transition)
-        } else {
-            // More input is required.
-            None
+                None => self.inqueue.recv().map_err(|e| {
+                    log::error!("SM with no timeout: receive error {:?}", e);
+                }),
+            };
+            let message = match message {
+                Ok(message) => message,
+                Err(()) => {
+                    //early exit required to avoid infinite loop after first RecvError
+                    return Err(self.driver.terminate(TerminationReason::InputClosed));
+                }
+            };
+            processed = self.driver.handle_message(message, Instant::now());
         }
     }
 
-    fn state_prepare(&mut self) {
-        self.timeout = self.state.timeout().map(after);
-        if let Some(output) = self.state.start() {
-            for m in output {
-                if let Err(err) = self.outqueue.send(m) {
-                    log::error!("State machine cannot send out message: {:?}", err);
-                }
+    /// Sends out the output of the step and returns its outcome
+    fn send_output(&self, step: Step<T>) -> Option<Result<T::FinalState, T::ErrorState>> {
+        for m in step.output {
+            if let Err(err) = self.outqueue.send(m) {
+                log::error!("State machine cannot send out message: {:?}", err);
             }
         }
+        step.outcome
     }
 }
 
@@ -151,12 +215,14 @@ impl<'a, T: StateMachineTraits> StateMachine<'a, T> {
 mod tests {
 
     use super::*;
-    use ecdsa_mpc::protocol::{Address, InputMessage, OutputMessage};
+    use ecdsa_mpc::protocol::{
+        Address, InputMessage, MessageHeader, OutputMessage, PartyIndex, ProtocolId, Round,
+        Session, SessionId,
+    };
     use std::thread;
-    use std::time::Duration;
 
-    use crate::types::State;
     use crate::types::Transition::FinalState;
+    use crate::types::{MessageClass, State, Transition};
 
     struct Phase();
 
@@ -168,6 +234,7 @@ mod tests {
         TimeoutError,
     }
 
+    #[derive(Clone)]
     enum MessageType {
         Init(),
     }
@@ -181,6 +248,34 @@ mod tests {
         type ErrorState = MachineError;
     }
 
+    impl MessageOrigin for InputMessage<MessageType> {
+        type Party = PartyIndex;
+        fn sender(&self) -> PartyIndex {
+            self.sender
+        }
+    }
+
+    impl Round for MessageType {
+        fn round(&self) -> u16 {
+            1
+        }
+    }
+
+    fn session() -> Session {
+        Session {
+            protocol: ProtocolId::Keygen,
+            id: SessionId::default(),
+        }
+    }
+
+    fn input(sender: PartyIndex, body: MessageType) -> InputMessage<MessageType> {
+        InputMessage {
+            sender,
+            header: MessageHeader::new(session(), &body),
+            body,
+        }
+    }
+
     type In = InputMessage<MessageType>;
     type Out = OutputMessage<MessageType>;
     type MachineResult = Result<Final, MachineError>;
@@ -190,8 +285,8 @@ mod tests {
             None
         }
 
-        fn is_message_expected(&self, _msg: &In, _current_msg_set: &[In]) -> bool {
-            true
+        fn classify_message(&self, _msg: &In, _current_msg_set: &[In]) -> MessageClass {
+            MessageClass::Accept
         }
 
         fn is_input_complete(&self, _current_msg_set: &[In]) -> bool {
@@ -218,30 +313,28 @@ mod tests {
         let start_state = Box::new(Phase());
         let mut machine = StateMachine::<TestTraits>::new(start_state, &rx, &tx);
         ingress
-            .send(In {
-                sender: Default::default(),
-                body: MessageType::Init(),
-            })
+            .send(input(Default::default(), MessageType::Init()))
             .unwrap();
         let result = machine.execute();
         assert!({
             match result {
-                Some(x) => x.is_ok(),
-                None => false,
+                Ok(x) => x.is_ok(),
+                Err(_) => false,
             }
         });
     }
 
     impl State<TestTraits> for TimedPhase {
         fn start(&mut self) -> Option<Vec<Out>> {
-            Some(vec![Out {
-                recipient: Address::Broadcast,
-                body: MessageType::Init(),
-            }])
+            Some(vec![OutputMessage::new(
+                session(),
+                Address::Broadcast,
+                MessageType::Init(),
+            )])
         }
 
-        fn is_message_expected(&self, _msg: &In, _current_msg_set: &[In]) -> bool {
-            true
+        fn classify_message(&self, _msg: &In, _current_msg_set: &[In]) -> MessageClass {
+            MessageClass::Accept
         }
 
         fn is_input_complete(&self, _current_msg_set: &[In]) -> bool {
@@ -281,10 +374,7 @@ mod tests {
             thread::sleep(Duration::from_secs(timeout_in_seconds + 2));
             log::info!("sending message");
             ingress
-                .send(In {
-                    sender: Default::default(),
-                    body: MessageType::Init(),
-                })
+                .send(input(Default::default(), MessageType::Init()))
                 .expect("Cannot send to a state machine in the thread");
         });
 
@@ -292,11 +382,11 @@ mod tests {
         log::info!("machine finished");
         assert!({
             match result {
-                Some(x) => match x.err() {
+                Ok(x) => match x.err() {
                     Some(e) => e == MachineError::TimeoutError,
                     None => false,
                 },
-                None => false,
+                Err(_) => false,
             }
         });
     }
@@ -0,0 +1,383 @@
+//! Transcripts of protocol sessions
+//!
+//! The driver can record every inbound and outbound message of a session, along with timeouts and the end of the session, into a transcript.
+//! Each record carries the time elapsed since the start of the session and the name of the phase which was current at that moment.
+//! The transcript is written as JSON lines: the first line is the [`TranscriptHeader`] with the version of the format, each following line is a [`Record`].
+//!
+//! The [`replay`] function feeds inbound messages and timeouts of the transcript into a fresh driver, at the recorded time, and reports the first record where the replayed run diverges.
+//! The input of the replay is taken from the transcript only. Outbound messages of the replayed run carry fresh randomness of its state objects,
+//! therefore the replay compares their number and phases rather than their contents.
+//! If the protocol validates peers' messages against its own secrets, like the signing protocol does in its last phases, the replayed driver has to start with the same secrets as the recorded one.
+//! In that case the driver should be restored from the first snapshot of the recorded session, see [`checkpoint`](../checkpoint/index.html).
+//! The output of the restored state object is not returned again, so that the replay does not compare it.
+//!
+//! [`TranscriptHeader`]: struct.TranscriptHeader.html
+//! [`Record`]: struct.Record.html
+//! [`replay`]: fn.replay.html
+
+use crate::driver::Driver;
+use crate::observer::Ending;
+use crate::types::{MessageOrigin, StateMachineTraits};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+/// Version of the transcript format
+pub const TRANSCRIPT_VERSION: u32 = 1;
+
+/// Errors of reading transcripts
+#[derive(Debug, Error)]
+pub enum TranscriptError {
+    #[error("transcript is empty")]
+    Empty,
+    #[error("unsupported transcript version {0}")]
+    UnsupportedVersion(u32),
+    #[error("transcript read error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("malformed transcript record {line}: {error}")]
+    Malformed {
+        line: usize,
+        error: serde_json::Error,
+    },
+}
+
+/// The first line of the transcript
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TranscriptHeader {
+    pub version: u32,
+    /// wall clock time of the start of the session, in milliseconds since UNIX epoch
+    pub started_at_ms: u64,
+}
+
+/// Event of the session
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Event<I, O> {
+    Inbound(I),
+    Outbound(O),
+    Timeout,
+    /// the driver asked awaited parties to send their messages again, the requests follow as outbound messages
+    ResendRequested,
+    Finished(Ending),
+}
+
+/// Record of the transcript
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record<I, O> {
+    /// time elapsed since the start of the session
+    pub at: Duration,
+    pub phase: String,
+    pub event: Event<I, O>,
+}
+
+/// Callback which receives events of the driver
+pub(crate) type RecordHook<I, O> =
+    Box<dyn for<'m> FnMut(Duration, &'static str, Event<&'m I, &'m O>) + Send>;
+
+/// Wraps the writer into the callback which writes the header and then records of the transcript
+pub(crate) fn record_hook<I, O, W>(mut writer: W) -> RecordHook<I, O>
+where
+    I: Serialize,
+    O: Serialize,
+    W: Write + Send + 'static,
+{
+    let mut header_written = false;
+    let mut write_line = move |line: serde_json::Result<String>| {
+        let written = line
+            .map_err(std::io::Error::from)
+            .and_then(|line| writeln!(writer, "{}", line))
+            .and_then(|_| writer.flush());
+        if let Err(e) = written {
+            log::error!("State machine cannot write the transcript: {:?}", e);
+        }
+    };
+    Box::new(move |at, phase, event| {
+        // the header is written along with the first record, which is made when the session starts
+        if !std::mem::replace(&mut header_written, true) {
+            let header = TranscriptHeader {
+                version: TRANSCRIPT_VERSION,
+                started_at_ms: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_millis() as u64)
+                    .unwrap_or(0),
+            };
+            write_line(serde_json::to_string(&header));
+        }
+        write_line(serde_json::to_string(&Record {
+            at,
+            phase: phase.to_owned(),
+            event,
+        }))
+    })
+}
+
+/// The record where the replayed run differs from the transcript
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// index of the record, not counting the header
+    pub record: usize,
+    pub kind: DivergenceKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DivergenceKind {
+    /// the replayed run is in another phase
+    Phase {
+        recorded: String,
+        replayed: &'static str,
+    },
+    /// the replayed run has sent another number of messages since the previous inbound message
+    OutputCount { recorded: usize, replayed: usize },
+    /// the replayed run did not time out at the recorded time
+    NoTimeout,
+    /// the replayed run ended differently or has not ended
+    Ending {
+        recorded: Ending,
+        replayed: Option<Ending>,
+    },
+    /// the replayed run ended before the transcript
+    EndedEarly(Ending),
+}
+
+/// The result of the replay
+#[derive(Debug, Clone)]
+pub struct ReplayReport {
+    /// number of records replayed before the divergence or the end of the transcript
+    pub replayed: usize,
+    pub divergence: Option<Divergence>,
+    pub ending: Option<Ending>,
+}
+
+/// Feeds the transcript into the fresh driver, and reports the first divergence of the replayed run
+pub fn replay<T, R>(transcript: R, mut driver: Driver<T>) -> Result<ReplayReport, TranscriptError>
+where
+    T: StateMachineTraits,
+    T::InMsg: MessageOrigin + DeserializeOwned,
+    R: BufRead,
+{
+    let mut lines = transcript.lines();
+    let header: TranscriptHeader = match lines.next() {
+        Some(line) => serde_json::from_str(&line?)
+            .map_err(|error| TranscriptError::Malformed { line: 0, error })?,
+        None => return Err(TranscriptError::Empty),
+    };
+    if header.version != TRANSCRIPT_VERSION {
+        return Err(TranscriptError::UnsupportedVersion(header.version));
+    }
+
+    let origin = Instant::now();
+    // the output of the restored state object has been recorded, but it is not returned again
+    let mut skip_output = driver.is_restored();
+    let mut replayed_output = match driver.start(origin) {
+        Ok(step) => step.output.len(),
+        Err(_) => 0,
+    };
+    let mut recorded_output = 0;
+    let mut report = ReplayReport {
+        replayed: 0,
+        divergence: None,
+        ending: None,
+    };
+
+    for (index, line) in lines.enumerate() {
+        let record: Record<T::InMsg, serde_json::Value> =
+            serde_json::from_str(&line?).map_err(|error| TranscriptError::Malformed {
+                line: index + 1,
+                error,
+            })?;
+        let diverged = |kind| {
+            Some(Divergence {
+                record: index,
+                kind,
+            })
+        };
+        skip_output &= matches!(record.event, Event::Outbound(_));
+        report.divergence = match record.event {
+            Event::Outbound(_) if skip_output => None,
+            Event::Outbound(_) => {
+                recorded_output += 1;
+                None
+            }
+            _ if recorded_output != replayed_output => diverged(DivergenceKind::OutputCount {
+                recorded: recorded_output,
+                replayed: replayed_output,
+            }),
+            Event::Finished(recorded) if driver.ending() != Some(recorded) => {
+                diverged(DivergenceKind::Ending {
+                    recorded,
+                    replayed: driver.ending(),
+                })
+            }
+            Event::Finished(_) => None,
+            _ if driver.ending().is_some() => {
+                diverged(DivergenceKind::EndedEarly(driver.ending().unwrap()))
+            }
+            _ if driver.phase() != record.phase => diverged(DivergenceKind::Phase {
+                recorded: record.phase,
+                replayed: driver.phase(),
+            }),
+            Event::Timeout => match driver.handle_timeout(origin + record.at) {
+                Some(_) => None,
+                None => diverged(DivergenceKind::NoTimeout),
+            },
+            Event::ResendRequested => {
+                recorded_output = 0;
+                replayed_output = driver.request_resend(origin + record.at).len();
+                None
+            }
+            Event::Inbound(message) => {
+                recorded_output = 0;
+                replayed_output = match driver.handle_message(message, origin + record.at) {
+                    Ok(step) => step.output.len(),
+                    Err(_) => 0,
+                };
+                None
+            }
+        };
+        if report.divergence.is_some() {
+            break;
+        }
+        report.replayed += 1;
+    }
+    report.ending = driver.ending();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{MessageClass, State, Transition};
+    use std::io::Cursor;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Msg {
+        sender: usize,
+        phase: u32,
+    }
+
+    impl MessageOrigin for Msg {
+        type Party = usize;
+        fn sender(&self) -> usize {
+            self.sender
+        }
+    }
+
+    struct TestTraits;
+
+    impl StateMachineTraits for TestTraits {
+        type InMsg = Msg;
+        type OutMsg = u32;
+        type FinalState = u32;
+        type ErrorState = u32;
+    }
+
+    /// announces its number and waits for messages with the same number from `peers` parties
+    struct Phase {
+        number: u32,
+        peers: usize,
+    }
+
+    impl State<TestTraits> for Phase {
+        fn start(&mut self) -> Option<Vec<u32>> {
+            Some(vec![self.number])
+        }
+
+        fn classify_message(&self, msg: &Msg, _current_msg_set: &[Msg]) -> MessageClass {
+            if msg.phase == self.number {
+                MessageClass::Accept
+            } else {
+                MessageClass::Defer
+            }
+        }
+
+        fn is_input_complete(&self, current_msg_set: &[Msg]) -> bool {
+            current_msg_set.len() == self.peers
+        }
+
+        fn consume(&self, _current_msg_set: Vec<Msg>) -> Transition<TestTraits> {
+            if self.number < 1 {
+                Transition::NewState(Box::new(Phase {
+                    number: self.number + 1,
+                    peers: self.peers,
+                }))
+            } else {
+                Transition::FinalState(Ok(self.number))
+            }
+        }
+
+        fn timeout_outcome(&self, _current_msg_set: Vec<Msg>) -> Result<u32, u32> {
+            Err(self.number)
+        }
+
+        fn name(&self) -> &'static str {
+            ["phase0", "phase1"][self.number as usize]
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn driver(peers: usize) -> Driver<TestTraits> {
+        Driver::new(Box::new(Phase { number: 0, peers }))
+    }
+
+    #[test]
+    fn replay_finds_divergence() {
+        let buffer = SharedBuffer::default();
+        let mut recorded = driver(2).with_recorder(buffer.clone());
+        let now = Instant::now();
+        recorded.start(now).unwrap();
+        for (sender, phase) in [(1, 1), (1, 0), (2, 0), (2, 1)].iter() {
+            recorded
+                .handle_message(
+                    Msg {
+                        sender: *sender,
+                        phase: *phase,
+                    },
+                    now,
+                )
+                .unwrap();
+        }
+        assert_eq!(recorded.ending(), Some(Ending::Completed));
+        let transcript = buffer.0.lock().unwrap().clone();
+
+        let report = replay(Cursor::new(&transcript), driver(2)).unwrap();
+        assert_eq!(report.divergence, None);
+        assert_eq!(report.replayed, 7);
+        assert_eq!(report.ending, Some(Ending::Completed));
+
+        // the machine which expects a single peer moves to the next phase after the second inbound message
+        let report = replay(Cursor::new(&transcript), driver(1)).unwrap();
+        assert_eq!(
+            report.divergence,
+            Some(Divergence {
+                record: 3,
+                kind: DivergenceKind::OutputCount {
+                    recorded: 0,
+                    replayed: 1
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn unsupported_version_refused() {
+        let transcript = "{\"version\":2,\"started_at_ms\":0}\n";
+        assert!(matches!(
+            replay(Cursor::new(transcript), driver(2)),
+            Err(TranscriptError::UnsupportedVersion(2))
+        ));
+    }
+}
@@ -1,9 +1,12 @@
+use crate::discarded_deck::DroppedMessage;
 use std::fmt::{Debug, Error, Formatter};
+use std::hash::Hash;
 use std::time::Duration;
 
 pub trait StateMachineTraits {
     type InMsg;
-    type OutMsg;
+    /// Output messages are cloned, as the machine keeps them so that it can send them again, see [`State::resend_output`](trait.State.html#method.resend_output)
+    type OutMsg: Clone;
     type FinalState;
     type ErrorState;
 }
@@ -27,11 +30,76 @@ impl<T> Debug for BoxedState<T> {
     }
 }
 
+/// Gives the machine access to the sender of an input message
+///
+/// The machine uses it to account for deferred messages per party.
+pub trait MessageOrigin {
+    type Party: Copy + Eq + Hash + Debug;
+    fn sender(&self) -> Self::Party;
+}
+
+/// Decision of a state about an input message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageClass {
+    /// the message belongs to the current phase
+    Accept,
+    /// the message may belong to one of the following phases, the machine keeps it in the discarded deck
+    Defer,
+    /// the message can never be used by the protocol, the machine drops it
+    Reject(RejectReason),
+}
+
+/// Reason why a state rejects an input message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RejectReason {
+    /// the sender has already delivered a message of the same kind
+    Duplicate,
+    /// the sender does not participate in the protocol
+    UnknownSender,
+    /// the message belongs to another session, protocol or version of the protocol
+    ForeignSession,
+    /// the round declared by the message does not match its contents
+    RoundMismatch,
+    /// the message exceeds limits of sizes of numbers or vectors
+    LimitExceeded,
+    /// the message belongs to a round which the state machine has already passed
+    Stale,
+}
+
+/// The record of a message rejected by a state
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RejectedMessage<P> {
+    pub sender: P,
+    pub reason: RejectReason,
+}
+
+/// Earlier output of the party selected by a state, on the request of a peer to send the round again
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resent<M> {
+    /// the round which the peer asks for
+    pub round: u16,
+    /// messages of the round addressed to the peer, empty if the party has not sent them yet
+    pub messages: Vec<M>,
+}
+
 /// Special wrapper for an input of a state machine. Enables termination of the machine via sending a message to it
+///
+/// The explanation carried by `Terminate` is returned to the caller as [`TerminationReason::Requested`](enum.TerminationReason.html#variant.Requested).
 #[derive(Debug, Clone)]
 pub enum Instruction<T> {
     Data(T),
-    Terminate,
+    Terminate(String),
+}
+
+/// Reason why the machine stopped before the protocol produced its outcome
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TerminationReason<P> {
+    /// the caller requested termination, with its own explanation
+    Requested(String),
+    /// the input channel of the machine was closed or failed
+    InputClosed,
+    /// the message overflowed the deck of deferred messages configured to abort the protocol
+    Overflow(DroppedMessage<P>),
 }
 
 ///   State interface
@@ -40,7 +108,7 @@ where
     T: StateMachineTraits,
 {
     fn start(&mut self) -> Option<Vec<T::OutMsg>>;
-    fn is_message_expected(&self, msg: &T::InMsg, current_msg_set: &[T::InMsg]) -> bool;
+    fn classify_message(&self, msg: &T::InMsg, current_msg_set: &[T::InMsg]) -> MessageClass;
     fn is_input_complete(&self, current_msg_set: &[T::InMsg]) -> bool;
     fn consume(&self, current_msg_set: Vec<T::InMsg>) -> Transition<T>;
 
@@ -51,6 +119,64 @@ where
         &self,
         current_msg_set: Vec<T::InMsg>,
     ) -> Result<T::FinalState, T::ErrorState>;
+
+    /// Serializes the state object so that it can be restored by [`RestoreState`](../checkpoint/trait.RestoreState.html)
+    ///
+    /// Returns `None` if the state does not support snapshots
+    fn snapshot(&self) -> Option<serde_json::Value> {
+        None
+    }
+
+    /// Stable name of the phase reported to [`Observer`](../observer/trait.Observer.html)
+    fn name(&self) -> &'static str {
+        "unnamed"
+    }
+
+    /// Parties which have not delivered their messages to the current phase yet
+    ///
+    /// Reported when the machine is aborted, so that the caller can tell silent peers. Returns an empty list by default.
+    fn awaited_senders(
+        &self,
+        _current_msg_set: &[T::InMsg],
+    ) -> Vec<<T::InMsg as MessageOrigin>::Party>
+    where
+        T::InMsg: MessageOrigin,
+    {
+        Vec::new()
+    }
+
+    /// Output sent by the state when its `consume` fails, typically a notice which lets peers stop the protocol without waiting for their timeouts
+    ///
+    /// Returns `None` by default.
+    fn abort_output(&self, _error: &T::ErrorState) -> Option<Vec<T::OutMsg>> {
+        None
+    }
+
+    /// Tells whether the message is the notice of a peer which has aborted the protocol
+    ///
+    /// The machine checks each message with this method before it classifies the message. If the method returns the error state, the machine stops with it.
+    /// Returns `None` by default.
+    fn peer_aborted(&self, _msg: &T::InMsg) -> Option<T::ErrorState> {
+        None
+    }
+
+    /// Requests which ask parties awaited by the state to send their messages again
+    ///
+    /// The machine sends them each time the state has waited for the resend interval, if the interval is configured.
+    /// Returns `None` by default.
+    fn resend_request(&self, _current_msg_set: &[T::InMsg]) -> Option<Vec<T::OutMsg>> {
+        None
+    }
+
+    /// Tells whether the message asks the party to send its earlier output again, and selects that output
+    ///
+    /// The machine checks each message with this method before it classifies the message, and passes the output of all states which have started so far, in the order of its creation.
+    /// If the method returns the selection, the machine does not process the message any further. It sends the selected messages out unchanged,
+    /// unless it has answered the same peer for the same round within the resend interval, see [`Driver::with_resend_interval`](../driver/struct.Driver.html#method.with_resend_interval).
+    /// Returns `None` by default.
+    fn resend_output(&self, _msg: &T::InMsg, _sent: &[T::OutMsg]) -> Option<Resent<T::OutMsg>> {
+        None
+    }
 }
 
 /////////////////////////////////////////////////////////////////////////
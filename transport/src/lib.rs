@@ -0,0 +1,304 @@
+//! Network transports of MPC protocols
+//!
+//! State machines of the crate `ecdsa-mpc` exchange [`OutputMessage`] and [`InputMessage`] with a network layer, which is not included in that crate.
+//! This crate provides that layer: the trait [`Transport`] maps `PartyIndex` of each peer to its connection, expands `Address::Broadcast` to every peer,
+//! and attaches the sender to each received message. The crate implements the trait for
+//! * an in-memory network of parties in the same process, see [`memory`], which is convenient for tests
+//! * TCP connections, see [`tcp`]
+//! * Unix domain sockets, see [`unix`]
+//! * a single connection of each party to an untrusted relay, which forwards messages between parties, see [`relay`]
+//!
+//! Connection based transports send messages in the binary form of the module `ecdsa_mpc::wire`, in frames prefixed by their big-endian `u32` length, see [`stream`].
+//! The sender of the message is the party connected on the other end of the connection, the transport does not authenticate it.
+//! Applications which need authenticated senders use the identity mode of the module `ecdsa_mpc::identity` on top of the transport.
+//!
+//! The function [`execute`] connects any [`StateMachine`] to a transport and runs the machine to its completion.
+//!
+//! ```text
+//!   let mut transport = tcp::connect(myself, listener, &peers, Duration::from_secs(30)).await?;
+//!   let result = execute(&mut transport, |inqueue, outqueue| {
+//!       StateMachine::new(start_phase, inqueue, outqueue)
+//!   })
+//!   .await;
+//!   transport.shutdown().await;
+//! ```
+//!
+//! [`OutputMessage`]: ../ecdsa_mpc/protocol/struct.OutputMessage.html
+//! [`InputMessage`]: ../ecdsa_mpc/protocol/struct.InputMessage.html
+//! [`Transport`]: trait.Transport.html
+//! [`memory`]: memory/index.html
+//! [`tcp`]: tcp/index.html
+//! [`unix`]: unix/index.html
+//! [`relay`]: relay/index.html
+//! [`stream`]: stream/index.html
+//! [`execute`]: fn.execute.html
+//! [`StateMachine`]: ../state_machine/async_channels/struct.StateMachine.html
+
+use ecdsa_mpc::protocol::{InputMessage, OutputMessage, PartyIndex};
+use ecdsa_mpc::wire::WireError;
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::future::{self, poll_fn, Either};
+use futures::{pin_mut, SinkExt, StreamExt};
+use state_machine::async_channels::StateMachine;
+use state_machine::driver::Aborted;
+use state_machine::types::{Instruction, StateMachineTraits};
+use std::task::{Context, Poll};
+use thiserror::Error;
+
+pub mod memory;
+pub mod relay;
+pub mod stream;
+pub mod tcp;
+#[cfg(unix)]
+pub mod unix;
+
+/// Errors of transports
+#[derive(Debug, Error)]
+pub enum TransportError {
+    #[error("party {0} is not connected to the transport")]
+    UnknownRecipient(PartyIndex),
+    #[error("connection to party {0} is closed")]
+    Disconnected(PartyIndex),
+    #[error("party {0} is listed twice")]
+    DuplicateParty(PartyIndex),
+    #[error("handshake failed: {0}")]
+    Handshake(String),
+    #[error("peers did not connect in time")]
+    Timeout,
+    #[error("confidential message cannot be broadcast")]
+    ConfidentialBroadcast,
+    #[error("connection to the relay is closed")]
+    RelayClosed,
+    #[error(transparent)]
+    Wire(#[from] WireError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Connection of the party to its peers
+///
+/// The transport delivers each message sent to `Address::Peer` to that peer, and each message sent to `Address::Broadcast` to every peer except the sender.
+pub trait Transport<B> {
+    /// The index of the party which owns the transport
+    fn party(&self) -> PartyIndex;
+
+    /// Sends the message to its recipients
+    ///
+    /// The method queues the message and does not wait until it is delivered.
+    fn send(&mut self, msg: OutputMessage<B>) -> Result<(), TransportError>;
+
+    /// Polls the next message received from any peer
+    ///
+    /// Returns `Ready(None)` when the transport cannot receive any message anymore.
+    fn poll_receive(&mut self, cx: &mut Context<'_>) -> Poll<Option<InputMessage<B>>>;
+}
+
+/// Receives the next message from the transport
+pub async fn receive<B, Tr>(transport: &mut Tr) -> Option<InputMessage<B>>
+where
+    Tr: Transport<B> + ?Sized,
+{
+    poll_fn(|cx| transport.poll_receive(cx)).await
+}
+
+enum Event<B> {
+    Output(Option<OutputMessage<B>>),
+    Input(Option<InputMessage<B>>),
+}
+
+/// Moves messages between the transport and queues of the state machine until either of them is closed
+async fn pump<B, Tr>(
+    transport: &mut Tr,
+    outqueue: &mut UnboundedReceiver<OutputMessage<B>>,
+    mut inqueue: UnboundedSender<Instruction<InputMessage<B>>>,
+) where
+    Tr: Transport<B> + ?Sized,
+{
+    loop {
+        let event = poll_fn(|cx| {
+            if let Poll::Ready(msg) = outqueue.poll_next_unpin(cx) {
+                return Poll::Ready(Event::Output(msg));
+            }
+            transport.poll_receive(cx).map(Event::Input)
+        })
+        .await;
+        match event {
+            Event::Output(Some(msg)) => {
+                if let Err(e) = transport.send(msg) {
+                    log::warn!("party {}: cannot send message: {}", transport.party(), e);
+                }
+            }
+            Event::Input(Some(msg)) => {
+                if inqueue.send(Instruction::Data(msg)).await.is_err() {
+                    return;
+                }
+            }
+            Event::Output(None) => return,
+            Event::Input(None) => {
+                log::warn!("party {}: transport is closed", transport.party());
+                return;
+            }
+        }
+    }
+}
+
+/// Runs the state machine over the transport
+///
+/// The function creates queues of the machine and passes them to `machine`, which creates the machine, so that the caller can configure it, e.g. with timeouts or an observer.
+/// The machine is then executed, while its output is sent to the transport and messages received by the transport are fed into the machine.
+/// The output produced along with the final state is sent out before the function returns.
+///
+/// If the transport is closed, the machine stops with [`TerminationReason::InputClosed`](../state_machine/types/enum.TerminationReason.html#variant.InputClosed), unless it completes or times out first.
+pub async fn execute<T, B, Tr, F>(
+    transport: &mut Tr,
+    machine: F,
+) -> Result<Result<T::FinalState, T::ErrorState>, Aborted<T>>
+where
+    T: StateMachineTraits<InMsg = InputMessage<B>, OutMsg = OutputMessage<B>>,
+    Tr: Transport<B> + ?Sized,
+    F: FnOnce(
+        UnboundedReceiver<Instruction<InputMessage<B>>>,
+        UnboundedSender<OutputMessage<B>>,
+    ) -> StateMachine<T>,
+{
+    let (in_sender, in_receiver) = mpsc::unbounded();
+    let (out_sender, mut out_receiver) = mpsc::unbounded();
+    let mut machine = machine(in_receiver, out_sender);
+
+    let outcome = {
+        let run = machine.execute();
+        let pumped = pump(transport, &mut out_receiver, in_sender);
+        pin_mut!(run, pumped);
+        match future::select(run, pumped).await {
+            Either::Left((outcome, _)) => outcome,
+            Either::Right(((), run)) => run.await,
+        }
+    };
+
+    while let Ok(msg) = out_receiver.try_recv() {
+        if let Err(e) = transport.send(msg) {
+            log::warn!("party {}: cannot send message: {}", transport.party(), e);
+        }
+    }
+    outcome
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use ecdsa_mpc::protocol::{Address, ProtocolId, Round, Session, SessionId};
+    use ecdsa_mpc::wire::{Reader, Wire};
+    use state_machine::types::{MessageClass, State, Transition};
+    use std::collections::BTreeSet;
+
+    /// The number contributed by a party
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Share(pub u16);
+
+    impl Round for Share {
+        fn round(&self) -> u16 {
+            1
+        }
+    }
+
+    impl Wire for Share {
+        fn encode(&self, out: &mut Vec<u8>) -> Result<(), WireError> {
+            self.0.encode(out)
+        }
+        fn decode(input: &mut Reader<'_>) -> Result<Self, WireError> {
+            Ok(Share(u16::decode(input)?))
+        }
+    }
+
+    pub fn session() -> Session {
+        Session {
+            protocol: ProtocolId::Keygen,
+            id: SessionId::default(),
+        }
+    }
+
+    struct SumTraits;
+
+    impl StateMachineTraits for SumTraits {
+        type InMsg = InputMessage<Share>;
+        type OutMsg = OutputMessage<Share>;
+        type FinalState = u16;
+        type ErrorState = ();
+    }
+
+    /// Each party broadcasts its share and sums shares of all parties
+    struct Sum {
+        share: u16,
+        peers: BTreeSet<PartyIndex>,
+    }
+
+    impl State<SumTraits> for Sum {
+        fn start(&mut self) -> Option<Vec<OutputMessage<Share>>> {
+            Some(vec![OutputMessage::new(
+                session(),
+                Address::Broadcast,
+                Share(self.share),
+            )])
+        }
+
+        fn classify_message(
+            &self,
+            msg: &InputMessage<Share>,
+            current_msg_set: &[InputMessage<Share>],
+        ) -> MessageClass {
+            if self.peers.contains(&msg.sender)
+                && !current_msg_set.iter().any(|m| m.sender == msg.sender)
+            {
+                MessageClass::Accept
+            } else {
+                MessageClass::Defer
+            }
+        }
+
+        fn is_input_complete(&self, current_msg_set: &[InputMessage<Share>]) -> bool {
+            current_msg_set.len() == self.peers.len()
+        }
+
+        fn consume(&self, current_msg_set: Vec<InputMessage<Share>>) -> Transition<SumTraits> {
+            Transition::FinalState(Ok(current_msg_set
+                .iter()
+                .fold(self.share, |sum, m| sum + m.body.0)))
+        }
+
+        fn timeout_outcome(&self, _current_msg_set: Vec<InputMessage<Share>>) -> Result<u16, ()> {
+            Err(())
+        }
+    }
+
+    /// Runs the protocol over the transport of each party and checks their results
+    pub async fn sum_over<Tr>(transports: Vec<Tr>)
+    where
+        Tr: Transport<Share> + Send + 'static,
+    {
+        let parties = transports.iter().map(|t| t.party()).collect::<Vec<_>>();
+        let expected = (1..=parties.len() as u16).sum::<u16>();
+        let handles = transports
+            .into_iter()
+            .zip(1..)
+            .map(|(mut transport, share)| {
+                let party = transport.party();
+                let peers = parties.iter().copied().filter(|p| *p != party).collect();
+                tokio::spawn(async move {
+                    let outcome = execute(&mut transport, |inqueue, outqueue| {
+                        StateMachine::new(Box::new(Sum { share, peers }), inqueue, outqueue)
+                    })
+                    .await;
+                    (outcome, transport)
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in future::join_all(handles).await {
+            let (outcome, _transport) = handle.expect("task panicked");
+            assert_eq!(outcome.ok().and_then(|r| r.ok()), Some(expected));
+        }
+    }
+
+    pub fn parties(n: usize) -> Vec<PartyIndex> {
+        (1..=n).map(PartyIndex::from).collect()
+    }
+}
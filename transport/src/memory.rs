@@ -0,0 +1,117 @@
+//! In-memory transport
+//!
+//! Connects parties running in the same process through unbounded queues. Messages are moved without serialization, broadcasts are cloned for each peer.
+
+use crate::{Transport, TransportError};
+use ecdsa_mpc::protocol::{Address, InputMessage, OutputMessage, PartyIndex};
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::StreamExt;
+use std::collections::BTreeMap;
+use std::task::{Context, Poll};
+
+/// Transport of a party of the in-memory network
+pub struct MemoryTransport<B> {
+    party: PartyIndex,
+    peers: BTreeMap<PartyIndex, UnboundedSender<InputMessage<B>>>,
+    incoming: UnboundedReceiver<InputMessage<B>>,
+}
+
+/// Creates the network which connects each of the parties to every other party
+///
+/// Transports are returned in the order of `parties`.
+pub fn network<B>(parties: &[PartyIndex]) -> Result<Vec<MemoryTransport<B>>, TransportError> {
+    let mut queues = BTreeMap::new();
+    let mut receivers = Vec::with_capacity(parties.len());
+    for party in parties {
+        let (sender, receiver) = mpsc::unbounded();
+        if queues.insert(*party, sender).is_some() {
+            return Err(TransportError::DuplicateParty(*party));
+        }
+        receivers.push(receiver);
+    }
+    Ok(parties
+        .iter()
+        .zip(receivers)
+        .map(|(party, incoming)| MemoryTransport {
+            party: *party,
+            peers: queues
+                .iter()
+                .filter(|(p, _)| *p != party)
+                .map(|(p, queue)| (*p, queue.clone()))
+                .collect(),
+            incoming,
+        })
+        .collect())
+}
+
+impl<B: Clone> MemoryTransport<B> {
+    fn deliver(&self, peer: PartyIndex, msg: OutputMessage<B>) -> Result<(), TransportError> {
+        let queue = self
+            .peers
+            .get(&peer)
+            .ok_or(TransportError::UnknownRecipient(peer))?;
+        queue
+            .unbounded_send(InputMessage::from_output(self.party, msg))
+            .map_err(|_| TransportError::Disconnected(peer))
+    }
+}
+
+impl<B: Clone> Transport<B> for MemoryTransport<B> {
+    fn party(&self) -> PartyIndex {
+        self.party
+    }
+
+    fn send(&mut self, msg: OutputMessage<B>) -> Result<(), TransportError> {
+        match msg.recipient {
+            Address::Peer(peer) => self.deliver(peer, msg),
+            Address::Broadcast => self
+                .peers
+                .keys()
+                .map(|peer| self.deliver(*peer, msg.clone()))
+                .fold(Ok(()), Result::and),
+        }
+    }
+
+    fn poll_receive(&mut self, cx: &mut Context<'_>) -> Poll<Option<InputMessage<B>>> {
+        self.incoming.poll_next_unpin(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::receive;
+    use crate::tests::{parties, session, sum_over, Share};
+
+    #[tokio::test]
+    async fn broadcast_reaches_peers_only() -> Result<(), TransportError> {
+        let mut transports = network::<Share>(&parties(3))?;
+        transports[0].send(OutputMessage::new(session(), Address::Broadcast, Share(7)))?;
+        let recipient = transports[2].party();
+        transports[1].send(OutputMessage::new(
+            session(),
+            Address::Peer(recipient),
+            Share(8),
+        ))?;
+        let sender = transports[0].party();
+        let msg = receive(&mut transports[1]).await.expect("message");
+        assert_eq!((msg.sender, msg.body), (sender, Share(7)));
+        let msg = receive(&mut transports[2]).await.expect("message");
+        assert_eq!((msg.sender, msg.body), (sender, Share(7)));
+        let msg = receive(&mut transports[2]).await.expect("message");
+        assert_eq!(msg.body, Share(8));
+
+        let stranger = PartyIndex::from(42);
+        assert!(matches!(
+            transports[0].send(OutputMessage::new(session(), Address::Peer(stranger), Share(1))),
+            Err(TransportError::UnknownRecipient(p)) if p == stranger
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn machines_run_over_memory() -> Result<(), TransportError> {
+        sum_over(network(&parties(4))?).await;
+        Ok(())
+    }
+}
@@ -0,0 +1,971 @@
+//! Star topology through an untrusted relay
+//!
+//! Parties which cannot connect to each other, like mobile or firewalled co-signers, connect to a single coordinator instead, which runs [`serve`].
+//! The coordinator fans out broadcasts to every other party and forwards peer messages to their recipients. It handles opaque packets, and is not trusted by the parties:
+//! * peer messages are encrypted with the channel key of the sender and the recipient, derived from their [`PartyKeys`], so the relay never sees shares or other secrets
+//! * each broadcast is sent to the relay once, along with an authentication tag for each recipient, which binds the digest of the broadcast to the channel key
+//! * the plaintext of each peer message and of each tag contains the recipient and the sequence number of the message in the channel from the sender to the recipient
+//!
+//! [`RelayTransport`] checks each packet it receives. Packets which are modified, forged, reflected back or replayed by the relay are refused.
+//! Gaps in sequence numbers reveal messages dropped by the relay. Both are counted as [`RelayViolation`]s, which the application can report along with the outcome of the protocol.
+//! The relay can still withhold every message of a party, which the protocol detects as a timeout of that party.
+//!
+//! Messages dropped at the end of a channel leave no gap. When the protocol is over, each party can learn how many messages each of its peers has sent to it,
+//! e.g. by a signed statement of the peer delivered over another path, and pass the number to [`RelayTransport::confirm`], which counts the withheld messages as the violation.
+//! Parties get their own numbers from [`RelayTransport::counters`].
+//!
+//! Parties connect to the relay in the identity mode of the module `ecdsa_mpc::identity`: the index of each party is derived from its identity key.
+//! The relay sends a random challenge to each connection, and accepts the connection of a listed party only if it signs the challenge with its identity key,
+//! so that nobody can take over the place of the party at the relay.
+//!
+//! Frames between parties and the relay are prefixed by their big-endian `u32` length, like frames of [`StreamTransport`](../stream/struct.StreamTransport.html).
+//! The first frame of the relay is the challenge, and the first frame of the party is its identity public key followed by its signature of the challenge. After that
+//! * the party sends the `Address` of each packet, followed by the packet
+//! * the relay sends the index of the sender of each packet, followed by the packet
+//!
+//! [`serve`]: fn.serve.html
+//! [`PartyKeys`]: ../../ecdsa_mpc/encryption/struct.PartyKeys.html
+//! [`RelayTransport`]: struct.RelayTransport.html
+//! [`RelayViolation`]: struct.RelayViolation.html
+//! [`RelayTransport::confirm`]: struct.RelayTransport.html#method.confirm
+//! [`RelayTransport::counters`]: struct.RelayTransport.html#method.counters
+
+use crate::stream::{read_frame, write_frame, write_frames, Listener};
+use crate::{Transport, TransportError};
+use ecdsa_mpc::encryption::{Confidential, PartyKeys, PartyPublicKey};
+use ecdsa_mpc::identity::{IdentityKeys, IdentityPublicKey};
+use ecdsa_mpc::protocol::{Address, InputMessage, OutputMessage, PartyIndex};
+use ecdsa_mpc::wire::{self, Reader, Wire, WireError};
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::future;
+use futures::StreamExt;
+use sha2::{Digest, Sha256};
+use sodiumoxide::crypto::{box_, sign};
+use sodiumoxide::randombytes::randombytes;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::convert::TryFrom;
+use std::io;
+use std::marker::PhantomData;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::task::JoinHandle;
+use zeroize::Zeroizing;
+
+const BROADCAST_PACKET: u8 = 0;
+const PEER_PACKET: u8 = 1;
+
+/// Length of the plaintext of the tag: recipient, sequence number and digest of the broadcast
+const TAG_LEN: usize = 32 + 8 + 32;
+
+/// Length of the challenge the relay sends to each connection
+const CHALLENGE_LEN: usize = 32;
+
+/// The number of distinct records of violations the channels keep
+///
+/// The relay can create a new record with each packet, e.g. by dropping a different number of messages each time.
+pub const MAX_VIOLATION_RECORDS: usize = 256;
+
+/// Misbehavior of the relay detected by a party
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ViolationKind {
+    /// the packet cannot be decrypted or its tag is invalid, it has been modified or forged
+    Unauthenticated,
+    /// the packet is addressed to another party, or the message in it is addressed differently than the packet
+    Misaddressed,
+    /// the packet cannot be parsed
+    Malformed,
+    /// the sender of the packet is unknown
+    UnknownSender,
+    /// messages of the channel preceding the received one have not arrived
+    Dropped { expected: u64, received: u64 },
+    /// the message of the channel has already been received
+    Replayed { expected: u64, received: u64 },
+    /// the confidential message arrived as a broadcast
+    Plaintext,
+    /// messages at the end of the channel have not arrived, the peer has sent more messages than the party has received
+    Withheld { received: u64, sent: u64 },
+}
+
+/// The record of a packet which reveals misbehavior of the relay, or of the sender
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RelayViolation {
+    pub sender: PartyIndex,
+    pub kind: ViolationKind,
+}
+
+/// Numbers of messages in the channels between the party and one of its peers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ChannelCounters {
+    /// messages sent by the party to the peer
+    pub sent: u64,
+    /// messages of the peer up to the last one received by the party, including the ones detected as dropped
+    pub received: u64,
+}
+
+/// Channels of the party to each of its peers through the relay
+///
+/// Seals messages of the party into packets and opens packets of its peers, see the [module documentation](index.html).
+pub struct RelayChannels {
+    party: PartyIndex,
+    keys: BTreeMap<PartyIndex, box_::PrecomputedKey>,
+    sent: BTreeMap<PartyIndex, u64>,
+    received: BTreeMap<PartyIndex, u64>,
+    violations: HashMap<RelayViolation, usize>,
+    unrecorded_violations: usize,
+}
+
+impl RelayChannels {
+    /// Precomputes channel keys with the key pair of the party and the public keys of the peers
+    pub fn new<I>(own_keys: &PartyKeys, peers: I) -> Self
+    where
+        I: IntoIterator<Item = (PartyIndex, PartyPublicKey)>,
+    {
+        let keys = peers
+            .into_iter()
+            .filter(|(party, _)| *party != own_keys.party())
+            .map(|(party, public_key)| (party, own_keys.channel_key(&public_key)))
+            .collect::<BTreeMap<_, _>>();
+        RelayChannels {
+            party: own_keys.party(),
+            sent: keys.keys().map(|p| (*p, 0)).collect(),
+            received: keys.keys().map(|p| (*p, 0)).collect(),
+            keys,
+            violations: HashMap::new(),
+            unrecorded_violations: 0,
+        }
+    }
+
+    pub fn party(&self) -> PartyIndex {
+        self.party
+    }
+
+    /// Numbers of violations detected since the channels were created, per sender and kind
+    ///
+    /// At most [`MAX_VIOLATION_RECORDS`](constant.MAX_VIOLATION_RECORDS.html) distinct records are kept.
+    pub fn violations(&self) -> &HashMap<RelayViolation, usize> {
+        &self.violations
+    }
+
+    /// The number of violations not counted by [`violations`](#method.violations), as their records would exceed the limit
+    pub fn unrecorded_violations(&self) -> usize {
+        self.unrecorded_violations
+    }
+
+    /// Numbers of messages sent to and received from each of the peers so far
+    pub fn counters(&self) -> BTreeMap<PartyIndex, ChannelCounters> {
+        self.keys
+            .keys()
+            .map(|peer| {
+                (
+                    *peer,
+                    ChannelCounters {
+                        sent: self.sent[peer],
+                        received: self.received[peer],
+                    },
+                )
+            })
+            .collect()
+    }
+
+    /// Checks the number of messages the peer states it has sent to the party against the number of received ones
+    ///
+    /// Records the [`Withheld`](enum.ViolationKind.html#variant.Withheld) violation and returns `false` if messages of the peer have not arrived.
+    pub fn confirm(&mut self, peer: PartyIndex, sent: u64) -> bool {
+        let received = match self.received.get(&peer) {
+            Some(received) => *received,
+            None => {
+                self.record(peer, ViolationKind::UnknownSender);
+                return false;
+            }
+        };
+        if received < sent {
+            self.record(peer, ViolationKind::Withheld { received, sent });
+            return false;
+        }
+        true
+    }
+
+    /// Seals the message into the frame for the relay
+    pub fn seal<B>(&mut self, msg: &OutputMessage<B>) -> Result<Vec<u8>, TransportError>
+    where
+        B: Wire + Confidential,
+    {
+        let body = Zeroizing::new(wire::to_bytes(msg)?);
+        let mut frame = Vec::new();
+        msg.recipient.encode(&mut frame)?;
+        match msg.recipient {
+            Address::Broadcast if msg.body.is_confidential() => {
+                return Err(TransportError::ConfidentialBroadcast)
+            }
+            Address::Broadcast => {
+                let digest = Sha256::digest(&body);
+                frame.push(BROADCAST_PACKET);
+                u16::try_from(self.keys.len())
+                    .map_err(|_| WireError::TooLong {
+                        kind: "peers",
+                        len: self.keys.len(),
+                        limit: u16::MAX as usize,
+                    })?
+                    .encode(&mut frame)?;
+                for (peer, key) in &self.keys {
+                    let seq = next_seq(&mut self.sent, *peer);
+                    let mut tag = Vec::with_capacity(TAG_LEN);
+                    peer.encode(&mut tag)?;
+                    tag.extend_from_slice(&seq.to_be_bytes());
+                    tag.extend_from_slice(&digest);
+                    let nonce = box_::gen_nonce();
+                    peer.encode(&mut frame)?;
+                    frame.extend_from_slice(&nonce.0);
+                    frame.extend_from_slice(&box_::seal_precomputed(&tag, &nonce, key));
+                }
+                frame.extend_from_slice(&body);
+            }
+            Address::Peer(peer) => {
+                let key = self
+                    .keys
+                    .get(&peer)
+                    .ok_or(TransportError::UnknownRecipient(peer))?;
+                let seq = next_seq(&mut self.sent, peer);
+                let mut plaintext = Zeroizing::new(Vec::with_capacity(40 + body.len()));
+                peer.encode(&mut plaintext)?;
+                plaintext.extend_from_slice(&seq.to_be_bytes());
+                plaintext.extend_from_slice(&body);
+                let nonce = box_::gen_nonce();
+                frame.push(PEER_PACKET);
+                frame.extend_from_slice(&nonce.0);
+                frame.extend_from_slice(&box_::seal_precomputed(&plaintext, &nonce, key));
+            }
+        }
+        Ok(frame)
+    }
+
+    /// Opens the frame received from the relay
+    ///
+    /// Returns `None` if the packet is refused. The violation is recorded, see [`violations`](#method.violations).
+    pub fn open<B>(&mut self, frame: &[u8]) -> Option<InputMessage<B>>
+    where
+        B: Wire + Confidential,
+    {
+        let mut input = Reader::new(frame);
+        let sender = match PartyIndex::decode(&mut input) {
+            Ok(sender) => sender,
+            Err(_) => {
+                log::warn!("party {}: malformed frame from the relay", self.party);
+                return None;
+            }
+        };
+        let packet = &frame[frame.len() - input.remaining()..];
+        match self.open_packet(sender, packet) {
+            Ok(msg) => Some(msg),
+            Err(kind) => {
+                self.record(sender, kind);
+                None
+            }
+        }
+    }
+
+    fn record(&mut self, sender: PartyIndex, kind: ViolationKind) {
+        log::warn!(
+            "party {}: relay violation in the channel from {}: {:?}",
+            self.party,
+            sender,
+            kind
+        );
+        let record = RelayViolation { sender, kind };
+        if let Some(count) = self.violations.get_mut(&record) {
+            *count += 1;
+        } else if self.violations.len() < MAX_VIOLATION_RECORDS {
+            self.violations.insert(record, 1);
+        } else {
+            self.unrecorded_violations += 1;
+        }
+    }
+
+    fn open_packet<B>(
+        &mut self,
+        sender: PartyIndex,
+        packet: &[u8],
+    ) -> Result<InputMessage<B>, ViolationKind>
+    where
+        B: Wire + Confidential,
+    {
+        let key = self
+            .keys
+            .get(&sender)
+            .ok_or(ViolationKind::UnknownSender)?
+            .clone();
+        let mut input = Reader::new(packet);
+        let (plaintext, body_offset) = match u8::decode(&mut input).map_err(malformed)? {
+            BROADCAST_PACKET => {
+                let count = u16::decode(&mut input).map_err(malformed)?;
+                let mut own_tag = None;
+                for _ in 0..count {
+                    let recipient = PartyIndex::decode(&mut input).map_err(malformed)?;
+                    let nonce = <[u8; box_::NONCEBYTES]>::decode(&mut input).map_err(malformed)?;
+                    let sealed =
+                        <[u8; TAG_LEN + box_::MACBYTES]>::decode(&mut input).map_err(malformed)?;
+                    if recipient == self.party {
+                        own_tag = Some((nonce, sealed));
+                    }
+                }
+                let body = &packet[packet.len() - input.remaining()..];
+                let (nonce, sealed) = own_tag.ok_or(ViolationKind::Misaddressed)?;
+                let tag = box_::open_precomputed(&sealed, &box_::Nonce(nonce), &key)
+                    .map_err(|_| ViolationKind::Unauthenticated)?;
+                if tag[40..] != Sha256::digest(body)[..] {
+                    return Err(ViolationKind::Unauthenticated);
+                }
+                let mut plaintext = Zeroizing::new(tag[..40].to_vec());
+                plaintext.extend_from_slice(body);
+                (plaintext, 40)
+            }
+            PEER_PACKET => {
+                let nonce = <[u8; box_::NONCEBYTES]>::decode(&mut input).map_err(malformed)?;
+                let ciphertext = &packet[packet.len() - input.remaining()..];
+                let plaintext = box_::open_precomputed(ciphertext, &box_::Nonce(nonce), &key)
+                    .map_err(|_| ViolationKind::Unauthenticated)?;
+                (Zeroizing::new(plaintext), 40)
+            }
+            _ => return Err(ViolationKind::Malformed),
+        };
+        let broadcast = packet[0] == BROADCAST_PACKET;
+
+        let mut input = Reader::new(&plaintext);
+        let recipient = PartyIndex::decode(&mut input).map_err(malformed)?;
+        let seq = u64::from_be_bytes(<[u8; 8]>::decode(&mut input).map_err(malformed)?);
+        if recipient != self.party {
+            return Err(ViolationKind::Misaddressed);
+        }
+        let msg: OutputMessage<B> =
+            wire::from_bytes(&plaintext[body_offset..]).map_err(malformed)?;
+        match msg.recipient {
+            Address::Broadcast if broadcast && msg.body.is_confidential() => {
+                return Err(ViolationKind::Plaintext)
+            }
+            Address::Broadcast if broadcast => {}
+            Address::Peer(party) if !broadcast && party == self.party => {}
+            _ => return Err(ViolationKind::Misaddressed),
+        }
+
+        let expected = self.received[&sender];
+        if seq < expected {
+            return Err(ViolationKind::Replayed {
+                expected,
+                received: seq,
+            });
+        }
+        if seq > expected {
+            self.record(
+                sender,
+                ViolationKind::Dropped {
+                    expected,
+                    received: seq,
+                },
+            );
+        }
+        self.received.insert(sender, seq + 1);
+        Ok(InputMessage::from_output(sender, msg))
+    }
+}
+
+fn next_seq(counters: &mut BTreeMap<PartyIndex, u64>, peer: PartyIndex) -> u64 {
+    let counter = counters.entry(peer).or_insert(0);
+    let seq = *counter;
+    *counter += 1;
+    seq
+}
+
+fn malformed(_: WireError) -> ViolationKind {
+    ViolationKind::Malformed
+}
+
+/// Transport of a party which is connected to the relay only
+pub struct RelayTransport<B> {
+    channels: RelayChannels,
+    frames: Option<UnboundedSender<Vec<u8>>>,
+    writer: JoinHandle<()>,
+    incoming: UnboundedReceiver<Vec<u8>>,
+    body: PhantomData<B>,
+}
+
+/// Connects the party to the relay over the stream
+///
+/// The party signs the challenge of the relay with its identity key, from which the index of the party is derived.
+/// Peers of the party are the parties whose public keys are given. The transport has to be created within a `tokio` runtime.
+pub async fn connect<S, B, I>(
+    mut stream: S,
+    identity: &IdentityKeys,
+    own_keys: &PartyKeys,
+    peers: I,
+) -> Result<RelayTransport<B>, TransportError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    I: IntoIterator<Item = (PartyIndex, PartyPublicKey)>,
+{
+    if identity.party_index() != own_keys.party() {
+        return Err(TransportError::Handshake(format!(
+            "index of party {} is not derived from its identity key",
+            own_keys.party()
+        )));
+    }
+    let channels = RelayChannels::new(own_keys, peers);
+    let challenge = read_frame(&mut stream).await?;
+    if challenge.len() != CHALLENGE_LEN {
+        return Err(TransportError::Handshake(format!(
+            "challenge of {} bytes",
+            challenge.len()
+        )));
+    }
+    let mut response = identity.public_key().0.to_vec();
+    response.extend_from_slice(&identity.sign_challenge(&challenge));
+    write_frame(&mut stream, &response).await?;
+    let (reader, writer) = tokio::io::split(stream);
+    let (frames, queue) = mpsc::unbounded();
+    let (incoming_sender, incoming) = mpsc::unbounded();
+    tokio::spawn(read_raw_frames(
+        format!("party {}: relay", channels.party),
+        reader,
+        incoming_sender,
+    ));
+    Ok(RelayTransport {
+        writer: tokio::spawn(write_frames("relay", writer, queue)),
+        frames: Some(frames),
+        channels,
+        incoming,
+        body: PhantomData,
+    })
+}
+
+impl<B> RelayTransport<B> {
+    /// Numbers of violations of the relay detected since the transport was created, per sender and kind
+    pub fn violations(&self) -> &HashMap<RelayViolation, usize> {
+        self.channels.violations()
+    }
+
+    /// The number of violations not counted by [`violations`](#method.violations), see [`RelayChannels::unrecorded_violations`](struct.RelayChannels.html#method.unrecorded_violations)
+    pub fn unrecorded_violations(&self) -> usize {
+        self.channels.unrecorded_violations()
+    }
+
+    /// Numbers of messages sent to and received from each of the peers, which the party states to its peers when the protocol is over
+    pub fn counters(&self) -> BTreeMap<PartyIndex, ChannelCounters> {
+        self.channels.counters()
+    }
+
+    /// Checks the number of messages the peer states it has sent to the party, see [`RelayChannels::confirm`](struct.RelayChannels.html#method.confirm)
+    pub fn confirm(&mut self, peer: PartyIndex, sent: u64) -> bool {
+        self.channels.confirm(peer, sent)
+    }
+
+    /// Closes the connection to the relay after all queued messages are written
+    pub async fn shutdown(mut self) {
+        self.frames = None;
+        if let Err(e) = self.writer.await {
+            log::error!("writer task failed: {}", e);
+        }
+    }
+}
+
+impl<B: Wire + Confidential> Transport<B> for RelayTransport<B> {
+    fn party(&self) -> PartyIndex {
+        self.channels.party()
+    }
+
+    fn send(&mut self, msg: OutputMessage<B>) -> Result<(), TransportError> {
+        let frame = self.channels.seal(&msg)?;
+        self.frames
+            .as_ref()
+            .ok_or(TransportError::RelayClosed)?
+            .unbounded_send(frame)
+            .map_err(|_| TransportError::RelayClosed)
+    }
+
+    fn poll_receive(&mut self, cx: &mut Context<'_>) -> Poll<Option<InputMessage<B>>> {
+        loop {
+            match self.incoming.poll_next_unpin(cx) {
+                Poll::Ready(Some(frame)) => {
+                    if let Some(msg) = self.channels.open(&frame) {
+                        return Poll::Ready(Some(msg));
+                    }
+                }
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// Reads frames of the stream into the queue until the stream or the queue is closed
+async fn read_raw_frames<R>(source: String, mut reader: R, queue: UnboundedSender<Vec<u8>>)
+where
+    R: AsyncRead + Unpin,
+{
+    loop {
+        match read_frame(&mut reader).await {
+            Ok(frame) => {
+                if queue.unbounded_send(frame).is_err() {
+                    return;
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                log::debug!("{} closed the stream", source);
+                return;
+            }
+            Err(e) => {
+                log::warn!("cannot read from {}: {}", source, e);
+                return;
+            }
+        }
+    }
+}
+
+/// Runs the relay for the parties
+///
+/// Waits until each of the parties connects to the listener, and fails with [`TransportError::Timeout`](../enum.TransportError.html#variant.Timeout)
+/// unless all of them are connected within `timeout`. Then forwards packets of the parties until all of them disconnect.
+/// Connections which fail to sign the challenge with the identity key of a listed party are dropped, as are packets addressed to unknown parties and malformed frames.
+/// Each connection answers the challenge in its own task, so that a connection which does not answer holds back no other party.
+pub async fn serve<L>(
+    mut listener: L,
+    parties: &[PartyIndex],
+    timeout: Duration,
+) -> Result<(), TransportError>
+where
+    L: Listener,
+{
+    let mut awaited = BTreeSet::new();
+    for party in parties {
+        if !awaited.insert(*party) {
+            return Err(TransportError::DuplicateParty(*party));
+        }
+    }
+    let _ = sodiumoxide::init();
+    let accepting = async {
+        let (handshakes, mut authenticated) = mpsc::unbounded();
+        let mut streams = Vec::new();
+        while !awaited.is_empty() {
+            tokio::select! {
+                stream = listener.accept() => {
+                    let mut stream = stream?;
+                    let handshakes = handshakes.clone();
+                    tokio::spawn(async move {
+                        let party = tokio::time::timeout(timeout, authenticate(&mut stream))
+                            .await
+                            .unwrap_or(Err(TransportError::Timeout));
+                        let _ = handshakes.unbounded_send((party, stream));
+                    });
+                }
+                Some((party, stream)) = authenticated.next() => match party {
+                    Ok(party) if awaited.remove(&party) => streams.push((party, stream)),
+                    Ok(party) => log::warn!("relay: dropped stream of {}", party),
+                    Err(e) => log::warn!("relay: failed handshake: {}", e),
+                },
+            }
+        }
+        Ok::<_, TransportError>(streams)
+    };
+    let streams = tokio::time::timeout(timeout, accepting)
+        .await
+        .map_err(|_| TransportError::Timeout)??;
+
+    let mut queues = BTreeMap::new();
+    let mut writers = Vec::new();
+    let mut readers = Vec::new();
+    for (party, stream) in streams {
+        let (reader, writer) = tokio::io::split(stream);
+        let (frames, queue) = mpsc::unbounded();
+        queues.insert(party, frames);
+        writers.push(tokio::spawn(write_frames(
+            format!("relay: party {}", party),
+            writer,
+            queue,
+        )));
+        readers.push((party, reader));
+    }
+    let forwarding = readers
+        .into_iter()
+        .map(|(party, reader)| tokio::spawn(forward(party, reader, queues.clone())))
+        .collect::<Vec<_>>();
+    drop(queues);
+    future::join_all(forwarding).await;
+    future::join_all(writers).await;
+    Ok(())
+}
+
+/// Sends the challenge to the party and returns its index if the party signs the challenge with its identity key
+async fn authenticate<S>(stream: &mut S) -> Result<PartyIndex, TransportError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let challenge = randombytes(CHALLENGE_LEN);
+    write_frame(stream, &challenge).await?;
+    let frame = read_frame(stream).await?;
+    let mut input = Reader::new(&frame);
+    let public_key = IdentityPublicKey(<[u8; sign::PUBLICKEYBYTES]>::decode(&mut input)?);
+    let signature = <[u8; sign::SIGNATUREBYTES]>::decode(&mut input)?;
+    if input.remaining() != 0 {
+        return Err(WireError::TrailingBytes(input.remaining()).into());
+    }
+    if !public_key.verify_challenge(&challenge, &signature) {
+        return Err(TransportError::Handshake(format!(
+            "invalid signature of {}",
+            public_key.party_index()
+        )));
+    }
+    Ok(public_key.party_index())
+}
+
+/// Forwards packets of the party to their recipients
+async fn forward<R>(
+    party: PartyIndex,
+    mut reader: R,
+    queues: BTreeMap<PartyIndex, UnboundedSender<Vec<u8>>>,
+) where
+    R: AsyncRead + Unpin,
+{
+    let mut sender = Vec::new();
+    if let Err(e) = party.encode(&mut sender) {
+        log::warn!("relay: cannot forward packets of {}: {}", party, e);
+        return;
+    }
+    loop {
+        let frame = match read_frame(&mut reader).await {
+            Ok(frame) => frame,
+            Err(e) => {
+                log::debug!("relay: party {} disconnected: {}", party, e);
+                return;
+            }
+        };
+        let mut input = Reader::new(&frame);
+        let recipient = match Address::decode(&mut input) {
+            Ok(recipient) => recipient,
+            Err(e) => {
+                log::warn!("relay: malformed frame from {}: {}", party, e);
+                continue;
+            }
+        };
+        let mut delivery = Vec::with_capacity(frame.len() + 32);
+        delivery.extend_from_slice(&sender);
+        delivery.extend_from_slice(&frame[frame.len() - input.remaining()..]);
+        let recipients = queues
+            .iter()
+            .filter(|(p, _)| match &recipient {
+                Address::Broadcast => **p != party,
+                Address::Peer(peer) => p == &peer,
+            })
+            .collect::<Vec<_>>();
+        if recipients.is_empty() {
+            log::warn!("relay: dropped packet of {} to {:?}", party, recipient);
+        }
+        for (_, queue) in recipients {
+            let _ = queue.unbounded_send(delivery.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{parties, session, sum_over, Share};
+    use std::collections::HashSet;
+    use tokio::net::{TcpListener, TcpStream};
+
+    impl Confidential for Share {
+        fn is_confidential(&self) -> bool {
+            self.0 >= 1000
+        }
+    }
+
+    fn channels(n: usize) -> Vec<RelayChannels> {
+        let keys = parties(n)
+            .into_iter()
+            .map(PartyKeys::generate)
+            .collect::<Vec<_>>();
+        let public = keys
+            .iter()
+            .map(|k| (k.party(), k.public_key()))
+            .collect::<Vec<_>>();
+        keys.iter()
+            .map(|k| RelayChannels::new(k, public.clone()))
+            .collect()
+    }
+
+    /// The frame which the relay delivers for the frame sent by the party
+    fn relayed(sender: PartyIndex, frame: &[u8]) -> Vec<u8> {
+        let mut input = Reader::new(frame);
+        Address::decode(&mut input).expect("address");
+        let mut delivery = Vec::new();
+        sender.encode(&mut delivery).unwrap();
+        delivery.extend_from_slice(&frame[frame.len() - input.remaining()..]);
+        delivery
+    }
+
+    #[test]
+    fn relay_cannot_read_or_modify_messages() -> Result<(), TransportError> {
+        let mut channels = channels(3);
+        let (a, b, c) = (
+            channels[0].party(),
+            channels[1].party(),
+            channels[2].party(),
+        );
+
+        let secret = OutputMessage::new(session(), Address::Peer(b), Share(4242));
+        let frame = channels[0].seal(&secret)?;
+        let plain = wire::to_bytes(&secret)?;
+        assert!(!frame.windows(plain.len()).any(|w| w == &plain[..]));
+        assert!(matches!(
+            channels[0].seal(&OutputMessage::new(
+                session(),
+                Address::Broadcast,
+                Share(4242)
+            )),
+            Err(TransportError::ConfidentialBroadcast)
+        ));
+
+        let mut modified = relayed(a, &frame);
+        *modified.last_mut().unwrap() ^= 1;
+        assert!(channels[1].open::<Share>(&modified).is_none());
+        // the reflected packet is refused too
+        assert!(channels[0].open::<Share>(&relayed(b, &frame)).is_none());
+        // the packet misdelivered to another party cannot be opened
+        assert!(channels[2].open::<Share>(&relayed(a, &frame)).is_none());
+        let msg = channels[1]
+            .open::<Share>(&relayed(a, &frame))
+            .expect("message");
+        assert_eq!((msg.sender, msg.body), (a, Share(4242)));
+
+        let broadcast =
+            channels[2].seal(&OutputMessage::new(session(), Address::Broadcast, Share(5)))?;
+        let mut modified = relayed(c, &broadcast);
+        *modified.last_mut().unwrap() ^= 1;
+        assert!(channels[0].open::<Share>(&modified).is_none());
+        assert!(channels[0].open::<Share>(&relayed(b, &broadcast)).is_none());
+        assert_eq!(
+            channels[0]
+                .open::<Share>(&relayed(c, &broadcast))
+                .map(|m| m.body),
+            Some(Share(5))
+        );
+
+        assert_eq!(
+            channels[0].violations(),
+            &HashMap::from([
+                (
+                    RelayViolation {
+                        sender: b,
+                        kind: ViolationKind::Misaddressed
+                    },
+                    1
+                ),
+                (
+                    RelayViolation {
+                        sender: c,
+                        kind: ViolationKind::Unauthenticated
+                    },
+                    1
+                ),
+                (
+                    RelayViolation {
+                        sender: b,
+                        kind: ViolationKind::Unauthenticated
+                    },
+                    1
+                )
+            ])
+        );
+        assert_eq!(
+            channels[1].violations(),
+            &HashMap::from([(
+                RelayViolation {
+                    sender: a,
+                    kind: ViolationKind::Unauthenticated
+                },
+                1
+            )])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn dropped_and_replayed_messages_detected() -> Result<(), TransportError> {
+        let mut channels = channels(2);
+        let (a, b) = (channels[0].party(), channels[1].party());
+        let frames = (0..3)
+            .map(|i| channels[0].seal(&OutputMessage::new(session(), Address::Peer(b), Share(i))))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        assert!(channels[1].open::<Share>(&relayed(a, &frames[0])).is_some());
+        assert!(channels[1].open::<Share>(&relayed(a, &frames[2])).is_some());
+        assert!(channels[1].open::<Share>(&relayed(a, &frames[2])).is_none());
+        assert_eq!(
+            channels[1]
+                .violations()
+                .keys()
+                .filter(|v| v.sender == a)
+                .map(|v| v.kind)
+                .collect::<HashSet<_>>(),
+            HashSet::from([
+                ViolationKind::Dropped {
+                    expected: 1,
+                    received: 2
+                },
+                ViolationKind::Replayed {
+                    expected: 3,
+                    received: 2
+                }
+            ])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn withheld_messages_detected() -> Result<(), TransportError> {
+        let mut channels = channels(2);
+        let (a, b) = (channels[0].party(), channels[1].party());
+        let frames = (0..3)
+            .map(|i| channels[0].seal(&OutputMessage::new(session(), Address::Peer(b), Share(i))))
+            .collect::<Result<Vec<_>, _>>()?;
+        // the relay drops the last message, which leaves no gap
+        for frame in &frames[..2] {
+            assert!(channels[1].open::<Share>(&relayed(a, frame)).is_some());
+        }
+        assert!(channels[1].violations().is_empty());
+
+        let sent = channels[0].counters()[&b].sent;
+        assert_eq!(
+            channels[1].counters()[&a],
+            ChannelCounters {
+                sent: 0,
+                received: 2
+            }
+        );
+        assert!(!channels[1].confirm(a, sent));
+        let sent_back = channels[1].counters()[&a].sent;
+        assert!(channels[0].confirm(b, sent_back));
+        assert_eq!(
+            channels[1].violations(),
+            &HashMap::from([(
+                RelayViolation {
+                    sender: a,
+                    kind: ViolationKind::Withheld {
+                        received: 2,
+                        sent: 3
+                    }
+                },
+                1
+            )])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn violations_counted_up_to_limit() -> Result<(), TransportError> {
+        let mut channels = channels(2);
+        let (a, b) = (channels[0].party(), channels[1].party());
+        let frame = channels[0].seal(&OutputMessage::new(session(), Address::Peer(b), Share(1)))?;
+        let mut modified = relayed(a, &frame);
+        *modified.last_mut().unwrap() ^= 1;
+        for _ in 0..3 {
+            assert!(channels[1].open::<Share>(&modified).is_none());
+        }
+        // each claimed number of messages makes a distinct record
+        for sent in 1..=MAX_VIOLATION_RECORDS as u64 + 1 {
+            channels[1].confirm(a, sent);
+        }
+        assert_eq!(channels[1].violations().len(), MAX_VIOLATION_RECORDS);
+        assert_eq!(
+            channels[1].violations()[&RelayViolation {
+                sender: a,
+                kind: ViolationKind::Unauthenticated
+            }],
+            3
+        );
+        assert_eq!(channels[1].unrecorded_violations(), 2);
+        Ok(())
+    }
+
+    /// identity keys of parties and their channel keys
+    fn identities(n: usize) -> Vec<(IdentityKeys, PartyKeys)> {
+        (0..n)
+            .map(|_| {
+                let identity = IdentityKeys::generate();
+                let keys = PartyKeys::generate(identity.party_index());
+                (identity, keys)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn machines_run_through_relay() -> Result<(), TransportError> {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let keys = identities(4);
+        let public = keys
+            .iter()
+            .map(|(_, k)| (k.party(), k.public_key()))
+            .collect::<Vec<_>>();
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let relay = tokio::spawn({
+            let parties = keys.iter().map(|(_, k)| k.party()).collect::<Vec<_>>();
+            async move { serve(listener, &parties, Duration::from_secs(10)).await }
+        });
+
+        // the connection which never answers the challenge does not hold back the parties
+        let _stalled = TcpStream::connect(addr).await?;
+        let mut transports = Vec::new();
+        for (identity, k) in &keys {
+            let stream = TcpStream::connect(addr).await?;
+            transports.push(connect::<_, Share, _>(stream, identity, k, public.clone()).await?);
+        }
+        sum_over(transports).await;
+        relay.await.expect("relay panicked")
+    }
+
+    #[tokio::test]
+    async fn impostors_not_connected() -> Result<(), TransportError> {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let keys = identities(2);
+        let public = keys
+            .iter()
+            .map(|(_, k)| (k.party(), k.public_key()))
+            .collect::<Vec<_>>();
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let relay = tokio::spawn({
+            let parties = keys.iter().map(|(_, k)| k.party()).collect::<Vec<_>>();
+            async move { serve(listener, &parties, Duration::from_secs(2)).await }
+        });
+
+        // the impostor claims the index of the first party, but has no identity key of that party
+        let impostor = IdentityKeys::generate();
+        assert!(matches!(
+            connect::<_, Share, _>(
+                TcpStream::connect(addr).await?,
+                &impostor,
+                &keys[0].1,
+                public.clone()
+            )
+            .await,
+            Err(TransportError::Handshake(_))
+        ));
+        let mut stream = TcpStream::connect(addr).await?;
+        let challenge = read_frame(&mut stream).await?;
+        let mut response = keys[0].0.public_key().0.to_vec();
+        response.extend_from_slice(&impostor.sign_challenge(&challenge));
+        write_frame(&mut stream, &response).await?;
+
+        connect::<_, Share, _>(
+            TcpStream::connect(addr).await?,
+            &keys[1].0,
+            &keys[1].1,
+            public,
+        )
+        .await?;
+        assert!(matches!(
+            relay.await.expect("relay panicked"),
+            Err(TransportError::Timeout)
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,317 @@
+//! Transport over byte streams
+//!
+//! [`StreamTransport`] runs over one byte stream per peer, like a TCP connection or a Unix domain socket. Each message is sent as
+//! `OutputMessage` in the binary form of the module `ecdsa_mpc::wire`, in a frame prefixed by its big-endian `u32` length, which is the framing of `WireCodec`.
+//! Frames longer than [`MAX_FRAME_LEN`] are refused before they are read.
+//!
+//! The transport spawns a reader and a writer task for each stream, so it has to be created within a `tokio` runtime.
+//! The reader attaches the peer of the stream as the sender of each message. It closes the stream if the peer sends a malformed frame,
+//! and drops messages which are addressed to another party.
+//!
+//! Mesh of streams is established by [`connect_mesh`]: each party dials peers whose index is greater than its own and accepts streams from the rest.
+//! Parties exchange their indexes in the first frame of each stream.
+//!
+//! [`StreamTransport`]: struct.StreamTransport.html
+//! [`MAX_FRAME_LEN`]: ../../ecdsa_mpc/wire/constant.MAX_FRAME_LEN.html
+//! [`connect_mesh`]: fn.connect_mesh.html
+
+use crate::{Transport, TransportError};
+use ecdsa_mpc::protocol::{Address, InputMessage, OutputMessage, PartyIndex};
+use ecdsa_mpc::wire::{self, Wire, MAX_FRAME_LEN};
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::future::{self, BoxFuture};
+use futures::StreamExt;
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::fmt::Display;
+use std::io;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::task::JoinHandle;
+
+/// Delay between attempts to dial a peer which is not listening yet
+const DIAL_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Transport over one byte stream per peer
+pub struct StreamTransport<B> {
+    party: PartyIndex,
+    writers: BTreeMap<PartyIndex, UnboundedSender<Vec<u8>>>,
+    tasks: Vec<JoinHandle<()>>,
+    incoming: UnboundedReceiver<InputMessage<B>>,
+}
+
+impl<B> StreamTransport<B>
+where
+    B: Wire + Send + 'static,
+{
+    /// Creates the transport over streams connected to peers of the party
+    ///
+    /// Streams have to be connected already, see [`connect_mesh`](fn.connect_mesh.html).
+    pub fn from_streams<S>(
+        party: PartyIndex,
+        streams: Vec<(PartyIndex, S)>,
+    ) -> Result<Self, TransportError>
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (incoming_sender, incoming) = mpsc::unbounded();
+        let mut writers = BTreeMap::new();
+        let mut tasks = Vec::with_capacity(streams.len());
+        for (peer, stream) in streams {
+            if peer == party || writers.contains_key(&peer) {
+                return Err(TransportError::DuplicateParty(peer));
+            }
+            let (reader, writer) = tokio::io::split(stream);
+            let (frames, queue) = mpsc::unbounded();
+            writers.insert(peer, frames);
+            tokio::spawn(read_messages(party, peer, reader, incoming_sender.clone()));
+            tasks.push(tokio::spawn(write_frames(
+                format!("peer {}", peer),
+                writer,
+                queue,
+            )));
+        }
+        Ok(StreamTransport {
+            party,
+            writers,
+            tasks,
+            incoming,
+        })
+    }
+}
+
+impl<B> StreamTransport<B> {
+    /// Peers connected to the party
+    pub fn peers(&self) -> impl Iterator<Item = &PartyIndex> {
+        self.writers.keys()
+    }
+
+    /// Closes streams after all queued messages are written
+    pub async fn shutdown(self) {
+        drop(self.writers);
+        for result in future::join_all(self.tasks).await {
+            if let Err(e) = result {
+                log::error!("writer task failed: {}", e);
+            }
+        }
+    }
+
+    fn queue(&self, peer: PartyIndex, frame: Vec<u8>) -> Result<(), TransportError> {
+        self.writers
+            .get(&peer)
+            .ok_or(TransportError::UnknownRecipient(peer))?
+            .unbounded_send(frame)
+            .map_err(|_| TransportError::Disconnected(peer))
+    }
+}
+
+impl<B: Wire> Transport<B> for StreamTransport<B> {
+    fn party(&self) -> PartyIndex {
+        self.party
+    }
+
+    fn send(&mut self, msg: OutputMessage<B>) -> Result<(), TransportError> {
+        let frame = wire::to_bytes(&msg)?;
+        match msg.recipient {
+            Address::Peer(peer) => self.queue(peer, frame),
+            Address::Broadcast => self
+                .writers
+                .keys()
+                .map(|peer| self.queue(*peer, frame.clone()))
+                .fold(Ok(()), Result::and),
+        }
+    }
+
+    fn poll_receive(&mut self, cx: &mut Context<'_>) -> Poll<Option<InputMessage<B>>> {
+        self.incoming.poll_next_unpin(cx)
+    }
+}
+
+/// Writes the frame prefixed by its length
+pub async fn write_frame<W>(writer: &mut W, frame: &[u8]) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let len = u32::try_from(frame.len())
+        .ok()
+        .filter(|len| *len as usize <= MAX_FRAME_LEN)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "frame is too long"))?;
+    writer.write_u32(len).await?;
+    writer.write_all(frame).await?;
+    writer.flush().await
+}
+
+/// Reads the frame prefixed by its length, refusing frames longer than [`MAX_FRAME_LEN`](../../ecdsa_mpc/wire/constant.MAX_FRAME_LEN.html)
+pub async fn read_frame<R>(reader: &mut R) -> io::Result<Vec<u8>>
+where
+    R: AsyncRead + Unpin,
+{
+    let len = reader.read_u32().await? as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "frame of {} bytes exceeds the limit of {} bytes",
+                len, MAX_FRAME_LEN
+            ),
+        ));
+    }
+    let mut frame = vec![0u8; len];
+    reader.read_exact(&mut frame).await?;
+    Ok(frame)
+}
+
+async fn read_messages<R, B>(
+    party: PartyIndex,
+    peer: PartyIndex,
+    mut reader: R,
+    incoming: UnboundedSender<InputMessage<B>>,
+) where
+    R: AsyncRead + Unpin,
+    B: Wire,
+{
+    loop {
+        let frame = match read_frame(&mut reader).await {
+            Ok(frame) => frame,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                log::debug!("party {}: peer {} closed the stream", party, peer);
+                return;
+            }
+            Err(e) => {
+                log::warn!("party {}: cannot read from peer {}: {}", party, peer, e);
+                return;
+            }
+        };
+        let msg = match wire::from_bytes::<OutputMessage<B>>(&frame) {
+            Ok(msg) => msg,
+            Err(e) => {
+                log::warn!("party {}: malformed message from {}: {}", party, peer, e);
+                return;
+            }
+        };
+        match msg.recipient {
+            Address::Peer(recipient) if recipient != party => {
+                log::warn!(
+                    "party {}: dropped message from {} addressed to {}",
+                    party,
+                    peer,
+                    recipient
+                );
+            }
+            _ => {
+                if incoming
+                    .unbounded_send(InputMessage::from_output(peer, msg))
+                    .is_err()
+                {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Writes queued frames to the stream until the queue is closed, then shuts the stream down
+pub(crate) async fn write_frames<W, D>(
+    target: D,
+    mut writer: W,
+    mut queue: UnboundedReceiver<Vec<u8>>,
+) where
+    W: AsyncWrite + Unpin,
+    D: Display,
+{
+    while let Some(frame) = queue.next().await {
+        if let Err(e) = write_frame(&mut writer, &frame).await {
+            log::warn!("cannot write to {}: {}", target, e);
+            return;
+        }
+    }
+    if let Err(e) = writer.shutdown().await {
+        log::debug!("cannot shut down the stream to {}: {}", target, e);
+    }
+}
+
+/// Listener of streams dialed by peers
+pub trait Listener: Send + 'static {
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+    fn accept(&mut self) -> BoxFuture<'_, io::Result<Self::Stream>>;
+}
+
+/// Establishes streams between the party and each of its peers
+///
+/// The party dials peers whose index is greater than its own, retrying until the peer listens, and accepts streams of the rest of its peers from the listener.
+/// Streams dialed by parties which are not peers, or which are already connected, are dropped.
+/// Fails with [`TransportError::Timeout`](../enum.TransportError.html#variant.Timeout) unless all peers are connected within `timeout`.
+pub async fn connect_mesh<L, D>(
+    party: PartyIndex,
+    mut listener: L,
+    peers: &[PartyIndex],
+    dial: D,
+    timeout: Duration,
+) -> Result<Vec<(PartyIndex, L::Stream)>, TransportError>
+where
+    L: Listener,
+    D: Fn(PartyIndex) -> BoxFuture<'static, io::Result<L::Stream>>,
+{
+    let mut awaited = BTreeSet::new();
+    for peer in peers {
+        if *peer == party || !awaited.insert(*peer) {
+            return Err(TransportError::DuplicateParty(*peer));
+        }
+    }
+    let (dialed, accepted): (Vec<_>, Vec<_>) = awaited.into_iter().partition(|p| *p > party);
+
+    let accepting = async move {
+        let mut awaited = accepted.into_iter().collect::<BTreeSet<_>>();
+        let mut streams = Vec::new();
+        while !awaited.is_empty() {
+            let mut stream = listener.accept().await?;
+            match handshake(party, &mut stream).await {
+                Ok(peer) if awaited.remove(&peer) => streams.push((peer, stream)),
+                Ok(peer) => log::warn!("party {}: dropped stream of {}", party, peer),
+                Err(e) => log::warn!("party {}: failed handshake: {}", party, e),
+            }
+        }
+        Ok::<_, TransportError>(streams)
+    };
+
+    let dialing = future::try_join_all(dialed.into_iter().map(|peer| {
+        let dial = &dial;
+        async move {
+            let mut stream = loop {
+                match dial(peer).await {
+                    Ok(stream) => break stream,
+                    Err(e) => {
+                        log::debug!("party {}: cannot dial {}: {}", party, peer, e);
+                        tokio::time::sleep(DIAL_RETRY_DELAY).await;
+                    }
+                }
+            };
+            let remote = handshake(party, &mut stream).await?;
+            if remote != peer {
+                return Err(TransportError::Handshake(format!(
+                    "dialed {}, connected to {}",
+                    peer, remote
+                )));
+            }
+            Ok((peer, stream))
+        }
+    }));
+
+    let (mut streams, accepted) =
+        tokio::time::timeout(timeout, future::try_join(dialing, accepting))
+            .await
+            .map_err(|_| TransportError::Timeout)??;
+    streams.extend(accepted);
+    Ok(streams)
+}
+
+/// Sends the index of the party and receives the index of the peer
+async fn handshake<S>(party: PartyIndex, stream: &mut S) -> Result<PartyIndex, TransportError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    write_frame(stream, &wire::to_bytes(&party)?).await?;
+    let frame = read_frame(stream).await?;
+    Ok(wire::from_bytes(&frame)?)
+}
@@ -0,0 +1,89 @@
+//! Transport over TCP connections
+//!
+//! Each pair of parties is connected by a single TCP connection, see [`connect_mesh`](../stream/fn.connect_mesh.html) for how the mesh is established.
+
+use crate::stream::{connect_mesh, Listener, StreamTransport};
+use crate::TransportError;
+use ecdsa_mpc::protocol::PartyIndex;
+use ecdsa_mpc::wire::Wire;
+use futures::future::{BoxFuture, FutureExt};
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+
+impl Listener for TcpListener {
+    type Stream = TcpStream;
+    fn accept(&mut self) -> BoxFuture<'_, io::Result<TcpStream>> {
+        async move {
+            let (stream, _) = TcpListener::accept(self).await?;
+            stream.set_nodelay(true)?;
+            Ok(stream)
+        }
+        .boxed()
+    }
+}
+
+async fn dial(addr: SocketAddr) -> io::Result<TcpStream> {
+    let stream = TcpStream::connect(addr).await?;
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
+
+/// Connects the party to its peers listening at given addresses
+///
+/// The party accepts connections of its peers on `listener`, which has to be bound before any peer dials it.
+pub async fn connect<B>(
+    party: PartyIndex,
+    listener: TcpListener,
+    peers: &HashMap<PartyIndex, SocketAddr>,
+    timeout: Duration,
+) -> Result<StreamTransport<B>, TransportError>
+where
+    B: Wire + Send + 'static,
+{
+    let parties = peers.keys().copied().collect::<Vec<_>>();
+    let streams = connect_mesh(
+        party,
+        listener,
+        &parties,
+        |peer| dial(peers[&peer]).boxed(),
+        timeout,
+    )
+    .await?;
+    StreamTransport::from_streams(party, streams)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{parties, sum_over, Share};
+    use futures::future;
+
+    #[tokio::test]
+    async fn machines_run_over_tcp() -> Result<(), TransportError> {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let parties = parties(4);
+        let mut listeners = Vec::new();
+        let mut addresses = HashMap::new();
+        for party in &parties {
+            let listener = TcpListener::bind("127.0.0.1:0").await?;
+            addresses.insert(*party, listener.local_addr()?);
+            listeners.push(listener);
+        }
+        let transports =
+            future::try_join_all(
+                parties.iter().zip(listeners).map(|(party, listener)| {
+                    let mut peers = addresses.clone();
+                    peers.remove(party);
+                    async move {
+                        connect::<Share>(*party, listener, &peers, Duration::from_secs(10)).await
+                    }
+                }),
+            )
+            .await?;
+        sum_over(transports).await;
+        Ok(())
+    }
+}
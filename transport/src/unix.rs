@@ -0,0 +1,82 @@
+//! Transport over Unix domain sockets
+//!
+//! Each pair of parties is connected by a single stream socket, see [`connect_mesh`](../stream/fn.connect_mesh.html) for how the mesh is established.
+
+use crate::stream::{connect_mesh, Listener, StreamTransport};
+use crate::TransportError;
+use ecdsa_mpc::protocol::PartyIndex;
+use ecdsa_mpc::wire::Wire;
+use futures::future::{BoxFuture, FutureExt};
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::net::{UnixListener, UnixStream};
+
+impl Listener for UnixListener {
+    type Stream = UnixStream;
+    fn accept(&mut self) -> BoxFuture<'_, io::Result<UnixStream>> {
+        async move { Ok(UnixListener::accept(self).await?.0) }.boxed()
+    }
+}
+
+/// Connects the party to its peers listening at given socket paths
+///
+/// The party accepts connections of its peers on `listener`, which has to be bound before any peer dials it.
+pub async fn connect<B>(
+    party: PartyIndex,
+    listener: UnixListener,
+    peers: &HashMap<PartyIndex, PathBuf>,
+    timeout: Duration,
+) -> Result<StreamTransport<B>, TransportError>
+where
+    B: Wire + Send + 'static,
+{
+    let parties = peers.keys().copied().collect::<Vec<_>>();
+    let streams = connect_mesh(
+        party,
+        listener,
+        &parties,
+        |peer| UnixStream::connect(peers[&peer].clone()).boxed(),
+        timeout,
+    )
+    .await?;
+    StreamTransport::from_streams(party, streams)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{parties, sum_over, Share};
+    use futures::future;
+    use std::fs;
+
+    #[tokio::test]
+    async fn machines_run_over_unix_sockets() -> Result<(), TransportError> {
+        let dir = std::env::temp_dir().join(format!("transport-test-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let parties = parties(3);
+        let mut listeners = Vec::new();
+        let mut paths = HashMap::new();
+        for (i, party) in parties.iter().enumerate() {
+            let path = dir.join(format!("party-{}.sock", i));
+            let _ = fs::remove_file(&path);
+            listeners.push(UnixListener::bind(&path)?);
+            paths.insert(*party, path);
+        }
+        let transports =
+            future::try_join_all(
+                parties.iter().zip(listeners).map(|(party, listener)| {
+                    let mut peers = paths.clone();
+                    peers.remove(party);
+                    async move {
+                        connect::<Share>(*party, listener, &peers, Duration::from_secs(10)).await
+                    }
+                }),
+            )
+            .await?;
+        sum_over(transports).await;
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}